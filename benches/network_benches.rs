@@ -93,7 +93,7 @@ mod benches {
 
         let backend = cuda_backend();
         let mut network = Layer::from_config(backend.clone(),
-                                             &LayerConfig::new("network", LayerType::Sequential(cfg)));
+                                             &LayerConfig::new("network", LayerType::Sequential(cfg))).unwrap();
 
         let _ = timeit_loops!(10, {
             let inp = SharedTensor::<f32>::new(&[1, 30, 30]);
@@ -127,6 +127,7 @@ mod benches {
             filter_shape: vec![11],
             padding: vec![2],
             stride: vec![4],
+            bias: false,
         };
         let mut conv1_cfg = LayerConfig::new("conv1", LayerType::Convolution(conv1_layer_cfg));
         conv1_cfg.add_input("data");
@@ -143,6 +144,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool1_cfg = LayerConfig::new("pool1", LayerType::Pooling(pool1_layer_cfg));
         pool1_cfg.add_input("conv1_out");
@@ -154,6 +156,7 @@ mod benches {
             filter_shape: vec![5],
             padding: vec![2],
             stride: vec![1],
+            bias: false,
         };
         let mut conv2_cfg = LayerConfig::new("conv2", LayerType::Convolution(conv2_layer_cfg));
         conv2_cfg.add_input("pool1_out");
@@ -170,6 +173,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool2_cfg = LayerConfig::new("pool2", LayerType::Pooling(pool2_layer_cfg));
         pool2_cfg.add_input("conv2_out");
@@ -181,6 +185,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv3_cfg = LayerConfig::new("conv3", LayerType::Convolution(conv3_layer_cfg));
         conv3_cfg.add_input("pool2_out");
@@ -197,6 +202,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv4_cfg = LayerConfig::new("conv4", LayerType::Convolution(conv4_layer_cfg));
         conv4_cfg.add_input("conv3_out");
@@ -213,6 +219,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv5_cfg = LayerConfig::new("conv5", LayerType::Convolution(conv5_layer_cfg));
         conv5_cfg.add_input("conv4_out");
@@ -229,6 +236,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool3_cfg = LayerConfig::new("pool3", LayerType::Pooling(pool3_layer_cfg));
         pool3_cfg.add_input("conv5_out");
@@ -256,7 +264,7 @@ mod benches {
         let backend = cuda_backend();
         // let native_backend = native_backend();
         let mut network = Layer::from_config(backend.clone(),
-                                             &LayerConfig::new("network", LayerType::Sequential(cfg)));
+                                             &LayerConfig::new("network", LayerType::Sequential(cfg))).unwrap();
 
         let func = || {
             let forward_time = timeit_loops!(1, {
@@ -284,6 +292,7 @@ mod benches {
             filter_shape: vec![11],
             padding: vec![2],
             stride: vec![4],
+            bias: false,
         };
         let mut conv1_cfg = LayerConfig::new("conv1", LayerType::Convolution(conv1_layer_cfg));
         conv1_cfg.add_input("data");
@@ -300,6 +309,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool1_cfg = LayerConfig::new("pool1", LayerType::Pooling(pool1_layer_cfg));
         pool1_cfg.add_input("conv1_out");
@@ -311,6 +321,7 @@ mod benches {
             filter_shape: vec![5],
             padding: vec![2],
             stride: vec![1],
+            bias: false,
         };
         let mut conv2_cfg = LayerConfig::new("conv2", LayerType::Convolution(conv2_layer_cfg));
         conv2_cfg.add_input("pool1_out");
@@ -327,6 +338,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool2_cfg = LayerConfig::new("pool2", LayerType::Pooling(pool2_layer_cfg));
         pool2_cfg.add_input("conv2_out");
@@ -338,6 +350,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv3_cfg = LayerConfig::new("conv3", LayerType::Convolution(conv3_layer_cfg));
         conv3_cfg.add_input("pool2_out");
@@ -354,6 +367,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv4_cfg = LayerConfig::new("conv4", LayerType::Convolution(conv4_layer_cfg));
         conv4_cfg.add_input("conv3_out");
@@ -370,6 +384,7 @@ mod benches {
             filter_shape: vec![3],
             padding: vec![1],
             stride: vec![1],
+            bias: false,
         };
         let mut conv5_cfg = LayerConfig::new("conv5", LayerType::Convolution(conv5_layer_cfg));
         conv5_cfg.add_input("conv4_out");
@@ -386,6 +401,7 @@ mod benches {
             filter_shape: vec![3],
             stride: vec![2],
             padding: vec![0], // TODO: make optional
+            padding_value: None,
         };
         let mut pool3_cfg = LayerConfig::new("pool3", LayerType::Pooling(pool3_layer_cfg));
         pool3_cfg.add_input("conv5_out");
@@ -413,7 +429,7 @@ mod benches {
         let backend = cuda_backend();
         // let native_backend = native_backend();
         let mut network = Layer::from_config(backend.clone(),
-                                             &LayerConfig::new("network", LayerType::Sequential(cfg)));
+                                             &LayerConfig::new("network", LayerType::Sequential(cfg))).unwrap();
 
         let mut func = || {
             let inp = SharedTensor::<f32>::new(&[128, 3, 112, 112]);
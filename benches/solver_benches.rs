@@ -0,0 +1,69 @@
+#![feature(test)]
+
+extern crate test;
+extern crate coaster as co;
+extern crate juice;
+
+mod benches {
+    use co::prelude::*;
+    use juice::layer::*;
+    use juice::layers::*;
+    use juice::solver::*;
+    use juice::util::{ArcLock, native_backend, write_to_memory};
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use test::Bencher;
+
+    fn tensor(shape: &[usize], data: &[f32]) -> ArcLock<SharedTensor<f32>> {
+        let native = native_backend();
+        let mut tensor = SharedTensor::new(&shape);
+        write_to_memory(tensor.write_only(native.device()).unwrap(), data);
+        Arc::new(RwLock::new(tensor))
+    }
+
+    /// A deep stack of small linear layers, so the update of the many small weight blobs
+    /// dominates the time of an iteration.
+    fn many_small_blobs_config(fused_update: bool) -> SolverConfig {
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[4, 8]);
+        for i in 0..64 {
+            net_cfg.add_layer(LayerConfig::new(&format!("linear{}", i), LinearConfig { output_size: 8 }));
+        }
+
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[4, 8]);
+        obj_cfg.add_input("label", &[4, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 8 }));
+
+        SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 0.001f32,
+            momentum: 0.9f32,
+            weight_decay: Some(0.0005f32),
+            regularization_method: Some(RegularizationMethod::L2),
+            fused_update: fused_update,
+            ..SolverConfig::default()
+        }
+    }
+
+    fn bench_update(b: &mut Bencher, fused_update: bool) {
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &many_small_blobs_config(fused_update));
+        let data = (0..32).map(|i| (i % 7) as f32 * 0.1).collect::<Vec<_>>();
+        let labels = [0f32, 3f32, 5f32, 7f32];
+        b.iter(|| {
+            solver.train_minibatch(tensor(&[4, 8], &data), tensor(&[4, 1], &labels));
+        });
+    }
+
+    #[bench]
+    fn separate_update_many_small_blobs(b: &mut Bencher) {
+        bench_update(b, false);
+    }
+
+    #[bench]
+    fn fused_update_many_small_blobs(b: &mut Bencher) {
+        bench_update(b, true);
+    }
+}
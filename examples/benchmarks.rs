@@ -129,6 +129,7 @@ fn bench_alexnet() {
         filter_shape: vec![11],
         padding: vec![2],
         stride: vec![4],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv1", conv1_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv1/relu", LayerType::ReLU));
@@ -138,6 +139,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv2",
@@ -146,6 +148,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![5],
                                        padding: vec![2],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv2/relu", LayerType::ReLU));
     cfg.add_layer(LayerConfig::new("pool2",
@@ -154,6 +157,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv3",
@@ -162,6 +166,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv3/relu", LayerType::ReLU));
 
@@ -171,6 +176,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv4/relu", LayerType::ReLU));
 
@@ -180,6 +186,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv5/relu", LayerType::ReLU));
     cfg.add_layer(LayerConfig::new("pool3",
@@ -188,6 +195,7 @@ fn bench_alexnet() {
                                        filter_shape: vec![3],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("fc1", LinearConfig { output_size: 4096 }));
@@ -197,7 +205,7 @@ fn bench_alexnet() {
     let backend = cuda_backend();
     // let native_backend = native_backend();
     let mut network = Layer::from_config(backend.clone(),
-                                         &LayerConfig::new("alexnet", LayerType::Sequential(cfg)));
+                                         &LayerConfig::new("alexnet", LayerType::Sequential(cfg))).unwrap();
 
     {
         let func = || {
@@ -258,6 +266,7 @@ fn bench_overfeat() {
         filter_shape: vec![11],
         padding: vec![0],
         stride: vec![4],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv1", conv1_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv1/relu", LayerType::ReLU));
@@ -266,6 +275,7 @@ fn bench_overfeat() {
         filter_shape: vec![2],
         stride: vec![2],
         padding: vec![0],
+        padding_value: None,
     };
     cfg.add_layer(LayerConfig::new("pool1", pool1_layer_cfg));
 
@@ -274,6 +284,7 @@ fn bench_overfeat() {
         filter_shape: vec![5],
         padding: vec![0],
         stride: vec![1],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv2", conv2_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv2/relu", LayerType::ReLU));
@@ -282,6 +293,7 @@ fn bench_overfeat() {
         filter_shape: vec![2],
         stride: vec![2],
         padding: vec![0],
+        padding_value: None,
     };
     cfg.add_layer(LayerConfig::new("pool2", pool2_layer_cfg));
 
@@ -290,6 +302,7 @@ fn bench_overfeat() {
         filter_shape: vec![3],
         padding: vec![1],
         stride: vec![1],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv3", conv3_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv3/relu", LayerType::ReLU));
@@ -299,6 +312,7 @@ fn bench_overfeat() {
         filter_shape: vec![3],
         padding: vec![1],
         stride: vec![1],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv4", conv4_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv4/relu", LayerType::ReLU));
@@ -308,6 +322,7 @@ fn bench_overfeat() {
         filter_shape: vec![3],
         padding: vec![1],
         stride: vec![1],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv5", conv5_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv5/relu", LayerType::ReLU));
@@ -316,6 +331,7 @@ fn bench_overfeat() {
         filter_shape: vec![2],
         stride: vec![2],
         padding: vec![0],
+        padding_value: None,
     };
     cfg.add_layer(LayerConfig::new("pool5", pool5_layer_cfg));
 
@@ -326,7 +342,7 @@ fn bench_overfeat() {
     let backend = cuda_backend();
     // let native_backend = native_backend();
     let mut network = Layer::from_config(backend.clone(),
-                                         &LayerConfig::new("overfeat", LayerType::Sequential(cfg)));
+                                         &LayerConfig::new("overfeat", LayerType::Sequential(cfg))).unwrap();
 
     {
         let func = || {
@@ -387,6 +403,7 @@ fn bench_vgg_a() {
         filter_shape: vec![3],
         padding: vec![1],
         stride: vec![1],
+        bias: false,
     };
     cfg.add_layer(LayerConfig::new("conv1", conv1_layer_cfg));
     cfg.add_layer(LayerConfig::new("conv1/relu", LayerType::ReLU));
@@ -396,6 +413,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![2],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv2",
@@ -404,6 +422,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv2/relu", LayerType::ReLU));
     let pool2_layer_cfg = PoolingConfig {
@@ -411,6 +430,7 @@ fn bench_vgg_a() {
         filter_shape: vec![2],
         stride: vec![2],
         padding: vec![0],
+        padding_value: None,
     };
     cfg.add_layer(LayerConfig::new("pool2",
                                    PoolingConfig {
@@ -418,6 +438,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![2],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv3",
@@ -426,6 +447,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv3/relu", LayerType::ReLU));
 
@@ -435,6 +457,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv4/relu", LayerType::ReLU));
     cfg.add_layer(LayerConfig::new("pool3",
@@ -443,6 +466,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![2],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv5",
@@ -451,6 +475,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv5/relu", LayerType::ReLU));
 
@@ -460,6 +485,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv6/relu", LayerType::ReLU));
     cfg.add_layer(LayerConfig::new("pool4",
@@ -468,6 +494,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![2],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
 
     cfg.add_layer(LayerConfig::new("conv7",
@@ -476,6 +503,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv7/relu", LayerType::ReLU));
 
@@ -485,6 +513,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![3],
                                        padding: vec![1],
                                        stride: vec![1],
+                                       bias: false,
                                    }));
     cfg.add_layer(LayerConfig::new("conv8/relu", LayerType::ReLU));
     cfg.add_layer(LayerConfig::new("pool5",
@@ -493,6 +522,7 @@ fn bench_vgg_a() {
                                        filter_shape: vec![2],
                                        stride: vec![2],
                                        padding: vec![0],
+                                       padding_value: None,
                                    }));
     cfg.add_layer(LayerConfig::new("fc1", LinearConfig { output_size: 4096 }));
     cfg.add_layer(LayerConfig::new("fc2", LinearConfig { output_size: 4096 }));
@@ -501,7 +531,7 @@ fn bench_vgg_a() {
     let backend = cuda_backend();
     // let native_backend = native_backend();
     let mut network = Layer::from_config(backend.clone(),
-                                         &LayerConfig::new("vgg_a", LayerType::Sequential(cfg)));
+                                         &LayerConfig::new("vgg_a", LayerType::Sequential(cfg))).unwrap();
 
     {
         let func = || {
@@ -0,0 +1,230 @@
+//! Provides benchmarks of the forward and backward passes of layers and networks.
+//!
+//! The passes are timed with the same `timeit` infrastructure that [Layer::forward][1]
+//! uses for its debug output. The backend is synchronized at the end of every timed
+//! pass, so asynchronous devices are measured correctly.
+//!
+//! The benchmarks run with any number of iterations, so tests can keep them
+//! exercised with a tiny iteration count.
+//!
+//! The [transfers][2] of the last iteration are recorded and reported along with the
+//! timings, which starts a new recording on the current thread.
+//!
+//! [1]: ../layer/struct.Layer.html#method.forward
+//! [2]: ../transfer/index.html
+
+use co::{IBackend, SharedTensor};
+use layer::*;
+use layers::SequentialConfig;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use transfer::{self, TransferSummary};
+use util::LayerOps;
+use weight::FillerType;
+
+/// The number of untimed iterations that are run before the timed ones.
+pub const WARMUP_ITERATIONS: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+/// The time a pass took over all timed iterations.
+pub struct Timing {
+    /// The mean time of one pass in milliseconds.
+    pub mean_ms: f64,
+    /// The standard deviation of the time of one pass in milliseconds.
+    pub stddev_ms: f64,
+}
+
+impl Timing {
+    fn from_samples(samples: &[f64]) -> Timing {
+        let n = samples.len() as f64;
+        let mean = samples.iter().fold(0f64, |sum, s| sum + s) / n;
+        let variance = samples.iter().fold(0f64, |sum, s| sum + (s - mean) * (s - mean)) / n;
+        Timing {
+            mean_ms: mean,
+            stddev_ms: variance.sqrt(),
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.5} ms +/- {:.5} ms", self.mean_ms, self.stddev_ms)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The result of a benchmark.
+pub struct BenchReport {
+    /// The name of the benchmarked layer or network.
+    pub name: String,
+    /// The number of timed iterations.
+    pub iterations: usize,
+    /// The number of samples in a batch.
+    pub batch_size: usize,
+    /// The timing of the forward pass.
+    pub forward: Timing,
+    /// The timing of the backward pass.
+    pub backward: Timing,
+    /// The transfers of one forward and backward pass.
+    pub transfers: TransferSummary,
+}
+
+impl BenchReport {
+    /// Returns the number of samples per second that are processed by a forward
+    /// and backward pass.
+    pub fn throughput(&self) -> f64 {
+        self.batch_size as f64 / ((self.forward.mean_ms + self.backward.mean_ms) / 1000f64)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{:<15} - Forward: {}, Backward: {}, {:.1} samples/sec ({} iterations), {} transfers per iteration",
+               self.name,
+               self.forward,
+               self.backward,
+               self.throughput(),
+               self.iterations,
+               self.transfers.count())
+    }
+}
+
+/// Benchmark a single layer that gets an input of shape `input_shape`.
+///
+/// The first dimension of the input shape is the batch size.
+pub fn bench_layer<B: IBackend + LayerOps<f32> + 'static>(backend: Rc<B>,
+                                                          config: &LayerConfig,
+                                                          input_shape: &[usize],
+                                                          iters: usize)
+                                                          -> BenchReport {
+    let mut net_config = SequentialConfig::default();
+    net_config.add_input("data", input_shape);
+    net_config.add_layer(config.clone());
+    let mut report = bench_network(backend, &net_config, iters);
+    report.name = config.name.clone();
+    report
+}
+
+/// Benchmark a network with the inputs of the shapes specified in its config.
+///
+/// The first dimension of the first input is the batch size.
+/// Panics if the network can not be created from its config.
+pub fn bench_network<B: IBackend + LayerOps<f32> + 'static>(backend: Rc<B>,
+                                                            net_config: &SequentialConfig,
+                                                            iters: usize)
+                                                            -> BenchReport {
+    assert!(iters > 0, "A benchmark needs at least one iteration");
+    let mut network = Layer::from_config(backend, &LayerConfig::new("network", net_config.clone()))
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    let inputs = net_config.inputs
+        .iter()
+        .map(|&(_, ref shape)| {
+            let mut input = SharedTensor::<f32>::new(shape);
+            FillerType::fill_constant(&mut input, 0.5f32);
+            Arc::new(RwLock::new(input))
+        })
+        .collect::<Vec<_>>();
+    let batch_size = net_config.inputs.get(0).and_then(|input| input.1.get(0).cloned()).unwrap_or(1);
+
+    let mut output_gradients = Vec::new();
+    let mut forward_samples = Vec::with_capacity(iters);
+    let mut backward_samples = Vec::with_capacity(iters);
+    let mut transfers = TransferSummary::default();
+    for i in 0..WARMUP_ITERATIONS + iters {
+        let last_iteration = i + 1 == WARMUP_ITERATIONS + iters;
+        if last_iteration {
+            transfer::start_recording();
+        }
+        let forward_time = timeit_loops!(1, {
+            network.forward(&inputs);
+            network.synchronize();
+        });
+        if output_gradients.is_empty() {
+            output_gradients = network.output_blobs_data
+                .iter()
+                .map(|output| {
+                    let mut gradient = SharedTensor::<f32>::new(output.read().unwrap().desc());
+                    FillerType::fill_constant(&mut gradient, 1f32);
+                    Arc::new(RwLock::new(gradient))
+                })
+                .collect();
+        }
+        let backward_time = timeit_loops!(1, {
+            network.backward(&output_gradients);
+            network.synchronize();
+        });
+        if last_iteration {
+            transfers = transfer::finish_recording().unwrap_or_default();
+        }
+        if i >= WARMUP_ITERATIONS {
+            forward_samples.push(forward_time * 1000f64);
+            backward_samples.push(backward_time * 1000f64);
+        }
+    }
+
+    BenchReport {
+        name: "network".to_owned(),
+        iterations: iters,
+        batch_size: batch_size,
+        forward: Timing::from_samples(&forward_samples),
+        backward: Timing::from_samples(&backward_samples),
+        transfers: transfers,
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use super::*;
+    use transfer::TransferCategory;
+
+    fn native_backend() -> Rc<Backend<Native>> {
+        Rc::new(::util::native_backend())
+    }
+
+    #[test]
+    fn bench_layer_reports_timings() {
+        let config = LayerConfig::new("linear", LinearConfig { output_size: 4 });
+        let report = bench_layer(native_backend(), &config, &[8, 16], 2);
+        assert_eq!("linear", report.name);
+        assert_eq!(2, report.iterations);
+        assert_eq!(8, report.batch_size);
+        assert!(report.forward.mean_ms >= 0f64);
+        assert!(report.backward.stddev_ms >= 0f64);
+        assert!(report.throughput() > 0f64);
+    }
+
+    #[test]
+    fn bench_network_runs_forward_and_backward() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[4, 6]);
+        cfg.add_layer(LayerConfig::new("linear1", LinearConfig { output_size: 5 }));
+        cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        cfg.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: 2 }));
+        let report = bench_network(native_backend(), &cfg, 1);
+        assert_eq!(4, report.batch_size);
+        assert_eq!(0f64, report.forward.stddev_ms);
+        assert!(format!("{}", report).contains("samples/sec"));
+        // every layer computes on the device
+        assert_eq!(0, report.transfers.category_count(TransferCategory::HostFallback));
+    }
+
+    #[test]
+    fn bench_reports_the_transfers_of_host_layers() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[4, 6]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 5 }));
+        cfg.add_layer(LayerConfig::new("dropout", DropoutConfig { probability: 0.5 }));
+        let report = bench_network(native_backend(), &cfg, 1);
+        // the input and the output of the dropout, and their gradients
+        assert_eq!(4, report.transfers.category_count(TransferCategory::HostFallback));
+        assert!(format!("{}", report).contains("4 transfers per iteration"), "{}", report);
+    }
+}
@@ -0,0 +1,294 @@
+//! Provides iterators over the batches of a dataset.
+//!
+//! A [DataIterator][1] yields the [Batches][2] of one epoch, each with the data and target
+//! tensors that can be passed to the [Solver][3] and the ids of the samples, which can
+//! be passed to [train_minibatch_with_ids][4].
+//!
+//! The [MmapIterator][5] streams datasets that do not fit into memory from a binary file.
+//!
+//! [1]: ./trait.DataIterator.html
+//! [2]: ./struct.Batch.html
+//! [3]: ../solver/struct.Solver.html
+//! [4]: ../solver/struct.Solver.html#method.train_minibatch_with_ids
+//! [5]: ./struct.MmapIterator.html
+
+use co::SharedTensor;
+use memmap::{Mmap, Protection};
+use rand::{Rng, SeedableRng, StdRng};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug, Clone)]
+/// A batch of samples.
+pub struct Batch {
+    /// The data of the samples, of shape `[batch_size, ...]`.
+    pub data: ArcLock<SharedTensor<f32>>,
+    /// The targets of the samples, of shape `[batch_size, ...]`.
+    pub target: ArcLock<SharedTensor<f32>>,
+    /// The ids of the samples, e.g. their index in the dataset.
+    pub sample_ids: Vec<usize>,
+}
+
+/// Iterates over the batches of a dataset, one epoch at a time.
+pub trait DataIterator {
+    /// Returns the next batch of the epoch, or `None` if the epoch is complete.
+    fn next_batch(&mut self) -> Option<Batch>;
+
+    /// Starts the next epoch.
+    fn reset(&mut self);
+
+    /// Returns the number of batches per epoch, if it is known in advance.
+    fn batches_per_epoch(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl fmt::Debug for DataIterator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({})", "DataIterator")
+    }
+}
+
+/// Iterates over a memory-mapped binary file of fixed-size records.
+///
+/// Every record consists of the values of the data followed by the values of the target,
+/// stored as little-endian `f32`. Only the records of the current batch are copied into
+/// the tensors, so the file is never loaded as a whole; the operating system pages the
+/// mapped file in and out as needed.
+///
+/// Records that do not fill a complete batch at the end of an epoch are skipped, so all
+/// batches have the same shape. With [shuffled][1] the records are visited in a new random
+/// order every epoch, which shuffles the offsets of the records, not the file.
+/// [1]: #method.shuffled
+///
+/// The file must not be modified while it is mapped.
+pub struct MmapIterator {
+    mmap: Mmap,
+    data_shape: Vec<usize>,
+    target_shape: Vec<usize>,
+    batch_size: usize,
+    num_records: usize,
+
+    order: Vec<usize>,
+    position: usize,
+    rng: Option<StdRng>,
+}
+
+impl MmapIterator {
+    /// Map the file at `path` with records of the data shape `data_shape` and the target
+    /// shape `target_shape` and iterate over it in batches of `batch_size` records.
+    pub fn open<P: AsRef<Path>>(path: P,
+                                data_shape: &[usize],
+                                target_shape: &[usize],
+                                batch_size: usize)
+                                -> io::Result<MmapIterator> {
+        if batch_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The batch size must be positive"));
+        }
+        let mmap = try!(Mmap::open_path(path, Protection::Read));
+        let record_bytes = 4 * (Self::size(data_shape) + Self::size(target_shape));
+        if record_bytes == 0 || mmap.len() % record_bytes != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("The file of {} bytes does not consist of records of {} bytes",
+                                              mmap.len(),
+                                              record_bytes)));
+        }
+        let num_records = mmap.len() / record_bytes;
+
+        Ok(MmapIterator {
+            mmap: mmap,
+            data_shape: data_shape.to_owned(),
+            target_shape: target_shape.to_owned(),
+            batch_size: batch_size,
+            num_records: num_records,
+
+            order: (0..num_records).collect(),
+            position: 0,
+            rng: None,
+        })
+    }
+
+    /// Visit the records in a random order that is drawn anew for every epoch.
+    ///
+    /// The same `seed` yields the same orders.
+    pub fn shuffled(mut self, seed: usize) -> MmapIterator {
+        let seed: &[usize] = &[seed];
+        self.rng = Some(SeedableRng::from_seed(seed));
+        self.reset();
+        self
+    }
+
+    /// Returns the number of records in the file.
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    /// Returns the number of complete batches per epoch.
+    pub fn num_batches(&self) -> usize {
+        self.num_records / self.batch_size
+    }
+
+    fn size(shape: &[usize]) -> usize {
+        shape.iter().fold(1, |prod, dim| prod * dim)
+    }
+
+    #[allow(unsafe_code)]
+    fn bytes(&self) -> &[u8] {
+        // The mapping is read-only and lives as long as `self`. The slice is only invalid
+        // if the file is modified while it is mapped, which is documented as not allowed.
+        unsafe { self.mmap.as_slice() }
+    }
+
+    /// Decodes the record `record` and appends its data and target values.
+    fn read_record(&self, record: usize, data: &mut Vec<f32>, target: &mut Vec<f32>) {
+        let data_size = Self::size(&self.data_shape);
+        let record_size = data_size + Self::size(&self.target_shape);
+        let bytes = &self.bytes()[record * record_size * 4..(record + 1) * record_size * 4];
+        for (i, value) in bytes.chunks(4).enumerate() {
+            let bits = value[0] as u32 | (value[1] as u32) << 8 | (value[2] as u32) << 16 | (value[3] as u32) << 24;
+            if i < data_size {
+                data.push(f32::from_bits(bits));
+            } else {
+                target.push(f32::from_bits(bits));
+            }
+        }
+    }
+
+    fn batch_tensor(&self, shape: &[usize], values: &[f32]) -> ArcLock<SharedTensor<f32>> {
+        let mut batch_shape = vec![self.batch_size];
+        batch_shape.extend_from_slice(shape);
+        let native = native_backend();
+        let mut tensor = SharedTensor::new(&batch_shape);
+        write_to_memory(tensor.write_only(native.device()).unwrap(), values);
+        Arc::new(RwLock::new(tensor))
+    }
+}
+
+impl DataIterator for MmapIterator {
+    fn next_batch(&mut self) -> Option<Batch> {
+        if self.position + self.batch_size > self.num_records {
+            return None;
+        }
+        let sample_ids = self.order[self.position..self.position + self.batch_size].to_vec();
+        self.position += self.batch_size;
+
+        let mut data = Vec::with_capacity(self.batch_size * Self::size(&self.data_shape));
+        let mut target = Vec::with_capacity(self.batch_size * Self::size(&self.target_shape));
+        for &record in &sample_ids {
+            self.read_record(record, &mut data, &mut target);
+        }
+        Some(Batch {
+            data: self.batch_tensor(&self.data_shape, &data),
+            target: self.batch_tensor(&self.target_shape, &target),
+            sample_ids: sample_ids,
+        })
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+        if let Some(ref mut rng) = self.rng {
+            rng.shuffle(&mut self.order);
+        }
+    }
+
+    fn batches_per_epoch(&self) -> Option<usize> {
+        Some(self.num_batches())
+    }
+}
+
+impl fmt::Debug for MmapIterator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "MmapIterator {{ records: {}, data_shape: {:?}, target_shape: {:?}, batch_size: {} }}",
+               self.num_records,
+               self.data_shape,
+               self.target_shape,
+               self.batch_size)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use super::*;
+    use util::testing::{read, temp_path};
+
+    /// Writes `num_records` records with the data `[i, i + 0.5]` and the target `[10 * i]`.
+    fn record_file(name: &str, num_records: usize) -> PathBuf {
+        let path = temp_path(&format!("data_{}.bin", name));
+        let mut file = File::create(&path).unwrap();
+        for i in 0..num_records {
+            for &value in &[i as f32, i as f32 + 0.5, 10f32 * i as f32] {
+                let bits = value.to_bits();
+                file.write_all(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8]).unwrap();
+            }
+        }
+        path
+    }
+
+    #[test]
+    fn batches_contain_the_records_in_order() {
+        let path = record_file("ordered", 5);
+        let mut iterator = MmapIterator::open(&path, &[2], &[1], 2).unwrap();
+        assert_eq!(5, iterator.num_records());
+        assert_eq!(2, iterator.num_batches());
+
+        let first = iterator.next_batch().unwrap();
+        assert_eq!(vec![0, 1], first.sample_ids);
+        assert_eq!(&vec![2, 2], first.data.read().unwrap().desc());
+        assert_eq!(vec![0f32, 0.5, 1.0, 1.5], read(&first.data));
+        assert_eq!(vec![0f32, 10.0], read(&first.target));
+        let second = iterator.next_batch().unwrap();
+        assert_eq!(vec![2f32, 2.5, 3.0, 3.5], read(&second.data));
+        // the fifth record does not fill a batch
+        assert!(iterator.next_batch().is_none());
+
+        iterator.reset();
+        assert_eq!(vec![0, 1], iterator.next_batch().unwrap().sample_ids);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shuffling_reorders_records_without_corrupting_them() {
+        let path = record_file("shuffled", 8);
+        let mut iterator = MmapIterator::open(&path, &[2], &[1], 2).unwrap().shuffled(42);
+
+        let mut epochs = Vec::new();
+        for _ in 0..2 {
+            let mut ids = Vec::new();
+            while let Some(batch) = iterator.next_batch() {
+                let data = read(&batch.data);
+                let target = read(&batch.target);
+                for (i, &id) in batch.sample_ids.iter().enumerate() {
+                    assert_eq!(&[id as f32, id as f32 + 0.5], &data[i * 2..i * 2 + 2]);
+                    assert_eq!(10f32 * id as f32, target[i]);
+                }
+                ids.extend(batch.sample_ids);
+            }
+            iterator.reset();
+            epochs.push(ids);
+        }
+
+        for ids in &epochs {
+            let mut sorted = ids.clone();
+            sorted.sort();
+            assert_eq!((0..8).collect::<Vec<_>>(), sorted);
+        }
+        assert!(epochs[0] != (0..8).collect::<Vec<_>>() || epochs[1] != (0..8).collect::<Vec<_>>());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn files_of_partial_records_are_rejected() {
+        let path = record_file("partial", 3);
+        assert!(MmapIterator::open(&path, &[4], &[1], 1).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+//! Provides greedy decoding and beam search over the outputs of sequence models.
+//!
+//! Sequence models predict a sequence one token at a time, where each prediction is fed
+//! back into the network for the next time step. How the network is run for a single
+//! time step and how its hidden state is threaded between the steps is up to the
+//! implementation of [DecoderState][1]. A typical implementation keeps the hidden state
+//! blobs in host memory, writes them together with the previous token into the inputs of
+//! the network, runs [Layer::forward][2] and reads the softmax output and the new hidden
+//! state back with [Layer::read_blob_into][3], passing it a native backend that it keeps
+//! for all steps.
+//!
+//! The search itself runs entirely on the host.
+//!
+//! [1]: ./trait.DecoderState.html
+//! [2]: ../layer/struct.Layer.html#method.forward
+//! [3]: ../layer/struct.Layer.html#method.read_blob_into
+
+use std::cmp::Ordering;
+
+/// The state of a sequence model that is decoded one time step at a time.
+///
+/// Beam search clones the state for every hypothesis it follows, so cloning has to
+/// copy the hidden state of the model.
+pub trait DecoderState: Clone {
+    /// Run the model for one time step with the previously predicted `token` as input.
+    ///
+    /// Advances the state to the next time step and returns the probabilities of
+    /// all tokens for it.
+    fn step(&mut self, token: usize) -> Result<Vec<f32>, String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A decoded sequence.
+pub struct Hypothesis {
+    /// The decoded tokens, ending with the EOS token if the sequence is finished.
+    ///
+    /// Does not contain the start token.
+    pub tokens: Vec<usize>,
+    /// The sum of the log probabilities of the tokens.
+    pub log_probability: f32,
+    /// If the sequence has been ended by the EOS token, instead of reaching the maximum length.
+    pub finished: bool,
+}
+
+impl Hypothesis {
+    fn empty() -> Hypothesis {
+        Hypothesis {
+            tokens: vec![],
+            log_probability: 0f32,
+            finished: false,
+        }
+    }
+
+    fn extend(&self, token: usize, probability: f32, eos_token: usize) -> Hypothesis {
+        let mut tokens = self.tokens.clone();
+        tokens.push(token);
+        Hypothesis {
+            tokens: tokens,
+            log_probability: self.log_probability + probability.ln(),
+            finished: token == eos_token,
+        }
+    }
+
+    fn last_token(&self, start_token: usize) -> usize {
+        self.tokens.last().cloned().unwrap_or(start_token)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// Specifies how a sequence is decoded.
+pub struct DecodingConfig {
+    /// The token that is fed into the model at the first time step.
+    pub start_token: usize,
+    /// The token that ends a sequence.
+    pub eos_token: usize,
+    /// The maximum number of tokens that are decoded for a sequence.
+    ///
+    /// Default: `50`
+    pub max_len: usize,
+    /// The number of hypotheses that beam search follows at each time step.
+    ///
+    /// Ignored by greedy decoding.
+    ///
+    /// Default: `4`
+    pub beam_width: usize,
+}
+
+impl Default for DecodingConfig {
+    fn default() -> DecodingConfig {
+        DecodingConfig {
+            start_token: 0,
+            eos_token: 1,
+            max_len: 50,
+            beam_width: 4,
+        }
+    }
+}
+
+fn descending(a: f32, b: f32) -> Ordering {
+    b.partial_cmp(&a).unwrap_or(Ordering::Equal)
+}
+
+/// Decode a sequence by picking the most probable token at each time step.
+pub fn greedy_decode<S: DecoderState>(mut state: S, config: &DecodingConfig) -> Result<Hypothesis, String> {
+    let mut hypothesis = Hypothesis::empty();
+    while hypothesis.tokens.len() < config.max_len && !hypothesis.finished {
+        let probabilities = try!(state.step(hypothesis.last_token(config.start_token)));
+        let (token, &probability) = try!(probabilities.iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(Ordering::Equal))
+            .ok_or("The decoder returned no probabilities".to_owned()));
+        hypothesis = hypothesis.extend(token, probability, config.eos_token);
+    }
+    Ok(hypothesis)
+}
+
+/// Decode a sequence with beam search.
+///
+/// At each time step all hypotheses are extended by every token and the `beam_width`
+/// most probable unfinished ones are kept. Hypotheses ending with the EOS token that
+/// are more probable than the least probable kept one are finished.
+/// The search stops once no unfinished hypothesis can become more probable than the
+/// finished ones, or the maximum length is reached.
+///
+/// Returns up to `beam_width` hypotheses, the most probable first.
+/// They include unfinished hypotheses that have reached the maximum length.
+pub fn beam_search<S: DecoderState>(state: S, config: &DecodingConfig) -> Result<Vec<Hypothesis>, String> {
+    if config.beam_width == 0 {
+        return Err("Beam search needs a beam_width of at least 1".to_owned());
+    }
+
+    let mut beams = vec![(Hypothesis::empty(), state)];
+    let mut finished = Vec::<Hypothesis>::new();
+    for _ in 0..config.max_len {
+        let mut stepped_states = Vec::with_capacity(beams.len());
+        let mut candidates = Vec::new();
+        for (beam_id, &(ref hypothesis, ref state)) in beams.iter().enumerate() {
+            let mut state = state.clone();
+            let probabilities = try!(state.step(hypothesis.last_token(config.start_token)));
+            if probabilities.is_empty() {
+                return Err("The decoder returned no probabilities".to_owned());
+            }
+            for (token, &probability) in probabilities.iter().enumerate() {
+                if probability > 0f32 {
+                    candidates.push((beam_id, hypothesis.extend(token, probability, config.eos_token)));
+                }
+            }
+            stepped_states.push(state);
+        }
+        candidates.sort_by(|a, b| descending(a.1.log_probability, b.1.log_probability));
+
+        let mut next_beams = Vec::with_capacity(config.beam_width);
+        for (beam_id, hypothesis) in candidates {
+            if next_beams.len() == config.beam_width {
+                break;
+            }
+            if hypothesis.finished {
+                finished.push(hypothesis);
+            } else {
+                next_beams.push((hypothesis, stepped_states[beam_id].clone()));
+            }
+        }
+        beams = next_beams;
+
+        // log probabilities only decrease, so the unfinished hypotheses can't overtake
+        // the finished ones anymore
+        finished.sort_by(|a, b| descending(a.log_probability, b.log_probability));
+        let best_unfinished = beams.first().map(|beam| beam.0.log_probability);
+        match (best_unfinished, finished.get(config.beam_width - 1)) {
+            (None, _) => break,
+            (Some(best), Some(worst_finished)) if best <= worst_finished.log_probability => break,
+            _ => {}
+        }
+    }
+
+    let mut hypotheses = finished;
+    hypotheses.extend(beams.into_iter().map(|beam| beam.0));
+    hypotheses.sort_by(|a, b| descending(a.log_probability, b.log_probability));
+    hypotheses.truncate(config.beam_width);
+    Ok(hypotheses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EOS: usize = 0;
+    const A: usize = 1;
+    const B: usize = 2;
+
+    /// A model with the three states EOS, A and B, where the probabilities
+    /// of the next token only depend on the previous one.
+    #[derive(Debug, Clone)]
+    struct ToyModel {
+        steps: usize,
+    }
+
+    impl DecoderState for ToyModel {
+        fn step(&mut self, token: usize) -> Result<Vec<f32>, String> {
+            self.steps += 1;
+            match token {
+                A => Ok(vec![0.1, 0.3, 0.6]),
+                B => Ok(vec![0.45, 0.5, 0.05]),
+                _ => Err(format!("No transition after token {}", token)),
+            }
+        }
+    }
+
+    fn config(beam_width: usize) -> DecodingConfig {
+        DecodingConfig {
+            start_token: A,
+            eos_token: EOS,
+            max_len: 3,
+            beam_width: beam_width,
+        }
+    }
+
+    #[test]
+    fn greedy_follows_most_probable_token() {
+        let hypothesis = greedy_decode(ToyModel { steps: 0 }, &config(1)).unwrap();
+        assert_eq!(vec![B, A, B], hypothesis.tokens);
+        assert!(!hypothesis.finished);
+        assert!((hypothesis.log_probability - 0.18f32.ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn beam_search_finds_more_probable_sequence() {
+        let hypotheses = beam_search(ToyModel { steps: 0 }, &config(2)).unwrap();
+        assert_eq!(2, hypotheses.len());
+        // B EOS: 0.6 * 0.45
+        assert_eq!(vec![B, EOS], hypotheses[0].tokens);
+        assert!(hypotheses[0].finished);
+        assert!((hypotheses[0].log_probability - 0.27f32.ln()).abs() < 1e-5);
+        // B A B: 0.6 * 0.5 * 0.6
+        assert_eq!(vec![B, A, B], hypotheses[1].tokens);
+        assert!(!hypotheses[1].finished);
+        assert!((hypotheses[1].log_probability - 0.18f32.ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn beam_width_one_is_greedy() {
+        let greedy = greedy_decode(ToyModel { steps: 0 }, &config(1)).unwrap();
+        let hypotheses = beam_search(ToyModel { steps: 0 }, &config(1)).unwrap();
+        assert_eq!(vec![greedy], hypotheses);
+    }
+
+    #[test]
+    fn beam_search_rejects_zero_width() {
+        assert!(beam_search(ToyModel { steps: 0 }, &config(0)).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Provides human-readable, field-level differences between configs, e.g. to log how an
+//! experiment differs from a baseline.
+//!
+//! The layers of Sequential layers are matched by name, so a layer that is added,
+//! removed or changed is reported under its path of layer names:
+//!
+//! ```
+//! # use juice::diff::config_diff;
+//! # use juice::layer::*;
+//! # use juice::layers::*;
+//! let mut baseline = SequentialConfig::default();
+//! baseline.add_input("data", &[1, 4]);
+//! baseline.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+//! let mut experiment = baseline.clone();
+//! experiment.layers[0].layer_type = LayerType::Linear(LinearConfig { output_size: 5 });
+//! experiment.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+//!
+//! let diff = config_diff(&LayerConfig::new("network", baseline), &LayerConfig::new("network", experiment));
+//! assert_eq!(vec!["network/linear.output_size: 3 -> 5", "network: added relu (ReLU)"], diff);
+//! ```
+//!
+//! Values are printed with their `Debug` representation. Custom learning rate schedulers
+//! of a [SolverConfig][1] are only compared by whether they are set.
+//! [1]: ../solver/struct.SolverConfig.html
+
+use layer::{LayerConfig, LayerType};
+use layers::SequentialConfig;
+use solver::SolverConfig;
+
+/// A config whose field-level differences to another config can be listed.
+pub trait ConfigDiff {
+    /// Returns the differences from `self` to `other`, one per line, or an empty vector
+    /// if there are none.
+    fn diff(&self, other: &Self) -> Vec<String>;
+}
+
+/// Returns the differences from the config `a` to the config `b`, see [ConfigDiff][1].
+/// [1]: ./trait.ConfigDiff.html
+pub fn config_diff<C: ConfigDiff>(a: &C, b: &C) -> Vec<String> {
+    a.diff(b)
+}
+
+/// Lists the names and `Debug` representations of the fields of a config.
+macro_rules! fields {
+    ($config:expr; $($field:ident),*) => (
+        vec![$((stringify!($field), format!("{:?}", $config.$field))),*]
+    )
+}
+
+type Fields = Vec<(&'static str, String)>;
+
+fn diff_fields(path: &str, a: Fields, b: Fields, diffs: &mut Vec<String>) {
+    for ((name, a), (_, b)) in a.into_iter().zip(b) {
+        if a != b {
+            diffs.push(format!("{}.{}: {} -> {}", path, name, a, b));
+        }
+    }
+}
+
+fn layer_config_fields(config: &LayerConfig) -> Fields {
+    fields![config; name, outputs, inputs, params, propagate_down, recompute_in_backward]
+}
+
+/// Returns the fields of the config of a layer type other than Sequential.
+fn layer_type_fields(layer_type: &LayerType) -> Fields {
+    match *layer_type {
+        LayerType::Convolution(ref config) => fields![config; num_output, filter_shape, stride, padding, bias],
+        LayerType::Linear(ref config) => fields![config; output_size],
+        LayerType::Pooling(ref config) => fields![config; mode, filter_shape, stride, padding, padding_value],
+        LayerType::LRN(ref config) => fields![config; local_size, alpha, beta, k],
+        LayerType::Dropout(ref config) => fields![config; probability],
+        LayerType::BatchNorm(ref config) => fields![config; momentum, epsilon, freeze_stats],
+        LayerType::ELU(ref config) => fields![config; alpha],
+        LayerType::SpectralNorm(ref config) => fields![config; layer_type, power_iterations],
+        LayerType::StochasticDepth(ref config) => fields![config; layer_type, survival_prob],
+        LayerType::NegativeLogLikelihood(ref config) => fields![config; num_classes],
+        LayerType::TripletLoss(ref config) => fields![config; margin],
+        LayerType::ContrastiveLoss(ref config) => fields![config; margin],
+        LayerType::SoftmaxLoss(ref config) => {
+            fields![config; num_classes, class_weights, ignore_index, ohem_top_k, output_probabilities]
+        }
+        LayerType::SmoothL1Loss(ref config) => fields![config; beta],
+        LayerType::Reshape(ref config) => fields![config; shape],
+        LayerType::Split(ref config) => fields![config; axis, sizes, num_chunks],
+        LayerType::Custom(ref config) => fields![config; layer_type, payload],
+        LayerType::Sequential(_) |
+        LayerType::LogSoftmax |
+        LayerType::MeanScale |
+        LayerType::Softmax |
+        LayerType::ReLU |
+        LayerType::TanH |
+        LayerType::Sigmoid |
+        LayerType::NchwToNhwc |
+        LayerType::NhwcToNchw => Vec::new(),
+    }
+}
+
+fn diff_layer(path: &str, a: &LayerConfig, b: &LayerConfig, diffs: &mut Vec<String>) {
+    diff_fields(path, layer_config_fields(a), layer_config_fields(b), diffs);
+    match (&a.layer_type, &b.layer_type) {
+        (&LayerType::Sequential(ref a), &LayerType::Sequential(ref b)) => diff_sequential(path, a, b, diffs),
+        (a, b) if a.name() != b.name() => diffs.push(format!("{}: layer type {} -> {}", path, a, b)),
+        (a, b) => diff_fields(path, layer_type_fields(a), layer_type_fields(b), diffs),
+    }
+}
+
+fn diff_sequential(path: &str, a: &SequentialConfig, b: &SequentialConfig, diffs: &mut Vec<String>) {
+    diff_fields(path,
+                fields![a; inputs, force_backward, checkpoint_segments, accumulate_loss_in_f64, init_defaults,
+                        memory_budget_bytes],
+                fields![b; inputs, force_backward, checkpoint_segments, accumulate_loss_in_f64, init_defaults,
+                        memory_budget_bytes],
+                diffs);
+
+    for layer in &a.layers {
+        match b.layers.iter().find(|other| other.name == layer.name) {
+            Some(other) => diff_layer(&format!("{}/{}", path, layer.name), layer, other, diffs),
+            None => diffs.push(format!("{}: removed {}", path, layer)),
+        }
+    }
+    for layer in &b.layers {
+        if !a.layers.iter().any(|other| other.name == layer.name) {
+            diffs.push(format!("{}: added {}", path, layer));
+        }
+    }
+
+    let order = |layers: &[LayerConfig], others: &[LayerConfig]| {
+        layers.iter()
+            .filter(|layer| others.iter().any(|other| other.name == layer.name))
+            .map(|layer| layer.name.clone())
+            .collect::<Vec<_>>()
+    };
+    let (order_a, order_b) = (order(&a.layers, &b.layers), order(&b.layers, &a.layers));
+    if order_a != order_b {
+        diffs.push(format!("{}: layer order {:?} -> {:?}", path, order_a, order_b));
+    }
+}
+
+impl ConfigDiff for LayerConfig {
+    fn diff(&self, other: &LayerConfig) -> Vec<String> {
+        let mut diffs = Vec::new();
+        diff_layer(&self.name, self, other, &mut diffs);
+        diffs
+    }
+}
+
+impl ConfigDiff for SolverConfig {
+    /// The differences of the network and the objective are prefixed with `network` and
+    /// `objective`.
+    fn diff(&self, other: &SolverConfig) -> Vec<String> {
+        let solver_fields = |config: &SolverConfig| {
+            fields![config; name, solver, minibatch_size, accumulate_steps, accumulate_in_f64, loss_history_size,
+                    snapshot_interval, snapshot_prefix, keep_best, freeze_schedule, lr_scheduler, lr_policy, base_lr,
+                    gamma, stepsize, clip_gradients, weight_decay, weight_decay_policy, weight_decay_gamma,
+                    weight_decay_stepsize, regularization_method, momentum, fused_update, lars_trust_coefficient,
+                    ema_decay, ema_warmup, max_iter, max_duration, target_loss, max_epochs, gradient_stats,
+                    record_transfers]
+        };
+        let mut diffs = Vec::new();
+        diff_fields("solver", solver_fields(self), solver_fields(other), &mut diffs);
+        diff_layer("network", &self.network, &other.network, &mut diffs);
+        diff_layer("objective", &self.objective, &other.objective, &mut diffs);
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layer::*;
+    use layers::*;
+    use solver::SolverConfig;
+    use super::*;
+    use weight::{FillerType, WeightConfig};
+
+    fn network() -> LayerConfig {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[4, 2]);
+        cfg.add_layer(LayerConfig::new("linear1", LinearConfig { output_size: 3 }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: 2 }));
+        LayerConfig::new("network", cfg)
+    }
+
+    fn layers(config: &mut LayerConfig) -> &mut Vec<LayerConfig> {
+        match config.layer_type {
+            LayerType::Sequential(ref mut cfg) => &mut cfg.layers,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn identical_configs_have_no_differences() {
+        assert_eq!(network(), network());
+        assert!(config_diff(&network(), &network()).is_empty());
+        let solver = SolverConfig { network: network(), ..SolverConfig::default() };
+        assert_eq!(solver, solver.clone());
+        assert!(config_diff(&solver, &solver.clone()).is_empty());
+    }
+
+    #[test]
+    fn reports_added_and_removed_layers() {
+        let mut changed = network();
+        layers(&mut changed).remove(1);
+        layers(&mut changed).push(LayerConfig::new("log_softmax", LayerType::LogSoftmax));
+        assert!(network() != changed);
+        assert_eq!(vec!["network: removed relu (ReLU)", "network: added log_softmax (LogSoftmax)"],
+                   config_diff(&network(), &changed));
+
+        let mut reordered = network();
+        layers(&mut reordered).swap(0, 2);
+        assert_eq!(vec![r#"network: layer order ["linear1", "relu", "linear2"] -> ["linear2", "relu", "linear1"]"#],
+                   config_diff(&network(), &reordered));
+    }
+
+    #[test]
+    fn reports_changed_nested_fields() {
+        let mut changed = network();
+        layers(&mut changed)[0].layer_type = LayerType::Linear(LinearConfig { output_size: 5 });
+        layers(&mut changed)[1].layer_type = LayerType::TanH;
+        let mut weight = WeightConfig::default();
+        weight.filler = Some(FillerType::Constant { value: 0.5 });
+        layers(&mut changed)[2].params.push(weight);
+        let diff = config_diff(&network(), &changed);
+        assert_eq!(3, diff.len(), "{:?}", diff);
+        assert_eq!("network/linear1.output_size: 3 -> 5", diff[0]);
+        assert_eq!("network/relu: layer type ReLU -> TanH", diff[1]);
+        assert!(diff[2].starts_with("network/linear2.params: [] -> [WeightConfig {"), "{}", diff[2]);
+
+        let baseline = SolverConfig { network: network(), ..SolverConfig::default() };
+        let experiment = SolverConfig { base_lr: 0.1, network: changed, ..baseline.clone() };
+        assert!(baseline != experiment);
+        let diff = config_diff(&baseline, &experiment);
+        assert_eq!("solver.base_lr: 0.01 -> 0.1", diff[0]);
+        assert_eq!(4, diff.len(), "{:?}", diff);
+    }
+}
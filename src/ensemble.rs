@@ -0,0 +1,171 @@
+//! Provides ensembling of networks that share the same architecture.
+//!
+//! An ensemble combines several networks, e.g. snapshots of the same network from
+//! different epochs or trainings with different seeds, either by averaging their
+//! predictions with [average_predict][1], or by averaging their weights into a single
+//! network with [weights_average][2].
+//!
+//! [1]: ./fn.average_predict.html
+//! [2]: ./fn.weights_average.html
+
+use co::{IBackend, ITensorDesc, SharedTensor};
+use layer::*;
+use std::rc::Rc;
+use util::{ArcLock, LayerOps, native_backend, write_to_memory};
+
+/// Run the same `inputs` through all networks and average their outputs.
+///
+/// For networks that end with a softmax this averages the class probabilities.
+/// Returns the averaged output of each sample of the batch.
+pub fn average_predict<B: IBackend>(nets: &mut [Layer<B>],
+                                    inputs: &[ArcLock<SharedTensor<f32>>])
+                                    -> Result<Vec<Vec<f32>>, String> {
+    if nets.is_empty() {
+        return Err("Can not predict with an empty ensemble".to_owned());
+    }
+
+    let native = native_backend();
+    let mut shape = None;
+    let mut sum = Vec::<f32>::new();
+    for (i, net) in nets.iter_mut().enumerate() {
+        let outputs = net.forward(inputs);
+        let output = outputs[0].read().unwrap();
+        if let Some(ref shape) = shape {
+            if shape != output.desc() {
+                return Err(format!("Network {} outputs shape {:?} instead of {:?}", i, output.desc(), shape));
+            }
+        }
+        if shape.is_none() {
+            shape = Some(output.desc().clone());
+            sum = vec![0f32; output.desc().size()];
+        }
+        let values = output.read(native.device()).unwrap().as_slice::<f32>();
+        for (sum, value) in sum.iter_mut().zip(values) {
+            *sum += *value;
+        }
+    }
+
+    let shape = shape.unwrap();
+    let batch_size = if shape.len() > 1 { shape[0] } else { 1 };
+    let num_nets = nets.len() as f32;
+    Ok(sum.chunks(sum.len() / batch_size)
+        .map(|sample| sample.iter().map(|value| value / num_nets).collect())
+        .collect())
+}
+
+/// Create a network whose learnable weights are the element-wise mean of the learnable
+/// weights of all networks.
+///
+/// The network is created from the configuration of the first network.
+/// Fails with the name of the offending weight if the learnable weights of the networks
+/// do not match in name or shape.
+pub fn weights_average<B: IBackend + LayerOps<f32> + 'static>(backend: Rc<B>,
+                                                              nets: &[Layer<B>])
+                                                              -> Result<Layer<B>, String> {
+    let first = try!(nets.first().ok_or("Can not average the weights of an empty ensemble".to_owned()));
+    let names = first.learnable_weights_names();
+    let weights = first.learnable_weights_data();
+    for (i, net) in nets.iter().enumerate().skip(1) {
+        let net_names = net.learnable_weights_names();
+        let net_weights = net.learnable_weights_data();
+        if net_names.len() != names.len() {
+            return Err(format!("Network {} has {} learnable weights instead of {}",
+                               i,
+                               net_names.len(),
+                               names.len()));
+        }
+        for (j, name) in names.iter().enumerate() {
+            if &net_names[j] != name {
+                return Err(format!("Weight {} of network {} does not match weight {}", net_names[j], i, name));
+            }
+            let shape = weights[j].read().unwrap().desc().clone();
+            let net_shape = net_weights[j].read().unwrap().desc().clone();
+            if net_shape != shape {
+                return Err(format!("Weight {} has shape {:?} in network {} instead of {:?}",
+                                   name,
+                                   net_shape,
+                                   i,
+                                   shape));
+            }
+        }
+    }
+
+    let native = native_backend();
+    let average = try!(Layer::from_config(backend, first.config()).map_err(|err| err.to_string()));
+    let num_nets = nets.len() as f32;
+    for (j, weight) in average.learnable_weights_data().iter().enumerate() {
+        let mut mean = vec![0f32; weight.read().unwrap().desc().size()];
+        for net in nets {
+            let net_weight = net.learnable_weights_data()[j].clone();
+            let net_weight = net_weight.read().unwrap();
+            let values = net_weight.read(native.device()).unwrap().as_slice::<f32>();
+            for (mean, value) in mean.iter_mut().zip(values) {
+                *mean += *value / num_nets;
+            }
+        }
+        write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &mean);
+    }
+    Ok(average)
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::*;
+    use util::{ArcLock, native_backend, write_to_memory};
+    use util::testing::read;
+
+    fn network(output_size: usize) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: output_size }));
+        cfg.add_layer(LayerConfig::new("softmax", LayerType::Softmax));
+        Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap()
+    }
+
+    fn copy_weights(from: &Layer<Backend<Native>>, to: &Layer<Backend<Native>>) {
+        let native = native_backend();
+        for (from, to) in from.learnable_weights_data().iter().zip(to.learnable_weights_data().iter()) {
+            write_to_memory(to.write().unwrap().write_only(native.device()).unwrap(), &read(from));
+        }
+    }
+
+    fn input() -> ArcLock<SharedTensor<f32>> {
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[0.5f32, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        Arc::new(RwLock::new(input))
+    }
+
+    #[test]
+    fn averaging_identical_networks_is_a_noop() {
+        let mut nets = vec![network(2), network(2)];
+        copy_weights(&nets[0], &nets[1]);
+
+        let single = read(&nets[0].forward(&[input()])[0]);
+        let averaged = average_predict(&mut nets, &[input()]).unwrap();
+        assert_eq!(2, averaged.len());
+        let averaged = averaged.concat();
+        for (single, averaged) in single.iter().zip(averaged.iter()) {
+            assert!((single - averaged).abs() < 1e-6);
+        }
+
+        let average = weights_average(Rc::new(native_backend()), &nets).unwrap();
+        assert_eq!(read(&nets[0].learnable_weights_data()[0]),
+                   read(&average.learnable_weights_data()[0]));
+    }
+
+    #[test]
+    fn mismatched_architectures_name_the_weight() {
+        let nets = vec![network(2), network(4)];
+        let name = nets[0].learnable_weights_names()[0].clone();
+
+        let error = weights_average(Rc::new(native_backend()), &nets).err().unwrap();
+        assert!(error.contains(&name), "{}", error);
+    }
+}
@@ -6,19 +6,32 @@
 use capnp_util::*;
 use co::prelude::*;
 use layers::*;
+use juice_capnp::bundle as capnp_bundle;
 use juice_capnp::layer as capnp_layer;
 use juice_capnp::layer_config as capnp_layer_config;
 use juice_capnp::layer_config::layer_type as capnp_layer_type;
+use juice_capnp::tensor as capnp_tensor;
+use juice_capnp::weight as capnp_weight;
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::io::{self, BufReader};
+use std::mem;
 use std::path::Path;
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
+use transfer::{self, TransferCategory};
 use util::{ArcLock, LayerOps};
-use weight::WeightConfig;
+use weight::{FillerType, InitDefaults, PruningReport, QuantizationReport, QuantizedWeight, WeightConfig};
+use weight::{record_weight_synced, record_weight_written, weight_is_latest_on, with_placeholder_weights};
+
+/// The version of the format of the bundles written by [Layer::export_bundle][1].
+/// [1]: ./struct.Layer.html#method.export_bundle
+///
+/// It is increased whenever a bundle can't be read by an older version of Juice anymore.
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
 
 #[derive(Debug)]
 /// The generic Layer
@@ -38,10 +51,18 @@ pub struct Layer<B: IBackend> {
     pub worker: Box<ILayer<B>>,
 
     backend: Rc<B>,
+    // backend of the backward pass, if it differs from the one of the forward pass
+    training_backend: Option<Rc<B>>,
 
     /// Determines if layer will skip comutations for [backward][1] step.
     /// [1]: ./trait.ILayer.html#method.backward
     needs_backward: bool,
+    // why the backward step runs or is skipped, see `backprop_plan`
+    backprop_reason: BackpropReason,
+    // frozen layers keep their weights, see `set_frozen`
+    frozen: bool,
+    // whether each pass ends with a synchronization, see `set_synchronize_passes`
+    synchronize_passes: bool,
 
     /// The vector that stores shared references to the weights in the form of blobs.
     pub weights_data: Vec<ArcLock<SharedTensor<f32>>>,
@@ -49,12 +70,26 @@ pub struct Layer<B: IBackend> {
     pub weights_gradient: Vec<ArcLock<SharedTensor<f32>>>,
     // contains all the learnable weights (does not include bias(?) and shared weights)
     learnable_weights: Vec<ArcLock<SharedTensor<f32>>>,
+    // index into the weight vectors for each learnable weight
+    learnable_weight_ids: Vec<usize>,
+    // index into the weight vectors for each weight that is not updated by the solver
+    non_learnable_weight_ids: Vec<usize>,
     // learning rate for each weight
     weights_lr: Vec<Option<f32>>,
     // weight decay for each weight
     weights_weight_decay: Vec<Option<f32>>,
+    // whether each weight is decayed if it has no configured weight decay
+    weights_decayable: Vec<bool>,
     // display name for each weight
     weights_display_names: Vec<String>,
+    // the filler each weight has been filled with from its WeightConfig or the InitDefaults
+    weights_filler: HashMap<usize, FillerType>,
+    // binary mask for each learnable weight, that keeps pruned weights at zero
+    pruning_masks: Vec<ArcLock<SharedTensor<f32>>>,
+    // int8 copy of each weight, if the layer has been quantized
+    quantized_weights: Vec<QuantizedWeight>,
+    // left and right singular vector of each weight whose spectral norm has been estimated
+    spectral_vectors: RefCell<HashMap<String, (SharedTensor<f32>, SharedTensor<f32>)>>,
 
     /// Vector indicating whether to compute the diff of each weight blob.
     ///
@@ -87,6 +122,9 @@ pub struct Layer<B: IBackend> {
     ///
     /// Does not contain anonymous blobs.
     pub blob_names: HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>,
+
+    // observers that are called after each forward step, see `register_forward_hook`
+    forward_hooks: Vec<Box<ForwardHook>>,
 }
 
 impl<B: IBackend> Layer<B> {
@@ -109,15 +147,16 @@ impl<B: IBackend> Layer<B> {
                                                   Option<f32>)>) {
         // connect to all required inputs
         for input_name in &self.config.inputs.clone() {
-            self.connect_input(input_name, registry)
+            self.connect_input_blob(input_name, registry)
         }
         // setup outputs
         for (output_id, _) in self.config.outputs.clone().iter().rev().enumerate() {
             self.append_output(output_id, registry);
         }
         let config = self.config.clone();
-        for (output_id, _) in self.config.outputs.clone().iter().rev().enumerate() {
-            self.append_weight(&config, weight_registry, 0, output_id);
+        let num_weights = self.worker.num_weight_blobs().unwrap_or(self.config.outputs.len());
+        for weight_id in 0..num_weights {
+            self.append_weight(&config, weight_registry, 0, weight_id);
         }
 
         // If the layer specifies that AutoTopBlobs() -> true and the LayerParameter
@@ -142,6 +181,11 @@ impl<B: IBackend> Layer<B> {
 
         self.worker.init(self.backend.clone());
         self.reshape();
+        self.fill_configured_weights();
+        // weight gradients are accumulated during backpropagation, so they have to start at zero
+        for weight_gradient in &self.weights_gradient {
+            ::weight::FillerType::fill_constant(&mut weight_gradient.write().unwrap(), 0f32);
+        }
         self.worker.resize_shared_workspace(self.backend.clone(), None);
         for t in &self.output_blobs_data {
             debug!("Layer {} - output shape: {:?}",
@@ -150,6 +194,49 @@ impl<B: IBackend> Layer<B> {
         }
     }
 
+    /// Fills the weights of the Layer that specify a filler in their [WeightConfig][1].
+    /// [1]: ../weight/struct.WeightConfig.html
+    ///
+    /// Only weights owned by the Layer are filled, so shared weights are filled once.
+    fn fill_configured_weights(&mut self) {
+        for &weight_id in &self.learnable_weight_ids {
+            if let Some(filler) = self.config.param(weight_id).and_then(|param| param.filler) {
+                filler.fill(&mut self.weights_data[weight_id].write().unwrap());
+                record_weight_written(&self.weights_data[weight_id], ::util::native_backend().device());
+                self.weights_filler.insert(weight_id, filler);
+            }
+        }
+    }
+
+    /// Fills the weights of the Layer that don't specify a filler in their [WeightConfig][1]
+    /// with the default filler of the layer type in `defaults`.
+    /// [1]: ../weight/struct.WeightConfig.html
+    ///
+    /// Weights without a default keep the initialization of the layer implementation.
+    /// This is done for every layer of a container with the [InitDefaults][2] of its
+    /// [SequentialConfig][3].
+    /// [2]: ../weight/struct.InitDefaults.html
+    /// [3]: ../layers/container/struct.SequentialConfig.html
+    pub fn fill_default_weights(&mut self, defaults: &InitDefaults) {
+        if self.worker.is_container() {
+            return;
+        }
+        for &weight_id in &self.learnable_weight_ids {
+            if self.config.param(weight_id).and_then(|param| param.filler).is_some() {
+                continue;
+            }
+            if let Some(filler) = defaults.filler(self.config.layer_type.name(), weight_id) {
+                {
+                    let mut weight = self.weights_data[weight_id].write().unwrap();
+                    let filler = filler.for_shape(weight.desc()).for_blob(&self.weights_display_names[weight_id]);
+                    filler.fill(&mut weight);
+                    self.weights_filler.insert(weight_id, filler);
+                }
+                record_weight_written(&self.weights_data[weight_id], ::util::native_backend().device());
+            }
+        }
+    }
+
     /// Append blob as [input blob][1] to the Layer.
     /// [1]: ../layer/index.html
     ///
@@ -159,9 +246,9 @@ impl<B: IBackend> Layer<B> {
     ///
     /// [3]: ../layer/struct.LayerConfig.html
     /// [5]: #method.init_backprop
-    fn connect_input(&mut self,
-                     blob_name: &str,
-                     available_blobs: &mut HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>) {
+    fn connect_input_blob(&mut self,
+                          blob_name: &str,
+                          available_blobs: &mut HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>) {
         let input_id = self.config.inputs.iter().position(|input_name| input_name == blob_name).unwrap();
 
         if !available_blobs.contains_key(&*blob_name) {
@@ -268,7 +355,7 @@ impl<B: IBackend> Layer<B> {
         if self.worker.auto_weight_blobs() {
             info!("Layer {} - appending weight", &layer_config.name);
             let weights_len = self.weights_data.len();
-            let weight_name = if weights_len > weight_id {
+            let weight_name = if layer_config.params_len() > weight_id {
                 layer_config.param(weight_id).unwrap().name.clone()
             } else {
                 "".to_owned()
@@ -286,12 +373,15 @@ impl<B: IBackend> Layer<B> {
 
             // add to tracking vectors
             let net_weight_id = weights_len;
-            let output_data = self.output_blobs_data[weight_id].read().unwrap();
+            // weights beyond the outputs are sized by the layer in reshape
+            let weight_shape = self.output_blobs_data
+                .get(weight_id)
+                .map_or(vec![1], |output_data| output_data.read().unwrap().desc().clone());
             debug!("Layer {} - creating weight and gradient of size {:?}",
                    &layer_config.name,
-                   output_data.desc());
-            let weight_data = Arc::new(RwLock::new(SharedTensor::new(output_data.desc())));
-            let weight_gradient = Arc::new(RwLock::new(SharedTensor::new(output_data.desc())));
+                   weight_shape);
+            let weight_data = Arc::new(RwLock::new(SharedTensor::new(&weight_shape)));
+            let weight_gradient = Arc::new(RwLock::new(SharedTensor::new(&weight_shape)));
             self.weights_data.push(weight_data.clone());
             self.weights_gradient.push(weight_gradient.clone());
 
@@ -305,17 +395,21 @@ impl<B: IBackend> Layer<B> {
             if weight_name.is_empty() || !registry.contains_key(&registry_name) {
                 // self.weight_owners.push(None);
                 if !weight_name.is_empty() {
-                    registry.insert(weight_name.clone(),
+                    registry.insert(registry_name.clone(),
                                     (weight_data.clone(),
                                      weight_gradient.clone(),
                                      weight_config.lr_mult,
                                      weight_config.decay_mult));
                 }
-                let learnable_weight_id = self.learnable_weights.len();
-                self.learnable_weights.push(weight_data.clone());
-                // self.learnable_weight_ids.push(learnable_weight_id);
-                self.weights_lr.push(weight_config.lr_mult);
-                self.weights_weight_decay.push(weight_config.decay_mult);
+                if self.worker.learnable_weight(weight_id) {
+                    self.learnable_weights.push(weight_data.clone());
+                    self.learnable_weight_ids.push(net_weight_id);
+                    self.weights_lr.push(weight_config.lr_mult);
+                    self.weights_weight_decay.push(weight_config.decay_mult);
+                    self.weights_decayable.push(self.worker.weight_decayable(weight_id));
+                } else {
+                    self.non_learnable_weight_ids.push(net_weight_id);
+                }
             } else {
                 // Named weight blob with name we've seen before: share weights
 
@@ -323,6 +417,11 @@ impl<B: IBackend> Layer<B> {
                     registry.get(&registry_name).unwrap().clone();
                 info!("Sharing weight blob '{}'", weight_name.clone());
 
+                // Shared weights share both data and gradient, so the gradients of
+                // all sharing layers accumulate in the same blob.
+                self.weights_data[net_weight_id] = shared_weight_data.clone();
+                self.weights_gradient[net_weight_id] = shared_weight_gradient.clone();
+
                 // can only share parameters if both have same lr_mult
                 if let Some(lr_mult) = weight_config.lr_mult {
                     if let Some(owner_lr_mult) = shared_lr {
@@ -384,81 +483,133 @@ impl<B: IBackend> Layer<B> {
         }
     }
 
-    /// Initializes layer for [backpropagation][1]
-    /// [1]: https://en.wikipedia.org/wiki/Backpropagation
+    /// Reshapes the layer to the shapes of its current inputs, but keeps the values of its
+    /// weights, which some layers initialize when they are reshaped.
     ///
-    /// Go through all the blobs of a layer to determine which blobs contribute to the
-    /// loss of the next layer. We can skip backward computation for blobs that don't contribute
-    /// to the loss.
-    /// If all of the blobs skip backpropagation we set a flag to skip backpropagation
-    /// of the whole layer.
-    pub fn init_backprop(&mut self, blobs_under_loss: &mut HashSet<String>, blobs_skip_backp: &mut HashSet<String>) {
-        let mut layer_contributes_loss = false;
-        let mut layer_skip_propagate_down = true;
-        for (output_id, _) in self.output_blobs_data.iter().enumerate() {
-            let blob_name = self.output_blob_names.get(output_id);
-
-            // layer is a loss layer or under a loss layer
-            if self.loss(output_id).is_some() || blob_name.is_some() && blobs_under_loss.contains(blob_name.unwrap()) {
-                layer_contributes_loss = true;
-            }
-            // layer is not marked to skip backpropagation
-            if blob_name.is_none() || blob_name.is_some() && !blobs_skip_backp.contains(blob_name.unwrap()) {
-                layer_skip_propagate_down = false;
-            }
-            // layer contributes loss to some
-            if layer_contributes_loss && !layer_skip_propagate_down {
-                break;
-            }
+    /// Is used when the batch size of the inputs changes, see [forward][1], and by container
+    /// layers to reshape the layers inside of them.
+    /// [1]: #method.forward
+    pub fn reshape_to_inputs(&mut self) {
+        let weights = self.weights_data.iter().chain(&self.weights_gradient).cloned().collect::<Vec<_>>();
+        let kept = weights.iter()
+            .map(|weight| ::std::mem::replace(&mut *weight.write().unwrap(), SharedTensor::new(&[1])))
+            .collect::<Vec<_>>();
+        self.reshape();
+        // in reverse, so a weight that appears twice gets back its values and not the placeholder
+        for (weight, kept) in weights.iter().zip(kept).rev() {
+            *weight.write().unwrap() = kept;
         }
+    }
 
-        // If this layer can skip backward computation, also all his input blobs
-        // don't need backpropagation
-        if self.needs_backward && layer_skip_propagate_down {
-            self.needs_backward = false;
-            for (input_id, _) in self.input_blobs_data.iter().enumerate() {
-                self.input_need_backwards[input_id] = false;
-            }
-        }
-        // layer doesn't contribute loss so it does not need to be backpropagated
-        if !layer_contributes_loss {
-            self.needs_backward = false;
-        }
-        {
-            info!("{} needs backward computation: {}",
-                  self.name,
-                  self.needs_backward);
-        }
+    /// Initializes layer for [backpropagation][1]
+    /// [1]: https://en.wikipedia.org/wiki/Backpropagation
+    ///
+    /// Is called by a container for its layers in reverse order, so the consumers of the
+    /// outputs of the layer have already been initialized. `blobs_need_backp` contains the
+    /// blobs whose gradient is computed by at least one of their consumers. A container adds
+    /// its own outputs, as their gradients are provided to its backward pass.
+    ///
+    /// The layer only needs a backward step if one of its outputs is a loss or needs its
+    /// gradient, otherwise no gradient flows into the layer and the step is skipped.
+    /// A blob that is consumed by several layers needs its gradient as long as one of the
+    /// consumers propagates down to it.
+    pub fn init_backprop(&mut self, blobs_need_backp: &mut HashSet<String>) {
+        let receives_gradient = self.has_loss_output() ||
+                                self.output_blob_names.iter().any(|name| blobs_need_backp.contains(name));
+        self.needs_backward = receives_gradient;
+        self.backprop_reason = if receives_gradient {
+            BackpropReason::UnderLoss
+        } else {
+            BackpropReason::NotPropagated
+        };
+        info!("{} needs backward computation: {}",
+              self.name,
+              self.needs_backward);
 
         for (input_id, input_name) in self.input_blob_names.iter().enumerate() {
-            if layer_contributes_loss {
-                blobs_under_loss.insert(input_name.clone());
-            } else {
+            if !receives_gradient {
                 self.input_need_backwards[input_id] = false;
             }
-            if !self.input_need_backwards[input_id] {
-                blobs_skip_backp.insert(input_name.clone());
+            if self.input_need_backwards[input_id] {
+                blobs_need_backp.insert(input_name.clone());
             }
         }
     }
 
+    /// Returns `true` if an output of the layer, or of a layer inside of it, is a loss.
+    fn has_loss_output(&self) -> bool {
+        match self.worker.sublayers() {
+            Some(layers) => layers.iter().any(|layer| layer.borrow().has_loss_output()),
+            None => (0..self.output_blobs_data.len()).any(|output_id| self.worker.loss_weight(output_id).is_some()),
+        }
+    }
+
     /// Set [backpropagation][1] flags to force this layer to backpropagate.
     /// [1]: https://en.wikipedia.org/wiki/Backpropagation
     ///
-    /// Is executed during Network initalization if [NetworkConfig][2].force_backward is true.
+    /// Is executed during Network initalization if [SequentialConfig][2].force_backward is true.
     /// Forcing backpropagation is useful for debugging.
+    /// [2]: ../layers/container/struct.SequentialConfig.html#structfield.force_backward
+    ///
+    /// The gradients of inputs that disable [propagate_down][3] or don't
+    /// [allow force backward][4] are still skipped.
+    /// [3]: ./struct.LayerConfig.html#structfield.propagate_down
+    /// [4]: ./trait.ILayer.html#method.allow_force_backward
     pub fn init_force_backward(&mut self) {
-        self.needs_backward = true;
-        for (input_id, _) in self.input_need_backwards.clone().iter().enumerate() {
-            self.input_need_backwards[input_id] = *self.input_need_backwards
-                .get(input_id)
-                .unwrap_or(&self.worker.allow_force_backward(input_id));
+        if !self.needs_backward {
+            self.needs_backward = true;
+            self.backprop_reason = BackpropReason::Forced;
+        }
+        for input_id in 0..self.input_need_backwards.len() {
+            let propagate_down = self.config.propagate_down.get(input_id).cloned().unwrap_or(true);
+            self.input_need_backwards[input_id] |= propagate_down && self.worker.allow_force_backward(input_id);
         }
         for (weight_id, _) in self.weights_data.clone().iter().enumerate() {
             self.set_weight_propagate_down(weight_id, true);
         }
     }
 
+    /// Returns `true` if the backward step of the layer runs.
+    ///
+    /// See [backprop_plan][1].
+    /// [1]: #method.backprop_plan
+    pub fn needs_backward(&self) -> bool {
+        self.needs_backward
+    }
+
+    /// Returns for each input whether its gradient is computed.
+    pub fn input_needs_backward(&self) -> &[bool] {
+        &self.input_need_backwards
+    }
+
+    /// Returns for every layer that is not a container whether its backward steps run
+    /// and why, in the order of the layers.
+    ///
+    /// Containers skip the backward steps of the layers that don't receive a gradient, see
+    /// [BackpropReason][1], and don't compute the gradients of inputs nobody needs.
+    /// [1]: ./enum.BackpropReason.html
+    pub fn backprop_plan(&self) -> Vec<LayerBackpropInfo> {
+        let mut plan = Vec::new();
+        self.plan_backprop(&mut plan);
+        plan
+    }
+
+    fn plan_backprop(&self, plan: &mut Vec<LayerBackpropInfo>) {
+        if let Some(layers) = self.worker.sublayers() {
+            for layer in layers {
+                layer.borrow().plan_backprop(plan);
+            }
+            return;
+        }
+        plan.push(LayerBackpropInfo {
+            name: self.name.clone(),
+            backward_input: self.needs_backward && self.input_need_backwards.iter().any(|&needed| needed),
+            backward_parameters: self.needs_backward && !self.frozen && !self.weights_data.is_empty(),
+            input_needs_backward: self.input_need_backwards.clone(),
+            reason: self.backprop_reason,
+        });
+    }
+
     /// Expose the internal inputs of a container layer.
     fn expose_inputs(&mut self) {
         if let Some(inputs) = self.worker.inputs_data() {
@@ -479,23 +630,112 @@ impl<B: IBackend> Layer<B> {
         }
     }
 
+    /// Binds the input `input_name` to the tensor `source`.
+    ///
+    /// The layer keeps a reference to `source` instead of copying it, so the output blob of
+    /// another layer can be used as input without a round-trip through host memory. The
+    /// bound input is used by every [forward][1] pass that doesn't provide its own inputs.
+    /// To backpropagate across the connection, pass the input gradients returned by
+    /// [backward][2] as output gradients to the backward pass of the other layer,
+    /// which also shares them by reference.
+    /// [1]: #method.forward
+    /// [2]: #method.backward
+    ///
+    /// Fails if the layer has no input named `input_name`, if the layer is still waiting
+    /// for its input shapes or if `source` doesn't have the size of the input.
+    pub fn connect_input(&mut self, input_name: &str, source: ArcLock<SharedTensor<f32>>) -> Result<(), String> {
+        if self.worker.awaits_input_shapes() {
+            return Err(format!("Layer {} can't connect input {} before its input shapes are known",
+                               self.name,
+                               input_name));
+        }
+        let input_id = try!(self.input_id(input_name)
+            .ok_or_else(|| format!("Layer {} has no input named {}", self.name, input_name)));
+
+        let input_shape = self.input_blobs_data[input_id].read().unwrap().desc().clone();
+        {
+            let mut source = source.write().unwrap();
+            if source.desc().size() != input_shape.size() {
+                return Err(format!("Tensor of shape {:?} can't be connected to input {} of shape {:?}",
+                                   source.desc(),
+                                   input_name,
+                                   input_shape));
+            }
+            source.reshape(&input_shape).unwrap();
+        }
+
+        if let Some(&mut (ref mut data, _)) = self.blob_names.get_mut(input_name) {
+            *data = source.clone();
+        }
+        self.input_blobs_data[input_id] = source;
+        Ok(())
+    }
+
+    /// Returns the index of the input `name`, including the inputs of a Sequential layer.
+    fn input_id(&self, name: &str) -> Option<usize> {
+        if let Some(input_id) = self.input_blob_names.iter().position(|input_name| input_name == name) {
+            return Some(input_id);
+        }
+        match self.config.layer_type {
+            LayerType::Sequential(ref config) => config.inputs.iter().position(|&(ref input_name, _)| input_name == name),
+            _ => None,
+        }
+    }
+
     /// Uses the underlying layer implementation to compute a forward step.
     ///
     /// See [ILayer.forward](./trait.ILayer.html#method.forward)
+    ///
+    /// The inputs may have another batch size, the first dimension, than the layer has been
+    /// created or last run with, as long as each sample has the size the layer expects. The
+    /// layer and all blobs inside of it are then [reshaped][1] to the new batch size before
+    /// the forward step, keeping the weights.
+    /// [1]: #method.reshape_to_inputs
     pub fn forward(&mut self, inputs: &[ArcLock<SharedTensor<f32>>]) -> Vec<ArcLock<SharedTensor<f32>>> {
         debug!("LAYER: {:?}", &self.name);
+        if self.worker.awaits_input_shapes() {
+            let input_shapes = inputs.iter().map(|input| input.read().unwrap().desc().clone()).collect::<Vec<_>>();
+            info!("Layer {} - initializing with input shapes {:?}",
+                  &self.name,
+                  input_shapes);
+            self.worker.init_input_shapes(self.backend.clone(), &input_shapes);
+            self.expose_inputs();
+            self.expose_outputs();
+            // the contained layers have only been created now
+            if let Some(training_backend) = self.training_backend.clone() {
+                self.set_training_backend(training_backend);
+            }
+        }
+        let mut batch_changed = false;
         for (input_i, input) in inputs.iter().enumerate() {
-            let reshaped_shape = self.input_blobs_data[input_i].read().unwrap().desc().clone();
+            let mut reshaped_shape = self.input_blobs_data[input_i].read().unwrap().desc().clone();
             self.input_blobs_data[input_i] = input.clone();
             // reshape input tensor to the reshaped shape
             let old_shape = self.input_blobs_data[input_i].read().unwrap().desc().clone();
             if old_shape.size() != reshaped_shape.size() {
-                panic!("The provided input does not have the expected shape of {:?}",
-                       reshaped_shape);
+                reshaped_shape = match batch_shape(&old_shape, &reshaped_shape) {
+                    Some(shape) => shape,
+                    None => {
+                        panic!("The provided input does not have the expected shape of {:?}",
+                               reshaped_shape)
+                    }
+                };
+                batch_changed = true;
             }
             self.input_blobs_data[input_i].write().unwrap().reshape(&reshaped_shape).unwrap();
         }
+        if batch_changed {
+            info!("Layer {} - reshaping to the batch size of the inputs", &self.name);
+            self.reshape_to_inputs();
+            self.worker.resize_shared_workspace(self.backend.clone(), None);
+        }
 
+        let host_fallback = self.worker.sync_native() && transfer::is_recording();
+        if host_fallback {
+            let native = ::util::native_backend();
+            record_host_fallback(&self.input_blob_names, "", &self.input_blobs_data, native.device());
+        }
+        self.dequantize_weights();
         let forward_time = timeit_loops!(1, {
             if self.is_using_in_place() {
                 self.worker.forward(&self.backend,
@@ -508,20 +748,113 @@ impl<B: IBackend> Layer<B> {
                                     &self.weights_data,
                                     &mut self.output_blobs_data);
             }
+            if host_fallback {
+                record_host_fallback(&self.output_blob_names,
+                                     "",
+                                     &self.output_blobs_data,
+                                     self.backend.device());
+            }
+            self.synchronize_pass();
         });
+        self.release_dequantized_weights();
         debug!("{:<15} - Forward time: {:.5} ms",
                &self.name,
                forward_time / 0.001);
+        if !self.forward_hooks.is_empty() {
+            let inputs = self.input_blobs_data.iter().map(|input| input.read().unwrap()).collect::<Vec<_>>();
+            let outputs = self.output_blobs_data.iter().map(|output| output.read().unwrap()).collect::<Vec<_>>();
+            let inputs = inputs.iter().map(|input| &**input).collect::<Vec<_>>();
+            let outputs = outputs.iter().map(|output| &**output).collect::<Vec<_>>();
+            for hook in &self.forward_hooks {
+                hook.forward(&inputs, &outputs);
+            }
+        }
         self.output_blobs_data.clone()
     }
 
+    /// Registers a hook that is called with the inputs and outputs of the layer `layer_name`
+    /// after each of its forward steps, e.g. for debugging or to extract features.
+    ///
+    /// `layer_name` may be the name of this layer or of any layer inside it, if it is a
+    /// container layer. The hooks of a layer are called in the order they have been
+    /// registered. They only observe the blobs and should return quickly, since the forward
+    /// pass waits for them.
+    ///
+    /// Returns an error if there is no layer named `layer_name`.
+    pub fn register_forward_hook(&mut self, layer_name: &str, hook: Box<ForwardHook>) -> Result<(), String> {
+        self.try_register_forward_hook(layer_name, hook)
+            .map_err(|_| format!("There is no layer named {} in {}", layer_name, self.name))
+    }
+
+    /// Registers the hook at the layer `layer_name` or hands it back if there is no such layer.
+    fn try_register_forward_hook(&mut self, layer_name: &str, hook: Box<ForwardHook>) -> Result<(), Box<ForwardHook>> {
+        if self.name == layer_name {
+            self.forward_hooks.push(hook);
+            return Ok(());
+        }
+        let mut hook = hook;
+        for layer in self.layers() {
+            match layer.borrow_mut().try_register_forward_hook(layer_name, hook) {
+                Ok(()) => return Ok(()),
+                Err(unused) => hook = unused,
+            }
+        }
+        Err(hook)
+    }
+
+    /// Returns the names of the inputs [forward][1] expects, in the order it expects them.
+    /// [1]: #method.forward
+    ///
+    /// For a Sequential layer these are the inputs declared in its [SequentialConfig][2],
+    /// no matter which of the contained layers consume them.
+    /// [2]: ../layers/container/struct.SequentialConfig.html#method.add_input
+    pub fn input_names(&self) -> Vec<String> {
+        match self.config.layer_type {
+            LayerType::Sequential(ref config) => config.inputs.iter().map(|&(ref name, _)| name.clone()).collect(),
+            _ => self.input_blob_names.clone(),
+        }
+    }
+
+    /// Computes a forward step like [forward][1] with the inputs given by their names.
+    /// [1]: #method.forward
+    ///
+    /// Every one of the [input_names][2] has to be provided. Fails if an input is
+    /// missing or if a provided input is not an input of the Layer.
+    /// [2]: #method.input_names
+    pub fn forward_named(&mut self,
+                         inputs: &HashMap<String, ArcLock<SharedTensor<f32>>>)
+                         -> Result<Vec<ArcLock<SharedTensor<f32>>>, String> {
+        let input_names = self.input_names();
+        let mut unused = inputs.keys().filter(|name| !input_names.contains(name)).cloned().collect::<Vec<_>>();
+        if !unused.is_empty() {
+            unused.sort();
+            return Err(format!("Layer {} has no inputs named {:?}", self.name, unused));
+        }
+
+        let mut ordered_inputs = Vec::with_capacity(input_names.len());
+        for name in &input_names {
+            match inputs.get(name) {
+                Some(input) => ordered_inputs.push(input.clone()),
+                None => return Err(format!("Input {} of layer {} has not been provided", name, self.name)),
+            }
+        }
+        Ok(self.forward(&ordered_inputs))
+    }
+
     /// Uses the underlying layer implementation to compute a backward step.
     ///
     /// See [ILayer.backward](./trait.ILayer.html#method.backward)
+    ///
+    /// The gradients w.r.t. the input and the parameters are computed before the single
+    /// [synchronization][1] of the backward step.
+    /// [1]: #method.set_synchronize_passes
     pub fn backward(&mut self, output_gradients: &[ArcLock<SharedTensor<f32>>]) -> Vec<ArcLock<SharedTensor<f32>>> {
         if self.needs_backward {
-            let input_gradients = self.backward_input(output_gradients);
-            self.backward_parameters();
+            let input_gradients = self.compute_backward_input(output_gradients);
+            if !self.frozen {
+                self.compute_backward_parameters();
+            }
+            self.synchronize_pass();
             input_gradients
         } else {
             vec![]
@@ -534,25 +867,49 @@ impl<B: IBackend> Layer<B> {
     pub fn backward_input(&mut self,
                           output_gradients: &[ArcLock<SharedTensor<f32>>])
                           -> Vec<ArcLock<SharedTensor<f32>>> {
+        let input_gradients = self.compute_backward_input(output_gradients);
+        self.synchronize_pass();
+        input_gradients
+    }
+
+    fn compute_backward_input(&mut self,
+                              output_gradients: &[ArcLock<SharedTensor<f32>>])
+                              -> Vec<ArcLock<SharedTensor<f32>>> {
         for (output_i, output) in output_gradients.iter().enumerate() {
             self.output_blobs_gradient[output_i] = output.clone();
         }
+        let host_fallback = self.worker.sync_native() && transfer::is_recording();
+        if host_fallback {
+            let native = ::util::native_backend();
+            record_host_fallback(&self.output_blob_names,
+                                 " gradient",
+                                 &self.output_blobs_gradient,
+                                 native.device());
+        }
 
         if self.is_using_in_place() {
-            self.worker.backward_input(&self.backend,
+            // the gradient is computed in-place on the output gradient
+            self.input_blobs_gradient[0] = self.output_blobs_gradient[0].clone();
+            self.worker.backward_input(self.backward_backend(),
                                        &self.weights_data,
                                        &[],
                                        &[],
                                        &self.input_blobs_data,
                                        &mut self.input_blobs_gradient)
         } else {
-            self.worker.backward_input(&self.backend,
+            self.worker.backward_input(self.backward_backend(),
                                        &self.weights_data,
                                        &self.output_blobs_data,
                                        &self.output_blobs_gradient,
                                        &self.input_blobs_data,
                                        &mut self.input_blobs_gradient)
         }
+        if host_fallback {
+            record_host_fallback(&self.input_blob_names,
+                                 " gradient",
+                                 &self.input_blobs_gradient,
+                                 self.backward_backend().device());
+        }
 
         self.input_blobs_gradient.clone()
     }
@@ -561,18 +918,133 @@ impl<B: IBackend> Layer<B> {
     ///
     /// "Parameters" here refers to weights and also possibly bias, depending on the layer.
     ///
+    /// The gradients are added to the current weight gradients, so that layers which share
+    /// weights accumulate their gradients in the shared gradient blob. Use
+    /// [clear_weights_gradients][1] to reset them before the next backpropagation.
+    /// [1]: #method.clear_weights_gradients
+    ///
     /// This method is mostly used when doing backpropagation.
+    ///
+    /// Does nothing if the layer is [frozen][2].
+    /// [2]: #method.set_frozen
     pub fn backward_parameters(&mut self) {
-        self.worker.backward_parameters(&self.backend,
+        if self.frozen {
+            return;
+        }
+        self.compute_backward_parameters();
+        self.synchronize_pass();
+    }
+
+    fn compute_backward_parameters(&mut self) {
+        self.worker.backward_parameters(self.backward_backend(),
                                         &self.output_blobs_data,
                                         &self.output_blobs_gradient,
                                         &self.input_blobs_data,
-                                        &mut self.weights_gradient)
+                                        &mut self.weights_gradient);
+        if self.worker.sync_native() && transfer::is_recording() {
+            record_host_fallback(&self.weights_display_names,
+                                 " gradient",
+                                 &self.weights_gradient,
+                                 self.backward_backend().device());
+        }
     }
 
     /// Synchronize the layers backend.
+    ///
+    /// Also synchronizes the [training backend][1], if one has been set. Every call is
+    /// counted as a [device synchronization][2] while transfers are recorded.
+    /// [1]: #method.set_training_backend
+    /// [2]: ../transfer/struct.TransferSummary.html#structfield.device_synchronizations
     pub fn synchronize(&self) {
-        self.backend.synchronize().unwrap();
+        transfer::record_device_synchronization(|| {
+            self.backend.synchronize().unwrap();
+            if let Some(ref training_backend) = self.training_backend {
+                training_backend.synchronize().unwrap();
+            }
+        })
+    }
+
+    /// Sets whether the [forward][1], [backward][2], [backward_input][3] and
+    /// [backward_parameters][4] steps of the layer end with a [synchronization][5].
+    ///
+    /// The operations of the layers are queued on the device in the order of the passes,
+    /// so the layers inside a pass don't need to wait for each other. The blobs of a
+    /// network are only guaranteed to be complete once its pass has synchronized, so there
+    /// is exactly one synchronization per pass and it is at the outermost layer:
+    /// A [Sequential][6] layer turns the synchronization of its contained layers off.
+    ///
+    /// Default: `true`
+    /// [1]: #method.forward
+    /// [2]: #method.backward
+    /// [3]: #method.backward_input
+    /// [4]: #method.backward_parameters
+    /// [5]: #method.synchronize
+    /// [6]: ../layers/container/sequential/struct.Sequential.html
+    pub fn set_synchronize_passes(&mut self, synchronize_passes: bool) {
+        self.synchronize_passes = synchronize_passes;
+    }
+
+    fn synchronize_pass(&self) {
+        if self.synchronize_passes {
+            self.synchronize();
+        }
+    }
+
+    /// Use a separate backend for the backward pass of the layer and all the layers it contains.
+    ///
+    /// The forward pass keeps running on the backend the layer has been created with,
+    /// while the gradients are computed and cleared on `backend`. This allows to e.g. train
+    /// on one device and run inference on another. The tensors are migrated between the
+    /// devices of the two backends by the synchronization of the SharedTensors,
+    /// whenever a pass reads a tensor that has last been written on the other device.
+    pub fn set_training_backend(&mut self, backend: Rc<B>) {
+        if let Some(sublayers) = self.worker.sublayers() {
+            for layer in sublayers {
+                layer.borrow_mut().set_training_backend(backend.clone());
+            }
+        }
+        self.training_backend = Some(backend);
+    }
+
+    /// Switches the layer and all the layers it contains between training and inference.
+    ///
+    /// Layers are created in training mode. In inference mode the layers that keep state
+    /// between passes don't update it, e.g. the power iteration of [SpectralNorm][1].
+    /// [1]: ../layers/common/spectral_norm/index.html
+    pub fn set_train(&mut self, train: bool) {
+        if let Some(sublayers) = self.worker.sublayers() {
+            for layer in sublayers {
+                layer.borrow_mut().set_train(train);
+            }
+        }
+        self.worker.set_train(train);
+    }
+
+    /// Tells the layer and all the layers it contains whether the following forward passes
+    /// recompute the activations of the last forward pass for the backward pass, e.g. for
+    /// the [checkpoint segments][1] of a Sequential layer.
+    /// [1]: ../layers/container/struct.SequentialConfig.html#structfield.checkpoint_segments
+    ///
+    /// A recomputing forward pass reproduces the activations the backward pass expects:
+    /// random layers like [Dropout][2] reuse their last random draws and layers like
+    /// [BatchNorm][3] don't update their state a second time.
+    /// [2]: ../layers/common/dropout/index.html
+    /// [3]: ../layers/common/batch_norm/index.html
+    pub fn set_recomputing(&mut self, recomputing: bool) {
+        if let Some(sublayers) = self.worker.sublayers() {
+            for layer in sublayers {
+                layer.borrow_mut().set_recomputing(recomputing);
+            }
+        }
+        self.worker.set_recomputing(recomputing);
+    }
+
+    /// Returns the backend that is used for the backward pass.
+    fn backward_backend(&self) -> &B {
+        match self.training_backend {
+            Some(ref backend) => &**backend,
+            None => &*self.backend,
+        }
     }
 
     /// Updates the [weights][1] with the weight update computed by the [Solver][2].
@@ -583,30 +1055,163 @@ impl<B: IBackend> Layer<B> {
     /// The update value is computed in previous steps according to the [learning rate policy][3]
     ///
     /// [3]: ../solver/enum.LRPolicy.html
+    ///
+    /// The weights of [frozen][4] layers are not updated.
+    /// [4]: #method.set_frozen
+    ///
+    /// In debug builds it is asserted that all weights are [synchronized][5] to the
+    /// device of `backend` first.
+    /// [5]: #method.sync_weights_to
     pub fn update_weights<SolverB: IBackend + ::util::SolverOps<f32>>(&mut self, backend: &SolverB) {
-        // PERF: allocate this scalar once
-        let shared_a = ::util::native_scalar(-1f32);
-        for (weight_gradient, weight_data) in
-            self.learnable_weights_gradients().iter().zip(&mut self.learnable_weights_data()) {
-            backend.axpy(&shared_a,
-                      &weight_gradient.read().unwrap(),
-                      &mut weight_data.write().unwrap())
+        if cfg!(debug_assertions) {
+            self.assert_weights_synced(backend.device());
+        }
+        let weights = self.learnable_weights_gradients()
+            .into_iter()
+            .zip(self.learnable_weights_data())
+            .zip(self.learnable_weights_frozen());
+        for ((weight_gradient, weight_data), frozen) in weights {
+            if frozen {
+                continue;
+            }
+            backend.scaled_add(&mut weight_data.write().unwrap(),
+                            -1f32,
+                            &weight_gradient.read().unwrap())
                 .unwrap();
+            record_weight_written(&weight_data, backend.device());
         }
+        self.apply_pruning_masks(backend);
     }
 
-    /// Clears the [weights][1] gradients and zero-inits them.
-    /// [1]: https://en.wikipedia.org/wiki/Synaptic_weight
+    /// Records that all the learnable weights have been written on `device`, e.g. by a
+    /// solver that updates the weights itself.
     ///
-    /// The gradients for the weights accumulate over the backpropagation steps of
-    /// a [Solver][2] minibatch and are cleared between each minibatch
-    /// to start over with a clean slate.
+    /// Until they are [synchronized][1], no other device holds their latest values.
+    /// [1]: #method.sync_weights_to
+    pub fn record_weights_written<D: IDevice>(&self, device: &D) {
+        for weight in self.learnable_weights_data() {
+            record_weight_written(&weight, device);
+        }
+    }
+
+    /// Prunes the `fraction` of each learnable weight blob with the smallest magnitude.
     ///
-    /// [2]: ../solver/struct.Solver.html
-    pub fn clear_weights_gradients(&mut self) {
-        for weight_gradient in &mut self.learnable_weights_gradients().iter() {
-            let filler = ::weight::FillerType::Constant { value: 0f32 };
-            filler.fill(&mut weight_gradient.write().unwrap());
+    /// The pruned weights are set to zero on the host, which is [recorded][2] as a write, and a
+    /// binary mask is registered for every weight blob. The masks are applied after each
+    /// [weight update][1], so pruned weights stay at zero during further training. Calling
+    /// this repeatedly with a growing `fraction` allows iterative magnitude pruning.
+    ///
+    /// The masks are saved and loaded together with the weights.
+    /// [1]: #method.update_weights
+    /// [2]: ../weight/fn.record_weight_written.html
+    pub fn prune_by_magnitude(&mut self, fraction: f32) -> PruningReport {
+        let names = self.learnable_weights_names();
+        let weights = self.learnable_weights_data();
+        let mut masks = Vec::with_capacity(weights.len());
+        let mut sparsity = Vec::with_capacity(weights.len());
+        for (name, weight) in names.into_iter().zip(weights) {
+            let mask = ::weight::magnitude_mask(&weight.read().unwrap(), fraction);
+            // the mask is applied on the host, which records the write
+            sparsity.push((name, ::weight::apply_mask(&weight, &mask)));
+            masks.push(Arc::new(RwLock::new(mask)));
+        }
+        self.pruning_masks = masks;
+
+        PruningReport { sparsity: sparsity }
+    }
+
+    /// Returns the sparsity of each learnable weight blob.
+    pub fn weights_sparsity(&self) -> PruningReport {
+        let native = ::util::native_backend();
+        let names = self.learnable_weights_names();
+        let weights = self.learnable_weights_data();
+        let mut sparsity = Vec::with_capacity(weights.len());
+        for (name, weight) in names.into_iter().zip(weights) {
+            let weight_lock = weight.read().unwrap();
+            let values = weight_lock.read(native.device()).unwrap().as_slice::<f32>();
+            let num_zeros = values.iter().filter(|&&value| value == 0f32).count();
+            sparsity.push((name, num_zeros as f32 / values.len() as f32));
+        }
+
+        PruningReport { sparsity: sparsity }
+    }
+
+    /// Quantizes the weights of all Linear and Convolution layers to int8 for inference.
+    ///
+    /// Each weight blob is quantized with one scale factor per output channel. The int8 copies
+    /// are kept in the layer (see [quantized_weights][1]) and the memory of the f32 weight blobs
+    /// is released. Each forward pass dequantizes the weights of a layer into its weight blobs
+    /// and releases them again once the layer has computed its outputs, so the activations stay
+    /// f32. The quantized layers can only be used for inference. Other layers are left untouched.
+    ///
+    /// Returns the maximum quantization error of each weight blob.
+    /// [1]: #method.quantized_weights
+    pub fn quantize_linear_weights(&mut self) -> QuantizationReport {
+        let mut report = QuantizationReport::default();
+        if let Some(layers) = self.worker.sublayers() {
+            for layer in layers {
+                report.merge(layer.borrow_mut().quantize_linear_weights());
+            }
+            return report;
+        }
+        match self.config.layer_type {
+            LayerType::Linear(_) | LayerType::Convolution(_) => {}
+            _ => return report,
+        }
+
+        self.quantized_weights.clear();
+        for (name, weight) in self.weights_display_names.iter().zip(&self.weights_data) {
+            let mut weight_lock = weight.write().unwrap();
+            let quantized = QuantizedWeight::quantize(&weight_lock);
+            report.max_error.push((name.clone(), quantized.max_error(&weight_lock)));
+            report.f32_bytes += weight_lock.desc().size() * 4;
+            report.int8_bytes += quantized.size_in_bytes();
+
+            *weight_lock = SharedTensor::new(&quantized.shape);
+            self.quantized_weights.push(quantized);
+        }
+
+        report
+    }
+
+    /// Writes the dequantized values of the int8 copies into the weight blobs, if the layer
+    /// has been quantized.
+    fn dequantize_weights(&self) {
+        for (weight, quantized) in self.weights_data.iter().zip(&self.quantized_weights) {
+            quantized.dequantize(&mut weight.write().unwrap());
+        }
+    }
+
+    /// Releases the memory of the weight blobs that have been dequantized for a forward pass.
+    fn release_dequantized_weights(&self) {
+        for (weight, quantized) in self.weights_data.iter().zip(&self.quantized_weights) {
+            *weight.write().unwrap() = SharedTensor::new(&quantized.shape);
+        }
+    }
+
+    /// Returns the int8 copies of the weights created by [quantize_linear_weights][1].
+    ///
+    /// Empty if the layer has not been quantized or is a container layer.
+    /// [1]: #method.quantize_linear_weights
+    pub fn quantized_weights(&self) -> &[QuantizedWeight] {
+        &self.quantized_weights
+    }
+
+    /// Re-zeroes the pruned weights after they have been changed, on the device of `backend`.
+    ///
+    /// Called by [update_weights][1], and by solvers that update the weights themselves.
+    /// [1]: #method.update_weights
+    pub fn apply_pruning_masks<SolverB: IBackend + ::util::SolverOps<f32>>(&mut self, backend: &SolverB) {
+        for (weight_lock, mask) in self.learnable_weights_data().iter().zip(&self.pruning_masks) {
+            {
+                let mut weight = weight_lock.write().unwrap();
+                let mask = mask.read().unwrap();
+                let mut unmasked = SharedTensor::new(weight.desc());
+                backend.weighted_sum(&[1f32], &[&*weight], &mut unmasked).unwrap();
+                // the ReLU gradient passes the values where the binary mask is positive and zeroes the others
+                backend.relu_grad(&mask, &unmasked, &mask, &mut weight).unwrap();
+            }
+            record_weight_written(weight_lock, backend.device());
         }
     }
 
@@ -628,7 +1233,7 @@ impl<B: IBackend> Layer<B> {
     /// let cfg = LayerConfig::new("network", net_cfg);
     ///
     /// let native_backend = Rc::new(util::native_backend());
-    /// let mut layer = Layer::from_config(native_backend, &cfg);
+    /// let mut layer = Layer::from_config(native_backend, &cfg).unwrap();
     /// // ... do stuff with the layer ...
     /// // ... and save it
     /// layer.save("mynetwork").unwrap();
@@ -647,6 +1252,9 @@ impl<B: IBackend> Layer<B> {
     /// # }
     /// ```
     pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if cfg!(debug_assertions) {
+            self.assert_weights_synced(::util::native_backend().device());
+        }
         let path = path.as_ref();
         let ref mut out = try!(File::create(path));
 
@@ -679,7 +1287,7 @@ impl<B: IBackend> Layer<B> {
     /// let native_backend = Rc::new(util::native_backend());
     /// # let mut net_cfg = SequentialConfig::default();
     /// # let cfg = LayerConfig::new("network", net_cfg);
-    /// # let mut layer = Layer::from_config(native_backend.clone(), &cfg);
+    /// # let mut layer = Layer::from_config(native_backend.clone(), &cfg).unwrap();
     /// # layer.save("mynetwork").unwrap();
     /// // Load layer from file "mynetwork"
     /// let layer = Layer::<Backend<Native>>::load(native_backend, "mynetwork").unwrap();
@@ -705,45 +1313,113 @@ impl<B: IBackend> Layer<B> {
         let mut reader = BufReader::new(file);
 
         let message_reader =
-            ::capnp::serialize_packed::read_message(&mut reader, ::capnp::message::ReaderOptions::new()).unwrap();
-        let read_layer = message_reader.get_root::<capnp_layer::Reader>().unwrap();
+            try!(::capnp::serialize_packed::read_message(&mut reader, ::capnp::message::ReaderOptions::new())
+                .map_err(invalid_data));
+        let read_layer = try!(message_reader.get_root::<capnp_layer::Reader>().map_err(invalid_data));
 
-        let name = read_layer.get_name().unwrap().to_owned();
-        let layer_config = LayerConfig::read_capnp(read_layer.get_config().unwrap());
-        let mut layer = Layer::from_config(backend, &layer_config);
-        layer.name = name;
+        read_capnp_layer(backend, read_layer)
+    }
 
-        let read_weights = read_layer.get_weights_data().unwrap();
+    /// Write the layer into a single self-contained file at `path`, from which
+    /// [load_bundle][1] creates a network that is ready for inference.
+    /// [1]: #method.load_bundle
+    ///
+    /// Besides the config and the weights that [save][2] writes, e.g. the statistics of a
+    /// [MeanScale][3] layer that preprocesses the input, the bundle contains the names and
+    /// shapes of the inputs and the [BUNDLE_FORMAT_VERSION][4].
+    /// [2]: #method.save
+    /// [3]: ../layers/common/mean_scale/index.html
+    /// [4]: ./constant.BUNDLE_FORMAT_VERSION.html
+    pub fn export_bundle<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if cfg!(debug_assertions) {
+            self.assert_weights_synced(::util::native_backend().device());
+        }
+        let ref mut out = try!(File::create(path.as_ref()));
 
-        let names = layer.learnable_weights_names();
-        let weights_data = layer.learnable_weights_data();
-
-        let native_backend = Backend::<Native>::default().unwrap();
-        for (i, (name, weight)) in names.iter().zip(weights_data).enumerate() {
-            for j in 0..read_weights.len() {
-                let capnp_weight = read_weights.get(i as u32);
-                if capnp_weight.get_name().unwrap() != name {
-                    continue;
+        let mut message = ::capnp::message::Builder::new_default();
+        {
+            let mut bundle = message.init_root::<capnp_bundle::Builder>();
+            bundle.set_format_version(BUNDLE_FORMAT_VERSION);
+            self.write_capnp(&mut bundle.borrow().init_layer());
+            let names = self.input_names();
+            let mut inputs = bundle.borrow().init_inputs(names.len() as u32);
+            for (i, name) in names.iter().enumerate() {
+                let mut input = inputs.borrow().get(i as u32);
+                input.set_name(name);
+                let shape = self.input_blobs_data[i].read().unwrap().desc().clone();
+                let mut dimensions = input.borrow().init_shape(shape.len() as u32);
+                for (j, dim) in shape.iter().enumerate() {
+                    dimensions.set(j as u32, *dim as u64);
                 }
+            }
+        }
+        try!(::capnp::serialize_packed::write_message(out, &message));
 
-                let mut weight_lock = weight.write().unwrap();
+        Ok(())
+    }
 
-                let capnp_tensor = capnp_weight.get_tensor().unwrap();
-                let mut shape = Vec::new();
-                let capnp_shape = capnp_tensor.get_shape().unwrap();
-                for k in 0..capnp_shape.len() {
-                    shape.push(capnp_shape.get(k) as usize)
-                }
-                weight_lock.reshape(&shape).unwrap();
+    /// Read a bundle written by [export_bundle][1] at `path` and create the network inside
+    /// it in [inference mode][2].
+    /// [1]: #method.export_bundle
+    /// [2]: #method.set_train
+    ///
+    /// Fails with an error of the kind `InvalidData` if the file is not a bundle, if it has
+    /// been written in another format version, if a section is missing or if the inputs
+    /// don't match the network.
+    pub fn load_bundle<LB: IBackend + LayerOps<f32> + 'static, P: AsRef<Path>>(backend: Rc<LB>,
+                                                                               path: P)
+                                                                               -> io::Result<Layer<LB>> {
+        let path = path.as_ref();
+        let invalid = |message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("Invalid bundle {}: {}", path.display(), message))
+        };
+        let ref mut file = try!(File::open(path));
+        let mut reader = BufReader::new(file);
 
-                let mut native_slice = weight_lock.write_only(native_backend.device()).unwrap().as_mut_slice::<f32>();
-                let data = capnp_tensor.get_data().unwrap();
-                for k in 0..data.len() {
-                    native_slice[k as usize] = data.get(k);
-                }
+        let message_reader =
+            try!(::capnp::serialize_packed::read_message(&mut reader, ::capnp::message::ReaderOptions::new())
+                .map_err(|err| invalid(&err.to_string())));
+        let read_bundle = try!(message_reader.get_root::<capnp_bundle::Reader>()
+            .map_err(|err| invalid(&err.to_string())));
+
+        match read_bundle.get_format_version() {
+            0 => return Err(invalid("the format version is missing")),
+            BUNDLE_FORMAT_VERSION => {}
+            version => {
+                return Err(invalid(&format!("the format version is {}, but only version {} is supported",
+                                            version,
+                                            BUNDLE_FORMAT_VERSION)))
             }
         }
+        for &(section, present) in &[("layer", read_bundle.has_layer()), ("inputs", read_bundle.has_inputs())] {
+            if !present {
+                return Err(invalid(&format!("the {} section is missing", section)));
+            }
+        }
+
+        let mut layer = try!(read_capnp_layer(backend,
+                                              try!(read_bundle.get_layer().map_err(|err| invalid(&err.to_string())))));
+        let read_inputs = try!(read_bundle.get_inputs().map_err(|err| invalid(&err.to_string())));
+        let mut inputs = Vec::new();
+        for i in 0..read_inputs.len() {
+            let input = read_inputs.get(i);
+            let read_shape = try!(input.get_shape().map_err(|err| invalid(&err.to_string())));
+            let shape = (0..read_shape.len()).map(|j| read_shape.get(j) as usize).collect::<Vec<_>>();
+            inputs.push((try!(input.get_name().map_err(|err| invalid(&err.to_string()))).to_owned(), shape));
+        }
+        let expected = layer.input_names()
+            .into_iter()
+            .zip(&layer.input_blobs_data)
+            .map(|(name, input)| (name, input.read().unwrap().desc().clone()))
+            .collect::<Vec<_>>();
+        if inputs != expected {
+            return Err(invalid(&format!("the inputs {:?} don't match the inputs {:?} of the network",
+                                        inputs,
+                                        expected)));
+        }
 
+        layer.set_train(false);
         Ok(layer)
     }
 
@@ -760,48 +1436,566 @@ impl<B: IBackend> Layer<B> {
 
     }
 
-    /// Returns `true` when the layer is using in-place computation.
+    /// Freezes or unfreezes the weights of the layer.
     ///
-    /// For a layer to use in-place computation it needs to support it via `compute_in_place`
-    /// and the names of the first input and output tensor have to match.
-    pub fn is_using_in_place(&self) -> bool {
-        self.worker.compute_in_place() && self.input_blob_names.get(0).is_some() &&
-        self.output_blob_names.get(0).is_some() && self.input_blob_names[0] == self.output_blob_names[0]
+    /// The weights of a frozen layer are neither updated nor are their gradients computed,
+    /// but the gradient w.r.t. the input is still propagated through the layer.
+    /// Freezing a container layer freezes all the layers inside it.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        if let Some(sublayers) = self.worker.sublayers() {
+            for layer in sublayers {
+                layer.borrow_mut().set_frozen(frozen);
+            }
+        } else {
+            self.frozen = frozen;
+        }
     }
 
-    /// Returns the names of all the input blobs.
-    pub fn input_blob_names(&self) -> &[String] {
-        &self.input_blob_names
+    /// Returns `true` if the layer is frozen.
+    ///
+    /// A container layer is frozen if all the layers inside it are frozen.
+    pub fn is_frozen(&self) -> bool {
+        match self.worker.sublayers() {
+            Some(sublayers) => sublayers.iter().all(|layer| layer.borrow().is_frozen()),
+            None => self.frozen,
+        }
     }
 
-    /// Returns the [loss weight][1] associated with the weight blob
-    /// with id `weight_id`.
-    /// [1]: http://caffe.berkeleyvision.org/tutorial/loss.html
-    pub fn loss(&self, weight_id: usize) -> Option<&f32> {
-        self.loss.get(weight_id)
+    /// Freezes or unfreezes all the layers inside a container layer whose name matches `pattern`.
+    ///
+    /// The pattern may contain `*` as a wildcard for any number of characters,
+    /// e.g. `conv*` matches all layers whose name starts with `conv`.
+    /// Container layers inside the layer are searched as well.
+    /// Returns the names of the matching layers.
+    ///
+    /// See [set_frozen][1].
+    /// [1]: #method.set_frozen
+    pub fn set_frozen_matching(&mut self, pattern: &str, frozen: bool) -> Vec<String> {
+        let mut matched = Vec::new();
+        for layer in self.layers() {
+            let mut layer = layer.borrow_mut();
+            if matches_pattern(&layer.name, pattern) {
+                layer.set_frozen(frozen);
+                matched.push(layer.name.clone());
+            } else {
+                matched.extend(layer.set_frozen_matching(pattern, frozen));
+            }
+        }
+        matched
     }
 
-    /// Returns all the learnable weights in the layer.
+    /// Returns the indices of the learnable weights of all the layers inside a container
+    /// layer whose name matches `pattern`, in the order of [learnable_weights_data][1].
     ///
-    /// If the layer is a container layer it will return all the weights of the
-    /// layers inside it.
-    pub fn learnable_weights_data(&self) -> Vec<ArcLock<SharedTensor<f32>>> {
-        if let Some(weights) = self.worker.learnable_weights() {
-            weights
-        } else {
-            self.weights_data.clone()
+    /// The weights of a matching container layer are all included. See
+    /// [set_frozen_matching][2] for the syntax of the pattern.
+    /// [1]: #method.learnable_weights_data
+    /// [2]: #method.set_frozen_matching
+    pub fn learnable_weights_matching(&self, pattern: &str) -> Vec<usize> {
+        self.layers()
+            .flat_map(|layer| layer.borrow().learnable_weights_matched(pattern, false))
+            .enumerate()
+            .filter(|&(_, matched)| matched)
+            .map(|(weight_id, _)| weight_id)
+            .collect()
+    }
+
+    /// Returns for all the learnable weights in the layer whether they belong to a layer
+    /// whose name matches `pattern`, or to one inside of it.
+    fn learnable_weights_matched(&self, pattern: &str, matched: bool) -> Vec<bool> {
+        let matched = matched || matches_pattern(&self.name, pattern);
+        match self.worker.sublayers() {
+            Some(sublayers) => {
+                sublayers.iter().flat_map(|layer| layer.borrow().learnable_weights_matched(pattern, matched)).collect()
+            }
+            None => vec![matched; self.learnable_weights_data().len()],
         }
     }
 
-    /// Returns the gradients for all the learnable weights in the layer.
+    /// Returns for all the learnable weights in the layer whether they are frozen.
     ///
-    /// If the layer is a container layer it will return all the gradients of the
-    /// layers inside it.
+    /// The order is the same as the one of [learnable_weights_data][1].
+    /// [1]: #method.learnable_weights_data
+    pub fn learnable_weights_frozen(&self) -> Vec<bool> {
+        match self.worker.sublayers() {
+            Some(sublayers) => {
+                sublayers.iter().flat_map(|layer| layer.borrow().learnable_weights_frozen()).collect()
+            }
+            None => vec![self.frozen; self.learnable_weights_data().len()],
+        }
+    }
+
+    /// Returns the weight decay multipliers for all the learnable weights in the layer.
+    ///
+    /// Weights without a configured [decay_mult][1] have a multiplier of `1`, except for
+    /// the ones the layer marks as not [decayable][2], like biases, which have a multiplier
+    /// of `0`. The order is the same as the one of [learnable_weights_data][3].
+    /// [1]: ../weight/struct.WeightConfig.html#structfield.decay_mult
+    /// [2]: ./trait.ILayer.html#method.weight_decayable
+    /// [3]: #method.learnable_weights_data
+    pub fn learnable_weights_decay_mult(&self) -> Vec<f32> {
+        match self.worker.sublayers() {
+            Some(sublayers) => {
+                sublayers.iter().flat_map(|layer| layer.borrow().learnable_weights_decay_mult()).collect()
+            }
+            None => {
+                self.weights_weight_decay
+                    .iter()
+                    .zip(&self.weights_decayable)
+                    .map(|(decay_mult, &decayable)| decay_mult.unwrap_or(if decayable { 1f32 } else { 0f32 }))
+                    .collect()
+            }
+        }
+    }
+
+    /// Synchronizes the latest copy of all the learnable weights to `device`.
+    ///
+    /// Weights that have last been written on another device, e.g. filled on the host
+    /// and updated on a CUDA device, are copied to `device`, so it holds their latest values.
+    /// Returns an error naming the weights that have not been written on any device yet,
+    /// as they have no values that could be synchronized.
+    pub fn sync_weights_to<D: IDevice>(&self, device: &D) -> Result<(), String> {
+        let mut unsynced = Vec::new();
+        for (name, weight) in self.learnable_weights_names().into_iter().zip(self.learnable_weights_data()) {
+            if weight_is_latest_on(&weight, device) == Some(true) {
+                continue;
+            }
+            if weight.read().unwrap().read(device).is_err() {
+                unsynced.push(name);
+            } else {
+                record_weight_synced(&weight, device);
+            }
+        }
+        if unsynced.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Layer {}: weights {} can not be synchronized, as they have not been written on any device",
+                        self.name,
+                        unsynced.join(", ")))
+        }
+    }
+
+    /// Returns the names of the learnable weights whose latest copy is not on `device`,
+    /// without synchronizing them.
+    ///
+    /// The latest copy of a weight is known if it has been written by the layer, e.g. by a
+    /// filler or [update_weights][1], or [recorded][2] by a solver. Weights that have been
+    /// written otherwise are not reported.
+    /// [1]: #method.update_weights
+    /// [2]: #method.record_weights_written
+    pub fn unsynced_weights<D: IDevice>(&self, device: &D) -> Vec<String> {
+        self.learnable_weights_names()
+            .into_iter()
+            .zip(self.learnable_weights_data())
+            .filter(|&(_, ref weight)| weight_is_latest_on(weight, device) == Some(false))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Asserts that the latest copy of all the learnable weights is on `device`.
+    ///
+    /// Panics naming the [unsynced weights][1], which need to be [synchronized][2] first.
+    /// [1]: #method.unsynced_weights
+    /// [2]: #method.sync_weights_to
+    pub fn assert_weights_synced<D: IDevice>(&self, device: &D) {
+        let unsynced = self.unsynced_weights(device);
+        if !unsynced.is_empty() {
+            panic!("Layer {}: the latest copy of weights {} is on another device, they need to be synchronized first",
+                   self.name,
+                   unsynced.join(", "));
+        }
+    }
+
+    /// Returns `true` when the layer is using in-place computation.
+    ///
+    /// For a layer to use in-place computation it needs to support it via `compute_in_place`
+    /// and the names of the first input and output tensor have to match.
+    pub fn is_using_in_place(&self) -> bool {
+        self.worker.compute_in_place() && self.input_blob_names.get(0).is_some() &&
+        self.output_blob_names.get(0).is_some() && self.input_blob_names[0] == self.output_blob_names[0]
+    }
+
+    /// Returns the names of all the input blobs.
+    pub fn input_blob_names(&self) -> &[String] {
+        &self.input_blob_names
+    }
+
+    /// Returns the names of all the output blobs.
+    pub fn output_blob_names(&self) -> &[String] {
+        &self.output_blob_names
+    }
+
+    /// Returns the computation graph of the layer in the [Graphviz DOT][1] format.
+    /// [1]: https://www.graphviz.org/doc/info/lang.html
+    ///
+    /// The layers inside a container layer are the nodes of the graph, together with a
+    /// node for every input of the container and the outputs of its last layer.
+    /// The blobs that connect them are the edges, labeled with their name and shape.
+    /// A layer that is not a container is a graph with a single node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph \"{}\" {{\n", dot_escape(&self.name));
+        for input_name in &self.input_blob_names {
+            dot.push_str(&format!("  \"input:{0}\" [label=\"{0}\", shape=ellipse];\n", dot_escape(input_name)));
+        }
+
+        let layers = match self.worker.sublayers() {
+            Some(layers) => layers.iter().map(|layer| layer.borrow()).collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        if layers.is_empty() {
+            dot.push_str(&format!("  \"layer:{0}\" [label=\"{0}\\n{1}\", shape=box];\n",
+                                  dot_escape(&self.name),
+                                  self.layer_type()));
+            for (input_name, input) in self.input_blob_names.iter().zip(&self.input_blobs_data) {
+                dot.push_str(&format!("  \"input:{0}\" -> \"layer:{1}\" [label=\"{0} {2:?}\"];\n",
+                                      dot_escape(input_name),
+                                      dot_escape(&self.name),
+                                      input.read().unwrap().desc()));
+            }
+            dot.push_str("}\n");
+            return dot;
+        }
+
+        for (i, layer) in layers.iter().enumerate() {
+            dot.push_str(&format!("  \"layer:{0}\" [label=\"{0}\\n{1}\", shape=box];\n",
+                                  dot_escape(&layer.name),
+                                  layer.layer_type()));
+            for (input_name, input) in layer.input_blob_names.iter().zip(&layer.input_blobs_data) {
+                // the blob comes from the latest layer that wrote it, or from an input of the container
+                let source = layers[..i]
+                    .iter()
+                    .rev()
+                    .find(|previous| previous.output_blob_names.contains(input_name))
+                    .map(|previous| format!("layer:{}", previous.name))
+                    .unwrap_or_else(|| format!("input:{}", input_name));
+                dot.push_str(&format!("  \"{}\" -> \"layer:{}\" [label=\"{} {:?}\"];\n",
+                                      dot_escape(&source),
+                                      dot_escape(&layer.name),
+                                      dot_escape(input_name),
+                                      input.read().unwrap().desc()));
+            }
+        }
+
+        let last_layer = &layers[layers.len() - 1];
+        for (output_name, output) in last_layer.output_blob_names.iter().zip(&last_layer.output_blobs_data) {
+            dot.push_str(&format!("  \"output:{0}\" [label=\"{0}\", shape=ellipse];\n", dot_escape(output_name)));
+            dot.push_str(&format!("  \"layer:{0}\" -> \"output:{1}\" [label=\"{1} {2:?}\"];\n",
+                                  dot_escape(&last_layer.name),
+                                  dot_escape(output_name),
+                                  output.read().unwrap().desc()));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Estimates the device memory required by the Layer in bytes.
+    ///
+    /// Sums the sizes of the input, output and weight blobs of all layers and of their
+    /// gradients, as well as the size of the workspace. The workspace is shared by all
+    /// layers, so it is as large as the largest workspace any of them requires.
+    /// Blobs that are shared, e.g. by layers computing in-place, are only counted once.
+    pub fn estimated_memory(&self) -> MemoryEstimate {
+        let mut estimate = MemoryEstimate::default();
+        self.estimate_blobs(&mut estimate, &mut HashSet::new());
+        estimate.workspace = self.worker.workspace_size();
+        estimate
+    }
+
+    /// Adds the blobs of the layers to the estimate, skipping the ones that are already `counted`.
+    fn estimate_blobs(&self, estimate: &mut MemoryEstimate, counted: &mut HashSet<*const RwLock<SharedTensor<f32>>>) {
+        if let Some(layers) = self.worker.sublayers() {
+            for layer in layers {
+                layer.borrow().estimate_blobs(estimate, counted);
+            }
+            return;
+        }
+
+        let blobs = self.input_blob_names
+            .iter()
+            .zip(self.input_blobs_data.iter().zip(&self.input_blobs_gradient))
+            .chain(self.output_blob_names.iter().zip(self.output_blobs_data.iter().zip(&self.output_blobs_gradient)));
+        for (name, (data, gradient)) in blobs {
+            let data_bytes = estimate.count_blob(name, data, counted);
+            let gradient_bytes = estimate.count_blob(&format!("{} gradient", name), gradient, counted);
+            estimate.activations += data_bytes;
+            estimate.gradients += gradient_bytes;
+        }
+        let weights = self.weights_display_names.iter().zip(self.weights_data.iter().zip(&self.weights_gradient));
+        for (name, (data, gradient)) in weights {
+            let data_bytes = estimate.count_blob(name, data, counted);
+            let gradient_bytes = estimate.count_blob(&format!("{} gradient", name), gradient, counted);
+            estimate.weights += data_bytes;
+            estimate.gradients += gradient_bytes;
+        }
+    }
+
+    /// Returns the theoretical number of multiply-adds of a forward pass of each layer
+    /// inside the Layer, together with the name of the layer.
+    ///
+    /// The counts are computed from the type of the layers and the shapes of their blobs
+    /// as resolved during initialization, so they are for a whole batch. See
+    /// [ILayer::flops][1] for how the individual layer types are counted.
+    /// [1]: ./trait.ILayer.html#method.flops
+    pub fn flops(&self) -> Vec<(String, u64)> {
+        let mut flops = Vec::new();
+        self.count_flops(&mut flops);
+        flops
+    }
+
+    fn count_flops(&self, flops: &mut Vec<(String, u64)>) {
+        if let Some(layers) = self.worker.sublayers() {
+            for layer in layers {
+                layer.borrow().count_flops(flops);
+            }
+            return;
+        }
+
+        let shapes = |blobs: &[ArcLock<SharedTensor<f32>>]| {
+            blobs.iter().map(|blob| blob.read().unwrap().desc().clone()).collect::<Vec<_>>()
+        };
+        let count = self.worker.flops(&shapes(&self.input_blobs_data), &shapes(&self.output_blobs_data));
+        flops.push((self.name.clone(), count));
+    }
+
+    /// Checks the [estimated memory][1] of the Layer against a budget of `budget` bytes.
+    /// [1]: #method.estimated_memory
+    pub fn check_memory_budget(&self, budget: usize) -> Result<MemoryEstimate, MemoryBudgetExceeded> {
+        let estimate = self.estimated_memory();
+        if estimate.total() > budget {
+            return Err(MemoryBudgetExceeded {
+                budget: budget,
+                estimate: estimate,
+            });
+        }
+        Ok(estimate)
+    }
+
+    /// Returns the [loss weight][1] associated with the weight blob
+    /// with id `weight_id`.
+    /// [1]: http://caffe.berkeleyvision.org/tutorial/loss.html
+    pub fn loss(&self, weight_id: usize) -> Option<&f32> {
+        self.loss.get(weight_id)
+    }
+
+    /// Returns the weighted sum of the losses computed by the last forward pass.
+    ///
+    /// Sums the outputs of all loss layers, including the ones contained in a container layer,
+    /// scaled by their [loss weight][1]. The sum is accumulated in `f32`, unless the layer is a
+    /// Sequential layer with [accumulate_loss_in_f64][2] set, which reduces the summation error
+    /// for many loss layers or large loss outputs.
+    /// [1]: ./trait.ILayer.html#method.loss_weight
+    /// [2]: ../layers/container/struct.SequentialConfig.html#structfield.accumulate_loss_in_f64
+    pub fn total_loss(&self) -> f32 {
+        let in_f64 = match self.config.layer_type {
+            LayerType::Sequential(ref config) => config.accumulate_loss_in_f64,
+            _ => false,
+        };
+        sum_losses(&self.losses(), in_f64)
+    }
+
+    /// Returns the weighted values of all loss outputs of the layer and the layers it contains.
+    fn losses(&self) -> Vec<f32> {
+        let mut losses = Vec::new();
+        for layer in self.layers() {
+            losses.extend(layer.borrow().losses());
+        }
+        let native = ::util::native_backend();
+        for (output_id, output) in self.output_blobs_data.iter().enumerate() {
+            if let Some(loss_weight) = self.worker.loss_weight(output_id) {
+                let output = output.read().unwrap();
+                // anonymous loss outputs are attributed to the layer
+                let blob = self.output_blob_names.get(output_id).unwrap_or(&self.name);
+                let bytes = output.desc().size() * mem::size_of::<f32>();
+                let values = transfer::record(TransferCategory::LossReadback,
+                                              blob,
+                                              bytes,
+                                              || output.read(native.device()).unwrap().as_slice::<f32>().to_vec());
+                losses.extend(values.iter().map(|value| loss_weight * value));
+            }
+        }
+        losses
+    }
+
+    /// Returns the loss of each sample of the last forward pass.
+    ///
+    /// Sums the [per-example losses][1] of all loss layers, including the ones contained in a
+    /// container layer, scaled by their [loss weight][2]. The mean of the per-example losses
+    /// equals the [total loss][3] if all samples contribute to it.
+    /// Returns `None` if the layer contains no loss layer.
+    /// [1]: ./trait.ILayer.html#method.per_example_loss
+    /// [2]: ./trait.ILayer.html#method.loss_weight
+    /// [3]: #method.total_loss
+    pub fn per_example_losses(&self) -> Option<Vec<f32>> {
+        let mut losses: Option<Vec<f32>> = None;
+        for layer in self.layers() {
+            if let Some(layer_losses) = layer.borrow().per_example_losses() {
+                losses = Some(add_losses(losses, layer_losses));
+            }
+        }
+        if let Some(loss_weight) = self.worker.loss_weight(0) {
+            let inputs = self.input_blobs_data.iter().map(|input| input.read().unwrap()).collect::<Vec<_>>();
+            let input_refs = inputs.iter().map(|input| &**input).collect::<Vec<&SharedTensor<f32>>>();
+            if let Some(layer_losses) = self.worker.per_example_loss(&input_refs) {
+                let layer_losses = layer_losses.iter().map(|loss| loss_weight * loss).collect();
+                losses = Some(add_losses(losses, layer_losses));
+            }
+        }
+        losses
+    }
+
+    /// Returns the configuration the layer has been created from.
+    pub fn config(&self) -> &LayerConfig {
+        &self.config
+    }
+
+    /// Returns the backend the layer computes its forward pass on.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns the name of the type of the layer, e.g. `"Linear"`.
+    ///
+    /// See [LayerType::name][1].
+    /// [1]: ./enum.LayerType.html#method.name
+    pub fn layer_type(&self) -> &str {
+        self.config.layer_type.name()
+    }
+
+    /// Returns the display name, data and gradient of all the learnable weights in the layer.
+    ///
+    /// If the layer is a container layer it will return the weights of all the
+    /// layers inside it.
+    ///
+    /// The tensors are shared with the layer, so an external optimizer can read the
+    /// gradients after [backward][1] and write the updated weights directly into them.
+    /// [1]: #method.backward
+    pub fn weight_blobs(&self) -> Vec<(String, ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)> {
+        self.learnable_weights_names()
+            .into_iter()
+            .zip(self.learnable_weights_data())
+            .zip(self.learnable_weights_gradients())
+            .map(|((name, data), gradient)| (name, data, gradient))
+            .collect()
+    }
+
+    /// Returns an iterator over the layers inside a container layer.
+    ///
+    /// The iterator is empty if the layer is not a container layer.
+    pub fn layers(&self) -> ::std::slice::Iter<RefCell<Layer<B>>> {
+        self.worker.sublayers().unwrap_or(&[]).iter()
+    }
+
+    /// Returns the data of the blob `name`.
+    ///
+    /// If the layer is a container layer it will also look through the blobs of the
+    /// layers inside it.
+    pub fn blob_data(&self, name: &str) -> Option<ArcLock<SharedTensor<f32>>> {
+        if let Some(blob) = self.worker.blob_data(name) {
+            return Some(blob);
+        }
+        if let Some(&(ref data, _)) = self.blob_names.get(name) {
+            return Some(data.clone());
+        }
+        self.input_blob_names
+            .iter()
+            .position(|input_name| input_name == name)
+            .map(|input_id| self.input_blobs_data[input_id].clone())
+    }
+
+    /// Returns the data of the blob `name` like [blob_data][1], but fails with an error
+    /// that lists the [available blobs][2] if there is no blob with that name.
+    /// [1]: #method.blob_data
+    /// [2]: #method.available_blob_names
+    pub fn try_blob_data(&self, name: &str) -> Result<ArcLock<SharedTensor<f32>>, String> {
+        self.blob_data(name).ok_or_else(|| {
+            format!("Layer {} has no blob named {}, available blobs: {}",
+                    self.name,
+                    name,
+                    self.available_blob_names().join(", "))
+        })
+    }
+
+    /// Returns the sorted names of the blobs that [blob_data][1] can return.
+    /// [1]: #method.blob_data
+    pub fn available_blob_names(&self) -> Vec<String> {
+        let mut names = HashSet::new();
+        self.collect_blob_names(&mut names);
+        let mut names = names.into_iter().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn collect_blob_names(&self, names: &mut HashSet<String>) {
+        names.extend(self.input_names());
+        names.extend(self.input_blob_names.iter().cloned());
+        names.extend(self.output_blob_names.iter().cloned());
+        names.extend(self.blob_names.keys().cloned());
+        for layer in self.layers() {
+            layer.borrow().collect_blob_names(names);
+        }
+    }
+
+    /// Copies the data of the blob `name` into `buffer` without allocating.
+    ///
+    /// The blob is synchronized to the host memory of `native` first, so a caller that reads
+    /// blobs repeatedly, e.g. once per decoding step, should reuse the same native backend.
+    /// Returns an error if there is no blob with that name or if the length of `buffer`
+    /// does not match the size of the blob.
+    pub fn read_blob_into(&self, native: &Backend<Native>, name: &str, buffer: &mut [f32]) -> Result<(), String> {
+        let blob = try!(self.try_blob_data(name));
+        let blob_lock = blob.read().unwrap();
+        if blob_lock.desc().size() != buffer.len() {
+            return Err(format!("Buffer of length {} does not match the size {} of blob {}",
+                               buffer.len(),
+                               blob_lock.desc().size(),
+                               name));
+        }
+
+        let data = try!(blob_lock.read(native.device()).map_err(|e| format!("{:?}", e)));
+        buffer.copy_from_slice(data.as_slice::<f32>());
+        Ok(())
+    }
+
+    /// Fills the learnable weight with the display name `name` with the values returned by
+    /// `filler`, see [FillerType::fill_with][1].
+    /// [1]: ../weight/enum.FillerType.html#method.fill_with
+    ///
+    /// Returns an error if the layer has no learnable weight with that name.
+    pub fn fill_weight_with<F: Fn(&[usize], usize) -> f32>(&self, name: &str, filler: F) -> Result<(), String> {
+        let weight_id = try!(self.learnable_weights_names()
+            .iter()
+            .position(|weight_name| weight_name == name)
+            .ok_or_else(|| format!("Layer {} has no learnable weight named {}", self.name, name)));
+        let weight = self.learnable_weights_data()[weight_id].clone();
+        FillerType::fill_with(&mut weight.write().unwrap(), filler);
+        record_weight_written(&weight, ::util::native_backend().device());
+        Ok(())
+    }
+
+    /// Returns all the learnable weights in the layer.
+    ///
+    /// If the layer is a container layer it will return all the weights of the
+    /// layers inside it.
+    ///
+    /// Weights that are shared between layers are only returned by the layer that
+    /// created them.
+    pub fn learnable_weights_data(&self) -> Vec<ArcLock<SharedTensor<f32>>> {
+        if let Some(weights) = self.worker.learnable_weights() {
+            weights
+        } else {
+            self.learnable_weight_ids.iter().map(|&id| self.weights_data[id].clone()).collect()
+        }
+    }
+
+    /// Returns the gradients for all the learnable weights in the layer.
+    ///
+    /// If the layer is a container layer it will return all the gradients of the
+    /// layers inside it.
     pub fn learnable_weights_gradients(&self) -> Vec<ArcLock<SharedTensor<f32>>> {
         if let Some(gradients) = self.worker.learnable_weights_gradients() {
             gradients
         } else {
-            self.weights_gradient.clone()
+            self.learnable_weight_ids.iter().map(|&id| self.weights_gradient[id].clone()).collect()
         }
     }
 
@@ -813,7 +2007,34 @@ impl<B: IBackend> Layer<B> {
         if let Some(names) = self.worker.learnable_weights_names() {
             names
         } else {
-            self.weights_display_names.clone()
+            self.learnable_weight_ids.iter().map(|&id| self.weights_display_names[id].clone()).collect()
+        }
+    }
+
+    /// Returns the weights in the layer that are not learnable, e.g. the statistics of a
+    /// [MeanScale][1] layer.
+    /// [1]: ../layers/common/mean_scale/index.html
+    ///
+    /// They are serialized like the learnable weights, but the [Solver][2] doesn't update
+    /// them. If the layer is a container layer it will return the non-learnable weights
+    /// of the layers inside it.
+    /// [2]: ../solver/struct.Solver.html
+    pub fn non_learnable_weights_data(&self) -> Vec<ArcLock<SharedTensor<f32>>> {
+        if let Some(weights) = self.worker.non_learnable_weights() {
+            weights
+        } else {
+            self.non_learnable_weight_ids.iter().map(|&id| self.weights_data[id].clone()).collect()
+        }
+    }
+
+    /// Returns the names of the weights in the layer that are not learnable, in the order
+    /// of [non_learnable_weights_data][1].
+    /// [1]: #method.non_learnable_weights_data
+    pub fn non_learnable_weights_names(&self) -> Vec<String> {
+        if let Some(names) = self.worker.non_learnable_weights_names() {
+            names
+        } else {
+            self.non_learnable_weight_ids.iter().map(|&id| self.weights_display_names[id].clone()).collect()
         }
     }
 
@@ -832,6 +2053,25 @@ impl<B: IBackend> Layer<B> {
     }
 }
 
+/// Escapes `name` for a quoted ID of the DOT language.
+fn dot_escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Returns `true` if `name` matches `pattern`, where `*` matches any number of characters.
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match pattern.find('*') {
+        None => name == pattern,
+        Some(star) => {
+            let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+            name.starts_with(prefix) &&
+            (prefix.len()..name.len() + 1)
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| matches_pattern(&name[i..], rest))
+        }
+    }
+}
+
 #[allow(unsafe_code)]
 unsafe impl<B: IBackend> Send for Layer<B> {}
 
@@ -846,55 +2086,365 @@ impl<'a, B: IBackend> CapnpWrite<'a> for Layer<B> {
             self.config.write_capnp(&mut layer_config);
         }
         {
-            let native_backend = Backend::<Native>::default().unwrap();
-            let mut weights = builder.borrow().init_weights_data(self.learnable_weights_names().len() as u32);
-            let names = self.learnable_weights_names();
-            let weights_data = self.learnable_weights_data();
+            // the non-learnable weights are stored after the learnable ones
+            let mut names = self.learnable_weights_names();
+            names.extend(self.non_learnable_weights_names());
+            let mut weights_data = self.learnable_weights_data();
+            weights_data.extend(self.non_learnable_weights_data());
+            let mut weights = builder.borrow().init_weights_data(names.len() as u32);
 
             for (i, (name, weight)) in names.iter().zip(weights_data).enumerate() {
-                let mut capnp_weight = weights.borrow().get(i as u32);
-                capnp_weight.set_name(name);
+                let capnp_weight = weights.borrow().get(i as u32);
+                write_capnp_weight(capnp_weight, name, &weight.read().unwrap());
+            }
+        }
+        {
+            let mut masks = builder.borrow().init_pruning_masks(self.pruning_masks.len() as u32);
+            let names = self.learnable_weights_names();
 
-                let weight_lock = weight.write().unwrap();
+            for (i, (name, mask)) in names.iter().zip(&self.pruning_masks).enumerate() {
+                let capnp_mask = masks.borrow().get(i as u32);
+                write_capnp_weight(capnp_mask, name, &mask.read().unwrap());
+            }
+        }
+    }
+}
 
-                let mut tensor = capnp_weight.init_tensor();
-                {
-                    let mut tensor_shape = tensor.borrow().init_shape(weight_lock.desc().len() as u32);
-                    for (i, dim) in weight_lock.desc().iter().enumerate() {
-                        tensor_shape.set(i as u32, *dim as u64);
-                    }
-                }
-                {
-                    let native_slice = weight_lock.read(native_backend.device())
-                        .unwrap().as_slice::<f32>();
-                    let mut tensor_data = tensor.borrow().init_data(native_slice.len() as u32);
-                    for (i, datum) in native_slice.iter().enumerate() {
-                        tensor_data.set(i as u32, *datum);
-                    }
+/// Write a named tensor into a capnp message.
+fn write_capnp_weight(mut capnp_weight: capnp_weight::Builder, name: &str, weight: &SharedTensor<f32>) {
+    let native_backend = Backend::<Native>::default().unwrap();
+    capnp_weight.set_name(name);
+
+    let mut tensor = capnp_weight.init_tensor();
+    {
+        let mut tensor_shape = tensor.borrow().init_shape(weight.desc().len() as u32);
+        for (i, dim) in weight.desc().iter().enumerate() {
+            tensor_shape.set(i as u32, *dim as u64);
+        }
+    }
+    {
+        let native_slice = weight.read(native_backend.device())
+            .unwrap().as_slice::<f32>();
+        let mut tensor_data = tensor.borrow().init_data(native_slice.len() as u32);
+        for (i, datum) in native_slice.iter().enumerate() {
+            tensor_data.set(i as u32, *datum);
+        }
+    }
+}
+
+/// Create the layer serialized in `read_layer` and load its weights.
+///
+/// Fails with an error of the kind `InvalidData` if a part of the layer is missing or
+/// malformed, or if a weight doesn't fit the weight of the layer it is loaded into.
+fn read_capnp_layer<LB: IBackend + LayerOps<f32> + 'static>(backend: Rc<LB>,
+                                                            read_layer: capnp_layer::Reader)
+                                                            -> io::Result<Layer<LB>> {
+    let name = try!(read_layer.get_name().map_err(invalid_data)).to_owned();
+    let layer_config = LayerConfig::read_capnp(try!(read_layer.get_config().map_err(invalid_data)));
+    let mut layer = try!(Layer::from_config(backend, &layer_config).map_err(invalid_data));
+    layer.name = name;
+
+    let read_weights = try!(read_layer.get_weights_data().map_err(invalid_data));
+
+    let mut names = layer.learnable_weights_names();
+    names.extend(layer.non_learnable_weights_names());
+    let mut weights_data = layer.learnable_weights_data();
+    weights_data.extend(layer.non_learnable_weights_data());
+
+    for (name, weight) in names.iter().zip(weights_data) {
+        for j in 0..read_weights.len() {
+            let capnp_weight = read_weights.get(j);
+            if try!(capnp_weight.get_name().map_err(invalid_data)) != name {
+                continue;
+            }
+
+            let tensor = try!(read_capnp_tensor(try!(capnp_weight.get_tensor().map_err(invalid_data))));
+            let mut weight_lock = weight.write().unwrap();
+            if tensor.desc().size() != weight_lock.desc().size() {
+                return Err(invalid_data(format!("the weight {} of shape {:?} doesn't fit the shape {:?}",
+                                                name,
+                                                tensor.desc(),
+                                                weight_lock.desc())));
+            }
+            *weight_lock = tensor;
+        }
+    }
+
+    let read_masks = try!(read_layer.get_pruning_masks().map_err(invalid_data));
+    if read_masks.len() > 0 {
+        let names = layer.learnable_weights_names();
+        for (name, weight) in names.iter().zip(layer.learnable_weights_data()) {
+            let mut mask = None;
+            for j in 0..read_masks.len() {
+                let capnp_mask = read_masks.get(j);
+                if try!(capnp_mask.get_name().map_err(invalid_data)) == name {
+                    mask = Some(try!(read_capnp_tensor(try!(capnp_mask.get_tensor().map_err(invalid_data)))));
                 }
             }
+            // weights without a stored mask are not pruned
+            let mask = mask.unwrap_or_else(|| {
+                let mut ones = SharedTensor::new(weight.read().unwrap().desc());
+                FillerType::fill_constant(&mut ones, 1f32);
+                ones
+            });
+            layer.pruning_masks.push(Arc::new(RwLock::new(mask)));
+        }
+    }
+
+    Ok(layer)
+}
+
+/// Read a tensor from a capnp message.
+///
+/// Fails with an error of the kind `InvalidData` if the shape or the data is missing or
+/// if they don't fit each other.
+fn read_capnp_tensor(capnp_tensor: capnp_tensor::Reader) -> io::Result<SharedTensor<f32>> {
+    let native_backend = Backend::<Native>::default().unwrap();
+    let mut shape = Vec::new();
+    let capnp_shape = try!(capnp_tensor.get_shape().map_err(invalid_data));
+    for k in 0..capnp_shape.len() {
+        shape.push(capnp_shape.get(k) as usize)
+    }
+
+    let data = try!(capnp_tensor.get_data().map_err(invalid_data));
+    if data.len() as usize != shape.size() {
+        return Err(invalid_data(format!("the tensor of shape {:?} has {} values", shape, data.len())));
+    }
+    let mut tensor = SharedTensor::new(&shape);
+    {
+        let native_slice = tensor.write_only(native_backend.device()).unwrap().as_mut_slice::<f32>();
+        for k in 0..data.len() {
+            native_slice[k as usize] = data.get(k);
         }
     }
+    Ok(tensor)
+}
+
+/// Returns an error of the kind `InvalidData` that describes a malformed serialized layer.
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid layer: {}", err.to_string()))
+}
+
+/// Synchronizes `blobs` to `device` and records every synchronization as a
+/// [host fallback][1] transfer of the blob with the same index in `names`, followed by
+/// `suffix`.
+/// [1]: ../transfer/enum.TransferCategory.html#variant.HostFallback
+///
+/// Used around a layer that computes on the host, which would otherwise synchronize its
+/// blobs implicitly.
+fn record_host_fallback<D: IDevice>(names: &[String], suffix: &str, blobs: &[ArcLock<SharedTensor<f32>>], device: &D) {
+    for (name, blob) in names.iter().zip(blobs) {
+        let blob = blob.read().unwrap();
+        let bytes = blob.desc().size() * mem::size_of::<f32>();
+        // a blob that has not been written on any device yet can't be synchronized
+        let _ = transfer::record(TransferCategory::HostFallback,
+                                 &format!("{}{}", name, suffix),
+                                 bytes,
+                                 || blob.read(device).is_ok());
+    }
+}
+
+/// Returns the shape `expected_shape` with the batch size of the input of shape
+/// `input_shape`, or `None` if the samples of the input don't have the expected size.
+fn batch_shape(input_shape: &[usize], expected_shape: &[usize]) -> Option<Vec<usize>> {
+    if input_shape.is_empty() || expected_shape.is_empty() || input_shape[0] == 0 {
+        return None;
+    }
+    let sample_size = expected_shape[1..].iter().fold(1, |size, dim| size * dim);
+    if input_shape.size() != input_shape[0] * sample_size {
+        return None;
+    }
+    let mut shape = expected_shape.to_vec();
+    shape[0] = input_shape[0];
+    Some(shape)
 }
 
 impl<B: IBackend + LayerOps<f32> + 'static> Layer<B> {
     /// Creates a new Layer from a [LayerConfig][1].
     /// [1]: ./struct.LayerConfig.html
-    pub fn from_config(backend: Rc<B>, config: &LayerConfig) -> Layer<B> {
+    ///
+    /// Returns an error if the config of the Layer, or of any layer inside of it, is
+    /// [invalid for its layer type][2], or if the network exceeds the [memory budget][3]
+    /// of its SequentialConfig.
+    /// [2]: ./enum.LayerType.html#method.validate
+    /// [3]: ../layers/container/struct.SequentialConfig.html#structfield.memory_budget_bytes
+    ///
+    /// The budget is checked before any memory is allocated: the network is first
+    /// constructed [with placeholder weights][4], whose blobs hold no memory, to estimate
+    /// its memory, and only constructed for real if the estimate fits into the budget.
+    /// [4]: ../weight/fn.with_placeholder_weights.html
+    pub fn from_config(backend: Rc<B>, config: &LayerConfig) -> Result<Layer<B>, LayerError> {
+        if let LayerType::Sequential(ref sequential_config) = config.layer_type {
+            if let Some(budget) = sequential_config.memory_budget_bytes {
+                let placeholder = try!(with_placeholder_weights(|| Layer::construct(backend.clone(), config)));
+                try!(placeholder.check_memory_budget(budget));
+            }
+        }
+        Layer::construct(backend, config)
+    }
+
+    /// Creates a new Layer from a [LayerConfig][1] without checking its memory budget.
+    /// [1]: ./struct.LayerConfig.html
+    fn construct(backend: Rc<B>, config: &LayerConfig) -> Result<Layer<B>, LayerError> {
+        let worker = try!(Layer::<B>::worker_from_config(backend.clone(), config));
+        Ok(Layer::from_worker(backend, config, worker))
+    }
+
+    /// Creates a new Layer from a [LayerConfig][1] like [from_config][2] and returns it
+    /// together with the [InitReport][3] of its initialization.
+    /// [1]: ./struct.LayerConfig.html
+    /// [2]: #method.from_config
+    /// [3]: ./struct.InitReport.html
+    pub fn from_config_with_report(backend: Rc<B>,
+                                   config: &LayerConfig)
+                                   -> Result<(Layer<B>, InitReport), LayerError> {
+        let layer = try!(Layer::from_config(backend, config));
+        let report = layer.init_report();
+        Ok((layer, report))
+    }
+
+    /// Returns what the initialization resolved for each layer inside the Layer.
+    ///
+    /// See [InitReport][1].
+    /// [1]: ./struct.InitReport.html
+    pub fn init_report(&self) -> InitReport {
+        let mut report = InitReport::default();
+        self.report_layers(&mut report);
+        report
+    }
+
+    fn report_layers(&self, report: &mut InitReport) {
+        if let Some(layers) = self.worker.sublayers() {
+            for layer in layers {
+                layer.borrow().report_layers(report);
+            }
+            return;
+        }
+
+        let blob_report = |name: &String, blob: &ArcLock<SharedTensor<f32>>| {
+            BlobReport {
+                name: name.clone(),
+                shape: blob.read().unwrap().desc().clone(),
+            }
+        };
+        let weights = self.weights_display_names
+            .iter()
+            .zip(&self.weights_data)
+            .enumerate()
+            .map(|(weight_id, (name, data))| {
+                WeightReport {
+                    name: name.clone(),
+                    shape: data.read().unwrap().desc().clone(),
+                    filler: self.weights_filler.get(&weight_id).map(|filler| format!("{:?}", filler)),
+                    shared: !self.learnable_weight_ids.contains(&weight_id),
+                }
+            })
+            .collect();
+        report.layers.push(LayerInitReport {
+            name: self.name.clone(),
+            layer_type: self.config.layer_type.name().to_owned(),
+            inputs: self.input_blob_names.iter().zip(&self.input_blobs_data).map(|(n, b)| blob_report(n, b)).collect(),
+            outputs: self.output_blob_names.iter().zip(&self.output_blobs_data).map(|(n, b)| blob_report(n, b)).collect(),
+            weights: weights,
+            needs_backward: self.needs_backward,
+            input_needs_backward: self.input_need_backwards.clone(),
+        });
+    }
+
+    /// Runs a single forward pass of the Layer described by `config` on `inputs` and
+    /// returns copies of its outputs.
+    ///
+    /// The Layer is connected to blobs created from the inputs instead of being part of
+    /// a container, which allows to test a layer implementation in isolation.
+    /// The inputs and outputs given in the `config` are replaced by these blobs.
+    pub fn run_standalone(backend: Rc<B>, config: &LayerConfig, inputs: &[SharedTensor<f32>]) -> Vec<SharedTensor<f32>> {
+        let native = ::util::native_backend();
+        let copy = |tensor: &SharedTensor<f32>| {
+            let mut copy = SharedTensor::new(tensor.desc());
+            ::util::write_to_memory(copy.write_only(native.device()).unwrap(),
+                                    tensor.read(native.device()).unwrap().as_slice::<f32>());
+            copy
+        };
+
+        let mut config = config.clone();
+        config.inputs = (0..inputs.len()).map(|i| format!("standalone_input_{}", i)).collect();
+        config.outputs = Vec::new();
+        let mut layer = Layer::from_config(backend, &config).unwrap_or_else(|err| panic!("{}", err));
+        let num_outputs = layer.worker
+            .exact_num_output_blobs()
+            .unwrap_or_else(|| cmp::max(layer.worker.min_output_blobs(), 1));
+        for i in 0..num_outputs {
+            layer.config.add_output(&format!("standalone_output_{}", i));
+        }
+
+        let mut registry = HashMap::new();
+        let mut input_blobs = Vec::new();
+        for (name, input) in config.inputs.iter().zip(inputs) {
+            let data = Arc::new(RwLock::new(copy(input)));
+            let gradient = Arc::new(RwLock::new(SharedTensor::new(input.desc())));
+            registry.insert(name.clone(), (data.clone(), gradient));
+            input_blobs.push(data);
+        }
+        layer.connect(&mut registry, &mut HashMap::new());
+
+        layer.forward(&input_blobs)
+            .iter()
+            .map(|output| copy(&output.read().unwrap()))
+            .collect()
+    }
+
+    /// Creates a new Layer from a [LayerConfig][1] that is initialized on the first [forward][2].
+    /// [1]: ./struct.LayerConfig.html
+    /// [2]: #method.forward
+    ///
+    /// For a Sequential layer the shapes of the inputs in the [SequentialConfig][3] are
+    /// ignored and can be left empty. The contained layers and their weights are only
+    /// created once the first forward call supplies concrete inputs, whose shapes are
+    /// then used to complete the initialization.
+    /// Until then the layer has no learnable weights.
+    ///
+    /// All other layer types are initialized immediately, like with [from_config][4].
+    /// [3]: ../layers/container/struct.SequentialConfig.html
+    /// [4]: #method.from_config
+    ///
+    /// The contained layers of a lazy Sequential layer are only validated once they are
+    /// created, so an invalid config panics on the first forward.
+    pub fn from_config_lazy(backend: Rc<B>, config: &LayerConfig) -> Result<Layer<B>, LayerError> {
+        let worker: Box<ILayer<B>> = match config.layer_type {
+            LayerType::Sequential(ref layer_config) => Box::new(Sequential::lazy(layer_config)),
+            _ => try!(Layer::<B>::worker_from_config(backend.clone(), config)),
+        };
+        Ok(Layer::from_worker(backend, config, worker))
+    }
+
+    /// Helper for [from_config] and [from_config_lazy] to wrap a layer implementation.
+    /// [1]: #method.from_config
+    /// [2]: #method.from_config_lazy
+    fn from_worker(backend: Rc<B>, config: &LayerConfig, worker: Box<ILayer<B>>) -> Layer<B> {
         let cl = config.clone();
         let cfg = Box::<LayerConfig>::new(cl);
         let mut layer = Layer {
             name: cfg.name.clone(),
 
             needs_backward: true,
+            backprop_reason: BackpropReason::Standalone,
+            frozen: false,
+            synchronize_passes: true,
 
             weights_data: Vec::new(),
             weights_gradient: Vec::new(),
             learnable_weights: Vec::new(),
+            learnable_weight_ids: Vec::new(),
+            non_learnable_weight_ids: Vec::new(),
             weight_propagate_down: Vec::new(),
             weights_lr: Vec::new(),
             weights_weight_decay: Vec::new(),
+            weights_decayable: Vec::new(),
             weights_display_names: Vec::new(),
+            weights_filler: HashMap::new(),
+            pruning_masks: Vec::new(),
+            quantized_weights: Vec::new(),
+            spectral_vectors: RefCell::new(HashMap::new()),
 
             input_blobs_data: Vec::new(),
             input_blobs_gradient: Vec::new(),
@@ -908,36 +2458,456 @@ impl<B: IBackend + LayerOps<f32> + 'static> Layer<B> {
 
             blob_names: HashMap::new(),
 
-            backend: backend.clone(),
+            forward_hooks: Vec::new(),
+
+            backend: backend,
+            training_backend: None,
+
+            worker: worker,
+            config: cfg,
+        };
+        layer.expose_inputs();
+        layer.expose_outputs();
+
+        layer
+    }
+
+    /// Clears the [weights][1] gradients and zero-inits them.
+    /// [1]: https://en.wikipedia.org/wiki/Synaptic_weight
+    ///
+    /// The gradients for the weights accumulate over the backpropagation steps of
+    /// a [Solver][2] minibatch and are cleared between each minibatch
+    /// to start over with a clean slate.
+    ///
+    /// [2]: ../solver/struct.Solver.html
+    pub fn clear_weights_gradients(&mut self) {
+        for weight_gradient in &mut self.learnable_weights_gradients().iter() {
+            self.backward_backend().fill(&mut weight_gradient.write().unwrap(), 0f32).unwrap();
+        }
+    }
+
+    /// Estimates the spectral norm, the largest singular value, of the learnable weight
+    /// `weight_name` with `iters` steps of power iteration.
+    ///
+    /// The weight is viewed as a matrix with one row per index of its first dimension,
+    /// e.g. one row per output feature map of a Convolution, see [power_iteration][1].
+    /// [1]: ../util/fn.power_iteration.html
+    ///
+    /// The left and right singular vectors are cached, so the next call continues the
+    /// iteration where the last one stopped. While the weight changes slowly, e.g. during
+    /// training with spectral normalization, a single iteration per call suffices.
+    pub fn spectral_norm(&self, weight_name: &str, iters: usize) -> Result<f32, String> {
+        if iters == 0 {
+            return Err("The spectral norm needs at least one iteration".to_owned());
+        }
+        let weight_id = try!(self.learnable_weights_names()
+            .iter()
+            .position(|name| name == weight_name)
+            .ok_or_else(|| format!("Layer {} has no learnable weight named {}", self.name, weight_name)));
+        let weight = self.learnable_weights_data()[weight_id].clone();
+        let weight = weight.read().unwrap();
+        let rows = weight.desc()[0];
+        let columns = weight.desc().size() / rows;
+
+        let mut cache = self.spectral_vectors.borrow_mut();
+        let reinit = cache.get(weight_name).map_or(true, |&(ref u, ref v)| {
+            u.desc().size() != rows || v.desc().size() != columns
+        });
+        if reinit {
+            cache.insert(weight_name.to_owned(), ::util::singular_vectors(rows, columns));
+        }
+        let &mut (ref mut u, ref mut v) = cache.get_mut(weight_name).unwrap();
+        ::util::power_iteration(&*self.backend, &weight, u, v, iters).map_err(|err| format!("{:?}", err))
+    }
+
+    /// Helper for [from_config] to validate a [LayerConfig][2] and match its [LayerType][3]
+    /// to its [implementation][4].
+    /// [1]: #method.from_config
+    /// [2]: ./struct.LayerConfig.html
+    /// [3]: ./enum.LayerType.html
+    /// [4]: ../layers/index.html
+    ///
+    /// Is also used by layers that wrap the implementation of another layer, like
+    /// [SpectralNorm][5].
+    /// [5]: ../layers/common/spectral_norm/index.html
+    pub fn worker_from_config(backend: Rc<B>, config: &LayerConfig) -> Result<Box<ILayer<B>>, LayerError> {
+        try!(config.validate().map_err(|message| LayerError::invalid_config(config, message)));
+        try!(config.layer_type.validate().map_err(|message| LayerError::invalid_config(config, message)));
+        Ok(match config.layer_type.clone() {
+            LayerType::Convolution(layer_config) => Box::new(Convolution::from_config(&layer_config)),
+            LayerType::Linear(layer_config) => Box::new(Linear::from_config(&layer_config)),
+            LayerType::LogSoftmax => Box::new(LogSoftmax::default()),
+            LayerType::Pooling(layer_config) => Box::new(Pooling::from_config(&layer_config)),
+            LayerType::LRN(layer_config) => Box::new(LRN::from_config(&layer_config)),
+            LayerType::MeanScale => Box::new(MeanScale::default()),
+            LayerType::Dropout(layer_config) => Box::new(Dropout::from_config(&layer_config)),
+            LayerType::BatchNorm(layer_config) => Box::new(BatchNorm::from_config(&layer_config)),
+            LayerType::Sequential(layer_config) => Box::new(try!(Sequential::from_config(backend, &layer_config))),
+            LayerType::Softmax => Box::new(Softmax::default()),
+            LayerType::SpectralNorm(layer_config) => Box::new(try!(SpectralNorm::from_config(backend, &layer_config))),
+            LayerType::StochasticDepth(layer_config) => {
+                Box::new(try!(StochasticDepth::from_config(backend, &layer_config)))
+            }
+            LayerType::ReLU => Box::new(ReLU),
+            LayerType::TanH => Box::new(TanH),
+            LayerType::ELU(layer_config) => Box::new(ELU::from_config(&layer_config)),
+            LayerType::Sigmoid => Box::new(Sigmoid),
+            LayerType::NegativeLogLikelihood(layer_config) => {
+                Box::new(NegativeLogLikelihood::from_config(&layer_config))
+            }
+            LayerType::TripletLoss(layer_config) => Box::new(TripletLoss::from_config(&layer_config)),
+            LayerType::ContrastiveLoss(layer_config) => Box::new(ContrastiveLoss::from_config(&layer_config)),
+            LayerType::SoftmaxLoss(layer_config) => Box::new(SoftmaxLoss::from_config(&layer_config)),
+            LayerType::SmoothL1Loss(layer_config) => Box::new(SmoothL1Loss::from_config(&layer_config)),
+            LayerType::Reshape(layer_config) => Box::new(Reshape::from_config(&layer_config)),
+            LayerType::NchwToNhwc => Box::new(NchwToNhwc),
+            LayerType::NhwcToNchw => Box::new(NhwcToNchw),
+            LayerType::Split(layer_config) => {
+                Box::new(try!(Split::from_config(&layer_config)
+                    .map_err(|message| LayerError::invalid_config(config, message))))
+            }
+            LayerType::Custom(layer_config) => {
+                match ::layers::custom::create_layer(config, &layer_config) {
+                    Some(worker) => worker,
+                    None => {
+                        let message = format!("layer_type: no layer factory is registered for {:?}",
+                                              layer_config.layer_type);
+                        return Err(LayerError::invalid_config(config, message));
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Observes the inputs and outputs of a layer after its forward step.
+///
+/// Is implemented for closures with the signature of [forward][1].
+/// [1]: #tymethod.forward
+///
+/// See [Layer::register_forward_hook][2].
+/// [2]: ./struct.Layer.html#method.register_forward_hook
+pub trait ForwardHook {
+    /// Called with the input and output blobs of the layer.
+    fn forward(&self, inputs: &[&SharedTensor<f32>], outputs: &[&SharedTensor<f32>]);
+}
+
+impl<F: Fn(&[&SharedTensor<f32>], &[&SharedTensor<f32>])> ForwardHook for F {
+    fn forward(&self, inputs: &[&SharedTensor<f32>], outputs: &[&SharedTensor<f32>]) {
+        self(inputs, outputs)
+    }
+}
+
+impl fmt::Debug for ForwardHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({})", "ForwardHook")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The estimated device memory of a Layer.
+///
+/// See [Layer::estimated_memory][1].
+/// [1]: ./struct.Layer.html#method.estimated_memory
+pub struct MemoryEstimate {
+    /// The bytes of the input and output blobs.
+    pub activations: usize,
+    /// The bytes of the gradients of the input, output and weight blobs.
+    pub gradients: usize,
+    /// The bytes of the weight blobs.
+    pub weights: usize,
+    /// The bytes of the shared workspace.
+    pub workspace: usize,
+    /// The name and bytes of every counted blob.
+    pub blobs: Vec<(String, usize)>,
+}
+
+impl MemoryEstimate {
+    /// Adds the blob `name` to the blobs and returns its bytes, or `0` if it is already `counted`.
+    fn count_blob(&mut self,
+                  name: &str,
+                  blob: &ArcLock<SharedTensor<f32>>,
+                  counted: &mut HashSet<*const RwLock<SharedTensor<f32>>>)
+                  -> usize {
+        let blob_ptr: *const RwLock<SharedTensor<f32>> = &**blob;
+        if !counted.insert(blob_ptr) {
+            return 0;
+        }
+        let bytes = blob.read().unwrap().desc().size() * mem::size_of::<f32>();
+        self.blobs.push((name.to_owned(), bytes));
+        bytes
+    }
+
+    /// Returns the total estimated memory in bytes.
+    pub fn total(&self) -> usize {
+        self.activations + self.gradients + self.weights + self.workspace
+    }
+
+    /// Returns the name and bytes of the `n` largest blobs, the largest first.
+    pub fn largest_blobs(&self, n: usize) -> Vec<(String, usize)> {
+        let mut blobs = self.blobs.clone();
+        blobs.sort_by(|a, b| b.1.cmp(&a.1));
+        blobs.truncate(n);
+        blobs
+    }
+}
+
+impl fmt::Display for MemoryEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{:<15} {} bytes", "activations", self.activations));
+        try!(writeln!(f, "{:<15} {} bytes", "gradients", self.gradients));
+        try!(writeln!(f, "{:<15} {} bytes", "weights", self.weights));
+        try!(writeln!(f, "{:<15} {} bytes", "workspace", self.workspace));
+        writeln!(f, "{:<15} {} bytes", "total", self.total())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+/// What the initialization of a Layer resolved, to inspect a network that misbehaves.
+///
+/// Contains a [LayerInitReport][1] for every layer that is not a container, in the order
+/// of their initialization. Is returned by [Layer::from_config_with_report][2] and
+/// [Layer::init_report][3], and can be printed with `to_string()`.
+/// [1]: ./struct.LayerInitReport.html
+/// [2]: ./struct.Layer.html#method.from_config_with_report
+/// [3]: ./struct.Layer.html#method.init_report
+///
+/// Blobs that are consumed by several layers are not split by additional layers, the
+/// containers sum their gradients instead, so the report contains exactly the configured layers.
+pub struct InitReport {
+    /// The reports of the layers.
+    pub layers: Vec<LayerInitReport>,
+}
+
+impl InitReport {
+    /// Returns the report of the layer `name`.
+    pub fn layer(&self, name: &str) -> Option<&LayerInitReport> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+}
+
+/// Validates `config` and infers the shapes of all blobs and weights like a real
+/// construction, without touching a GPU, and returns the resulting [InitReport][1].
+/// [1]: ./struct.InitReport.html
+///
+/// The Layer is constructed on the native backend, so neither cuDNN nor cuBLAS are
+/// called, and dropped right away. All its blobs are only placeholders that hold no
+/// memory: tensors only allocate memory once they are read or written, and the weights
+/// are constructed [with placeholder weights][3], so they are not filled. The
+/// [memory estimate][4] only depends on the shapes of the blobs.
+///
+/// Returns the same errors as [Layer::from_config][2], including an exceeded memory budget.
+/// [2]: ./struct.Layer.html#method.from_config
+/// [3]: ../weight/fn.with_placeholder_weights.html
+/// [4]: ./struct.Layer.html#method.estimated_memory
+#[cfg(feature = "native")]
+pub fn dry_run(config: &LayerConfig) -> Result<InitReport, LayerError> {
+    dry_run_layer(config).map(|layer| layer.init_report())
+}
+
+/// Constructs the Layer of a [dry_run][1].
+/// [1]: ./fn.dry_run.html
+#[cfg(feature = "native")]
+fn dry_run_layer(config: &LayerConfig) -> Result<Layer<Backend<Native>>, LayerError> {
+    let backend = Rc::new(::util::native_backend());
+    with_placeholder_weights(|| Layer::from_config(backend, config))
+}
+
+impl fmt::Display for InitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for layer in &self.layers {
+            try!(writeln!(f,
+                          "Layer {} ({}){}",
+                          layer.name,
+                          layer.layer_type,
+                          if layer.needs_backward { "" } else { " - no backward" }));
+            for (input, needs_backward) in layer.inputs.iter().zip(&layer.input_needs_backward) {
+                try!(writeln!(f,
+                              "  input  {} {:?}{}",
+                              input.name,
+                              input.shape,
+                              if *needs_backward { "" } else { " - no gradient" }));
+            }
+            for output in &layer.outputs {
+                try!(writeln!(f, "  output {} {:?}", output.name, output.shape));
+            }
+            for weight in &layer.weights {
+                let filler = match weight.filler {
+                    Some(ref filler) => filler.as_str(),
+                    None if weight.shared => "shared",
+                    None => "layer default",
+                };
+                try!(writeln!(f, "  weight {} {:?} - {}", weight.name, weight.shape, filler));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+/// What the initialization resolved for a single layer, see [InitReport][1].
+/// [1]: ./struct.InitReport.html
+pub struct LayerInitReport {
+    /// The name of the layer.
+    pub name: String,
+    /// The [name of the type][1] of the layer.
+    /// [1]: ./enum.LayerType.html#method.name
+    pub layer_type: String,
+    /// The input blobs the layer has been connected to.
+    pub inputs: Vec<BlobReport>,
+    /// The output blobs of the layer, including anonymous ones.
+    pub outputs: Vec<BlobReport>,
+    /// The weight blobs of the layer.
+    pub weights: Vec<WeightReport>,
+    /// Whether the backward pass of the layer is computed.
+    pub needs_backward: bool,
+    /// Whether the gradient of each input is computed.
+    pub input_needs_backward: Vec<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+/// The name and shape of a blob in an [InitReport][1].
+/// [1]: ./struct.InitReport.html
+pub struct BlobReport {
+    /// The name of the blob.
+    pub name: String,
+    /// The shape of the blob.
+    pub shape: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+/// A weight blob in an [InitReport][1].
+/// [1]: ./struct.InitReport.html
+pub struct WeightReport {
+    /// The display name of the weight.
+    pub name: String,
+    /// The shape of the weight.
+    pub shape: Vec<usize>,
+    /// The filler of the [WeightConfig][1] or the [InitDefaults][2] the weight has been
+    /// filled with, or `None` if it keeps the initialization of the layer implementation.
+    /// [1]: ../weight/struct.WeightConfig.html#structfield.filler
+    /// [2]: ../weight/struct.InitDefaults.html
+    pub filler: Option<String>,
+    /// Whether the weight is shared with and owned by another layer.
+    pub shared: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Whether the backward steps of a layer run, see [Layer::backprop_plan][1].
+/// [1]: ./struct.Layer.html#method.backprop_plan
+pub struct LayerBackpropInfo {
+    /// The name of the layer.
+    pub name: String,
+    /// Whether the gradients of the inputs are computed.
+    pub backward_input: bool,
+    /// Whether the gradients of the weights are computed, which is skipped for
+    /// [frozen][1] layers and layers without weights.
+    /// [1]: ./struct.Layer.html#method.set_frozen
+    pub backward_parameters: bool,
+    /// Whether the gradient of each input is computed.
+    pub input_needs_backward: Vec<bool>,
+    /// Why the backward steps run or are skipped.
+    pub reason: BackpropReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why the backward steps of a layer run or are skipped, see [LayerBackpropInfo][1].
+/// [1]: ./struct.LayerBackpropInfo.html
+pub enum BackpropReason {
+    /// The layer is a loss layer or the gradient of one of its outputs is needed.
+    UnderLoss,
+    /// No output of the layer needs a gradient, but [force_backward][1] is set.
+    /// [1]: ../layers/container/struct.SequentialConfig.html#structfield.force_backward
+    Forced,
+    /// No gradient flows into the layer, as all consumers of its outputs disable
+    /// [propagate_down][1] for them, e.g. for a branch that augments the data. Skipped.
+    /// [1]: ./struct.LayerConfig.html#structfield.propagate_down
+    NotPropagated,
+    /// The layer isn't inside a container, so its backward steps run whenever they are called.
+    Standalone,
+}
+
+#[derive(Debug, Clone)]
+/// The error of a Layer whose estimated memory exceeds its memory budget.
+///
+/// See [Layer::from_config][1].
+/// [1]: ./struct.Layer.html#method.from_config
+pub struct MemoryBudgetExceeded {
+    /// The memory budget in bytes.
+    pub budget: usize,
+    /// The estimated memory of the Layer.
+    pub estimate: MemoryEstimate,
+}
+
+impl MemoryBudgetExceeded {
+    /// Returns the name and bytes of the five largest blobs, the largest first.
+    pub fn largest_blobs(&self) -> Vec<(String, usize)> {
+        self.estimate.largest_blobs(5)
+    }
+}
+
+impl fmt::Display for MemoryBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f,
+                      "The estimated memory of {} bytes exceeds the budget of {} bytes.",
+                      self.estimate.total(),
+                      self.budget));
+        try!(writeln!(f, "The largest blobs are:"));
+        for (name, bytes) in self.largest_blobs() {
+            try!(writeln!(f, "{:<15} {} bytes", name, bytes));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The error of a Layer that can not be created from its [LayerConfig][1].
+/// [1]: ./struct.LayerConfig.html
+///
+/// See [Layer::from_config][2].
+/// [2]: ./struct.Layer.html#method.from_config
+pub enum LayerError {
+    /// A field of the config of a layer has an invalid value.
+    InvalidConfig {
+        /// The name of the layer.
+        layer: String,
+        /// The name of the type of the layer.
+        layer_type: String,
+        /// Names the invalid field and describes what is wrong with it.
+        message: String,
+    },
+    /// The estimated memory of the Layer exceeds the memory budget of its SequentialConfig.
+    MemoryBudgetExceeded(MemoryBudgetExceeded),
+}
 
-            worker: Layer::<B>::worker_from_config(backend, &cfg),
-            config: cfg,
-        };
-        layer.expose_inputs();
-        layer.expose_outputs();
+impl LayerError {
+    /// Create an InvalidConfig error for the layer configured by `config`.
+    pub fn invalid_config(config: &LayerConfig, message: String) -> LayerError {
+        LayerError::InvalidConfig {
+            layer: config.name.clone(),
+            layer_type: config.layer_type.name().to_owned(),
+            message: message,
+        }
+    }
+}
 
-        layer
+impl From<MemoryBudgetExceeded> for LayerError {
+    fn from(err: MemoryBudgetExceeded) -> LayerError {
+        LayerError::MemoryBudgetExceeded(err)
     }
+}
 
-    /// Helper for [from_config] to match a [LayerType][2] to its [implementation][3].
-    /// [1]: #method.from_config
-    /// [2]: ./enum.LayerType.html
-    /// [3]: ../layers/index.html
-    fn worker_from_config(backend: Rc<B>, config: &LayerConfig) -> Box<ILayer<B>> {
-        match config.layer_type.clone() {
-            LayerType::Convolution(layer_config) => Box::new(Convolution::from_config(&layer_config)),
-            LayerType::Linear(layer_config) => Box::new(Linear::from_config(&layer_config)),
-            LayerType::LogSoftmax => Box::new(LogSoftmax::default()),
-            LayerType::Pooling(layer_config) => Box::new(Pooling::from_config(&layer_config)),
-            LayerType::Sequential(layer_config) => Box::new(Sequential::from_config(backend, &layer_config)),
-            LayerType::Softmax => Box::new(Softmax::default()),
-            LayerType::ReLU => Box::new(ReLU),
-            LayerType::TanH => Box::new(TanH),
-            LayerType::Sigmoid => Box::new(Sigmoid),
-            LayerType::NegativeLogLikelihood(layer_config) => {
-                Box::new(NegativeLogLikelihood::from_config(&layer_config))
+impl fmt::Display for LayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayerError::InvalidConfig { ref layer, ref layer_type, ref message } => {
+                write!(f, "Invalid config of the {} layer '{}': {}", layer_type, layer, message)
             }
-            LayerType::Reshape(layer_config) => Box::new(Reshape::from_config(&layer_config)),
+            LayerError::MemoryBudgetExceeded(ref err) => err.fmt(f),
         }
     }
 }
@@ -985,6 +2955,25 @@ pub trait ILayer<B: IBackend>
         workspace
     }
 
+    /// Returns the size of the workspace the layer requires in bytes.
+    ///
+    /// Is used to [estimate the memory][1] of a Layer.
+    /// [1]: ./struct.Layer.html#method.estimated_memory
+    fn workspace_size(&self) -> usize {
+        0
+    }
+
+    /// Returns the number of multiply-adds of a forward pass with inputs of the shapes
+    /// `input_shapes` that produces outputs of the shapes `output_shapes`.
+    ///
+    /// Is used to count the [FLOPs][1] of a Layer. Defaults to one operation per output
+    /// value, which fits element-wise layers like activations. Layers that only rearrange
+    /// their input, like Reshape, Split and the layout conversions, count zero.
+    /// [1]: ./struct.Layer.html#method.flops
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        output_shapes.iter().fold(0, |sum, shape| sum + shape.size() as u64)
+    }
+
     /// Compute the [feedforward][1] layer output using the provided Backend.
     /// [1]: https://en.wikipedia.org/wiki/Feedforward_neural_network
     ///
@@ -1117,6 +3106,35 @@ pub trait ILayer<B: IBackend>
     fn auto_weight_blobs(&self) -> bool {
         false
     }
+    /// Returns the number of weight blobs that are created automatically for the layer,
+    /// or `None` to create one for every output blob.
+    ///
+    /// Only used if [auto_weight_blobs][1] returns true. Layers with additional weights,
+    /// like the bias of a Convolution, override it.
+    /// [1]: #method.auto_weight_blobs
+    fn num_weight_blobs(&self) -> Option<usize> {
+        None
+    }
+    /// Return whether the weight blob `weight_id` is subject to weight decay by default.
+    ///
+    /// Decaying biases and the scale and shift of normalization layers usually hurts
+    /// training, so layers return false for those. A [decay_mult][1] configured in the
+    /// WeightConfig of the weight takes precedence.
+    /// [1]: ../weight/struct.WeightConfig.html#structfield.decay_mult
+    fn weight_decayable(&self, weight_id: usize) -> bool {
+        true
+    }
+    /// Return whether the weight blob `weight_id` is learnable, i.e. updated by the Solver.
+    ///
+    /// Weights that are not learnable, like the statistics of a [MeanScale][1] layer, are
+    /// created and serialized like the other weights, but are excluded from the learnable
+    /// weights of the layer. They are returned by [Layer::non_learnable_weights_data][2]
+    /// instead.
+    /// [1]: ../layers/common/mean_scale/index.html
+    /// [2]: ./struct.Layer.html#method.non_learnable_weights_data
+    fn learnable_weight(&self, weight_id: usize) -> bool {
+        true
+    }
     /// Returns the exact number of input blobs required by the layer,
     /// or `None` if no exact number is required.
     ///
@@ -1169,6 +3187,16 @@ pub trait ILayer<B: IBackend>
         None
     }
 
+    /// Returns the loss of each sample of the inputs.
+    ///
+    /// The loss output of the layer is the mean of these losses over the samples that
+    /// contribute to it. Samples that do not contribute, e.g. masked ones, have a loss of `0`.
+    ///
+    /// This is usually overridden by loss layers.
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        None
+    }
+
     /// Return the input tensors of the layer.
     ///
     /// This should only be overridden by container layers,
@@ -1225,6 +3253,22 @@ pub trait ILayer<B: IBackend>
         None
     }
 
+    /// Return the weights inside the layer that are not learnable.
+    ///
+    /// This should only be overridden by container layers,
+    /// where the weights are not easily exposable.
+    fn non_learnable_weights(&self) -> Option<Vec<ArcLock<SharedTensor<f32>>>> {
+        None
+    }
+
+    /// Return the names of the weights inside the layer that are not learnable.
+    ///
+    /// This should only be overridden by container layers,
+    /// where the weights are not easily exposable.
+    fn non_learnable_weights_names(&self) -> Option<Vec<String>> {
+        None
+    }
+
     /// Return the learning rates for the learnable weights inside the layer.
     ///
     /// This should only be overridden by container layers,
@@ -1232,6 +3276,47 @@ pub trait ILayer<B: IBackend>
     fn learnable_weights_lr(&self) -> Option<Vec<Option<f32>>> {
         None
     }
+
+    /// Return wether the layer waits for the shapes of its inputs to complete its initialization.
+    ///
+    /// See [Layer::from_config_lazy][1].
+    /// [1]: ./struct.Layer.html#method.from_config_lazy
+    fn awaits_input_shapes(&self) -> bool {
+        false
+    }
+
+    /// Complete the initialization of a lazily created layer with the shapes of its first inputs.
+    ///
+    /// Only called if [awaits_input_shapes][1] returns `true`.
+    /// [1]: #method.awaits_input_shapes
+    fn init_input_shapes(&mut self, backend: Rc<B>, input_shapes: &[Vec<usize>]) {}
+
+    /// Switch the layer between training and inference, see [Layer::set_train][1].
+    /// [1]: ./struct.Layer.html#method.set_train
+    ///
+    /// Only needs to be overridden by layers that behave differently during training.
+    fn set_train(&mut self, train: bool) {}
+
+    /// Tell the layer whether its following forward passes recompute the activations of
+    /// the last forward pass, see [Layer::set_recomputing][1].
+    /// [1]: ./struct.Layer.html#method.set_recomputing
+    ///
+    /// Only needs to be overridden by layers whose forward pass is random or updates state.
+    fn set_recomputing(&mut self, recomputing: bool) {}
+
+    /// Return the data of the blob `name` inside a container layer.
+    ///
+    /// Only needs to be overridden by container layers.
+    fn blob_data(&self, name: &str) -> Option<ArcLock<SharedTensor<f32>>> {
+        None
+    }
+
+    /// Return the layers inside a container layer.
+    ///
+    /// Only needs to be overridden by container layers.
+    fn sublayers(&self) -> Option<&[RefCell<Layer<B>>]> {
+        None
+    }
 }
 
 /// A Layer that can compute the output for a given input.
@@ -1274,7 +3359,7 @@ impl<B: IBackend> fmt::Debug for ILayer<B> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Layer Configuration Struct
 pub struct LayerConfig {
     /// The name of the Layer
@@ -1295,9 +3380,23 @@ pub struct LayerConfig {
     /// Specifies on which inputs the backpropagation should be skipped.
     /// The size must be either 0 or equal to the number of inputs.
     pub propagate_down: Vec<bool>,
+
+    /// Release the outputs of the Layer after the forward pass of its container
+    /// and recompute them during the backward pass.
+    ///
+    /// Consecutive layers with this flag are recomputed together from the retained
+    /// input of the first one, which trades computation for memory like the
+    /// [checkpoint_segments][1] of a [SequentialConfig][2]. The output of the last one
+    /// is retained. A layer that computes in-place always follows the layer it computes on.
+    /// Only has an effect inside a Sequential layer without `checkpoint_segments`.
+    /// [1]: ../layers/container/struct.SequentialConfig.html#structfield.checkpoint_segments
+    /// [2]: ../layers/container/struct.SequentialConfig.html
+    ///
+    /// Default: `false`
+    pub recompute_in_backward: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// The Layer Types
 pub enum LayerType {
     // Common layers
@@ -1309,23 +3408,54 @@ pub enum LayerType {
     LogSoftmax,
     /// Pooling Layer
     Pooling(PoolingConfig),
+    /// LRN Layer
+    LRN(LRNConfig),
+    /// MeanScale Layer
+    MeanScale,
+    /// Dropout Layer
+    Dropout(DropoutConfig),
+    /// BatchNorm Layer
+    BatchNorm(BatchNormConfig),
     /// Sequential Layer
     Sequential(SequentialConfig),
     /// Softmax Layer
     Softmax,
+    /// SpectralNorm Layer, which wraps another layer
+    SpectralNorm(SpectralNormConfig),
+    /// StochasticDepth Layer, which wraps the residual branch of a block
+    StochasticDepth(StochasticDepthConfig),
     // Activation layers
     /// ReLU Layer
     ReLU,
     /// TanH Layer
     TanH,
+    /// ELU Layer
+    ELU(ELUConfig),
     /// Sigmoid Layer
     Sigmoid,
     // Loss layers
     /// NegativeLogLikelihood Layer
     NegativeLogLikelihood(NegativeLogLikelihoodConfig),
+    /// TripletLoss Layer
+    TripletLoss(TripletLossConfig),
+    /// ContrastiveLoss Layer
+    ContrastiveLoss(ContrastiveLossConfig),
+    /// SoftmaxLoss Layer
+    SoftmaxLoss(SoftmaxLossConfig),
+    /// SmoothL1Loss Layer
+    SmoothL1Loss(SmoothL1LossConfig),
     // Utility layers
     /// Reshape Layer
     Reshape(ReshapeConfig),
+    /// NchwToNhwc Layer
+    NchwToNhwc,
+    /// NhwcToNchw Layer
+    NhwcToNchw,
+    /// Split Layer
+    Split(SplitConfig),
+    // Custom layers
+    /// Custom Layer created by a registered [factory](../layers/custom/index.html)
+    Custom(CustomConfig),
 }
 
 
@@ -1339,17 +3469,104 @@ impl LayerType {
             LayerType::LogSoftmax => false,
             LayerType::Sequential(_) => false,
             LayerType::Softmax => false,
+            LayerType::SpectralNorm(_) => false,
+            LayerType::StochasticDepth(_) => false,
+            LayerType::LRN(_) => false,
+            LayerType::MeanScale => false,
+            LayerType::Dropout(_) => false,
+            LayerType::BatchNorm(_) => false,
             LayerType::ReLU => true,
             LayerType::TanH => true,
+            LayerType::ELU(_) => true,
             LayerType::Sigmoid => true,
             LayerType::NegativeLogLikelihood(_) => false,
+            LayerType::TripletLoss(_) => false,
+            LayerType::ContrastiveLoss(_) => false,
+            LayerType::SoftmaxLoss(_) => false,
+            LayerType::SmoothL1Loss(_) => false,
             LayerType::Reshape(_) => true,
+            LayerType::NchwToNhwc => false,
+            LayerType::NhwcToNchw => false,
+            LayerType::Split(_) => false,
             LayerType::Convolution(_) => false,
             LayerType::Pooling(_) => false,
+            LayerType::Custom(_) => false,
+        }
+    }
+
+    /// Checks the constraints the layer type imposes on its config, e.g. that a Linear
+    /// layer has a positive output size.
+    ///
+    /// Returns a message that names the invalid field. Constraints that depend on the
+    /// shapes of the inputs can only be checked once the layer is connected.
+    /// The layers inside a Sequential layer are validated when they are created.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            LayerType::Convolution(ref config) => config.validate(),
+            LayerType::Linear(ref config) => config.validate(),
+            LayerType::Pooling(ref config) => config.validate(),
+            LayerType::LRN(ref config) => config.validate(),
+            LayerType::Dropout(ref config) => config.validate(),
+            LayerType::BatchNorm(ref config) => config.validate(),
+            LayerType::ELU(ref config) => config.validate(),
+            LayerType::Sequential(ref config) => config.validate(),
+            LayerType::SpectralNorm(ref config) => config.validate(),
+            LayerType::StochasticDepth(ref config) => config.validate(),
+            LayerType::NegativeLogLikelihood(ref config) => config.validate(),
+            LayerType::TripletLoss(ref config) => config.validate(),
+            LayerType::ContrastiveLoss(ref config) => config.validate(),
+            LayerType::SoftmaxLoss(ref config) => config.validate(),
+            LayerType::SmoothL1Loss(ref config) => config.validate(),
+            LayerType::Reshape(ref config) => config.validate(),
+            LayerType::Split(ref config) => config.validate(),
+            // the factory of a custom layer interprets its payload
+            LayerType::Custom(_) => Ok(()),
+            LayerType::LogSoftmax | LayerType::MeanScale | LayerType::Softmax | LayerType::ReLU |
+            LayerType::TanH | LayerType::Sigmoid | LayerType::NchwToNhwc | LayerType::NhwcToNchw => Ok(()),
+        }
+    }
+
+    /// Returns the name of the layer type.
+    ///
+    /// For custom layers this is the name their factory has been registered under.
+    pub fn name(&self) -> &str {
+        match *self {
+            LayerType::Convolution(_) => "Convolution",
+            LayerType::Linear(_) => "Linear",
+            LayerType::LogSoftmax => "LogSoftmax",
+            LayerType::Pooling(_) => "Pooling",
+            LayerType::LRN(_) => "LRN",
+            LayerType::MeanScale => "MeanScale",
+            LayerType::Dropout(_) => "Dropout",
+            LayerType::BatchNorm(_) => "BatchNorm",
+            LayerType::Sequential(_) => "Sequential",
+            LayerType::Softmax => "Softmax",
+            LayerType::SpectralNorm(_) => "SpectralNorm",
+            LayerType::StochasticDepth(_) => "StochasticDepth",
+            LayerType::ReLU => "ReLU",
+            LayerType::TanH => "TanH",
+            LayerType::ELU(_) => "ELU",
+            LayerType::Sigmoid => "Sigmoid",
+            LayerType::NegativeLogLikelihood(_) => "NegativeLogLikelihood",
+            LayerType::TripletLoss(_) => "TripletLoss",
+            LayerType::ContrastiveLoss(_) => "ContrastiveLoss",
+            LayerType::SoftmaxLoss(_) => "SoftmaxLoss",
+            LayerType::SmoothL1Loss(_) => "SmoothL1Loss",
+            LayerType::Reshape(_) => "Reshape",
+            LayerType::NchwToNhwc => "NchwToNhwc",
+            LayerType::NhwcToNchw => "NhwcToNchw",
+            LayerType::Split(_) => "Split",
+            LayerType::Custom(ref config) => &config.layer_type,
         }
     }
 }
 
+impl fmt::Display for LayerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl<'a> CapnpWrite<'a> for LayerType {
     type Builder = capnp_layer_type::Builder<'a>;
 
@@ -1366,17 +3583,51 @@ impl<'a> CapnpWrite<'a> for LayerType {
                 cfg.write_capnp(config);
             }
             &LayerType::Softmax => builder.set_softmax(()),
+            &LayerType::SpectralNorm(ref cfg) => {
+                let ref mut config = builder.borrow().init_spectral_norm();
+                cfg.write_capnp(config);
+            }
+            &LayerType::StochasticDepth(ref cfg) => {
+                let ref mut config = builder.borrow().init_stochastic_depth();
+                cfg.write_capnp(config);
+            }
             &LayerType::ReLU => builder.set_relu(()),
             &LayerType::TanH => builder.set_tanh(()),
+            &LayerType::ELU(ref cfg) => {
+                let ref mut config = builder.borrow().init_elu();
+                cfg.write_capnp(config);
+            }
             &LayerType::Sigmoid => builder.set_sigmoid(()),
             &LayerType::NegativeLogLikelihood(ref cfg) => {
                 let ref mut config = builder.borrow().init_negative_log_likelihood();
                 cfg.write_capnp(config);
             }
+            &LayerType::TripletLoss(ref cfg) => {
+                let ref mut config = builder.borrow().init_triplet_loss();
+                cfg.write_capnp(config);
+            }
+            &LayerType::ContrastiveLoss(ref cfg) => {
+                let ref mut config = builder.borrow().init_contrastive_loss();
+                cfg.write_capnp(config);
+            }
+            &LayerType::SoftmaxLoss(ref cfg) => {
+                let ref mut config = builder.borrow().init_softmax_loss();
+                cfg.write_capnp(config);
+            }
+            &LayerType::SmoothL1Loss(ref cfg) => {
+                let ref mut config = builder.borrow().init_smooth_l1_loss();
+                cfg.write_capnp(config);
+            }
             &LayerType::Reshape(ref cfg) => {
                 let ref mut config = builder.borrow().init_reshape();
                 cfg.write_capnp(config);
             }
+            &LayerType::NchwToNhwc => builder.set_nchw_to_nhwc(()),
+            &LayerType::NhwcToNchw => builder.set_nhwc_to_nchw(()),
+            &LayerType::Split(ref cfg) => {
+                let ref mut config = builder.borrow().init_split();
+                cfg.write_capnp(config);
+            }
             &LayerType::Convolution(ref cfg) => {
                 let ref mut config = builder.borrow().init_convolution();
                 cfg.write_capnp(config);
@@ -1385,6 +3636,23 @@ impl<'a> CapnpWrite<'a> for LayerType {
                 let ref mut config = builder.borrow().init_pooling();
                 cfg.write_capnp(config);
             }
+            &LayerType::LRN(ref cfg) => {
+                let ref mut config = builder.borrow().init_lrn();
+                cfg.write_capnp(config);
+            }
+            &LayerType::MeanScale => builder.set_mean_scale(()),
+            &LayerType::Dropout(ref cfg) => {
+                let ref mut config = builder.borrow().init_dropout();
+                cfg.write_capnp(config);
+            }
+            &LayerType::BatchNorm(ref cfg) => {
+                let ref mut config = builder.borrow().init_batch_norm();
+                cfg.write_capnp(config);
+            }
+            &LayerType::Custom(ref cfg) => {
+                let ref mut config = builder.borrow().init_custom();
+                cfg.write_capnp(config);
+            }
         }
     }
 }
@@ -1404,25 +3672,76 @@ impl<'a> CapnpRead<'a> for LayerType {
                 LayerType::Sequential(config)
             }
             capnp_layer_type::Which::Softmax(_) => LayerType::Softmax,
+            capnp_layer_type::Which::SpectralNorm(read_config) => {
+                let config = SpectralNormConfig::read_capnp(read_config.unwrap());
+                LayerType::SpectralNorm(config)
+            }
+            capnp_layer_type::Which::StochasticDepth(read_config) => {
+                let config = StochasticDepthConfig::read_capnp(read_config.unwrap());
+                LayerType::StochasticDepth(config)
+            }
             capnp_layer_type::Which::Relu(_) => LayerType::ReLU,
             capnp_layer_type::Which::Tanh(_) => LayerType::TanH,
+            capnp_layer_type::Which::Elu(read_config) => {
+                let config = ELUConfig::read_capnp(read_config.unwrap());
+                LayerType::ELU(config)
+            }
             capnp_layer_type::Which::Sigmoid(_) => LayerType::Sigmoid,
             capnp_layer_type::Which::NegativeLogLikelihood(read_config) => {
                 let config = NegativeLogLikelihoodConfig::read_capnp(read_config.unwrap());
                 LayerType::NegativeLogLikelihood(config)
             }
+            capnp_layer_type::Which::TripletLoss(read_config) => {
+                let config = TripletLossConfig::read_capnp(read_config.unwrap());
+                LayerType::TripletLoss(config)
+            }
+            capnp_layer_type::Which::ContrastiveLoss(read_config) => {
+                let config = ContrastiveLossConfig::read_capnp(read_config.unwrap());
+                LayerType::ContrastiveLoss(config)
+            }
+            capnp_layer_type::Which::SoftmaxLoss(read_config) => {
+                let config = SoftmaxLossConfig::read_capnp(read_config.unwrap());
+                LayerType::SoftmaxLoss(config)
+            }
+            capnp_layer_type::Which::SmoothL1Loss(read_config) => {
+                let config = SmoothL1LossConfig::read_capnp(read_config.unwrap());
+                LayerType::SmoothL1Loss(config)
+            }
             capnp_layer_type::Which::Reshape(read_config) => {
                 let config = ReshapeConfig::read_capnp(read_config.unwrap());
                 LayerType::Reshape(config)
             }
+            capnp_layer_type::Which::NchwToNhwc(_) => LayerType::NchwToNhwc,
+            capnp_layer_type::Which::NhwcToNchw(_) => LayerType::NhwcToNchw,
+            capnp_layer_type::Which::Split(read_config) => {
+                let config = SplitConfig::read_capnp(read_config.unwrap());
+                LayerType::Split(config)
+            }
             capnp_layer_type::Which::Pooling(read_config) => {
                 let config = PoolingConfig::read_capnp(read_config.unwrap());
                 LayerType::Pooling(config)
             }
+            capnp_layer_type::Which::Lrn(read_config) => {
+                let config = LRNConfig::read_capnp(read_config.unwrap());
+                LayerType::LRN(config)
+            }
+            capnp_layer_type::Which::MeanScale(_) => LayerType::MeanScale,
+            capnp_layer_type::Which::Dropout(read_config) => {
+                let config = DropoutConfig::read_capnp(read_config.unwrap());
+                LayerType::Dropout(config)
+            }
+            capnp_layer_type::Which::BatchNorm(read_config) => {
+                let config = BatchNormConfig::read_capnp(read_config.unwrap());
+                LayerType::BatchNorm(config)
+            }
             capnp_layer_type::Which::Convolution(read_config) => {
                 let config = ConvolutionConfig::read_capnp(read_config.unwrap());
                 LayerType::Convolution(config)
             }
+            capnp_layer_type::Which::Custom(read_config) => {
+                let config = CustomConfig::read_capnp(read_config.unwrap());
+                LayerType::Custom(config)
+            }
         }
     }
 }
@@ -1439,6 +3758,8 @@ impl LayerConfig {
 
             params: Vec::new(),
             propagate_down: Vec::new(),
+
+            recompute_in_backward: false,
         }
     }
 
@@ -1483,8 +3804,14 @@ impl LayerConfig {
     }
 
     /// Check if the configured parameters make sense.
-    pub fn validate(&self) -> Result<(), &'static str> {
-        try!(self.validate_propagate_down_len());
+    pub fn validate(&self) -> Result<(), String> {
+        try!(self.validate_propagate_down_len().map_err(|message| message.to_owned()));
+        for (weight_id, param) in self.params.iter().enumerate() {
+            if let Some(filler) = param.filler {
+                try!(filler.validate()
+                    .map_err(|message| format!("params: the filler of weight {}: {}", weight_id, message)));
+            }
+        }
         Ok(())
     }
 
@@ -1498,6 +3825,12 @@ impl LayerConfig {
     }
 }
 
+impl fmt::Display for LayerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.layer_type)
+    }
+}
+
 impl<'a> CapnpWrite<'a> for LayerConfig {
     type Builder = capnp_layer_config::Builder<'a>;
 
@@ -1533,6 +3866,7 @@ impl<'a> CapnpWrite<'a> for LayerConfig {
                 propagate_down.set(i as u32, *input);
             }
         }
+        builder.set_recompute_in_backward(self.recompute_in_backward);
     }
 }
 
@@ -1565,6 +3899,7 @@ impl<'a> CapnpRead<'a> for LayerConfig {
         for i in 0..read_propagate_down.len() {
             propagate_down.push(read_propagate_down.get(i))
         }
+        let recompute_in_backward = reader.get_recompute_in_backward();
 
         LayerConfig {
             name: name,
@@ -1573,6 +3908,730 @@ impl<'a> CapnpRead<'a> for LayerConfig {
             inputs: inputs,
             params: params,
             propagate_down: propagate_down,
+            recompute_in_backward: recompute_in_backward,
+        }
+    }
+}
+
+/// Sums loss values in `f32`, or in `f64` with the result rounded to `f32`.
+fn sum_losses(losses: &[f32], in_f64: bool) -> f32 {
+    if in_f64 {
+        losses.iter().fold(0f64, |sum, &loss| sum + loss as f64) as f32
+    } else {
+        losses.iter().fold(0f32, |sum, &loss| sum + loss)
+    }
+}
+
+/// Adds the per-example `losses` of a layer to the per-example losses summed so far.
+fn add_losses(sum: Option<Vec<f32>>, losses: Vec<f32>) -> Vec<f32> {
+    match sum {
+        Some(sum) => {
+            assert_eq!(sum.len(), losses.len(), "Loss layers need the same number of samples");
+            sum.iter().zip(losses).map(|(sum, loss)| sum + loss).collect()
+        }
+        None => losses,
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layers::*;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::*;
+    use util::{ArcLock, native_backend, write_to_memory};
+    use util::testing::{read, temp_path, tensor};
+    use weight::WeightConfig;
+
+    type Registry = HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>;
+    type WeightRegistry = HashMap<String,
+                                  (ArcLock<SharedTensor<f32>>,
+                                   ArcLock<SharedTensor<f32>>,
+                                   Option<f32>,
+                                   Option<f32>)>;
+
+    fn linear_branch(name: &str,
+                     weight_name: &str,
+                     registry: &mut Registry,
+                     weight_registry: &mut WeightRegistry)
+                     -> Layer<Backend<Native>> {
+        let mut cfg = LayerConfig::new(name, LinearConfig { output_size: 2 });
+        cfg.add_input(&format!("{}_in", name));
+        cfg.add_output(&format!("{}_out", name));
+        cfg.params.push(WeightConfig { name: weight_name.to_owned(), ..WeightConfig::default() });
+        let mut layer = Layer::from_config(Rc::new(native_backend()), &cfg).unwrap();
+        layer.connect(registry, weight_registry);
+        layer
+    }
+
+    /// Runs both branches of a Siamese network with the loss `0.5 * |left - right|^2`
+    /// and returns the branches after backpropagation.
+    fn siamese_backward(left_weight: &str, right_weight: &str) -> (Layer<Backend<Native>>, Layer<Backend<Native>>) {
+        let weights = [0.5f32, -1.0, 0.25, 2.0, 0.1, -0.3];
+        let mut registry = Registry::new();
+        registry.insert("left_in".to_owned(), (tensor(&[1, 3], &[1.0, 2.0, 3.0]), tensor(&[1, 3], &[0.0; 3])));
+        registry.insert("right_in".to_owned(), (tensor(&[1, 3], &[-1.0, 0.5, 2.0]), tensor(&[1, 3], &[0.0; 3])));
+        let mut weight_registry = WeightRegistry::new();
+
+        let mut left = linear_branch("left", left_weight, &mut registry, &mut weight_registry);
+        let mut right = linear_branch("right", right_weight, &mut registry, &mut weight_registry);
+        for layer in &[&left, &right] {
+            for weight in &layer.weights_data {
+                let native = native_backend();
+                write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &weights);
+            }
+        }
+
+        let left_out = read(&left.forward(&[])[0]);
+        let right_out = read(&right.forward(&[])[0]);
+        let left_gradient = left_out.iter().zip(&right_out).map(|(l, r)| l - r).collect::<Vec<_>>();
+        let right_gradient = left_gradient.iter().map(|g| -g).collect::<Vec<_>>();
+
+        left.output_blobs_gradient[0] = tensor(&[1, 2], &left_gradient);
+        left.backward_parameters();
+        right.output_blobs_gradient[0] = tensor(&[1, 2], &right_gradient);
+        right.backward_parameters();
+        (left, right)
+    }
+
+    #[test]
+    fn shared_weight_gradients_accumulate() {
+        let (shared_left, shared_right) = siamese_backward("shared", "shared");
+        assert_eq!(1, shared_left.learnable_weights_data().len());
+        assert!(shared_right.learnable_weights_data().is_empty());
+
+        let (left, right) = siamese_backward("", "");
+        let expected = read(&left.weights_gradient[0])
+            .iter()
+            .zip(read(&right.weights_gradient[0]).iter())
+            .map(|(l, r)| l + r)
+            .collect::<Vec<_>>();
+
+        let shared_gradient = read(&shared_left.learnable_weights_gradients()[0]);
+        assert_eq!(shared_gradient, read(&shared_right.weights_gradient[0]));
+        for (shared, expected) in shared_gradient.iter().zip(expected.iter()) {
+            assert!((shared - expected).abs() < 1e-5, "{} != {}", shared, expected);
+        }
+    }
+
+    #[test]
+    fn weight_gradients_accumulate_until_cleared() {
+        let (mut left, _) = siamese_backward("", "");
+        let once = read(&left.weights_gradient[0]);
+        left.backward_parameters();
+        let twice = read(&left.weights_gradient[0]);
+        for (once, twice) in once.iter().zip(twice.iter()) {
+            assert!((2f32 * once - twice).abs() < 1e-5, "{} != {}", 2f32 * once, twice);
+        }
+
+        left.clear_weights_gradients();
+        assert!(read(&left.weights_gradient[0]).iter().all(|&gradient| gradient == 0f32));
+    }
+
+    /// Runs a forward and backward pass through a small network and returns
+    /// the network together with its input gradient.
+    fn linear_sigmoid_backward(training_backend: Option<Rc<Backend<Native>>>) -> (Layer<Backend<Native>>, Vec<f32>) {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        if let Some(backend) = training_backend {
+            network.set_training_backend(backend);
+        }
+        let native = native_backend();
+        write_to_memory(network.learnable_weights_data()[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[0.5f32, -1.0, 0.25, 2.0, 0.1, -0.3]);
+
+        network.forward(&[tensor(&[2, 3], &[1.0, 2.0, 3.0, -1.0, 0.5, 2.0])]);
+        let input_gradient = network.backward(&[tensor(&[2, 2], &[1.0, -1.0, 0.5, 2.0])]);
+        let input_gradient = read(&input_gradient[0]);
+        (network, input_gradient)
+    }
+
+    #[test]
+    fn separate_training_backend_computes_same_gradients() {
+        let training_backend = Rc::new(native_backend());
+        let (network, input_gradient) = linear_sigmoid_backward(Some(training_backend.clone()));
+        for layer in network.layers() {
+            assert!(Rc::ptr_eq(layer.borrow().training_backend.as_ref().unwrap(), &training_backend));
+        }
+
+        let (expected_network, expected_input_gradient) = linear_sigmoid_backward(None);
+        assert_eq!(expected_input_gradient, input_gradient);
+        assert_eq!(read(&expected_network.learnable_weights_gradients()[0]),
+                   read(&network.learnable_weights_gradients()[0]));
+    }
+
+    #[test]
+    fn f64_loss_accumulation_is_more_accurate() {
+        let mut losses = vec![1f32];
+        losses.extend(vec![1e-8f32; 1_000_000]);
+        let exact = 1.01f64;
+
+        let naive = sum_losses(&losses, false);
+        let accumulated = sum_losses(&losses, true);
+        assert!((accumulated as f64 - exact).abs() < 1e-6);
+        assert!((naive as f64 - exact).abs() > (accumulated as f64 - exact).abs());
+    }
+
+    #[test]
+    fn total_loss_sums_loss_layers() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("network_out", &[2, 2]);
+        cfg.add_input("label", &[2, 1]);
+        cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 2 }));
+        cfg.accumulate_loss_in_f64 = true;
+        let mut objective = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("objective", cfg)).unwrap();
+
+        objective.forward(&[tensor(&[2, 2], &[-0.5, -1.0, -2.0, -0.1]), tensor(&[2, 1], &[0.0, 1.0])]);
+        assert!((objective.total_loss() - 0.3).abs() < 1e-6);
+    }
+
+    fn set_weights(network: &Layer<Backend<Native>>, weights: &[&[f32]]) {
+        let native = native_backend();
+        for (weight, values) in network.learnable_weights_data().iter().zip(weights) {
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), values);
+        }
+    }
+
+    #[test]
+    fn connected_networks_match_merged_network() {
+        let backend = Rc::new(native_backend());
+        let hidden_weights = [0.2f32, -0.4, 0.6, 0.1, 0.3, -0.5, -0.2, 0.7, 0.4];
+        let output_weights = [0.5f32, -0.3, 0.8, -0.6, 0.2, 0.1];
+
+        let mut merged_cfg = SequentialConfig::default();
+        merged_cfg.add_input("data", &[2, 3]);
+        merged_cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+        merged_cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        merged_cfg.add_layer(LayerConfig::new("output", LinearConfig { output_size: 2 }));
+        let mut merged = Layer::from_config(backend.clone(), &LayerConfig::new("merged", merged_cfg)).unwrap();
+        set_weights(&merged, &[&hidden_weights, &output_weights]);
+
+        let mut feature_cfg = SequentialConfig::default();
+        feature_cfg.add_input("data", &[2, 3]);
+        feature_cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+        feature_cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        let mut features = Layer::from_config(backend.clone(), &LayerConfig::new("features", feature_cfg)).unwrap();
+        set_weights(&features, &[&hidden_weights]);
+
+        let mut head_cfg = SequentialConfig::default();
+        head_cfg.add_input("features", &[2, 3]);
+        head_cfg.add_layer(LayerConfig::new("output", LinearConfig { output_size: 2 }));
+        let mut head = Layer::from_config(backend, &LayerConfig::new("head", head_cfg)).unwrap();
+        set_weights(&head, &[&output_weights]);
+
+        let input = tensor(&[2, 3], &[0.5, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        let feature_out = features.forward(&[input.clone()])[0].clone();
+        assert!(head.connect_input("missing", feature_out.clone()).is_err());
+        assert!(head.connect_input("features", tensor(&[2, 2], &[0.0; 4])).is_err());
+        head.connect_input("features", feature_out.clone()).unwrap();
+        assert!(Arc::ptr_eq(&feature_out, &head.input_blobs_data[0]));
+
+        let head_out = head.forward(&[])[0].clone();
+        let merged_out = merged.forward(&[input])[0].clone();
+        assert_eq!(read(&merged_out), read(&head_out));
+
+        let output_gradient = tensor(&[2, 2], &[1.0, -0.5, 0.25, 2.0]);
+        let merged_gradient = merged.backward(&[output_gradient.clone()]);
+        let head_gradient = head.backward(&[output_gradient]);
+        let chained_gradient = features.backward(&head_gradient[0..1]);
+        assert_eq!(read(&merged_gradient[0]), read(&chained_gradient[0]));
+        let merged_weight_gradients = merged.learnable_weights_gradients();
+        assert_eq!(read(&merged_weight_gradients[0]), read(&features.learnable_weights_gradients()[0]));
+        assert_eq!(read(&merged_weight_gradients[1]), read(&head.learnable_weights_gradients()[0]));
+    }
+
+    #[test]
+    fn run_standalone_relu() {
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&[2, 2]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[-1f32, 0f32, 2f32, -3f32]);
+
+        let outputs = Layer::run_standalone(Rc::new(native_backend()),
+                                            &LayerConfig::new("relu", LayerType::ReLU),
+                                            &[input]);
+        assert_eq!(1, outputs.len());
+        assert_eq!(&vec![2, 2], outputs[0].desc());
+        assert_eq!(&[0f32, 0f32, 2f32, 0f32],
+                   outputs[0].read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn sync_weights_to_reports_unwritten_weights() {
+        let native = native_backend();
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 3]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        assert!(network.sync_weights_to(native.device()).is_ok());
+
+        {
+            let mut linear = network.layers().next().unwrap().borrow_mut();
+            let shape = linear.weights_data[0].read().unwrap().desc().clone();
+            linear.weights_data[0] = Arc::new(RwLock::new(SharedTensor::new(&shape)));
+        }
+        let error = network.sync_weights_to(native.device()).unwrap_err();
+        assert!(error.contains("linear-0"), "{}", error);
+    }
+
+    #[test]
+    fn weights_written_by_the_layer_are_latest_on_their_device() {
+        let native = native_backend();
+        let mut linear_cfg = LayerConfig::new("linear", LinearConfig { output_size: 2 });
+        linear_cfg.params.push(WeightConfig {
+            filler: Some(FillerType::Constant { value: 0.5 }),
+            ..WeightConfig::default()
+        });
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 3]);
+        cfg.add_layer(linear_cfg);
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        assert!(network.unsynced_weights(native.device()).is_empty());
+
+        network.clear_weights_gradients();
+        network.update_weights(&native);
+        assert!(network.unsynced_weights(native.device()).is_empty());
+        network.assert_weights_synced(native.device());
+    }
+
+    #[test]
+    fn init_report_lists_blobs_weights_and_fillers() {
+        let mut linear_cfg = LayerConfig::new("linear", LinearConfig { output_size: 4 });
+        linear_cfg.params.push(WeightConfig {
+            name: "linear_weight".to_owned(),
+            filler: Some(FillerType::Constant { value: 0.5 }),
+            ..WeightConfig::default()
+        });
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[2, 3]);
+        net_cfg.add_layer(linear_cfg);
+        net_cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        let (_, report) = Layer::from_config_with_report(Rc::new(native_backend()),
+                                                         &LayerConfig::new("network", net_cfg)).unwrap();
+
+        assert_eq!(2, report.layers.len());
+        let linear = report.layer("linear").unwrap();
+        assert_eq!("Linear", linear.layer_type);
+        assert_eq!("data", linear.inputs[0].name);
+        assert_eq!(vec![2, 3], linear.inputs[0].shape);
+        assert_eq!(vec![2, 4], linear.outputs[0].shape);
+        assert_eq!(1, linear.weights.len());
+        assert_eq!("linear_weight", linear.weights[0].name);
+        assert_eq!(vec![4, 3], linear.weights[0].shape);
+        assert_eq!(Some("Constant { value: 0.5 }".to_owned()), linear.weights[0].filler);
+        assert!(!linear.weights[0].shared);
+        assert!(linear.needs_backward);
+
+        let sigmoid = report.layer("sigmoid").unwrap();
+        assert_eq!(linear.outputs[0].name, sigmoid.inputs[0].name);
+        assert!(sigmoid.weights.is_empty());
+
+        let text = report.to_string();
+        assert!(text.contains("Layer linear (Linear)"), "{}", text);
+        assert!(text.contains("weight linear_weight [4, 3] - Constant { value: 0.5 }"), "{}", text);
+    }
+
+    #[test]
+    fn seeded_default_filler_fills_every_weight_differently() {
+        let config = |std: f32| {
+            let mut net_cfg = SequentialConfig::default();
+            net_cfg.add_input("data", &[2, 3]);
+            net_cfg.add_layer(LayerConfig::new("first", LinearConfig { output_size: 3 }));
+            net_cfg.add_layer(LayerConfig::new("second", LinearConfig { output_size: 3 }));
+            net_cfg.init_defaults = Some(InitDefaults::new().with_filler("Linear", 0, FillerType::Gaussian {
+                mean: 0f32,
+                std: std,
+                seed: Some(7),
+            }));
+            LayerConfig::new("network", net_cfg)
+        };
+        let values = |layer: &Layer<Backend<Native>>| {
+            let native = native_backend();
+            layer.learnable_weights_data()
+                .iter()
+                .map(|weight| weight.read().unwrap().read(native.device()).unwrap().as_slice::<f32>().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let network = Layer::from_config(Rc::new(native_backend()), &config(0.1)).unwrap();
+        let weights = values(&network);
+        assert!(weights[0] != weights[1]);
+        let rebuilt = Layer::from_config(Rc::new(native_backend()), &config(0.1)).unwrap();
+        assert_eq!(weights, values(&rebuilt));
+
+        match Layer::from_config(Rc::new(native_backend()), &config(-0.1)) {
+            Err(LayerError::InvalidConfig { ref message, .. }) => assert!(message.contains("init_defaults")),
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn dry_run_reports_like_real_construction() {
+        let mut linear_cfg = LayerConfig::new("linear", LinearConfig { output_size: 4 });
+        linear_cfg.params.push(WeightConfig {
+            filler: Some(FillerType::Constant { value: 0.5 }),
+            ..WeightConfig::default()
+        });
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[2, 3]);
+        net_cfg.add_layer(linear_cfg);
+        net_cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        net_cfg.add_layer(LayerConfig::new("output", LinearConfig { output_size: 2 }));
+        net_cfg.init_defaults = Some(InitDefaults::new().with_filler("Linear", 0, FillerType::Glorot {
+            input_size: 0,
+            output_size: 0,
+        }));
+        let config = LayerConfig::new("network", net_cfg);
+
+        let report = dry_run(&config).unwrap();
+        let (_, expected) = Layer::from_config_with_report(Rc::new(native_backend()), &config).unwrap();
+        assert_eq!(expected, report);
+        assert_eq!(3, report.layers.len());
+        assert_eq!(vec![2, 4], report.layers[1].inputs[0].shape);
+        assert!(report.layer("output").unwrap().weights[0].filler.as_ref().unwrap().starts_with("Glorot"));
+        // no blob holds memory
+        let native = native_backend();
+        let layer = dry_run_layer(&config).unwrap();
+        let weights = layer.learnable_weights_data();
+        assert_eq!(2, weights.len());
+        for blob in weights.iter().chain(&layer.learnable_weights_gradients()).chain(&layer.output_blobs_data) {
+            assert!(blob.read().unwrap().read(native.device()).is_err());
+        }
+        let constructed = Layer::from_config(Rc::new(native_backend()), &config).unwrap();
+        assert_eq!(constructed.estimated_memory().total(), layer.estimated_memory().total());
+
+        let mut invalid = config.clone();
+        if let LayerType::Sequential(ref mut net_cfg) = invalid.layer_type {
+            net_cfg.layers[2] = LayerConfig::new("output", LinearConfig { output_size: 0 });
+        }
+        match dry_run(&invalid) {
+            Err(LayerError::InvalidConfig { ref layer, .. }) => assert_eq!("output", layer),
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature="cuda")]
+    fn weights_filled_on_native_and_updated_on_cuda_are_saved() {
+        let native = native_backend();
+        let cuda = Rc::new(Backend::<Cuda>::default().unwrap());
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[1, 2]);
+        net_cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(cuda.clone(), &LayerConfig::new("network", net_cfg)).unwrap();
+        let names = network.learnable_weights_names();
+
+        // fill on native
+        network.fill_weight_with(&names[0], |_, i| (i + 1) as f32).unwrap();
+        assert_eq!(names, network.unsynced_weights(cuda.device()));
+        network.sync_weights_to(cuda.device()).unwrap();
+        assert!(network.unsynced_weights(cuda.device()).is_empty());
+        // train on cuda
+        let gradient = network.learnable_weights_gradients()[0].clone();
+        write_to_memory(gradient.write().unwrap().write_only(native.device()).unwrap(), &[1f32, 1f32, 1f32, 1f32]);
+        network.update_weights(&*cuda);
+        // the host copy is stale until it is synchronized
+        assert_eq!(names, network.unsynced_weights(native.device()));
+        // save and load on native
+        let path = temp_path("sync_weights.capnp");
+        network.sync_weights_to(native.device()).unwrap();
+        assert!(network.unsynced_weights(native.device()).is_empty());
+        network.save(&path).unwrap();
+        let loaded = Layer::<Backend<Native>>::load(Rc::new(native), &path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(vec![0f32, 1f32, 2f32, 3f32], read(&loaded.learnable_weights_data()[0]));
+    }
+
+    #[test]
+    #[cfg(feature="cuda")]
+    fn weights_pruned_after_training_on_cuda_are_saved() {
+        let native = native_backend();
+        let cuda = Rc::new(Backend::<Cuda>::default().unwrap());
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[1, 2]);
+        net_cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(cuda.clone(), &LayerConfig::new("network", net_cfg)).unwrap();
+        let names = network.learnable_weights_names();
+        network.fill_weight_with(&names[0], |_, i| (i + 1) as f32).unwrap();
+        network.sync_weights_to(cuda.device()).unwrap();
+
+        // train on cuda
+        let gradient = network.learnable_weights_gradients()[0].clone();
+        write_to_memory(gradient.write().unwrap().write_only(native.device()).unwrap(), &[1f32, 1f32, 1f32, 1f32]);
+        network.update_weights(&*cuda);
+        // pruning writes the latest values on the host
+        network.prune_by_magnitude(0.5f32);
+        assert!(network.unsynced_weights(native.device()).is_empty());
+        assert_eq!(names, network.unsynced_weights(cuda.device()));
+
+        let path = temp_path("pruned_weights.capnp");
+        network.save(&path).unwrap();
+        let loaded = Layer::<Backend<Native>>::load(Rc::new(native), &path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(vec![0f32, 0f32, 2f32, 3f32], read(&loaded.learnable_weights_data()[0]));
+    }
+
+    #[test]
+    fn bundle_of_trained_network_predicts_identically() {
+        use solver::{Solver, SolverConfig};
+
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[2, 2]);
+        net_cfg.add_layer(LayerConfig::new("preprocess", LayerType::MeanScale));
+        net_cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[2, 3]);
+        obj_cfg.add_input("label", &[2, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 3 }));
+        let cfg = SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 0.1,
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        let mean = solver.network().non_learnable_weights_data()[0].clone();
+        write_to_memory(mean.write().unwrap().write_only(native_backend().device()).unwrap(),
+                        &[1f32, -2f32]);
+        let inputs = [3f32, 0.5, -1.0, 1.25];
+        for _ in 0..3 {
+            solver.train_minibatch(tensor(&[2, 2], &inputs), tensor(&[2, 1], &[0f32, 2f32]));
+        }
+
+        let path = temp_path("bundle.capnp");
+        solver.mut_network().export_bundle(&path).unwrap();
+        let expected = read(&solver.mut_network().forward(&[tensor(&[2, 2], &inputs)])[0]);
+        let mut loaded = Layer::<Backend<Native>>::load_bundle(Rc::new(native_backend()), &path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(vec!["data".to_owned()], loaded.input_names());
+        assert_eq!(expected, read(&loaded.forward(&[tensor(&[2, 2], &inputs)])[0]));
+    }
+
+    #[test]
+    fn bundles_of_other_versions_or_with_missing_sections_are_rejected() {
+        use juice_capnp::bundle as capnp_bundle;
+
+        let path = temp_path("invalid_bundle.capnp");
+        let error = |version: Option<u32>| {
+            match version {
+                Some(version) => {
+                    let mut message = ::capnp::message::Builder::new_default();
+                    message.init_root::<capnp_bundle::Builder>().set_format_version(version);
+                    let ref mut out = ::std::fs::File::create(&path).unwrap();
+                    ::capnp::serialize_packed::write_message(out, &message).unwrap();
+                }
+                // a network written by save is not a bundle
+                None => {
+                    let mut cfg = SequentialConfig::default();
+                    cfg.add_input("data", &[1, 2]);
+                    let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg))
+                        .unwrap();
+                    network.save(&path).unwrap();
+                }
+            }
+            let error = Layer::<Backend<Native>>::load_bundle(Rc::new(native_backend()), &path).unwrap_err();
+            ::std::fs::remove_file(&path).unwrap();
+            assert_eq!(::std::io::ErrorKind::InvalidData, error.kind());
+            error.to_string()
+        };
+
+        assert!(error(Some(BUNDLE_FORMAT_VERSION + 1)).contains("format version is 2, but only version 1"));
+        assert!(error(Some(BUNDLE_FORMAT_VERSION)).contains("the layer section is missing"));
+        assert!(error(None).contains("the format version is missing"));
+    }
+
+    #[test]
+    fn layers_with_malformed_weights_are_rejected() {
+        use juice_capnp::layer as capnp_layer;
+
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let path = temp_path("malformed_layer.capnp");
+        {
+            let mut message = ::capnp::message::Builder::new_default();
+            {
+                let mut layer = message.init_root::<capnp_layer::Builder>();
+                network.write_capnp(&mut layer);
+                // the weight of shape [3, 2] has only a single value
+                layer.borrow().get_weights_data().unwrap().get(0).get_tensor().unwrap().init_data(1);
+            }
+            let ref mut out = ::std::fs::File::create(&path).unwrap();
+            ::capnp::serialize_packed::write_message(out, &message).unwrap();
+        }
+        let error = Layer::<Backend<Native>>::load(Rc::new(native_backend()), &path).unwrap_err();
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(::std::io::ErrorKind::InvalidData, error.kind());
+        assert!(error.to_string().contains("has 1 values"), "{}", error);
+    }
+
+    fn config_error(config: LayerConfig) -> String {
+        match Layer::from_config(Rc::new(native_backend()), &config) {
+            Err(err @ LayerError::InvalidConfig { .. }) => err.to_string(),
+            Err(err) => panic!("Expected an invalid config, got: {}", err),
+            Ok(_) => panic!("The config of the layer {} has not been rejected", config.name),
+        }
+    }
+
+    #[test]
+    fn invalid_configs_name_layer_and_field() {
+        let conv = |num_output, filter_size, stride| {
+            ConvolutionConfig {
+                num_output: num_output,
+                filter_shape: vec![filter_size],
+                stride: vec![stride],
+                padding: vec![0],
+                bias: false,
+            }
+        };
+        let pool = |filter_size, padding| {
+            PoolingConfig {
+                mode: PoolingMode::Max,
+                filter_shape: vec![filter_size],
+                stride: vec![1],
+                padding: vec![padding],
+                padding_value: None,
+            }
+        };
+        let mut input_shapes = SequentialConfig::default();
+        input_shapes.add_input("data", &[0, 4]);
+        let mut duplicate_inputs = SequentialConfig::default();
+        duplicate_inputs.add_input("data", &[2, 4]);
+        duplicate_inputs.add_input("data", &[2, 4]);
+
+        let cases: Vec<(LayerConfig, &str)> =
+            vec![(LayerConfig::new("conv", conv(0, 3, 1)), "num_output"),
+                 (LayerConfig::new("conv", conv(8, 0, 1)), "filter_shape"),
+                 (LayerConfig::new("conv", conv(8, 3, 0)), "stride"),
+                 (LayerConfig::new("linear", LinearConfig { output_size: 0 }), "output_size"),
+                 (LayerConfig::new("pool", pool(2, 2)), "padding"),
+                 (LayerConfig::new("pool", pool(0, 0)), "filter_shape"),
+                 (LayerConfig::new("lrn", LRNConfig { local_size: 4, ..LRNConfig::default() }), "local_size"),
+                 (LayerConfig::new("lrn", LRNConfig { k: 0f32, ..LRNConfig::default() }), "k"),
+                 (LayerConfig::new("dropout", DropoutConfig { probability: 1.5 }), "probability"),
+                 (LayerConfig::new("bn", BatchNormConfig { epsilon: 0f32, ..BatchNormConfig::default() }), "epsilon"),
+                 (LayerConfig::new("elu", ELUConfig { alpha: -1f32 }), "alpha"),
+                 (LayerConfig::new("network", input_shapes), "inputs"),
+                 (LayerConfig::new("network", duplicate_inputs), "inputs"),
+                 (LayerConfig::new("spectral_norm",
+                                   SpectralNormConfig {
+                                       power_iterations: 0,
+                                       ..SpectralNormConfig::new(conv(8, 3, 1))
+                                   }),
+                  "power_iterations"),
+                 (LayerConfig::new("spectral_norm", SpectralNormConfig::new(LinearConfig { output_size: 0 })),
+                  "output_size"),
+                 (LayerConfig::new("stochastic_depth", StochasticDepthConfig::new(LayerType::ReLU, 0f32)),
+                  "survival_prob"),
+                 (LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 0 }), "num_classes"),
+                 (LayerConfig::new("triplet", TripletLossConfig { margin: -1f32 }), "margin"),
+                 (LayerConfig::new("contrastive", ContrastiveLossConfig { margin: ::std::f32::NAN }), "margin"),
+                 (LayerConfig::new("softmax_loss", SoftmaxLossConfig::new(0)), "num_classes"),
+                 (LayerConfig::new("softmax_loss",
+                                   SoftmaxLossConfig { class_weights: vec![1f32], ..SoftmaxLossConfig::new(3) }),
+                  "class_weights"),
+                 (LayerConfig::new("smooth_l1", SmoothL1LossConfig { beta: 0f32 }), "beta"),
+                 (LayerConfig::new("reshape", ReshapeConfig::of_shape(&[2, 0])), "shape"),
+                 (LayerConfig::new("split",
+                                   SplitConfig {
+                                       axis: 1,
+                                       sizes: vec![],
+                                       num_chunks: 0,
+                                   }),
+                  "num_chunks"),
+                 (LayerConfig::new("split", SplitConfig::with_sizes(1, &[2, 0])), "sizes"),
+                 (LayerConfig::new("custom", CustomConfig::new("NotRegistered")), "layer_type")];
+        for (config, field) in cases {
+            let name = config.name.clone();
+            let message = config_error(config);
+            assert!(message.contains(&format!("'{}'", name)), "{}", message);
+            assert!(message.contains(field), "{}", message);
+        }
+
+        // layer types without fields of their own are checked for the fields every layer has
+        for layer_type in vec![LayerType::LogSoftmax,
+                               LayerType::MeanScale,
+                               LayerType::Softmax,
+                               LayerType::ReLU,
+                               LayerType::TanH,
+                               LayerType::Sigmoid,
+                               LayerType::NchwToNhwc,
+                               LayerType::NhwcToNchw] {
+            let mut config = LayerConfig::new("fieldless", layer_type);
+            config.add_input("data");
+            config.propagate_down = vec![true, false];
+            let message = config_error(config);
+            assert!(message.contains("'fieldless'") && message.contains("propagate_down"), "{}", message);
         }
     }
+
+    #[test]
+    fn invalid_layer_config_is_reported_through_sequential() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+        cfg.add_layer(LayerConfig::new("empty", LinearConfig { output_size: 0 }));
+
+        let message = config_error(LayerConfig::new("network", cfg));
+        assert_eq!("Invalid config of the Linear layer 'empty': output_size must be positive, but is 0",
+                   message);
+    }
+
+    #[test]
+    fn forward_hook_fires_once_per_forward_pass() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("linear1", LinearConfig { output_size: 4 }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let shapes = Rc::new(RefCell::new(Vec::new()));
+        let recorded = shapes.clone();
+        let hook = move |inputs: &[&SharedTensor<f32>], outputs: &[&SharedTensor<f32>]| {
+            assert_eq!(1, inputs.len());
+            recorded.borrow_mut().push(outputs[0].desc().clone());
+        };
+        network.register_forward_hook("relu", Box::new(hook)).unwrap();
+        let noop = |_: &[&SharedTensor<f32>], _: &[&SharedTensor<f32>]| {};
+        assert!(network.register_forward_hook("unknown", Box::new(noop)).unwrap_err().contains("unknown"));
+
+        let input = tensor(&[2, 3], &[1.0, -2.0, 0.5, 3.0, 0.0, -1.0]);
+        network.forward(&[input.clone()]);
+        assert_eq!(vec![vec![2, 4]], *shapes.borrow());
+        network.forward(&[input]);
+        assert_eq!(vec![vec![2, 4], vec![2, 4]], *shapes.borrow());
+    }
+
+    #[test]
+    fn spectral_norm_converges_to_largest_singular_value() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let weight = network.learnable_weights_data()[0].clone();
+        write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        // the singular values of [[1, 2], [3, 4], [5, 6]] are 9.5255 and 0.5143
+        let sigma = network.spectral_norm("linear-0", 20).unwrap();
+        assert!((sigma - 9.52551809).abs() < 1e-4, "{}", sigma);
+        // the cached singular vectors keep the estimate with a single iteration
+        let sigma = network.spectral_norm("linear-0", 1).unwrap();
+        assert!((sigma - 9.52551809).abs() < 1e-4, "{}", sigma);
+
+        assert!(network.spectral_norm("unknown", 1).unwrap_err().contains("unknown"));
+    }
 }
@@ -0,0 +1,202 @@
+//! Applies the nonlinear Exponential Linear Unit.
+//!
+//! Non-linearity activation function: y = x for x > 0, y = alpha * (exp(x) - 1) otherwise
+//!
+//! Unlike ReLU, ELU has negative outputs for negative inputs, which pushes the mean of
+//! the activations towards zero. See [Fast and Accurate Deep Network Learning by
+//! Exponential Linear Units (ELUs)][1].
+//! [1]: https://arxiv.org/abs/1511.07289
+//!
+//! The gradient is computed from the output of the forward pass: it is `1` for positive
+//! outputs and `y + alpha` otherwise, so the backward pass doesn't evaluate `exp` again.
+
+use capnp_util::*;
+use co::{IBackend, SharedTensor};
+use juice_capnp::elu_config as capnp_config;
+use layer::*;
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug, Clone)]
+/// ELU Activation Layer
+pub struct ELU {
+    alpha: f32,
+}
+
+impl ELU {
+    /// Create an ELU layer from an ELUConfig.
+    pub fn from_config(config: &ELUConfig) -> ELU {
+        ELU { alpha: config.alpha }
+    }
+}
+
+impl<B: IBackend> ILayer<B> for ELU {
+    impl_ilayer_activation!();
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn compute_in_place(&self) -> bool {
+        true
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        if let Some(inp) = input_data.get(0) {
+            let read_inp = inp.read().unwrap();
+            let input_desc = read_inp.desc();
+            input_gradient[0].write().unwrap().resize(input_desc).unwrap();
+            output_data[0].write().unwrap().resize(input_desc).unwrap();
+            output_gradient[0].write().unwrap().resize(input_desc).unwrap();
+        }
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for ELU {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let output = {
+            // in-place the input is the output
+            let input = match input_data.get(0) {
+                Some(input) => input.read(native.device()).unwrap().as_slice::<f32>(),
+                None => output_data[0].read(native.device()).unwrap().as_slice::<f32>(),
+            };
+            input.iter()
+                .map(|&x| if x > 0f32 { x } else { self.alpha * x.exp_m1() })
+                .collect::<Vec<_>>()
+        };
+        write_to_memory(output_data[0].write_only(native.device()).unwrap(), &output);
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for ELU {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let gradient = {
+            // in-place the output is the input and the output gradient is the input gradient
+            let output = output_data.get(0).unwrap_or(&input_data[0]).read(native.device()).unwrap();
+            let output_gradient = match output_gradients.get(0) {
+                Some(gradient) => gradient.read(native.device()).unwrap(),
+                None => input_gradients[0].read(native.device()).unwrap(),
+            };
+            output.as_slice::<f32>()
+                .iter()
+                .zip(output_gradient.as_slice::<f32>())
+                .map(|(&y, gradient)| if y > 0f32 { *gradient } else { gradient * (y + self.alpha) })
+                .collect::<Vec<_>>()
+        };
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(), &gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for ELU {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Specifies configuration parameters for an ELU Layer.
+pub struct ELUConfig {
+    /// The value the output approaches for large negative inputs is `-alpha`.
+    ///
+    /// Default: `1.0`
+    pub alpha: f32,
+}
+
+impl Default for ELUConfig {
+    fn default() -> ELUConfig {
+        ELUConfig { alpha: 1f32 }
+    }
+}
+
+impl ELUConfig {
+    /// Checks that alpha is a non-negative number.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.alpha.is_finite() || self.alpha < 0f32 {
+            return Err(format!("alpha must be a non-negative number, but is {}", self.alpha));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CapnpWrite<'a> for ELUConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the ELUConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_alpha(self.alpha);
+    }
+}
+
+impl<'a> CapnpRead<'a> for ELUConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        ELUConfig { alpha: reader.get_alpha() }
+    }
+}
+
+impl Into<LayerType> for ELUConfig {
+    fn into(self) -> LayerType {
+        LayerType::ELU(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use util::native_backend;
+    use util::testing::{read, tensor};
+
+    /// Two ELU layers, the first of which works in-place unless the blobs are connected
+    /// explicitly.
+    fn network(in_place: bool) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 2]);
+        cfg.add_layer(LayerConfig::new("elu1", ELUConfig { alpha: 0.5 }));
+        cfg.add_layer(LayerConfig::new("elu2", ELUConfig { alpha: 0.5 }));
+        if !in_place {
+            cfg.layers[0].add_output("hidden");
+            cfg.layers[1].add_input("hidden");
+        }
+        Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap()
+    }
+
+    #[test]
+    fn forward_and_backward_match_the_derivative() {
+        let elu = |x: f32| if x > 0f32 { x } else { 0.5 * (x.exp() - 1f32) };
+        let derivative = |x: f32| if x > 0f32 { 1f32 } else { 0.5 * x.exp() };
+        let values = [2f32, 0f32, -1f32, -3f32];
+        let expected_output = values.iter().map(|&x| elu(elu(x))).collect::<Vec<_>>();
+        let expected_gradient = values.iter().map(|&x| 3f32 * derivative(elu(x)) * derivative(x)).collect::<Vec<_>>();
+
+        for in_place in vec![false, true] {
+            let mut network = network(in_place);
+            let output = read(&network.forward(&[tensor(&[2, 2], &values)])[0]);
+            for (expected, actual) in expected_output.iter().zip(&output) {
+                assert!((expected - actual).abs() < 1e-6, "{:?}", output);
+            }
+            let gradient = read(&network.backward(&[tensor(&[2, 2], &[3f32; 4])])[0]);
+            for (expected, actual) in expected_gradient.iter().zip(&gradient) {
+                assert!((expected - actual).abs() < 1e-6, "{:?}", gradient);
+            }
+        }
+    }
+}
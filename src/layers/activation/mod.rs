@@ -29,10 +29,12 @@ macro_rules! impl_ilayer_activation {
     )
 }
 
+pub use self::elu::{ELU, ELUConfig};
 pub use self::relu::ReLU;
 pub use self::sigmoid::Sigmoid;
 pub use self::tanh::TanH;
 
+pub mod elu;
 pub mod relu;
 pub mod sigmoid;
 pub mod tanh;
@@ -0,0 +1,563 @@
+//! Normalizes the input per channel with the statistics of the batch.
+//!
+//! In training mode every channel is normalized with the mean and variance of its values
+//! in the batch, then scaled by `gamma` and shifted by `beta`:
+//!
+//! `y = gamma * (x - mean) / sqrt(variance + epsilon) + beta`
+//!
+//! The layer keeps running estimates of the mean and the variance, which are updated in
+//! every forward pass in training mode:
+//!
+//! `running = momentum * running + (1 - momentum) * batch`
+//!
+//! In inference mode, see [Layer::set_train][1], the running estimates are used instead
+//! of the statistics of the batch. See [Batch Normalization: Accelerating Deep Network
+//! Training by Reducing Internal Covariate Shift][2].
+//! [1]: ../../../layer/struct.Layer.html#method.set_train
+//! [2]: https://arxiv.org/abs/1502.03167
+//!
+//! With [freeze_stats][3] the running estimates are used and kept in training mode as
+//! well, e.g. to fine-tune a network on a small dataset. `gamma` and `beta` are still
+//! trained.
+//! [3]: ./struct.BatchNormConfig.html#structfield.freeze_stats
+//!
+//! A forward pass that [recomputes][4] the output for the backward pass doesn't update the
+//! running estimates a second time.
+//! [4]: ../../../layer/struct.Layer.html#method.set_recomputing
+//!
+//! ## Weights
+//!
+//! The weights of the layer are `gamma`, `beta`, the running mean and the running
+//! variance, each with one value per channel. `gamma` and `beta` are learnable, start out
+//! as `1` and `0` and are not decayed by default. The running estimates start out as `0`
+//! and `1`; they are not [learnable][5], but are saved and loaded with the network.
+//! [5]: ../../../layer/trait.ILayer.html#method.learnable_weight
+//!
+//! ## Input Data
+//!
+//! The layer expects the input to have the channels in its second dimension, e.g. in 4D
+//! NCHW format. The statistics of a channel are taken over the batch and the spatial
+//! dimensions.
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use juice_capnp::batch_norm_config as capnp_config;
+use layer::*;
+use std::cell::{Cell, RefCell};
+use util::{ArcLock, native_backend, write_to_memory};
+use weight::FillerType;
+
+#[derive(Debug)]
+/// [BatchNorm](./index.html) Layer
+pub struct BatchNorm {
+    momentum: f32,
+    epsilon: f32,
+    freeze_stats: bool,
+    train: bool,
+    recomputing: bool,
+
+    /// The normalized input of the last forward pass, before the scale and shift.
+    normalized: RefCell<Vec<f32>>,
+    /// `1 / sqrt(variance + epsilon)` of every channel in the last forward pass.
+    inv_std: RefCell<Vec<f32>>,
+    /// Whether the last forward pass used the statistics of the batch.
+    batch_statistics: Cell<bool>,
+}
+
+impl BatchNorm {
+    /// Create a BatchNorm layer from a BatchNormConfig.
+    pub fn from_config(config: &BatchNormConfig) -> BatchNorm {
+        BatchNorm {
+            momentum: config.momentum,
+            epsilon: config.epsilon,
+            freeze_stats: config.freeze_stats,
+            train: true,
+            recomputing: false,
+
+            normalized: RefCell::new(Vec::new()),
+            inv_std: RefCell::new(Vec::new()),
+            batch_statistics: Cell::new(true),
+        }
+    }
+
+    /// Returns the number of channels and the number of values per channel of a sample.
+    fn channel_dims(shape: &[usize]) -> (usize, usize) {
+        assert!(shape.len() >= 2,
+                "A BatchNorm layer expects the channels in the second dimension of its input, but its shape is {:?}",
+                shape);
+        (shape[1], shape[2..].iter().fold(1, |size, dim| size * dim))
+    }
+
+    /// Returns the sums over the values of every channel of `values` of shape `shape`.
+    fn channel_sums<F: Fn(usize) -> f32>(shape: &[usize], len: usize, value: F) -> Vec<f32> {
+        let (channels, inner) = Self::channel_dims(shape);
+        let mut sums = vec![0f32; channels];
+        for i in 0..len {
+            sums[i / inner % channels] += value(i);
+        }
+        sums
+    }
+
+    /// Computes the output for the input `input` of shape `shape` and saves the normalized
+    /// input for the backward pass.
+    ///
+    /// Returns the output and the mean and the unbiased variance of the batch, if the
+    /// statistics of the batch have been used.
+    fn normalize(&self,
+                 shape: &[usize],
+                 input: &[f32],
+                 weights: &[&SharedTensor<f32>])
+                 -> (Vec<f32>, Option<(Vec<f32>, Vec<f32>)>) {
+        let native = native_backend();
+        let (channels, inner) = Self::channel_dims(shape);
+        let read = |weight: &SharedTensor<f32>| weight.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+        let (gamma, beta) = (read(weights[0]), read(weights[1]));
+
+        let (mean, variance, statistics) = if self.train && !self.freeze_stats {
+            let count = (input.len() / channels) as f32;
+            let mean = Self::channel_sums(shape, input.len(), |i| input[i])
+                .iter()
+                .map(|sum| sum / count)
+                .collect::<Vec<_>>();
+            let variance = Self::channel_sums(shape, input.len(), |i| (input[i] - mean[i / inner % channels]).powi(2))
+                .iter()
+                .map(|sum| sum / count)
+                .collect::<Vec<_>>();
+            let correction = if count > 1f32 { count / (count - 1f32) } else { 1f32 };
+            let unbiased = variance.iter().map(|variance| variance * correction).collect();
+            (mean.clone(), variance, Some((mean, unbiased)))
+        } else {
+            (read(weights[2]), read(weights[3]), None)
+        };
+
+        let inv_std = variance.iter().map(|variance| 1f32 / (variance + self.epsilon).sqrt()).collect::<Vec<_>>();
+        let normalized = input.iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let channel = i / inner % channels;
+                (value - mean[channel]) * inv_std[channel]
+            })
+            .collect::<Vec<_>>();
+        let output = normalized.iter()
+            .enumerate()
+            .map(|(i, normalized)| {
+                let channel = i / inner % channels;
+                gamma[channel] * normalized + beta[channel]
+            })
+            .collect();
+
+        *self.normalized.borrow_mut() = normalized;
+        *self.inv_std.borrow_mut() = inv_std;
+        self.batch_statistics.set(statistics.is_some());
+        (output, statistics)
+    }
+
+    /// Moves the running estimate `running` towards the statistic `batch` of the batch.
+    fn update_running(&self, running: &ArcLock<SharedTensor<f32>>, batch: &[f32]) {
+        let native = native_backend();
+        let mut running = running.write().unwrap();
+        let updated = running.read(native.device())
+            .unwrap()
+            .as_slice::<f32>()
+            .iter()
+            .zip(batch)
+            .map(|(running, batch)| self.momentum * running + (1f32 - self.momentum) * batch)
+            .collect::<Vec<_>>();
+        write_to_memory(running.write_only(native.device()).unwrap(), &updated);
+    }
+}
+
+impl<B: IBackend> ILayer<B> for BatchNorm {
+    impl_ilayer_common!();
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn auto_weight_blobs(&self) -> bool {
+        true
+    }
+
+    fn num_weight_blobs(&self) -> Option<usize> {
+        Some(4)
+    }
+
+    fn weight_decayable(&self, weight_id: usize) -> bool {
+        false
+    }
+
+    fn learnable_weight(&self, weight_id: usize) -> bool {
+        // gamma and beta, but not the running estimates
+        weight_id < 2
+    }
+
+    fn set_train(&mut self, train: bool) {
+        self.train = train;
+    }
+
+    fn set_recomputing(&mut self, recomputing: bool) {
+        self.recomputing = recomputing;
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let input_desc = input_data[0].read().unwrap().desc().clone();
+        input_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+        output_data[0].write().unwrap().resize(&input_desc).unwrap();
+        output_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+
+        // weights of the current number of channels are kept
+        let channels = vec![Self::channel_dims(&input_desc).0];
+        for (weight_id, initial) in [1f32, 0f32, 0f32, 1f32].iter().enumerate() {
+            if weights_data[weight_id].read().unwrap().desc() == &channels {
+                continue;
+            }
+            let mut weight = weights_data[weight_id].write().unwrap();
+            weight.resize(&channels).unwrap();
+            FillerType::fill_constant(&mut weight, *initial);
+            let mut gradient = weights_gradient[weight_id].write().unwrap();
+            gradient.resize(&channels).unwrap();
+            FillerType::fill_constant(&mut gradient, 0f32);
+        }
+    }
+
+    // overridden to update the running estimates, which are weights of the layer
+    fn forward(&self,
+               backend: &B,
+               input_data: &[ArcLock<SharedTensor<f32>>],
+               weights_data: &[ArcLock<SharedTensor<f32>>],
+               output_data: &mut [ArcLock<SharedTensor<f32>>]) {
+        let native = native_backend();
+        let (output, statistics) = {
+            let input = input_data[0].read().unwrap();
+            let weights = weights_data.iter().map(|weight| weight.read().unwrap()).collect::<Vec<_>>();
+            let weights = weights.iter().map(|weight| &**weight).collect::<Vec<_>>();
+            self.normalize(input.desc(),
+                           input.read(native.device()).unwrap().as_slice::<f32>(),
+                           &weights)
+        };
+        if let Some((mean, variance)) = statistics {
+            if !self.recomputing {
+                self.update_running(&weights_data[2], &mean);
+                self.update_running(&weights_data[3], &variance);
+            }
+        }
+        let mut output_data = output_data[0].write().unwrap();
+        write_to_memory(output_data.write_only(native.device()).unwrap(), &output);
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for BatchNorm {
+    // we are overriding `forward` and not calling `compute_output`
+    fn compute_output(&self,
+                      backend: &B,
+                      weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for BatchNorm {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let shape = input_data[0].desc().clone();
+        let (channels, inner) = Self::channel_dims(&shape);
+        let gradient = {
+            let output_gradient = output_gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+            let gamma = weights_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            let normalized = self.normalized.borrow();
+            let inv_std = self.inv_std.borrow();
+            assert_eq!(normalized.len(),
+                       output_gradient.len(),
+                       "The backward pass of a BatchNorm layer needs a preceding forward pass");
+            let len = output_gradient.len();
+            if self.batch_statistics.get() {
+                // the mean and the variance depend on every value of the channel
+                let count = (len / channels) as f32;
+                let sum = Self::channel_sums(&shape, len, |i| output_gradient[i]);
+                let dot = Self::channel_sums(&shape, len, |i| output_gradient[i] * normalized[i]);
+                (0..len)
+                    .map(|i| {
+                        let channel = i / inner % channels;
+                        gamma[channel] * inv_std[channel] *
+                        (output_gradient[i] - (sum[channel] + normalized[i] * dot[channel]) / count)
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                (0..len)
+                    .map(|i| {
+                        let channel = i / inner % channels;
+                        gamma[channel] * inv_std[channel] * output_gradient[i]
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(), &gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for BatchNorm {
+    fn compute_parameters_gradient(&self,
+                                   backend: &B,
+                                   output_data: &[&SharedTensor<f32>],
+                                   output_gradients: &[&SharedTensor<f32>],
+                                   input_data: &[&SharedTensor<f32>],
+                                   parameters_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let shape = input_data[0].desc().clone();
+        let (gamma_gradient, beta_gradient) = {
+            let output_gradient = output_gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+            let normalized = self.normalized.borrow();
+            let len = output_gradient.len();
+            (Self::channel_sums(&shape, len, |i| output_gradient[i] * normalized[i]),
+             Self::channel_sums(&shape, len, |i| output_gradient[i]))
+        };
+        // accumulated as the weights might be shared with other layers
+        for (weight_id, gradient) in [gamma_gradient, beta_gradient].iter().enumerate() {
+            let accumulated = parameters_gradients[weight_id].read_write(native.device()).unwrap();
+            for (accumulated, gradient) in accumulated.as_mut_slice::<f32>().iter_mut().zip(gradient) {
+                *accumulated += *gradient;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Specifies configuration parameters for a BatchNorm Layer.
+pub struct BatchNormConfig {
+    /// The weight of the old running estimates when they are updated with the statistics
+    /// of a batch.
+    ///
+    /// Default: `0.9`
+    pub momentum: f32,
+    /// The constant added to the variance, which keeps channels of a tiny variance from
+    /// being divided by zero.
+    ///
+    /// Default: `1e-5`
+    pub epsilon: f32,
+    /// Whether the running estimates are used instead of the statistics of the batch and
+    /// are not updated in training mode as well.
+    ///
+    /// The gradients of `gamma` and `beta` are computed either way.
+    ///
+    /// Default: `false`
+    pub freeze_stats: bool,
+}
+
+impl Default for BatchNormConfig {
+    fn default() -> BatchNormConfig {
+        BatchNormConfig {
+            momentum: 0.9,
+            epsilon: 1e-5,
+            freeze_stats: false,
+        }
+    }
+}
+
+impl BatchNormConfig {
+    /// Checks that the momentum is in `[0, 1]` and that epsilon is positive.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.momentum >= 0f32 && self.momentum <= 1f32) {
+            return Err(format!("momentum must be in [0, 1], but is {}", self.momentum));
+        }
+        if !self.epsilon.is_finite() || self.epsilon <= 0f32 {
+            return Err(format!("epsilon must be a positive number, but is {}", self.epsilon));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CapnpWrite<'a> for BatchNormConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the BatchNormConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_momentum(self.momentum);
+        builder.set_epsilon(self.epsilon);
+        builder.set_freeze_stats(self.freeze_stats);
+    }
+}
+
+impl<'a> CapnpRead<'a> for BatchNormConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        BatchNormConfig {
+            momentum: reader.get_momentum(),
+            epsilon: reader.get_epsilon(),
+            freeze_stats: reader.get_freeze_stats(),
+        }
+    }
+}
+
+impl Into<LayerType> for BatchNormConfig {
+    fn into(self) -> LayerType {
+        LayerType::BatchNorm(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use util::{native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+
+    /// A BatchNorm layer of two channels with a gamma of `[2, 0.5]` and a beta of `[1, -1]`.
+    fn network(momentum: f32) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[3, 2]);
+        cfg.add_layer(LayerConfig::new("bn", BatchNormConfig { momentum: momentum, ..BatchNormConfig::default() }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(), &[2f32, 0.5]);
+        write_to_memory(weights[1].write().unwrap().write_only(native.device()).unwrap(), &[1f32, -1.0]);
+        network
+    }
+
+    fn assert_close(expected: &[f32], actual: &[f32], tolerance: f32) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < tolerance, "expected {:?}, got {:?}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn training_updates_running_statistics_used_in_inference() {
+        let mut network = network(0.5);
+        assert_eq!(vec!["bn-0", "bn-1"], network.learnable_weights_names());
+        assert_eq!(vec!["bn-2", "bn-3"], network.non_learnable_weights_names());
+        // channel 0 has a mean of 2 and a variance of 2/3, channel 1 a mean of 0 and a variance of 8/3
+        let input = tensor(&[3, 2], &[1f32, -2.0, 2.0, 0.0, 3.0, 2.0]);
+        let output = read(&network.forward(&[input.clone()])[0]);
+        let x = 1f32 / (2f32 / 3f32 + 1e-5).sqrt();
+        let y = 1f32 / (8f32 / 3f32 + 1e-5).sqrt();
+        assert_close(&[1.0 - 2.0 * x, -1.0 - 1.0 * y, 1.0, -1.0, 1.0 + 2.0 * x, -1.0 + 1.0 * y], &output, 1e-5);
+
+        // the unbiased variances are 1 and 4
+        let statistics = network.non_learnable_weights_data();
+        assert_close(&[1.0, 0.0], &read(&statistics[0]), 1e-6);
+        assert_close(&[1.0, 2.5], &read(&statistics[1]), 1e-6);
+
+        network.set_train(false);
+        let output = read(&network.forward(&[input])[0]);
+        let x = 1f32 / (1f32 + 1e-5).sqrt();
+        let y = 1f32 / (2.5f32 + 1e-5).sqrt();
+        assert_close(&[1.0, -1.0 - y, 1.0 + 2.0 * x, -1.0, 1.0 + 4.0 * x, -1.0 + y], &output, 1e-5);
+        assert_close(&[1.0, 0.0], &read(&statistics[0]), 1e-6);
+    }
+
+    #[test]
+    fn frozen_statistics_are_used_and_kept_while_training() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[3, 2]);
+        let bn = BatchNormConfig { freeze_stats: true, ..BatchNormConfig::default() };
+        cfg.add_layer(LayerConfig::new("bn", bn));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let statistics = network.non_learnable_weights_data();
+        write_to_memory(statistics[0].write().unwrap().write_only(native.device()).unwrap(), &[1f32, 0.0]);
+        write_to_memory(statistics[1].write().unwrap().write_only(native.device()).unwrap(), &[1f32, 4.0]);
+
+        let input = tensor(&[3, 2], &[1f32, -2.0, 2.0, 0.0, 3.0, 2.0]);
+        let x = 1f32 / (1f32 + 1e-5).sqrt();
+        let y = 1f32 / (4f32 + 1e-5).sqrt();
+        for _ in 0..2 {
+            let output = read(&network.forward(&[input.clone()])[0]);
+            assert_close(&[0.0, -2.0 * y, x, 0.0, 2.0 * x, 2.0 * y], &output, 1e-5);
+            assert_close(&[1.0, 0.0], &read(&statistics[0]), 1e-6);
+            assert_close(&[1.0, 4.0], &read(&statistics[1]), 1e-6);
+        }
+
+        // gamma receives the sum of the normalized input, beta the sum of the output gradient
+        network.clear_weights_gradients();
+        let input_gradient = read(&network.backward(&[tensor(&[3, 2], &[1f32; 6])])[0]);
+        assert_close(&[x, y, x, y, x, y], &input_gradient, 1e-5);
+        let gradients = network.learnable_weights_gradients().iter().map(read).collect::<Vec<_>>();
+        assert_close(&[3.0 * x, 0.0], &gradients[0], 1e-5);
+        assert_close(&[3.0, 3.0], &gradients[1], 1e-6);
+    }
+
+    #[test]
+    fn recomputed_forward_updates_running_statistics_once() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[3, 2]);
+        let mut bn = LayerConfig::new("bn", BatchNormConfig { momentum: 0.5, ..BatchNormConfig::default() });
+        bn.recompute_in_backward = true;
+        cfg.add_layer(bn);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        // the backward pass recomputes the output of the BatchNorm layer
+        network.forward(&[tensor(&[3, 2], &[1f32, -2.0, 2.0, 0.0, 3.0, 2.0])]);
+        network.backward(&[tensor(&[3, 2], &[1f32; 6])]);
+        let statistics = network.non_learnable_weights_data();
+        assert_close(&[1.0, 0.0], &read(&statistics[0]), 1e-6);
+        assert_close(&[1.0, 2.5], &read(&statistics[1]), 1e-6);
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut network = network(0.9);
+        let input = [0.3f32, -1.2, 1.5, 0.4, -0.8, 2.0];
+        // the loss is the dot product of the output with these coefficients
+        let coefficients = [1f32, -0.5, 0.25, 2.0, -1.5, 0.75];
+        let loss = |network: &mut Layer<Backend<Native>>, input: &[f32]| {
+            let output = read(&network.forward(&[tensor(&[3, 2], input)])[0]);
+            output.iter().zip(&coefficients).fold(0f32, |loss, (output, coefficient)| loss + output * coefficient)
+        };
+
+        loss(&mut network, &input);
+        network.clear_weights_gradients();
+        let input_gradient = read(&network.backward(&[tensor(&[3, 2], &coefficients)])[0]);
+        let weight_gradients = network.learnable_weights_gradients().iter().map(read).collect::<Vec<_>>();
+
+        let step = 1e-2f32;
+        for i in 0..input.len() {
+            let (mut plus, mut minus) = (input.to_vec(), input.to_vec());
+            plus[i] += step;
+            minus[i] -= step;
+            let numeric = (loss(&mut network, &plus) - loss(&mut network, &minus)) / (2f32 * step);
+            assert!((numeric - input_gradient[i]).abs() < 1e-2,
+                    "input {}: {} != {}",
+                    i,
+                    numeric,
+                    input_gradient[i]);
+        }
+        let weights = network.learnable_weights_data();
+        for (weight, gradient) in weights.iter().zip(&weight_gradients) {
+            let values = read(weight);
+            for channel in 0..2 {
+                let mut shifted = values.clone();
+                shifted[channel] += step;
+                write_to_memory(weight.write().unwrap().write_only(native_backend().device()).unwrap(), &shifted);
+                let plus = loss(&mut network, &input);
+                shifted[channel] -= 2f32 * step;
+                write_to_memory(weight.write().unwrap().write_only(native_backend().device()).unwrap(), &shifted);
+                let minus = loss(&mut network, &input);
+                write_to_memory(weight.write().unwrap().write_only(native_backend().device()).unwrap(), &values);
+                let numeric = (plus - minus) / (2f32 * step);
+                assert!((numeric - gradient[channel]).abs() < 1e-2,
+                        "{} != {}",
+                        numeric,
+                        gradient[channel]);
+            }
+        }
+    }
+}
@@ -10,18 +10,36 @@
 //!
 //! The layer expects the input to be in 4D NCHW format (2 spatial dimensions).
 //!
+//! ## Bias
+//!
+//! With [ConvolutionConfig.bias][1] a learnable bias is added to every output feature map.
+//! The bias is the second weight of the layer and is excluded from weight decay unless
+//! its WeightConfig specifies a `decay_mult`. It is added and its gradient is computed
+//! with gemm, broadcasting and summing with vectors of ones.
+//! [1]: ./struct.ConvolutionConfig.html#structfield.bias
+//!
+//! ## Shared Configs
+//!
+//! Convolution layers with the same input shape, filter, stride and padding on the same
+//! backend share the config of the backend, so its descriptors are only built and its
+//! algorithms only searched once, see [op_cache][2].
+//! [2]: ../../../op_cache/index.html
+//!
 //! [cs231n_convnets]: https://cs231n.github.io/convolutional-networks
 
-use super::FilterLayer;
+use super::{FilterLayer, validate_filter_config};
 use capnp_util::*;
 use co::prelude::*;
+use coblas::plugin::{Axpy, Gemm};
+use coblas::transpose::Transpose;
 use conn;
 use conn::ConvolutionConfig as connConvolutionConfig;
 use layer::*;
 use juice_capnp::convolution_config as capnp_config;
+use op_cache::{self, OpKey};
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
-use util::{ArcLock, cast_vec_usize_to_i32};
+use util::{ArcLock, cast_vec_usize_to_i32, native_scalar, ones};
 use weight::FillerType;
 
 #[derive(Debug, Clone)]
@@ -31,6 +49,7 @@ pub struct Convolution<B: conn::Convolution<f32>> {
     filter_shape: Vec<usize>,
     stride: Vec<usize>,
     padding: Vec<usize>,
+    bias: bool,
 
     workspace: Option<ArcLock<SharedTensor<u8>>>,
     convolution_config: Option<Rc<B::CC>>,
@@ -45,6 +64,7 @@ impl<B: conn::Convolution<f32>> Convolution<B> {
             filter_shape: config.filter_shape.clone(),
             stride: config.stride.clone(),
             padding: config.padding.clone(),
+            bias: config.bias,
 
             workspace: None,
             convolution_config: None,
@@ -69,6 +89,64 @@ impl<B: conn::Convolution<f32>> Convolution<B> {
     }
 }
 
+/// Adds the bias of each feature map to every value of the map in the NCHW `output`.
+fn add_bias<B: IBackend + Gemm<f32>>(backend: &B,
+                                     bias: &SharedTensor<f32>,
+                                     output: &mut SharedTensor<f32>)
+                                     -> Result<(), ::co::error::Error> {
+    let batch_size = output.desc()[0];
+    let num_output = bias.desc().size();
+    let map_size = output.desc().size() / batch_size / num_output;
+    let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+    // the bias of every value of a sample, broadcast over the positions of each map
+    let mut sample_bias = SharedTensor::new(&[num_output, map_size]);
+    try!(backend.gemm(&one,
+                      Transpose::NoTrans,
+                      bias,
+                      Transpose::NoTrans,
+                      &ones(&[1, map_size]),
+                      &zero,
+                      &mut sample_bias));
+    try!(sample_bias.reshape(&[1, num_output * map_size]));
+    // broadcast over the samples and added to the output
+    backend.gemm(&one,
+                 Transpose::NoTrans,
+                 &ones(&[batch_size, 1]),
+                 Transpose::NoTrans,
+                 &sample_bias,
+                 &one,
+                 output)
+}
+
+/// Accumulates the sum of the NCHW `output_gradient` over each feature map into `bias_gradient`.
+fn accumulate_bias_gradient<B: IBackend + Gemm<f32>>(backend: &B,
+                                                     output_gradient: &SharedTensor<f32>,
+                                                     bias_gradient: &mut SharedTensor<f32>)
+                                                     -> Result<(), ::co::error::Error> {
+    let batch_size = output_gradient.desc()[0];
+    let num_output = bias_gradient.desc().size();
+    let map_size = output_gradient.desc().size() / batch_size / num_output;
+    let one = native_scalar(1f32);
+    // summed over the samples
+    let mut sample_gradient = SharedTensor::new(&[1, num_output * map_size]);
+    try!(backend.gemm(&one,
+                      Transpose::NoTrans,
+                      &ones(&[1, batch_size]),
+                      Transpose::NoTrans,
+                      output_gradient,
+                      &native_scalar(0f32),
+                      &mut sample_gradient));
+    try!(sample_gradient.reshape(&[num_output, map_size]));
+    // summed over the positions of each map
+    backend.gemm(&one,
+                 Transpose::NoTrans,
+                 &sample_gradient,
+                 Transpose::NoTrans,
+                 &ones(&[map_size, 1]),
+                 &one,
+                 bias_gradient)
+}
+
 impl<B: conn::Convolution<f32>> FilterLayer for Convolution<B> {
     /// Calculates the number of spatial dimensions for the convolution operation.
     fn num_spatial_dims(&self, input_shape: &[usize]) -> usize {
@@ -109,13 +187,22 @@ impl<B: conn::Convolution<f32>> FilterLayer for Convolution<B> {
     }
 }
 
-impl<B: IBackend + conn::Convolution<f32>> ILayer<B> for Convolution<B> {
+impl<B: IBackend + conn::Convolution<f32> + Axpy<f32> + Gemm<f32> + 'static> ILayer<B> for Convolution<B> {
     impl_ilayer_common!();
 
     fn auto_weight_blobs(&self) -> bool {
         true
     }
 
+    fn num_weight_blobs(&self) -> Option<usize> {
+        if self.bias { Some(2) } else { None }
+    }
+
+    /// Only the filter is decayed, not the bias.
+    fn weight_decayable(&self, weight_id: usize) -> bool {
+        weight_id == 0
+    }
+
     fn reshape(&mut self,
                backend: Rc<B>,
                input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
@@ -136,17 +223,28 @@ impl<B: IBackend + conn::Convolution<f32>> ILayer<B> for Convolution<B> {
             let device = <B as IBackend>::device(&backend);
             let num_spatial_dims = self.num_spatial_dims(inp.desc());
             let mut filter = self.create_filter(input_shape);
-            let stride = cast_vec_usize_to_i32(self.stride_dims(num_spatial_dims));
-            let padding = cast_vec_usize_to_i32(self.padding_dims(num_spatial_dims));
-
-            let config = backend.new_convolution_config(&inp,
-                                        &output_data,
-                                        &mut filter,
-                                        conn::ConvForwardAlgo::Auto,
-                                        conn::ConvBackwardFilterAlgo::Auto,
-                                        conn::ConvBackwardDataAlgo::Auto,
-                                        &stride,
-                                        &padding)
+            let stride_dims = self.stride_dims(num_spatial_dims);
+            let padding_dims = self.padding_dims(num_spatial_dims);
+            let stride = cast_vec_usize_to_i32(stride_dims.clone());
+            let padding = cast_vec_usize_to_i32(padding_dims.clone());
+
+            // layers with the same geometry share the config, see the module documentation
+            let key = OpKey::new::<f32>("convolution",
+                                        &[&input_shape[..],
+                                          &output_shape[..],
+                                          &filter.desc()[..],
+                                          &stride_dims[..],
+                                          &padding_dims[..]]);
+            let config = op_cache::shared_config(&backend, key, || {
+                    backend.new_convolution_config(&inp,
+                                                   &output_data,
+                                                   &mut filter,
+                                                   conn::ConvForwardAlgo::Auto,
+                                                   conn::ConvBackwardFilterAlgo::Auto,
+                                                   conn::ConvBackwardDataAlgo::Auto,
+                                                   &stride,
+                                                   &padding)
+                })
                 .unwrap();
 
             // resize and fill weights
@@ -157,7 +255,12 @@ impl<B: IBackend + conn::Convolution<f32>> ILayer<B> for Convolution<B> {
             };
             filler.fill(&mut weights_data[0].write().unwrap());
             weights_gradient[0].write().unwrap().resize(filter.desc()).unwrap();
-            self.convolution_config = Some(Rc::new(config));
+            if self.bias {
+                weights_data[1].write().unwrap().resize(&[self.num_output]).unwrap();
+                FillerType::Constant { value: 0f32 }.fill(&mut weights_data[1].write().unwrap());
+                weights_gradient[1].write().unwrap().resize(&[self.num_output]).unwrap();
+            }
+            self.convolution_config = Some(config);
         }
     }
 
@@ -181,9 +284,20 @@ impl<B: IBackend + conn::Convolution<f32>> ILayer<B> for Convolution<B> {
         self.workspace = Some(new_workspace.clone());
         Some(new_workspace)
     }
+
+    fn workspace_size(&self) -> usize {
+        self.convolution_config.as_ref().map_or(0, |config| config.workspace_size())
+    }
+
+    /// Every output value is the dot product of a filter with the input channels.
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        let num_spatial_dims = self.num_spatial_dims(&input_shapes[0]);
+        let filter_size = self.spatial_filter_dims(num_spatial_dims).iter().fold(1, |prod, dim| prod * dim);
+        (output_shapes[0].size() * input_shapes[0][1] * filter_size) as u64
+    }
 }
 
-impl<B: IBackend + conn::Convolution<f32>> ComputeOutput<f32, B> for Convolution<B> {
+impl<B: IBackend + conn::Convolution<f32> + Gemm<f32>> ComputeOutput<f32, B> for Convolution<B> {
     fn compute_output(&self,
                       backend: &B,
                       weights: &[&SharedTensor<f32>],
@@ -198,6 +312,9 @@ impl<B: IBackend + conn::Convolution<f32>> ComputeOutput<f32, B> for Convolution
                          &mut workspace,
                          conv_config)
             .unwrap();
+        if self.bias {
+            add_bias(backend, weights[1], output_data[0]).unwrap();
+        }
     }
 }
 
@@ -222,29 +339,36 @@ impl<B: IBackend + conn::Convolution<f32>> ComputeInputGradient<f32, B> for Conv
     }
 }
 
-impl<B: IBackend + conn::Convolution<f32>> ComputeParametersGradient<f32, B> for Convolution<B> {
+impl<B: IBackend + conn::Convolution<f32> + Axpy<f32> + Gemm<f32>> ComputeParametersGradient<f32, B>
+    for Convolution<B> {
     fn compute_parameters_gradient(&self,
                                    backend: &B,
                                    _output_data: &[&SharedTensor<f32>],
                                    output_gradients: &[&SharedTensor<f32>],
                                    input_data: &[&SharedTensor<f32>],
                                    parameters_gradients: &mut [&mut SharedTensor<f32>]) {
-        // TODO: compute gradient w.r.t to bias
-        let filter_gradient = &mut parameters_gradients[0];
+        let mut filter_gradient = SharedTensor::<f32>::new(parameters_gradients[0].desc());
         let conv_config = self.convolution_config.as_ref().unwrap();
         let mut workspace = self.workspace.as_ref().unwrap().write().unwrap();
         // compute gradient w.r.t. filter
         backend.convolution_grad_filter(input_data[0],
                                      output_gradients[0],
-                                     filter_gradient,
+                                     &mut filter_gradient,
                                      &mut workspace,
                                      conv_config)
             .unwrap();
+        // accumulate, as the filter might be shared with other layers
+        backend.axpy(&native_scalar(1f32), &filter_gradient, parameters_gradients[0])
+            .unwrap();
+        if self.bias {
+            // compute gradient w.r.t. bias
+            accumulate_bias_gradient(backend, output_gradients[0], parameters_gradients[1]).unwrap();
+        }
     }
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Specifies configuration parameters for a Convolution Layer.
 pub struct ConvolutionConfig {
     /// The number of output feature maps
@@ -255,6 +379,18 @@ pub struct ConvolutionConfig {
     pub stride: Vec<usize>,
     /// The padding size
     pub padding: Vec<usize>,
+    /// Add a learnable bias to every output feature map
+    pub bias: bool,
+}
+
+impl ConvolutionConfig {
+    /// Checks that there is at least one output feature map and a valid filter.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_output == 0 {
+            return Err("num_output must be positive, but is 0".to_owned());
+        }
+        validate_filter_config(&self.filter_shape, &self.stride, &self.padding)
+    }
 }
 
 impl Into<LayerType> for ConvolutionConfig {
@@ -287,6 +423,7 @@ impl<'a> CapnpWrite<'a> for ConvolutionConfig {
                 padding.set(i as u32, *dim as u64);
             }
         }
+        builder.borrow().set_bias(self.bias);
     }
 }
 
@@ -317,15 +454,17 @@ impl<'a> CapnpRead<'a> for ConvolutionConfig {
             filter_shape: filter_shape,
             stride: stride,
             padding: padding,
+            bias: reader.get_bias(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Convolution, ConvolutionConfig};
+    use super::{Convolution, ConvolutionConfig, accumulate_bias_gradient, add_bias};
     use super::super::FilterLayer;
     use co::*;
+    use layer::ILayer;
 
     #[test]
     #[cfg(feature="cuda")]
@@ -336,6 +475,7 @@ mod tests {
             filter_shape: vec![11],
             padding: vec![2],
             stride: vec![4],
+            bias: false,
         };
         let layer = Convolution::<Backend<Cuda>>::from_config(&cfg);
         let num_spatial_dims = layer.num_spatial_dims(&[1, 3, 224, 224]);
@@ -348,4 +488,140 @@ mod tests {
         assert_eq!(vec![1, 64, 55, 55],
                    layer.calculate_output_shape(&[1, 3, 224, 224]));
     }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn flops_match_formula() {
+        let cfg = ConvolutionConfig {
+            num_output: 64,
+
+            filter_shape: vec![11],
+            padding: vec![2],
+            stride: vec![4],
+            bias: false,
+        };
+        let layer = Convolution::<Backend<Native>>::from_config(&cfg);
+        let input_shape = vec![2, 3, 224, 224];
+        let output_shape = layer.calculate_output_shape(&input_shape);
+        assert_eq!(vec![2, 64, 55, 55], output_shape);
+
+        // out_elems * in_channels * kh * kw
+        let expected = (2 * 64 * 55 * 55) * 3 * (11 * 11);
+        assert_eq!(expected as u64,
+                   <Convolution<Backend<Native>> as ILayer<Backend<Native>>>::flops(&layer,
+                                                                                    &[input_shape],
+                                                                                    &[output_shape]));
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn identical_layers_share_their_config() {
+        use layer::{Layer, LayerConfig};
+        use layers::SequentialConfig;
+        use op_cache;
+        use std::rc::Rc;
+        use util::native_backend;
+
+        // a chain of convolutions that keep the shape of their input
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4, 3, 3]);
+        for i in 0..50 {
+            cfg.add_layer(LayerConfig::new(&format!("conv{}", i),
+                                           ConvolutionConfig {
+                                               num_output: 4,
+                                               filter_shape: vec![1],
+                                               padding: vec![0],
+                                               stride: vec![1],
+                                               bias: false,
+                                           }));
+        }
+        let cfg = LayerConfig::new("network", cfg);
+
+        let backend = Rc::new(native_backend());
+        let network = Layer::from_config(backend.clone(), &cfg).unwrap();
+        let stats = op_cache::stats(&backend);
+        assert_eq!((49, 1), (stats.hits, stats.misses));
+        let again = Layer::from_config(backend.clone(), &cfg).unwrap();
+        let stats = op_cache::stats(&backend);
+        assert_eq!((99, 1), (stats.hits, stats.misses));
+        assert_eq!(1, op_cache::len(&backend));
+
+        // the configs are not shared across backends
+        let other_backend = Rc::new(native_backend());
+        let other = Layer::from_config(other_backend.clone(), &cfg).unwrap();
+        let stats = op_cache::stats(&other_backend);
+        assert_eq!((49, 1), (stats.hits, stats.misses));
+        for network in &[network, again, other] {
+            assert_eq!(50, network.learnable_weights_data().len());
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn bias_is_added_per_feature_map() {
+        use util::{native_backend, write_to_memory};
+
+        let native = native_backend();
+        let tensor = |shape: &[usize], data: &[f32]| {
+            let mut tensor = SharedTensor::new(&shape);
+            write_to_memory(tensor.write_only(native.device()).unwrap(), data);
+            tensor
+        };
+        let read = |tensor: &SharedTensor<f32>| tensor.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+
+        // two samples with two 1x2 feature maps each
+        let mut output = tensor(&[2, 2, 1, 2], &[1f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        add_bias(&native, &tensor(&[2], &[10f32, 20.0]), &mut output).unwrap();
+        assert_eq!(vec![11f32, 12.0, 23.0, 24.0, 15.0, 16.0, 27.0, 28.0], read(&output));
+
+        // the gradient of the bias accumulates over samples and positions
+        let mut bias_gradient = tensor(&[2], &[1f32, 0.0]);
+        let output_gradient = tensor(&[2, 2, 1, 2], &[1f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        accumulate_bias_gradient(&native, &output_gradient, &mut bias_gradient).unwrap();
+        assert_eq!(vec![1f32 + 3.0 + 11.0, 7.0 + 15.0], read(&bias_gradient));
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn bias_is_excluded_from_weight_decay() {
+        use layer::{Layer, LayerConfig};
+        use layers::SequentialConfig;
+        use solver::{RegularizationMethod, SolverConfig};
+        use std::rc::Rc;
+        use weight::WeightConfig;
+
+        let conv_cfg = LayerConfig::new("conv",
+                                        ConvolutionConfig {
+                                            num_output: 4,
+                                            filter_shape: vec![3],
+                                            padding: vec![1],
+                                            stride: vec![1],
+                                            bias: true,
+                                        });
+        let network = |conv_cfg: &LayerConfig| {
+            let mut cfg = SequentialConfig::default();
+            cfg.add_input("data", &[2, 3, 8, 8]);
+            cfg.add_layer(conv_cfg.clone());
+            let backend = Rc::new(Backend::<Native>::default().unwrap());
+            Layer::from_config(backend, &LayerConfig::new("network", cfg)).unwrap()
+        };
+        let solver_cfg = SolverConfig {
+            weight_decay: Some(0.01f32),
+            regularization_method: Some(RegularizationMethod::L2),
+            ..SolverConfig::default()
+        };
+
+        let net = network(&conv_cfg);
+        assert_eq!(&vec![4], net.learnable_weights_data()[1].read().unwrap().desc());
+        let decay_mult = net.learnable_weights_decay_mult();
+        assert_eq!(vec![1f32, 0f32], decay_mult);
+        assert_eq!(0.01f32, solver_cfg.get_weight_decay(0, decay_mult[0]));
+        assert_eq!(0f32, solver_cfg.get_weight_decay(0, decay_mult[1]));
+
+        // a configured decay_mult decays the bias anyway
+        let mut decayed_cfg = conv_cfg.clone();
+        decayed_cfg.params.push(WeightConfig::default());
+        decayed_cfg.params.push(WeightConfig { decay_mult: Some(0.5f32), ..WeightConfig::default() });
+        assert_eq!(vec![1f32, 0.5f32], network(&decayed_cfg).learnable_weights_decay_mult());
+    }
 }
@@ -0,0 +1,251 @@
+//! Randomly zeroes activations during training.
+//!
+//! In training mode every value of the input is dropped with the probability
+//! `probability` and the surviving values are scaled by `1 / (1 - probability)`, so the
+//! expected value of each output equals its input. In inference mode, see
+//! [Layer::set_train][1], the layer passes its input through unchanged and needs no
+//! rescaling. See [Dropout: A Simple Way to Prevent Neural Networks from Overfitting][2].
+//! [1]: ../../../layer/struct.Layer.html#method.set_train
+//! [2]: http://jmlr.org/papers/v15/srivastava14a.html
+//!
+//! The backward pass reuses the mask of the last forward pass, so the gradient only flows
+//! through the kept values.
+//!
+//! A forward pass that [recomputes][3] the output for the backward pass, e.g. in a
+//! checkpoint segment, doesn't draw a new mask but reuses the one of the last forward pass.
+//! [3]: ../../../layer/struct.Layer.html#method.set_recomputing
+
+use capnp_util::*;
+use co::{IBackend, SharedTensor};
+use juice_capnp::dropout_config as capnp_config;
+use layer::*;
+use rand::{self, Rng};
+use std::cell::RefCell;
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug)]
+/// [Dropout](./index.html) Layer
+pub struct Dropout {
+    probability: f32,
+    train: bool,
+    recomputing: bool,
+
+    /// The factor of every value in the last forward pass, `0` for dropped values and
+    /// `1 / (1 - probability)` for kept ones, or `None` if it passed its input through.
+    mask: RefCell<Option<Vec<f32>>>,
+}
+
+impl Dropout {
+    /// Create a Dropout layer from a DropoutConfig.
+    pub fn from_config(config: &DropoutConfig) -> Dropout {
+        Dropout {
+            probability: config.probability,
+            train: true,
+            recomputing: false,
+
+            mask: RefCell::new(None),
+        }
+    }
+
+    /// Draws a new mask for `len` values.
+    fn draw_mask(&self, len: usize) -> Vec<f32> {
+        let mut rng = rand::thread_rng();
+        // with a probability of 1 every value is dropped and the scale is irrelevant
+        let scale = if self.probability < 1f32 { 1f32 / (1f32 - self.probability) } else { 0f32 };
+        (0..len)
+            .map(|_| if rng.gen::<f32>() < self.probability { 0f32 } else { scale })
+            .collect()
+    }
+
+    /// Multiplies `values` with the mask of the last forward pass.
+    fn apply_mask(&self, values: &[f32]) -> Vec<f32> {
+        match *self.mask.borrow() {
+            Some(ref mask) => values.iter().zip(mask).map(|(value, factor)| value * factor).collect(),
+            None => values.to_vec(),
+        }
+    }
+}
+
+impl<B: IBackend> ILayer<B> for Dropout {
+    impl_ilayer_common!();
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let input_desc = input_data[0].read().unwrap().desc().clone();
+        input_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+        output_data[0].write().unwrap().resize(&input_desc).unwrap();
+        output_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+        // a mask of the old shape must not be used
+        *self.mask.borrow_mut() = None;
+    }
+
+    fn set_train(&mut self, train: bool) {
+        self.train = train;
+    }
+
+    fn set_recomputing(&mut self, recomputing: bool) {
+        self.recomputing = recomputing;
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for Dropout {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let output = {
+            let input = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            if !self.recomputing {
+                *self.mask.borrow_mut() = if self.train { Some(self.draw_mask(input.len())) } else { None };
+            }
+            self.apply_mask(input)
+        };
+        write_to_memory(output_data[0].write_only(native.device()).unwrap(), &output);
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for Dropout {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let gradient = {
+            let output_gradient = output_gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+            self.apply_mask(output_gradient)
+        };
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(), &gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for Dropout {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Specifies configuration parameters for a Dropout Layer.
+pub struct DropoutConfig {
+    /// The probability that a value is dropped in training mode.
+    ///
+    /// Default: `0.5`
+    pub probability: f32,
+}
+
+impl Default for DropoutConfig {
+    fn default() -> DropoutConfig {
+        DropoutConfig { probability: 0.5 }
+    }
+}
+
+impl DropoutConfig {
+    /// Checks that the probability is in `[0, 1]`.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.probability >= 0f32 && self.probability <= 1f32) {
+            return Err(format!("probability must be in [0, 1], but is {}", self.probability));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CapnpWrite<'a> for DropoutConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the DropoutConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_probability(self.probability);
+    }
+}
+
+impl<'a> CapnpRead<'a> for DropoutConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        DropoutConfig { probability: reader.get_probability() }
+    }
+}
+
+impl Into<LayerType> for DropoutConfig {
+    fn into(self) -> LayerType {
+        LayerType::Dropout(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use util::{native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+
+    fn network(probability: f32) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("dropout", DropoutConfig { probability: probability }));
+        Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap()
+    }
+
+    #[test]
+    fn probability_zero_is_identity_and_one_drops_everything() {
+        let values = [1f32, -2.0, 3.0, 0.5, -0.25, 4.0];
+        let mut keep_all = network(0f32);
+        assert_eq!(values.to_vec(), read(&keep_all.forward(&[tensor(&[2, 3], &values)])[0]));
+        assert_eq!(values.to_vec(), read(&keep_all.backward(&[tensor(&[2, 3], &values)])[0]));
+
+        let mut drop_all = network(1f32);
+        assert_eq!(vec![0f32; 6], read(&drop_all.forward(&[tensor(&[2, 3], &values)])[0]));
+        assert_eq!(vec![0f32; 6], read(&drop_all.backward(&[tensor(&[2, 3], &values)])[0]));
+    }
+
+    #[test]
+    fn backward_reuses_the_mask_and_inference_passes_through() {
+        let mut network = network(0.5);
+        let output = read(&network.forward(&[tensor(&[2, 3], &[1f32; 6])])[0]);
+        assert!(output.iter().all(|value| *value == 0f32 || *value == 2f32), "{:?}", output);
+        let gradient = read(&network.backward(&[tensor(&[2, 3], &[3f32; 6])])[0]);
+        assert_eq!(output.iter().map(|value| 3f32 * value).collect::<Vec<_>>(), gradient);
+
+        network.set_train(false);
+        let values = [1f32, -2.0, 3.0, 0.5, -0.25, 4.0];
+        assert_eq!(values.to_vec(), read(&network.forward(&[tensor(&[2, 3], &values)])[0]));
+        assert_eq!(values.to_vec(), read(&network.backward(&[tensor(&[2, 3], &values)])[0]));
+    }
+
+    #[test]
+    fn recomputed_forward_reuses_the_mask() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        let mut dropout = LayerConfig::new("dropout", DropoutConfig { probability: 0.5 });
+        dropout.recompute_in_backward = true;
+        cfg.add_layer(dropout);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let identity = [1f32, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        let weight = network.learnable_weights_data()[0].clone();
+        write_to_memory(weight.write().unwrap().write_only(native_backend().device()).unwrap(),
+                        &identity);
+
+        // the backward pass recomputes the output of the dropout, which has to use the mask
+        // of the forward pass
+        for _ in 0..10 {
+            let output = read(&network.forward(&[tensor(&[2, 3], &[1f32; 6])])[0]);
+            let gradient = read(&network.backward(&[tensor(&[2, 3], &[3f32; 6])])[0]);
+            assert_eq!(output.iter().map(|value| 3f32 * value).collect::<Vec<_>>(), gradient);
+        }
+    }
+}
@@ -21,7 +21,7 @@
 
 use capnp_util::*;
 use co::backend::IBackend;
-use co::tensor::SharedTensor;
+use co::tensor::{ITensorDesc, SharedTensor};
 use coblas::transpose::Transpose;
 use layer::*;
 use juice_capnp::linear_config as capnp_config;
@@ -102,6 +102,11 @@ impl<B: IBackend + LayerOps<f32>> ILayer<B> for Linear {
             weight.write().unwrap().resize(&weight_shape).unwrap();
         }
     }
+
+    /// Every output value is the dot product of a weight row with a sample of the input.
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        (output_shapes[0].size() * Self::calculate_input_size(&input_shapes[0])) as u64
+    }
 }
 
 impl<B: IBackend + LayerOps<f32>> ComputeOutput<f32, B> for Linear {
@@ -158,13 +163,13 @@ impl<B: IBackend + LayerOps<f32>> ComputeParametersGradient<f32, B> for Linear {
                                    output_gradients: &[&SharedTensor<f32>],
                                    input_data: &[&SharedTensor<f32>],
                                    parameters_gradients: &mut [&mut SharedTensor<f32>]) {
-        // gradient w.r.t. weights
+        // gradient w.r.t. weights, accumulated as the weights might be shared with other layers
         backend.gemm(&self.one,
                   Transpose::Trans,
                   output_gradients[0],
                   Transpose::NoTrans,
                   input_data[0],
-                  &self.zero,
+                  &self.one,
                   parameters_gradients[0])
             .unwrap();
 
@@ -188,7 +193,7 @@ impl ::std::default::Default for Linear {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(missing_copy_implementations)]
 /// Specifies configuration parameters for a Linear Layer.
 pub struct LinearConfig {
@@ -215,6 +220,16 @@ impl<'a> CapnpRead<'a> for LinearConfig {
     }
 }
 
+impl LinearConfig {
+    /// Checks that the output size is positive.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.output_size == 0 {
+            return Err("output_size must be positive, but is 0".to_owned());
+        }
+        Ok(())
+    }
+}
+
 impl Into<LayerType> for LinearConfig {
     fn into(self) -> LayerType {
         LayerType::Linear(self)
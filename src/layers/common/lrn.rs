@@ -0,0 +1,301 @@
+//! Applies local response normalization across channels to the input.
+//!
+//! Every value is divided by a power of the sum of the squares of the values at the same
+//! position in the `local_size` adjacent channels, which normalizes the responses of
+//! neighbouring feature maps, as in AlexNet:
+//!
+//! `y = x / scale^beta` with `scale = k + alpha / local_size * Σ x'^2`
+//!
+//! The window of a channel `c` starts at channel `c - (local_size - 1) / 2` and is clipped
+//! at the first and last channel.
+//!
+//! ## Input Data
+//!
+//! The layer expects the input to have the channels in its second dimension, e.g. in 4D
+//! NCHW format.
+//!
+//! ## Saved Scale
+//!
+//! The backward pass needs the `scale` of the forward pass. The layer owns the scale of its
+//! last forward pass and the backward pass consumes it, so every backward pass has to be
+//! preceded by a forward pass. Without one the [operation][1] returns an error instead of
+//! computing a gradient from a stale or missing scale.
+//! [1]: ../../../util/trait.LocalResponseNormalization.html#tymethod.lrn_backward
+//!
+//! ## Shared Configs
+//!
+//! LRN layers with the same hyperparameters on the same backend share the config of the
+//! backend, see [op_cache][2].
+//! [2]: ../../../op_cache/index.html
+
+use capnp_util::*;
+use co::{IBackend, SharedTensor};
+use juice_capnp::lrn_config as capnp_config;
+use layer::*;
+use op_cache::{self, OpKey};
+use std::cell::RefCell;
+use std::rc::Rc;
+use util::{ArcLock, LocalResponseNormalization, LrnScale};
+
+#[derive(Debug)]
+/// [LRN](./index.html) Layer
+pub struct LRN<B: LocalResponseNormalization<f32>> {
+    local_size: usize,
+    alpha: f32,
+    beta: f32,
+    k: f32,
+
+    config: Option<Rc<B::CLRN>>,
+    /// The scale of the last forward pass, which the backward pass consumes.
+    scale: RefCell<LrnScale>,
+}
+
+impl<B: LocalResponseNormalization<f32>> LRN<B> {
+    /// Create a LRN layer from a LRNConfig.
+    pub fn from_config(config: &LRNConfig) -> LRN<B> {
+        LRN {
+            local_size: config.local_size,
+            alpha: config.alpha,
+            beta: config.beta,
+            k: config.k,
+
+            config: None,
+            scale: RefCell::new(LrnScale::default()),
+        }
+    }
+}
+
+impl<B: IBackend + LocalResponseNormalization<f32> + 'static> ILayer<B> for LRN<B> {
+    impl_ilayer_common!();
+
+    fn reshape(&mut self,
+               backend: Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let input_desc = input_data[0].read().unwrap().desc().clone();
+        input_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+        output_data[0].write().unwrap().resize(&input_desc).unwrap();
+        output_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+
+        // the hyperparameters are part of the key by their bits
+        let hyperparameters = [self.local_size,
+                               self.alpha.to_bits() as usize,
+                               self.beta.to_bits() as usize,
+                               self.k.to_bits() as usize];
+        let key = OpKey::new::<f32>("lrn", &[&hyperparameters[..]]);
+        let config = op_cache::shared_config(&backend,
+                                             key,
+                                             || backend.new_lrn_config(self.local_size, self.alpha, self.beta, self.k))
+            .unwrap();
+        self.config = Some(config);
+        // a saved scale of the old shape must not be used
+        self.scale.borrow_mut().clear();
+    }
+}
+
+impl<B: IBackend + LocalResponseNormalization<f32>> ComputeOutput<f32, B> for LRN<B> {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let config = self.config.as_ref().unwrap();
+        backend.lrn_forward(config, &mut self.scale.borrow_mut(), input_data[0], output_data[0])
+            .unwrap();
+    }
+}
+
+impl<B: IBackend + LocalResponseNormalization<f32>> ComputeInputGradient<f32, B> for LRN<B> {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let config = self.config.as_ref().unwrap();
+        backend.lrn_backward(config,
+                          &mut self.scale.borrow_mut(),
+                          input_data[0],
+                          output_data[0],
+                          output_gradients[0],
+                          input_gradients[0])
+            .unwrap();
+    }
+}
+
+impl<B: IBackend + LocalResponseNormalization<f32>> ComputeParametersGradient<f32, B> for LRN<B> {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_copy_implementations)]
+/// Specifies configuration parameters for a LRN Layer.
+pub struct LRNConfig {
+    /// The number of adjacent channels that are summed over, including the channel itself.
+    ///
+    /// Default: `5`
+    pub local_size: usize,
+    /// The factor of the sum of the squares.
+    ///
+    /// Default: `1e-4`
+    pub alpha: f32,
+    /// The exponent of the scale.
+    ///
+    /// Default: `0.75`
+    pub beta: f32,
+    /// The constant added to the scale.
+    ///
+    /// Default: `2.0`
+    pub k: f32,
+}
+
+impl Default for LRNConfig {
+    fn default() -> LRNConfig {
+        LRNConfig {
+            local_size: 5,
+            alpha: 1e-4,
+            beta: 0.75,
+            k: 2f32,
+        }
+    }
+}
+
+impl LRNConfig {
+    /// Checks that the window is centered and that the scale is positive, as it is raised
+    /// to the power of `-beta`.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.local_size % 2 == 0 {
+            return Err(format!("local_size must be an odd number, but is {}", self.local_size));
+        }
+        if !self.alpha.is_finite() || self.alpha < 0f32 {
+            return Err(format!("alpha must be a non-negative number, but is {}", self.alpha));
+        }
+        if !self.beta.is_finite() {
+            return Err(format!("beta must be a finite number, but is {}", self.beta));
+        }
+        if !self.k.is_finite() || self.k <= 0f32 {
+            return Err(format!("k must be a positive number, but is {}", self.k));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CapnpWrite<'a> for LRNConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the LRNConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_local_size(self.local_size as u64);
+        builder.set_alpha(self.alpha);
+        builder.set_beta(self.beta);
+        builder.set_k(self.k);
+    }
+}
+
+impl<'a> CapnpRead<'a> for LRNConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        LRNConfig {
+            local_size: reader.get_local_size() as usize,
+            alpha: reader.get_alpha(),
+            beta: reader.get_beta(),
+            k: reader.get_k(),
+        }
+    }
+}
+
+impl Into<LayerType> for LRNConfig {
+    fn into(self) -> LayerType {
+        LayerType::LRN(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use super::LRNConfig;
+    use util::{LocalResponseNormalization, LrnScale, native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+
+    fn conv(num_output: usize) -> ConvolutionConfig {
+        ConvolutionConfig {
+            num_output: num_output,
+            filter_shape: vec![1],
+            stride: vec![1],
+            padding: vec![0],
+            bias: false,
+        }
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences_between_convolutions() {
+        let shape = [2, 2, 2, 2];
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &shape);
+        cfg.add_layer(LayerConfig::new("conv1", conv(4)));
+        cfg.add_layer(LayerConfig::new("lrn",
+                                       LRNConfig {
+                                           local_size: 3,
+                                           alpha: 0.5,
+                                           ..LRNConfig::default()
+                                       }));
+        cfg.add_layer(LayerConfig::new("conv2", conv(2)));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        for (i, weight) in network.learnable_weights_data().iter().enumerate() {
+            let size = weight.read().unwrap().desc().size();
+            let values = (0..size).map(|j| ((i * 7 + j) % 5) as f32 * 0.4 - 0.8).collect::<Vec<_>>();
+            write_to_memory(weight.write().unwrap().write_only(native_backend().device()).unwrap(),
+                            &values);
+        }
+
+        let input = (0..16).map(|i| ((i * 3) % 7) as f32 * 0.5 - 1.5).collect::<Vec<_>>();
+        let output_gradient = (0..16).map(|i| ((i * 5) % 4) as f32 * 0.5 - 0.75).collect::<Vec<_>>();
+        // the loss whose gradient w.r.t. the output is output_gradient
+        let loss = |network: &mut Layer<Backend<Native>>, input: &[f32]| {
+            let output = read(&network.forward(&[tensor(&shape, input)])[0]);
+            output.iter().zip(&output_gradient).fold(0f32, |sum, (output, gradient)| sum + output * gradient)
+        };
+
+        network.forward(&[tensor(&shape, &input)]);
+        let gradient = read(&network.backward(&[tensor(&shape, &output_gradient)])[0]);
+
+        let epsilon = 1e-2f32;
+        for i in 0..input.len() {
+            let mut shifted = input.clone();
+            shifted[i] = input[i] + epsilon;
+            let upper = loss(&mut network, &shifted);
+            shifted[i] = input[i] - epsilon;
+            let lower = loss(&mut network, &shifted);
+            let numerical = (upper - lower) / (2f32 * epsilon);
+            assert!((numerical - gradient[i]).abs() < 1e-2 * (1f32 + numerical.abs()),
+                    "{}: {} != {}",
+                    i,
+                    numerical,
+                    gradient[i]);
+        }
+    }
+
+    #[test]
+    fn backward_consumes_the_saved_scale() {
+        let backend = native_backend();
+        let config = backend.new_lrn_config(5, 1e-4, 0.75, 2f32).unwrap();
+        let mut scale = LrnScale::default();
+        let input = tensor(&[1, 3, 1, 1], &[1f32, -2f32, 3f32]);
+        let input = input.read().unwrap();
+        let mut output = SharedTensor::new(&[1, 3, 1, 1]);
+        let mut gradient = SharedTensor::new(&[1, 3, 1, 1]);
+        assert!(backend.lrn_backward(&config, &mut scale, &input, &input, &input, &mut gradient).is_err());
+
+        backend.lrn_forward(&config, &mut scale, &input, &mut output).unwrap();
+        assert!(backend.lrn_backward(&config, &mut scale, &input, &output, &input, &mut gradient).is_ok());
+        assert!(backend.lrn_backward(&config, &mut scale, &input, &output, &input, &mut gradient).is_err());
+    }
+}
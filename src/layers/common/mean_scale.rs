@@ -0,0 +1,351 @@
+//! Preprocesses the input per channel by subtracting a mean and multiplying with a scale.
+//!
+//! Computes `(x - mean) * scale` with one `mean` and `scale` per channel, so the
+//! preprocessing of the data, e.g. the normalization of images with the statistics of
+//! the training set, is part of the network and applied identically at deployment.
+//!
+//! The mean and the scale are the two weights of the layer. They are not
+//! [learnable][learnable], so the Solver doesn't update them, but they are saved and
+//! loaded with the network like the learnable weights. They start out as `0` and `1` and
+//! are set by writing to the [non-learnable weights][weights] of the layer.
+//!
+//! [learnable]: ../../../layer/trait.ILayer.html#method.learnable_weight
+//! [weights]: ../../../layer/struct.Layer.html#method.non_learnable_weights_data
+//!
+//! The mean is subtracted with a gemm that broadcasts it over the channels, and the scale
+//! is applied with a 1x1 convolution whose filter has the scale on its diagonal, so both
+//! passes run on the device. The filter is built from the outer product of the scale with
+//! ones, masked to its diagonal, so it takes `channels * channels` values.
+//!
+//! ## Input Data
+//!
+//! The layer expects the input to have the channels in its second dimension, e.g. in 4D
+//! NCHW format.
+
+use co::{IBackend, ITensorDesc, SharedTensor};
+use coblas::plugin::Gemm;
+use coblas::transpose::Transpose;
+use conn;
+use conn::ConvolutionConfig as connConvolutionConfig;
+use layer::*;
+use op_cache::{self, OpKey};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use util::{ArcLock, LayerOps, diagonal_matrix, native_scalar, ones};
+use weight::FillerType;
+
+#[derive(Debug)]
+/// [MeanScale](./index.html) Layer
+pub struct MeanScale<B: conn::Convolution<f32>> {
+    /// The input without the mean, in the NCHW shape of the convolution.
+    centered: RefCell<SharedTensor<f32>>,
+    workspace: Option<ArcLock<SharedTensor<u8>>>,
+    convolution_config: Option<Rc<B::CC>>,
+}
+
+impl<B: conn::Convolution<f32>> MeanScale<B> {
+    /// Returns the number of channels and the number of values per channel of a sample.
+    fn channel_dims(shape: &[usize]) -> (usize, usize) {
+        assert!(shape.len() >= 2,
+                "A MeanScale layer expects the channels in the second dimension of its input, but its shape is {:?}",
+                shape);
+        (shape[1], shape[2..].iter().fold(1, |size, dim| size * dim))
+    }
+
+    /// Returns the NCHW shape in which the convolution sees an input of `shape`.
+    fn convolution_shape(shape: &[usize]) -> Vec<usize> {
+        let (channels, inner) = Self::channel_dims(shape);
+        vec![shape[0], channels, inner, 1]
+    }
+}
+
+impl<B: conn::Convolution<f32>> Default for MeanScale<B> {
+    fn default() -> MeanScale<B> {
+        MeanScale {
+            centered: RefCell::new(SharedTensor::new(&[1])),
+            workspace: None,
+            convolution_config: None,
+        }
+    }
+}
+
+/// Returns the 1x1 convolution filter of shape `[channels, channels, 1, 1]` that multiplies
+/// every channel with its `scale`.
+fn scale_filter<B: IBackend + Gemm<f32> + conn::Relu<f32>>(backend: &B,
+                                                           scale: &SharedTensor<f32>)
+                                                           -> Result<SharedTensor<f32>, ::co::error::Error> {
+    let channels = scale.desc().size();
+    let mut filter = try!(diagonal_matrix(backend, scale));
+    try!(filter.reshape(&[channels, channels, 1, 1]));
+    Ok(filter)
+}
+
+/// Subtracts the `mean` of each channel from every value of the channel in the NCHW `data`.
+fn subtract_mean<B: IBackend + Gemm<f32>>(backend: &B,
+                                          mean: &SharedTensor<f32>,
+                                          data: &mut SharedTensor<f32>)
+                                          -> Result<(), ::co::error::Error> {
+    let batch_size = data.desc()[0];
+    let channels = mean.desc().size();
+    let inner = data.desc().size() / batch_size / channels;
+    let one = native_scalar(1f32);
+    // the mean of every value of a sample, broadcast over the values of each channel
+    let mut sample_mean = SharedTensor::new(&[channels, inner]);
+    try!(backend.gemm(&one,
+                      Transpose::NoTrans,
+                      mean,
+                      Transpose::NoTrans,
+                      &ones(&[1, inner]),
+                      &native_scalar(0f32),
+                      &mut sample_mean));
+    try!(sample_mean.reshape(&[1, channels * inner]));
+    // broadcast over the samples and subtracted from the data
+    backend.gemm(&native_scalar(-1f32),
+                 Transpose::NoTrans,
+                 &ones(&[batch_size, 1]),
+                 Transpose::NoTrans,
+                 &sample_mean,
+                 &one,
+                 data)
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for MeanScale<B> {
+    impl_ilayer_common!();
+
+    fn auto_weight_blobs(&self) -> bool {
+        true
+    }
+
+    fn num_weight_blobs(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn learnable_weight(&self, weight_id: usize) -> bool {
+        false
+    }
+
+    fn reshape(&mut self,
+               backend: Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let input_desc = input_data[0].read().unwrap().desc().clone();
+        input_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+        output_data[0].write().unwrap().resize(&input_desc).unwrap();
+        output_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+
+        // statistics of the current number of channels are kept
+        let channels = Self::channel_dims(&input_desc).0;
+        for (weight_id, initial) in [0f32, 1f32].iter().enumerate() {
+            if weights_data[weight_id].read().unwrap().desc() == &vec![channels] {
+                continue;
+            }
+            let mut weight = weights_data[weight_id].write().unwrap();
+            weight.resize(&[channels]).unwrap();
+            FillerType::fill_constant(&mut weight, *initial);
+            weights_gradient[weight_id].write().unwrap().resize(&[channels]).unwrap();
+        }
+
+        // shares the config with the 1x1 convolution layers of the same geometry
+        let shape = Self::convolution_shape(&input_desc);
+        let centered = SharedTensor::new(&shape);
+        let output = SharedTensor::<f32>::new(&shape);
+        let mut filter = SharedTensor::<f32>::new(&[channels, channels, 1, 1]);
+        let key = OpKey::new::<f32>("convolution",
+                                    &[&shape[..], &shape[..], &filter.desc()[..], &[1, 1], &[0, 0]]);
+        let config = op_cache::shared_config(&backend, key, || {
+                backend.new_convolution_config(&centered,
+                                               &output,
+                                               &mut filter,
+                                               conn::ConvForwardAlgo::Auto,
+                                               conn::ConvBackwardFilterAlgo::Auto,
+                                               conn::ConvBackwardDataAlgo::Auto,
+                                               &[1, 1],
+                                               &[0, 0])
+            })
+            .unwrap();
+        self.convolution_config = Some(config);
+        *self.centered.borrow_mut() = centered;
+    }
+
+    fn resize_shared_workspace(&mut self,
+                               backend: Rc<B>,
+                               workspace: Option<ArcLock<SharedTensor<u8>>>)
+                               -> Option<ArcLock<SharedTensor<u8>>> {
+        let required_size = self.workspace_size();
+        let new_workspace = match workspace {
+            Some(ref workspace) if workspace.read().unwrap().capacity() >= required_size => workspace.clone(),
+            _ => Arc::new(RwLock::new(SharedTensor::<u8>::new(&[required_size]))),
+        };
+        self.workspace = Some(new_workspace.clone());
+        Some(new_workspace)
+    }
+
+    fn workspace_size(&self) -> usize {
+        self.convolution_config.as_ref().map_or(0, |config| config.workspace_size())
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeOutput<f32, B> for MeanScale<B> {
+    fn compute_output(&self,
+                      backend: &B,
+                      weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let shape = input_data[0].desc().clone();
+        let mut centered = self.centered.borrow_mut();
+        backend.weighted_sum(&[1f32], &[input_data[0]], &mut centered).unwrap();
+        centered.reshape(&Self::convolution_shape(&shape)).unwrap();
+        subtract_mean(backend, weights[0], &mut centered).unwrap();
+
+        let filter = scale_filter(backend, weights[1]).unwrap();
+        let mut workspace = self.workspace.as_ref().unwrap().write().unwrap();
+        output_data[0].reshape(&Self::convolution_shape(&shape)).unwrap();
+        backend.convolution(&filter,
+                         &centered,
+                         output_data[0],
+                         &mut workspace,
+                         self.convolution_config.as_ref().unwrap())
+            .unwrap();
+        output_data[0].reshape(&shape).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeInputGradient<f32, B> for MeanScale<B> {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let shape = input_data[0].desc().clone();
+        let convolution_shape = Self::convolution_shape(&shape);
+        // the gradient is copied into the shape of the convolution unless it has it already
+        let mut reshaped = None;
+        if output_gradients[0].desc() != &convolution_shape {
+            let mut gradient = self.centered.borrow_mut();
+            backend.weighted_sum(&[1f32], &[output_gradients[0]], &mut gradient).unwrap();
+            gradient.reshape(&convolution_shape).unwrap();
+            reshaped = Some(gradient);
+        }
+
+        let filter = scale_filter(backend, weights_data[1]).unwrap();
+        let mut workspace = self.workspace.as_ref().unwrap().write().unwrap();
+        input_gradients[0].reshape(&convolution_shape).unwrap();
+        backend.convolution_grad_data(&filter,
+                                   reshaped.as_ref().map_or(output_gradients[0], |gradient| &**gradient),
+                                   input_gradients[0],
+                                   &mut workspace,
+                                   self.convolution_config.as_ref().unwrap())
+            .unwrap();
+        input_gradients[0].reshape(&shape).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeParametersGradient<f32, B> for MeanScale<B> {}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use solver::SolverConfig;
+    use solver::Solver;
+    use std::rc::Rc;
+    use util::{native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+
+    /// A MeanScale layer of two channels in front of a Linear layer.
+    fn network_config() -> LayerConfig {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 2]);
+        cfg.add_layer(LayerConfig::new("preprocess", LayerType::MeanScale));
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        LayerConfig::new("network", cfg)
+    }
+
+    fn set_statistics(network: &Layer<Backend<Native>>) {
+        let native = native_backend();
+        let statistics = network.non_learnable_weights_data();
+        write_to_memory(statistics[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, -2f32]);
+        write_to_memory(statistics[1].write().unwrap().write_only(native.device()).unwrap(),
+                        &[0.5f32, 2f32]);
+    }
+
+    #[test]
+    fn statistics_round_trip_through_checkpoints() {
+        let mut network = Layer::from_config(Rc::new(native_backend()), &network_config()).unwrap();
+        assert_eq!(vec!["preprocess-0", "preprocess-1"], network.non_learnable_weights_names());
+        assert_eq!(vec!["linear-0"], network.learnable_weights_names());
+        set_statistics(&network);
+        let input = tensor(&[2, 2], &[3f32, 0f32, -1f32, 1f32]);
+        let output = read(&network.forward(&[input.clone()])[0]);
+        let preprocessed = read(&network.blob_data("SEQUENTIAL_0").unwrap());
+        assert_eq!(vec![1f32, 4f32, -1f32, 6f32], preprocessed);
+
+        let path = ::util::testing::temp_path("mean_scale_checkpoint");
+        network.save(&path).unwrap();
+        let mut loaded = Layer::<Backend<Native>>::load(Rc::new(native_backend()), &path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        let statistics = loaded.non_learnable_weights_data();
+        assert_eq!(vec![1f32, -2f32], read(&statistics[0]));
+        assert_eq!(vec![0.5f32, 2f32], read(&statistics[1]));
+        assert_eq!(output, read(&loaded.forward(&[input])[0]));
+    }
+
+    #[test]
+    fn wrapped_statistics_are_not_learnable_and_round_trip() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 2]);
+        cfg.add_layer(LayerConfig::new("block", StochasticDepthConfig::new(LayerType::MeanScale, 1f32)));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        assert_eq!(vec!["block-0", "block-1"], network.non_learnable_weights_names());
+        assert!(network.learnable_weights_names().is_empty());
+        set_statistics(&network);
+
+        let path = ::util::testing::temp_path("wrapped_mean_scale_checkpoint");
+        network.save(&path).unwrap();
+        let loaded = Layer::<Backend<Native>>::load(Rc::new(native_backend()), &path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        let statistics = loaded.non_learnable_weights_data();
+        assert_eq!(vec![1f32, -2f32], read(&statistics[0]));
+        assert_eq!(vec![0.5f32, 2f32], read(&statistics[1]));
+    }
+
+    #[test]
+    fn solver_never_updates_statistics() {
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[2, 3]);
+        obj_cfg.add_input("label", &[2, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 3 }));
+        let cfg = SolverConfig {
+            network: network_config(),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 0.5,
+            weight_decay: Some(0.1),
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        set_statistics(solver.network());
+        let linear_weight = read(&solver.network().learnable_weights_data()[0]);
+
+        for _ in 0..3 {
+            solver.train_minibatch(tensor(&[2, 2], &[3f32, 0f32, -1f32, 1f32]), tensor(&[2, 1], &[0f32, 2f32]));
+        }
+
+        let statistics = solver.network().non_learnable_weights_data();
+        assert_eq!(vec![1f32, -2f32], read(&statistics[0]));
+        assert_eq!(vec![0.5f32, 2f32], read(&statistics[1]));
+        assert!(linear_weight != read(&solver.network().learnable_weights_data()[0]));
+    }
+}
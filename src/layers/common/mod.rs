@@ -10,17 +10,47 @@ macro_rules! impl_ilayer_common {
     )
 }
 
+pub use self::batch_norm::{BatchNorm, BatchNormConfig};
 pub use self::convolution::{Convolution, ConvolutionConfig};
+pub use self::dropout::{Dropout, DropoutConfig};
 pub use self::linear::{Linear, LinearConfig};
 pub use self::log_softmax::LogSoftmax;
+pub use self::lrn::{LRN, LRNConfig};
+pub use self::mean_scale::MeanScale;
 pub use self::pooling::{Pooling, PoolingConfig, PoolingMode};
 pub use self::softmax::Softmax;
+pub use self::spectral_norm::{SpectralNorm, SpectralNormConfig};
+pub use self::stochastic_depth::{StochasticDepth, StochasticDepthConfig};
 
+pub mod batch_norm;
 pub mod convolution;
+pub mod dropout;
 pub mod linear;
 pub mod log_softmax;
+pub mod lrn;
+pub mod mean_scale;
 pub mod pooling;
 pub mod softmax;
+pub mod spectral_norm;
+pub mod stochastic_depth;
+
+/// Checks the filter shape, stride and padding of the config of a Convolution or Pooling layer.
+///
+/// Only one value per field, which is used for all spatial dimensions, is supported yet.
+fn validate_filter_config(filter_shape: &[usize], stride: &[usize], padding: &[usize]) -> Result<(), String> {
+    for &(field, values) in &[("filter_shape", filter_shape), ("stride", stride), ("padding", padding)] {
+        if values.len() != 1 {
+            return Err(format!("{} must contain exactly one value, but is {:?}", field, values));
+        }
+    }
+    if filter_shape[0] == 0 {
+        return Err("filter_shape must be positive, but is [0]".to_owned());
+    }
+    if stride[0] == 0 {
+        return Err("stride must be positive, but is [0]".to_owned());
+    }
+    Ok(())
+}
 
 /// Provides common utilities for Layers that utilize a filter with stride and padding.
 ///
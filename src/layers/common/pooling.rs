@@ -10,18 +10,34 @@
 //!
 //! The layer expects the input to be in either 4D NCHW (2 spatial dimensions)
 //! or 5D NCDHW (3 spatial dimensions) format.
+//!
+//! ## Padding
+//!
+//! By default the backend pads the input: padded values are never selected by max
+//! pooling, as if they were negative infinity, see [PaddedMaxPooling][padded_max], and count
+//! as zeros for average pooling.
+//!
+//! With a [padding_value][padding_value] the padded border is filled with that value
+//! instead. No backend supports a custom padding value, so the layer pads the input on the
+//! host itself and runs the pooling of the backend without padding.
+//!
+//! [padded_max]: ../../../util/trait.PaddedMaxPooling.html
+//! [padding_value]: ./struct.PoolingConfig.html#structfield.padding_value
 
-use super::FilterLayer;
+use super::{FilterLayer, validate_filter_config};
 use capnp_util::*;
-use co::{IBackend, SharedTensor};
+use co::{IBackend, ITensorDesc, SharedTensor};
 use conn;
 use layer::*;
 use juice_capnp::PoolingMode as CapnpPoolingMode;
 use juice_capnp::pooling_config as capnp_config;
+use juice_capnp::pooling_config::padding_value as capnp_padding_value;
+use op_cache::{self, OpKey};
+use std::cell::RefCell;
 use std::rc::Rc;
-use util::{ArcLock, cast_vec_usize_to_i32};
+use util::{ArcLock, PaddedMaxPooling, PoolingWindow, cast_vec_usize_to_i32, native_backend, write_to_memory};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 /// [Pooling](./index.html) Layer
 pub struct Pooling<T, B: conn::Pooling<T>> {
     mode: PoolingMode,
@@ -29,8 +45,14 @@ pub struct Pooling<T, B: conn::Pooling<T>> {
     filter_shape: Vec<usize>,
     stride: Vec<usize>,
     padding: Vec<usize>,
+    padding_value: Option<f32>,
 
     pooling_configs: Vec<Rc<B::CPOOL>>,
+    /// The window of the pooling of the backend, without the padding if the layer pads the
+    /// input itself.
+    window: PoolingWindow,
+    padded_input: RefCell<SharedTensor<f32>>,
+    padded_gradient: RefCell<SharedTensor<f32>>,
 }
 
 impl<T, B: conn::Pooling<T>> Pooling<T, B> {
@@ -42,12 +64,54 @@ impl<T, B: conn::Pooling<T>> Pooling<T, B> {
             filter_shape: config.filter_shape.clone(),
             stride: config.stride.clone(),
             padding: config.padding.clone(),
+            padding_value: config.padding_value,
 
             pooling_configs: vec![],
+            window: PoolingWindow::default(),
+            padded_input: RefCell::new(SharedTensor::new(&[1])),
+            padded_gradient: RefCell::new(SharedTensor::new(&[1])),
+        }
+    }
+
+    /// Returns the padding of the spatial dimensions of the input of shape `input_shape` if
+    /// the layer pads the input itself, which it only does for a padding value.
+    fn host_padding(&self, input_shape: &[usize]) -> Option<Vec<usize>> {
+        let padding = self.padding_dims(self.num_spatial_dims(input_shape));
+        if self.padding_value.is_none() || padding.iter().all(|&pad| pad == 0) {
+            None
+        } else {
+            Some(padding)
+        }
+    }
+
+    /// Writes `input` into the padded input, surrounded by the padding value.
+    fn pad_input(&self, input: &SharedTensor<f32>, padding: &[usize], value: f32) {
+        let native = native_backend();
+        let mut padded_input = self.padded_input.borrow_mut();
+        let mut padded = vec![value; padded_input.desc().size()];
+        let values = input.read(native.device()).unwrap().as_slice::<f32>();
+        for (index, value) in values.iter().enumerate() {
+            padded[padded_index(input.desc(), padding, index)] = *value;
         }
+        write_to_memory(padded_input.write_only(native.device()).unwrap(), &padded);
     }
 }
 
+/// Returns the index in the padded tensor of the value at `index` in the tensor of shape
+/// `shape`, whose spatial dimensions are padded by `padding` on both sides.
+fn padded_index(shape: &[usize], padding: &[usize], index: usize) -> usize {
+    let mut index = index;
+    let mut padded_index = 0;
+    let mut padded_stride = 1;
+    for dim in (0..shape.len()).rev() {
+        let pad = if dim >= 2 { padding[dim - 2] } else { 0 };
+        padded_index += (index % shape[dim] + pad) * padded_stride;
+        padded_stride *= shape[dim] + 2 * pad;
+        index /= shape[dim];
+    }
+    padded_index
+}
+
 impl<T, B: conn::Pooling<T>> FilterLayer for Pooling<T, B> {
     /// Calculates the number of spatial dimensions for the pooling operation.
     fn num_spatial_dims(&self, input_shape: &[usize]) -> usize {
@@ -87,7 +151,7 @@ impl<T, B: conn::Pooling<T>> FilterLayer for Pooling<T, B> {
     }
 }
 
-impl<B: IBackend + conn::Pooling<f32>> ILayer<B> for Pooling<f32, B> {
+impl<B: IBackend + PaddedMaxPooling<f32> + 'static> ILayer<B> for Pooling<f32, B> {
     impl_ilayer_common!();
 
     fn reshape(&mut self,
@@ -106,30 +170,71 @@ impl<B: IBackend + conn::Pooling<f32>> ILayer<B> for Pooling<f32, B> {
             output_gradient[0].write().unwrap().resize(&output_shape).unwrap();
 
             let num_spatial_dims = self.num_spatial_dims(inp.desc());
-            let filter = cast_vec_usize_to_i32(self.spatial_filter_dims(num_spatial_dims));
-            let stride = cast_vec_usize_to_i32(self.stride_dims(num_spatial_dims));
-            let padding = cast_vec_usize_to_i32(self.padding_dims(num_spatial_dims));
+            let filter_dims = self.spatial_filter_dims(num_spatial_dims);
+            let stride_dims = self.stride_dims(num_spatial_dims);
+            let filter = cast_vec_usize_to_i32(filter_dims.clone());
+            let stride = cast_vec_usize_to_i32(stride_dims.clone());
+            // a padding value is padded by the layer, see the module documentation
+            let padding_dims = match self.host_padding(input_shape) {
+                Some(spatial_padding) => {
+                    let mut padded_shape = input_shape.clone();
+                    for (dim, pad) in padded_shape[2..].iter_mut().zip(spatial_padding) {
+                        *dim += 2 * pad;
+                    }
+                    self.padded_input.borrow_mut().resize(&padded_shape).unwrap();
+                    self.padded_gradient.borrow_mut().resize(&padded_shape).unwrap();
+                    vec![0; num_spatial_dims]
+                }
+                None => self.padding_dims(num_spatial_dims),
+            };
+            let padding = cast_vec_usize_to_i32(padding_dims.clone());
+            self.window = PoolingWindow {
+                filter: filter_dims.clone(),
+                stride: stride_dims.clone(),
+                padding: padding_dims.clone(),
+            };
 
-            let config = backend.new_pooling_config(&filter, &stride, &padding).unwrap();
-            self.pooling_configs.push(Rc::new(config));
+            // layers with the same window share the config, see the op_cache module
+            let key = OpKey::new::<f32>("pooling", &[&filter_dims[..], &stride_dims[..], &padding_dims[..]]);
+            let config = op_cache::shared_config(&backend, key, || {
+                    backend.new_pooling_config(&filter, &stride, &padding)
+                })
+                .unwrap();
+            self.pooling_configs.push(config);
         }
     }
+
+    /// Every output value reduces one window of the input.
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        let num_spatial_dims = self.num_spatial_dims(&input_shapes[0]);
+        let window_size = self.spatial_filter_dims(num_spatial_dims).iter().fold(1, |prod, dim| prod * dim);
+        (output_shapes[0].size() * window_size) as u64
+    }
 }
 
-impl<B: IBackend + conn::Pooling<f32>> ComputeOutput<f32, B> for Pooling<f32, B> {
+impl<B: IBackend + PaddedMaxPooling<f32>> ComputeOutput<f32, B> for Pooling<f32, B> {
     fn compute_output(&self,
                       backend: &B,
                       weights: &[&SharedTensor<f32>],
                       input_data: &[&SharedTensor<f32>],
                       output_data: &mut [&mut SharedTensor<f32>]) {
         let config = &self.pooling_configs[0];
+        let padded_input;
+        let input = match self.host_padding(input_data[0].desc()) {
+            Some(padding) => {
+                self.pad_input(input_data[0], &padding, self.padding_value.unwrap());
+                padded_input = self.padded_input.borrow();
+                &*padded_input
+            }
+            None => input_data[0],
+        };
         match self.mode {
             PoolingMode::Max => {
-                backend.pooling_max(input_data[0], output_data[0], &*config)
+                backend.padded_pooling_max(input, output_data[0], &*config, &self.window)
                     .unwrap()
             }
             PoolingMode::Average => {
-                backend.pooling_avg(input_data[0], output_data[0], &*config)
+                backend.pooling_avg(input, output_data[0], &*config)
                     .unwrap()
             }
             _ => panic!("Unknown Parameter {:?} for PoolingMode", self.mode),
@@ -137,7 +242,7 @@ impl<B: IBackend + conn::Pooling<f32>> ComputeOutput<f32, B> for Pooling<f32, B>
     }
 }
 
-impl<B: IBackend + conn::Pooling<f32>> ComputeInputGradient<f32, B> for Pooling<f32, B> {
+impl<B: IBackend + PaddedMaxPooling<f32>> ComputeInputGradient<f32, B> for Pooling<f32, B> {
     fn compute_input_gradient(&self,
                               backend: &B,
                               _weights_data: &[&SharedTensor<f32>],
@@ -146,31 +251,65 @@ impl<B: IBackend + conn::Pooling<f32>> ComputeInputGradient<f32, B> for Pooling<
                               input_data: &[&SharedTensor<f32>],
                               input_gradients: &mut [&mut SharedTensor<f32>]) {
         let config = &self.pooling_configs[0];
-        match self.mode {
-            PoolingMode::Max => {
-                backend.pooling_max_grad(output_data[0],
-                                      output_gradients[0],
-                                      input_data[0],
-                                      input_gradients[0],
-                                      config)
-                    .unwrap()
+        let padding = match self.host_padding(input_data[0].desc()) {
+            Some(padding) => padding,
+            None => {
+                pooling_grad(backend,
+                             self.mode,
+                             &self.window,
+                             output_data[0],
+                             output_gradients[0],
+                             input_data[0],
+                             input_gradients[0],
+                             config);
+                return;
             }
-            PoolingMode::Average => {
-                backend.pooling_avg_grad(output_data[0],
-                                      output_gradients[0],
-                                      input_data[0],
-                                      input_gradients[0],
-                                      config)
-                    .unwrap()
-            }
-            _ => panic!("Unknown parameter {:?} for PoolingMode", self.mode),
+        };
+
+        // the padded input of the forward pass is still in place
+        let padded_input = self.padded_input.borrow();
+        let mut padded_gradient = self.padded_gradient.borrow_mut();
+        pooling_grad(backend,
+                     self.mode,
+                     &self.window,
+                     output_data[0],
+                     output_gradients[0],
+                     &padded_input,
+                     &mut padded_gradient,
+                     config);
+        // the gradient of the padding is discarded
+        let native = native_backend();
+        let padded = padded_gradient.read(native.device()).unwrap().as_slice::<f32>();
+        let gradient = (0..input_data[0].desc().size())
+            .map(|index| padded[padded_index(input_data[0].desc(), &padding, index)])
+            .collect::<Vec<_>>();
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(), &gradient);
+    }
+}
+
+fn pooling_grad<B: IBackend + PaddedMaxPooling<f32>>(backend: &B,
+                                                     mode: PoolingMode,
+                                                     window: &PoolingWindow,
+                                                     output: &SharedTensor<f32>,
+                                                     output_gradient: &SharedTensor<f32>,
+                                                     input: &SharedTensor<f32>,
+                                                     input_gradient: &mut SharedTensor<f32>,
+                                                     config: &B::CPOOL) {
+    match mode {
+        PoolingMode::Max => {
+            backend.padded_pooling_max_grad(output, output_gradient, input, input_gradient, config, window)
+                .unwrap()
+        }
+        PoolingMode::Average => {
+            backend.pooling_avg_grad(output, output_gradient, input, input_gradient, config)
+                .unwrap()
         }
     }
 }
 
-impl<B: IBackend + conn::Pooling<f32>> ComputeParametersGradient<f32, B> for Pooling<f32, B> {}
+impl<B: IBackend + PaddedMaxPooling<f32>> ComputeParametersGradient<f32, B> for Pooling<f32, B> {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Specifies configuration parameters for a Pooling Layer.
 pub struct PoolingConfig {
     /// The PoolingMode to use
@@ -181,6 +320,28 @@ pub struct PoolingConfig {
     pub stride: Vec<usize>,
     /// The padding size
     pub padding: Vec<usize>,
+    /// The value of the padded border of the input.
+    ///
+    /// If `None`, padded values are never selected by max pooling and count as zeros for
+    /// average pooling, which matches the convention of cuDNN. A padding value of `0.0`
+    /// makes max pooling clamp negative inputs at the border to zero.
+    ///
+    /// Default: `None`
+    pub padding_value: Option<f32>,
+}
+
+impl PoolingConfig {
+    /// Checks that the pooling window is valid and larger than the padding, so every
+    /// window covers at least one input value.
+    pub fn validate(&self) -> Result<(), String> {
+        try!(validate_filter_config(&self.filter_shape, &self.stride, &self.padding));
+        if self.padding[0] >= self.filter_shape[0] {
+            return Err(format!("padding must be smaller than the filter_shape {:?}, but is {:?}",
+                               self.filter_shape,
+                               self.padding));
+        }
+        Ok(())
+    }
 }
 
 impl Into<LayerType> for PoolingConfig {
@@ -213,6 +374,10 @@ impl<'a> CapnpWrite<'a> for PoolingConfig {
                 padding.set(i as u32, *dim as u64);
             }
         }
+        match self.padding_value {
+            Some(value) => builder.borrow().init_padding_value().set_value(value),
+            None => builder.borrow().init_padding_value().set_default(()),
+        }
     }
 }
 
@@ -238,16 +403,22 @@ impl<'a> CapnpRead<'a> for PoolingConfig {
             padding.push(read_padding.get(i) as usize)
         }
 
+        let padding_value = match reader.get_padding_value().which().unwrap() {
+            capnp_padding_value::Which::Default(_) => None,
+            capnp_padding_value::Which::Value(value) => Some(value),
+        };
+
         PoolingConfig {
             mode: mode,
             filter_shape: filter_shape,
             stride: stride,
             padding: padding,
+            padding_value: padding_value,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// The different modes of pooling that can be calculated.
 pub enum PoolingMode {
     /// The maximum value inside the pooling window will be used as result.
@@ -273,3 +444,61 @@ impl PoolingMode {
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use util::{LayerOps, native_backend};
+    use util::testing::{read, tensor};
+
+    /// Returns a network of a 3x3 max pooling with a padding of 1 and its output for a
+    /// 3x3 input of negative values.
+    fn pool_negative_input<B: IBackend + LayerOps<f32> + 'static>(backend: B,
+                                                                 padding_value: Option<f32>)
+                                                                 -> (Layer<B>, Vec<f32>) {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 1, 3, 3]);
+        cfg.add_layer(LayerConfig::new("pool",
+                                       PoolingConfig {
+                                           mode: PoolingMode::Max,
+                                           filter_shape: vec![3],
+                                           stride: vec![1],
+                                           padding: vec![1],
+                                           padding_value: padding_value,
+                                       }));
+        let mut network = Layer::from_config(Rc::new(backend), &LayerConfig::new("network", cfg)).unwrap();
+        let input = tensor(&[1, 1, 3, 3], &[-1f32, -2.0, -3.0, -4.0, -5.0, -6.0, -7.0, -8.0, -9.0]);
+        let output = read(&network.forward(&[input])[0]);
+        (network, output)
+    }
+
+    #[test]
+    fn max_pooling_never_selects_the_default_padding() {
+        let (mut network, output) = pool_negative_input(native_backend(), None);
+        assert_eq!(vec![-1f32, -1.0, -2.0, -1.0, -1.0, -2.0, -4.0, -4.0, -5.0], output);
+
+        // every output routes its gradient to the maximum of its window
+        let gradient = network.backward(&[tensor(&[1, 1, 3, 3], &[1f32; 9])]);
+        assert_eq!(vec![4f32, 2.0, 0.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0], read(&gradient[0]));
+    }
+
+    #[test]
+    fn max_pooling_selects_an_explicit_padding_value() {
+        let (_, output) = pool_negative_input(native_backend(), Some(0f32));
+        assert_eq!(vec![0f32, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 0.0], output);
+    }
+
+    #[test]
+    #[cfg(feature="cuda")]
+    fn backends_agree_on_the_padding() {
+        for padding_value in vec![None, Some(0f32), Some(-2f32)] {
+            let (_, native_output) = pool_negative_input(native_backend(), padding_value);
+            let (_, cuda_output) = pool_negative_input(Backend::<Cuda>::default().unwrap(), padding_value);
+            assert_eq!(native_output, cuda_output);
+        }
+    }
+}
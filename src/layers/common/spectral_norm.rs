@@ -0,0 +1,475 @@
+//! Normalizes the weight of a wrapped layer by its spectral norm.
+//!
+//! The forward pass divides the first weight `W` of the wrapped layer, e.g. the weight of
+//! a Linear layer or the filter of a Convolution, by an estimate of its largest singular
+//! value `σ`, so the wrapped layer computes with a weight of spectral norm ~1. This bounds
+//! the Lipschitz constant of the layer, which stabilizes e.g. the training of the
+//! discriminator of a GAN. The weight itself stays unnormalized and is updated by the
+//! Solver as usual.
+//!
+//! `σ = uᵀ W v` is estimated by power iteration like [Layer::spectral_norm][1]. The vectors
+//! `u` and `v` are kept between passes and advanced by `power_iterations` steps in every
+//! forward pass in training mode. In inference mode they are not updated, see
+//! [Layer::set_train][2].
+//! [1]: ../../../layer/struct.Layer.html#method.spectral_norm
+//! [2]: ../../../layer/struct.Layer.html#method.set_train
+//!
+//! The backward pass turns the gradient `G` w.r.t. the normalized weight into the gradient
+//! w.r.t. `W`, treating `u` and `v` as constants: `(G - <G, W / σ> u vᵀ) / σ`.
+//!
+//! The weight is viewed as a matrix with one row per index of its first dimension.
+
+use capnp_util::*;
+use co::prelude::*;
+use coblas::transpose::Transpose;
+use juice_capnp::spectral_norm_config as capnp_config;
+use layer::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use util::{ArcLock, LayerOps, gemm_dot, native_scalar, power_iteration, singular_value, singular_vectors};
+
+#[derive(Debug)]
+/// SpectralNorm Layer
+pub struct SpectralNorm<B: IBackend + LayerOps<f32>> {
+    layer: Box<ILayer<B>>,
+    power_iterations: usize,
+    train: bool,
+
+    state: RefCell<PowerIteration>,
+    normalized_weight: RefCell<SharedTensor<f32>>,
+    normalized_gradient: RefCell<SharedTensor<f32>>,
+}
+
+#[derive(Debug, Default)]
+/// The singular vectors `u` and `v` and the spectral norm estimated by power iteration.
+struct PowerIteration {
+    vectors: Option<(SharedTensor<f32>, SharedTensor<f32>)>,
+    sigma: f32,
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> SpectralNorm<B> {
+    /// Create a SpectralNorm layer from a SpectralNormConfig.
+    ///
+    /// Returns an error if the wrapped layer can not be created.
+    pub fn from_config(backend: Rc<B>, config: &SpectralNormConfig) -> Result<SpectralNorm<B>, LayerError> {
+        let layer_config = LayerConfig::new("spectral_norm", (*config.layer_type).clone());
+        let layer = try!(Layer::<B>::worker_from_config(backend, &layer_config));
+
+        Ok(SpectralNorm {
+            layer: layer,
+            power_iterations: config.power_iterations,
+            train: true,
+
+            state: RefCell::new(PowerIteration::default()),
+            normalized_weight: RefCell::new(SharedTensor::new(&[1])),
+            normalized_gradient: RefCell::new(SharedTensor::new(&[1])),
+        })
+    }
+
+    /// Estimates the spectral norm of `weight` and writes the normalized weight.
+    fn normalize_weight(&self, backend: &B, weight: &SharedTensor<f32>) {
+        let rows = weight.desc()[0];
+        let columns = weight.desc().size() / rows;
+
+        let mut state = self.state.borrow_mut();
+        let reinit = state.vectors.as_ref().map_or(true, |&(ref u, ref v)| {
+            u.desc().size() != rows || v.desc().size() != columns
+        });
+        if reinit {
+            let (mut u, mut v) = singular_vectors(rows, columns);
+            // v is derived from u, also in inference mode
+            power_iteration(backend, weight, &mut u, &mut v, 1).unwrap();
+            state.vectors = Some((u, v));
+        }
+        let sigma = {
+            let &mut (ref mut u, ref mut v) = state.vectors.as_mut().unwrap();
+            if self.train {
+                power_iteration(backend, weight, u, v, self.power_iterations).unwrap();
+            }
+            singular_value(backend, weight, u, v).unwrap()
+        };
+        // clamped, so a zero weight stays zero instead of becoming NaN
+        state.sigma = sigma.max(::std::f32::EPSILON);
+
+        let mut normalized = self.normalized_weight.borrow_mut();
+        backend.weighted_sum(&[1f32 / state.sigma], &[weight], &mut normalized).unwrap();
+    }
+}
+
+/// Accumulates the gradient w.r.t. the unnormalized weight into `weight_gradient`, given the
+/// gradient `normalized_gradient` w.r.t. the weight normalized by `state.sigma`.
+fn accumulate_weight_gradient<B: IBackend + LayerOps<f32>>(backend: &B,
+                                                           normalized_gradient: &mut SharedTensor<f32>,
+                                                           normalized_weight: &mut SharedTensor<f32>,
+                                                           state: &PowerIteration,
+                                                           weight_gradient: &mut SharedTensor<f32>)
+                                                           -> Result<(), ::co::error::Error> {
+    let projection = try!(gemm_dot(backend, normalized_gradient, normalized_weight));
+    let &(ref u, ref v) = state.vectors.as_ref().unwrap();
+    try!(backend.axpy(&native_scalar(1f32 / state.sigma), normalized_gradient, weight_gradient));
+    backend.gemm(&native_scalar(-projection / state.sigma),
+                 Transpose::NoTrans,
+                 u,
+                 Transpose::Trans,
+                 v,
+                 &native_scalar(1f32),
+                 weight_gradient)
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for SpectralNorm<B> {
+    fn init(&mut self, backend: Rc<B>) {
+        self.layer.init(backend);
+    }
+
+    fn reshape(&mut self,
+               backend: Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        self.layer.reshape(backend,
+                           input_data,
+                           input_gradient,
+                           weights_data,
+                           weights_gradient,
+                           output_data,
+                           output_gradient);
+    }
+
+    fn resize_shared_workspace(&mut self,
+                               backend: Rc<B>,
+                               workspace: Option<ArcLock<SharedTensor<u8>>>)
+                               -> Option<ArcLock<SharedTensor<u8>>> {
+        self.layer.resize_shared_workspace(backend, workspace)
+    }
+
+    fn workspace_size(&self) -> usize {
+        self.layer.workspace_size()
+    }
+
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        self.layer.flops(input_shapes, output_shapes)
+    }
+
+    fn auto_output_blobs(&self) -> bool {
+        self.layer.auto_output_blobs()
+    }
+
+    fn min_output_blobs(&self) -> usize {
+        self.layer.min_output_blobs()
+    }
+
+    fn exact_num_output_blobs(&self) -> Option<usize> {
+        self.layer.exact_num_output_blobs()
+    }
+
+    fn auto_weight_blobs(&self) -> bool {
+        self.layer.auto_weight_blobs()
+    }
+
+    fn num_weight_blobs(&self) -> Option<usize> {
+        self.layer.num_weight_blobs()
+    }
+
+    fn weight_decayable(&self, weight_id: usize) -> bool {
+        self.layer.weight_decayable(weight_id)
+    }
+
+    fn learnable_weight(&self, weight_id: usize) -> bool {
+        self.layer.learnable_weight(weight_id)
+    }
+
+    fn exact_num_input_blobs(&self) -> Option<usize> {
+        self.layer.exact_num_input_blobs()
+    }
+
+    fn allow_force_backward(&self, input_id: usize) -> bool {
+        self.layer.allow_force_backward(input_id)
+    }
+
+    fn loss_weight(&self, output_id: usize) -> Option<f32> {
+        self.layer.loss_weight(output_id)
+    }
+
+    fn set_train(&mut self, train: bool) {
+        self.train = train;
+        self.layer.set_train(train);
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeOutput<f32, B> for SpectralNorm<B> {
+    fn compute_output(&self,
+                      backend: &B,
+                      weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        if weights.is_empty() {
+            return self.layer.compute_output(backend, weights, input_data, output_data);
+        }
+        self.normalize_weight(backend, weights[0]);
+        let normalized = self.normalized_weight.borrow();
+        let mut weights = weights.to_vec();
+        weights[0] = &*normalized;
+        self.layer.compute_output(backend, &weights, input_data, output_data);
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeInputGradient<f32, B> for SpectralNorm<B> {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        if weights_data.is_empty() {
+            return self.layer.compute_input_gradient(backend,
+                                                     weights_data,
+                                                     output_data,
+                                                     output_gradients,
+                                                     input_data,
+                                                     input_gradients);
+        }
+        // the forward pass has normalized the weight already
+        let normalized = self.normalized_weight.borrow();
+        let mut weights_data = weights_data.to_vec();
+        weights_data[0] = &*normalized;
+        self.layer.compute_input_gradient(backend,
+                                          &weights_data,
+                                          output_data,
+                                          output_gradients,
+                                          input_data,
+                                          input_gradients);
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeParametersGradient<f32, B> for SpectralNorm<B> {
+    fn compute_parameters_gradient(&self,
+                                   backend: &B,
+                                   output_data: &[&SharedTensor<f32>],
+                                   output_gradients: &[&SharedTensor<f32>],
+                                   input_data: &[&SharedTensor<f32>],
+                                   parameters_gradients: &mut [&mut SharedTensor<f32>]) {
+        let (weight_gradient, other_gradients) = match parameters_gradients.split_first_mut() {
+            Some(gradients) => gradients,
+            None => {
+                return self.layer.compute_parameters_gradient(backend,
+                                                              output_data,
+                                                              output_gradients,
+                                                              input_data,
+                                                              &mut []);
+            }
+        };
+        let mut normalized_gradient = self.normalized_gradient.borrow_mut();
+        if normalized_gradient.desc() != weight_gradient.desc() {
+            normalized_gradient.resize(weight_gradient.desc()).unwrap();
+        }
+        // the wrapped layer accumulates the gradient w.r.t. the normalized weight
+        backend.fill(&mut *normalized_gradient, 0f32).unwrap();
+        {
+            let mut gradients = vec![&mut *normalized_gradient];
+            gradients.extend(other_gradients.iter_mut().map(|gradient| &mut **gradient));
+            self.layer.compute_parameters_gradient(backend, output_data, output_gradients, input_data, &mut gradients);
+        }
+
+        accumulate_weight_gradient(backend,
+                                   &mut normalized_gradient,
+                                   &mut self.normalized_weight.borrow_mut(),
+                                   &self.state.borrow(),
+                                   weight_gradient)
+            .unwrap();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Specifies configuration parameters for a SpectralNorm Layer.
+pub struct SpectralNormConfig {
+    /// The type of the wrapped layer, whose first weight is normalized.
+    ///
+    /// The weights are configured in the [params][1] of the SpectralNorm layer.
+    /// [1]: ../../../layer/struct.LayerConfig.html#structfield.params
+    pub layer_type: Box<LayerType>,
+    /// The number of power iteration steps in every forward pass in training mode.
+    ///
+    /// While the weight changes slowly during training a single step suffices.
+    pub power_iterations: usize,
+}
+
+impl SpectralNormConfig {
+    /// Create a SpectralNormConfig that wraps a layer of the type `layer_type` with one
+    /// power iteration step per forward pass.
+    pub fn new<L: Into<LayerType>>(layer_type: L) -> SpectralNormConfig {
+        SpectralNormConfig {
+            layer_type: Box::new(layer_type.into()),
+            power_iterations: 1,
+        }
+    }
+
+    /// Checks that there is at least one power iteration step and that the wrapped layer
+    /// is valid and not a container.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.power_iterations == 0 {
+            return Err("power_iterations must be positive, but is 0".to_owned());
+        }
+        match *self.layer_type {
+            LayerType::Sequential(_) |
+            LayerType::SpectralNorm(_) => {
+                Err(format!("layer_type: a {} layer can't be wrapped", self.layer_type.name()))
+            }
+            ref layer_type => layer_type.validate().map_err(|message| format!("layer_type: {}", message)),
+        }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for SpectralNormConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the SpectralNormConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        {
+            let mut layer = builder.borrow().init_layer();
+            let mut layer_type = layer.borrow().init_layer_type();
+            self.layer_type.write_capnp(&mut layer_type);
+        }
+        builder.set_power_iterations(self.power_iterations as u64);
+    }
+}
+
+impl<'a> CapnpRead<'a> for SpectralNormConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let layer_type = LayerType::read_capnp(reader.get_layer().unwrap().get_layer_type());
+
+        SpectralNormConfig {
+            layer_type: Box::new(layer_type),
+            power_iterations: reader.get_power_iterations() as usize,
+        }
+    }
+}
+
+impl Into<LayerType> for SpectralNormConfig {
+    fn into(self) -> LayerType {
+        LayerType::SpectralNorm(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::{PowerIteration, accumulate_weight_gradient};
+    use util::{native_backend, write_to_memory};
+    use util::testing::{read, shared_tensor, tensor};
+
+    /// Returns the largest singular value of the 2x3 matrix `m` from the eigenvalues of `m mᵀ`.
+    fn largest_singular_value(m: &[f32]) -> f32 {
+        let a = m[0] * m[0] + m[1] * m[1] + m[2] * m[2];
+        let b = m[0] * m[3] + m[1] * m[4] + m[2] * m[5];
+        let c = m[3] * m[3] + m[4] * m[4] + m[5] * m[5];
+        ((a + c) / 2f32 + (((a - c) / 2f32).powi(2) + b * b).sqrt()).sqrt()
+    }
+
+    /// A spectrally normalized Linear layer, whose output for an identity input is the
+    /// transposed normalized weight.
+    fn network(weight: &[f32]) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 2]);
+        cfg.add_layer(LayerConfig::new("linear", SpectralNormConfig::new(LinearConfig { output_size: 3 })));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let weight_blob = network.learnable_weights_data()[0].clone();
+        assert_eq!(&vec![3, 2], weight_blob.read().unwrap().desc());
+        write_to_memory(weight_blob.write().unwrap().write_only(native.device()).unwrap(), weight);
+        network
+    }
+
+    #[test]
+    fn normalized_weight_has_spectral_norm_one() {
+        let mut other = network(&[1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut network = network(&[1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let identity = tensor(&[2, 2], &[1f32, 0.0, 0.0, 1.0]);
+        let mut output = read(&network.forward(&[identity.clone()])[0]);
+        // the power iteration starts from a seeded vector
+        assert_eq!(output, read(&other.forward(&[identity.clone()])[0]));
+        for _ in 0..20 {
+            output = read(&network.forward(&[identity.clone()])[0]);
+        }
+        assert!((largest_singular_value(&output) - 1f32).abs() < 1e-4,
+                "{}",
+                largest_singular_value(&output));
+        // the spectral norm of [[1, 2], [3, 4], [5, 6]] is 9.52551809
+        assert!((output[0] - 1f32 / 9.52551809).abs() < 1e-5, "{:?}", output);
+        // the weight itself is not modified
+        assert_eq!(vec![1f32, 2.0, 3.0, 4.0, 5.0, 6.0],
+                   read(&network.learnable_weights_data()[0]));
+    }
+
+    #[test]
+    fn power_iteration_only_updates_in_train_mode() {
+        let mut network = network(&[1f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let identity = tensor(&[2, 2], &[1f32, 0.0, 0.0, 1.0]);
+        for _ in 0..20 {
+            network.forward(&[identity.clone()]);
+        }
+
+        // with the singular vectors of the old weight the estimate of the new weight is off
+        network.set_train(false);
+        let native = native_backend();
+        let weight = network.learnable_weights_data()[0].clone();
+        write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(),
+                        &[6f32, 5.0, 4.0, 3.0, 2.0, 1.0]);
+        let inference = read(&network.forward(&[identity.clone()])[0]);
+        assert!(largest_singular_value(&inference) > 1.5, "{:?}", inference);
+        assert_eq!(inference, read(&network.forward(&[identity.clone()])[0]));
+
+        network.set_train(true);
+        let mut output = Vec::new();
+        for _ in 0..20 {
+            output = read(&network.forward(&[identity.clone()])[0]);
+        }
+        assert!((largest_singular_value(&output) - 1f32).abs() < 1e-4, "{:?}", output);
+    }
+
+    #[test]
+    fn weight_gradient_accounts_for_normalization() {
+        // f(W) = <C, W / (uᵀ W v)> for fixed u and v, whose gradient w.r.t. W / σ is C
+        let weight = [1f32, 2.0, 3.0, 4.0];
+        let c = [0.5f32, -1.0, 2.0, 0.25];
+        let u = [0.6f32, 0.8];
+        let v = [0.6f32, 0.8];
+        let f = |weight: &[f32]| {
+            let sigma = u[0] * (weight[0] * v[0] + weight[1] * v[1]) + u[1] * (weight[2] * v[0] + weight[3] * v[1]);
+            weight.iter().zip(&c).fold(0f32, |sum, (w, c)| sum + w * c) / sigma
+        };
+        let sigma = u[0] * (weight[0] * v[0] + weight[1] * v[1]) + u[1] * (weight[2] * v[0] + weight[3] * v[1]);
+        let state = PowerIteration {
+            vectors: Some((shared_tensor(&[2, 1], &u), shared_tensor(&[2, 1], &v))),
+            sigma: sigma,
+        };
+        let normalized = weight.iter().map(|w| w / sigma).collect::<Vec<_>>();
+        let mut weight_gradient = shared_tensor(&[2, 2], &[0f32; 4]);
+        accumulate_weight_gradient(&native_backend(),
+                                   &mut shared_tensor(&[2, 2], &c),
+                                   &mut shared_tensor(&[2, 2], &normalized),
+                                   &state,
+                                   &mut weight_gradient)
+            .unwrap();
+        let gradient = read(&Arc::new(RwLock::new(weight_gradient)));
+
+        for i in 0..4 {
+            let mut plus = weight.to_vec();
+            let mut minus = weight.to_vec();
+            plus[i] += 1e-2;
+            minus[i] -= 1e-2;
+            let numeric = (f(&plus) - f(&minus)) / 2e-2;
+            assert!((gradient[i] - numeric).abs() < 1e-2, "{}: {} vs {}", i, gradient[i], numeric);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! Randomly skips the residual branch of a residual block during training.
+//!
+//! The wrapped layer computes the residual branch `f` of a block whose output is
+//! `x + f(x)`. In training mode the branch survives each forward pass with the probability
+//! `survival_prob`; otherwise it is not computed at all and the block passes its input
+//! through unchanged. In inference mode the branch is always computed and scaled by its
+//! expected contribution: the output is `x + survival_prob * f(x)`. See [Deep Networks
+//! with Stochastic Depth][1].
+//! [1]: https://arxiv.org/abs/1603.09382
+//!
+//! The wrapped layer has to preserve the shape of its input, so the output of the branch
+//! can be added to it.
+//!
+//! A forward pass that [recomputes][2] the output for the backward pass keeps the decision
+//! of the last forward pass whether the branch survives.
+//! [2]: ../../../layer/struct.Layer.html#method.set_recomputing
+
+use capnp_util::*;
+use co::prelude::*;
+use juice_capnp::stochastic_depth_config as capnp_config;
+use layer::*;
+use rand::{self, Rng};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use util::{ArcLock, LayerOps, native_scalar};
+
+#[derive(Debug)]
+/// StochasticDepth Layer
+pub struct StochasticDepth<B: IBackend + LayerOps<f32>> {
+    layer: Box<ILayer<B>>,
+    survival_prob: f32,
+    train: bool,
+    recomputing: bool,
+
+    /// Whether the branch has been computed in the last forward pass.
+    survived: Cell<bool>,
+    branch_output: RefCell<SharedTensor<f32>>,
+    branch_gradient: RefCell<SharedTensor<f32>>,
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> StochasticDepth<B> {
+    /// Create a StochasticDepth layer from a StochasticDepthConfig.
+    ///
+    /// Returns an error if the wrapped layer can not be created.
+    pub fn from_config(backend: Rc<B>, config: &StochasticDepthConfig) -> Result<StochasticDepth<B>, LayerError> {
+        let layer_config = LayerConfig::new("stochastic_depth", (*config.layer_type).clone());
+        let layer = try!(Layer::<B>::worker_from_config(backend, &layer_config));
+
+        Ok(StochasticDepth {
+            layer: layer,
+            survival_prob: config.survival_prob,
+            train: true,
+            recomputing: false,
+
+            survived: Cell::new(true),
+            branch_output: RefCell::new(SharedTensor::new(&[1])),
+            branch_gradient: RefCell::new(SharedTensor::new(&[1])),
+        })
+    }
+
+    /// The factor of the output of the branch, which is only scaled in inference mode.
+    fn branch_scale(&self) -> f32 {
+        if self.train { 1f32 } else { self.survival_prob }
+    }
+
+    /// Writes the gradient w.r.t. the output of the branch.
+    fn compute_branch_gradient(&self, backend: &B, output_gradient: &SharedTensor<f32>) {
+        let mut branch_gradient = self.branch_gradient.borrow_mut();
+        backend.weighted_sum(&[self.branch_scale()], &[output_gradient], &mut branch_gradient).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for StochasticDepth<B> {
+    impl_ilayer_common!();
+
+    fn init(&mut self, backend: Rc<B>) {
+        self.layer.init(backend);
+    }
+
+    fn reshape(&mut self,
+               backend: Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        self.layer.reshape(backend,
+                           input_data,
+                           input_gradient,
+                           weights_data,
+                           weights_gradient,
+                           output_data,
+                           output_gradient);
+        let input_shape = input_data[0].read().unwrap().desc().clone();
+        let output_shape = output_data[0].read().unwrap().desc().clone();
+        assert_eq!(input_shape,
+                   output_shape,
+                   "The residual branch of a StochasticDepth layer has to preserve the shape of its input");
+        self.branch_output.borrow_mut().resize(&output_shape).unwrap();
+        self.branch_gradient.borrow_mut().resize(&output_shape).unwrap();
+    }
+
+    fn resize_shared_workspace(&mut self,
+                               backend: Rc<B>,
+                               workspace: Option<ArcLock<SharedTensor<u8>>>)
+                               -> Option<ArcLock<SharedTensor<u8>>> {
+        self.layer.resize_shared_workspace(backend, workspace)
+    }
+
+    fn workspace_size(&self) -> usize {
+        self.layer.workspace_size()
+    }
+
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        // the sum with the input
+        let additions = output_shapes.iter()
+            .fold(0, |sum, shape| sum + shape.iter().fold(1, |prod, i| prod * i));
+        self.layer.flops(input_shapes, output_shapes) + additions as u64
+    }
+
+    fn auto_weight_blobs(&self) -> bool {
+        self.layer.auto_weight_blobs()
+    }
+
+    fn num_weight_blobs(&self) -> Option<usize> {
+        self.layer.num_weight_blobs()
+    }
+
+    fn weight_decayable(&self, weight_id: usize) -> bool {
+        self.layer.weight_decayable(weight_id)
+    }
+
+    fn learnable_weight(&self, weight_id: usize) -> bool {
+        self.layer.learnable_weight(weight_id)
+    }
+
+    fn allow_force_backward(&self, input_id: usize) -> bool {
+        self.layer.allow_force_backward(input_id)
+    }
+
+    fn set_train(&mut self, train: bool) {
+        self.train = train;
+        self.layer.set_train(train);
+    }
+
+    fn set_recomputing(&mut self, recomputing: bool) {
+        self.recomputing = recomputing;
+        self.layer.set_recomputing(recomputing);
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeOutput<f32, B> for StochasticDepth<B> {
+    fn compute_output(&self,
+                      backend: &B,
+                      weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let survived = if self.recomputing {
+            self.survived.get()
+        } else {
+            !self.train || rand::thread_rng().gen::<f32>() < self.survival_prob
+        };
+        self.survived.set(survived);
+
+        if !survived {
+            backend.weighted_sum(&[1f32], &[input_data[0]], output_data[0]).unwrap();
+            return;
+        }
+        let mut branch_output = self.branch_output.borrow_mut();
+        self.layer.compute_output(backend, weights, input_data, &mut [&mut *branch_output]);
+        backend.weighted_sum(&[1f32, self.branch_scale()],
+                          &[input_data[0], &*branch_output],
+                          output_data[0])
+            .unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeInputGradient<f32, B> for StochasticDepth<B> {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        if !self.survived.get() {
+            backend.weighted_sum(&[1f32], &[output_gradients[0]], input_gradients[0]).unwrap();
+            return;
+        }
+        self.compute_branch_gradient(backend, output_gradients[0]);
+        let branch_output = self.branch_output.borrow();
+        let branch_gradient = self.branch_gradient.borrow();
+        self.layer.compute_input_gradient(backend,
+                                          weights_data,
+                                          &[&*branch_output],
+                                          &[&*branch_gradient],
+                                          input_data,
+                                          input_gradients);
+
+        // the gradient of the identity
+        backend.axpy(&native_scalar(1f32), output_gradients[0], input_gradients[0]).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32> + 'static> ComputeParametersGradient<f32, B> for StochasticDepth<B> {
+    fn compute_parameters_gradient(&self,
+                                   backend: &B,
+                                   output_data: &[&SharedTensor<f32>],
+                                   output_gradients: &[&SharedTensor<f32>],
+                                   input_data: &[&SharedTensor<f32>],
+                                   parameters_gradients: &mut [&mut SharedTensor<f32>]) {
+        // a skipped branch doesn't contribute to the gradients of its weights
+        if !self.survived.get() {
+            return;
+        }
+        self.compute_branch_gradient(backend, output_gradients[0]);
+        let branch_output = self.branch_output.borrow();
+        let branch_gradient = self.branch_gradient.borrow();
+        self.layer.compute_parameters_gradient(backend,
+                                               &[&*branch_output],
+                                               &[&*branch_gradient],
+                                               input_data,
+                                               parameters_gradients);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Specifies configuration parameters for a StochasticDepth Layer.
+pub struct StochasticDepthConfig {
+    /// The type of the wrapped layer, which computes the residual branch.
+    ///
+    /// The weights are configured in the [params][1] of the StochasticDepth layer.
+    /// [1]: ../../../layer/struct.LayerConfig.html#structfield.params
+    pub layer_type: Box<LayerType>,
+    /// The probability that the residual branch is computed in a forward pass in
+    /// training mode.
+    ///
+    /// The original paper decays it linearly from 1 for the first block to 0.5 for the
+    /// last block.
+    pub survival_prob: f32,
+}
+
+impl StochasticDepthConfig {
+    /// Create a StochasticDepthConfig whose residual branch is a layer of the type
+    /// `layer_type` that survives with the probability `survival_prob`.
+    pub fn new<L: Into<LayerType>>(layer_type: L, survival_prob: f32) -> StochasticDepthConfig {
+        StochasticDepthConfig {
+            layer_type: Box::new(layer_type.into()),
+            survival_prob: survival_prob,
+        }
+    }
+
+    /// Checks that the survival probability is in `(0, 1]` and that the wrapped layer
+    /// is valid and not a container.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.survival_prob > 0f32 && self.survival_prob <= 1f32) {
+            return Err(format!("survival_prob must be in (0, 1], but is {}", self.survival_prob));
+        }
+        match *self.layer_type {
+            LayerType::Sequential(_) => Err(format!("layer_type: a {} layer can't be wrapped", self.layer_type.name())),
+            ref layer_type => layer_type.validate().map_err(|message| format!("layer_type: {}", message)),
+        }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for StochasticDepthConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the StochasticDepthConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        {
+            let mut layer = builder.borrow().init_layer();
+            let mut layer_type = layer.borrow().init_layer_type();
+            self.layer_type.write_capnp(&mut layer_type);
+        }
+        builder.set_survival_prob(self.survival_prob);
+    }
+}
+
+impl<'a> CapnpRead<'a> for StochasticDepthConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let layer_type = LayerType::read_capnp(reader.get_layer().unwrap().get_layer_type());
+
+        StochasticDepthConfig {
+            layer_type: Box::new(layer_type),
+            survival_prob: reader.get_survival_prob(),
+        }
+    }
+}
+
+impl Into<LayerType> for StochasticDepthConfig {
+    fn into(self) -> LayerType {
+        LayerType::StochasticDepth(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use util::{native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+
+    /// A block whose residual branch is a Linear layer that doubles its input.
+    fn network(survival_prob: f32) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_layer(LayerConfig::new("block",
+                                       StochasticDepthConfig::new(LinearConfig { output_size: 2 }, survival_prob)));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let weight = network.learnable_weights_data()[0].clone();
+        write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(),
+                        &[2f32, 0.0, 0.0, 2.0]);
+        network
+    }
+
+    #[test]
+    fn identity_is_taken_at_expected_rate() {
+        let mut network = network(0.7);
+        let input = tensor(&[1, 2], &[1f32, -2.0]);
+        let runs = 2000;
+        let mut skipped = 0;
+        for _ in 0..runs {
+            let output = read(&network.forward(&[input.clone()])[0]);
+            if output == vec![1f32, -2.0] {
+                skipped += 1;
+            } else {
+                assert_eq!(vec![3f32, -6.0], output);
+            }
+        }
+        // the standard deviation of the rate is about 0.01
+        let rate = skipped as f32 / runs as f32;
+        assert!((rate - 0.3).abs() < 0.05, "{}", rate);
+    }
+
+    #[test]
+    fn inference_scales_branch_by_survival_prob() {
+        let mut network = network(0.7);
+        network.set_train(false);
+        let input = tensor(&[1, 2], &[1f32, -2.0]);
+        for _ in 0..10 {
+            let output = read(&network.forward(&[input.clone()])[0]);
+            assert!((output[0] - 2.4).abs() < 1e-6 && (output[1] + 4.8).abs() < 1e-6,
+                    "{:?}",
+                    output);
+        }
+
+        // the gradient flows through the identity and the scaled branch
+        let input_gradient = network.backward(&[tensor(&[1, 2], &[1f32, 1.0])]);
+        let input_gradient = read(&input_gradient[0]);
+        assert!((input_gradient[0] - 2.4).abs() < 1e-6, "{:?}", input_gradient);
+    }
+
+    #[test]
+    fn recomputed_forward_keeps_the_decision() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        let mut block = LayerConfig::new("block", StochasticDepthConfig::new(LinearConfig { output_size: 2 }, 0.5));
+        block.recompute_in_backward = true;
+        cfg.add_layer(block);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[2f32, 0.0, 0.0, 2.0]);
+        write_to_memory(weights[1].write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, 0.0, 0.0, 1.0]);
+
+        // the block either passes its input through or triples it, and the gradient of the
+        // recomputed block has to match the decision of the forward pass
+        for _ in 0..20 {
+            let output = read(&network.forward(&[tensor(&[1, 2], &[1f32, -2.0])])[0]);
+            let gradient = read(&network.backward(&[tensor(&[1, 2], &[1f32, 1.0])])[0]);
+            if output == vec![1f32, -2.0] {
+                assert_eq!(vec![1f32, 1.0], gradient);
+            } else {
+                assert_eq!(vec![3f32, -6.0], output);
+                assert_eq!(vec![3f32, 3.0], gradient);
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 use capnp_util::*;
 use co::{IBackend, SharedTensor};
+use coblas::plugin::Axpy;
 use layer::*;
 use juice_capnp::sequential_config as capnp_config;
 use juice_capnp::shaped_input as capnp_shaped_input;
@@ -9,7 +10,8 @@ use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
-use util::{ArcLock, LayerOps};
+use util::{ArcLock, Fill, LayerOps, native_scalar};
+use weight::{FillerType, InitDefaults};
 
 #[derive(Debug)]
 /// Sequential Layer
@@ -24,6 +26,16 @@ pub struct Sequential<B: IBackend + LayerOps<f32>> {
     output_gradient_tensors: Vec<ArcLock<SharedTensor<f32>>>,
 
     registry: HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>,
+
+    lazy_config: Option<SequentialConfig>,
+
+    // index of the first layer of each checkpoint segment, empty if checkpointing is disabled
+    checkpoint_segment_starts: Vec<usize>,
+    // whether the activations of each checkpoint segment are released and recomputed
+    recomputed_segments: Vec<bool>,
+    // for each layer the private input gradients it computes and the gradient of the
+    // blob consumed by several layers they are added to
+    fan_out_gradients: Vec<Vec<(ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>>,
 }
 
 impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
@@ -40,14 +52,34 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
             output_gradient_tensors: vec![],
 
             registry: HashMap::new(),
+
+            lazy_config: None,
+
+            checkpoint_segment_starts: vec![],
+            recomputed_segments: vec![],
+            fan_out_gradients: vec![],
         }
     }
 
     /// Create a Sequential layer from a SequentialConfig.
-    pub fn from_config(backend: Rc<B>, config: &SequentialConfig) -> Sequential<B> {
+    ///
+    /// Returns the error of the first contained layer that can not be created.
+    pub fn from_config(backend: Rc<B>, config: &SequentialConfig) -> Result<Sequential<B>, LayerError> {
         let mut layer = Self::empty();
 
-        layer.init_layers(backend, config);
+        try!(layer.init_layers(backend, config));
+
+        Ok(layer)
+    }
+
+    /// Create a Sequential layer that is initialized once the shapes of its inputs are known.
+    ///
+    /// The shapes of the inputs in the SequentialConfig are ignored.
+    /// See [Layer::from_config_lazy][1].
+    /// [1]: ../../../layer/struct.Layer.html#method.from_config_lazy
+    pub fn lazy(config: &SequentialConfig) -> Sequential<B> {
+        let mut layer = Self::empty();
+        layer.lazy_config = Some(config.clone());
 
         layer
     }
@@ -59,7 +91,7 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
     /// to be executed for each tensor and layer.
     ///
     /// [1]: ./struct.SequentialConfig.html
-    pub fn init_layers(&mut self, backend: Rc<B>, in_config: &SequentialConfig) {
+    pub fn init_layers(&mut self, backend: Rc<B>, in_config: &SequentialConfig) -> Result<(), LayerError> {
         let mut config = in_config.clone();
         let mut registry = HashMap::<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>::new();
         let weight_registry =
@@ -70,10 +102,20 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
             self.init_input_blob(backend.clone(), &input_name, &input_shape, &mut registry);
         }
 
-        // add input names to first layer so they correctly connect
+        // add input names to first layer so they correctly connect, except for inputs
+        // like labels or masks that later layers explicitly consume. The first input feeds
+        // the first layer unless it declares its own inputs.
+        let consumed_inputs = config.layers
+            .iter()
+            .flat_map(|layer| layer.inputs.iter().cloned())
+            .collect::<HashSet<_>>();
         if let Some(first_layer) = config.layers.first_mut() {
-            for container_input in &self.input_tensor_names {
-                first_layer.add_input(&container_input);
+            let declares_inputs = !first_layer.inputs.is_empty();
+            for (i, container_input) in self.input_tensor_names.iter().enumerate() {
+                let feeds_first_layer = i == 0 && !declares_inputs;
+                if feeds_first_layer || !consumed_inputs.contains(container_input) {
+                    first_layer.add_input(&container_input);
+                }
             }
         }
         // connect each layer to the next one
@@ -100,25 +142,39 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
             }
         }
 
+        // nested containers without defaults of their own use the defaults of this container
+        if let Some(ref defaults) = config.init_defaults {
+            for layer_config in &mut config.layers {
+                if let LayerType::Sequential(ref mut nested_config) = layer_config.layer_type {
+                    if nested_config.init_defaults.is_none() {
+                        nested_config.init_defaults = Some(defaults.clone());
+                    }
+                }
+            }
+        }
+
         let mut shared_workspace = None;
         for layer_config in &config.layers {
-            self.init_layer(backend.clone(),
-                            &layer_config,
-                            &mut registry,
-                            weight_registry);
+            try!(self.init_layer(backend.clone(), &layer_config, &mut registry, weight_registry));
             shared_workspace = self.resize_shared_workspace(backend.clone(), shared_workspace);
         }
 
-        // Go through the net backwards to determine which blobs contribute to the
-        // loss.  We can skip backward computation for blobs that don't contribute
-        // to the loss.
-        // Also checks if all bottom blobs don't need backward computation (possible
-        // because the skip_propagate_down config) and so we can skip backward
-        // computation for the entire layer
-        let blobs_under_loss = &mut HashSet::<String>::new();
-        let blobs_skip_backp = &mut HashSet::<String>::new();
+        if let Some(ref defaults) = config.init_defaults {
+            for layer in &self.layers {
+                layer.borrow_mut().fill_default_weights(defaults);
+            }
+        }
+
+        // Go through the net backwards to determine which gradients are needed. We can
+        // skip the backward computation of layers whose outputs don't receive a gradient,
+        // because all their consumers disable propagate_down for them.
+        // The outputs of the container receive their gradients from outside.
+        let blobs_need_backp = &mut HashSet::<String>::new();
+        if let Some(last_layer) = self.layers.last() {
+            blobs_need_backp.extend(last_layer.borrow().output_blob_names().iter().cloned());
+        }
         for layer in &mut self.layers.iter_mut().rev() {
-            layer.borrow_mut().init_backprop(blobs_under_loss, blobs_skip_backp);
+            layer.borrow_mut().init_backprop(blobs_need_backp);
         }
 
         if config.force_backward {
@@ -127,6 +183,8 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
             }
         }
 
+        try!(self.init_fan_out_gradients());
+
         // Outputs of the last layer are considered output of the container
         if let Some(last_layer) = self.layers.last() {
             for data_tensor in &last_layer.borrow().output_blobs_data {
@@ -139,7 +197,202 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
 
         self.registry = registry;
 
+        if let Some(num_segments) = config.checkpoint_segments {
+            self.init_checkpoint_segments(num_segments);
+        } else {
+            self.init_recompute_segments(&config.layers);
+        }
+
         info!("Sequential container initialization done.");
+        Ok(())
+    }
+
+    /// Gives each layer that consumes a blob together with other layers a private gradient
+    /// for that blob.
+    ///
+    /// Otherwise the consumers would overwrite each other's gradient of the blob during
+    /// backpropagation. Instead the gradient of the blob is zeroed at the start of the
+    /// backward pass and the private gradients are added to it.
+    /// A layer that computes in-place starts a new version of its blob, which is
+    /// consumed by the following layers.
+    ///
+    /// Returns an InvalidConfig error if a layer computes in-place on a blob that other
+    /// layers consume as well, as it would overwrite the blob and its gradient for them.
+    fn init_fan_out_gradients(&mut self) -> Result<(), LayerError> {
+        let mut consumers = HashMap::<String, Vec<(usize, usize)>>::new();
+        let mut groups = Vec::new();
+        for (layer_id, layer) in self.layers.iter().enumerate() {
+            let layer = layer.borrow();
+            for (input_id, input_name) in layer.input_blob_names().iter().enumerate() {
+                consumers.entry(input_name.clone()).or_insert_with(Vec::new).push((layer_id, input_id));
+            }
+            for output_name in layer.output_blob_names() {
+                if let Some(group) = consumers.remove(output_name) {
+                    groups.push((output_name.clone(), group));
+                }
+            }
+        }
+        groups.extend(consumers.into_iter());
+
+        self.fan_out_gradients = vec![vec![]; self.layers.len()];
+        for (blob_name, group) in groups {
+            if group.len() < 2 {
+                continue;
+            }
+            if let Some(&(layer_id, _)) = group.iter().find(|&&(layer_id, _)| {
+                self.layers[layer_id].borrow().is_using_in_place()
+            }) {
+                let layer = self.layers[layer_id].borrow();
+                return Err(LayerError::invalid_config(&layer.config,
+                                                      format!("computes in-place on blob {}, which is also \
+                                                               consumed by other layers",
+                                                              blob_name)));
+            }
+            let (first_layer, first_input) = group[0];
+            let blob_gradient = self.layers[first_layer].borrow().input_blobs_gradient[first_input].clone();
+            for (layer_id, input_id) in group {
+                let mut layer = self.layers[layer_id].borrow_mut();
+                let mut gradient = SharedTensor::new(layer.input_blobs_data[input_id].read().unwrap().desc());
+                // a layer that does not propagate down keeps its gradient to itself
+                FillerType::fill_constant(&mut gradient, 0f32);
+                let gradient = Arc::new(RwLock::new(gradient));
+                layer.input_blobs_gradient[input_id] = gradient.clone();
+                if layer.input_needs_backward()[input_id] {
+                    self.fan_out_gradients[layer_id].push((gradient, blob_gradient.clone()));
+                }
+            }
+            info!("Accumulating the gradient of blob {} from several layers", blob_name);
+        }
+        Ok(())
+    }
+
+    /// Computes the input gradients of the layer `layer_id` and adds its private gradients
+    /// to the gradients of the blobs it shares with other layers.
+    ///
+    /// Does nothing if none of the input gradients is needed.
+    fn backward_layer_input(&self, backend: &B, layer_id: usize) {
+        {
+            let layer = self.layers[layer_id].borrow();
+            if !layer.needs_backward() || !layer.input_needs_backward().iter().any(|&needed| needed) {
+                return;
+            }
+        }
+        self.layers[layer_id].borrow_mut().backward_input(&[]);
+        for &(ref gradient, ref blob_gradient) in &self.fan_out_gradients[layer_id] {
+            backend.axpy(&native_scalar(1f32),
+                      &gradient.read().unwrap(),
+                      &mut blob_gradient.write().unwrap())
+                .unwrap();
+        }
+    }
+
+    /// Computes the weight gradients of the layer `layer_id`, unless it doesn't receive a gradient.
+    fn backward_layer_parameters(&self, layer_id: usize) {
+        if self.layers[layer_id].borrow().needs_backward() {
+            self.layers[layer_id].borrow_mut().backward_parameters();
+        }
+    }
+
+    /// Divides the layers into `num_segments` checkpoint segments of roughly equal length.
+    ///
+    /// A segment never starts with a layer that computes in-place, as recomputing it
+    /// would apply it twice to the retained output of the previous segment.
+    fn init_checkpoint_segments(&mut self, num_segments: usize) {
+        self.checkpoint_segment_starts = vec![];
+        self.recomputed_segments = vec![];
+        let num_layers = self.layers.len();
+        if num_segments < 2 || num_layers < 2 {
+            return;
+        }
+        let segment_length = (num_layers + num_segments - 1) / num_segments;
+
+        self.checkpoint_segment_starts.push(0);
+        for segment in 1..num_segments {
+            let mut start = segment * segment_length;
+            while start < num_layers && self.layers[start].borrow().is_using_in_place() {
+                start += 1;
+            }
+            if start < num_layers && start > *self.checkpoint_segment_starts.last().unwrap() {
+                self.checkpoint_segment_starts.push(start);
+            }
+        }
+        // the activations of the last segment are needed right away by the backward pass
+        let num_segments = self.checkpoint_segment_starts.len();
+        self.recomputed_segments = (0..num_segments).map(|segment| segment + 1 < num_segments).collect();
+        info!("Checkpoint segments start at layers {:?}",
+              self.checkpoint_segment_starts);
+    }
+
+    /// Divides the layers into checkpoint segments of consecutive layers that share the same
+    /// [recompute_in_backward][1] flag.
+    /// [1]: ../../../layer/struct.LayerConfig.html#structfield.recompute_in_backward
+    ///
+    /// A layer that computes in-place joins the segment of the previous layer.
+    fn init_recompute_segments(&mut self, layer_configs: &[LayerConfig]) {
+        self.checkpoint_segment_starts = vec![];
+        self.recomputed_segments = vec![];
+        if !layer_configs.iter().any(|layer_config| layer_config.recompute_in_backward) {
+            return;
+        }
+
+        let mut previous_recompute = None;
+        for (i, layer_config) in layer_configs.iter().enumerate() {
+            let recompute = if self.layers[i].borrow().is_using_in_place() {
+                previous_recompute.unwrap_or(false)
+            } else {
+                layer_config.recompute_in_backward
+            };
+            if previous_recompute != Some(recompute) {
+                self.checkpoint_segment_starts.push(i);
+                self.recomputed_segments.push(recompute);
+            }
+            previous_recompute = Some(recompute);
+        }
+        info!("Checkpoint segments start at layers {:?}, recomputed: {:?}",
+              self.checkpoint_segment_starts,
+              self.recomputed_segments);
+    }
+
+    /// Returns the range of layers of the checkpoint segment `segment`.
+    fn checkpoint_segment(&self, segment: usize) -> ::std::ops::Range<usize> {
+        let start = self.checkpoint_segment_starts[segment];
+        let end = self.checkpoint_segment_starts.get(segment + 1).cloned().unwrap_or(self.layers.len());
+        start..end
+    }
+
+    /// Binds the inputs of the container to the inputs of `layer` that consume them.
+    ///
+    /// Container inputs can be consumed by any layer, not only the first one.
+    fn bind_container_inputs(&self, layer: &RefCell<Layer<B>>, input_data: &[ArcLock<SharedTensor<f32>>]) {
+        let input_names = layer.borrow().input_blob_names().to_vec();
+        for (i, input_name) in input_names.iter().enumerate() {
+            if let Some(input_id) = self.input_tensor_names.iter().position(|name| name == input_name) {
+                layer.borrow_mut().input_blobs_data[i] = input_data[input_id].clone();
+            }
+        }
+    }
+
+    /// Releases the memory of the activations inside the checkpoint segment `segment`.
+    ///
+    /// Outputs that are used as inputs by later segments, like the output of the last layer
+    /// of the segment, and the outputs of the container are kept.
+    fn release_checkpoint_segment(&self, segment: usize) {
+        let layers = self.checkpoint_segment(segment);
+        let retained = self.layers[layers.end..]
+            .iter()
+            .flat_map(|layer| layer.borrow().input_blobs_data.clone())
+            .chain(self.output_data_tensors.iter().cloned())
+            .collect::<Vec<_>>();
+        for layer in &self.layers[layers] {
+            for output in &layer.borrow().output_blobs_data {
+                if retained.iter().any(|input| Arc::ptr_eq(input, output)) {
+                    continue;
+                }
+                // resizing a tensor drops its memory
+                let shape = output.read().unwrap().desc().clone();
+                output.write().unwrap().resize(&shape).unwrap();
+            }
+        }
     }
 
     /// Initialize a input tensor for the Sequential container.
@@ -182,6 +435,8 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
     /// output tensors it will also append anonymous output tensors that are required by the specific
     /// [Layer implemenations][4]. It also sets up the backpropagation flags.
     ///
+    /// Returns an error if the layer can not be created from its config.
+    ///
     /// [3]: ../layer/struct.Layer.html
     /// [4]: ../layers/index.html
     fn init_layer(&mut self,
@@ -192,19 +447,19 @@ impl<B: IBackend + LayerOps<f32> + 'static> Sequential<B> {
                                                 (ArcLock<SharedTensor<f32>>,
                                                  ArcLock<SharedTensor<f32>>,
                                                  Option<f32>,
-                                                 Option<f32>)>) {
+                                                 Option<f32>)>)
+                  -> Result<(), LayerError> {
         // Setup layer.
-        if let Err(e) = layer_config.validate() {
-            error!("{}", e);
-        }
-
         info!("Creating Layer {}", &layer_config.name);
-        let mut layer = Layer::from_config(backend, &layer_config);
+        let mut layer = try!(Layer::from_config(backend, &layer_config));
+        // the passes of the container synchronize once for all of its layers
+        layer.set_synchronize_passes(false);
 
         // Figure out this layer's input and output
         layer.connect(registry, weight_registry);
 
         self.layers.push(RefCell::new(layer));
+        Ok(())
     }
 }
 
@@ -244,6 +499,55 @@ impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for Sequential<B> {
         Some(names)
     }
 
+    fn non_learnable_weights(&self) -> Option<Vec<ArcLock<SharedTensor<f32>>>> {
+        let weights = self.layers.iter().flat_map(|layer| layer.borrow().non_learnable_weights_data()).collect();
+        Some(weights)
+    }
+
+    fn non_learnable_weights_names(&self) -> Option<Vec<String>> {
+        let names = self.layers.iter().flat_map(|layer| layer.borrow().non_learnable_weights_names()).collect();
+        Some(names)
+    }
+
+    fn awaits_input_shapes(&self) -> bool {
+        self.lazy_config.is_some()
+    }
+
+    /// Reshapes the contained layers in order to the shapes of the inputs, keeping their
+    /// weights, e.g. when the batch size of the inputs has changed.
+    fn reshape(&mut self,
+               backend: Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        for layer in &self.layers {
+            self.bind_container_inputs(layer, input_data);
+            layer.borrow_mut().reshape_to_inputs();
+        }
+    }
+
+    fn init_input_shapes(&mut self, backend: Rc<B>, input_shapes: &[Vec<usize>]) {
+        if let Some(mut config) = self.lazy_config.take() {
+            for (input, input_shape) in config.inputs.iter_mut().zip(input_shapes) {
+                input.1 = input_shape.clone();
+            }
+            if let Err(err) = self.init_layers(backend, &config) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    fn blob_data(&self, name: &str) -> Option<ArcLock<SharedTensor<f32>>> {
+        self.registry.get(name).map(|&(ref data, _)| data.clone())
+    }
+
+    fn sublayers(&self) -> Option<&[RefCell<Layer<B>>]> {
+        Some(&self.layers)
+    }
+
     fn resize_shared_workspace(&mut self,
                                backend: Rc<B>,
                                workspace: Option<ArcLock<SharedTensor<u8>>>)
@@ -258,21 +562,23 @@ impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for Sequential<B> {
         shared_workspace
     }
 
+    fn workspace_size(&self) -> usize {
+        self.layers.iter().map(|layer| layer.borrow().worker.workspace_size()).max().unwrap_or(0)
+    }
+
     fn forward(&self,
                backend: &B,
                input_data: &[ArcLock<SharedTensor<f32>>],
                weights_data: &[ArcLock<SharedTensor<f32>>],
                output_data: &mut [ArcLock<SharedTensor<f32>>]) {
         for layer in &self.layers {
-            for (i, (input, input_name)) in input_data.iter().zip(self.input_tensor_names.iter()).enumerate() {
-                if &layer.borrow().input_blob_names[i] == input_name {
-                    layer.borrow_mut().input_blobs_data[i] = input.clone();
-                }
-            }
+            self.bind_container_inputs(layer, input_data);
             layer.borrow_mut().forward(&[]);
         }
-        if let Some(last_layer) = self.layers.last() {
-            last_layer.borrow_mut().synchronize();
+        for segment in 0..self.checkpoint_segment_starts.len() {
+            if self.recomputed_segments[segment] {
+                self.release_checkpoint_segment(segment);
+            }
         }
     }
 
@@ -288,11 +594,36 @@ impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for Sequential<B> {
                 last_layer.borrow_mut().output_blobs_gradient[i] = output_gradient.clone();
             }
         }
-        for layer in self.layers.iter().rev() {
-            layer.borrow_mut().backward_input(&[]);
+        // the gradients of blobs consumed by several layers are accumulated
+        for &(_, ref blob_gradient) in self.fan_out_gradients.iter().flat_map(|gradients| gradients) {
+            backend.fill(&mut blob_gradient.write().unwrap(), 0f32).unwrap();
         }
-        if let Some(first_layer) = self.layers.iter().rev().last() {
-            first_layer.borrow_mut().synchronize();
+        if self.checkpoint_segment_starts.is_empty() {
+            for layer_id in (0..self.layers.len()).rev() {
+                self.backward_layer_input(backend, layer_id);
+            }
+        } else {
+            // With checkpointing the parameter gradients are computed here as well,
+            // while the recomputed activations of a segment are available.
+            for segment in (0..self.checkpoint_segment_starts.len()).rev() {
+                let layers = self.checkpoint_segment(segment);
+                let recomputed = self.recomputed_segments[segment];
+                if recomputed {
+                    for layer in &self.layers[layers.clone()] {
+                        let mut layer = layer.borrow_mut();
+                        layer.set_recomputing(true);
+                        layer.forward(&[]);
+                        layer.set_recomputing(false);
+                    }
+                }
+                for layer_id in layers.rev() {
+                    self.backward_layer_input(backend, layer_id);
+                    self.backward_layer_parameters(layer_id);
+                }
+                if recomputed {
+                    self.release_checkpoint_segment(segment);
+                }
+            }
         }
     }
 
@@ -302,11 +633,12 @@ impl<B: IBackend + LayerOps<f32> + 'static> ILayer<B> for Sequential<B> {
                            output_gradients: &[ArcLock<SharedTensor<f32>>],
                            input_data: &[ArcLock<SharedTensor<f32>>],
                            weights_gradients: &mut [ArcLock<SharedTensor<f32>>]) {
-        for layer in self.layers.iter().rev() {
-            layer.borrow_mut().backward_parameters();
+        // with checkpointing the parameter gradients have already been computed in `backward_input`
+        if !self.checkpoint_segment_starts.is_empty() {
+            return;
         }
-        if let Some(first_layer) = self.layers.iter().rev().last() {
-            first_layer.borrow_mut().synchronize();
+        for layer_id in (0..self.layers.len()).rev() {
+            self.backward_layer_parameters(layer_id);
         }
     }
 }
@@ -344,7 +676,7 @@ impl<B: IBackend + LayerOps<f32> + 'static> ComputeParametersGradient<f32, B> fo
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(missing_copy_implementations)]
 /// Specifies configuration parameters for a Sequential Layer.
 pub struct SequentialConfig {
@@ -369,6 +701,49 @@ pub struct SequentialConfig {
     ///
     /// Default: `false`
     pub force_backward: bool,
+
+    /// Divides the layers into this many segments of roughly equal length for
+    /// gradient checkpointing.
+    ///
+    /// Only the activations at the boundaries of the segments are kept after the
+    /// forward pass. The activations inside a segment are recomputed from its
+    /// boundary during the backward pass, which trades computation for memory.
+    /// The activations of the last segment are always kept.
+    ///
+    /// With checkpointing the parameter gradients are computed together with the
+    /// input gradients, and intermediate blobs can not be read after a forward pass.
+    ///
+    /// Default: `None`
+    pub checkpoint_segments: Option<usize>,
+
+    /// Defines if the [total loss][1] of the container is accumulated in `f64`.
+    /// [1]: ../../../layer/struct.Layer.html#method.total_loss
+    ///
+    /// The result is still returned as `f32`, but summing many loss values in `f64`
+    /// loses less precision.
+    ///
+    /// Default: `false`
+    pub accumulate_loss_in_f64: bool,
+
+    /// The default fillers of the weights of the contained layers, for weights
+    /// that don't specify a filler in their [WeightConfig][1].
+    /// [1]: ../../../weight/struct.WeightConfig.html
+    ///
+    /// Contained Sequential layers without InitDefaults of their own use these.
+    /// The InitDefaults are not serialized, as the weights are saved with the Layer.
+    ///
+    /// Default: `None`
+    pub init_defaults: Option<InitDefaults>,
+
+    /// The maximum memory the container may use in bytes.
+    ///
+    /// If the [estimated memory][1] of the container exceeds the budget, creating
+    /// it [fails][2] before the memory of the activations is allocated.
+    /// [1]: ../../../layer/struct.Layer.html#method.estimated_memory
+    /// [2]: ../../../layer/struct.Layer.html#method.from_config
+    ///
+    /// Default: `None`
+    pub memory_budget_bytes: Option<usize>,
 }
 
 impl SequentialConfig {
@@ -399,7 +774,37 @@ impl SequentialConfig {
         self.layers.push(layer);
     }
 
+    /// Checks that the inputs have distinct names and non-empty shapes, that the number of
+    /// checkpoint segments is positive and that the default fillers are valid.
+    ///
+    /// The contained layers are validated when they are created.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for &(ref name, ref shape) in &self.inputs {
+            if !names.insert(name) {
+                return Err(format!("inputs: the input '{}' is declared more than once", name));
+            }
+            if shape.contains(&0) {
+                return Err(format!("inputs: the shape {:?} of the input '{}' contains a zero dimension",
+                                   shape,
+                                   name));
+            }
+        }
+        if self.checkpoint_segments == Some(0) {
+            return Err("checkpoint_segments must be positive, but is 0".to_owned());
+        }
+        if let Some(ref defaults) = self.init_defaults {
+            try!(defaults.validate().map_err(|message| format!("init_defaults: {}", message)));
+        }
+        Ok(())
+    }
+
     /// Add a input to the network.
+    ///
+    /// The first input feeds the first layer. Any layer can consume an input by
+    /// [naming it as input][1], e.g. a loss layer its labels. Inputs that no layer
+    /// names are fed to the first layer as well.
+    /// [1]: ../../../layer/struct.LayerConfig.html#method.add_input
     pub fn add_input(&mut self, input_name: &str, shape: &[usize]) {
         self.inputs.push((input_name.to_owned(), shape.to_owned()));
     }
@@ -437,6 +842,9 @@ impl<'a> CapnpWrite<'a> for SequentialConfig {
             }
         }
         builder.set_force_backward(self.force_backward);
+        builder.set_checkpoint_segments(self.checkpoint_segments.unwrap_or(0) as u64);
+        builder.set_accumulate_loss_in_f64(self.accumulate_loss_in_f64);
+        builder.set_memory_budget_bytes(self.memory_budget_bytes.unwrap_or(0) as u64);
     }
 }
 
@@ -465,11 +873,24 @@ impl<'a> CapnpRead<'a> for SequentialConfig {
             inputs.push((name, shape))
         }
         let force_backward = reader.get_force_backward();
+        let checkpoint_segments = match reader.get_checkpoint_segments() {
+            0 => None,
+            segments => Some(segments as usize),
+        };
+        let accumulate_loss_in_f64 = reader.get_accumulate_loss_in_f64();
+        let memory_budget_bytes = match reader.get_memory_budget_bytes() {
+            0 => None,
+            bytes => Some(bytes as usize),
+        };
 
         SequentialConfig {
             layers: layers,
             inputs: inputs,
             force_backward: force_backward,
+            checkpoint_segments: checkpoint_segments,
+            accumulate_loss_in_f64: accumulate_loss_in_f64,
+            init_defaults: None,
+            memory_budget_bytes: memory_budget_bytes,
         }
     }
 }
@@ -486,6 +907,697 @@ impl ::std::default::Default for SequentialConfig {
             layers: vec![],
             inputs: vec![],
             force_backward: false,
+            checkpoint_segments: None,
+            accumulate_loss_in_f64: false,
+            init_defaults: None,
+            memory_budget_bytes: None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use transfer;
+    use util::{ArcLock, native_backend, write_to_memory};
+    use util::testing::{read, tensor};
+    use weight::{FillerType, InitDefaults, WeightConfig};
+
+    #[test]
+    fn lazy_init_infers_weight_shapes() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+
+        let mut network = Layer::from_config_lazy(Rc::new(native_backend()),
+                                                  &LayerConfig::new("network", cfg)).unwrap();
+        assert!(network.learnable_weights_data().is_empty());
+
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&[2, 4]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[1f32; 8]);
+        let outputs = network.forward(&[Arc::new(RwLock::new(input))]);
+
+        let weights = network.learnable_weights_data();
+        assert_eq!(1, weights.len());
+        assert_eq!(&vec![3, 4], weights[0].read().unwrap().desc());
+        assert_eq!(&vec![2, 3], outputs[0].read().unwrap().desc());
+    }
+
+    #[test]
+    fn read_blob_into_checks_buffer_length() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&[2, 4]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[1f32; 8]);
+        network.forward(&[Arc::new(RwLock::new(input))]);
+
+        let mut buffer = [0f32; 6];
+        assert!(network.read_blob_into(&native, "SEQUENTIAL_OUTPUT_0", &mut buffer).is_ok());
+        let mut short_buffer = [0f32; 5];
+        let error = network.read_blob_into(&native, "SEQUENTIAL_OUTPUT_0", &mut short_buffer).unwrap_err();
+        assert!(error.contains("length 5"));
+        assert!(network.read_blob_into(&native, "missing", &mut buffer).is_err());
+    }
+
+    #[test]
+    fn unknown_blob_name_lists_available_blobs() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        assert!(network.try_blob_data("data").is_ok());
+        let error = network.try_blob_data("dtaa").unwrap_err();
+        assert!(error.contains("no blob named dtaa"), "{}", error);
+        assert!(error.contains("available blobs: SEQUENTIAL_OUTPUT_0, data"), "{}", error);
+    }
+
+    #[test]
+    fn layers_can_be_inspected() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        assert_eq!("Sequential", network.layer_type());
+        let layer_types = network.layers().map(|layer| layer.borrow().layer_type().to_owned()).collect::<Vec<_>>();
+        assert_eq!(vec!["Linear", "Sigmoid"], layer_types);
+
+        let weights = network.weight_blobs();
+        assert_eq!(1, weights.len());
+        assert_eq!("linear-0", weights[0].0);
+        assert_eq!(&vec![3, 4], weights[0].1.read().unwrap().desc());
+        assert_eq!(&vec![3, 4], weights[0].2.read().unwrap().desc());
+    }
+
+    #[test]
+    fn weight_blobs_are_shared_with_the_network() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 2 }));
+        cfg.add_layer(LayerConfig::new("out", LinearConfig { output_size: 1 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let weights = network.weight_blobs();
+        assert_eq!(network.learnable_weights_names(),
+                   weights.iter().map(|&(ref name, _, _)| name.clone()).collect::<Vec<_>>());
+        assert_eq!(network.learnable_weights_data().len(), weights.len());
+
+        // an external optimizer writes the weights through the returned tensors
+        let native = native_backend();
+        write_to_memory(weights[0].1.write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, 0.0, 0.0, 1.0]);
+        write_to_memory(weights[1].1.write().unwrap().write_only(native.device()).unwrap(),
+                        &[2f32, -1.0]);
+        let output = network.forward(&[tensor(&[1, 2], &[3.0, 5.0])]);
+        assert_eq!(vec![1f32], read(&output[0]));
+
+        // and reads the gradients the network has computed
+        network.clear_weights_gradients();
+        network.backward(&[tensor(&[1, 1], &[1.0])]);
+        assert_eq!(vec![3f32, 5.0], read(&weights[1].2));
+        assert_eq!(vec![6f32, 10.0, -3.0, -5.0], read(&weights[0].2));
+    }
+
+    #[test]
+    fn to_dot_contains_layers_and_blob_connections() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        let mut features = LayerConfig::new("features", LinearConfig { output_size: 3 });
+        features.add_output("features");
+        cfg.add_layer(features);
+        let mut left = LayerConfig::new("left", LinearConfig { output_size: 3 });
+        left.add_input("features");
+        left.add_output("left_out");
+        cfg.add_layer(left);
+        // the loss compares the branch with the features it has been computed from
+        let mut loss = LayerConfig::new("loss", SmoothL1LossConfig::default());
+        loss.add_input("left_out");
+        loss.add_input("features");
+        cfg.add_layer(loss);
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let dot = network.to_dot();
+        assert!(dot.starts_with("digraph \"network\" {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(3, dot.matches("shape=box").count());
+        for layer in &["features\\nLinear", "left\\nLinear", "loss\\nSmoothL1Loss"] {
+            assert!(dot.contains(layer), "{} is missing in {}", layer, dot);
+        }
+        for edge in &["\"input:data\" -> \"layer:features\" [label=\"data [2, 4]\"]",
+                      "\"layer:features\" -> \"layer:left\" [label=\"features [2, 3]\"]",
+                      "\"layer:left\" -> \"layer:loss\" [label=\"left_out [2, 3]\"]",
+                      "\"layer:features\" -> \"layer:loss\" [label=\"features [2, 3]\"]",
+                      "\"layer:loss\" -> \"output:SEQUENTIAL_OUTPUT_2\" [label=\"SEQUENTIAL_OUTPUT_2 [1]\"]"] {
+            assert!(dot.contains(edge), "{} is missing in {}", edge, dot);
+        }
+        assert_eq!(5, dot.matches(" -> ").count());
+    }
+
+    #[test]
+    fn init_defaults_fill_weights_without_filler() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("first", LinearConfig { output_size: 3 }));
+        let mut last = LayerConfig::new("last", LinearConfig { output_size: 2 });
+        last.params.push(WeightConfig { filler: Some(FillerType::Constant { value: -1f32 }), ..WeightConfig::default() });
+        cfg.add_layer(last);
+        cfg.init_defaults = Some(InitDefaults::new().with_filler("Linear", 0, FillerType::Constant { value: 0.5f32 }));
+
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let weights = network.learnable_weights_data();
+        // the default of the layer type replaces the Glorot initialization of Linear
+        assert_eq!(vec![0.5f32; 12], read(&weights[0]));
+        // the filler of the weight wins over the default
+        assert_eq!(vec![-1f32; 6], read(&weights[1]));
+    }
+
+    #[test]
+    fn estimated_memory_counts_blobs_once() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("output", LinearConfig { output_size: 2 }));
+
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let estimate = network.estimated_memory();
+        // ReLU computes in-place, so its blobs are the output blobs of linear
+        assert_eq!((8 + 6 + 4) * 4, estimate.activations);
+        assert_eq!((8 + 6 + 4 + 12 + 6) * 4, estimate.gradients);
+        assert_eq!((12 + 6) * 4, estimate.weights);
+        assert_eq!(0, estimate.workspace);
+        assert_eq!(10, estimate.blobs.len());
+        assert_eq!(estimate.activations + estimate.gradients + estimate.weights, estimate.total());
+    }
+
+    #[test]
+    fn exceeding_memory_budget_fails() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[64, 256]);
+        cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 512 }));
+        cfg.add_layer(LayerConfig::new("output", LinearConfig { output_size: 1024 }));
+        cfg.memory_budget_bytes = Some(1024 * 1024);
+
+        let err = match Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg.clone())) {
+            Err(LayerError::MemoryBudgetExceeded(err)) => err,
+            other => panic!("Expected the memory budget to be exceeded, got {:?}", other.err()),
+        };
+        assert_eq!(1024 * 1024, err.budget);
+        let largest = err.largest_blobs();
+        assert_eq!(5, largest.len());
+        assert_eq!(("output-0".to_owned(), 512 * 1024 * 4), largest[0]);
+        assert!(largest.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(format!("{}", err).contains("output-0"));
+
+        cfg.memory_budget_bytes = Some(err.estimate.total());
+        assert!(Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).is_ok());
+    }
+
+    fn nine_layer_network(checkpoint_segments: Option<usize>) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        for i in 0..4 {
+            cfg.add_layer(LayerConfig::new(&format!("linear{}", i), LinearConfig { output_size: 3 }));
+            cfg.add_layer(LayerConfig::new(&format!("sigmoid{}", i), LayerType::Sigmoid));
+        }
+        cfg.add_layer(LayerConfig::new("linear_out", LinearConfig { output_size: 2 }));
+        cfg.checkpoint_segments = checkpoint_segments;
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        // replace the random initialization, so both networks have the same weights
+        let native = native_backend();
+        for (i, weight) in network.learnable_weights_data().iter().enumerate() {
+            let size = weight.read().unwrap().desc().size();
+            let values = (0..size).map(|j| ((i * 7 + j) % 5) as f32 * 0.2 - 0.4).collect::<Vec<_>>();
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &values);
+        }
+        network
+    }
+
+    /// Returns the number of retained activations after the forward pass
+    /// and the weight and input gradients after the backward pass.
+    fn checkpointed_backward(checkpoint_segments: Option<usize>) -> (usize, Vec<Vec<f32>>) {
+        let native = native_backend();
+        let mut network = nine_layer_network(checkpoint_segments);
+        let mut input = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[0.5f32, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        network.forward(&[Arc::new(RwLock::new(input))]);
+
+        let retained = network.layers()
+            .flat_map(|layer| layer.borrow().output_blobs_data.clone())
+            .filter(|output| output.read().unwrap().read(native.device()).is_ok())
+            .count();
+
+        let mut output_gradient = SharedTensor::<f32>::new(&[2, 2]);
+        write_to_memory(output_gradient.write_only(native.device()).unwrap(), &[1.0f32, -0.5, 0.25, 2.0]);
+        let input_gradients = network.backward(&[Arc::new(RwLock::new(output_gradient))]);
+
+        let mut gradients = network.learnable_weights_gradients().iter().map(read).collect::<Vec<_>>();
+        gradients.push(read(&input_gradients[0]));
+        (retained, gradients)
+    }
+
+    #[test]
+    fn checkpoint_segments_match_full_backward() {
+        let (full_retained, full_gradients) = checkpointed_backward(None);
+        let (retained, gradients) = checkpointed_backward(Some(3));
+
+        assert_eq!(9, full_retained);
+        assert!(retained < full_retained, "{} activations retained", retained);
+        assert_eq!(full_gradients.len(), gradients.len());
+        for (full, checkpointed) in full_gradients.iter().zip(gradients.iter()) {
+            for (f, c) in full.iter().zip(checkpointed.iter()) {
+                assert!((f - c).abs() < 1e-6, "{} != {}", f, c);
+            }
+        }
+    }
+
+    /// Returns the bytes of the distinct output blobs that hold data after the forward pass
+    /// and the weight and input gradients after the backward pass of a chain of 30 layers.
+    fn recomputed_chain_backward(recompute: bool) -> (usize, Vec<Vec<f32>>) {
+        let native = native_backend();
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        for i in 0..15 {
+            let mut linear = LayerConfig::new(&format!("linear{}", i), LinearConfig { output_size: 3 });
+            // every fifth linear layer and its sigmoid keep their activations
+            linear.recompute_in_backward = recompute && i % 5 != 0;
+            cfg.add_layer(linear);
+            cfg.add_layer(LayerConfig::new(&format!("sigmoid{}", i), LayerType::Sigmoid));
+        }
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        for (i, weight) in network.learnable_weights_data().iter().enumerate() {
+            let size = weight.read().unwrap().desc().size();
+            let values = (0..size).map(|j| ((i * 7 + j) % 5) as f32 * 0.2 - 0.4).collect::<Vec<_>>();
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &values);
         }
+
+        let mut input = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[0.5f32, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        network.forward(&[Arc::new(RwLock::new(input))]);
+
+        let mut outputs: Vec<ArcLock<SharedTensor<f32>>> = Vec::new();
+        for output in network.layers().flat_map(|layer| layer.borrow().output_blobs_data.clone()) {
+            if !outputs.iter().any(|known| Arc::ptr_eq(known, &output)) {
+                outputs.push(output);
+            }
+        }
+        let activation_bytes = outputs.iter()
+            .filter(|output| output.read().unwrap().read(native.device()).is_ok())
+            .fold(0, |bytes, output| bytes + output.read().unwrap().desc().size() * 4);
+
+        let mut output_gradient = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(output_gradient.write_only(native.device()).unwrap(), &[1.0f32, -0.5, 0.25, 2.0, 0.0, -1.0]);
+        let input_gradients = network.backward(&[Arc::new(RwLock::new(output_gradient))]);
+
+        let mut gradients = network.learnable_weights_gradients().iter().map(read).collect::<Vec<_>>();
+        gradients.push(read(&input_gradients[0]));
+        (activation_bytes, gradients)
+    }
+
+    #[test]
+    fn recompute_in_backward_matches_full_backward() {
+        let (full_bytes, full_gradients) = recomputed_chain_backward(false);
+        let (bytes, gradients) = recomputed_chain_backward(true);
+
+        // 15 linear outputs shared with the in-place sigmoids and the output of the last sigmoid
+        assert_eq!(16 * 6 * 4, full_bytes);
+        // the outputs of linear1-3, 6-8 and 11-13 are released
+        assert_eq!(7 * 6 * 4, bytes);
+        assert_eq!(full_gradients.len(), gradients.len());
+        for (full, recomputed) in full_gradients.iter().zip(gradients.iter()) {
+            for (f, r) in full.iter().zip(recomputed.iter()) {
+                assert!((f - r).abs() < 1e-6, "{} != {}", f, r);
+            }
+        }
+    }
+
+    /// Returns a network whose inputs feed layers at different depths: the data feeds the
+    /// linear layer, the labels the contrastive loss and the target the smooth L1 loss.
+    fn three_input_network() -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_input("label", &[1, 1]);
+        cfg.add_input("target", &[1]);
+        let mut linear = LayerConfig::new("linear", LinearConfig { output_size: 2 });
+        linear.add_output("embedding");
+        cfg.add_layer(linear);
+        let mut contrastive = LayerConfig::new("contrastive", ContrastiveLossConfig::default());
+        contrastive.add_input("embedding");
+        contrastive.add_input("data");
+        contrastive.add_input("label");
+        contrastive.add_output("distance");
+        cfg.add_layer(contrastive);
+        let mut smooth_l1 = LayerConfig::new("smooth_l1", SmoothL1LossConfig::default());
+        smooth_l1.add_input("distance");
+        smooth_l1.add_input("target");
+        cfg.add_layer(smooth_l1);
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, 0.0, 0.0, 1.0]);
+        network
+    }
+
+    fn named_inputs(label: f32) -> HashMap<String, ArcLock<SharedTensor<f32>>> {
+        let mut inputs = HashMap::new();
+        inputs.insert("data".to_owned(), tensor(&[1, 2], &[0.3, -0.2]));
+        inputs.insert("label".to_owned(), tensor(&[1, 1], &[label]));
+        inputs.insert("target".to_owned(), tensor(&[1], &[0.2]));
+        inputs
+    }
+
+    #[test]
+    fn inputs_feed_layers_at_different_depths() {
+        let mut network = three_input_network();
+        assert_eq!(vec!["data", "label", "target"], network.input_names());
+        let input_names = network.layers().map(|layer| layer.borrow().input_blob_names().to_vec()).collect::<Vec<_>>();
+        assert_eq!(vec!["data"], input_names[0]);
+        assert_eq!(vec!["embedding", "data", "label"], input_names[1]);
+        assert_eq!(vec!["distance", "target"], input_names[2]);
+
+        // the embedding equals the data, so dissimilar pairs have a contrastive loss of 1,
+        // whose smooth L1 loss to the target of 0.2 is 0.5 * 0.8^2
+        let outputs = network.forward_named(&named_inputs(0f32)).unwrap();
+        assert!((read(&outputs[0])[0] - 0.5 * 0.8 * 0.8).abs() < 1e-6);
+        // and similar pairs a contrastive loss of 0, whose smooth L1 loss is 0.5 * 0.2^2
+        let outputs = network.forward_named(&named_inputs(1f32)).unwrap();
+        assert!((read(&outputs[0])[0] - 0.5 * 0.2 * 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn named_inputs_are_validated() {
+        let mut network = three_input_network();
+        let mut missing = named_inputs(0f32);
+        missing.remove("target");
+        assert!(network.forward_named(&missing).is_err());
+
+        let mut unused = named_inputs(0f32);
+        unused.insert("mask".to_owned(), tensor(&[1, 2], &[1.0, 1.0]));
+        assert!(network.forward_named(&unused).is_err());
+    }
+
+    /// Returns a network whose input is consumed by a linear layer and, as the positive, by a
+    /// triplet loss that takes the output of the linear layer as both anchor and negative.
+    fn branching_network() -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        let mut linear = LayerConfig::new("linear", LinearConfig { output_size: 2 });
+        linear.add_output("embedding");
+        cfg.add_layer(linear);
+        let mut loss = LayerConfig::new("loss", TripletLossConfig::default());
+        loss.add_input("embedding");
+        loss.add_input("data");
+        loss.add_input("embedding");
+        cfg.add_layer(loss);
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[0.5f32, -0.3, 0.8, 0.2]);
+        network
+    }
+
+    fn branching_loss(network: &mut Layer<Backend<Native>>, data: &[f32]) -> f32 {
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&[1, 2]);
+        write_to_memory(input.write_only(native.device()).unwrap(), data);
+        network.forward(&[Arc::new(RwLock::new(input))]);
+        network.total_loss()
+    }
+
+    #[test]
+    fn gradients_of_blob_with_several_consumers_are_summed() {
+        let data = [0.7f32, -0.4];
+        let mut network = branching_network();
+        branching_loss(&mut network, &data);
+        let input_gradients = network.backward(&[]);
+        let gradient = read(&input_gradients[0]);
+
+        // the loss is quadratic in the input, so central differences are exact
+        let epsilon = 0.01f32;
+        for i in 0..2 {
+            let mut plus = data.to_vec();
+            plus[i] += epsilon;
+            let mut minus = data.to_vec();
+            minus[i] -= epsilon;
+            let expected = (branching_loss(&mut network, &plus) - branching_loss(&mut network, &minus)) /
+                           (2f32 * epsilon);
+            assert!((gradient[i] - expected).abs() < 1e-3,
+                    "{} != {}",
+                    gradient[i],
+                    expected);
+        }
+    }
+
+    #[test]
+    fn in_place_consumer_of_a_shared_blob_is_rejected() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2]);
+        let mut linear = LayerConfig::new("linear", LinearConfig { output_size: 2 });
+        linear.add_output("embedding");
+        cfg.add_layer(linear);
+        let mut projection = LayerConfig::new("projection", LinearConfig { output_size: 2 });
+        projection.add_input("embedding");
+        cfg.add_layer(projection);
+        // the ReLU would overwrite the embedding that the projection consumes as well
+        let mut relu = LayerConfig::new("relu", LayerType::ReLU);
+        relu.add_input("embedding");
+        relu.add_output("embedding");
+        cfg.add_layer(relu);
+        let mut loss = LayerConfig::new("loss", TripletLossConfig::default());
+        loss.add_input("embedding");
+        loss.add_input("data");
+        loss.add_input("embedding");
+        cfg.add_layer(loss);
+
+        match Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)) {
+            Err(LayerError::InvalidConfig { ref layer, .. }) => assert_eq!("relu", layer),
+            _ => panic!("expected an InvalidConfig error"),
+        }
+    }
+
+    /// Returns the number of distinct output blobs, the input gradient and the
+    /// weight gradients of a chain of 20 activations.
+    fn activation_chain_backward(in_place: bool) -> (usize, Vec<Vec<f32>>) {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("linear_in", LinearConfig { output_size: 3 }));
+        for i in 0..20 {
+            let layer_type = match i % 3 {
+                0 => LayerType::ReLU,
+                1 => LayerType::Sigmoid,
+                _ => LayerType::TanH,
+            };
+            cfg.add_layer(LayerConfig::new(&format!("activation{}", i), layer_type));
+        }
+        cfg.add_layer(LayerConfig::new("linear_out", LinearConfig { output_size: 2 }));
+        if !in_place {
+            let num_layers = cfg.layers.len();
+            for i in 0..num_layers - 1 {
+                cfg.layers[i].add_output(&format!("blob{}", i));
+                cfg.layers[i + 1].add_input(&format!("blob{}", i));
+            }
+        }
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let native = native_backend();
+        for (i, weight) in network.learnable_weights_data().iter().enumerate() {
+            let size = weight.read().unwrap().desc().size();
+            let values = (0..size).map(|j| ((i * 7 + j) % 5) as f32 * 0.2 - 0.4).collect::<Vec<_>>();
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &values);
+        }
+        let mut input = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(input.write_only(native.device()).unwrap(), &[0.5f32, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        network.forward(&[Arc::new(RwLock::new(input))]);
+
+        let mut blobs: Vec<ArcLock<SharedTensor<f32>>> = Vec::new();
+        for output in network.layers().flat_map(|layer| layer.borrow().output_blobs_data.clone()) {
+            if !blobs.iter().any(|blob| Arc::ptr_eq(blob, &output)) {
+                blobs.push(output);
+            }
+        }
+
+        let mut output_gradient = SharedTensor::<f32>::new(&[2, 2]);
+        write_to_memory(output_gradient.write_only(native.device()).unwrap(), &[1.0f32, -0.5, 0.25, 2.0]);
+        let input_gradients = network.backward(&[Arc::new(RwLock::new(output_gradient))]);
+
+        let mut gradients = network.learnable_weights_gradients().iter().map(read).collect::<Vec<_>>();
+        gradients.push(read(&input_gradients[0]));
+        (blobs.len(), gradients)
+    }
+
+    #[test]
+    fn in_place_activations_match_out_of_place() {
+        let (out_of_place_blobs, out_of_place_gradients) = activation_chain_backward(false);
+        let (in_place_blobs, in_place_gradients) = activation_chain_backward(true);
+
+        assert_eq!(22, out_of_place_blobs);
+        assert_eq!(2, in_place_blobs);
+        assert_eq!(out_of_place_gradients.len(), in_place_gradients.len());
+        for (out_of_place, in_place) in out_of_place_gradients.iter().zip(in_place_gradients.iter()) {
+            for (o, i) in out_of_place.iter().zip(in_place.iter()) {
+                assert!((o - i).abs() < 1e-6, "{} != {}", o, i);
+            }
+        }
+    }
+
+    /// Returns a network with an augmentation branch that receives no gradient and a shared
+    /// embedding that feeds the triplet loss as positive and, through a layer that doesn't
+    /// propagate down, as anchor.
+    fn augmented_triplet_network(force_backward: bool) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.force_backward = force_backward;
+        cfg.add_input("data", &[1, 2]);
+        cfg.add_input("negative", &[1, 2]);
+        let mut augment = LayerConfig::new("augment", LayerType::Sigmoid);
+        augment.add_input("data");
+        augment.add_output("augmented");
+        cfg.add_layer(augment);
+        let mut shared = LayerConfig::new("shared", LinearConfig { output_size: 2 });
+        shared.add_input("augmented");
+        shared.add_output("features");
+        shared.propagate_down = vec![false];
+        cfg.add_layer(shared);
+        let mut stop = LayerConfig::new("stop", LayerType::Sigmoid);
+        stop.add_input("features");
+        stop.add_output("stopped");
+        stop.propagate_down = vec![false];
+        cfg.add_layer(stop);
+        let mut loss = LayerConfig::new("loss", TripletLossConfig { margin: 10f32 });
+        loss.add_input("stopped");
+        loss.add_input("features");
+        loss.add_input("negative");
+        cfg.add_layer(loss);
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[0.5f32, -0.3, 0.8, 0.2]);
+        network
+    }
+
+    fn plan_entry(plan: &[LayerBackpropInfo], name: &str) -> LayerBackpropInfo {
+        plan.iter().find(|info| info.name == name).unwrap().clone()
+    }
+
+    #[test]
+    fn backprop_plan_skips_branches_without_gradient() {
+        let mut network = augmented_triplet_network(false);
+        let plan = network.backprop_plan();
+        assert_eq!(vec!["augment", "shared", "stop", "loss"],
+                   plan.iter().map(|info| info.name.as_str()).collect::<Vec<_>>());
+
+        // the augmentation only feeds a layer that doesn't propagate down
+        let augment = plan_entry(&plan, "augment");
+        assert_eq!(BackpropReason::NotPropagated, augment.reason);
+        assert!(!augment.backward_input && !augment.backward_parameters);
+        // the features still receive the gradient of the positive, although the anchor
+        // path doesn't propagate down to them
+        let shared = plan_entry(&plan, "shared");
+        assert_eq!(BackpropReason::UnderLoss, shared.reason);
+        assert!(!shared.backward_input && shared.backward_parameters);
+        let stop = plan_entry(&plan, "stop");
+        assert_eq!(BackpropReason::UnderLoss, stop.reason);
+        assert!(!stop.backward_input && !stop.backward_parameters);
+        let loss = plan_entry(&plan, "loss");
+        assert_eq!(vec![true, true, true], loss.input_needs_backward);
+
+        // the weight gradient only contains the path through the positive
+        network.forward(&[tensor(&[1, 2], &[0.4, -1.2]), tensor(&[1, 2], &[3.0, -2.0])]);
+        network.backward(&[]);
+        let augmented = read(&network.try_blob_data("augmented").unwrap());
+        let features = read(&network.try_blob_data("features").unwrap());
+        let stopped = read(&network.try_blob_data("stopped").unwrap());
+        let gradient = read(&network.learnable_weights_gradients()[0]);
+        for i in 0..2 {
+            for j in 0..2 {
+                let expected = 2f32 * (features[i] - stopped[i]) * augmented[j];
+                assert!((gradient[i * 2 + j] - expected).abs() < 1e-5,
+                        "{} != {}",
+                        gradient[i * 2 + j],
+                        expected);
+            }
+        }
+    }
+
+    #[test]
+    fn backprop_plan_of_frozen_branch() {
+        let mut network = augmented_triplet_network(false);
+        network.layers().nth(1).unwrap().borrow_mut().set_frozen(true);
+        let plan = network.backprop_plan();
+
+        let shared = plan_entry(&plan, "shared");
+        assert_eq!(BackpropReason::UnderLoss, shared.reason);
+        assert!(!shared.backward_input && !shared.backward_parameters);
+
+        // the weight gradient is left untouched
+        let native = native_backend();
+        let gradient = network.learnable_weights_gradients()[0].clone();
+        write_to_memory(gradient.write().unwrap().write_only(native.device()).unwrap(), &[7f32; 4]);
+        network.forward(&[tensor(&[1, 2], &[0.4, -1.2]), tensor(&[1, 2], &[3.0, -2.0])]);
+        network.backward(&[]);
+        assert_eq!(vec![7f32; 4], read(&gradient));
+    }
+
+    #[test]
+    fn backprop_plan_with_force_backward() {
+        let network = augmented_triplet_network(true);
+        let plan = network.backprop_plan();
+
+        let augment = plan_entry(&plan, "augment");
+        assert_eq!(BackpropReason::Forced, augment.reason);
+        assert!(augment.backward_input);
+        assert_eq!(vec![true], augment.input_needs_backward);
+        // propagate_down is still respected
+        let shared = plan_entry(&plan, "shared");
+        assert_eq!(BackpropReason::UnderLoss, shared.reason);
+        assert_eq!(vec![false], shared.input_needs_backward);
+        assert!(!shared.backward_input && shared.backward_parameters);
+    }
+
+    #[test]
+    fn nested_layers_synchronize_once_per_pass() {
+        let mut block = SequentialConfig::default();
+        block.add_input("block_in", &[2, 3]);
+        block.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        block.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 3]);
+        cfg.add_layer(LayerConfig::new("block", block));
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let input = tensor(&[2, 3], &[0.5, -1.0, 2.0, 0.1, 0.3, -0.7]);
+        let output_gradient = tensor(&[2, 2], &[1.0, -0.5, 0.25, 2.0]);
+
+        transfer::start_recording();
+        network.forward(&[input.clone()]);
+        assert_eq!(1, transfer::finish_recording().unwrap().device_synchronizations);
+
+        transfer::start_recording();
+        network.backward(&[output_gradient.clone()]);
+        assert_eq!(1, transfer::finish_recording().unwrap().device_synchronizations);
+
+        transfer::start_recording();
+        network.backward_input(&[output_gradient]);
+        network.backward_parameters();
+        assert_eq!(2, transfer::finish_recording().unwrap().device_synchronizations);
     }
 }
@@ -0,0 +1,265 @@
+//! Provides the registration of user-defined layers.
+//!
+//! Layers that are not part of Juice can be used by registering a factory for them
+//! under a name with [register_layer_factory][1]. A [LayerConfig][2] with a
+//! [CustomConfig][3] of the same name will then create the layer through that factory.
+//!
+//! The [CustomConfig][3] carries an opaque payload, that the factory can use to store
+//! the configuration of the layer. It is serialized together with the rest of the
+//! network, so custom layers can be saved and loaded like any other layer, as long as
+//! the factory is registered before loading.
+//!
+//! Factories are registered per thread and per backend type.
+//!
+//! [1]: ./fn.register_layer_factory.html
+//! [2]: ../../layer/struct.LayerConfig.html
+//! [3]: ./struct.CustomConfig.html
+
+use capnp_util::*;
+use co::IBackend;
+use layer::*;
+use juice_capnp::custom_config as capnp_config;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Creates the implementation of a custom layer from its LayerConfig.
+pub type LayerFactory<B> = Box<Fn(&LayerConfig) -> Box<ILayer<B>>>;
+
+thread_local! {
+    static LAYER_FACTORIES: RefCell<HashMap<(TypeId, String), Box<Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Register a factory for the custom layer type `name` on the backend `B`.
+///
+/// Replaces a factory that has previously been registered under the same name.
+pub fn register_layer_factory<B: IBackend + 'static>(name: &str, factory: LayerFactory<B>) {
+    LAYER_FACTORIES.with(|factories| {
+        factories.borrow_mut().insert((TypeId::of::<B>(), name.to_owned()), Box::new(factory));
+    });
+}
+
+/// Create a custom layer with the factory registered for its type.
+///
+/// Returns `None` if no factory has been registered for the type on the backend `B`.
+pub fn create_layer<B: IBackend + 'static>(config: &LayerConfig, custom_config: &CustomConfig) -> Option<Box<ILayer<B>>> {
+    LAYER_FACTORIES.with(|factories| {
+        factories.borrow()
+            .get(&(TypeId::of::<B>(), custom_config.layer_type.clone()))
+            .and_then(|factory| factory.downcast_ref::<LayerFactory<B>>())
+            .map(|factory| factory(config))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Specifies configuration parameters for a custom layer.
+pub struct CustomConfig {
+    /// The name the factory of the layer has been registered under.
+    pub layer_type: String,
+    /// Opaque configuration of the layer, that is only interpreted by its factory.
+    ///
+    /// Default: empty
+    pub payload: Vec<u8>,
+}
+
+impl CustomConfig {
+    /// Create a CustomConfig for the layer type `layer_type` with an empty payload.
+    pub fn new(layer_type: &str) -> CustomConfig {
+        CustomConfig {
+            layer_type: layer_type.to_owned(),
+            payload: Vec::new(),
+        }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for CustomConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the CustomConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_layer_type(&self.layer_type);
+        builder.set_payload(&self.payload);
+    }
+}
+
+impl<'a> CapnpRead<'a> for CustomConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let layer_type = reader.get_layer_type().unwrap().to_owned();
+        let payload = reader.get_payload().unwrap().to_owned();
+
+        CustomConfig {
+            layer_type: layer_type,
+            payload: payload,
+        }
+    }
+}
+
+impl Into<LayerType> for CustomConfig {
+    fn into(self) -> LayerType {
+        LayerType::Custom(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use solver::*;
+    use std::rc::Rc;
+    use util::{ArcLock, native_backend, write_to_memory};
+    use util::testing::{temp_path, tensor};
+    use weight::FillerType;
+    use super::*;
+
+    /// Computes `y = a * x^2` with a learnable scalar `a`.
+    #[derive(Debug, Clone)]
+    struct ScaledSquare {
+        initial_scale: f32,
+    }
+
+    impl<B: IBackend> ILayer<B> for ScaledSquare {
+        fn exact_num_output_blobs(&self) -> Option<usize> {
+            Some(1)
+        }
+
+        fn exact_num_input_blobs(&self) -> Option<usize> {
+            Some(1)
+        }
+
+        fn auto_weight_blobs(&self) -> bool {
+            true
+        }
+
+        fn reshape(&mut self,
+                   backend: Rc<B>,
+                   input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                   input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                   weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                   weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                   output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                   output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+            let input_desc = input_data[0].read().unwrap().desc().clone();
+            input_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+            output_data[0].write().unwrap().resize(&input_desc).unwrap();
+            output_gradient[0].write().unwrap().resize(&input_desc).unwrap();
+            if let Some(weight) = weights_data.get(0) {
+                weight.write().unwrap().resize(&[1]).unwrap();
+                FillerType::fill_constant(&mut weight.write().unwrap(), self.initial_scale);
+            }
+            if let Some(gradient) = weights_gradient.get(0) {
+                gradient.write().unwrap().resize(&[1]).unwrap();
+            }
+        }
+    }
+
+    impl<B: IBackend> ComputeOutput<f32, B> for ScaledSquare {
+        fn compute_output(&self,
+                          backend: &B,
+                          weights: &[&SharedTensor<f32>],
+                          input_data: &[&SharedTensor<f32>],
+                          output_data: &mut [&mut SharedTensor<f32>]) {
+            let native = native_backend();
+            let scale = weights[0].read(native.device()).unwrap().as_slice::<f32>()[0];
+            let input = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            let output = input.iter().map(|x| scale * x * x).collect::<Vec<_>>();
+            write_to_memory(output_data[0].write_only(native.device()).unwrap(), &output);
+        }
+    }
+
+    impl<B: IBackend> ComputeInputGradient<f32, B> for ScaledSquare {
+        fn compute_input_gradient(&self,
+                                  backend: &B,
+                                  weights_data: &[&SharedTensor<f32>],
+                                  output_data: &[&SharedTensor<f32>],
+                                  output_gradients: &[&SharedTensor<f32>],
+                                  input_data: &[&SharedTensor<f32>],
+                                  input_gradients: &mut [&mut SharedTensor<f32>]) {
+            let native = native_backend();
+            let scale = weights_data[0].read(native.device()).unwrap().as_slice::<f32>()[0];
+            let input = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            let output_gradient = output_gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+            let gradient = input.iter()
+                .zip(output_gradient)
+                .map(|(x, dy)| 2f32 * scale * x * dy)
+                .collect::<Vec<_>>();
+            write_to_memory(input_gradients[0].write_only(native.device()).unwrap(), &gradient);
+        }
+    }
+
+    impl<B: IBackend> ComputeParametersGradient<f32, B> for ScaledSquare {
+        fn compute_parameters_gradient(&self,
+                                       backend: &B,
+                                       output_data: &[&SharedTensor<f32>],
+                                       output_gradients: &[&SharedTensor<f32>],
+                                       input_data: &[&SharedTensor<f32>],
+                                       parameters_gradients: &mut [&mut SharedTensor<f32>]) {
+            let native = native_backend();
+            let input = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            let output_gradient = output_gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+            let gradient = input.iter().zip(output_gradient).fold(0f32, |sum, (x, dy)| sum + x * x * dy);
+            write_to_memory(parameters_gradients[0].write_only(native.device()).unwrap(),
+                            &[gradient]);
+        }
+    }
+
+    fn scaled_square_factory(config: &LayerConfig) -> Box<ILayer<Backend<Native>>> {
+        let initial_scale = match config.layer_type {
+            LayerType::Custom(ref cfg) => String::from_utf8(cfg.payload.clone()).unwrap().parse().unwrap(),
+            _ => unreachable!(),
+        };
+        Box::new(ScaledSquare { initial_scale: initial_scale })
+    }
+
+    fn scale(network: &Layer<Backend<Native>>) -> f32 {
+        let native = native_backend();
+        let weights = network.learnable_weights_data();
+        let weight = weights[0].read().unwrap();
+        let scale = weight.read(native.device()).unwrap().as_slice::<f32>()[0];
+        scale
+    }
+
+    #[test]
+    fn custom_layer_trains_and_serializes() {
+        register_layer_factory::<Backend<Native>>("ScaledSquare", Box::new(scaled_square_factory));
+
+        let mut custom_cfg = CustomConfig::new("ScaledSquare");
+        custom_cfg.payload = b"-0.5".to_vec();
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[4, 2]);
+        net_cfg.add_layer(LayerConfig::new("square", custom_cfg));
+        net_cfg.add_layer(LayerConfig::new("log_softmax", LayerType::LogSoftmax));
+
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[4, 2]);
+        obj_cfg.add_input("label", &[4, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 2 }));
+
+        let cfg = SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 0.1f32,
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend.clone(), &cfg);
+        assert_eq!(-0.5f32, scale(solver.network()));
+
+        // the label is the feature with the larger magnitude, which requires a positive scale
+        let inputs = [2f32, 0.5f32, -0.3f32, 1f32, -1.5f32, 1f32, 0.2f32, -0.7f32];
+        let labels = [0f32, 1f32, 0f32, 1f32];
+        for _ in 0..50 {
+            solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+        }
+        let trained_scale = scale(solver.network());
+        assert!(trained_scale > 0f32);
+
+        let path = temp_path("custom_layer.capnp");
+        solver.mut_network().save(&path).unwrap();
+        let loaded = Layer::<Backend<Native>>::load(backend, &path).unwrap();
+        assert_eq!(trained_scale, scale(&loaded));
+    }
+}
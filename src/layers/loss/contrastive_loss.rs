@@ -0,0 +1,387 @@
+//! Computes the contrastive loss of pairs of embeddings.
+//!
+//! Takes two inputs of shape `[batch_size, embedding_size]` with the embeddings
+//! of each pair and a third input of shape `[batch_size]` (or `[batch_size, 1]`)
+//! with a label that is `1` for similar and `0` for dissimilar pairs.
+//! For each pair the loss is
+//!
+//! `label * d^2 + (1 - label) * max(0, margin - d)^2`
+//!
+//! with `d` being the euclidean distance between the embeddings, so similar pairs are
+//! pulled together while dissimilar pairs are pushed apart until they are at least
+//! `margin` apart. Dissimilar pairs at a distance of exactly `margin` have no loss and
+//! no gradient.
+//!
+//! This is typically used for Siamese networks, whose branches [share their weights][1].
+//!
+//! The output is the mean loss over the batch.
+//! [1]: ../../../weight/struct.WeightConfig.html#structfield.name
+//!
+//! The differences of the embeddings and their gradients are computed on the device. The
+//! squared distances are the [row dot products][row_dots] of the differences, so only the
+//! distances and the labels, one value per pair, are read on the host.
+//! [row_dots]: ../../../util/fn.row_dots.html
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use layer::*;
+use juice_capnp::contrastive_loss_config as capnp_config;
+use util::{ArcLock, LayerOps, native_backend, read_on_host, row_dots, scale_rows, write_to_memory};
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// ContrastiveLoss Loss Layer
+pub struct ContrastiveLoss {
+    margin: f32,
+}
+
+impl ContrastiveLoss {
+    /// Create a ContrastiveLoss layer from a ContrastiveLossConfig.
+    pub fn from_config(config: &ContrastiveLossConfig) -> ContrastiveLoss {
+        ContrastiveLoss { margin: config.margin }
+    }
+
+    fn distance(x: &[f32], y: &[f32]) -> f32 {
+        x.iter().zip(y).fold(0f32, |sum, (a, b)| sum + (a - b) * (a - b)).sqrt()
+    }
+
+    fn pair_loss(&self, distance: f32, label: f32) -> f32 {
+        let violation = (self.margin - distance).max(0f32);
+        label * distance * distance + (1f32 - label) * violation * violation
+    }
+
+    /// Returns the derivative of the pair loss w.r.t. the difference of the embeddings,
+    /// as a factor of that difference.
+    fn pair_gradient_scale(&self, distance: f32, label: f32) -> f32 {
+        let violation = (self.margin - distance).max(0f32);
+        // guard the derivative of the distance for identical embeddings
+        let distance = distance.max(::std::f32::EPSILON);
+        2f32 * label - 2f32 * (1f32 - label) * violation / distance
+    }
+
+    /// Returns the loss of each pair.
+    fn sample_losses(&self, input_data: &[&SharedTensor<f32>]) -> Vec<f32> {
+        let embedding_size = input_data[0].desc().size() / input_data[0].desc()[0];
+        let native = native_backend();
+        let left = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let right = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+        let labels = input_data[2].read(native.device()).unwrap().as_slice::<f32>();
+
+        left.chunks(embedding_size)
+            .zip(right.chunks(embedding_size))
+            .zip(labels)
+            .map(|((l, r), &label)| self.pair_loss(Self::distance(l, r), label))
+            .collect()
+    }
+}
+
+/// The differences of the embeddings of each pair, computed on the device, with the
+/// distances and the labels of the pairs.
+struct Pairs {
+    differences: SharedTensor<f32>,
+    distances: Vec<f32>,
+    labels: Vec<f32>,
+}
+
+impl Pairs {
+    /// Computes the differences and the distances of the pairs of `input_data`.
+    fn new<B: IBackend + LayerOps<f32>>(backend: &B,
+                                        input_data: &[&SharedTensor<f32>])
+                                        -> Result<Pairs, ::co::error::Error> {
+        let mut differences = SharedTensor::new(input_data[0].desc());
+        try!(backend.weighted_sum(&[1f32, -1f32], &input_data[..2], &mut differences));
+        let mut squared_distances = SharedTensor::new(&[input_data[0].desc()[0]]);
+        try!(row_dots(backend, &differences, &differences, &mut squared_distances));
+
+        let native = native_backend();
+        let distances = try!(read_on_host(&squared_distances, "squared_distances", &native))
+            .iter()
+            .map(|squared| squared.max(0f32).sqrt())
+            .collect();
+        let labels = try!(read_on_host(input_data[2], "labels", &native)).to_vec();
+        Ok(Pairs {
+            differences: differences,
+            distances: distances,
+            labels: labels,
+        })
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ILayer<B> for ContrastiveLoss {
+    impl_ilayer_loss!();
+
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        Some(self.sample_losses(input_data))
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        for i in 0..2 {
+            input_gradient[i].write().unwrap().resize(input_data[i].read().unwrap().desc()).unwrap();
+        }
+        output_data[0].write().unwrap().resize(&[1]).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeOutput<f32, B> for ContrastiveLoss {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let pairs = Pairs::new(backend, input_data).unwrap();
+        let loss = pairs.distances
+            .iter()
+            .zip(&pairs.labels)
+            .fold(0f32, |sum, (&distance, &label)| sum + self.pair_loss(distance, label)) /
+                   pairs.labels.len() as f32;
+        backend.fill(output_data[0], loss).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeInputGradient<f32, B> for ContrastiveLoss {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let pairs = Pairs::new(backend, input_data).unwrap();
+        let batch_size = pairs.labels.len();
+        let scales = pairs.distances
+            .iter()
+            .zip(&pairs.labels)
+            .map(|(&distance, &label)| self.pair_gradient_scale(distance, label))
+            .collect::<Vec<_>>();
+        let mut row_scales = SharedTensor::new(&[batch_size]);
+        write_to_memory(row_scales.write_only(native_backend().device()).unwrap(), &scales);
+
+        // every difference scaled by the factor of its pair
+        scale_rows(backend, &row_scales, &pairs.differences, 0f32, input_gradients[0]).unwrap();
+        let (left_gradient, right_gradient) = input_gradients.split_at_mut(1);
+        backend.weighted_sum(&[-1f32], &[&*left_gradient[0]], right_gradient[0]).unwrap();
+    }
+}
+
+impl<B: IBackend + LayerOps<f32>> ComputeParametersGradient<f32, B> for ContrastiveLoss {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_copy_implementations)]
+/// Specifies configuration parameters for a ContrastiveLoss Layer.
+pub struct ContrastiveLossConfig {
+    /// The distance beyond which dissimilar pairs don't contribute to the loss.
+    ///
+    /// Default: `1.0`
+    pub margin: f32,
+}
+
+impl Default for ContrastiveLossConfig {
+    fn default() -> ContrastiveLossConfig {
+        ContrastiveLossConfig { margin: 1f32 }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for ContrastiveLossConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the ContrastiveLossConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_margin(self.margin);
+    }
+}
+
+impl<'a> CapnpRead<'a> for ContrastiveLossConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let margin = reader.get_margin();
+
+        ContrastiveLossConfig { margin: margin }
+    }
+}
+
+impl ContrastiveLossConfig {
+    /// Checks that the margin is a finite, non-negative number.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.margin.is_finite() || self.margin < 0f32 {
+            return Err(format!("margin must be a non-negative number, but is {}", self.margin));
+        }
+        Ok(())
+    }
+}
+
+impl Into<LayerType> for ContrastiveLossConfig {
+    fn into(self) -> LayerType {
+        LayerType::ContrastiveLoss(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::{ContrastiveLoss, ContrastiveLossConfig};
+    use util::{ArcLock, native_backend};
+    use util::testing::shared_tensor;
+
+    fn loss(layer: &ContrastiveLoss, left: &[f32], right: &[f32], labels: &[f32]) -> f32 {
+        let native = native_backend();
+        let inputs = [shared_tensor(&[2, 2], left), shared_tensor(&[2, 2], right), shared_tensor(&[2], labels)];
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs.iter().collect::<Vec<_>>(), &mut [&mut loss]);
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        loss
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences() {
+        let native = native_backend();
+        let layer = ContrastiveLoss::from_config(&ContrastiveLossConfig::default());
+        // a similar pair and a dissimilar pair within the margin
+        let left = [0.2f32, -0.1, 0.5, 0.3];
+        let right = [0.6f32, 0.4, 0.1, 0.0];
+        let labels = [1f32, 0f32];
+
+        let inputs = [shared_tensor(&[2, 2], &left), shared_tensor(&[2, 2], &right), shared_tensor(&[2], &labels)];
+        let mut gradients = vec![SharedTensor::new(&[2, 2]), SharedTensor::new(&[2, 2])];
+        {
+            let mut gradient_refs = gradients.iter_mut().collect::<Vec<_>>();
+            layer.compute_input_gradient(&native,
+                                         &[],
+                                         &[],
+                                         &[],
+                                         &inputs.iter().collect::<Vec<_>>(),
+                                         &mut gradient_refs);
+        }
+        let left_gradient = gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+
+        let h = 1e-2f32;
+        for i in 0..4 {
+            let mut plus = left.to_vec();
+            plus[i] += h;
+            let mut minus = left.to_vec();
+            minus[i] -= h;
+            // the loss is the mean over the batch of 2, the gradient is not
+            let numeric = 2f32 * (loss(&layer, &plus, &right, &labels) - loss(&layer, &minus, &right, &labels)) /
+                          (2f32 * h);
+            assert!((left_gradient[i] - numeric).abs() < 1e-3,
+                    "{} != {}",
+                    left_gradient[i],
+                    numeric);
+        }
+    }
+
+    #[test]
+    fn similar_pair_and_distant_dissimilar_pair() {
+        let native = native_backend();
+        let layer = ContrastiveLoss::from_config(&ContrastiveLossConfig::default());
+        // a similar pair at distance 0.5 and a dissimilar pair at distance 2
+        let left = [0.0f32, 0.0, 0.0, 0.0];
+        let right = [0.3f32, 0.4, 2.0, 0.0];
+        let labels = [1f32, 0f32];
+        assert!((loss(&layer, &left, &right, &labels) - 0.25f32 / 2f32).abs() < 1e-6);
+
+        let inputs = [shared_tensor(&[2, 2], &left), shared_tensor(&[2, 2], &right), shared_tensor(&[2], &labels)];
+        let mut gradients = vec![SharedTensor::new(&[2, 2]), SharedTensor::new(&[2, 2])];
+        {
+            let mut gradient_refs = gradients.iter_mut().collect::<Vec<_>>();
+            layer.compute_input_gradient(&native,
+                                         &[],
+                                         &[],
+                                         &[],
+                                         &inputs.iter().collect::<Vec<_>>(),
+                                         &mut gradient_refs);
+        }
+        let left_gradient = gradients[0].read(native.device()).unwrap().as_slice::<f32>();
+        let right_gradient = gradients[1].read(native.device()).unwrap().as_slice::<f32>();
+        // descending the gradient pulls the similar pair together
+        assert!(left_gradient[0] < 0f32 && left_gradient[1] < 0f32);
+        assert!(right_gradient[0] > 0f32 && right_gradient[1] > 0f32);
+        // the dissimilar pair is already beyond the margin
+        assert_eq!(&[0f32, 0f32], &left_gradient[2..4]);
+        assert_eq!(&[0f32, 0f32], &right_gradient[2..4]);
+    }
+
+    #[test]
+    fn dissimilar_pair_at_margin_has_no_gradient() {
+        let native = native_backend();
+        let layer = ContrastiveLoss::from_config(&ContrastiveLossConfig::default());
+        let inputs = [shared_tensor(&[1, 2], &[0.0, 0.0]),
+                      shared_tensor(&[1, 2], &[1.0, 0.0]),
+                      shared_tensor(&[1], &[0.0])];
+        let mut gradients = vec![SharedTensor::new(&[1, 2]), SharedTensor::new(&[1, 2])];
+        {
+            let mut gradient_refs = gradients.iter_mut().collect::<Vec<_>>();
+            layer.compute_input_gradient(&native,
+                                         &[],
+                                         &[],
+                                         &[],
+                                         &inputs.iter().collect::<Vec<_>>(),
+                                         &mut gradient_refs);
+        }
+        assert_eq!(&[0f32, 0f32],
+                   gradients[0].read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn training_moves_similar_pairs_closer() {
+        let native = native_backend();
+        let mut registry = HashMap::<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>::new();
+        let blob = |data: &[f32], shape: &[usize]| -> (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>) {
+            (Arc::new(RwLock::new(shared_tensor(shape, data))), Arc::new(RwLock::new(SharedTensor::new(shape))))
+        };
+        registry.insert("left".to_owned(), blob(&[1.0, 2.0, 0.0, 0.0], &[2, 2]));
+        registry.insert("right".to_owned(), blob(&[-1.0, 0.5, 0.1, 0.2], &[2, 2]));
+        registry.insert("label".to_owned(), blob(&[1.0, 0.0], &[2]));
+
+        let mut cfg = LayerConfig::new("contrastive", ContrastiveLossConfig::default());
+        cfg.add_input("left");
+        cfg.add_input("right");
+        cfg.add_input("label");
+        cfg.add_output("loss");
+        let mut layer = Layer::from_config(Rc::new(native_backend()), &cfg).unwrap();
+        layer.connect(&mut registry, &mut HashMap::new());
+
+        let distances = |registry: &HashMap<String, (ArcLock<SharedTensor<f32>>, ArcLock<SharedTensor<f32>>)>| {
+            let left = registry["left"].0.read().unwrap();
+            let right = registry["right"].0.read().unwrap();
+            let left = left.read(native.device()).unwrap().as_slice::<f32>();
+            let right = right.read(native.device()).unwrap().as_slice::<f32>();
+            vec![ContrastiveLoss::distance(&left[0..2], &right[0..2]),
+                 ContrastiveLoss::distance(&left[2..4], &right[2..4])]
+        };
+        let initial = distances(&registry);
+
+        for _ in 0..20 {
+            layer.forward(&[]);
+            layer.backward_input(&[]);
+            // gradient descent on the embeddings themselves
+            for name in &["left", "right"] {
+                let (ref data, ref gradient) = registry[*name];
+                let gradient = gradient.read().unwrap();
+                let gradient = gradient.read(native.device()).unwrap().as_slice::<f32>();
+                let mut data = data.write().unwrap();
+                let data = data.read_write(native.device()).unwrap().as_mut_slice::<f32>();
+                for (value, gradient) in data.iter_mut().zip(gradient) {
+                    *value -= 0.05 * gradient;
+                }
+            }
+        }
+
+        let trained = distances(&registry);
+        assert!(trained[0] < initial[0]);
+        // the dissimilar pair is pushed out to the margin
+        assert!(trained[1] > initial[1]);
+    }
+}
@@ -1,6 +1,17 @@
 //! Provides methods to calculate the loss (cost) of some output.
 //!
 //! A loss function is also sometimes called cost function.
+//!
+//! For classification the [SoftmaxLoss][1] is recommended, as it computes the loss
+//! directly from the logits.
+//! [1]: ./softmax_loss/index.html
+//!
+//! Besides the loss of the minibatch, the loss layers provide the loss of each sample,
+//! see [Layer::per_example_losses][2].
+//! [2]: ../../layer/struct.Layer.html#method.per_example_losses
+//!
+//! The loss layers output the mean loss over the batch, but their gradients are the
+//! gradients of the summed loss: they are not divided by the batch size.
 #[macro_export]
 macro_rules! impl_ilayer_loss {
     () => (
@@ -18,6 +29,14 @@ macro_rules! impl_ilayer_loss {
     )
 }
 
+pub use self::contrastive_loss::{ContrastiveLoss, ContrastiveLossConfig};
 pub use self::negative_log_likelihood::{NegativeLogLikelihood, NegativeLogLikelihoodConfig};
+pub use self::smooth_l1_loss::{SmoothL1Loss, SmoothL1LossConfig};
+pub use self::softmax_loss::{SoftmaxLoss, SoftmaxLossConfig};
+pub use self::triplet_loss::{TripletLoss, TripletLossConfig};
 
+pub mod contrastive_loss;
 pub mod negative_log_likelihood;
+pub mod smooth_l1_loss;
+pub mod softmax_loss;
+pub mod triplet_loss;
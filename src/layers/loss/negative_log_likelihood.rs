@@ -1,5 +1,12 @@
 //! TODO: DOC
 //!
+//! An optional third input with one value per label masks the samples: samples with
+//! a mask value of `0` neither contribute to the loss nor receive a gradient, and the
+//! loss is averaged over the unmasked samples only. This is used to ignore the padding
+//! of sequences that have been batched with [pad_sequences][1], after flattening
+//! the time steps into the batch.
+//!
+//! [1]: ../../../util/fn.pad_sequences.html
 
 use capnp_util::*;
 use co::{IBackend, ITensorDesc, SharedTensor};
@@ -35,6 +42,35 @@ impl NegativeLogLikelihood {
             _ => panic!("NegativeLogLikelihood layer only supports 1D/2D inputs"),
         }
     }
+
+    /// Returns the mask of the samples, which is all ones if no mask input is given.
+    fn mask(input_data: &[&SharedTensor<f32>], num_samples: usize) -> Vec<f32> {
+        match input_data.get(2) {
+            Some(mask) => {
+                let native = native_backend();
+                let mask = mask.read(native.device()).unwrap().as_slice::<f32>();
+                assert_eq!(num_samples, mask.len(), "NegativeLogLikelihood mask needs one value per sample");
+                mask.to_vec()
+            }
+            None => vec![1f32; num_samples],
+        }
+    }
+
+    /// Returns the loss of each sample, which is `0` for masked samples.
+    fn sample_losses(&self, input_data: &[&SharedTensor<f32>]) -> Vec<f32> {
+        let native = native_backend();
+        let labels = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+        let probabilities = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let mask = Self::mask(input_data, labels.len());
+
+        labels.iter()
+            .enumerate()
+            .map(|(batch_n, &label_value)| {
+                let index = (self.num_classes * batch_n) + label_value as usize;
+                -mask[batch_n] * probabilities[index]
+            })
+            .collect()
+    }
 }
 
 impl<B: IBackend> ILayer<B> for NegativeLogLikelihood {
@@ -44,6 +80,10 @@ impl<B: IBackend> ILayer<B> for NegativeLogLikelihood {
         true
     }
 
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        Some(self.sample_losses(input_data))
+    }
+
     fn reshape(&mut self,
                backend: ::std::rc::Rc<B>,
                input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
@@ -66,26 +106,16 @@ impl<B: IBackend> ComputeOutput<f32, B> for NegativeLogLikelihood {
                       _weights: &[&SharedTensor<f32>],
                       input_data: &[&SharedTensor<f32>],
                       output_data: &mut [&mut SharedTensor<f32>]) {
-        let probabilities = input_data[0];
-        let labels = input_data[1];
-
-        let batch_size = Self::batch_size(labels.desc());
-
         let native = native_backend();
-        let native_labels = labels.read(native.device()).unwrap()
-            .as_slice::<f32>();
-        let native_probabilities = probabilities.read(native.device()).unwrap()
-            .as_slice::<f32>();
+        let num_samples = input_data[1].desc().size();
+        let mask = Self::mask(input_data, num_samples);
 
-        let mut writable_loss = Vec::<f32>::new();
-        for &label_value in native_labels {
-            let probability_value = native_probabilities[label_value as usize];
-            writable_loss.push(-probability_value);
+        let mut loss = self.sample_losses(input_data).iter().fold(0f32, |sum, &val| sum + val);
+        let num_unmasked = mask.iter().fold(0f32, |sum, &val| sum + val);
+        if num_unmasked > 0f32 {
+            loss = loss / num_unmasked;
         }
-
-        let mut loss = writable_loss.iter().fold(0f32, |sum, &val| sum + val);
-        loss = loss / (batch_size as f32);
-        writable_loss = vec![loss];
+        let writable_loss = vec![loss];
 
         ::util::write_to_memory(output_data[0].write_only(native.device()).unwrap(),
                                 &writable_loss);
@@ -108,10 +138,11 @@ impl<B: IBackend> ComputeInputGradient<f32, B> for NegativeLogLikelihood {
         let native_labels = labels.read(native.device()).unwrap()
             .as_slice::<f32>();
         let mut writable_gradient = vec![0f32; input_gradients[0].desc().size()];
+        let mask = Self::mask(input_data, native_labels.len());
 
         for (batch_n, &label_value) in native_labels.iter().enumerate() {
             let index = (num_classes * batch_n) + label_value as usize;
-            writable_gradient[index] = -1f32;
+            writable_gradient[index] = -mask[batch_n];
         }
         ::util::write_to_memory(input_gradients[0].write_only(native.device()).unwrap(),
                                 &writable_gradient);
@@ -120,7 +151,7 @@ impl<B: IBackend> ComputeInputGradient<f32, B> for NegativeLogLikelihood {
 
 impl<B: IBackend> ComputeParametersGradient<f32, B> for NegativeLogLikelihood {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(missing_copy_implementations)]
 /// Specifies configuration parameters for a NegativeLogLikelihood Layer.
 pub struct NegativeLogLikelihoodConfig {
@@ -147,8 +178,48 @@ impl<'a> CapnpRead<'a> for NegativeLogLikelihoodConfig {
     }
 }
 
+impl NegativeLogLikelihoodConfig {
+    /// Checks that there is at least one class.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_classes == 0 {
+            return Err("num_classes must be positive, but is 0".to_owned());
+        }
+        Ok(())
+    }
+}
+
 impl Into<LayerType> for NegativeLogLikelihoodConfig {
     fn into(self) -> LayerType {
         LayerType::NegativeLogLikelihood(self)
     }
 }
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use super::{NegativeLogLikelihood, NegativeLogLikelihoodConfig};
+    use util::native_backend;
+    use util::testing::shared_tensor;
+
+    #[test]
+    fn masked_samples_are_ignored() {
+        let native = native_backend();
+        let layer = NegativeLogLikelihood::from_config(&NegativeLogLikelihoodConfig { num_classes: 2 });
+        let probabilities = shared_tensor(&[3, 2], &[-0.5, -1.0, -2.0, -0.1, -0.3, -4.0]);
+        let labels = shared_tensor(&[3, 1], &[0.0, 1.0, 1.0]);
+        let mask = shared_tensor(&[3], &[1.0, 1.0, 0.0]);
+        let inputs = [&probabilities, &labels, &mask];
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs, &mut [&mut loss]);
+        let mut gradient = SharedTensor::new(&[3, 2]);
+        layer.compute_input_gradient(&native, &[], &[], &[], &inputs, &mut [&mut gradient]);
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        assert!((loss - 0.3).abs() < 1e-6);
+        assert_eq!(&[-1f32, 0.0, 0.0, -1.0, 0.0, 0.0],
+                   gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+}
@@ -0,0 +1,230 @@
+//! Computes the smooth L1 (Huber) loss of a regression.
+//!
+//! Takes the predictions and the targets, both of shape `[batch_size, ...]`.
+//! For each element with the residual `x = prediction - target` the loss is
+//!
+//! - `0.5 * x^2` for `|x| < beta`
+//! - `beta * (|x| - 0.5 * beta)` otherwise
+//!
+//! so small residuals are penalized quadratically, while large residuals only grow
+//! linearly, which makes the loss less sensitive to outliers. This is commonly used for
+//! the bounding box regression of object detectors.
+//!
+//! The gradient is the residual clamped to `[-beta, beta]`.
+//!
+//! The output is the sum of the losses of all elements, averaged over the batch.
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use layer::*;
+use juice_capnp::smooth_l1_loss_config as capnp_config;
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// SmoothL1Loss Loss Layer
+pub struct SmoothL1Loss {
+    beta: f32,
+}
+
+impl SmoothL1Loss {
+    /// Create a SmoothL1Loss layer from a SmoothL1LossConfig.
+    pub fn from_config(config: &SmoothL1LossConfig) -> SmoothL1Loss {
+        SmoothL1Loss { beta: config.beta }
+    }
+
+    fn element_loss(&self, residual: f32) -> f32 {
+        if residual.abs() < self.beta {
+            0.5f32 * residual * residual
+        } else {
+            self.beta * (residual.abs() - 0.5f32 * self.beta)
+        }
+    }
+
+    fn element_gradient(&self, residual: f32) -> f32 {
+        residual.max(-self.beta).min(self.beta)
+    }
+
+    /// Returns the sum of the losses of the elements of each sample.
+    fn sample_losses(&self, input_data: &[&SharedTensor<f32>]) -> Vec<f32> {
+        assert_eq!(input_data[0].desc().size(), input_data[1].desc().size());
+        let batch_size = input_data[0].desc()[0];
+        let sample_size = input_data[0].desc().size() / batch_size;
+
+        let native = native_backend();
+        let predictions = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let targets = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+
+        predictions.chunks(sample_size)
+            .zip(targets.chunks(sample_size))
+            .map(|(predictions, targets)| {
+                predictions.iter()
+                    .zip(targets)
+                    .fold(0f32, |sum, (prediction, target)| sum + self.element_loss(prediction - target))
+            })
+            .collect()
+    }
+}
+
+impl<B: IBackend> ILayer<B> for SmoothL1Loss {
+    impl_ilayer_loss!();
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        Some(self.sample_losses(input_data))
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        input_gradient[0].write().unwrap().resize(input_data[0].read().unwrap().desc()).unwrap();
+        output_data[0].write().unwrap().resize(&[1]).unwrap();
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for SmoothL1Loss {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let batch_size = input_data[0].desc()[0];
+        let native = native_backend();
+        let loss = self.sample_losses(input_data).iter().fold(0f32, |sum, &val| sum + val) / batch_size as f32;
+
+        write_to_memory(output_data[0].write_only(native.device()).unwrap(), &[loss]);
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for SmoothL1Loss {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let predictions = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let targets = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+
+        let gradient = predictions.iter()
+            .zip(targets)
+            .map(|(prediction, target)| self.element_gradient(prediction - target))
+            .collect::<Vec<_>>();
+
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(),
+                        &gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for SmoothL1Loss {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_copy_implementations)]
+/// Specifies configuration parameters for a SmoothL1Loss Layer.
+pub struct SmoothL1LossConfig {
+    /// The absolute residual at which the loss changes from quadratic to linear.
+    ///
+    /// Default: `1.0`
+    pub beta: f32,
+}
+
+impl Default for SmoothL1LossConfig {
+    fn default() -> SmoothL1LossConfig {
+        SmoothL1LossConfig { beta: 1f32 }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for SmoothL1LossConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the SmoothL1LossConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_beta(self.beta);
+    }
+}
+
+impl<'a> CapnpRead<'a> for SmoothL1LossConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let beta = reader.get_beta();
+
+        SmoothL1LossConfig { beta: beta }
+    }
+}
+
+impl SmoothL1LossConfig {
+    /// Checks that `beta` is a finite, positive number, as the quadratic part of the loss
+    /// is divided by it.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.beta.is_finite() || self.beta <= 0f32 {
+            return Err(format!("beta must be a positive number, but is {}", self.beta));
+        }
+        Ok(())
+    }
+}
+
+impl Into<LayerType> for SmoothL1LossConfig {
+    fn into(self) -> LayerType {
+        LayerType::SmoothL1Loss(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use super::{SmoothL1Loss, SmoothL1LossConfig};
+    use util::native_backend;
+    use util::testing::shared_tensor;
+
+    #[test]
+    fn quadratic_and_linear_regions() {
+        let native = native_backend();
+        let layer = SmoothL1Loss::from_config(&SmoothL1LossConfig::default());
+        // residuals: 0.5 and -0.25 are quadratic, 3 and -2 are linear
+        let predictions = shared_tensor(&[2, 2], &[1.5, 0.75, 4.0, -1.0]);
+        let targets = shared_tensor(&[2, 2], &[1.0, 1.0, 1.0, 1.0]);
+        let inputs = [&predictions, &targets];
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs, &mut [&mut loss]);
+        let mut gradient = SharedTensor::new(&[2, 2]);
+        layer.compute_input_gradient(&native, &[], &[], &[], &inputs, &mut [&mut gradient]);
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        let expected = (0.125f32 + 0.03125 + 2.5 + 1.5) / 2f32;
+        assert!((loss - expected).abs() < 1e-6, "{} != {}", loss, expected);
+        assert_eq!(&[0.5f32, -0.25, 1.0, -1.0],
+                   gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn beta_moves_the_transition() {
+        let native = native_backend();
+        let layer = SmoothL1Loss::from_config(&SmoothL1LossConfig { beta: 0.5 });
+        let predictions = shared_tensor(&[1, 2], &[0.25, 3.0]);
+        let targets = shared_tensor(&[1, 2], &[0.0, 0.0]);
+        let inputs = [&predictions, &targets];
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs, &mut [&mut loss]);
+        let mut gradient = SharedTensor::new(&[1, 2]);
+        layer.compute_input_gradient(&native, &[], &[], &[], &inputs, &mut [&mut gradient]);
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        assert!((loss - (0.03125f32 + 1.375)).abs() < 1e-6);
+        assert_eq!(&[0.25f32, 0.5], gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+}
@@ -0,0 +1,627 @@
+//! Computes the softmax cross-entropy loss directly from the logits.
+//!
+//! Takes the unnormalized logits of shape `[batch_size, num_classes]` and the class
+//! index of each sample of shape `[batch_size]` (or `[batch_size, 1]`).
+//! For each sample the loss is
+//!
+//! `log(sum_j(e^x_j)) - x_t`
+//!
+//! with `t` being the target class. The log-sum-exp is computed after subtracting the
+//! largest logit, so it neither overflows nor loses precision for confident predictions,
+//! as a separate Softmax followed by a log would. The gradient is `p - onehot(t)`,
+//! with `p` being the softmax of the logits.
+//!
+//! This is the recommended loss for classification, instead of a [LogSoftmax][1] layer
+//! followed by a [NegativeLogLikelihood][2] layer.
+//!
+//! The labels can also be target distributions of shape `[batch_size, num_classes]`,
+//! e.g. one-hot vectors or the soft labels of a teacher network for distillation.
+//! Then the loss of each sample is the full cross-entropy
+//!
+//! `sum_j(y_j * (log(sum_k(e^x_k)) - x_j))`
+//!
+//! and the gradient is `p * sum_j(y_j) - y`, which is `p - y` for normalized targets.
+//! Which kind of labels is given is detected from their shape. With a single class the
+//! shape `[batch_size, 1]` is ambiguous and the labels are treated as class indices.
+//!
+//! Each sample can be weighted by the weight of its class, and samples whose label is
+//! the `ignore_index` neither contribute to the loss nor receive a gradient. Both only
+//! apply to class indices, samples with target distributions all have the weight `1`.
+//!
+//! With online hard example mining (`ohem_top_k`) only the `k` samples with the largest
+//! losses of each minibatch contribute to the loss and receive a gradient. This keeps the
+//! many easy samples of imbalanced tasks like detection or segmentation from dominating
+//! the training.
+//!
+//! The output is the weighted mean loss over the samples that are not ignored.
+//!
+//! With `output_probabilities` the layer has a second output with the softmax of the
+//! logits, of shape `[batch_size, num_classes]`, so the same layer can be used for
+//! training and inference. It is not a loss and no gradient flows back through it.
+//! [1]: ../../common/log_softmax/index.html
+//! [2]: ../negative_log_likelihood/index.html
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use layer::*;
+use juice_capnp::softmax_loss_config as capnp_config;
+use std::cell::RefCell;
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The kind of labels of a SoftmaxLoss.
+enum LabelMode {
+    /// The class index of each sample.
+    Index,
+    /// A distribution over the classes for each sample.
+    Distribution,
+}
+
+#[derive(Debug, Clone)]
+/// SoftmaxLoss Loss Layer
+pub struct SoftmaxLoss {
+    num_classes: usize,
+    class_weights: Vec<f32>,
+    ignore_index: Option<usize>,
+    ohem_top_k: Option<usize>,
+    output_probabilities: bool,
+    /// The samples selected by the last forward pass, which receive a gradient.
+    selected: RefCell<Vec<bool>>,
+}
+
+impl SoftmaxLoss {
+    /// Create a SoftmaxLoss layer from a SoftmaxLossConfig.
+    pub fn from_config(config: &SoftmaxLossConfig) -> SoftmaxLoss {
+        assert!(config.class_weights.is_empty() || config.class_weights.len() == config.num_classes,
+                "SoftmaxLoss needs one class weight per class");
+        SoftmaxLoss {
+            num_classes: config.num_classes,
+            class_weights: config.class_weights.clone(),
+            ignore_index: config.ignore_index,
+            ohem_top_k: config.ohem_top_k,
+            output_probabilities: config.output_probabilities,
+            selected: RefCell::new(vec![]),
+        }
+    }
+
+    /// Returns the weight of a sample with the class `label`.
+    fn sample_weight(&self, label: usize) -> f32 {
+        if Some(label) == self.ignore_index {
+            0f32
+        } else {
+            self.class_weights.get(label).cloned().unwrap_or(1f32)
+        }
+    }
+
+    /// Returns the kind of labels of the shape `label_shape`.
+    fn label_mode(&self, label_shape: &[usize]) -> LabelMode {
+        if self.num_classes > 1 && label_shape.len() > 1 && label_shape[label_shape.len() - 1] == self.num_classes {
+            LabelMode::Distribution
+        } else {
+            LabelMode::Index
+        }
+    }
+
+    /// Returns the weight of each sample in the mean loss.
+    fn sample_weights(&self, labels: &[f32], mode: LabelMode) -> Vec<f32> {
+        match mode {
+            LabelMode::Index => labels.iter().map(|&label| self.sample_weight(label as usize)).collect(),
+            LabelMode::Distribution => vec![1f32; labels.len() / self.num_classes],
+        }
+    }
+
+    /// Returns the log-sum-exp of the logits of a sample.
+    fn log_sum_exp(logits: &[f32]) -> f32 {
+        let max = logits.iter().fold(::std::f32::NEG_INFINITY, |max, &x| max.max(x));
+        let sum = logits.iter().fold(0f32, |sum, &x| sum + (x - max).exp());
+        max + sum.ln()
+    }
+
+    /// Returns the weighted loss of each sample, which is `0` for ignored samples
+    /// and samples that are not mined.
+    fn sample_losses(&self, input_data: &[&SharedTensor<f32>]) -> Vec<f32> {
+        let losses = self.weighted_losses(input_data);
+        let selected = self.selected_samples(&losses);
+        losses.iter().zip(selected).map(|(&loss, selected)| if selected { loss } else { 0f32 }).collect()
+    }
+
+    /// Returns the weighted loss of each sample before mining, which is `0` for
+    /// ignored samples.
+    fn weighted_losses(&self, input_data: &[&SharedTensor<f32>]) -> Vec<f32> {
+        let native = native_backend();
+        let logits = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let labels = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+
+        match self.label_mode(input_data[1].desc()) {
+            LabelMode::Index => {
+                logits.chunks(self.num_classes)
+                    .zip(labels)
+                    .map(|(sample, &label)| {
+                        let label = label as usize;
+                        let weight = self.sample_weight(label);
+                        if weight != 0f32 {
+                            weight * (Self::log_sum_exp(sample) - sample[label])
+                        } else {
+                            0f32
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            }
+            LabelMode::Distribution => {
+                logits.chunks(self.num_classes)
+                    .zip(labels.chunks(self.num_classes))
+                    .map(|(sample, target)| {
+                        let log_sum_exp = Self::log_sum_exp(sample);
+                        sample.iter().zip(target).fold(0f32, |sum, (&x, &y)| sum + y * (log_sum_exp - x))
+                    })
+                    .collect::<Vec<_>>()
+            }
+        }
+    }
+
+    /// Returns for each sample whether it contributes to the loss.
+    ///
+    /// With `ohem_top_k` these are the `k` samples with the largest losses,
+    /// otherwise all samples.
+    fn selected_samples(&self, losses: &[f32]) -> Vec<bool> {
+        let top_k = match self.ohem_top_k {
+            Some(top_k) => top_k,
+            None => return vec![true; losses.len()],
+        };
+        let mut order = (0..losses.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| losses[b].partial_cmp(&losses[a]).unwrap_or(::std::cmp::Ordering::Equal));
+        let mut selected = vec![false; losses.len()];
+        for &sample in order.iter().take(top_k) {
+            selected[sample] = true;
+        }
+        selected
+    }
+}
+
+impl<B: IBackend> ILayer<B> for SoftmaxLoss {
+    fn exact_num_output_blobs(&self) -> Option<usize> {
+        if self.output_probabilities { Some(2) } else { Some(1) }
+    }
+
+    fn exact_num_input_blobs(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn auto_output_blobs(&self) -> bool {
+        true
+    }
+
+    fn loss_weight(&self, output_id: usize) -> Option<f32> {
+        if output_id == 0 { Some(1f32) } else { None }
+    }
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        Some(self.sample_losses(input_data))
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let label_shape = input_data[1].read().unwrap().desc().clone();
+        if self.num_classes == 1 && label_shape.len() > 1 && label_shape[label_shape.len() - 1] == 1 {
+            info!("SoftmaxLoss - labels of shape {:?} are ambiguous with a single class, treating them as class indices",
+                  label_shape);
+        }
+        input_gradient[0].write().unwrap().resize(input_data[0].read().unwrap().desc()).unwrap();
+        output_data[0].write().unwrap().resize(&[1]).unwrap();
+        if self.output_probabilities {
+            output_data[1].write().unwrap().resize(input_data[0].read().unwrap().desc()).unwrap();
+        }
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for SoftmaxLoss {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let labels = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+
+        // the samples are selected once per step, the backward pass reuses the selection
+        let losses = self.weighted_losses(input_data);
+        let selected = self.selected_samples(&losses);
+        let weights = self.sample_weights(labels, self.label_mode(input_data[1].desc()));
+        let (mut loss, total_weight) = losses.iter()
+            .zip(weights)
+            .zip(&selected)
+            .filter(|&(_, &selected)| selected)
+            .fold((0f32, 0f32),
+                  |(loss, total_weight), ((&sample_loss, weight), _)| (loss + sample_loss, total_weight + weight));
+        if total_weight > 0f32 {
+            loss = loss / total_weight;
+        }
+        *self.selected.borrow_mut() = selected;
+
+        write_to_memory(output_data[0].write_only(native.device()).unwrap(), &[loss]);
+
+        if self.output_probabilities {
+            let logits = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+            let probabilities = logits.chunks(self.num_classes)
+                .flat_map(|sample| {
+                    let log_sum_exp = Self::log_sum_exp(sample);
+                    sample.iter().map(move |&x| (x - log_sum_exp).exp())
+                })
+                .collect::<Vec<_>>();
+            write_to_memory(output_data[1].write_only(native.device()).unwrap(), &probabilities);
+        }
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for SoftmaxLoss {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let native = native_backend();
+        let logits = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let labels = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+
+        let selected = self.selected.borrow();
+        let mode = self.label_mode(input_data[1].desc());
+        let weights = self.sample_weights(labels, mode);
+
+        let mut gradient = vec![0f32; logits.len()];
+        for (sample_n, ((sample, sample_gradient), selected)) in logits.chunks(self.num_classes)
+            .zip(gradient.chunks_mut(self.num_classes))
+            .zip(selected.iter())
+            .enumerate() {
+            let weight = weights[sample_n];
+            if weight == 0f32 || !*selected {
+                continue;
+            }
+            let log_sum_exp = Self::log_sum_exp(sample);
+            match mode {
+                LabelMode::Index => {
+                    let label = labels[sample_n] as usize;
+                    for (class, (&x, gradient)) in sample.iter().zip(sample_gradient.iter_mut()).enumerate() {
+                        let probability = (x - log_sum_exp).exp();
+                        let target = if class == label { 1f32 } else { 0f32 };
+                        *gradient = weight * (probability - target);
+                    }
+                }
+                LabelMode::Distribution => {
+                    let target = &labels[sample_n * self.num_classes..(sample_n + 1) * self.num_classes];
+                    let target_sum = target.iter().fold(0f32, |sum, &y| sum + y);
+                    for ((&x, &y), gradient) in sample.iter().zip(target).zip(sample_gradient.iter_mut()) {
+                        let probability = (x - log_sum_exp).exp();
+                        *gradient = weight * (probability * target_sum - y);
+                    }
+                }
+            }
+        }
+
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(),
+                        &gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for SoftmaxLoss {}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Specifies configuration parameters for a SoftmaxLoss Layer.
+pub struct SoftmaxLossConfig {
+    /// How many different classes can be classified.
+    pub num_classes: usize,
+    /// The weight of the samples of each class.
+    ///
+    /// If empty, all classes are weighted equally.
+    ///
+    /// Default: `vec![]`
+    pub class_weights: Vec<f32>,
+    /// A label that marks samples which are ignored.
+    ///
+    /// Default: `None`
+    pub ignore_index: Option<usize>,
+    /// The number of samples with the largest losses that contribute to the loss and
+    /// receive a gradient, for online hard example mining.
+    ///
+    /// If `None`, all samples contribute.
+    ///
+    /// Default: `None`
+    pub ohem_top_k: Option<usize>,
+    /// Adds a second output with the softmax probabilities of the logits.
+    ///
+    /// Default: `false`
+    pub output_probabilities: bool,
+}
+
+impl SoftmaxLossConfig {
+    /// Create a SoftmaxLossConfig for `num_classes` equally weighted classes.
+    pub fn new(num_classes: usize) -> SoftmaxLossConfig {
+        SoftmaxLossConfig {
+            num_classes: num_classes,
+            class_weights: vec![],
+            ignore_index: None,
+            ohem_top_k: None,
+            output_probabilities: false,
+        }
+    }
+
+    /// Checks that there is at least one class, one class weight per class if any
+    /// and that online hard example mining selects at least one sample.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_classes == 0 {
+            return Err("num_classes must be positive, but is 0".to_owned());
+        }
+        if !self.class_weights.is_empty() && self.class_weights.len() != self.num_classes {
+            return Err(format!("class_weights must contain one weight for each of the {} classes, but contains {}",
+                               self.num_classes,
+                               self.class_weights.len()));
+        }
+        if self.ohem_top_k == Some(0) {
+            return Err("ohem_top_k must be positive, but is 0".to_owned());
+        }
+        Ok(())
+    }
+}
+
+impl<'a> CapnpWrite<'a> for SoftmaxLossConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the SoftmaxLossConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_num_classes(self.num_classes as u64);
+        {
+            let mut class_weights = builder.borrow().init_class_weights(self.class_weights.len() as u32);
+            for (i, weight) in self.class_weights.iter().enumerate() {
+                class_weights.set(i as u32, *weight);
+            }
+        }
+        builder.set_ignore_index(self.ignore_index.map(|index| index as i64).unwrap_or(-1));
+        builder.set_ohem_top_k(self.ohem_top_k.unwrap_or(0) as u64);
+        builder.set_output_probabilities(self.output_probabilities);
+    }
+}
+
+impl<'a> CapnpRead<'a> for SoftmaxLossConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let num_classes = reader.get_num_classes() as usize;
+        let read_class_weights = reader.get_class_weights().unwrap();
+        let mut class_weights = Vec::new();
+        for i in 0..read_class_weights.len() {
+            class_weights.push(read_class_weights.get(i));
+        }
+        let ignore_index = match reader.get_ignore_index() {
+            index if index < 0 => None,
+            index => Some(index as usize),
+        };
+        let ohem_top_k = match reader.get_ohem_top_k() {
+            0 => None,
+            top_k => Some(top_k as usize),
+        };
+        let output_probabilities = reader.get_output_probabilities();
+
+        SoftmaxLossConfig {
+            num_classes: num_classes,
+            class_weights: class_weights,
+            ignore_index: ignore_index,
+            ohem_top_k: ohem_top_k,
+            output_probabilities: output_probabilities,
+        }
+    }
+}
+
+impl Into<LayerType> for SoftmaxLossConfig {
+    fn into(self) -> LayerType {
+        LayerType::SoftmaxLoss(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::Softmax;
+    use super::{LabelMode, SoftmaxLoss, SoftmaxLossConfig};
+    use util::native_backend;
+    use util::testing::shared_tensor;
+
+    /// Returns the loss and the gradient of the layer.
+    fn loss_and_gradient(layer: &SoftmaxLoss, logits: &[f32], labels: &[f32]) -> (f32, Vec<f32>) {
+        let native = native_backend();
+        let num_classes = logits.len() / labels.len();
+        let logits = shared_tensor(&[labels.len(), num_classes], logits);
+        let labels = shared_tensor(&[labels.len()], labels);
+        let inputs = [&logits, &labels];
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs, &mut [&mut loss]);
+        let mut gradient = SharedTensor::new(logits.desc());
+        layer.compute_input_gradient(&native, &[], &[], &[], &inputs, &mut [&mut gradient]);
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        let gradient = gradient.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+        (loss, gradient)
+    }
+
+    /// Returns the loss and the gradient of the layer for target distributions.
+    fn distribution_loss_and_gradient(layer: &SoftmaxLoss, logits: &[f32], targets: &[f32]) -> (f32, Vec<f32>) {
+        let native = native_backend();
+        let num_classes = layer.num_classes;
+        let logits = shared_tensor(&[logits.len() / num_classes, num_classes], logits);
+        let targets = shared_tensor(logits.desc(), targets);
+        let inputs = [&logits, &targets];
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &inputs, &mut [&mut loss]);
+        let mut gradient = SharedTensor::new(logits.desc());
+        layer.compute_input_gradient(&native, &[], &[], &[], &inputs, &mut [&mut gradient]);
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        let gradient = gradient.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+        (loss, gradient)
+    }
+
+    /// Returns the loss and the gradient of a single sample, computed in f64.
+    fn reference(logits: &[f32], label: usize) -> (f64, Vec<f64>) {
+        let logits = logits.iter().map(|&x| x as f64).collect::<Vec<_>>();
+        let max = logits.iter().fold(::std::f64::NEG_INFINITY, |max, &x| max.max(x));
+        let sum = logits.iter().fold(0f64, |sum, &x| sum + (x - max).exp());
+        let log_sum_exp = max + sum.ln();
+        let gradient = logits.iter()
+            .enumerate()
+            .map(|(class, &x)| (x - log_sum_exp).exp() - if class == label { 1f64 } else { 0f64 })
+            .collect();
+        (log_sum_exp - logits[label], gradient)
+    }
+
+    #[test]
+    fn matches_f64_reference_at_extreme_logits() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig::new(3));
+        let samples: [([f32; 3], usize); 4] = [([50.0, -50.0, 0.0], 0),
+                                               ([50.0, -50.0, 0.0], 1),
+                                               ([-50.0, -50.0, 50.0], 0),
+                                               ([1.0, 2.0, 3.0], 2)];
+        for &(ref logits, label) in &samples {
+            let (loss, gradient) = loss_and_gradient(&layer, logits, &[label as f32]);
+            let (expected_loss, expected_gradient) = reference(logits, label);
+            assert!(loss.is_finite());
+            assert!((loss as f64 - expected_loss).abs() <= 1e-5 * expected_loss.max(1f64),
+                    "{} != {}",
+                    loss,
+                    expected_loss);
+            for (g, e) in gradient.iter().zip(expected_gradient.iter()) {
+                assert!((*g as f64 - e).abs() < 1e-6, "{} != {}", g, e);
+            }
+        }
+    }
+
+    #[test]
+    fn class_weights_and_ignore_index() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig {
+            num_classes: 2,
+            class_weights: vec![1.0, 3.0],
+            ignore_index: Some(0),
+            ..SoftmaxLossConfig::new(2)
+        });
+        let logits = [0.5f32, -0.5, 0.5, -0.5, 2.0, 1.0];
+        let (loss, gradient) = loss_and_gradient(&layer, &logits, &[0.0, 1.0, 1.0]);
+
+        let (expected_1, gradient_1) = reference(&logits[2..4], 1);
+        let (expected_2, gradient_2) = reference(&logits[4..6], 1);
+        assert!((loss as f64 - (expected_1 + expected_2) / 2f64).abs() < 1e-5);
+        assert_eq!(&[0f32, 0f32], &gradient[0..2]);
+        for (g, e) in gradient[2..].iter().zip(gradient_1.iter().chain(gradient_2.iter())) {
+            assert!((*g as f64 - 3f64 * e).abs() < 1e-6, "{} != {}", g, 3f64 * e);
+        }
+    }
+
+    #[test]
+    fn ohem_only_trains_hardest_samples() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig { ohem_top_k: Some(2), ..SoftmaxLossConfig::new(2) });
+        // the first and third samples are confidently correct, the others are wrong
+        let logits = [8.0f32, -8.0, 1.0, -1.0, -9.0, 9.0, 0.5, 0.0];
+        let (loss, gradient) = loss_and_gradient(&layer, &logits, &[0.0, 1.0, 1.0, 1.0]);
+
+        let (expected_1, gradient_1) = reference(&logits[2..4], 1);
+        let (expected_3, gradient_3) = reference(&logits[6..8], 1);
+        assert!((loss as f64 - (expected_1 + expected_3) / 2f64).abs() < 1e-5);
+        assert_eq!(&[0f32, 0f32], &gradient[0..2]);
+        assert_eq!(&[0f32, 0f32], &gradient[4..6]);
+        let hard_gradient = gradient[2..4].iter().chain(gradient[6..8].iter());
+        for (g, e) in hard_gradient.zip(gradient_1.iter().chain(gradient_3.iter())) {
+            assert!((*g as f64 - e).abs() < 1e-6, "{} != {}", g, e);
+        }
+    }
+
+    #[test]
+    fn one_hot_labels_match_class_indices() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig::new(3));
+        let logits = [1.0f32, 2.0, 3.0, 0.5, -1.0, 2.5];
+        let (index_loss, index_gradient) = loss_and_gradient(&layer, &logits, &[2.0, 0.0]);
+        let (loss, gradient) = distribution_loss_and_gradient(&layer, &logits, &[0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+
+        assert!((loss - index_loss).abs() < 1e-6, "{} != {}", loss, index_loss);
+        for (g, e) in gradient.iter().zip(index_gradient.iter()) {
+            assert!((g - e).abs() < 1e-6, "{} != {}", g, e);
+        }
+    }
+
+    #[test]
+    fn soft_labels_use_full_cross_entropy() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig::new(2));
+        let logits = [1.0f32, -1.0];
+        let targets = [0.25f32, 0.75];
+        let (loss, gradient) = distribution_loss_and_gradient(&layer, &logits, &targets);
+
+        let (loss_0, gradient_0) = reference(&logits, 0);
+        let (loss_1, gradient_1) = reference(&logits, 1);
+        let expected_loss = 0.25 * loss_0 + 0.75 * loss_1;
+        assert!((loss as f64 - expected_loss).abs() < 1e-5, "{} != {}", loss, expected_loss);
+        // p - y, the mixture of the gradients of both classes
+        for (class, g) in gradient.iter().enumerate() {
+            let expected = 0.25 * gradient_0[class] + 0.75 * gradient_1[class];
+            assert!((*g as f64 - expected).abs() < 1e-6, "{} != {}", g, expected);
+        }
+    }
+
+    #[test]
+    fn probability_output_matches_softmax() {
+        let native = native_backend();
+        let config = SoftmaxLossConfig { output_probabilities: true, ..SoftmaxLossConfig::new(3) };
+        let layer = SoftmaxLoss::from_config(&config);
+        assert_eq!(Some(2), ILayer::<Backend<Native>>::exact_num_output_blobs(&layer));
+        assert_eq!(None, ILayer::<Backend<Native>>::loss_weight(&layer, 1));
+
+        let logits = shared_tensor(&[2, 3], &[1.0, 2.0, 3.0, 50.0, -50.0, 0.5]);
+        let labels = shared_tensor(&[2], &[2.0, 1.0]);
+        let mut loss = SharedTensor::new(&[1]);
+        let mut probabilities = SharedTensor::new(logits.desc());
+        layer.compute_output(&native, &[], &[&logits, &labels], &mut [&mut loss, &mut probabilities]);
+        let mut softmax = SharedTensor::new(logits.desc());
+        Softmax.compute_output(&native, &[], &[&logits], &mut [&mut softmax]);
+
+        let probabilities = probabilities.read(native.device()).unwrap().as_slice::<f32>();
+        let softmax = softmax.read(native.device()).unwrap().as_slice::<f32>();
+        for sample in probabilities.chunks(3) {
+            let sum = sample.iter().fold(0f32, |sum, &p| sum + p);
+            assert!((sum - 1f32).abs() < 1e-6, "{}", sum);
+        }
+        for (p, s) in probabilities.iter().zip(softmax) {
+            assert!((p - s).abs() < 1e-6, "{} != {}", p, s);
+        }
+
+        // the loss and the gradient are the ones of the layer without probabilities
+        let (expected_loss, expected_gradient) =
+            loss_and_gradient(&SoftmaxLoss::from_config(&SoftmaxLossConfig::new(3)),
+                              &[1.0, 2.0, 3.0, 50.0, -50.0, 0.5],
+                              &[2.0, 1.0]);
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        assert_eq!(expected_loss, loss);
+        let mut gradient = SharedTensor::new(logits.desc());
+        layer.compute_input_gradient(&native, &[], &[], &[], &[&logits, &labels], &mut [&mut gradient]);
+        assert_eq!(expected_gradient, gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn single_class_labels_are_indices() {
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig::new(1));
+        assert_eq!(LabelMode::Index, layer.label_mode(&[4, 1]));
+        let layer = SoftmaxLoss::from_config(&SoftmaxLossConfig::new(3));
+        assert_eq!(LabelMode::Index, layer.label_mode(&[4, 1]));
+        assert_eq!(LabelMode::Index, layer.label_mode(&[4]));
+        assert_eq!(LabelMode::Distribution, layer.label_mode(&[4, 3]));
+    }
+}
@@ -0,0 +1,257 @@
+//! Computes the triplet margin loss of embeddings.
+//!
+//! Takes three inputs of shape `[batch_size, embedding_size]`: the anchor,
+//! positive and negative embeddings. For each sample the loss is
+//!
+//! `max(0, d(anchor, positive) - d(anchor, negative) + margin)`
+//!
+//! with `d` being the squared euclidean distance, so it pulls the positive
+//! embedding towards the anchor until it is closer by at least `margin` than
+//! the negative embedding.
+//!
+//! The output is the mean loss over the batch.
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use layer::*;
+use juice_capnp::triplet_loss_config as capnp_config;
+use util::{ArcLock, native_backend, write_to_memory};
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// TripletLoss Loss Layer
+pub struct TripletLoss {
+    margin: f32,
+}
+
+impl TripletLoss {
+    /// Create a TripletLoss layer from a TripletLossConfig.
+    pub fn from_config(config: &TripletLossConfig) -> TripletLoss {
+        TripletLoss { margin: config.margin }
+    }
+
+    fn squared_distance(x: &[f32], y: &[f32]) -> f32 {
+        x.iter().zip(y).fold(0f32, |sum, (a, b)| sum + (a - b) * (a - b))
+    }
+
+    /// Returns the loss of each sample.
+    fn sample_losses(&self, anchor: &[f32], positive: &[f32], negative: &[f32], embedding_size: usize) -> Vec<f32> {
+        anchor.chunks(embedding_size)
+            .zip(positive.chunks(embedding_size))
+            .zip(negative.chunks(embedding_size))
+            .map(|((a, p), n)| {
+                let loss = Self::squared_distance(a, p) - Self::squared_distance(a, n) + self.margin;
+                loss.max(0f32)
+            })
+            .collect()
+    }
+}
+
+impl<B: IBackend> ILayer<B> for TripletLoss {
+    impl_ilayer_loss!();
+
+    fn sync_native(&self) -> bool {
+        true
+    }
+
+    fn per_example_loss(&self, input_data: &[&SharedTensor<f32>]) -> Option<Vec<f32>> {
+        let embedding_size = input_data[0].desc().size() / input_data[0].desc()[0];
+        let native = native_backend();
+        let anchor = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let positive = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+        let negative = input_data[2].read(native.device()).unwrap().as_slice::<f32>();
+        Some(self.sample_losses(anchor, positive, negative, embedding_size))
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        for (data, gradient) in input_data.iter().zip(input_gradient.iter()) {
+            gradient.write().unwrap().resize(data.read().unwrap().desc()).unwrap();
+        }
+        output_data[0].write().unwrap().resize(&[1]).unwrap();
+    }
+}
+
+impl<B: IBackend> ComputeOutput<f32, B> for TripletLoss {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let batch_size = input_data[0].desc()[0];
+        let embedding_size = input_data[0].desc().size() / batch_size;
+
+        let native = native_backend();
+        let anchor = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let positive = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+        let negative = input_data[2].read(native.device()).unwrap().as_slice::<f32>();
+
+        let losses = self.sample_losses(anchor, positive, negative, embedding_size);
+        let loss = losses.iter().fold(0f32, |sum, &val| sum + val) / batch_size as f32;
+
+        write_to_memory(output_data[0].write_only(native.device()).unwrap(), &[loss]);
+    }
+}
+
+impl<B: IBackend> ComputeInputGradient<f32, B> for TripletLoss {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let batch_size = input_data[0].desc()[0];
+        let embedding_size = input_data[0].desc().size() / batch_size;
+
+        let native = native_backend();
+        let anchor = input_data[0].read(native.device()).unwrap().as_slice::<f32>();
+        let positive = input_data[1].read(native.device()).unwrap().as_slice::<f32>();
+        let negative = input_data[2].read(native.device()).unwrap().as_slice::<f32>();
+        let losses = self.sample_losses(anchor, positive, negative, embedding_size);
+
+        let mut anchor_gradient = vec![0f32; anchor.len()];
+        let mut positive_gradient = vec![0f32; positive.len()];
+        let mut negative_gradient = vec![0f32; negative.len()];
+        for (sample, &loss) in losses.iter().enumerate() {
+            // samples that satisfy the margin don't contribute
+            if loss <= 0f32 {
+                continue;
+            }
+            for i in sample * embedding_size..(sample + 1) * embedding_size {
+                anchor_gradient[i] = 2f32 * (negative[i] - positive[i]);
+                positive_gradient[i] = 2f32 * (positive[i] - anchor[i]);
+                negative_gradient[i] = 2f32 * (anchor[i] - negative[i]);
+            }
+        }
+
+        write_to_memory(input_gradients[0].write_only(native.device()).unwrap(),
+                        &anchor_gradient);
+        write_to_memory(input_gradients[1].write_only(native.device()).unwrap(),
+                        &positive_gradient);
+        write_to_memory(input_gradients[2].write_only(native.device()).unwrap(),
+                        &negative_gradient);
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for TripletLoss {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_copy_implementations)]
+/// Specifies configuration parameters for a TripletLoss Layer.
+pub struct TripletLossConfig {
+    /// The margin by which the positive embedding has to be closer to the anchor
+    /// than the negative embedding.
+    ///
+    /// Default: `1.0`
+    pub margin: f32,
+}
+
+impl Default for TripletLossConfig {
+    fn default() -> TripletLossConfig {
+        TripletLossConfig { margin: 1f32 }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for TripletLossConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the TripletLossConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_margin(self.margin);
+    }
+}
+
+impl<'a> CapnpRead<'a> for TripletLossConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let margin = reader.get_margin();
+
+        TripletLossConfig { margin: margin }
+    }
+}
+
+impl TripletLossConfig {
+    /// Checks that the margin is a finite, non-negative number.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.margin.is_finite() || self.margin < 0f32 {
+            return Err(format!("margin must be a non-negative number, but is {}", self.margin));
+        }
+        Ok(())
+    }
+}
+
+impl Into<LayerType> for TripletLossConfig {
+    fn into(self) -> LayerType {
+        LayerType::TripletLoss(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use super::{TripletLoss, TripletLossConfig};
+    use util::{native_backend, write_to_memory};
+
+    fn tensor(data: &[f32]) -> SharedTensor<f32> {
+        let native = native_backend();
+        let mut tensor = SharedTensor::new(&[1, data.len()]);
+        write_to_memory(tensor.write_only(native.device()).unwrap(), data);
+        tensor
+    }
+
+    fn loss_and_gradients(anchor: &[f32], positive: &[f32], negative: &[f32]) -> (f32, Vec<Vec<f32>>) {
+        let native = native_backend();
+        let layer = TripletLoss::from_config(&TripletLossConfig::default());
+        let inputs = [tensor(anchor), tensor(positive), tensor(negative)];
+        let input_refs = inputs.iter().collect::<Vec<_>>();
+
+        let mut loss = SharedTensor::new(&[1]);
+        layer.compute_output(&native, &[], &input_refs, &mut [&mut loss]);
+
+        let mut gradients = vec![SharedTensor::new(&[1, anchor.len()]),
+                                 SharedTensor::new(&[1, anchor.len()]),
+                                 SharedTensor::new(&[1, anchor.len()])];
+        {
+            let mut gradient_refs = gradients.iter_mut().collect::<Vec<_>>();
+            layer.compute_input_gradient(&native, &[], &[], &[], &input_refs, &mut gradient_refs);
+        }
+
+        let loss = loss.read(native.device()).unwrap().as_slice::<f32>()[0];
+        let gradients = gradients.iter()
+            .map(|gradient| gradient.read(native.device()).unwrap().as_slice::<f32>().to_vec())
+            .collect();
+        (loss, gradients)
+    }
+
+    #[test]
+    fn satisfied_margin_has_no_loss() {
+        let (loss, gradients) = loss_and_gradients(&[0.0, 0.0], &[0.5, 0.0], &[2.0, 0.0]);
+        assert_eq!(0f32, loss);
+        for gradient in gradients {
+            assert_eq!(vec![0f32, 0f32], gradient);
+        }
+    }
+
+    #[test]
+    fn violated_margin_pulls_positive_and_pushes_negative() {
+        let (loss, gradients) = loss_and_gradients(&[0.0, 0.0], &[1.0, 0.0], &[0.0, 1.0]);
+        // 1 - 1 + margin
+        assert_eq!(1f32, loss);
+        // descending the gradient moves the positive towards the anchor ...
+        assert!(gradients[1][0] > 0f32);
+        // ... and the negative away from it
+        assert!(gradients[2][1] < 0f32);
+        // the anchor moves towards the positive and away from the negative
+        assert_eq!(vec![-2f32, 2f32], gradients[0]);
+    }
+}
@@ -39,32 +39,55 @@
 //! For more information about how these layers work together, see the
 //! documentation for the general [Layer module][3].
 //!
+//! Layers that are not provided by Juice can be registered as [custom layers][mod_custom].
+//!
+//! ## Host Layers
+//!
+//! Not every layer has kernels for the device backends yet. The [ELU][elu], [BatchNorm][batch_norm]
+//! and [Dropout][dropout] layers, the loss layers other than the [contrastive loss][contrastive_loss]
+//! and the layout conversions compute on the host, see [ILayer::sync_native][4], so on a
+//! device their inputs are copied to the host and their outputs back, which is
+//! [recorded][5] like the other transfers.
+//!
 //! [2]: https://en.wikipedia.org/wiki/Activation_function
 //! [3]: ../layer/index.html
+//! [4]: ../layer/trait.ILayer.html#method.sync_native
+//! [5]: ../transfer/index.html
 //!
 //! [mod_activation]: ./activation/index.html
 //! [mod_common]: ./common/index.html
 //! [mod_loss]: ./loss/index.html
+//! [contrastive_loss]: ./loss/contrastive_loss/index.html
 //! [mod_utility]: ./utility/index.html
 //! [mod_container]: ./container/index.html
+//! [mod_custom]: ./custom/index.html
+//! [elu]: ./activation/elu/index.html
+//! [batch_norm]: ./common/batch_norm/index.html
+//! [dropout]: ./common/dropout/index.html
 
 /// Implement [ILayer][1] for [activation layers][2].
 /// [1]: ./layer/trait.ILayer.html
 /// [2]: ./layers/activation/index.html
 
-pub use self::activation::{ReLU, Sigmoid, TanH};
+pub use self::activation::{ELU, ELUConfig, ReLU, Sigmoid, TanH};
 
-pub use self::common::{Convolution, ConvolutionConfig, Pooling, PoolingConfig, PoolingMode, Linear, LinearConfig,
-                       LogSoftmax, Softmax};
+pub use self::common::{BatchNorm, BatchNormConfig, Convolution, ConvolutionConfig, Dropout, DropoutConfig, Pooling,
+                       PoolingConfig, PoolingMode, Linear, LinearConfig, LogSoftmax, LRN, LRNConfig, MeanScale,
+                       Softmax, SpectralNorm, SpectralNormConfig, StochasticDepth, StochasticDepthConfig};
 
 pub use self::container::{Sequential, SequentialConfig};
 
-pub use self::loss::{NegativeLogLikelihood, NegativeLogLikelihoodConfig};
+pub use self::custom::{CustomConfig, LayerFactory, register_layer_factory};
+
+pub use self::loss::{ContrastiveLoss, ContrastiveLossConfig, NegativeLogLikelihood, NegativeLogLikelihoodConfig,
+                     SmoothL1Loss, SmoothL1LossConfig, SoftmaxLoss, SoftmaxLossConfig, TripletLoss,
+                     TripletLossConfig};
 
-pub use self::utility::{Flatten, Reshape, ReshapeConfig};
+pub use self::utility::{Flatten, NchwToNhwc, NhwcToNchw, Reshape, ReshapeConfig, Split, SplitConfig};
 
 pub mod activation;
 pub mod common;
 pub mod loss;
 pub mod utility;
 pub mod container;
+pub mod custom;
@@ -0,0 +1,161 @@
+//! Utility layers to convert 4D tensors between the NCHW and NHWC layouts.
+//!
+//! The letters stand for:
+//!
+//! - `N` : number of batch samples
+//! - `C` : number of feature maps
+//! - `H` : height
+//! - `W` : width
+//!
+//! The layers of Juice expect NCHW, while models imported from other frameworks
+//! might use NHWC. The conversion permutes the axes of the data, and the inverse
+//! permutation is applied to the gradient in the backward pass.
+//!
+//! The permutation is computed on the host.
+
+use co::{IBackend, SharedTensor};
+use layer::*;
+use util::{ArcLock, native_backend, write_to_memory};
+
+/// Returns the shape of a 4D tensor of shape `shape` after permuting its axes.
+fn permuted_shape(shape: &[usize], axes: &[usize; 4]) -> Vec<usize> {
+    assert_eq!(4, shape.len(), "Layout conversion layers only support 4D inputs");
+    axes.iter().map(|&axis| shape[axis]).collect()
+}
+
+/// Permutes the axes of a 4D tensor of shape `shape`, so axis `i` of the result
+/// is axis `axes[i]` of the input.
+fn permute(x: &[f32], shape: &[usize], axes: &[usize; 4]) -> Vec<f32> {
+    let mut strides = [1usize; 4];
+    for i in (0..3).rev() {
+        strides[i] = strides[i + 1] * shape[i + 1];
+    }
+    let result_shape = permuted_shape(shape, axes);
+
+    let mut result = Vec::with_capacity(x.len());
+    for a in 0..result_shape[0] {
+        for b in 0..result_shape[1] {
+            for c in 0..result_shape[2] {
+                for d in 0..result_shape[3] {
+                    let index = a * strides[axes[0]] + b * strides[axes[1]] + c * strides[axes[2]] +
+                                d * strides[axes[3]];
+                    result.push(x[index]);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Permutes the axes of `x` into `result`.
+fn permute_tensor(x: &SharedTensor<f32>, axes: &[usize; 4], result: &mut SharedTensor<f32>) {
+    let native = native_backend();
+    let permuted = permute(x.read(native.device()).unwrap().as_slice::<f32>(), x.desc(), axes);
+    write_to_memory(result.write_only(native.device()).unwrap(), &permuted);
+}
+
+macro_rules! impl_layout_conversion {
+    ($layer:ident, $axes:expr, $inverse_axes:expr) => (
+        impl<B: IBackend> ILayer<B> for $layer {
+            fn exact_num_output_blobs(&self) -> Option<usize> { Some(1) }
+            fn exact_num_input_blobs(&self) -> Option<usize> { Some(1) }
+
+            fn sync_native(&self) -> bool {
+                true
+            }
+
+            fn reshape(&mut self,
+                       backend: ::std::rc::Rc<B>,
+                       input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                       input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                       weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                       weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                       output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+                       output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+                let input_shape = input_data[0].read().unwrap().desc().clone();
+                let output_shape = permuted_shape(&input_shape, &$axes);
+                input_gradient[0].write().unwrap().resize(&input_shape).unwrap();
+                output_data[0].write().unwrap().resize(&output_shape).unwrap();
+                output_gradient[0].write().unwrap().resize(&output_shape).unwrap();
+            }
+
+            /// The values are only moved, not computed.
+            fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+                0
+            }
+        }
+
+        impl<B: IBackend> ComputeOutput<f32, B> for $layer {
+            fn compute_output(&self,
+                              backend: &B,
+                              _weights: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              output_data: &mut [&mut SharedTensor<f32>]) {
+                permute_tensor(input_data[0], &$axes, output_data[0]);
+            }
+        }
+
+        impl<B: IBackend> ComputeInputGradient<f32, B> for $layer {
+            fn compute_input_gradient(&self,
+                                      backend: &B,
+                                      weights_data: &[&SharedTensor<f32>],
+                                      output_data: &[&SharedTensor<f32>],
+                                      output_gradients: &[&SharedTensor<f32>],
+                                      input_data: &[&SharedTensor<f32>],
+                                      input_gradients: &mut [&mut SharedTensor<f32>]) {
+                permute_tensor(output_gradients[0], &$inverse_axes, input_gradients[0]);
+            }
+        }
+
+        impl<B: IBackend> ComputeParametersGradient<f32, B> for $layer {}
+    )
+}
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// Converts a NCHW tensor into a NHWC tensor.
+pub struct NchwToNhwc;
+
+impl_layout_conversion!(NchwToNhwc, [0, 2, 3, 1], [0, 3, 1, 2]);
+
+#[derive(Debug, Clone)]
+#[allow(missing_copy_implementations)]
+/// Converts a NHWC tensor into a NCHW tensor.
+pub struct NhwcToNchw;
+
+impl_layout_conversion!(NhwcToNchw, [0, 3, 1, 2], [0, 2, 3, 1]);
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use super::{NchwToNhwc, NhwcToNchw};
+    use util::native_backend;
+    use util::testing::shared_tensor;
+
+    #[test]
+    fn round_trip_restores_values_and_layout() {
+        let native = native_backend();
+        let values = (0..96).map(|i| i as f32).collect::<Vec<_>>();
+        let nchw = shared_tensor(&[2, 3, 4, 4], &values);
+
+        let mut nhwc = SharedTensor::new(&[2, 4, 4, 3]);
+        NchwToNhwc.compute_output(&native, &[], &[&nchw], &mut [&mut nhwc]);
+        {
+            let data = nhwc.read(native.device()).unwrap().as_slice::<f32>();
+            // n = 1, h = 2, w = 3, c = 1 is at n = 1, c = 1, h = 2, w = 3 in NCHW
+            assert_eq!(values[48 + 16 + 8 + 3], data[48 + 2 * 12 + 3 * 3 + 1]);
+        }
+
+        let mut restored = SharedTensor::new(&[2, 3, 4, 4]);
+        NhwcToNchw.compute_output(&native, &[], &[&nhwc], &mut [&mut restored]);
+        assert_eq!(&vec![2, 3, 4, 4], restored.desc());
+        assert_eq!(&values[..], restored.read(native.device()).unwrap().as_slice::<f32>());
+
+        // the backward pass applies the inverse permutation to the gradient
+        let mut gradient = SharedTensor::new(&[2, 3, 4, 4]);
+        NchwToNhwc.compute_input_gradient(&native, &[], &[], &[&nhwc], &[&nchw], &mut [&mut gradient]);
+        assert_eq!(&values[..], gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+}
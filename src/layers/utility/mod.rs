@@ -11,7 +11,11 @@
 //! [1]: ../../layer/index.html
 
 pub use self::flatten::Flatten;
+pub use self::layout::{NchwToNhwc, NhwcToNchw};
 pub use self::reshape::{Reshape, ReshapeConfig};
+pub use self::split::{Split, SplitConfig};
 
 pub mod flatten;
+pub mod layout;
 pub mod reshape;
+pub mod split;
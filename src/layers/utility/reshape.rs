@@ -55,6 +55,10 @@ impl<B: IBackend> ILayer<B> for Reshape {
         output_data[0].write().unwrap().resize(&self.shape).unwrap();
         output_gradient[0].write().unwrap().resize(&self.shape).unwrap();
     }
+
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        0
+    }
 }
 
 impl<B: IBackend> ComputeOutput<f32, B> for Reshape {
@@ -79,7 +83,7 @@ impl<B: IBackend> ComputeInputGradient<f32, B> for Reshape {
 
 impl<B: IBackend> ComputeParametersGradient<f32, B> for Reshape {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Specifies configuration parameters for a Reshape Layer.
 pub struct ReshapeConfig {
     /// The target shape that the input should assume.
@@ -95,6 +99,14 @@ impl ReshapeConfig {
     pub fn of_shape(shape: &[usize]) -> ReshapeConfig {
         ReshapeConfig { shape: shape.to_owned() }
     }
+
+    /// Checks that the shape has no zero dimension.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.shape.contains(&0) {
+            return Err(format!("shape must not contain a zero dimension, but is {:?}", self.shape));
+        }
+        Ok(())
+    }
 }
 
 impl<'a> CapnpWrite<'a> for ReshapeConfig {
@@ -0,0 +1,367 @@
+//! Utility layer to split a tensor into chunks along an axis.
+//!
+//! The input is divided along [SplitConfig.axis][1] into either a number of equally
+//! sized chunks or chunks of explicitly specified sizes. Every chunk is written into
+//! an output blob of its own, so the layer needs one output per chunk.
+//! [1]: ./struct.SplitConfig.html#structfield.axis
+//!
+//! This is the inverse of a concatenation, e.g. to separate the heads of a multi-head
+//! attention or the halves of a gating unit. The backward pass concatenates the
+//! gradients of the chunks back into the gradient of the input.
+//!
+//! SharedTensors can not alias parts of each other, so the chunks are copies of
+//! the slices of the input. The input is viewed as a matrix with one row per index before
+//! the split axis, and every chunk is a block of its columns, which is copied on the device
+//! by multiplying the matrix with a [column_block][2] with gemm.
+//! [2]: ../../../util/fn.column_block.html
+
+use capnp_util::*;
+use co::{IBackend, ITensorDesc, SharedTensor};
+use coblas::plugin::Gemm;
+use coblas::transpose::Transpose;
+use layer::*;
+use juice_capnp::split_config as capnp_config;
+use std::cell::RefCell;
+use util::{ArcLock, WeightedSum, column_block, native_scalar};
+
+#[derive(Debug)]
+/// Split Utility Layer
+pub struct Split {
+    axis: usize,
+    num_chunks: usize,
+    sizes: Vec<usize>,
+
+    /// The input, or the gradient of a chunk, viewed as a matrix.
+    rows: RefCell<SharedTensor<f32>>,
+}
+
+impl Split {
+    /// Create a Split layer from a SplitConfig.
+    ///
+    /// Returns an error if the config is [invalid](./struct.SplitConfig.html#method.validate).
+    pub fn from_config(config: &SplitConfig) -> Result<Split, String> {
+        try!(config.validate());
+        Ok(Split {
+            axis: config.axis,
+            num_chunks: config.num_chunks(),
+            sizes: config.sizes.clone(),
+
+            rows: RefCell::new(SharedTensor::new(&[1])),
+        })
+    }
+
+    /// Returns the sizes of the chunks of an axis of size `axis_size`.
+    fn chunk_sizes(&self, axis_size: usize) -> Vec<usize> {
+        if self.sizes.is_empty() {
+            assert!(axis_size % self.num_chunks == 0,
+                    "Split: axis {} of size {} can not be divided into {} equal chunks",
+                    self.axis,
+                    axis_size,
+                    self.num_chunks);
+            vec![axis_size / self.num_chunks; self.num_chunks]
+        } else {
+            let total = self.sizes.iter().fold(0, |sum, size| sum + size);
+            assert_eq!(axis_size,
+                       total,
+                       "Split: the chunk sizes {:?} do not add up to the size of axis {}",
+                       self.sizes,
+                       self.axis);
+            self.sizes.clone()
+        }
+    }
+
+    /// Returns the number of elements before and after the split axis of `shape`.
+    fn outer_inner(&self, shape: &[usize]) -> (usize, usize) {
+        assert!(self.axis < shape.len(),
+                "Split: axis {} is out of range for an input of shape {:?}",
+                self.axis,
+                shape);
+        let outer = shape[..self.axis].iter().fold(1, |prod, dim| prod * dim);
+        let inner = shape[self.axis + 1..].iter().fold(1, |prod, dim| prod * dim);
+        (outer, inner)
+    }
+}
+
+impl<B: IBackend + Gemm<f32> + WeightedSum<f32>> ILayer<B> for Split {
+    fn exact_num_input_blobs(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn exact_num_output_blobs(&self) -> Option<usize> {
+        Some(self.num_chunks)
+    }
+
+    fn reshape(&mut self,
+               backend: ::std::rc::Rc<B>,
+               input_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               input_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               weights_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_data: &mut Vec<ArcLock<SharedTensor<f32>>>,
+               output_gradient: &mut Vec<ArcLock<SharedTensor<f32>>>) {
+        let input_shape = input_data[0].read().unwrap().desc().clone();
+        self.outer_inner(&input_shape);
+        input_gradient[0].write().unwrap().resize(&input_shape).unwrap();
+        let chunk_sizes = self.chunk_sizes(input_shape[self.axis]);
+        for (i, chunk_size) in chunk_sizes.into_iter().enumerate() {
+            let mut chunk_shape = input_shape.clone();
+            chunk_shape[self.axis] = chunk_size;
+            output_data[i].write().unwrap().resize(&chunk_shape).unwrap();
+            output_gradient[i].write().unwrap().resize(&chunk_shape).unwrap();
+        }
+    }
+
+    /// The values are only copied into the chunks, not computed.
+    fn flops(&self, input_shapes: &[Vec<usize>], output_shapes: &[Vec<usize>]) -> u64 {
+        0
+    }
+}
+
+impl<B: IBackend + Gemm<f32> + WeightedSum<f32>> ComputeOutput<f32, B> for Split {
+    fn compute_output(&self,
+                      backend: &B,
+                      _weights: &[&SharedTensor<f32>],
+                      input_data: &[&SharedTensor<f32>],
+                      output_data: &mut [&mut SharedTensor<f32>]) {
+        let (outer, inner) = self.outer_inner(input_data[0].desc());
+        let chunk_sizes = self.chunk_sizes(input_data[0].desc()[self.axis]);
+        let row_size = input_data[0].desc().size() / outer;
+        let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+
+        let mut rows = self.rows.borrow_mut();
+        backend.weighted_sum(&[1f32], &[input_data[0]], &mut rows).unwrap();
+        rows.reshape(&[outer, row_size]).unwrap();
+        let mut offset = 0;
+        for (chunk_size, output) in chunk_sizes.into_iter().zip(output_data.iter_mut()) {
+            let chunk_row_size = chunk_size * inner;
+            let chunk_shape = output.desc().clone();
+            output.reshape(&[outer, chunk_row_size]).unwrap();
+            backend.gemm(&one,
+                         Transpose::NoTrans,
+                         &rows,
+                         Transpose::NoTrans,
+                         &column_block(row_size, offset, chunk_row_size),
+                         &zero,
+                         output)
+                .unwrap();
+            output.reshape(&chunk_shape).unwrap();
+            offset += chunk_row_size;
+        }
+    }
+}
+
+impl<B: IBackend + Gemm<f32> + WeightedSum<f32>> ComputeInputGradient<f32, B> for Split {
+    fn compute_input_gradient(&self,
+                              backend: &B,
+                              weights_data: &[&SharedTensor<f32>],
+                              output_data: &[&SharedTensor<f32>],
+                              output_gradients: &[&SharedTensor<f32>],
+                              input_data: &[&SharedTensor<f32>],
+                              input_gradients: &mut [&mut SharedTensor<f32>]) {
+        let (outer, inner) = self.outer_inner(input_data[0].desc());
+        let chunk_sizes = self.chunk_sizes(input_data[0].desc()[self.axis]);
+        let row_size = input_data[0].desc().size() / outer;
+        let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+
+        let input_shape = input_data[0].desc().clone();
+        input_gradients[0].reshape(&[outer, row_size]).unwrap();
+        let mut chunk_rows = self.rows.borrow_mut();
+        let mut offset = 0;
+        for (i, (chunk_size, output_gradient)) in chunk_sizes.into_iter().zip(output_gradients).enumerate() {
+            let chunk_row_size = chunk_size * inner;
+            backend.weighted_sum(&[1f32], &[*output_gradient], &mut chunk_rows).unwrap();
+            chunk_rows.reshape(&[outer, chunk_row_size]).unwrap();
+            // the first chunk overwrites the gradient, the others are scattered into it
+            backend.gemm(&one,
+                         Transpose::NoTrans,
+                         &chunk_rows,
+                         Transpose::Trans,
+                         &column_block(row_size, offset, chunk_row_size),
+                         if i == 0 { &zero } else { &one },
+                         &mut input_gradients[0])
+                .unwrap();
+            offset += chunk_row_size;
+        }
+        input_gradients[0].reshape(&input_shape).unwrap();
+    }
+}
+
+impl<B: IBackend> ComputeParametersGradient<f32, B> for Split {}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Specifies configuration parameters for a Split Layer.
+pub struct SplitConfig {
+    /// The axis along which the input is split.
+    pub axis: usize,
+    /// The sizes of the chunks along `axis`, which have to add up to the size of the axis.
+    ///
+    /// If empty, the axis is split into `num_chunks` chunks of equal size instead.
+    pub sizes: Vec<usize>,
+    /// The number of equally sized chunks, if no `sizes` are specified.
+    ///
+    /// The size of the axis has to be divisible by it.
+    pub num_chunks: usize,
+}
+
+impl SplitConfig {
+    /// Create a SplitConfig that splits `axis` into `num_chunks` chunks of equal size.
+    pub fn equal(axis: usize, num_chunks: usize) -> SplitConfig {
+        assert!(num_chunks > 0, "Split: the number of chunks must be positive");
+        SplitConfig {
+            axis: axis,
+            sizes: Vec::new(),
+            num_chunks: num_chunks,
+        }
+    }
+
+    /// Create a SplitConfig that splits `axis` into chunks of the sizes `sizes`.
+    pub fn with_sizes(axis: usize, sizes: &[usize]) -> SplitConfig {
+        SplitConfig {
+            axis: axis,
+            sizes: sizes.to_owned(),
+            num_chunks: sizes.len(),
+        }
+    }
+
+    /// Checks that there is at least one chunk and no chunk is empty.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sizes.is_empty() && self.num_chunks == 0 {
+            return Err("num_chunks must be positive, but is 0".to_owned());
+        }
+        if self.sizes.contains(&0) {
+            return Err(format!("sizes must be positive, but are {:?}", self.sizes));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of chunks, and therefore output blobs, of the layer.
+    pub fn num_chunks(&self) -> usize {
+        if self.sizes.is_empty() {
+            self.num_chunks
+        } else {
+            self.sizes.len()
+        }
+    }
+}
+
+impl<'a> CapnpWrite<'a> for SplitConfig {
+    type Builder = capnp_config::Builder<'a>;
+
+    /// Write the SplitConfig into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_axis(self.axis as u64);
+        builder.set_num_chunks(self.num_chunks as u64);
+        let mut sizes = builder.borrow().init_sizes(self.sizes.len() as u32);
+        for (i, size) in self.sizes.iter().enumerate() {
+            sizes.set(i as u32, *size as u64);
+        }
+    }
+}
+
+impl<'a> CapnpRead<'a> for SplitConfig {
+    type Reader = capnp_config::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let read_sizes = reader.get_sizes().unwrap();
+        let mut sizes = Vec::new();
+        for i in 0..read_sizes.len() {
+            sizes.push(read_sizes.get(i) as usize)
+        }
+
+        SplitConfig {
+            axis: reader.get_axis() as usize,
+            sizes: sizes,
+            num_chunks: reader.get_num_chunks() as usize,
+        }
+    }
+}
+
+impl Into<LayerType> for SplitConfig {
+    fn into(self) -> LayerType {
+        LayerType::Split(self)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use super::{Split, SplitConfig};
+    use util::native_backend;
+    use util::testing::shared_tensor;
+
+    #[test]
+    fn split_into_equal_chunks_and_reassemble_gradient() {
+        let native = native_backend();
+        let layer = Split::from_config(&SplitConfig::equal(1, 3)).unwrap();
+        let values = (0..12).map(|i| i as f32).collect::<Vec<_>>();
+        let input = shared_tensor(&[2, 6], &values);
+
+        let mut chunks = vec![SharedTensor::new(&[2, 2]), SharedTensor::new(&[2, 2]), SharedTensor::new(&[2, 2])];
+        {
+            let mut outputs = chunks.iter_mut().collect::<Vec<_>>();
+            layer.compute_output(&native, &[], &[&input], &mut outputs);
+        }
+        assert_eq!(&[0f32, 1.0, 6.0, 7.0], chunks[0].read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[2f32, 3.0, 8.0, 9.0], chunks[1].read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[4f32, 5.0, 10.0, 11.0], chunks[2].read(native.device()).unwrap().as_slice::<f32>());
+
+        // the chunks are their own gradients, so the reassembled gradient equals the input
+        let mut gradient = SharedTensor::new(&[2, 6]);
+        {
+            let chunk_gradients = chunks.iter().collect::<Vec<_>>();
+            layer.compute_input_gradient(&native, &[], &[], &chunk_gradients, &[&input], &mut [&mut gradient]);
+        }
+        assert_eq!(&values[..], gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn split_into_chunks_of_specified_sizes() {
+        let native = native_backend();
+        let layer = Split::from_config(&SplitConfig::with_sizes(0, &[1, 2])).unwrap();
+        let input = shared_tensor(&[3, 2], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let mut first = SharedTensor::new(&[1, 2]);
+        let mut second = SharedTensor::new(&[2, 2]);
+        layer.compute_output(&native, &[], &[&input], &mut [&mut first, &mut second]);
+        assert_eq!(&[1f32, 2.0], first.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[3f32, 4.0, 5.0, 6.0], second.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn split_inner_axis_of_higher_rank_input() {
+        let native = native_backend();
+        let layer = Split::from_config(&SplitConfig::with_sizes(1, &[2, 1])).unwrap();
+        let values = (0..12).map(|i| i as f32).collect::<Vec<_>>();
+        let input = shared_tensor(&[2, 3, 2], &values);
+
+        let mut first = SharedTensor::new(&[2, 2, 2]);
+        let mut second = SharedTensor::new(&[2, 1, 2]);
+        layer.compute_output(&native, &[], &[&input], &mut [&mut first, &mut second]);
+        assert_eq!(&[2, 2, 2], &first.desc()[..]);
+        assert_eq!(&[0f32, 1.0, 2.0, 3.0, 6.0, 7.0, 8.0, 9.0],
+                   first.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[4f32, 5.0, 10.0, 11.0], second.read(native.device()).unwrap().as_slice::<f32>());
+
+        let mut gradient = SharedTensor::new(&[2, 3, 2]);
+        layer.compute_input_gradient(&native, &[], &[], &[&first, &second], &[&input], &mut [&mut gradient]);
+        assert_eq!(&[2, 3, 2], &gradient.desc()[..]);
+        assert_eq!(&values[..], gradient.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn config_without_chunks_is_rejected() {
+        assert!(Split::from_config(&SplitConfig::with_sizes(0, &[])).unwrap_err().contains("num_chunks"));
+        let config = SplitConfig { num_chunks: 0, ..SplitConfig::equal(0, 1) };
+        assert!(Split::from_config(&config).is_err());
+        assert!(Split::from_config(&SplitConfig::with_sizes(0, &[2, 0])).unwrap_err().contains("sizes"));
+    }
+
+    #[test]
+    #[should_panic(expected = "can not be divided into 4 equal chunks")]
+    fn indivisible_axis_is_rejected() {
+        let layer = Split::from_config(&SplitConfig::equal(1, 4)).unwrap();
+        layer.chunk_sizes(6);
+    }
+}
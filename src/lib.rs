@@ -112,15 +112,33 @@ extern crate timeit;
 #[macro_use]
 extern crate log;
 extern crate rand;
+extern crate memmap;
+#[cfg(feature = "serialization")]
+extern crate serde;
+#[cfg(feature = "serialization")]
+#[macro_use]
+extern crate serde_derive;
 extern crate num;
 extern crate capnp;
 extern crate coaster as co;
 extern crate coaster_blas as coblas;
 extern crate coaster_nn as conn;
+pub mod augmentation;
+pub mod bench;
+pub mod data;
+pub mod decoding;
+pub mod diff;
+pub mod ensemble;
 pub mod layer;
 pub mod layers;
+pub mod metrics;
+pub mod models;
+pub mod net2net;
+pub mod op_cache;
+pub mod serve;
 pub mod solver;
 pub mod solvers;
+pub mod transfer;
 pub mod weight;
 
 pub mod util;
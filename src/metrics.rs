@@ -0,0 +1,210 @@
+//! Provides metrics to evaluate the predictions of a classifier.
+
+use co::{ITensorDesc, SharedTensor};
+use std::fmt;
+use util::native_backend;
+
+/// Accumulates per-class [precision and recall][wiki] over batches of predictions.
+///
+/// Precision, recall and F1 score are undefined for a class that has never been
+/// predicted, never been the target or both. By convention they are reported as `0`
+/// in that case, so they lower the macro averages instead of turning them into NaN.
+///
+/// [wiki]: https://en.wikipedia.org/wiki/Precision_and_recall
+#[derive(Debug, Clone)]
+pub struct PrecisionRecall {
+    true_positives: Vec<usize>,
+    false_positives: Vec<usize>,
+    false_negatives: Vec<usize>,
+}
+
+impl PrecisionRecall {
+    /// Create a PrecisionRecall accumulator for `num_classes` classes.
+    pub fn new(num_classes: usize) -> PrecisionRecall {
+        PrecisionRecall {
+            true_positives: vec![0; num_classes],
+            false_positives: vec![0; num_classes],
+            false_negatives: vec![0; num_classes],
+        }
+    }
+
+    /// Returns the number of classes.
+    pub fn num_classes(&self) -> usize {
+        self.true_positives.len()
+    }
+
+    /// Add a sample by providing the `prediction` and the expected `target` class.
+    pub fn add_sample(&mut self, prediction: usize, target: usize) {
+        assert!(prediction < self.num_classes() && target < self.num_classes(),
+                "Class out of range for {} classes: prediction {}, target {}",
+                self.num_classes(),
+                prediction,
+                target);
+        if prediction == target {
+            self.true_positives[target] += 1;
+        } else {
+            self.false_positives[prediction] += 1;
+            self.false_negatives[target] += 1;
+        }
+    }
+
+    /// Add a batch of samples.
+    ///
+    /// See [add_sample](#method.add_sample).
+    pub fn add_samples(&mut self, predictions: &[usize], targets: &[usize]) {
+        for (&prediction, &target) in predictions.iter().zip(targets.iter()) {
+            self.add_sample(prediction, target);
+        }
+    }
+
+    /// Add a batch of samples from the output of a network of shape `[batch_size, num_classes]`.
+    ///
+    /// The prediction for each sample is the class with the highest output value.
+    pub fn add_network_output(&mut self, network_out: &SharedTensor<f32>, targets: &[usize]) {
+        let native = native_backend();
+        self.add_outputs(network_out.read(native.device()).unwrap().as_slice::<f32>(), targets);
+    }
+
+    /// Add a batch of samples from network outputs in host memory, e.g. the averaged
+    /// outputs of [predict_multicrop][1].
+    /// [1]: ../augmentation/fn.predict_multicrop.html
+    ///
+    /// See [add_network_output](#method.add_network_output).
+    pub fn add_outputs(&mut self, outputs: &[f32], targets: &[usize]) {
+        assert_eq!(0, outputs.len() % self.num_classes());
+        let predictions = outputs.chunks(self.num_classes())
+            .map(|sample| {
+                sample.iter()
+                    .enumerate()
+                    .fold((0, ::std::f32::NEG_INFINITY), |best, (class, &value)| {
+                        if value > best.1 { (class, value) } else { best }
+                    })
+                    .0
+            })
+            .collect::<Vec<_>>();
+        self.add_samples(&predictions, targets);
+    }
+
+    /// Returns the precision of `class`, the fraction of its predictions that are correct.
+    pub fn precision(&self, class: usize) -> f32 {
+        Self::ratio(self.true_positives[class],
+                    self.true_positives[class] + self.false_positives[class])
+    }
+
+    /// Returns the recall of `class`, the fraction of its samples that have been predicted.
+    pub fn recall(&self, class: usize) -> f32 {
+        Self::ratio(self.true_positives[class],
+                    self.true_positives[class] + self.false_negatives[class])
+    }
+
+    /// Returns the F1 score of `class`, the harmonic mean of precision and recall.
+    pub fn f1(&self, class: usize) -> f32 {
+        let precision = self.precision(class);
+        let recall = self.recall(class);
+        if precision + recall == 0f32 {
+            0f32
+        } else {
+            2f32 * precision * recall / (precision + recall)
+        }
+    }
+
+    /// Returns the precision averaged over all classes.
+    pub fn macro_precision(&self) -> f32 {
+        self.macro_average(|class| self.precision(class))
+    }
+
+    /// Returns the recall averaged over all classes.
+    pub fn macro_recall(&self) -> f32 {
+        self.macro_average(|class| self.recall(class))
+    }
+
+    /// Returns the F1 score averaged over all classes.
+    pub fn macro_f1(&self) -> f32 {
+        self.macro_average(|class| self.f1(class))
+    }
+
+    fn macro_average<F: Fn(usize) -> f32>(&self, metric: F) -> f32 {
+        if self.num_classes() == 0 {
+            return 0f32;
+        }
+        (0..self.num_classes()).map(metric).fold(0f32, |sum, value| sum + value) / self.num_classes() as f32
+    }
+
+    fn ratio(numerator: usize, denominator: usize) -> f32 {
+        if denominator == 0 {
+            0f32
+        } else {
+            numerator as f32 / denominator as f32
+        }
+    }
+}
+
+impl fmt::Display for PrecisionRecall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for class in 0..self.num_classes() {
+            try!(writeln!(f,
+                          "Class {}: precision {:.4}, recall {:.4}, F1 {:.4}",
+                          class,
+                          self.precision(class),
+                          self.recall(class),
+                          self.f1(class)));
+        }
+        write!(f,
+               "Macro average: precision {:.4}, recall {:.4}, F1 {:.4}",
+               self.macro_precision(),
+               self.macro_recall(),
+               self.macro_f1())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use super::PrecisionRecall;
+    use util::{native_backend, write_to_memory};
+
+    fn assert_close(expected: f32, actual: f32) {
+        assert!((expected - actual).abs() < 1e-6, "{} != {}", expected, actual);
+    }
+
+    #[test]
+    fn per_class_and_macro_metrics() {
+        let mut metrics = PrecisionRecall::new(3);
+        // class 2 is never predicted
+        metrics.add_samples(&[0, 0, 1], &[0, 1, 1]);
+        metrics.add_samples(&[1, 0, 1], &[2, 0, 2]);
+
+        // class 0: tp 2, fp 1, fn 0
+        assert_close(2.0 / 3.0, metrics.precision(0));
+        assert_close(1.0, metrics.recall(0));
+        assert_close(0.8, metrics.f1(0));
+        // class 1: tp 1, fp 2, fn 1
+        assert_close(1.0 / 3.0, metrics.precision(1));
+        assert_close(0.5, metrics.recall(1));
+        assert_close(0.4, metrics.f1(1));
+        // class 2: tp 0, fp 0, fn 2
+        assert_close(0.0, metrics.precision(2));
+        assert_close(0.0, metrics.recall(2));
+        assert_close(0.0, metrics.f1(2));
+
+        assert_close(1.0 / 3.0, metrics.macro_precision());
+        assert_close(0.5, metrics.macro_recall());
+        assert_close(0.4, metrics.macro_f1());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn predictions_from_network_output() {
+        let native = native_backend();
+        let mut output = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(output.write_only(native.device()).unwrap(),
+                        &[0.1f32, 0.7, 0.2, 0.5, 0.3, 0.2]);
+
+        let mut metrics = PrecisionRecall::new(3);
+        metrics.add_network_output(&output, &[1, 2]);
+        assert_close(1.0, metrics.precision(1));
+        assert_close(0.0, metrics.precision(0));
+        assert_close(0.0, metrics.recall(2));
+    }
+}
@@ -0,0 +1,166 @@
+//! Provides the configs of well-known networks.
+//!
+//! The functions return fully wired [SequentialConfigs][1] that can be passed to a
+//! [SolverConfig][2] or [Layer::from_config][3]. The classifiers end with a [LogSoftmax][4],
+//! so they are trained with the [NegativeLogLikelihood][5] objective of [nll_objective][6].
+//! Their weights are initialized with the defaults of their layers.
+//!
+//! ```
+//! # use juice::models;
+//! # use juice::solver::SolverConfig;
+//! # use juice::layer::LayerConfig;
+//! let config = SolverConfig {
+//!     network: LayerConfig::new("network", models::lenet(32, 10)),
+//!     objective: LayerConfig::new("objective", models::nll_objective(32, 10)),
+//!     ..SolverConfig::default()
+//! };
+//! ```
+//!
+//! [1]: ../layers/container/struct.SequentialConfig.html
+//! [2]: ../solver/struct.SolverConfig.html
+//! [3]: ../layer/struct.Layer.html#method.from_config
+//! [4]: ../layers/common/log_softmax/index.html
+//! [5]: ../layers/loss/negative_log_likelihood/index.html
+//! [6]: ./fn.nll_objective.html
+
+use layer::{LayerConfig, LayerType};
+use layers::{ConvolutionConfig, LinearConfig, NegativeLogLikelihoodConfig, PoolingConfig, PoolingMode,
+             SequentialConfig};
+
+fn conv(num_output: usize, filter_size: usize, padding: usize) -> ConvolutionConfig {
+    ConvolutionConfig {
+        num_output: num_output,
+        filter_shape: vec![filter_size],
+        stride: vec![1],
+        padding: vec![padding],
+        bias: true,
+    }
+}
+
+fn max_pool() -> PoolingConfig {
+    PoolingConfig {
+        mode: PoolingMode::Max,
+        filter_shape: vec![2],
+        stride: vec![2],
+        padding: vec![0],
+        padding_value: None,
+    }
+}
+
+/// Returns the config of a multilayer perceptron with the layer sizes `sizes`.
+///
+/// `sizes[0]` is the size of the input of shape `[batch_size, sizes[0]]`, the following
+/// sizes are the outputs of the Linear layers, the last one being the number of classes.
+/// All but the last Linear layer are followed by a ReLU.
+///
+/// Panics if there are less than two sizes.
+pub fn mlp(batch_size: usize, sizes: &[usize]) -> SequentialConfig {
+    assert!(sizes.len() >= 2,
+            "A multilayer perceptron needs an input size and at least one layer, got {:?}",
+            sizes);
+    let mut cfg = SequentialConfig::default();
+    cfg.add_input("data", &[batch_size, sizes[0]]);
+    for (i, &size) in sizes[1..].iter().enumerate() {
+        if i > 0 {
+            cfg.add_layer(LayerConfig::new(&format!("relu{}", i), LayerType::ReLU));
+        }
+        cfg.add_layer(LayerConfig::new(&format!("linear{}", i + 1), LinearConfig { output_size: size }));
+    }
+    cfg.add_layer(LayerConfig::new("log_softmax", LayerType::LogSoftmax));
+    cfg
+}
+
+/// Returns the config of [LeNet-5][1] for grayscale images of 28x28 pixels, like MNIST.
+/// [1]: http://yann.lecun.com/exdb/lenet/
+///
+/// The input has the shape `[batch_size, 1, 28, 28]`. Two 5x5 convolutions with 20 and
+/// 50 feature maps, each followed by a 2x2 max pooling, feed a hidden Linear layer of
+/// 500 units.
+pub fn lenet(batch_size: usize, num_classes: usize) -> SequentialConfig {
+    let mut cfg = SequentialConfig::default();
+    cfg.add_input("data", &[batch_size, 1, 28, 28]);
+    cfg.add_layer(LayerConfig::new("conv1", conv(20, 5, 0)));
+    cfg.add_layer(LayerConfig::new("pool1", max_pool()));
+    cfg.add_layer(LayerConfig::new("conv2", conv(50, 5, 0)));
+    cfg.add_layer(LayerConfig::new("pool2", max_pool()));
+    cfg.add_layer(LayerConfig::new("linear1", LinearConfig { output_size: 500 }));
+    cfg.add_layer(LayerConfig::new("relu1", LayerType::ReLU));
+    cfg.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: num_classes }));
+    cfg.add_layer(LayerConfig::new("log_softmax", LayerType::LogSoftmax));
+    cfg
+}
+
+/// Returns the config of a small [VGG-style][1] network for color images of 32x32 pixels,
+/// like CIFAR-10.
+/// [1]: https://arxiv.org/abs/1409.1556
+///
+/// The input has the shape `[batch_size, 3, 32, 32]`. Two stages of two 3x3 convolutions
+/// with 32 and 64 feature maps, each stage followed by a 2x2 max pooling, feed a hidden
+/// Linear layer of 256 units. All convolutions are followed by a ReLU.
+pub fn vgg_small(batch_size: usize, num_classes: usize) -> SequentialConfig {
+    let mut cfg = SequentialConfig::default();
+    cfg.add_input("data", &[batch_size, 3, 32, 32]);
+    for (stage, &num_output) in [32, 64].iter().enumerate() {
+        for i in 1..3 {
+            let name = format!("conv{}_{}", stage + 1, i);
+            cfg.add_layer(LayerConfig::new(&name, conv(num_output, 3, 1)));
+            cfg.add_layer(LayerConfig::new(&format!("relu{}_{}", stage + 1, i), LayerType::ReLU));
+        }
+        cfg.add_layer(LayerConfig::new(&format!("pool{}", stage + 1), max_pool()));
+    }
+    cfg.add_layer(LayerConfig::new("linear1", LinearConfig { output_size: 256 }));
+    cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+    cfg.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: num_classes }));
+    cfg.add_layer(LayerConfig::new("log_softmax", LayerType::LogSoftmax));
+    cfg
+}
+
+/// Returns the config of the objective for the classifiers, which takes the output of the
+/// network of shape `[batch_size, num_classes]` and the class labels of shape `[batch_size, 1]`.
+pub fn nll_objective(batch_size: usize, num_classes: usize) -> SequentialConfig {
+    let mut cfg = SequentialConfig::default();
+    cfg.add_input("network_out", &[batch_size, num_classes]);
+    cfg.add_input("label", &[batch_size, 1]);
+    cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: num_classes }));
+    cfg
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::*;
+    use util::{Fill, native_backend};
+
+    fn output_shape(cfg: SequentialConfig, input_shape: &[usize]) -> Vec<usize> {
+        let mut network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        let native = native_backend();
+        let mut input = SharedTensor::<f32>::new(&input_shape);
+        native.fill(&mut input, 0.5f32).unwrap();
+        let outputs = network.forward(&[Arc::new(RwLock::new(input))]);
+        let shape = outputs[0].read().unwrap().desc().clone();
+        shape
+    }
+
+    #[test]
+    fn mlp_has_one_linear_layer_per_size() {
+        let network = Layer::from_config(Rc::new(native_backend()),
+                                         &LayerConfig::new("network", mlp(4, &[6, 5, 3])))
+            .unwrap();
+        let shapes = network.learnable_weights_data()
+            .iter()
+            .map(|weight| weight.read().unwrap().desc().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![vec![5, 6], vec![3, 5]], shapes);
+        assert_eq!(vec![4, 3], output_shape(mlp(4, &[6, 5, 3]), &[4, 6]));
+    }
+
+    #[test]
+    fn classifiers_output_one_value_per_class() {
+        assert_eq!(vec![2, 10], output_shape(lenet(2, 10), &[2, 1, 28, 28]));
+        assert_eq!(vec![2, 7], output_shape(vgg_small(2, 7), &[2, 3, 32, 32]));
+    }
+}
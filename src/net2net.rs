@@ -0,0 +1,415 @@
+//! Provides function-preserving transformations to warm-start a wider or deeper network
+//! from a smaller trained one, as described in
+//!
+//! `[Chen et al. 2015]: Net2Net: Accelerating Learning via Knowledge Transfer.`
+//!
+//! The transformations take a trained Sequential network and return the config of the
+//! grown network together with a [WeightMap][1] of all its learnable weights. The grown
+//! network computes the same function as the trained one until it is trained further:
+//!
+//! ```
+//! # #[cfg(feature="native")]
+//! # fn main() {
+//! # use juice::layer::*;
+//! # use juice::layers::*;
+//! # use juice::net2net;
+//! # use juice::util::native_backend;
+//! # use std::rc::Rc;
+//! let mut cfg = SequentialConfig::default();
+//! cfg.add_input("data", &[1, 4]);
+//! cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+//! cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+//! cfg.add_layer(LayerConfig::new("out", LinearConfig { output_size: 2 }));
+//! let backend = Rc::new(native_backend());
+//! let net = Layer::from_config(backend.clone(), &LayerConfig::new("net", cfg)).unwrap();
+//!
+//! let (config, weights) = net2net::widen_linear(&net, "hidden", 5, 0.01).unwrap();
+//! let wider = Layer::from_config(backend, &config).unwrap();
+//! net2net::load_weights(&wider, &weights).unwrap();
+//! # }
+//! # #[cfg(not(feature="native"))]
+//! # fn main() {}
+//! ```
+//!
+//! [1]: ./type.WeightMap.html
+
+use co::{IBackend, ITensorDesc};
+use layer::*;
+use layers::{ConvolutionConfig, LinearConfig, SequentialConfig};
+use rand::{self, Rng};
+use std::collections::HashMap;
+use util::native_backend;
+use weight::{FillerType, record_weight_written};
+
+/// The values of learnable weights by their display name.
+pub type WeightMap = HashMap<String, Vec<f32>>;
+
+/// Fills the learnable weights of `net` that have an entry in `weights` with its values.
+///
+/// Weights without an entry keep their values. The weights are written on the host, which is
+/// [recorded][1], so they have to be [synchronized][2] before `net` computes on another
+/// device. Returns an error if an entry does not name a learnable weight of `net` or does not
+/// match its size.
+/// [1]: ../weight/fn.record_weight_written.html
+/// [2]: ../layer/struct.Layer.html#method.sync_weights_to
+pub fn load_weights<B: IBackend>(net: &Layer<B>, weights: &WeightMap) -> Result<(), String> {
+    let names = net.learnable_weights_names();
+    let data = net.learnable_weights_data();
+    for (name, values) in weights {
+        let weight_id = try!(names.iter()
+            .position(|weight_name| weight_name == name)
+            .ok_or_else(|| format!("Network {} has no learnable weight named {}", net.name, name)));
+        let size = data[weight_id].read().unwrap().desc().size();
+        if size != values.len() {
+            return Err(format!("Weight {} has {} values instead of {}", name, values.len(), size));
+        }
+        FillerType::fill_with(&mut data[weight_id].write().unwrap(), |_, i| values[i]);
+        record_weight_written(&data[weight_id], native_backend().device());
+    }
+    Ok(())
+}
+
+/// Returns the values of all the learnable weights of `net`.
+fn read_weights<B: IBackend>(net: &Layer<B>) -> WeightMap {
+    let native = native_backend();
+    net.weight_blobs()
+        .into_iter()
+        .map(|(name, data, _)| {
+            let data = data.read().unwrap();
+            let values = data.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+            (name, values)
+        })
+        .collect()
+}
+
+/// Returns the SequentialConfig of `net`.
+fn sequential_config<B: IBackend>(net: &Layer<B>) -> Result<&SequentialConfig, String> {
+    match net.config.layer_type {
+        LayerType::Sequential(ref config) => Ok(config),
+        _ => Err(format!("Network {} is not a Sequential layer", net.name)),
+    }
+}
+
+/// Returns the position of the layer `layer_name` in `config`.
+fn layer_position(config: &SequentialConfig, layer_name: &str) -> Result<usize, String> {
+    config.layers
+        .iter()
+        .position(|layer| layer.name == layer_name)
+        .ok_or_else(|| format!("There is no layer named {}", layer_name))
+}
+
+/// Returns the display name and the shape of the single learnable weight of the layer
+/// at `position` in `net`.
+fn linear_weight<B: IBackend>(net: &Layer<B>, position: usize) -> Result<(String, Vec<usize>), String> {
+    let layer = net.layers().nth(position).unwrap().borrow();
+    let names = layer.learnable_weights_names();
+    if names.len() != 1 {
+        return Err(format!("Layer {} does not own exactly one learnable weight", layer.name));
+    }
+    let shape = layer.learnable_weights_data()[0].read().unwrap().desc().clone();
+    Ok((names[0].clone(), shape))
+}
+
+/// Widens the Linear layer `layer_name` of the Sequential network `net` to `new_width`
+/// outputs.
+///
+/// The additional units replicate the existing ones, which are chosen round-robin. The
+/// outgoing weights of every unit are split among its replicas in the next Linear layer,
+/// so the output of the network does not change.
+///
+/// Exact replicas would receive the same gradients and stay replicas during further
+/// training. So the outgoing weights are not split evenly: the share of each replica is
+/// perturbed by a uniform relative `noise` and the shares of the replicas of a unit are
+/// normalized to sum to one. A `noise` of `0` splits them evenly.
+///
+/// The layers between the widened and the next Linear layer have to be element-wise
+/// activations (ReLU, Sigmoid or TanH), and the output of the widened layer must only be
+/// consumed along this chain.
+///
+/// Returns the config of the wider network and all its learnable weights, to be loaded
+/// with [load_weights][1].
+/// [1]: ./fn.load_weights.html
+pub fn widen_linear<B: IBackend>(net: &Layer<B>,
+                                 layer_name: &str,
+                                 new_width: usize,
+                                 noise: f32)
+                                 -> Result<(LayerConfig, WeightMap), String> {
+    if !(noise >= 0f32 && noise < 1f32) {
+        return Err(format!("The noise has to be at least 0 and less than 1, but is {}", noise));
+    }
+    let mut config = try!(sequential_config(net)).clone();
+    let position = try!(layer_position(&config, layer_name));
+    let width = match config.layers[position].layer_type {
+        LayerType::Linear(ref linear) => linear.output_size,
+        _ => return Err(format!("Layer {} is not a Linear layer", layer_name)),
+    };
+    if new_width < width {
+        return Err(format!("Layer {} can not be narrowed from {} to {} outputs", layer_name, width, new_width));
+    }
+
+    let mut next = position + 1;
+    loop {
+        match config.layers.get(next).map(|layer| &layer.layer_type) {
+            Some(&LayerType::ReLU) |
+            Some(&LayerType::Sigmoid) |
+            Some(&LayerType::TanH) |
+            Some(&LayerType::ELU(_)) => next += 1,
+            Some(&LayerType::Linear(_)) => break,
+            Some(_) => {
+                return Err(format!("Layer {} between {} and the next Linear layer is not an element-wise activation",
+                                   config.layers[next].name,
+                                   layer_name))
+            }
+            None => return Err(format!("Layer {} is not followed by a Linear layer", layer_name)),
+        }
+    }
+
+    let mut weights = read_weights(net);
+    let (in_name, in_shape) = try!(linear_weight(net, position));
+    let (out_name, out_shape) = try!(linear_weight(net, next));
+    let replicated = (0..new_width).map(|unit| unit % width).collect::<Vec<_>>();
+
+    // the incoming weights of a replica are a copy of the ones of its unit
+    let input_size = in_shape[1];
+    let widened_in = replicated.iter()
+        .flat_map(|&unit| weights[&in_name][unit * input_size..(unit + 1) * input_size].to_vec())
+        .collect::<Vec<_>>();
+    // the outgoing weights of a unit are split among its replicas with noisy shares that sum to one
+    let widened_out = {
+        let out = &weights[&out_name];
+        let mut rng = rand::thread_rng();
+        let mut widened_out = Vec::with_capacity(out_shape[0] * new_width);
+        for output in 0..out_shape[0] {
+            let shares = replicated.iter()
+                .map(|_| if noise > 0f32 { 1f32 + rng.gen_range(-noise, noise) } else { 1f32 })
+                .collect::<Vec<_>>();
+            let mut share_sums = vec![0f32; width];
+            for (&unit, &share) in replicated.iter().zip(&shares) {
+                share_sums[unit] += share;
+            }
+            widened_out.extend(replicated.iter()
+                .zip(&shares)
+                .map(|(&unit, &share)| out[output * width + unit] * share / share_sums[unit]));
+        }
+        widened_out
+    };
+    weights.insert(in_name, widened_in);
+    weights.insert(out_name, widened_out);
+
+    config.layers[position].layer_type = LayerType::Linear(LinearConfig { output_size: new_width });
+    Ok((LayerConfig::new(&net.name, config), weights))
+}
+
+/// Inserts the layer `layer` after the layer `after_layer` of the Sequential network `net`,
+/// initialized to compute the identity.
+///
+/// `layer` has to be a Linear layer with as many outputs as the features of its input of
+/// shape `[batch_size, features]`, or a Convolution with as many feature maps as its input,
+/// a stride of 1 and an odd filter size that is padded to keep the spatial dimensions.
+/// The filter of the identity convolution is 1 at its center for the matching feature
+/// map, the bias is 0.
+///
+/// Activations after the inserted layer only preserve the function if they are the
+/// identity on its input, e.g. a ReLU after a ReLU.
+///
+/// Returns the config of the deeper network and all its learnable weights, to be loaded
+/// with [load_weights][1].
+/// [1]: ./fn.load_weights.html
+pub fn deepen_with_identity<B: IBackend>(net: &Layer<B>,
+                                         after_layer: &str,
+                                         layer: LayerConfig)
+                                         -> Result<(LayerConfig, WeightMap), String> {
+    let mut config = try!(sequential_config(net)).clone();
+    let position = try!(layer_position(&config, after_layer));
+    if config.layers.iter().any(|existing| existing.name == layer.name) {
+        return Err(format!("There already is a layer named {}", layer.name));
+    }
+    let input_shape = {
+        let after = net.layers().nth(position).unwrap().borrow();
+        let output = after.output_blobs_data[0].read().unwrap();
+        output.desc().clone()
+    };
+
+    let (kernel, channels) = match layer.layer_type {
+        LayerType::Linear(LinearConfig { output_size }) => {
+            if input_shape.len() != 2 || input_shape[1] != output_size {
+                return Err(format!("The identity Linear layer {} needs an input of shape [batch_size, {}], \
+                                    but gets {:?}",
+                                   layer.name,
+                                   output_size,
+                                   input_shape));
+            }
+            (1, output_size)
+        }
+        LayerType::Convolution(ConvolutionConfig { num_output, ref filter_shape, ref stride, ref padding, .. }) => {
+            if input_shape.len() != 4 || input_shape[1] != num_output {
+                return Err(format!("The identity Convolution {} needs an input with {} feature maps, but gets {:?}",
+                                   layer.name,
+                                   num_output,
+                                   input_shape));
+            }
+            if filter_shape.len() != 1 || filter_shape[0] % 2 == 0 || stride[..] != [1] ||
+               padding[..] != [filter_shape[0] / 2] {
+                return Err(format!("The identity Convolution {} needs a single odd filter size, a stride of 1 \
+                                    and a padding of half the filter size",
+                                   layer.name));
+            }
+            (filter_shape[0], num_output)
+        }
+        _ => return Err(format!("Layer {} is neither a Linear nor a Convolution layer", layer.name)),
+    };
+
+    let mut weights = read_weights(net);
+    let weight_names = (0..2)
+        .map(|weight_id| match layer.param(weight_id) {
+            Some(param) if !param.name.is_empty() => param.name.clone(),
+            _ => format!("{}-{}", layer.name, weight_id),
+        })
+        .collect::<Vec<_>>();
+    // [channels, channels, kernel, kernel], with a kernel of 1 for the Linear layer
+    let kernel_size = kernel * kernel;
+    let center = kernel_size / 2;
+    let identity = (0..channels * channels * kernel_size)
+        .map(|i| {
+            let (output, input, offset) = (i / (channels * kernel_size), i / kernel_size % channels, i % kernel_size);
+            if output == input && offset == center { 1f32 } else { 0f32 }
+        })
+        .collect();
+    weights.insert(weight_names[0].clone(), identity);
+    if let LayerType::Convolution(ConvolutionConfig { bias: true, .. }) = layer.layer_type {
+        weights.insert(weight_names[1].clone(), vec![0f32; channels]);
+    }
+
+    config.layers.insert(position + 1, layer);
+    Ok((LayerConfig::new(&net.name, config), weights))
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use super::*;
+    use util::{native_backend, write_to_memory};
+    use weight::{FillerType, record_weight_written};
+
+    fn network(cfg: SequentialConfig) -> Layer<Backend<Native>> {
+        Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap()
+    }
+
+    /// Fills the weights of `net` with deterministic values that are not too regular.
+    fn fill_weights(net: &Layer<Backend<Native>>) {
+        for (i, weight) in net.learnable_weights_data().iter().enumerate() {
+            FillerType::fill_with(&mut weight.write().unwrap(),
+                                  |_, j| (((i * 31 + j * 7) % 11) as f32 - 5f32) * 0.1f32);
+        }
+    }
+
+    /// Returns the outputs of `net` and of the grown network for the same input.
+    fn outputs(net: &mut Layer<Backend<Native>>,
+               grown: (LayerConfig, WeightMap),
+               input_shape: &[usize])
+               -> (Vec<f32>, Vec<f32>) {
+        let mut grown_net = Layer::from_config(Rc::new(native_backend()), &grown.0).unwrap();
+        load_weights(&grown_net, &grown.1).unwrap();
+
+        let native = native_backend();
+        let size = input_shape.iter().fold(1, |size, dim| size * dim);
+        let values = (0..size).map(|i| ((i * 13 % 17) as f32 - 8f32) * 0.25f32).collect::<Vec<_>>();
+        let mut input = SharedTensor::new(&input_shape);
+        write_to_memory(input.write_only(native.device()).unwrap(), &values);
+        let input = Arc::new(RwLock::new(input));
+
+        let read = |net: &mut Layer<Backend<Native>>| {
+            let output = net.forward(&[input.clone()])[0].clone();
+            let output = output.read().unwrap();
+            let values = output.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+            values
+        };
+        (read(net), read(&mut grown_net))
+    }
+
+    fn assert_close(expected: &[f32], actual: &[f32]) {
+        assert_eq!(expected.len(), actual.len());
+        for (e, a) in expected.iter().zip(actual) {
+            assert!((e - a).abs() < 1e-5, "{:?} != {:?}", expected, actual);
+        }
+    }
+
+    fn mlp() -> SequentialConfig {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[2, 4]);
+        cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("out", LinearConfig { output_size: 2 }));
+        cfg
+    }
+
+    #[test]
+    fn widened_linear_layer_preserves_function() {
+        let mut net = network(mlp());
+        fill_weights(&net);
+        let grown = widen_linear(&net, "hidden", 7, 0.01).unwrap();
+        assert_eq!(7 * 4, grown.1["hidden-0"].len());
+        // the unit 0 is replicated as the units 3 and 6, with the same incoming weights
+        // but different shares of the outgoing weights
+        assert_eq!(grown.1["hidden-0"][0..4], grown.1["hidden-0"][12..16]);
+        let out = &grown.1["out-0"];
+        assert!(out[0] != out[3] && out[3] != out[6] && out[0] != out[6]);
+        let (original, widened) = outputs(&mut net, grown, &[2, 4]);
+        assert_close(&original, &widened);
+
+        assert!(widen_linear(&net, "hidden", 2, 0.01).is_err());
+        assert!(widen_linear(&net, "hidden", 7, 1.0).is_err());
+        assert!(widen_linear(&net, "out", 4, 0.01).unwrap_err().contains("not followed by a Linear layer"));
+    }
+
+    #[test]
+    fn identity_linear_layer_preserves_function() {
+        let mut net = network(mlp());
+        fill_weights(&net);
+        let grown = deepen_with_identity(&net, "relu", LayerConfig::new("identity", LinearConfig { output_size: 3 }))
+            .unwrap();
+        let (original, deepened) = outputs(&mut net, grown, &[2, 4]);
+        assert_close(&original, &deepened);
+
+        let wrong_size = LayerConfig::new("identity", LinearConfig { output_size: 4 });
+        assert!(deepen_with_identity(&net, "relu", wrong_size).is_err());
+    }
+
+    #[test]
+    fn identity_convolution_preserves_function() {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[1, 2, 5, 5]);
+        cfg.add_layer(LayerConfig::new("conv",
+                                       ConvolutionConfig {
+                                           num_output: 3,
+                                           filter_shape: vec![3],
+                                           stride: vec![1],
+                                           padding: vec![1],
+                                           bias: true,
+                                       }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("out", LinearConfig { output_size: 2 }));
+        let mut net = network(cfg);
+        fill_weights(&net);
+
+        let identity = ConvolutionConfig {
+            num_output: 3,
+            filter_shape: vec![3],
+            stride: vec![1],
+            padding: vec![1],
+            bias: true,
+        };
+        let grown = deepen_with_identity(&net, "relu", LayerConfig::new("identity", identity.clone())).unwrap();
+        assert_eq!(vec![0f32; 3], grown.1["identity-1"]);
+        let (original, deepened) = outputs(&mut net, grown, &[1, 2, 5, 5]);
+        assert_close(&original, &deepened);
+
+        let strided = ConvolutionConfig { stride: vec![2], ..identity };
+        assert!(deepen_with_identity(&net, "relu", LayerConfig::new("identity", strided)).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Provides the sharing of the configs of backend operations between layers with an
+//! identical geometry.
+//!
+//! Creating the config of an operation, e.g. of the convolution of a [Convolution][1]
+//! layer, builds its descriptors and can run a search for the fastest
+//! algorithm on the device. Networks made of repeated blocks contain many layers whose
+//! configs are identical, so the layers look their config up by an [OpKey][2], the
+//! structural key of the operation, and only the first layer with a key creates it.
+//! The configs are immutable once they are created; the workspace that the operations
+//! need is not part of them but [shared by the layers][3] of a network.
+//!
+//! Every backend instance has its own cache, which lives as long as the backend: the
+//! configs of a backend are dropped once the backend has been dropped. A backend is shared
+//! through an `Rc`, which never leaves the thread it has been created on, so every layer
+//! that uses a backend finds its cache. The [hits, misses and creation time][4] of the
+//! cache of a backend are counted.
+//!
+//! The [Convolution][1], the [Pooling][5] and the [LRN][6] layer have backend configs.
+//!
+//! [1]: ../layers/common/convolution/index.html
+//! [2]: ./struct.OpKey.html
+//! [3]: ../layer/trait.ILayer.html#method.resize_shared_workspace
+//! [4]: ./fn.stats.html
+//! [5]: ../layers/common/pooling/index.html
+//! [6]: ../layers/common/lrn/index.html
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The structural key of the config of an operation: the kind of operation, the data
+/// type it computes on and the shapes and hyperparameters the config depends on.
+pub struct OpKey {
+    op: &'static str,
+    dtype: TypeId,
+    dims: Vec<Vec<usize>>,
+}
+
+impl OpKey {
+    /// Create the key of the operation `op` on values of type `T`, whose config depends
+    /// on the shapes and hyperparameters `dims`, e.g. the input shape and the stride.
+    pub fn new<T: 'static>(op: &'static str, dims: &[&[usize]]) -> OpKey {
+        OpKey {
+            op: op,
+            dtype: TypeId::of::<T>(),
+            dims: dims.iter().map(|dims| dims.to_vec()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The lookups of configs in the cache of a backend since the last [reset_stats][1].
+/// [1]: ./fn.reset_stats.html
+pub struct OpCacheStats {
+    /// The number of lookups that found a cached config.
+    pub hits: usize,
+    /// The number of lookups that created a new config.
+    pub misses: usize,
+    /// The time spent creating the configs of the misses.
+    pub creation_time: Duration,
+}
+
+/// The cache of the configs of one backend.
+struct BackendCache {
+    /// Whether the backend is still alive.
+    alive: Box<Fn() -> bool>,
+    /// The `Rc`s of the configs by their key.
+    configs: HashMap<OpKey, Box<Any>>,
+    stats: OpCacheStats,
+}
+
+thread_local! {
+    /// The caches by the address of their backend.
+    static CACHES: RefCell<HashMap<usize, BackendCache>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the address that identifies `backend`.
+///
+/// The cache keeps a weak reference to the backend, so the address is not reused while
+/// the cache of the backend exists.
+fn backend_address<B>(backend: &Rc<B>) -> usize {
+    let backend_ptr: *const B = &**backend;
+    backend_ptr as usize
+}
+
+/// Calls `f` with the cache of `backend`, if it has one.
+fn with_cache<B, T, F>(backend: &Rc<B>, f: F) -> Option<T>
+    where F: FnOnce(&mut BackendCache) -> T
+{
+    CACHES.with(|caches| caches.borrow_mut().get_mut(&backend_address(backend)).map(f))
+}
+
+/// Returns the config of the operation `key` on `backend`, which is created with `create`
+/// unless it is cached already.
+///
+/// Returns the error of `create` if the config can not be created.
+pub fn shared_config<B, C, E, F>(backend: &Rc<B>, key: OpKey, create: F) -> Result<Rc<C>, E>
+    where B: 'static,
+          C: 'static,
+          F: FnOnce() -> Result<C, E>
+{
+    let cached = with_cache(backend, |cache| {
+            let config = cache.configs.get(&key).and_then(|config| config.downcast_ref::<Rc<C>>().cloned());
+            if config.is_some() {
+                cache.stats.hits += 1;
+            }
+            config
+        })
+        .and_then(|config| config);
+    if let Some(config) = cached {
+        return Ok(config);
+    }
+
+    // the cache is not borrowed while the config is created, which may take a while
+    let start = Instant::now();
+    let config = Rc::new(try!(create()));
+    let creation_time = start.elapsed();
+    CACHES.with(|caches| {
+        let mut caches = caches.borrow_mut();
+        // the caches of dropped backends are evicted
+        caches.retain(|_, cache| (cache.alive)());
+        let weak_backend = Rc::downgrade(backend);
+        let cache = caches.entry(backend_address(backend)).or_insert_with(|| {
+            BackendCache {
+                alive: Box::new(move || weak_backend.upgrade().is_some()),
+                configs: HashMap::new(),
+                stats: OpCacheStats::default(),
+            }
+        });
+        cache.stats.misses += 1;
+        cache.stats.creation_time += creation_time;
+        cache.configs.insert(key, Box::new(config.clone()));
+    });
+    Ok(config)
+}
+
+/// Returns the hits, misses and creation time of the cache of `backend`.
+pub fn stats<B>(backend: &Rc<B>) -> OpCacheStats {
+    with_cache(backend, |cache| cache.stats).unwrap_or_default()
+}
+
+/// Reset the hits, misses and creation time of the cache of `backend`.
+pub fn reset_stats<B>(backend: &Rc<B>) {
+    with_cache(backend, |cache| cache.stats = OpCacheStats::default());
+}
+
+/// Drop all configs cached for `backend`, e.g. to free the memory of the configs of a
+/// network that is not used anymore while its backend is.
+pub fn clear<B>(backend: &Rc<B>) {
+    with_cache(backend, |cache| cache.configs.clear());
+}
+
+/// Returns the number of configs cached for `backend`.
+pub fn len<B>(backend: &Rc<B>) -> usize {
+    with_cache(backend, |cache| cache.configs.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+
+    fn lookup(backend: &Rc<String>, dims: &[usize], value: u32) -> Rc<u32> {
+        let key = OpKey::new::<f32>("op", &[dims]);
+        shared_config::<_, _, (), _>(backend, key, || Ok(value)).unwrap()
+    }
+
+    #[test]
+    fn configs_are_shared_per_backend_and_key() {
+        let (first, second) = (Rc::new("first".to_owned()), Rc::new("second".to_owned()));
+        assert_eq!(1, *lookup(&first, &[2, 3], 1));
+        assert_eq!(1, *lookup(&first, &[2, 3], 2));
+        assert_eq!(3, *lookup(&first, &[3, 2], 3));
+        assert_eq!(4, *lookup(&second, &[2, 3], 4));
+        assert_eq!((1, 2), (stats(&first).hits, stats(&first).misses));
+        assert_eq!((0, 1), (stats(&second).hits, stats(&second).misses));
+        let key = OpKey::new::<f64>("op", &[&[2, 3]]);
+        assert_eq!(5, *shared_config::<_, _, (), _>(&first, key, || Ok(5u32)).unwrap());
+        assert_eq!((3, 1), (len(&first), len(&second)));
+
+        reset_stats(&first);
+        assert_eq!(OpCacheStats::default(), stats(&first));
+        clear(&first);
+        assert_eq!(0, len(&first));
+    }
+
+    #[test]
+    fn the_cache_of_a_dropped_backend_is_evicted() {
+        let first = Rc::new("first".to_owned());
+        lookup(&first, &[2, 3], 1);
+        drop(first);
+
+        // the next miss evicts the cache of the dropped backend
+        let second = Rc::new("second".to_owned());
+        assert_eq!(2, *lookup(&second, &[2, 3], 2));
+        CACHES.with(|caches| {
+            let caches = caches.borrow();
+            assert!(caches.len() == 1 && caches.contains_key(&backend_address(&second)));
+        });
+    }
+
+    #[test]
+    fn creation_time_is_measured() {
+        let backend = Rc::new("backend".to_owned());
+        shared_config::<_, _, (), _>(&backend, OpKey::new::<f32>("op", &[]), || {
+                ::std::thread::sleep(Duration::from_millis(5));
+                Ok(1u32)
+            })
+            .unwrap();
+        assert!(stats(&backend).creation_time >= Duration::from_millis(5));
+    }
+}
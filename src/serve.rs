@@ -0,0 +1,334 @@
+//! Provides the serving of a network to many callers with batched inference.
+//!
+//! Running a forward pass for each sample wastes most of the time on the overhead of the
+//! pass. A [Batcher][1] coalesces the single-sample requests of its callers into batches
+//! and runs one forward pass per batch.
+//!
+//! The network is created and owned by a dedicated thread, so it doesn't need to be `Send`
+//! or `Sync`. The callers talk to that thread through [BatcherHandles][2], which can be
+//! cloned and sent to other threads.
+//!
+//! ```
+//! # #[cfg(feature="native")]
+//! # fn main() {
+//! # use juice::layer::*;
+//! # use juice::layers::*;
+//! # use juice::serve::*;
+//! # use juice::util::native_backend;
+//! # use std::rc::Rc;
+//! # use std::time::Duration;
+//! let batcher = Batcher::spawn(BatcherConfig { max_batch: 8, max_wait: Duration::from_millis(2) },
+//!                              || {
+//!     let mut cfg = SequentialConfig::default();
+//!     cfg.add_input("data", &[8, 4]);
+//!     cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 2 }));
+//!     Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap()
+//! });
+//! let output = batcher.infer(vec![1f32, 2f32, 3f32, 4f32]).unwrap();
+//! assert_eq!(2, output.len());
+//! # }
+//! # #[cfg(not(feature="native"))]
+//! # fn main() {}
+//! ```
+//!
+//! [1]: ./struct.Batcher.html
+//! [2]: ./struct.BatcherHandle.html
+
+use co::prelude::*;
+use layer::Layer;
+use std::cmp;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use util::{LayerOps, native_backend, write_to_memory};
+
+/// The output of a single-sample inference, or a description of why it failed.
+pub type Response = Result<Vec<f32>, String>;
+
+#[derive(Debug, Clone)]
+/// Specifies how a [Batcher][1] coalesces requests.
+/// [1]: ./struct.Batcher.html
+pub struct BatcherConfig {
+    /// The maximum number of requests in a batch.
+    pub max_batch: usize,
+    /// The maximum time to wait for more requests after the first request of a batch.
+    pub max_wait: Duration,
+}
+
+impl Default for BatcherConfig {
+    fn default() -> BatcherConfig {
+        BatcherConfig {
+            max_batch: 32,
+            max_wait: Duration::from_millis(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A single-sample inference request.
+struct Request {
+    input: Vec<f32>,
+    response: Sender<Response>,
+}
+
+#[derive(Debug, Clone)]
+/// Submits requests to a [Batcher][1] from any thread.
+/// [1]: ./struct.Batcher.html
+pub struct BatcherHandle {
+    requests: Sender<Request>,
+}
+
+impl BatcherHandle {
+    /// Submit the sample `input` for inference.
+    ///
+    /// Returns the receiver of the response, which is sent once the batch of the request
+    /// has been run. The response is an error if `input` doesn't have the size of a
+    /// sample of the network or if the Batcher has stopped.
+    pub fn submit(&self, input: Vec<f32>) -> Receiver<Response> {
+        let (response, receiver) = mpsc::channel();
+        let request = Request {
+            input: input,
+            response: response,
+        };
+        if let Err(mpsc::SendError(request)) = self.requests.send(request) {
+            let _ = request.response.send(Err("The Batcher has stopped".to_owned()));
+        }
+        receiver
+    }
+
+    /// Run the inference of the sample `input` and wait for its output, see [submit][1].
+    /// [1]: #method.submit
+    pub fn infer(&self, input: Vec<f32>) -> Response {
+        self.submit(input).recv().unwrap_or_else(|_| Err("The Batcher has stopped".to_owned()))
+    }
+}
+
+#[derive(Debug)]
+/// Serves a network by coalescing single-sample inference requests into batches.
+///
+/// A batch is run once it has `max_batch` requests or `max_wait` has passed since its
+/// first request. The network is [reshaped][1] to the number of requests of each batch,
+/// so no computation is spent on empty rows. The network runs in [inference mode][2].
+/// [1]: ../layer/struct.Layer.html#method.forward
+/// [2]: ../layer/struct.Layer.html#method.set_train
+///
+/// Dropping the Batcher stops its thread after the pending requests have been answered.
+pub struct Batcher {
+    handle: Option<BatcherHandle>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Batcher {
+    /// Spawn the thread of the Batcher, which creates the network with `create_network`.
+    ///
+    /// The network must have a single input of shape `[batch_size, ...]` and return one
+    /// output row per sample. Its batch size is irrelevant, as the network is reshaped to
+    /// the size of every batch.
+    pub fn spawn<B, F>(config: BatcherConfig, create_network: F) -> Batcher
+        where B: IBackend + LayerOps<f32> + 'static,
+              F: FnOnce() -> Layer<B> + Send + 'static
+    {
+        let (requests, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let mut network = create_network();
+            network.set_train(false);
+            serve(&mut network, &config, &receiver);
+        });
+        Batcher {
+            handle: Some(BatcherHandle { requests: requests }),
+            worker: Some(worker),
+        }
+    }
+
+    /// Returns a handle to submit requests from other threads.
+    pub fn handle(&self) -> BatcherHandle {
+        self.handle.clone().unwrap()
+    }
+
+    /// Submit the sample `input` for inference, see [BatcherHandle::submit][1].
+    /// [1]: ./struct.BatcherHandle.html#method.submit
+    pub fn submit(&self, input: Vec<f32>) -> Receiver<Response> {
+        self.handle.as_ref().unwrap().submit(input)
+    }
+
+    /// Run the inference of the sample `input`, see [BatcherHandle::infer][1].
+    /// [1]: ./struct.BatcherHandle.html#method.infer
+    pub fn infer(&self, input: Vec<f32>) -> Response {
+        self.handle.as_ref().unwrap().infer(input)
+    }
+}
+
+impl Drop for Batcher {
+    fn drop(&mut self) {
+        // the thread stops once all handles are dropped
+        self.handle.take();
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The thread of the Batcher panicked");
+            }
+        }
+    }
+}
+
+/// Answers the requests of `receiver` in batches until all senders are dropped.
+fn serve<B: IBackend + LayerOps<f32> + 'static>(network: &mut Layer<B>,
+                                                config: &BatcherConfig,
+                                                receiver: &Receiver<Request>) {
+    let mut input_shape = network.input_blobs_data[0].read().unwrap().desc().clone();
+    let sample_size = input_shape.size() / input_shape[0];
+    let max_batch = cmp::max(1, config.max_batch);
+
+    while let Ok(first) = receiver.recv() {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + config.max_wait;
+        while batch.len() < max_batch {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match receiver.recv_timeout(deadline - now) {
+                Ok(request) => batch.push(request),
+                Err(RecvTimeoutError::Timeout) |
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let (batch, invalid): (Vec<_>, Vec<_>) = batch.into_iter()
+            .partition(|request| request.input.len() == sample_size);
+        for request in invalid {
+            let _ = request.response.send(Err(format!("The input has {} values instead of {}",
+                                                      request.input.len(),
+                                                      sample_size)));
+        }
+        if !batch.is_empty() {
+            input_shape[0] = batch.len();
+            run_batch(network, &input_shape, &batch);
+        }
+    }
+}
+
+/// Runs one forward pass for the requests of `batch`, whose input has the shape
+/// `input_shape`, and sends each its output row.
+fn run_batch<B: IBackend + LayerOps<f32> + 'static>(network: &mut Layer<B>,
+                                                    input_shape: &[usize],
+                                                    batch: &[Request]) {
+    let native = native_backend();
+    let values = batch.iter().flat_map(|request| request.input.iter().cloned()).collect::<Vec<_>>();
+    let mut input = SharedTensor::new(&input_shape);
+    write_to_memory(input.write_only(native.device()).unwrap(), &values);
+
+    let output = network.forward(&[Arc::new(RwLock::new(input))])[0].clone();
+    let output = output.read().unwrap();
+    let output = output.read(native.device()).unwrap().as_slice::<f32>();
+    let row_size = output.len() / input_shape[0];
+    for (row, request) in output.chunks(row_size).zip(batch) {
+        let _ = request.response.send(Ok(row.to_vec()));
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use super::*;
+    use util::{native_backend, write_to_memory};
+    use weight::FillerType;
+
+    /// Returns a small conv network for inputs of shape `[batch_size, 1, 8, 8]` with
+    /// deterministic weights.
+    fn conv_network(batch_size: usize) -> Layer<Backend<Native>> {
+        let mut cfg = SequentialConfig::default();
+        cfg.add_input("data", &[batch_size, 1, 8, 8]);
+        cfg.add_layer(LayerConfig::new("conv",
+                                       ConvolutionConfig {
+                                           num_output: 8,
+                                           filter_shape: vec![3],
+                                           stride: vec![1],
+                                           padding: vec![1],
+                                           bias: true,
+                                       }));
+        cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+        cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+        let network = Layer::from_config(Rc::new(native_backend()), &LayerConfig::new("network", cfg)).unwrap();
+        for weight in network.learnable_weights_data() {
+            FillerType::fill_with(&mut weight.write().unwrap(),
+                                  |_, i| ((i * 7 % 13) as f32 - 6f32) * 0.05f32);
+        }
+        network
+    }
+
+    fn sample(i: usize) -> Vec<f32> {
+        (0..64).map(|j| ((i * 64 + j) % 11) as f32 * 0.1f32).collect()
+    }
+
+    #[test]
+    fn responses_match_single_sample_inference() {
+        let batcher = Batcher::spawn(BatcherConfig { max_batch: 4, max_wait: Duration::from_millis(20) },
+                                     || conv_network(4));
+        let receivers = (0..6).map(|i| batcher.submit(sample(i))).collect::<Vec<_>>();
+        let invalid = batcher.infer(vec![0f32; 3]);
+        assert!(invalid.is_err());
+
+        let native = native_backend();
+        let mut single = conv_network(1);
+        single.set_train(false);
+        for (i, receiver) in receivers.into_iter().enumerate() {
+            let mut input = SharedTensor::new(&[1, 1, 8, 8]);
+            write_to_memory(input.write_only(native.device()).unwrap(), &sample(i));
+            let expected = single.forward(&[Arc::new(RwLock::new(input))])[0].clone();
+            let expected = expected.read().unwrap();
+            let expected = expected.read(native.device()).unwrap().as_slice::<f32>();
+
+            let output = receiver.recv().unwrap().unwrap();
+            assert_eq!(3, output.len());
+            for (o, e) in output.iter().zip(expected) {
+                assert!((o - e).abs() < 1e-5, "{:?} != {:?}", output, expected);
+            }
+        }
+    }
+
+    /// Returns the time the Batcher takes to answer `num_requests` requests that are
+    /// submitted at once and the number of forward passes it runs for them.
+    fn serve_time(max_batch: usize, num_requests: usize) -> (Duration, usize) {
+        let passes = Arc::new(AtomicUsize::new(0));
+        let counter = passes.clone();
+        let batcher = Batcher::spawn(BatcherConfig { max_batch: max_batch, max_wait: Duration::from_millis(50) },
+                                     move || {
+            let mut network = conv_network(1);
+            let count_pass = move |_: &[&SharedTensor<f32>], _: &[&SharedTensor<f32>]| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            };
+            network.register_forward_hook("network", Box::new(count_pass)).unwrap();
+            network
+        });
+        // warm up
+        batcher.infer(sample(0)).unwrap();
+        passes.store(0, Ordering::SeqCst);
+        let start = Instant::now();
+        let receivers = (0..num_requests).map(|i| batcher.submit(sample(i))).collect::<Vec<_>>();
+        for receiver in receivers {
+            receiver.recv().unwrap().unwrap();
+        }
+        (start.elapsed(), passes.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn coalescing_beats_per_request_forward_passes() {
+        let (per_request, per_request_passes) = serve_time(1, 256);
+        let (coalesced, coalesced_passes) = serve_time(32, 256);
+        assert_eq!(256, per_request_passes);
+        // the requests are queued at once, so every batch is full
+        assert_eq!(8, coalesced_passes);
+        assert!(coalesced * 2 < per_request,
+                "coalesced: {:?}, per request: {:?}",
+                coalesced,
+                per_request);
+    }
+}
@@ -0,0 +1,258 @@
+//! Provides a solver that trains a generator and a discriminator alternately.
+//!
+//! This is the setup of [Generative Adversarial Networks][gan]: the discriminator learns
+//! to tell real samples from the samples of the generator, while the generator learns to
+//! produce samples that the discriminator classifies as real.
+//!
+//! The output of the generator is fed into the discriminator as is, so the samples never
+//! leave the device. When the generator is trained, the gradient flows back through the
+//! discriminator into the generator, but the weights of the discriminator stay frozen:
+//! only the gradient w.r.t. its input is computed.
+//!
+//! [gan]: https://arxiv.org/abs/1406.2661
+
+use co::prelude::*;
+use layer::*;
+use solver::*;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use util::{ArcLock, LayerOps, SolverOps};
+use weight::FillerType;
+
+/// The class of real samples in the labels of the objectives.
+pub const REAL_LABEL: f32 = 1f32;
+/// The class of generated samples in the labels of the objectives.
+pub const FAKE_LABEL: f32 = 0f32;
+
+#[derive(Debug)]
+/// Solver that trains a generator and a discriminator [Layer][1] alternately.
+/// [1]: ../../layer/index.html
+///
+/// Both networks have their own [SolverConfig][2], solver state and objective.
+/// The objectives get the output of the discriminator and a label of shape
+/// `[batch_size, 1]` with [REAL_LABEL][3] or [FAKE_LABEL][4] for each sample,
+/// e.g. a [SoftmaxLoss][5] with two classes.
+/// [2]: ../struct.SolverConfig.html
+/// [3]: ./constant.REAL_LABEL.html
+/// [4]: ./constant.FAKE_LABEL.html
+/// [5]: ../../layers/loss/softmax_loss/index.html
+pub struct DualSolver<SolverB: IBackend + SolverOps<f32>, B: IBackend + LayerOps<f32>> {
+    generator: Layer<B>,
+    generator_objective: Layer<SolverB>,
+    generator_worker: Box<ISolver<SolverB, B>>,
+    generator_config: SolverConfig,
+    generator_iter: usize,
+
+    discriminator: Layer<B>,
+    discriminator_objective: Layer<SolverB>,
+    discriminator_worker: Box<ISolver<SolverB, B>>,
+    discriminator_config: SolverConfig,
+    discriminator_iter: usize,
+}
+
+impl<SolverB: IBackend + SolverOps<f32> + 'static, B: IBackend + LayerOps<f32> + 'static> DualSolver<SolverB, B> {
+    /// Create a DualSolver from the [SolverConfig][1]s of the generator and the discriminator.
+    /// [1]: ../struct.SolverConfig.html
+    ///
+    /// Panics if one of the networks or objectives can not be [created from its config][2].
+    /// [2]: ../../layer/struct.Layer.html#method.from_config
+    pub fn from_config(net_backend: Rc<B>,
+                       obj_backend: Rc<SolverB>,
+                       generator_config: &SolverConfig,
+                       discriminator_config: &SolverConfig)
+                       -> DualSolver<SolverB, B> {
+        let generator = Layer::from_config(net_backend.clone(), &generator_config.network)
+            .unwrap_or_else(|err| panic!("{}", err));
+        let mut generator_worker = generator_config.solver.with_config(obj_backend.clone(), generator_config);
+        generator_worker.init(&generator);
+
+        let discriminator = Layer::from_config(net_backend, &discriminator_config.network)
+            .unwrap_or_else(|err| panic!("{}", err));
+        let mut discriminator_worker = discriminator_config.solver
+            .with_config(obj_backend.clone(), discriminator_config);
+        discriminator_worker.init(&discriminator);
+
+        DualSolver {
+            generator: generator,
+            generator_objective: Layer::from_config(obj_backend.clone(), &generator_config.objective)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            generator_worker: generator_worker,
+            generator_config: generator_config.clone(),
+            generator_iter: 0,
+
+            discriminator: discriminator,
+            discriminator_objective: Layer::from_config(obj_backend, &discriminator_config.objective)
+                .unwrap_or_else(|err| panic!("{}", err)),
+            discriminator_worker: discriminator_worker,
+            discriminator_config: discriminator_config.clone(),
+            discriminator_iter: 0,
+        }
+    }
+
+    /// Train the discriminator with one minibatch of `real` samples and one minibatch of
+    /// samples generated from `noise`.
+    ///
+    /// The generator is not changed. Returns the sum of the losses of both minibatches.
+    pub fn step_discriminator(&mut self,
+                              real: ArcLock<SharedTensor<f32>>,
+                              noise: ArcLock<SharedTensor<f32>>)
+                              -> f32 {
+        let fake = self.generator.forward(&[noise])[0].clone();
+
+        // the gradients of both minibatches accumulate
+        self.discriminator.clear_weights_gradients();
+        let mut loss = 0f32;
+        for (samples, label) in vec![(real, REAL_LABEL), (fake, FAKE_LABEL)] {
+            let discriminator_out = self.discriminator.forward(&[samples])[0].clone();
+            let labels = Self::labels(&discriminator_out, label);
+            self.discriminator_objective.forward(&[discriminator_out, labels]);
+            loss += self.discriminator_objective.total_loss();
+
+            let objective_gradient = self.discriminator_objective.backward(&[]);
+            self.discriminator.backward(&objective_gradient[0..1]);
+        }
+
+        self.discriminator_worker.compute_update(&self.discriminator_config,
+                                                 &mut self.discriminator,
+                                                 self.discriminator_iter);
+        if !self.discriminator_worker.updates_weights(&self.discriminator_config) {
+            self.discriminator.update_weights(self.discriminator_worker.backend());
+        }
+        self.discriminator_iter += 1;
+        loss
+    }
+
+    /// Train the generator with one minibatch of samples generated from `noise`.
+    ///
+    /// The generator is trained to make the discriminator classify its samples as real.
+    /// The discriminator is frozen: the gradient is propagated through it, but its weights
+    /// are not changed. Returns the loss of the minibatch.
+    pub fn step_generator(&mut self, noise: ArcLock<SharedTensor<f32>>) -> f32 {
+        let fake = self.generator.forward(&[noise])[0].clone();
+        let discriminator_out = self.discriminator.forward(&[fake])[0].clone();
+        let labels = Self::labels(&discriminator_out, REAL_LABEL);
+        self.generator_objective.forward(&[discriminator_out, labels]);
+        let loss = self.generator_objective.total_loss();
+
+        self.generator.clear_weights_gradients();
+        let objective_gradient = self.generator_objective.backward(&[]);
+        let discriminator_gradient = self.discriminator.backward_input(&objective_gradient[0..1]);
+        self.generator.backward(&discriminator_gradient[0..1]);
+
+        self.generator_worker.compute_update(&self.generator_config, &mut self.generator, self.generator_iter);
+        if !self.generator_worker.updates_weights(&self.generator_config) {
+            self.generator.update_weights(self.generator_worker.backend());
+        }
+        self.generator_iter += 1;
+        loss
+    }
+
+    /// Returns a label of shape `[batch_size, 1]` with `value` for every sample of `output`.
+    fn labels(output: &ArcLock<SharedTensor<f32>>, value: f32) -> ArcLock<SharedTensor<f32>> {
+        let batch_size = output.read().unwrap().desc()[0];
+        let mut labels = SharedTensor::new(&[batch_size, 1]);
+        FillerType::fill_constant(&mut labels, value);
+        Arc::new(RwLock::new(labels))
+    }
+
+    /// Returns the generator.
+    pub fn generator(&self) -> &Layer<B> {
+        &self.generator
+    }
+
+    /// Returns the generator, e.g. to generate samples.
+    pub fn mut_generator(&mut self) -> &mut Layer<B> {
+        &mut self.generator
+    }
+
+    /// Returns the discriminator.
+    pub fn discriminator(&self) -> &Layer<B> {
+        &self.discriminator
+    }
+
+    /// Returns the discriminator.
+    pub fn mut_discriminator(&mut self) -> &mut Layer<B> {
+        &mut self.discriminator
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use layer::*;
+    use layers::*;
+    use solver::SolverConfig;
+    use std::rc::Rc;
+    use super::*;
+    use util::{native_backend, write_to_memory};
+    use util::testing::tensor;
+
+    fn set_weights(network: &Layer<Backend<Native>>) {
+        let native = native_backend();
+        for (i, weight) in network.learnable_weights_data().iter().enumerate() {
+            let size = weight.read().unwrap().desc().size();
+            let values = (0..size).map(|j| ((i * 7 + j) % 5) as f32 * 0.2 - 0.4).collect::<Vec<_>>();
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), &values);
+        }
+    }
+
+    fn mean_generated(solver: &mut DualSolver<Backend<Native>, Backend<Native>>, noise: &[f32]) -> f32 {
+        let native = native_backend();
+        let outputs = solver.mut_generator().forward(&[tensor(&[8, 1], noise)]);
+        let output = outputs[0].read().unwrap();
+        let samples = output.read(native.device()).unwrap().as_slice::<f32>();
+        samples.iter().fold(0f32, |sum, sample| sum + sample) / samples.len() as f32
+    }
+
+    #[test]
+    fn generator_moves_toward_real_distribution() {
+        let mut generator_cfg = SequentialConfig::default();
+        generator_cfg.add_input("noise", &[8, 1]);
+        generator_cfg.add_layer(LayerConfig::new("generate", LinearConfig { output_size: 1 }));
+
+        let mut discriminator_cfg = SequentialConfig::default();
+        discriminator_cfg.add_input("samples", &[8, 1]);
+        discriminator_cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 8 }));
+        discriminator_cfg.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
+        discriminator_cfg.add_layer(LayerConfig::new("classify", LinearConfig { output_size: 2 }));
+
+        let mut objective_cfg = SequentialConfig::default();
+        objective_cfg.add_input("discriminator_out", &[8, 2]);
+        objective_cfg.add_input("label", &[8, 1]);
+        objective_cfg.add_layer(LayerConfig::new("loss", SoftmaxLossConfig::new(2)));
+        let objective = LayerConfig::new("objective", objective_cfg);
+
+        let generator_config = SolverConfig {
+            network: LayerConfig::new("generator", generator_cfg),
+            objective: objective.clone(),
+            base_lr: 0.05f32,
+            ..SolverConfig::default()
+        };
+        let discriminator_config = SolverConfig {
+            network: LayerConfig::new("discriminator", discriminator_cfg),
+            objective: objective,
+            base_lr: 0.05f32,
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = DualSolver::from_config(backend.clone(), backend, &generator_config, &discriminator_config);
+        set_weights(solver.generator());
+        set_weights(solver.discriminator());
+
+        // the real samples are spread around 3, the generator starts around 0
+        let noise = (0..8).map(|i| 0.6 + 0.1 * i as f32).collect::<Vec<_>>();
+        let real = (0..8).map(|i| 2.6 + 0.1 * i as f32).collect::<Vec<_>>();
+        let initial_mean = mean_generated(&mut solver, &noise);
+        for _ in 0..20 {
+            solver.step_discriminator(tensor(&[8, 1], &real), tensor(&[8, 1], &noise));
+            solver.step_generator(tensor(&[8, 1], &noise));
+        }
+        let mean = mean_generated(&mut solver, &noise);
+
+        assert!((mean - 3f32).abs() < (initial_mean - 3f32).abs() / 2f32,
+                "generated mean moved from {} to {}",
+                initial_mean,
+                mean);
+    }
+}
@@ -0,0 +1,158 @@
+//! Provides the learning rate schedules of the [Solver][1].
+//! [1]: ../struct.Solver.html
+//!
+//! A schedule implements [LrScheduler][2] and is consulted for the learning rate of
+//! every iteration. The built-in [LRPolicies][3] are implemented by [ConstantLr][4],
+//! [StepLr][5] and [ExpLr][6]. Custom schedules can be used by setting
+//! [SolverConfig.lr_scheduler][7].
+//!
+//! Schedules can be composed, e.g. a [CosineLr][8] can be started with a [WarmupLr][9]:
+//!
+//! ```
+//! # use juice::solver::*;
+//! // rises to 0.1 in 100 iterations, then decays to 0 in another 1000 iterations
+//! let scheduler = WarmupLr::new(100, CosineLr::new(0.1, 0.0, 1000));
+//! assert_eq!(0.1, scheduler.lr(100));
+//! ```
+//! [2]: ./trait.LrScheduler.html
+//! [3]: ../enum.LRPolicy.html
+//! [4]: ./struct.ConstantLr.html
+//! [5]: ./struct.StepLr.html
+//! [6]: ./struct.ExpLr.html
+//! [7]: ../struct.SolverConfig.html#structfield.lr_scheduler
+//! [8]: ./struct.CosineLr.html
+//! [9]: ./struct.WarmupLr.html
+
+use std::f32::consts::PI;
+use std::fmt;
+
+/// A schedule of the learning rate over the iterations of the training.
+pub trait LrScheduler {
+    /// Returns the learning rate for the iteration `iteration`.
+    fn lr(&self, iteration: usize) -> f32;
+}
+
+impl fmt::Debug for LrScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({})", "LrScheduler")
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Always returns the same learning rate.
+pub struct ConstantLr {
+    /// The learning rate.
+    pub lr: f32,
+}
+
+impl LrScheduler for ConstantLr {
+    fn lr(&self, iteration: usize) -> f32 {
+        self.lr
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Decays the learning rate every `stepsize` iterations.
+///
+/// Returns `base_lr * gamma ^ (floor(iteration / stepsize))`.
+pub struct StepLr {
+    /// The learning rate of the first step.
+    pub base_lr: f32,
+    /// The factor the learning rate is multiplied with at each step.
+    pub gamma: f32,
+    /// The number of iterations of each step.
+    pub stepsize: usize,
+}
+
+impl LrScheduler for StepLr {
+    fn lr(&self, iteration: usize) -> f32 {
+        let current_step = iteration / self.stepsize;
+        self.base_lr * self.gamma.powf(current_step as f32)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Decays the learning rate every iteration.
+///
+/// Returns `base_lr * gamma ^ iteration`.
+pub struct ExpLr {
+    /// The learning rate of the first iteration.
+    pub base_lr: f32,
+    /// The factor the learning rate is multiplied with at each iteration.
+    pub gamma: f32,
+}
+
+impl LrScheduler for ExpLr {
+    fn lr(&self, iteration: usize) -> f32 {
+        self.base_lr * self.gamma.powf(iteration as f32)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Anneals the learning rate from `base_lr` to `min_lr` along half a cosine wave.
+///
+/// Returns `min_lr + (base_lr - min_lr) * (1 + cos(pi * iteration / period)) / 2`
+/// and `min_lr` after `period` iterations.
+pub struct CosineLr {
+    /// The learning rate of the first iteration.
+    pub base_lr: f32,
+    /// The learning rate at the end of the period.
+    pub min_lr: f32,
+    /// The number of iterations it takes to anneal the learning rate to `min_lr`.
+    pub period: usize,
+}
+
+impl CosineLr {
+    /// Create a CosineLr that anneals from `base_lr` to `min_lr` in `period` iterations.
+    pub fn new(base_lr: f32, min_lr: f32, period: usize) -> CosineLr {
+        CosineLr {
+            base_lr: base_lr,
+            min_lr: min_lr,
+            period: period,
+        }
+    }
+}
+
+impl LrScheduler for CosineLr {
+    fn lr(&self, iteration: usize) -> f32 {
+        if iteration >= self.period {
+            return self.min_lr;
+        }
+        let progress = iteration as f32 / self.period as f32;
+        self.min_lr + (self.base_lr - self.min_lr) * (1f32 + (PI * progress).cos()) / 2f32
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Increases the learning rate linearly for `warmup_iters` iterations before
+/// handing over to another schedule.
+///
+/// During the warmup the learning rate rises to the first learning rate of the wrapped
+/// schedule, which is reached in the last warmup iteration. Afterwards the wrapped schedule
+/// is consulted, starting at its first iteration.
+pub struct WarmupLr<S: LrScheduler> {
+    /// The number of warmup iterations.
+    pub warmup_iters: usize,
+    /// The schedule that follows the warmup.
+    pub scheduler: S,
+}
+
+impl<S: LrScheduler> WarmupLr<S> {
+    /// Create a WarmupLr that warms up for `warmup_iters` iterations before following `scheduler`.
+    pub fn new(warmup_iters: usize, scheduler: S) -> WarmupLr<S> {
+        WarmupLr {
+            warmup_iters: warmup_iters,
+            scheduler: scheduler,
+        }
+    }
+}
+
+impl<S: LrScheduler> LrScheduler for WarmupLr<S> {
+    fn lr(&self, iteration: usize) -> f32 {
+        if iteration < self.warmup_iters {
+            self.scheduler.lr(0) * (iteration + 1) as f32 / self.warmup_iters as f32
+        } else {
+            self.scheduler.lr(iteration - self.warmup_iters)
+        }
+    }
+}
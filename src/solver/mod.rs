@@ -4,16 +4,38 @@
 //! [solvers]: ../solvers/index.html
 
 pub mod confusion_matrix;
+pub mod dual;
+pub mod lr_scheduler;
+pub mod plan;
+pub mod regularizer;
 
 pub use self::confusion_matrix::ConfusionMatrix;
+pub use self::dual::DualSolver;
+pub use self::lr_scheduler::{ConstantLr, CosineLr, ExpLr, LrScheduler, StepLr, WarmupLr};
+pub use self::plan::{PhaseBoundary, PhaseSummary, TrainingPhase, TrainingPlan};
+pub use self::regularizer::{RegularizedWeight, StructuralRegularizer};
+use capnp_util::*;
 use co::prelude::*;
+use data::DataIterator;
+use juice_capnp::training_progress as capnp_progress;
+use juice_capnp::training_progress::phase as capnp_phase;
 use layer::*;
 use layers::SequentialConfig;
 use solvers::*;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
 use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
 
 use std::rc::Rc;
-use util::{ArcLock, LayerOps, SolverOps};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use transfer::{self, TransferCategory, TransferSummary};
+use util::{ArcLock, LayerOps, SolverOps, native_backend, native_scalar, write_to_memory};
 
 #[derive(Debug)]
 /// Solver that optimizes a [Layer][1] with a given objective.
@@ -28,6 +50,54 @@ pub struct Solver<SolverB: IBackend + SolverOps<f32>, B: IBackend + LayerOps<f32
 
     /// The current iteration / number of times weights have been updated
     iter: usize,
+    /// The number of minibatches that have been trained.
+    minibatches: usize,
+    /// The wall clock time spent in [fit](#method.fit).
+    elapsed: Duration,
+
+    /// The gradients accumulated over the micro-batches of the current iteration.
+    accumulated_gradients: Vec<ArcLock<SharedTensor<f32>>>,
+    /// The gradients accumulated on the host if they are accumulated in `f64`.
+    accumulated_gradients_f64: Vec<Vec<f64>>,
+    /// The number of micro-batches accumulated in the current iteration.
+    accumulated_steps: usize,
+
+    /// The losses of the most recent minibatches, the oldest first.
+    recent_losses: VecDeque<f32>,
+
+    /// The snapshots that have been written and not been pruned yet, the oldest first.
+    snapshots: Vec<Snapshot>,
+    /// The best validation metric reported so far.
+    best_metric: Option<f32>,
+
+    /// The phase of the freeze schedule that is currently applied to the network.
+    freeze_phase: Option<usize>,
+    /// The phase of the training plan that is being trained, if a plan is run.
+    plan_phase: Option<usize>,
+    /// The batch size of the phase of the training plan that is being trained, if it has one.
+    plan_batch_size: Option<usize>,
+    /// The starts of the phases of the training plans that have been trained.
+    phase_boundaries: Vec<PhaseBoundary>,
+
+    /// Receives the per-example losses of every trained minibatch.
+    sample_loss_callback: Option<Box<SampleLossCallback>>,
+
+    /// The probe of the activations of the network, if any.
+    activation_probe: Option<ActivationProbe>,
+
+    /// The structural regularizers with the name and index of their learnable weight.
+    structural_regularizers: Vec<(String, usize, RegularizedWeight)>,
+
+    /// The exponential moving averages of the learnable weights, if they are kept.
+    ema_weights: Vec<ArcLock<SharedTensor<f32>>>,
+    /// Whether the moving averages are currently swapped into the network.
+    ema_swapped_in: bool,
+
+    /// The gradient statistics of the learnable weights from the last iteration they were computed.
+    gradient_stats: Vec<GradientStats>,
+
+    /// The transfers of the last trained minibatch, if they are recorded.
+    transfer_summary: Option<TransferSummary>,
 
     solver_backend: PhantomData<SolverB>,
 }
@@ -37,16 +107,48 @@ impl<SolverB: IBackend + SolverOps<f32> + 'static, B: IBackend + LayerOps<f32> +
     /// [1]: ./struct.SolverConfig.html
     ///
     /// This is the **preferred method** to create a Solver for training a neural network.
+    ///
+    /// Panics if the network or the objective can not be [created from their config][2].
+    /// [2]: ../layer/struct.Layer.html#method.from_config
     pub fn from_config(net_backend: Rc<B>, obj_backend: Rc<SolverB>, config: &SolverConfig) -> Solver<SolverB, B> {
-        let network = Layer::from_config(net_backend, &config.network);
+        let network = Layer::from_config(net_backend, &config.network).unwrap_or_else(|err| panic!("{}", err));
         let mut worker = config.solver.with_config(obj_backend.clone(), &config);
         worker.init(&network);
 
         Solver {
             worker: worker,
             net: network,
-            objective: Layer::from_config(obj_backend, &config.objective),
+            objective: Layer::from_config(obj_backend, &config.objective).unwrap_or_else(|err| panic!("{}", err)),
             iter: 0,
+            minibatches: 0,
+            elapsed: Duration::from_secs(0),
+
+            accumulated_gradients: Vec::new(),
+            accumulated_gradients_f64: Vec::new(),
+            accumulated_steps: 0,
+
+            recent_losses: VecDeque::with_capacity(config.loss_history_size),
+
+            snapshots: Vec::new(),
+            best_metric: None,
+
+            freeze_phase: None,
+            plan_phase: None,
+            plan_batch_size: None,
+            phase_boundaries: Vec::new(),
+
+            sample_loss_callback: None,
+
+            activation_probe: None,
+
+            structural_regularizers: Vec::new(),
+
+            ema_weights: Vec::new(),
+            ema_swapped_in: false,
+
+            gradient_stats: Vec::new(),
+
+            transfer_summary: None,
 
             config: config.clone(),
             solver_backend: PhantomData::<SolverB>,
@@ -64,29 +166,1011 @@ impl<SolverB: IBackend + SolverOps<f32> + 'static, B: IBackend + LayerOps<f32> +
 
     /// Initialize the training net
     fn init_net(&mut self, backend: Rc<B>, param: &mut SolverConfig) {
-        self.net = Layer::from_config(backend, &param.network);
+        self.net = Layer::from_config(backend, &param.network).unwrap_or_else(|err| panic!("{}", err));
     }
 
     /// Train the network with one minibatch
+    ///
+    /// If [SolverConfig.accumulate_steps][1] is larger than one, the minibatch is treated as a
+    /// micro-batch: its gradients are accumulated and the weights are only updated once
+    /// `accumulate_steps` micro-batches have been processed.
+    /// [1]: ./struct.SolverConfig.html
     pub fn train_minibatch(&mut self,
                            mb_data: ArcLock<SharedTensor<f32>>,
                            mb_target: ArcLock<SharedTensor<f32>>)
                            -> ArcLock<SharedTensor<f32>> {
+        self.train(mb_data, mb_target, None)
+    }
+
+    /// Train the network with one minibatch whose samples are identified by `sample_ids`.
+    ///
+    /// Works like [train_minibatch][1], but the [SampleLossCallback][2] receives the
+    /// `sample_ids` instead of the positions of the samples in the minibatch, so it can
+    /// map the losses back to the items of the dataset.
+    /// [1]: #method.train_minibatch
+    /// [2]: ./trait.SampleLossCallback.html
+    pub fn train_minibatch_with_ids(&mut self,
+                                    mb_data: ArcLock<SharedTensor<f32>>,
+                                    mb_target: ArcLock<SharedTensor<f32>>,
+                                    sample_ids: &[usize])
+                                    -> ArcLock<SharedTensor<f32>> {
+        self.train(mb_data, mb_target, Some(sample_ids))
+    }
+
+    fn train(&mut self,
+             mb_data: ArcLock<SharedTensor<f32>>,
+             mb_target: ArcLock<SharedTensor<f32>>,
+             sample_ids: Option<&[usize]>)
+             -> ArcLock<SharedTensor<f32>> {
+        assert!(!self.ema_swapped_in,
+                "The moving averages of the weights have to be swapped out before training");
+        if self.config.record_transfers {
+            transfer::start_recording();
+        }
+        let network_out = self.train_step(mb_data, mb_target, sample_ids);
+        if self.config.record_transfers {
+            self.transfer_summary = transfer::finish_recording();
+            if let Some(ref summary) = self.transfer_summary {
+                debug!("Iteration {} - {}", self.iter, summary);
+            }
+        }
+        network_out
+    }
+
+    fn train_step(&mut self,
+                  mb_data: ArcLock<SharedTensor<f32>>,
+                  mb_target: ArcLock<SharedTensor<f32>>,
+                  sample_ids: Option<&[usize]>)
+                  -> ArcLock<SharedTensor<f32>> {
+        self.apply_freeze_schedule();
+
+        // feed the minibatch to the devices of the network and the classifier
+        self.feed_input("data", &mb_data, self.net.backend().device());
+        self.feed_input("target", &mb_target, self.objective.backend().device());
+
         // forward through network and classifier
         let network_out = self.net.forward(&[mb_data])[0].clone();
         let _ = self.objective.forward(&[network_out.clone(), mb_target]);
+        self.minibatches += 1;
+        self.record_loss();
+        self.report_sample_losses(sample_ids);
 
-        // forward through network and classifier
+        // backpropagate through classifier and network
+        self.net.clear_weights_gradients();
         let classifier_gradient = self.objective.backward(&[]);
         self.net.backward(&classifier_gradient[0..1]);
 
+        if self.config.accumulate_steps > 1 {
+            self.accumulate_gradients();
+            if self.accumulated_steps < self.config.accumulate_steps {
+                return network_out;
+            }
+            self.restore_accumulated_gradients();
+        }
+
+        self.track_gradient_stats();
+        self.apply_structural_regularizers();
+        // the weights may have been filled or updated on another device
+        self.net.sync_weights_to(self.worker.backend().device()).unwrap();
         self.worker.compute_update(&self.config, &mut self.net, self.iter);
-        self.net.update_weights(self.worker.backend());
+        if self.worker.updates_weights(&self.config) {
+            self.net.record_weights_written(self.worker.backend().device());
+        } else {
+            self.net.update_weights(self.worker.backend());
+        }
         self.iter += 1;
+        self.update_ema();
+        self.run_activation_probe();
+
+        if let Some(interval) = self.config.snapshot_interval {
+            if interval > 0 && self.iter % interval == 0 {
+                if let Err(e) = self.snapshot(None) {
+                    error!("Failed to write snapshot at iteration {}: {}", self.iter, e);
+                }
+            }
+        }
 
         network_out
     }
 
+    /// Train the network with the batches of `data` until one of the stopping criteria of
+    /// the [SolverConfig][1] is met, starting a new epoch whenever `data` runs out of batches.
+    /// [1]: ./struct.SolverConfig.html#structfield.max_iter
+    ///
+    /// The criteria are checked after every minibatch, so the first one to trigger wins.
+    /// If several trigger after the same minibatch, `max_iter` takes precedence over
+    /// `max_duration`, `target_loss` and `max_epochs`, in that order.
+    ///
+    /// Returns an error if no criterion is configured, if `max_epochs` is configured but
+    /// `data` doesn't know the [number of batches per epoch][2] or if `data` has no batches.
+    /// [2]: ../data/trait.DataIterator.html#method.batches_per_epoch
+    pub fn fit(&mut self, data: &mut DataIterator) -> Result<TrainingSummary, String> {
+        let batches_per_epoch = data.batches_per_epoch();
+        if self.config.max_epochs.is_some() && batches_per_epoch.is_none() {
+            return Err("max_epochs requires a DataIterator that knows the number of batches per epoch".to_owned());
+        }
+        if self.config.max_iter.is_none() && self.config.max_duration.is_none() &&
+           self.config.target_loss.is_none() && self.config.max_epochs.is_none() {
+            return Err("No stopping criterion is configured, set max_iter, max_duration, target_loss or max_epochs"
+                .to_owned());
+        }
+
+        let start = Instant::now();
+        let elapsed = self.elapsed;
+        loop {
+            let batch = match data.next_batch() {
+                Some(batch) => batch,
+                None => {
+                    data.reset();
+                    try!(data.next_batch().ok_or("The DataIterator has no batches".to_owned()))
+                }
+            };
+            if let Some(batch_size) = self.plan_batch_size {
+                let samples = batch.data.read().unwrap().desc()[0];
+                if samples != batch_size {
+                    return Err(format!("The phase {} has a batch size of {}, but got a batch of {} samples",
+                                       self.plan_phase.unwrap_or(0),
+                                       batch_size,
+                                       samples));
+                }
+            }
+            self.train_minibatch_with_ids(batch.data, batch.target, &batch.sample_ids);
+            self.elapsed = elapsed + start.elapsed();
+
+            if let Some(criterion) = self.stop_criterion(batches_per_epoch) {
+                let summary = TrainingSummary {
+                    criterion: criterion,
+                    iterations: self.iter,
+                    minibatches: self.minibatches,
+                    elapsed: self.elapsed,
+                    smoothed_loss: self.smoothed_loss(),
+                };
+                info!("{}", summary);
+                return Ok(summary);
+            }
+        }
+    }
+
+    /// Train the network through the phases of `plan` with the batches of `loaders`, one
+    /// loader per phase, see [TrainingPlan][1].
+    /// [1]: ./plan/struct.TrainingPlan.html
+    ///
+    /// Every phase is trained with [fit][2] until the iteration at which the next phase
+    /// starts, so the `max_iter` of the [SolverConfig][3] is ignored. When a phase starts,
+    /// its learning rate schedule, minibatch size and frozen layers replace those of the
+    /// SolverConfig, which keeps them after the plan, and the boundary is logged and
+    /// [recorded][6]. The other stopping criteria of the SolverConfig still apply: if one of
+    /// them ends a phase early, the plan stops there. Running the plan again continues with
+    /// that phase, also after a [resume][4] from the [progress][5] of a snapshot, which
+    /// records the phase.
+    /// [2]: #method.fit
+    /// [3]: ./struct.SolverConfig.html
+    /// [4]: #method.resume
+    /// [5]: ./struct.TrainingProgress.html#method.snapshot_path
+    /// [6]: #method.phase_boundaries
+    ///
+    /// Returns the summaries of the phases that have been trained, or an error if the plan
+    /// is invalid, the number of loaders doesn't match the number of phases, a loader
+    /// yields a batch that doesn't have the batch size of its phase or a phase can not be
+    /// trained, see [fit][2].
+    pub fn run_plan(&mut self,
+                    plan: &TrainingPlan,
+                    loaders: &mut [&mut DataIterator])
+                    -> Result<Vec<PhaseSummary>, String> {
+        try!(plan.validate());
+        if loaders.len() != plan.phases.len() {
+            return Err(format!("The plan has {} phases, but there are {} loaders",
+                               plan.phases.len(),
+                               loaders.len()));
+        }
+
+        let max_iter = self.config.max_iter;
+        let first_phase = self.plan_phase.unwrap_or(0);
+        let mut summaries = Vec::new();
+        for (phase_id, (phase, data)) in plan.phases.iter().zip(loaders.iter_mut()).enumerate().skip(first_phase) {
+            let start_iter = plan.start_iter(phase_id);
+            let end_iter = start_iter + phase.iterations;
+            if self.iter >= end_iter {
+                continue;
+            }
+            self.start_plan_phase(phase_id, phase, start_iter);
+            self.config.max_iter = Some(end_iter);
+            let result = self.fit(&mut **data);
+            self.config.max_iter = max_iter;
+            self.plan_batch_size = None;
+
+            let summary = try!(result);
+            let criterion = summary.criterion;
+            summaries.push(PhaseSummary {
+                phase: phase_id,
+                name: phase.name.clone(),
+                start_iter: start_iter,
+                summary: summary,
+            });
+            if criterion != StopCriterion::MaxIter {
+                info!("Phase {} ({}) stopped early by {:?}", phase_id, phase.name, criterion);
+                break;
+            }
+        }
+        Ok(summaries)
+    }
+
+    /// Apply the learning rate schedule and the frozen layers of the phase `phase_id` of a
+    /// training plan, which starts at iteration `start_iter`.
+    fn start_plan_phase(&mut self, phase_id: usize, phase: &TrainingPhase, start_iter: usize) {
+        let at_boundary = self.iter == start_iter;
+        info!("Iteration {}: {} phase {} ({})",
+              self.iter,
+              if at_boundary { "starting" } else { "continuing" },
+              phase_id,
+              phase.name);
+        self.plan_phase = Some(phase_id);
+        self.plan_batch_size = phase.batch_size;
+
+        if let Some(ref lr_scheduler) = phase.lr_scheduler {
+            self.config.lr_scheduler = Some(lr_scheduler.clone());
+        }
+        if let Some(batch_size) = phase.batch_size {
+            self.config.minibatch_size = batch_size;
+        }
+        self.config.freeze_schedule = FreezeSchedule {
+            phases: vec![FreezePhase {
+                             start_iter: start_iter,
+                             frozen: phase.frozen.clone(),
+                         }],
+        };
+        // the schedule of every phase has a single freeze phase, which has to be applied
+        // although its index equals the one of the previous schedule
+        self.freeze_phase = None;
+        self.apply_freeze_schedule();
+
+        if phase.reset_history && at_boundary {
+            let weight_ids = (0..self.net.learnable_weights_data().len()).collect::<Vec<_>>();
+            self.worker.reset_history(&weight_ids);
+        }
+
+        if at_boundary {
+            let frozen_weights = self.net
+                .learnable_weights_names()
+                .into_iter()
+                .zip(self.net.learnable_weights_frozen())
+                .filter(|&(_, frozen)| frozen)
+                .map(|(name, _)| name)
+                .collect();
+            self.phase_boundaries.push(PhaseBoundary {
+                phase: phase_id,
+                name: phase.name.clone(),
+                iter: start_iter,
+                lr: self.config.get_learning_rate(start_iter),
+                minibatch_size: self.config.minibatch_size,
+                frozen_weights: frozen_weights,
+            });
+        }
+    }
+
+    /// Returns the starts of the phases of the [training plans][1] that have been trained by
+    /// this Solver, in the order they have been started.
+    ///
+    /// A phase that is continued, e.g. after a [resume][2], has already been recorded when
+    /// it started.
+    /// [1]: #method.run_plan
+    /// [2]: #method.resume
+    pub fn phase_boundaries(&self) -> &[PhaseBoundary] {
+        &self.phase_boundaries
+    }
+
+    /// Returns the first stopping criterion that is met, if any.
+    fn stop_criterion(&self, batches_per_epoch: Option<usize>) -> Option<StopCriterion> {
+        if self.config.max_iter.map_or(false, |max_iter| self.iter >= max_iter) {
+            return Some(StopCriterion::MaxIter);
+        }
+        if self.config.max_duration.map_or(false, |max_duration| self.elapsed >= max_duration) {
+            return Some(StopCriterion::MaxDuration);
+        }
+        if let (Some(target_loss), Some(loss)) = (self.config.target_loss, self.smoothed_loss()) {
+            if loss < target_loss {
+                return Some(StopCriterion::TargetLoss);
+            }
+        }
+        if let (Some(max_epochs), Some(batches)) = (self.config.max_epochs, batches_per_epoch) {
+            if self.minibatches >= max_epochs * batches {
+                return Some(StopCriterion::MaxEpochs);
+            }
+        }
+        None
+    }
+
+    /// Returns the mean loss of the objective over one epoch of the validation data `val_data`.
+    ///
+    /// `val_data` is [reset][1] before and after the epoch. The network and the objective
+    /// run in [inference mode][2], e.g. without dropout, and are switched back into training
+    /// mode afterwards. The weights are not updated and the validation losses are not added
+    /// to the [recent losses][3].
+    /// [1]: ../data/trait.DataIterator.html#tymethod.reset
+    /// [2]: ../layer/struct.Layer.html#method.set_train
+    /// [3]: #method.recent_losses
+    ///
+    /// The result can be passed to [report_validation_metric][4].
+    /// [4]: #method.report_validation_metric
+    ///
+    /// Panics if `val_data` has no batches.
+    pub fn validate(&mut self, val_data: &mut DataIterator) -> f32 {
+        self.net.set_train(false);
+        self.objective.set_train(false);
+        val_data.reset();
+
+        let mut loss_sum = 0f32;
+        let mut num_batches = 0;
+        while let Some(batch) = val_data.next_batch() {
+            let network_out = self.net.forward(&[batch.data])[0].clone();
+            self.objective.forward(&[network_out, batch.target]);
+            loss_sum += self.objective.total_loss();
+            num_batches += 1;
+        }
+
+        val_data.reset();
+        self.net.set_train(true);
+        self.objective.set_train(true);
+        assert!(num_batches > 0, "The validation data has no batches");
+        loss_sum / num_batches as f32
+    }
+
+    /// Returns the mean of the [recent losses][1], or `None` if no losses are kept yet.
+    /// [1]: #method.recent_losses
+    pub fn smoothed_loss(&self) -> Option<f32> {
+        if self.recent_losses.is_empty() {
+            return None;
+        }
+        Some(self.recent_losses.iter().fold(0f32, |sum, loss| sum + loss) / self.recent_losses.len() as f32)
+    }
+
+    /// Returns how far the training has progressed, e.g. to store it next to a snapshot
+    /// and [resume][1] from it later.
+    /// [1]: #method.resume
+    pub fn progress(&self) -> TrainingProgress {
+        TrainingProgress {
+            iter: self.iter,
+            minibatches: self.minibatches,
+            elapsed: self.elapsed,
+            phase: self.plan_phase,
+        }
+    }
+
+    /// Continue the training from `progress`, e.g. after the network has been restored
+    /// from a snapshot.
+    ///
+    /// The elapsed time counts towards [max_duration][1] unless `reset_elapsed` is set,
+    /// in which case the time limit starts anew. A [training plan][2] that is run afterwards
+    /// continues with the phase of the progress.
+    /// [1]: ./struct.SolverConfig.html#structfield.max_duration
+    /// [2]: #method.run_plan
+    pub fn resume(&mut self, progress: &TrainingProgress, reset_elapsed: bool) {
+        self.iter = progress.iter;
+        self.minibatches = progress.minibatches;
+        self.plan_phase = progress.phase;
+        self.elapsed = if reset_elapsed {
+            Duration::from_secs(0)
+        } else {
+            progress.elapsed
+        };
+    }
+
+    /// Report the validation metric of the network at the current iteration.
+    ///
+    /// Lower values are better, so report e.g. the validation loss or the negated accuracy.
+    /// If the metric is better than all previously reported ones and a
+    /// [snapshot_prefix][1] is configured, a snapshot is written and its path is returned.
+    /// [1]: ./struct.SolverConfig.html#structfield.snapshot_prefix
+    pub fn report_validation_metric(&mut self, metric: f32) -> io::Result<Option<PathBuf>> {
+        let improved = self.best_metric.map_or(true, |best| metric < best);
+        if !improved {
+            return Ok(None);
+        }
+        self.best_metric = Some(metric);
+        self.snapshot(Some(metric))
+    }
+
+    /// Write a snapshot of the network and prune the old ones.
+    ///
+    /// The snapshot is named after the [snapshot_prefix][1], the iteration and the `metric`.
+    /// The [progress][2] of the training is written next to it.
+    /// Does nothing if no snapshot_prefix is configured.
+    /// [1]: ./struct.SolverConfig.html#structfield.snapshot_prefix
+    /// [2]: ./struct.TrainingProgress.html#method.snapshot_path
+    fn snapshot(&mut self, metric: Option<f32>) -> io::Result<Option<PathBuf>> {
+        let prefix = match self.config.snapshot_prefix {
+            Some(ref prefix) => prefix.clone(),
+            None => return Ok(None),
+        };
+        let name = match metric {
+            Some(metric) => format!("{}_iter_{}_metric_{:.6}", prefix, self.iter, metric),
+            None => format!("{}_iter_{}", prefix, self.iter),
+        };
+        let mut path = PathBuf::from(format!("{}.capnp", name));
+        let mut collisions = 0;
+        while path.exists() {
+            collisions += 1;
+            path = PathBuf::from(format!("{}_{}.capnp", name, collisions));
+        }
+
+        // the network is saved with its trained weights, even during a validation with
+        // the moving averages swapped in
+        let was_swapped_in = self.ema_swapped_in;
+        self.set_ema_swapped_in(false);
+        let result = self.save_with_ema(&path);
+        self.set_ema_swapped_in(was_swapped_in);
+        let ema_path = try!(result);
+        let progress_path = TrainingProgress::snapshot_path(&path);
+        try!(self.progress().save(&progress_path));
+
+        info!("Wrote snapshot {}", path.display());
+        self.snapshots.push(Snapshot {
+            path: path.clone(),
+            ema_path: ema_path,
+            progress_path: progress_path,
+            metric: metric,
+        });
+        try!(self.prune_snapshots());
+        Ok(Some(path))
+    }
+
+    /// Save the network to `path` and, if moving averages of the weights are kept,
+    /// the network with the moving averages next to it.
+    ///
+    /// Returns the path of the network with the moving averages.
+    fn save_with_ema(&mut self, path: &PathBuf) -> io::Result<Option<PathBuf>> {
+        try!(self.net
+            .sync_weights_to(native_backend().device())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        try!(self.net.save(path));
+        if self.ema_weights.is_empty() {
+            return Ok(None);
+        }
+        let ema_path = path.with_extension("ema.capnp");
+        self.set_ema_swapped_in(true);
+        let result = self.net.save(&ema_path);
+        self.set_ema_swapped_in(false);
+        try!(result);
+        Ok(Some(ema_path))
+    }
+
+    /// Delete all snapshots except the latest one and the [keep_best][1] ones
+    /// with the best metric.
+    /// [1]: ./struct.SolverConfig.html#structfield.keep_best
+    fn prune_snapshots(&mut self) -> io::Result<()> {
+        if self.config.keep_best == 0 || self.snapshots.is_empty() {
+            return Ok(());
+        }
+        let latest = self.snapshots.len() - 1;
+        let mut ranked = (0..self.snapshots.len())
+            .filter(|&i| self.snapshots[i].metric.is_some())
+            .collect::<Vec<_>>();
+        ranked.sort_by(|&a, &b| {
+            let a = self.snapshots[a].metric.unwrap();
+            let b = self.snapshots[b].metric.unwrap();
+            a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+        ranked.truncate(self.config.keep_best);
+
+        let snapshots = ::std::mem::replace(&mut self.snapshots, Vec::new());
+        for (i, snapshot) in snapshots.into_iter().enumerate() {
+            if i == latest || ranked.contains(&i) {
+                self.snapshots.push(snapshot);
+            } else {
+                let paths = vec![&snapshot.path, &snapshot.progress_path];
+                for path in paths.into_iter().chain(snapshot.ema_path.as_ref()) {
+                    match fs::remove_file(path) {
+                        Ok(_) => info!("Removed snapshot {}", path.display()),
+                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Freeze the layers of the network according to the phase of the
+    /// [freeze schedule][1] for the current iteration.
+    /// [1]: ./struct.SolverConfig.html#structfield.freeze_schedule
+    ///
+    /// The solver history of weights that are unfrozen is reset.
+    fn apply_freeze_schedule(&mut self) {
+        let phase = self.config.freeze_schedule.phase(self.iter);
+        if phase == self.freeze_phase {
+            return;
+        }
+        self.freeze_phase = phase;
+
+        let was_frozen = self.net.learnable_weights_frozen();
+        self.net.set_frozen(false);
+        if let Some(phase) = phase {
+            for pattern in &self.config.freeze_schedule.phases[phase].frozen {
+                if self.net.set_frozen_matching(pattern, true).is_empty() {
+                    warn!("Freeze pattern '{}' does not match any layer", pattern);
+                }
+            }
+        }
+
+        let names = self.net.learnable_weights_names();
+        let mut unfrozen = Vec::new();
+        for (weight_id, (was_frozen, frozen)) in
+            was_frozen.into_iter().zip(self.net.learnable_weights_frozen()).enumerate() {
+            if was_frozen && !frozen {
+                info!("Iteration {}: unfreezing weight {}", self.iter, names[weight_id]);
+                unfrozen.push(weight_id);
+            } else if !was_frozen && frozen {
+                info!("Iteration {}: freezing weight {}", self.iter, names[weight_id]);
+            }
+        }
+        self.worker.reset_history(&unfrozen);
+    }
+
+    /// Synchronize the input `name` of the minibatch to `device`, so it is recorded as a
+    /// [transfer][1] of its own instead of being part of the first layer that reads it.
+    /// [1]: ../transfer/index.html
+    fn feed_input<D: IDevice>(&self, name: &str, input: &ArcLock<SharedTensor<f32>>, device: &D) {
+        let input = input.read().unwrap();
+        let bytes = input.desc().size() * mem::size_of::<f32>();
+        // an input that has not been written yet fails the forward pass with a better error
+        let _ = transfer::record(TransferCategory::InputFeed, name, bytes, || input.read(device).is_ok());
+    }
+
+    /// Add the loss of the current minibatch to the recent losses.
+    fn record_loss(&mut self) {
+        if self.config.loss_history_size == 0 {
+            return;
+        }
+        if self.recent_losses.len() == self.config.loss_history_size {
+            self.recent_losses.pop_front();
+        }
+        self.recent_losses.push_back(self.objective.total_loss());
+    }
+
+    /// Set the callback that receives the per-example losses of every trained minibatch,
+    /// e.g. to mine hard examples.
+    ///
+    /// The losses are taken from the loss layers of the objective, see
+    /// [Layer::per_example_losses][1].
+    /// [1]: ../layer/struct.Layer.html#method.per_example_losses
+    pub fn set_sample_loss_callback<C: SampleLossCallback + 'static>(&mut self, callback: C) {
+        self.sample_loss_callback = Some(Box::new(callback));
+    }
+
+    /// Regularize the 2-D learnable weights of the layers whose name matches `pattern` with
+    /// `regularizer`, see [StructuralRegularizer][1].
+    /// [1]: ./regularizer/enum.StructuralRegularizer.html
+    ///
+    /// The gradient of the penalty is added to the gradient of the weights before every
+    /// weight update, scaled by the minibatch size, as the gradients are averaged over the
+    /// minibatch afterwards. Weights of other shapes, like biases, are skipped. See
+    /// [Layer::set_frozen_matching][2] for the syntax of the pattern. Several regularizers
+    /// can be added for the same weights.
+    /// [2]: ../layer/struct.Layer.html#method.set_frozen_matching
+    ///
+    /// Returns the names of the regularized weights, or an error if the regularizer is
+    /// invalid or no 2-D weight matches.
+    pub fn add_structural_regularizer(&mut self,
+                                      pattern: &str,
+                                      regularizer: StructuralRegularizer)
+                                      -> Result<Vec<String>, String> {
+        try!(regularizer.validate());
+        let weights = self.net.learnable_weights_data();
+        let names = self.net.learnable_weights_names();
+        let mut regularized = Vec::new();
+        for weight_id in self.net.learnable_weights_matching(pattern) {
+            let shape = weights[weight_id].read().unwrap().desc().clone();
+            if shape.len() == 2 {
+                regularized.push((names[weight_id].clone(),
+                                  weight_id,
+                                  try!(RegularizedWeight::new(regularizer, &shape))));
+            }
+        }
+        if regularized.is_empty() {
+            return Err(format!("The pattern '{}' does not match any layer with a 2-D weight", pattern));
+        }
+        let regularized_names = regularized.iter().map(|&(ref name, _, _)| name.clone()).collect();
+        self.structural_regularizers.extend(regularized);
+        Ok(regularized_names)
+    }
+
+    /// Returns the names of the weights regularized by a [spectral norm penalty][1] and
+    /// the estimates of their largest singular value at the last weight update.
+    /// [1]: ./regularizer/enum.StructuralRegularizer.html#variant.SpectralNorm
+    pub fn spectral_norm_estimates(&self) -> Vec<(String, f32)> {
+        self.structural_regularizers
+            .iter()
+            .filter_map(|&(ref name, _, ref regularized)| {
+                regularized.spectral_norm().map(|sigma| (name.clone(), sigma))
+            })
+            .collect()
+    }
+
+    /// Adds the gradients of the structural regularizers to the gradients of the network.
+    fn apply_structural_regularizers(&mut self) {
+        if self.structural_regularizers.is_empty() {
+            return;
+        }
+        let weights = self.net.learnable_weights_data();
+        let gradients = self.net.learnable_weights_gradients();
+        let scale = self.config.minibatch_size as f32;
+        let backend = self.worker.backend();
+        for &mut (ref name, weight_id, ref mut regularized) in &mut self.structural_regularizers {
+            if let Err(err) = regularized.add_gradient(backend,
+                                                       &weights[weight_id].read().unwrap(),
+                                                       &mut gradients[weight_id].write().unwrap(),
+                                                       scale) {
+                panic!("Failed to regularize weight {}: {}", name, err);
+            }
+        }
+    }
+
+    /// Probe the activations of the network on the fixed batch `batch` every `interval`
+    /// iterations, e.g. to track the drift of the representations or dead units over the
+    /// training.
+    ///
+    /// After every `interval`-th iteration the network runs a forward pass on `batch` in
+    /// [inference mode][1] and `callback` receives the iteration and the activations of
+    /// the blobs `blob_names`, see [Layer::blob_data][2]. The probe doesn't compute
+    /// gradients, doesn't draw random numbers, as the layers don't in inference mode, and
+    /// restores the outputs of the trained minibatch afterwards. `batch` must have the
+    /// shape of the input of the network.
+    /// [1]: ../layer/struct.Layer.html#method.set_train
+    /// [2]: ../layer/struct.Layer.html#method.blob_data
+    ///
+    /// Replaces a previously set probe. Returns an error if `interval` is 0 or if the
+    /// network has no blob of one of the names.
+    pub fn set_activation_probe<C: ProbeCallback + 'static>(&mut self,
+                                                             batch: ArcLock<SharedTensor<f32>>,
+                                                             blob_names: &[&str],
+                                                             interval: usize,
+                                                             callback: C)
+                                                             -> Result<(), String> {
+        if interval == 0 {
+            return Err("The interval of the activation probe must be positive, but is 0".to_owned());
+        }
+        for name in blob_names {
+            try!(self.net.try_blob_data(name));
+        }
+        self.activation_probe = Some(ActivationProbe {
+            batch: batch,
+            blob_names: blob_names.iter().map(|name| name.to_string()).collect(),
+            interval: interval,
+            callback: Box::new(callback),
+        });
+        Ok(())
+    }
+
+    /// Returns the activations of the network on the batch of the [activation probe][1],
+    /// or `None` if no probe is set.
+    /// [1]: #method.set_activation_probe
+    ///
+    /// Unlike the probe after an iteration, this overwrites the outputs of the last
+    /// trained minibatch.
+    pub fn probe_activations(&mut self) -> Option<Vec<ProbeActivation>> {
+        let (batch, blob_names) = match self.activation_probe {
+            Some(ref probe) => (probe.batch.clone(), probe.blob_names.clone()),
+            None => return None,
+        };
+        self.net.set_train(false);
+        self.net.forward(&[batch]);
+        let native = native_backend();
+        let activations = blob_names.into_iter()
+            .map(|name| {
+                // the names have been checked when the probe was set
+                let blob = self.net.blob_data(&name).unwrap();
+                let blob = blob.read().unwrap();
+                let values = blob.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+                ProbeActivation {
+                    name: name,
+                    shape: blob.desc().clone(),
+                    values: values,
+                }
+            })
+            .collect();
+        self.net.set_train(true);
+        Some(activations)
+    }
+
+    /// Run the activation probe if it is due in the current iteration.
+    fn run_activation_probe(&mut self) {
+        match self.activation_probe {
+            Some(ref probe) if self.iter % probe.interval == 0 => {}
+            _ => return,
+        }
+        // the caller reads the outputs of the trained minibatch, that the probe overwrites
+        let native = native_backend();
+        let outputs = self.net
+            .output_blobs_data
+            .iter()
+            .map(|output| output.read().unwrap().read(native.device()).unwrap().as_slice::<f32>().to_vec())
+            .collect::<Vec<_>>();
+        let activations = self.probe_activations().unwrap();
+        for (output, values) in self.net.output_blobs_data.iter().zip(outputs) {
+            write_to_memory(output.write().unwrap().write_only(native.device()).unwrap(), &values);
+        }
+
+        let iter = self.iter;
+        if let Some(ref mut probe) = self.activation_probe {
+            probe.callback.activations(iter, &activations);
+        }
+    }
+
+    /// Pass the per-example losses of the current minibatch to the SampleLossCallback.
+    ///
+    /// Without `sample_ids` the samples are identified by their position in the minibatch.
+    fn report_sample_losses(&mut self, sample_ids: Option<&[usize]>) {
+        let callback = match self.sample_loss_callback {
+            Some(ref mut callback) => callback,
+            None => return,
+        };
+        let losses = match self.objective.per_example_losses() {
+            Some(losses) => losses,
+            None => return,
+        };
+        match sample_ids {
+            Some(sample_ids) => {
+                assert_eq!(sample_ids.len(), losses.len(), "Need one sample id per sample");
+                callback.sample_losses(sample_ids, &losses);
+            }
+            None => {
+                let positions = (0..losses.len()).collect::<Vec<_>>();
+                callback.sample_losses(&positions, &losses);
+            }
+        }
+    }
+
+    /// Returns the losses of the last `n` minibatches, the most recent one last.
+    ///
+    /// At most [SolverConfig.loss_history_size][1] losses are kept, so fewer than `n`
+    /// losses are returned if `n` is larger, or if fewer minibatches have been trained.
+    /// [1]: ./struct.SolverConfig.html#structfield.loss_history_size
+    pub fn recent_losses(&self, n: usize) -> Vec<f32> {
+        let skip = self.recent_losses.len().saturating_sub(n);
+        self.recent_losses.iter().skip(skip).cloned().collect()
+    }
+
+    /// Add the gradients of the current micro-batch to the accumulated gradients.
+    ///
+    /// Each micro-batch contributes `1 / accumulate_steps` of its gradient, so the
+    /// accumulated gradient is the mean over all micro-batches and the effective
+    /// learning rate does not change with the number of accumulation steps.
+    fn accumulate_gradients(&mut self) {
+        if self.config.accumulate_in_f64 {
+            return self.accumulate_gradients_f64();
+        }
+        let gradients = self.net.learnable_weights_gradients();
+        if self.accumulated_gradients.is_empty() {
+            for gradient in &gradients {
+                let shape = gradient.read().unwrap().desc().clone();
+                let mut tensor = SharedTensor::new(&shape);
+                self.worker.backend().fill(&mut tensor, 0f32).unwrap();
+                self.accumulated_gradients.push(Arc::new(RwLock::new(tensor)));
+            }
+        }
+
+        let scale = native_scalar(1f32 / self.config.accumulate_steps as f32);
+        let backend = self.worker.backend();
+        for (gradient, accumulated) in gradients.iter().zip(&self.accumulated_gradients) {
+            backend.axpy(&scale,
+                      &gradient.read().unwrap(),
+                      &mut accumulated.write().unwrap())
+                .unwrap();
+        }
+        self.accumulated_steps += 1;
+    }
+
+    /// Add the gradients of the current micro-batch to the accumulated `f64` gradients.
+    ///
+    /// The gradients are accumulated on the host, so small contributions of many
+    /// micro-batches are not lost to the rounding of `f32`.
+    fn accumulate_gradients_f64(&mut self) {
+        let native = native_backend();
+        let gradients = self.net.learnable_weights_gradients();
+        if self.accumulated_gradients_f64.is_empty() {
+            for gradient in &gradients {
+                self.accumulated_gradients_f64.push(vec![0f64; gradient.read().unwrap().desc().size()]);
+            }
+        }
+
+        let scale = 1f64 / self.config.accumulate_steps as f64;
+        for (gradient, accumulated) in gradients.iter().zip(&mut self.accumulated_gradients_f64) {
+            let gradient = gradient.read().unwrap();
+            let values = gradient.read(native.device()).unwrap().as_slice::<f32>();
+            for (sum, value) in accumulated.iter_mut().zip(values) {
+                *sum += *value as f64 * scale;
+            }
+        }
+        self.accumulated_steps += 1;
+    }
+
+    /// Move the accumulated gradients into the network and reset the accumulation.
+    fn restore_accumulated_gradients(&mut self) {
+        if self.config.accumulate_in_f64 {
+            let native = native_backend();
+            let gradients = self.net.learnable_weights_gradients();
+            for (gradient, accumulated) in gradients.iter().zip(&mut self.accumulated_gradients_f64) {
+                let values = accumulated.iter().map(|sum| *sum as f32).collect::<Vec<_>>();
+                write_to_memory(gradient.write().unwrap().write_only(native.device()).unwrap(),
+                                &values);
+                for sum in accumulated.iter_mut() {
+                    *sum = 0f64;
+                }
+            }
+            self.accumulated_steps = 0;
+            return;
+        }
+        let gradients = self.net.learnable_weights_gradients();
+        let backend = self.worker.backend();
+        for (gradient, accumulated) in gradients.iter().zip(&self.accumulated_gradients) {
+            backend.copy(&accumulated.read().unwrap(), &mut gradient.write().unwrap())
+                .unwrap();
+            backend.fill(&mut accumulated.write().unwrap(), 0f32).unwrap();
+        }
+        self.accumulated_steps = 0;
+    }
+
+    /// Update the exponential moving averages of the weights after an iteration.
+    ///
+    /// The averages start at the weights after the first iteration.
+    fn update_ema(&mut self) {
+        let decay = match self.config.ema_decay {
+            Some(decay) => decay,
+            None => return,
+        };
+        let weights = self.net.learnable_weights_data();
+        let backend = self.worker.backend();
+        if self.ema_weights.is_empty() {
+            for weight in &weights {
+                let weight = weight.read().unwrap();
+                let mut ema = SharedTensor::new(weight.desc());
+                backend.copy(&weight, &mut ema).unwrap();
+                self.ema_weights.push(Arc::new(RwLock::new(ema)));
+            }
+            return;
+        }
+
+        let decay = if self.config.ema_warmup {
+            let iter = self.iter as f32;
+            decay.min((1f32 + iter) / (10f32 + iter))
+        } else {
+            decay
+        };
+        let weight_scale = native_scalar(1f32 - decay);
+        let ema_scale = native_scalar(decay);
+        for (weight, ema) in weights.iter().zip(&self.ema_weights) {
+            backend.axpby(&weight_scale,
+                       &weight.read().unwrap(),
+                       &ema_scale,
+                       &mut ema.write().unwrap())
+                .unwrap();
+        }
+    }
+
+    /// Swap the [moving averages][1] of the weights into the network, e.g. to validate or
+    /// export the network with the averaged weights.
+    /// [1]: ./struct.SolverConfig.html#structfield.ema_decay
+    ///
+    /// The trained weights are kept and restored by [swap_out_ema][2], which has to be called
+    /// before training continues. Returns an error if no moving averages are kept yet
+    /// or if they are already swapped in.
+    /// [2]: #method.swap_out_ema
+    pub fn swap_in_ema(&mut self) -> Result<(), String> {
+        if self.ema_weights.is_empty() {
+            return Err("No moving averages of the weights are kept, ema_decay is not set \
+                        or no iteration has been trained"
+                .to_owned());
+        }
+        if self.ema_swapped_in {
+            return Err("The moving averages of the weights are already swapped in".to_owned());
+        }
+        self.set_ema_swapped_in(true);
+        Ok(())
+    }
+
+    /// Swap the trained weights back into the network after [swap_in_ema][1].
+    /// [1]: #method.swap_in_ema
+    ///
+    /// Returns an error if the moving averages are not swapped in.
+    pub fn swap_out_ema(&mut self) -> Result<(), String> {
+        if !self.ema_swapped_in {
+            return Err("The moving averages of the weights are not swapped in".to_owned());
+        }
+        self.set_ema_swapped_in(false);
+        Ok(())
+    }
+
+    /// Exchange the weights of the network and their moving averages if they are not
+    /// swapped in or out as requested by `swapped_in`.
+    fn set_ema_swapped_in(&mut self, swapped_in: bool) {
+        if self.ema_swapped_in == swapped_in {
+            return;
+        }
+        for (weight, ema) in self.net.learnable_weights_data().iter().zip(&self.ema_weights) {
+            ::std::mem::swap(&mut *weight.write().unwrap(), &mut *ema.write().unwrap());
+        }
+        self.ema_swapped_in = swapped_in;
+    }
+
+    /// Returns the [LARS][1] ratios that scaled the learning rate of each learnable weight
+    /// blob in the last update, in the order of [Layer::learnable_weights_data][2].
+    /// [1]: ./struct.SolverConfig.html#structfield.lars_trust_coefficient
+    /// [2]: ../layer/struct.Layer.html#method.learnable_weights_data
+    ///
+    /// Is empty if LARS is not enabled or no update has been computed yet.
+    pub fn lars_ratios(&self) -> &[f32] {
+        self.worker.lars_ratios()
+    }
+
+    /// Compute the [gradient statistics][1] of the learnable weights if they are due in the
+    /// current iteration.
+    /// [1]: ./struct.SolverConfig.html#structfield.gradient_stats
+    fn track_gradient_stats(&mut self) {
+        let config = match self.config.gradient_stats {
+            Some(ref config) if self.iter % config.interval == 0 => config.clone(),
+            _ => return,
+        };
+        let native = native_backend();
+        let backend = self.worker.backend();
+        let reduce = |name: &str, result: &SharedTensor<f32>| {
+            transfer::record(TransferCategory::DebugStats,
+                             name,
+                             mem::size_of::<f32>(),
+                             || result.read(native.device()).unwrap().as_slice::<f32>()[0])
+        };
+
+        self.gradient_stats.clear();
+        for (name, _, gradient) in self.net.weight_blobs() {
+            let gradient = gradient.read().unwrap();
+            let mut result = SharedTensor::new(&[1]);
+            backend.nrm2(&gradient, &mut result).unwrap();
+            let norm = reduce(&name, &result);
+            backend.asum(&gradient, &mut result).unwrap();
+            let mean_abs = reduce(&name, &result) / gradient.desc().size() as f32;
+
+            let bytes = gradient.desc().size() * mem::size_of::<f32>();
+            let values = transfer::record(TransferCategory::DebugStats,
+                                          &name,
+                                          bytes,
+                                          || gradient.read(native.device()).unwrap().as_slice::<f32>());
+            let stride = cmp::max(1, values.len() / config.max_samples);
+            let (mut samples, mut near_zero, mut max_abs) = (0, 0, 0f32);
+            for value in values.chunks(stride).map(|chunk| chunk[0]) {
+                samples += 1;
+                if value.abs() <= config.zero_threshold {
+                    near_zero += 1;
+                }
+                max_abs = max_abs.max(value.abs());
+            }
+
+            let stats = GradientStats {
+                name: name,
+                norm: norm,
+                mean_abs: mean_abs,
+                max_abs: max_abs,
+                near_zero_fraction: near_zero as f32 / samples as f32,
+            };
+            info!("Iteration {} - {}", self.iter, stats);
+            self.gradient_stats.push(stats);
+        }
+    }
+
+    /// Returns the [transfers][1] of the last trained minibatch, or `None` if they are not
+    /// [recorded][2].
+    /// [1]: ../transfer/struct.TransferSummary.html
+    /// [2]: ./struct.SolverConfig.html#structfield.record_transfers
+    pub fn transfer_summary(&self) -> Option<&TransferSummary> {
+        self.transfer_summary.as_ref()
+    }
+
+    /// Returns the [gradient statistics][1] of the learnable weights from the last iteration
+    /// they have been computed in, in the order of [Layer::weight_blobs][2].
+    /// [1]: ./struct.SolverConfig.html#structfield.gradient_stats
+    /// [2]: ../layer/struct.Layer.html#method.weight_blobs
+    pub fn gradient_stats(&self) -> &[GradientStats] {
+        &self.gradient_stats
+    }
+
     /// Returns the network trained by the solver.
     ///
     /// This is the recommended method to get a usable trained network.
@@ -105,6 +1189,220 @@ impl<SolverB: IBackend + SolverOps<f32> + 'static, B: IBackend + LayerOps<f32> +
     }
 }
 
+#[derive(Debug, Clone)]
+/// A snapshot of the network written by the solver.
+struct Snapshot {
+    path: PathBuf,
+    /// The path of the network with the moving averages of the weights, if they are kept.
+    ema_path: Option<PathBuf>,
+    /// The path of the [progress](./struct.TrainingProgress.html) of the training.
+    progress_path: PathBuf,
+    /// The validation metric the snapshot has been written for, if any.
+    metric: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The stopping criterion that ended [Solver::fit][1].
+/// [1]: ./struct.Solver.html#method.fit
+pub enum StopCriterion {
+    /// The [max_iter](./struct.SolverConfig.html#structfield.max_iter) has been reached.
+    MaxIter,
+    /// The [max_duration](./struct.SolverConfig.html#structfield.max_duration) has passed.
+    MaxDuration,
+    /// The smoothed loss dropped below the [target_loss](./struct.SolverConfig.html#structfield.target_loss).
+    TargetLoss,
+    /// The [max_epochs](./struct.SolverConfig.html#structfield.max_epochs) have been trained.
+    MaxEpochs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The summary of a [Solver::fit][1].
+/// [1]: ./struct.Solver.html#method.fit
+pub struct TrainingSummary {
+    /// The stopping criterion that ended the training.
+    pub criterion: StopCriterion,
+    /// The number of iterations, including those before a [resume][1].
+    /// [1]: ./struct.Solver.html#method.resume
+    pub iterations: usize,
+    /// The number of trained minibatches, including those before a resume.
+    pub minibatches: usize,
+    /// The wall clock time spent in training, including the time before a resume
+    /// unless it has been reset.
+    pub elapsed: Duration,
+    /// The [smoothed loss](./struct.Solver.html#method.smoothed_loss) at the end of the training.
+    pub smoothed_loss: Option<f32>,
+}
+
+impl fmt::Display for TrainingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "Training stopped by {:?} after {} iterations ({} minibatches) in {}.{:03}s",
+                    self.criterion,
+                    self.iterations,
+                    self.minibatches,
+                    self.elapsed.as_secs(),
+                    self.elapsed.subsec_nanos() / 1_000_000));
+        match self.smoothed_loss {
+            Some(loss) => write!(f, ", smoothed loss {}", loss),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// How far the training of a [Solver][1] has progressed.
+/// [1]: ./struct.Solver.html#method.progress
+pub struct TrainingProgress {
+    /// The number of iterations.
+    pub iter: usize,
+    /// The number of trained minibatches.
+    pub minibatches: usize,
+    /// The wall clock time spent in [Solver::fit](./struct.Solver.html#method.fit).
+    pub elapsed: Duration,
+    /// The index of the phase of the [training plan](./struct.Solver.html#method.run_plan)
+    /// that is being trained, or `None` if no plan has been run.
+    pub phase: Option<usize>,
+}
+
+impl TrainingProgress {
+    /// Returns the path of the progress that is written next to the snapshot of the network
+    /// at `snapshot_path`.
+    ///
+    /// To continue a training from a snapshot, load the network from the snapshot and
+    /// [resume][1] the Solver from the progress [loaded][2] from this path.
+    /// [1]: ./struct.Solver.html#method.resume
+    /// [2]: #method.load
+    pub fn snapshot_path<P: AsRef<Path>>(snapshot_path: P) -> PathBuf {
+        snapshot_path.as_ref().with_extension("progress.capnp")
+    }
+
+    /// Serialize the progress to a Cap'n Proto file at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ref mut out = try!(File::create(path.as_ref()));
+
+        let mut message = ::capnp::message::Builder::new_default();
+        {
+            let mut progress = message.init_root::<capnp_progress::Builder>();
+            self.write_capnp(&mut progress);
+        }
+        ::capnp::serialize_packed::write_message(out, &message)
+    }
+
+    /// Read the progress from a Cap'n Proto file at `path` that has been written by [save][1].
+    /// [1]: #method.save
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<TrainingProgress> {
+        let path = path.as_ref();
+        let invalid = |err: ::capnp::Error| {
+            io::Error::new(io::ErrorKind::InvalidData,
+                           format!("Invalid training progress {}: {}", path.display(), err))
+        };
+        let ref mut file = try!(File::open(path));
+        let mut reader = BufReader::new(file);
+
+        let message_reader =
+            try!(::capnp::serialize_packed::read_message(&mut reader, ::capnp::message::ReaderOptions::new())
+                .map_err(&invalid));
+        let read_progress = try!(message_reader.get_root::<capnp_progress::Reader>().map_err(&invalid));
+        Ok(TrainingProgress::read_capnp(read_progress))
+    }
+}
+
+impl<'a> CapnpWrite<'a> for TrainingProgress {
+    type Builder = capnp_progress::Builder<'a>;
+
+    /// Write the TrainingProgress into a capnp message.
+    fn write_capnp(&self, builder: &mut Self::Builder) {
+        builder.set_iter(self.iter as u64);
+        builder.set_minibatches(self.minibatches as u64);
+        builder.set_elapsed_secs(self.elapsed.as_secs());
+        builder.set_elapsed_nanos(self.elapsed.subsec_nanos());
+        match self.phase {
+            Some(phase) => builder.borrow().init_phase().set_index(phase as u64),
+            None => builder.borrow().init_phase().set_none(()),
+        }
+    }
+}
+
+impl<'a> CapnpRead<'a> for TrainingProgress {
+    type Reader = capnp_progress::Reader<'a>;
+
+    fn read_capnp(reader: Self::Reader) -> Self {
+        let phase = match reader.get_phase().which().unwrap() {
+            capnp_phase::Which::None(_) => None,
+            capnp_phase::Which::Index(phase) => Some(phase as usize),
+        };
+
+        TrainingProgress {
+            iter: reader.get_iter() as usize,
+            minibatches: reader.get_minibatches() as usize,
+            elapsed: Duration::new(reader.get_elapsed_secs(), reader.get_elapsed_nanos()),
+            phase: phase,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The statistics of the gradient of a learnable weight, see [Solver::gradient_stats][1].
+/// [1]: ./struct.Solver.html#method.gradient_stats
+///
+/// Comparing them by the depth of the layers helps to detect vanishing or exploding gradients.
+pub struct GradientStats {
+    /// The display name of the weight.
+    pub name: String,
+    /// The L2 norm of the gradient.
+    pub norm: f32,
+    /// The mean of the absolute values of the gradient.
+    pub mean_abs: f32,
+    /// The largest absolute value among the sampled entries of the gradient.
+    pub max_abs: f32,
+    /// The fraction of the sampled entries of the gradient whose absolute value is at most
+    /// the [zero_threshold][1].
+    /// [1]: ./struct.GradientStatsConfig.html#structfield.zero_threshold
+    pub near_zero_fraction: f32,
+}
+
+impl fmt::Display for GradientStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "gradient of {}: norm {}, mean abs {}, max abs {}, near zero {:.1}%",
+               self.name,
+               self.norm,
+               self.mean_abs,
+               self.max_abs,
+               self.near_zero_fraction * 100f32)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Configures how often and how thoroughly the [GradientStats][1] are computed.
+/// [1]: ./struct.GradientStats.html
+pub struct GradientStatsConfig {
+    /// The statistics are computed every `interval` iterations.
+    ///
+    /// Default: 100
+    pub interval: usize,
+    /// The maximum number of evenly spaced entries of each gradient that are sampled for
+    /// `max_abs` and `near_zero_fraction`, which are counted on the host.
+    /// The norms are always computed over all entries.
+    ///
+    /// Default: 10000
+    pub max_samples: usize,
+    /// The absolute value up to which an entry counts as near zero.
+    ///
+    /// Default: 1e-8
+    pub zero_threshold: f32,
+}
+
+impl Default for GradientStatsConfig {
+    fn default() -> GradientStatsConfig {
+        GradientStatsConfig {
+            interval: 100,
+            max_samples: 10000,
+            zero_threshold: 1e-8,
+        }
+    }
+}
+
 /// Implementation of a specific Solver.
 ///
 /// See [Solvers][1]
@@ -113,6 +1411,14 @@ pub trait ISolver<SolverB, B: IBackend + LayerOps<f32>> {
     /// Initialize the solver, setting up any network related data.
     fn init(&mut self, net: &Layer<B>) {}
 
+    /// Reset the state that the solver keeps for the learnable weights with the ids
+    /// `weight_ids`, e.g. the momentum history.
+    ///
+    /// Used when weights are unfrozen by the [FreezeSchedule][1], so their first updates
+    /// do not contain stale values from before they have been frozen.
+    /// [1]: ./struct.FreezeSchedule.html
+    fn reset_history(&mut self, weight_ids: &[usize]) {}
+
     /// Update the weights of the net with part of the gradient.
     ///
     /// The [second phase of backpropagation learning][1].
@@ -126,6 +1432,20 @@ pub trait ISolver<SolverB, B: IBackend + LayerOps<f32>> {
     /// [2]: ./struct.Solver.html#method.step
     fn compute_update(&mut self, param: &SolverConfig, network: &mut Layer<B>, iter: usize);
 
+    /// Returns `true` if [compute_update][1] already applies the update to the weights of
+    /// the network, so [Layer::update_weights][2] must not be called afterwards.
+    /// [1]: #tymethod.compute_update
+    /// [2]: ../layer/struct.Layer.html#method.update_weights
+    fn updates_weights(&self, param: &SolverConfig) -> bool {
+        false
+    }
+
+    /// Returns the [LARS][1] ratios of the learnable weight blobs computed in the last update.
+    /// [1]: ./struct.SolverConfig.html#structfield.lars_trust_coefficient
+    fn lars_ratios(&self) -> &[f32] {
+        &[]
+    }
+
     /// Returns the backend used by the solver.
     fn backend(&self) -> &SolverB;
 }
@@ -136,6 +1456,71 @@ impl<SolverB, B: IBackend + LayerOps<f32>> ::std::fmt::Debug for ISolver<SolverB
     }
 }
 
+/// Receives the loss of each sample of a trained minibatch.
+///
+/// Is implemented for closures with the signature of [sample_losses][1].
+/// [1]: #tymethod.sample_losses
+pub trait SampleLossCallback {
+    /// Called with the identifiers of the samples of the minibatch and their losses.
+    fn sample_losses(&mut self, sample_ids: &[usize], losses: &[f32]);
+}
+
+impl<F: FnMut(&[usize], &[f32])> SampleLossCallback for F {
+    fn sample_losses(&mut self, sample_ids: &[usize], losses: &[f32]) {
+        self(sample_ids, losses)
+    }
+}
+
+impl ::std::fmt::Debug for SampleLossCallback {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "({})", "SampleLossCallback")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The activation of a blob of the network on the batch of an [activation probe][1].
+/// [1]: ./struct.Solver.html#method.set_activation_probe
+pub struct ProbeActivation {
+    /// The name of the blob.
+    pub name: String,
+    /// The shape of the blob.
+    pub shape: Vec<usize>,
+    /// The values of the blob.
+    pub values: Vec<f32>,
+}
+
+/// Receives the activations of an [activation probe][1].
+/// [1]: ./struct.Solver.html#method.set_activation_probe
+///
+/// Is implemented for closures with the signature of [activations][2].
+/// [2]: #tymethod.activations
+pub trait ProbeCallback {
+    /// Called with the iteration after which the probe ran and the activations of its blobs,
+    /// in the order of their names.
+    fn activations(&mut self, iter: usize, activations: &[ProbeActivation]);
+}
+
+impl<F: FnMut(usize, &[ProbeActivation])> ProbeCallback for F {
+    fn activations(&mut self, iter: usize, activations: &[ProbeActivation]) {
+        self(iter, activations)
+    }
+}
+
+impl ::std::fmt::Debug for ProbeCallback {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "({})", "ProbeCallback")
+    }
+}
+
+#[derive(Debug)]
+/// A fixed batch whose activations are passed to a callback every `interval` iterations.
+struct ActivationProbe {
+    batch: ArcLock<SharedTensor<f32>>,
+    blob_names: Vec<String>,
+    interval: usize,
+    callback: Box<ProbeCallback>,
+}
+
 #[derive(Debug, Clone)]
 /// Configuration for a Solver
 pub struct SolverConfig {
@@ -154,6 +1539,62 @@ pub struct SolverConfig {
     ///
     /// Default: 1
     pub minibatch_size: usize,
+    /// The number of minibatches whose gradients are accumulated before the weights are updated.
+    ///
+    /// The gradient of every minibatch is scaled by `1 / accumulate_steps` before it is
+    /// accumulated, which allows to train with an effective batch size that does not fit
+    /// into memory at once.
+    ///
+    /// Default: 1
+    pub accumulate_steps: usize,
+    /// Accumulate the gradients of the micro-batches in `f64` instead of `f32`.
+    ///
+    /// Adding the small gradients of many micro-batches in `f32` loses precision.
+    /// With this option the gradients are accumulated in a `f64` copy on the host and
+    /// only converted back to `f32` for the weight update. The weights stay in `f32`.
+    /// Only has an effect if `accumulate_steps` is larger than one.
+    ///
+    /// Default: false
+    pub accumulate_in_f64: bool,
+    /// The number of minibatch losses that are kept for [Solver.recent_losses][1].
+    /// [1]: ./struct.Solver.html#method.recent_losses
+    ///
+    /// If set to `0` no losses are kept.
+    ///
+    /// Default: 100
+    pub loss_history_size: usize,
+    /// The number of iterations between two snapshots of the network.
+    ///
+    /// If set to `None` snapshots are only written when the reported
+    /// [validation metric improves][1].
+    /// [1]: ./struct.Solver.html#method.report_validation_metric
+    ///
+    /// Default: None
+    pub snapshot_interval: Option<usize>,
+    /// The path prefix of the snapshot files.
+    ///
+    /// The iteration and the validation metric, if any, are appended to it.
+    /// If set to `None` no snapshots are written.
+    ///
+    /// Default: None
+    pub snapshot_prefix: Option<String>,
+    /// The number of snapshots with the best validation metric that are kept.
+    ///
+    /// All other snapshots but the latest one are deleted.
+    /// If set to `0` all snapshots are kept.
+    ///
+    /// Default: 0
+    pub keep_best: usize,
+    /// The schedule of which layers of the network are frozen during which iterations.
+    ///
+    /// Default: empty, no layers are frozen
+    pub freeze_schedule: FreezeSchedule,
+    /// A custom schedule of the learning rate.
+    ///
+    /// If set, it is used instead of the `lr_policy`.
+    ///
+    /// Default: None
+    pub lr_scheduler: Option<Rc<LrScheduler>>,
     /// The learning rate policy to be used.
     ///
     /// Default: Fixed
@@ -190,6 +1631,27 @@ pub struct SolverConfig {
     ///
     /// [3]: https://cs231n.github.io/neural-networks-2/#reg
     pub weight_decay: Option<f32>,
+    /// The policy by which the [weight_decay][1] changes over the iterations.
+    /// [1]: #structfield.weight_decay
+    ///
+    /// Works like the [lr_policy][2] with the `weight_decay` as the base value and
+    /// [weight_decay_gamma][3] and [weight_decay_stepsize][4] as its parameters.
+    /// [2]: #structfield.lr_policy
+    /// [3]: #structfield.weight_decay_gamma
+    /// [4]: #structfield.weight_decay_stepsize
+    ///
+    /// Default: Fixed
+    pub weight_decay_policy: LRPolicy,
+    /// gamma as used by the [weight_decay_policy][1].
+    /// [1]: #structfield.weight_decay_policy
+    ///
+    /// Default: 0.1
+    pub weight_decay_gamma: f32,
+    /// The stepsize used by the Step [weight_decay_policy][1].
+    /// [1]: #structfield.weight_decay_policy
+    ///
+    /// Default: 10
+    pub weight_decay_stepsize: usize,
     /// The method of [regularization][1] to use.
     /// [1]: https://cs231n.github.io/neural-networks-2/#reg
     ///
@@ -215,6 +1677,96 @@ pub struct SolverConfig {
     ///
     /// Default: 0
     pub momentum: f32,
+    /// Compute the updates of [SGD solvers][1] with the fused [sgd_update][2] of the backend,
+    /// which applies the weight decay, the momentum and the learning rate and updates the
+    /// weights in a single pass over each weight blob.
+    /// [1]: ../solvers/sgd/index.html
+    /// [2]: ../util/trait.SgdUpdate.html
+    ///
+    /// The results are identical to the separate passes, but on the native backend models
+    /// with many small weight blobs spend considerably less time per iteration. The CUDA
+    /// backend falls back to separate BLAS calls, so the option makes no difference there.
+    ///
+    /// Default: false
+    pub fused_update: bool,
+    /// The trust coefficient of [Layer-wise Adaptive Rate Scaling (LARS)][1] for
+    /// [SGD solvers][2].
+    /// [1]: https://arxiv.org/abs/1708.03888
+    /// [2]: ../solvers/sgd/index.html
+    ///
+    /// If set, the learning rate of each weight blob is scaled by
+    /// `lars_trust_coefficient * ||w|| / (||grad|| + weight_decay * ||w||)` before the
+    /// momentum update, which keeps training stable with very large (accumulated) batches.
+    /// Blobs with a zero norm, like freshly initialized biases, use the unscaled learning rate.
+    /// The ratios of the last update are returned by [Solver::lars_ratios][3].
+    /// [3]: ./struct.Solver.html#method.lars_ratios
+    ///
+    /// Default: None
+    pub lars_trust_coefficient: Option<f32>,
+    /// The decay of the exponential moving averages of the weights that are kept
+    /// for evaluation.
+    ///
+    /// After each iteration the averages are updated as
+    /// `ema = ema_decay * ema + (1 - ema_decay) * weight`.
+    /// The averaged weights often generalize better than the trained ones. They can be
+    /// swapped into the network with [Solver::swap_in_ema][1] and are saved next to the
+    /// snapshots of the network.
+    /// [1]: ./struct.Solver.html#method.swap_in_ema
+    ///
+    /// If set to `None` no moving averages are kept.
+    ///
+    /// Default: None
+    pub ema_decay: Option<f32>,
+    /// Warm up the decay of the moving averages as `min(ema_decay, (1 + t) / (10 + t))`
+    /// for iteration `t`, so the averages forget the early weights quickly.
+    ///
+    /// Default: false
+    pub ema_warmup: bool,
+    /// Stop [Solver::fit][1] after this many iterations.
+    /// [1]: ./struct.Solver.html#method.fit
+    ///
+    /// Default: None
+    pub max_iter: Option<usize>,
+    /// Stop [Solver::fit][1] once this much wall clock time has been spent training.
+    /// [1]: ./struct.Solver.html#method.fit
+    ///
+    /// The time spent before a [resume][2] counts as well unless it is reset.
+    /// [2]: ./struct.Solver.html#method.resume
+    ///
+    /// Default: None
+    pub max_duration: Option<Duration>,
+    /// Stop [Solver::fit][1] once the [smoothed loss][2] drops below this value.
+    /// [1]: ./struct.Solver.html#method.fit
+    /// [2]: ./struct.Solver.html#method.smoothed_loss
+    ///
+    /// Requires a `loss_history_size` of at least 1.
+    ///
+    /// Default: None
+    pub target_loss: Option<f32>,
+    /// Stop [Solver::fit][1] after this many epochs.
+    /// [1]: ./struct.Solver.html#method.fit
+    ///
+    /// The epochs are counted in minibatches, so this requires a [DataIterator][2] that
+    /// knows the number of batches per epoch.
+    /// [2]: ../data/trait.DataIterator.html#method.batches_per_epoch
+    ///
+    /// Default: None
+    pub max_epochs: Option<usize>,
+    /// Compute the [statistics of the gradients][1] of all learnable weights, after they
+    /// have been accumulated and before they are clipped, normalized and regularized.
+    /// [1]: ./struct.Solver.html#method.gradient_stats
+    ///
+    /// If set to `None` no statistics are computed.
+    ///
+    /// Default: None
+    pub gradient_stats: Option<GradientStatsConfig>,
+    /// Record the [host<->device transfers][1] of every minibatch, see
+    /// [Solver::transfer_summary][2].
+    /// [1]: ../transfer/index.html
+    /// [2]: ./struct.Solver.html#method.transfer_summary
+    ///
+    /// Default: false
+    pub record_transfers: bool,
 }
 
 impl Default for SolverConfig {
@@ -225,106 +1777,511 @@ impl Default for SolverConfig {
             objective: LayerConfig::new("default", SequentialConfig::default()),
             solver: SolverKind::SGD(SGDKind::Momentum),
 
-            minibatch_size: 1,
+            minibatch_size: 1,
+            accumulate_steps: 1,
+            accumulate_in_f64: false,
+            loss_history_size: 100,
+
+            snapshot_interval: None,
+            snapshot_prefix: None,
+            keep_best: 0,
+
+            freeze_schedule: FreezeSchedule::default(),
+
+            lr_scheduler: None,
+            lr_policy: LRPolicy::Fixed,
+            base_lr: 0.01f32,
+            gamma: 0.1f32,
+            stepsize: 10,
+
+            clip_gradients: None,
+
+            weight_decay: None,
+            weight_decay_policy: LRPolicy::Fixed,
+            weight_decay_gamma: 0.1f32,
+            weight_decay_stepsize: 10,
+            regularization_method: None,
+
+            momentum: 0f32,
+            fused_update: false,
+            lars_trust_coefficient: None,
+
+            ema_decay: None,
+            ema_warmup: false,
+
+            max_iter: None,
+            max_duration: None,
+            target_loss: None,
+            max_epochs: None,
+
+            gradient_stats: None,
+            record_transfers: false,
+        }
+    }
+}
+
+/// Custom learning rate schedulers are only equal if they are the same instance.
+impl PartialEq for SolverConfig {
+    fn eq(&self, other: &SolverConfig) -> bool {
+        let same_lr_scheduler = match (&self.lr_scheduler, &other.lr_scheduler) {
+            (&Some(ref a), &Some(ref b)) => Rc::ptr_eq(a, b),
+            (&None, &None) => true,
+            _ => false,
+        };
+        same_lr_scheduler && self.name == other.name && self.network == other.network &&
+        self.objective == other.objective && self.solver == other.solver &&
+        self.minibatch_size == other.minibatch_size && self.accumulate_steps == other.accumulate_steps &&
+        self.accumulate_in_f64 == other.accumulate_in_f64 &&
+        self.loss_history_size == other.loss_history_size &&
+        self.snapshot_interval == other.snapshot_interval && self.snapshot_prefix == other.snapshot_prefix &&
+        self.keep_best == other.keep_best && self.freeze_schedule == other.freeze_schedule &&
+        self.lr_policy == other.lr_policy && self.base_lr == other.base_lr && self.gamma == other.gamma &&
+        self.stepsize == other.stepsize && self.clip_gradients == other.clip_gradients &&
+        self.weight_decay == other.weight_decay && self.weight_decay_policy == other.weight_decay_policy &&
+        self.weight_decay_gamma == other.weight_decay_gamma &&
+        self.weight_decay_stepsize == other.weight_decay_stepsize &&
+        self.regularization_method == other.regularization_method && self.momentum == other.momentum &&
+        self.fused_update == other.fused_update && self.lars_trust_coefficient == other.lars_trust_coefficient &&
+        self.ema_decay == other.ema_decay && self.ema_warmup == other.ema_warmup &&
+        self.max_iter == other.max_iter && self.max_duration == other.max_duration &&
+        self.target_loss == other.target_loss && self.max_epochs == other.max_epochs &&
+        self.gradient_stats == other.gradient_stats && self.record_transfers == other.record_transfers
+    }
+}
+
+impl SolverConfig {
+    /// Return the learning rate for a supplied iteration.
+    ///
+    /// The learning rate is taken from the [lr_scheduler][1] if one is set,
+    /// otherwise from the scheduler of the configured [LRPolicy][2].
+    ///
+    /// [1]: #structfield.lr_scheduler
+    /// [2]: ./enum.LRPolicy.html
+    ///
+    /// Used by the [Solver][3] to calculate the learning rate for the current iteration.
+    /// The calculated learning rate has a different effect on training dependent on what
+    /// [type of Solver][4] you are using.
+    ///
+    /// [3]: ./struct.Solver.html
+    /// [4]: ../solvers/index.html
+    pub fn get_learning_rate(&self, iter: usize) -> f32 {
+        match self.lr_scheduler {
+            Some(ref scheduler) => scheduler.lr(iter),
+            None => self.lr_policy.scheduler(self).lr(iter),
+        }
+    }
+
+    /// Returns the L2 weight decay of a weight blob with the decay multiplier `decay_mult`
+    /// for a supplied iteration.
+    ///
+    /// The decay follows the [weight_decay_policy][1] and is zero if no [weight_decay][2]
+    /// or [regularization_method][3] is configured.
+    /// [1]: #structfield.weight_decay_policy
+    /// [2]: #structfield.weight_decay
+    /// [3]: #structfield.regularization_method
+    pub fn get_weight_decay(&self, iter: usize, decay_mult: f32) -> f32 {
+        match (self.weight_decay, self.regularization_method) {
+            (Some(_), Some(RegularizationMethod::L2)) => {
+                self.weight_decay_policy.weight_decay_scheduler(self).lr(iter) * decay_mult
+            }
+            _ => 0f32,
+        }
+    }
+
+    /// Returns a [SolverConfigBuilder][1] that starts from the default SolverConfig.
+    /// [1]: ./struct.SolverConfigBuilder.html
+    pub fn builder() -> SolverConfigBuilder {
+        SolverConfigBuilder::new()
+    }
+
+    /// Checks that the values of the SolverConfig are in range and consistent with each other.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.minibatch_size < 1 {
+            return Err("minibatch_size must be at least 1".to_owned());
+        }
+        if self.accumulate_steps < 1 {
+            return Err("accumulate_steps must be at least 1".to_owned());
+        }
+        if self.accumulate_in_f64 && self.accumulate_steps == 1 {
+            return Err("accumulate_in_f64 requires accumulate_steps to be larger than 1".to_owned());
+        }
+        if !(self.base_lr > 0f32) {
+            return Err(format!("base_lr must be positive, but is {}", self.base_lr));
+        }
+        if let Some(threshold) = self.clip_gradients {
+            if !(threshold > 0f32) {
+                return Err(format!("clip_gradients must be positive when set, but is {}", threshold));
+            }
+        }
+        if let Some(weight_decay) = self.weight_decay {
+            if !(weight_decay >= 0f32) {
+                return Err(format!("weight_decay must not be negative, but is {}", weight_decay));
+            }
+        }
+        if self.regularization_method.is_some() && self.weight_decay.is_none() {
+            return Err("regularization_method is set without a weight_decay".to_owned());
+        }
+        match self.weight_decay_policy {
+            LRPolicy::Fixed => {}
+            _ if self.weight_decay.is_none() => {
+                return Err("weight_decay_policy is set without a weight_decay".to_owned());
+            }
+            LRPolicy::Step if self.weight_decay_stepsize == 0 => {
+                return Err("the Step weight_decay_policy requires a weight_decay_stepsize of at least 1".to_owned());
+            }
+            _ => {}
+        }
+        if !(self.momentum >= 0f32 && self.momentum < 1f32) {
+            return Err(format!("momentum must be in [0, 1), but is {}", self.momentum));
+        }
+        if let Some(trust_coefficient) = self.lars_trust_coefficient {
+            if !(trust_coefficient > 0f32) {
+                return Err(format!("lars_trust_coefficient must be positive when set, but is {}",
+                                   trust_coefficient));
+            }
+        }
+        if let Some(decay) = self.ema_decay {
+            if !(decay > 0f32 && decay < 1f32) {
+                return Err(format!("ema_decay must be in (0, 1) when set, but is {}", decay));
+            }
+        }
+        if self.ema_warmup && self.ema_decay.is_none() {
+            return Err("ema_warmup is set without an ema_decay".to_owned());
+        }
+        if self.max_iter == Some(0) {
+            return Err("max_iter must be at least 1 when set".to_owned());
+        }
+        if self.max_epochs == Some(0) {
+            return Err("max_epochs must be at least 1 when set".to_owned());
+        }
+        if let Some(target_loss) = self.target_loss {
+            if !target_loss.is_finite() {
+                return Err(format!("target_loss must be finite when set, but is {}", target_loss));
+            }
+            if self.loss_history_size == 0 {
+                return Err("target_loss requires a loss_history_size of at least 1".to_owned());
+            }
+        }
+        if let Some(ref stats) = self.gradient_stats {
+            if stats.interval == 0 || stats.max_samples == 0 {
+                return Err("gradient_stats requires an interval and max_samples of at least 1".to_owned());
+            }
+        }
+        match self.lr_policy {
+            LRPolicy::Fixed => {}
+            _ if self.lr_scheduler.is_some() => {
+                return Err("lr_scheduler and lr_policy are mutually exclusive, \
+                            the lr_policy has to be Fixed when a lr_scheduler is set"
+                    .to_owned());
+            }
+            LRPolicy::Step if self.stepsize == 0 => {
+                return Err("the Step lr_policy requires a stepsize of at least 1".to_owned());
+            }
+            _ => {}
+        }
+        if self.snapshot_prefix.is_none() && (self.snapshot_interval.is_some() || self.keep_best > 0) {
+            return Err("snapshot_interval and keep_best require a snapshot_prefix".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Builds a [SolverConfig][1] with chainable setters.
+/// [1]: ./struct.SolverConfig.html
+///
+/// The fields that are not set keep their default values. [build][2] checks that the
+/// resulting SolverConfig is [valid][3].
+/// [2]: #method.build
+/// [3]: ./struct.SolverConfig.html#method.validate
+///
+/// ```
+/// # use juice::solver::*;
+/// let config = SolverConfig::builder()
+///     .base_lr(0.1)
+///     .momentum(0.9)
+///     .accumulate_steps(4)
+///     .build()
+///     .unwrap();
+/// assert_eq!(4, config.accumulate_steps);
+///
+/// let err = SolverConfig::builder().clip_gradients(-1.0).build().unwrap_err();
+/// assert!(err.contains("clip_gradients"));
+/// ```
+pub struct SolverConfigBuilder {
+    config: SolverConfig,
+}
+
+impl SolverConfigBuilder {
+    /// Create a SolverConfigBuilder that starts from the default SolverConfig.
+    pub fn new() -> SolverConfigBuilder {
+        SolverConfigBuilder::default()
+    }
+
+    /// Set the [name](./struct.SolverConfig.html#structfield.name).
+    pub fn name(mut self, name: &str) -> SolverConfigBuilder {
+        self.config.name = name.to_owned();
+        self
+    }
+
+    /// Set the [network](./struct.SolverConfig.html#structfield.network).
+    pub fn network(mut self, network: LayerConfig) -> SolverConfigBuilder {
+        self.config.network = network;
+        self
+    }
+
+    /// Set the [objective](./struct.SolverConfig.html#structfield.objective).
+    pub fn objective(mut self, objective: LayerConfig) -> SolverConfigBuilder {
+        self.config.objective = objective;
+        self
+    }
+
+    /// Set the [solver](./struct.SolverConfig.html#structfield.solver).
+    pub fn solver(mut self, solver: SolverKind) -> SolverConfigBuilder {
+        self.config.solver = solver;
+        self
+    }
+
+    /// Set the [minibatch_size](./struct.SolverConfig.html#structfield.minibatch_size).
+    pub fn minibatch_size(mut self, minibatch_size: usize) -> SolverConfigBuilder {
+        self.config.minibatch_size = minibatch_size;
+        self
+    }
+
+    /// Set the [accumulate_steps](./struct.SolverConfig.html#structfield.accumulate_steps).
+    pub fn accumulate_steps(mut self, accumulate_steps: usize) -> SolverConfigBuilder {
+        self.config.accumulate_steps = accumulate_steps;
+        self
+    }
+
+    /// Set [accumulate_in_f64](./struct.SolverConfig.html#structfield.accumulate_in_f64).
+    pub fn accumulate_in_f64(mut self, accumulate_in_f64: bool) -> SolverConfigBuilder {
+        self.config.accumulate_in_f64 = accumulate_in_f64;
+        self
+    }
+
+    /// Set the [loss_history_size](./struct.SolverConfig.html#structfield.loss_history_size).
+    pub fn loss_history_size(mut self, loss_history_size: usize) -> SolverConfigBuilder {
+        self.config.loss_history_size = loss_history_size;
+        self
+    }
+
+    /// Set the [snapshot_interval](./struct.SolverConfig.html#structfield.snapshot_interval).
+    pub fn snapshot_interval(mut self, snapshot_interval: usize) -> SolverConfigBuilder {
+        self.config.snapshot_interval = Some(snapshot_interval);
+        self
+    }
+
+    /// Set the [snapshot_prefix](./struct.SolverConfig.html#structfield.snapshot_prefix).
+    pub fn snapshot_prefix(mut self, snapshot_prefix: &str) -> SolverConfigBuilder {
+        self.config.snapshot_prefix = Some(snapshot_prefix.to_owned());
+        self
+    }
+
+    /// Set [keep_best](./struct.SolverConfig.html#structfield.keep_best).
+    pub fn keep_best(mut self, keep_best: usize) -> SolverConfigBuilder {
+        self.config.keep_best = keep_best;
+        self
+    }
+
+    /// Set the [freeze_schedule](./struct.SolverConfig.html#structfield.freeze_schedule).
+    pub fn freeze_schedule(mut self, freeze_schedule: FreezeSchedule) -> SolverConfigBuilder {
+        self.config.freeze_schedule = freeze_schedule;
+        self
+    }
+
+    /// Set the [lr_scheduler](./struct.SolverConfig.html#structfield.lr_scheduler).
+    pub fn lr_scheduler<S: LrScheduler + 'static>(mut self, lr_scheduler: S) -> SolverConfigBuilder {
+        self.config.lr_scheduler = Some(Rc::new(lr_scheduler));
+        self
+    }
+
+    /// Set the [lr_policy](./struct.SolverConfig.html#structfield.lr_policy).
+    pub fn lr_policy(mut self, lr_policy: LRPolicy) -> SolverConfigBuilder {
+        self.config.lr_policy = lr_policy;
+        self
+    }
+
+    /// Set the [base_lr](./struct.SolverConfig.html#structfield.base_lr).
+    pub fn base_lr(mut self, base_lr: f32) -> SolverConfigBuilder {
+        self.config.base_lr = base_lr;
+        self
+    }
+
+    /// Set [gamma](./struct.SolverConfig.html#structfield.gamma).
+    pub fn gamma(mut self, gamma: f32) -> SolverConfigBuilder {
+        self.config.gamma = gamma;
+        self
+    }
+
+    /// Set the [stepsize](./struct.SolverConfig.html#structfield.stepsize).
+    pub fn stepsize(mut self, stepsize: usize) -> SolverConfigBuilder {
+        self.config.stepsize = stepsize;
+        self
+    }
+
+    /// Set the threshold for [clip_gradients](./struct.SolverConfig.html#structfield.clip_gradients).
+    pub fn clip_gradients(mut self, threshold: f32) -> SolverConfigBuilder {
+        self.config.clip_gradients = Some(threshold);
+        self
+    }
+
+    /// Set the [weight_decay](./struct.SolverConfig.html#structfield.weight_decay).
+    pub fn weight_decay(mut self, weight_decay: f32) -> SolverConfigBuilder {
+        self.config.weight_decay = Some(weight_decay);
+        self
+    }
+
+    /// Set the [weight_decay_policy](./struct.SolverConfig.html#structfield.weight_decay_policy).
+    pub fn weight_decay_policy(mut self, policy: LRPolicy) -> SolverConfigBuilder {
+        self.config.weight_decay_policy = policy;
+        self
+    }
+
+    /// Set the [weight_decay_gamma](./struct.SolverConfig.html#structfield.weight_decay_gamma).
+    pub fn weight_decay_gamma(mut self, gamma: f32) -> SolverConfigBuilder {
+        self.config.weight_decay_gamma = gamma;
+        self
+    }
+
+    /// Set the [weight_decay_stepsize](./struct.SolverConfig.html#structfield.weight_decay_stepsize).
+    pub fn weight_decay_stepsize(mut self, stepsize: usize) -> SolverConfigBuilder {
+        self.config.weight_decay_stepsize = stepsize;
+        self
+    }
+
+    /// Set the [regularization_method](./struct.SolverConfig.html#structfield.regularization_method).
+    pub fn regularization_method(mut self, method: RegularizationMethod) -> SolverConfigBuilder {
+        self.config.regularization_method = Some(method);
+        self
+    }
+
+    /// Set the [momentum](./struct.SolverConfig.html#structfield.momentum).
+    pub fn momentum(mut self, momentum: f32) -> SolverConfigBuilder {
+        self.config.momentum = momentum;
+        self
+    }
+
+    /// Set the [fused_update](./struct.SolverConfig.html#structfield.fused_update).
+    pub fn fused_update(mut self, fused_update: bool) -> SolverConfigBuilder {
+        self.config.fused_update = fused_update;
+        self
+    }
+
+    /// Set the [lars_trust_coefficient](./struct.SolverConfig.html#structfield.lars_trust_coefficient).
+    pub fn lars_trust_coefficient(mut self, trust_coefficient: f32) -> SolverConfigBuilder {
+        self.config.lars_trust_coefficient = Some(trust_coefficient);
+        self
+    }
 
-            lr_policy: LRPolicy::Fixed,
-            base_lr: 0.01f32,
-            gamma: 0.1f32,
-            stepsize: 10,
+    /// Set the [ema_decay](./struct.SolverConfig.html#structfield.ema_decay).
+    pub fn ema_decay(mut self, decay: f32) -> SolverConfigBuilder {
+        self.config.ema_decay = Some(decay);
+        self
+    }
 
-            clip_gradients: None,
+    /// Set the [ema_warmup](./struct.SolverConfig.html#structfield.ema_warmup).
+    pub fn ema_warmup(mut self, ema_warmup: bool) -> SolverConfigBuilder {
+        self.config.ema_warmup = ema_warmup;
+        self
+    }
 
-            weight_decay: None,
-            regularization_method: None,
+    /// Set the [max_iter](./struct.SolverConfig.html#structfield.max_iter).
+    pub fn max_iter(mut self, max_iter: usize) -> SolverConfigBuilder {
+        self.config.max_iter = Some(max_iter);
+        self
+    }
 
-            momentum: 0f32,
-        }
+    /// Set the [max_duration](./struct.SolverConfig.html#structfield.max_duration).
+    pub fn max_duration(mut self, max_duration: Duration) -> SolverConfigBuilder {
+        self.config.max_duration = Some(max_duration);
+        self
     }
-}
 
-impl SolverConfig {
-    /// Return the learning rate for a supplied iteration.
-    ///
-    /// The way the learning rate is calculated depends on the configured [LRPolicy][1].
-    ///
-    /// [1]: ./enum.LRPolicy.html
-    ///
-    /// Used by the [Solver][2] to calculate the learning rate for the current iteration.
-    /// The calculated learning rate has a different effect on training dependent on what
-    /// [type of Solver][3] you are using.
-    ///
-    /// [2]: ./struct.Solver.html
-    /// [3]: ../solvers/index.html
-    pub fn get_learning_rate(&self, iter: usize) -> f32 {
-        match self.lr_policy() {
-            LRPolicy::Fixed => self.base_lr(),
-            LRPolicy::Step => {
-                let current_step = self.step(iter);
-                self.base_lr() * self.gamma().powf(current_step as f32)
-            }
-            // LRPolicy::Multistep => {
-            //     // TODO: the current step can be calculated on-demand
-            //     //   if (this->current_step_ < this->param_.stepvalue_size() &&
-            //     //         this->iter_ >= this->param_.stepvalue(this->current_step_)) {
-            //     //     this->current_step_++;
-            //     //     LOG(INFO) << "MultiStep Status: Iteration " <<
-            //     //     this->iter_ << ", step = " << this->current_step_;
-            //     //   }
-            //     //   rate = this->param_.base_lr() *
-            //     //       pow(this->param_.gamma(), this->current_step_);
-            //     unimplemented!();
-            // }
-            LRPolicy::Exp => self.base_lr() * self.gamma().powf(iter as f32),
-            // LRPolicy::Inv => {
-            //     //   rate = this->param_.base_lr() *
-            //     //       pow(Dtype(1) + this->param_.gamma() * this->iter_,
-            //     //           - this->param_.power());
-            //     unimplemented!();
-            // }
-            // LRPolicy::Poly => {
-            //     //   rate = this->param_.base_lr() * pow(Dtype(1.) -
-            //     //       (Dtype(this->iter_) / Dtype(this->param_.max_iter())),
-            //     //       this->param_.power());
-            //     unimplemented!();
-            // }
-            // LRPolicy::Sigmoid => {
-            //     //   rate = this->param_.base_lr() * (Dtype(1.) /
-            //     //       (Dtype(1.) + exp(-this->param_.gamma() * (Dtype(this->iter_) -
-            //     //         Dtype(this->param_.stepsize())))));
-            //     unimplemented!();
-            // }
-        }
+    /// Set the [target_loss](./struct.SolverConfig.html#structfield.target_loss).
+    pub fn target_loss(mut self, target_loss: f32) -> SolverConfigBuilder {
+        self.config.target_loss = Some(target_loss);
+        self
     }
 
-    /// Return current step at iteration `iter`.
-    ///
-    /// Small helper for learning rate calculation.
-    fn step(&self, iter: usize) -> usize {
-        iter / self.stepsize()
+    /// Set the [max_epochs](./struct.SolverConfig.html#structfield.max_epochs).
+    pub fn max_epochs(mut self, max_epochs: usize) -> SolverConfigBuilder {
+        self.config.max_epochs = Some(max_epochs);
+        self
     }
 
-    /// Return learning rate policy.
-    fn lr_policy(&self) -> LRPolicy {
-        self.lr_policy
+    /// Set the [gradient_stats](./struct.SolverConfig.html#structfield.gradient_stats).
+    pub fn gradient_stats(mut self, gradient_stats: GradientStatsConfig) -> SolverConfigBuilder {
+        self.config.gradient_stats = Some(gradient_stats);
+        self
     }
 
-    /// Return the base learning rate.
-    fn base_lr(&self) -> f32 {
-        self.base_lr
+    /// Set [record_transfers](./struct.SolverConfig.html#structfield.record_transfers).
+    pub fn record_transfers(mut self, record_transfers: bool) -> SolverConfigBuilder {
+        self.config.record_transfers = record_transfers;
+        self
     }
 
-    /// Return the gamma for learning rate calculations.
-    fn gamma(&self) -> f32 {
-        self.gamma
+    /// Returns the SolverConfig if it is [valid][1], otherwise a description of the problem.
+    /// [1]: ./struct.SolverConfig.html#method.validate
+    pub fn build(self) -> Result<SolverConfig, String> {
+        try!(self.config.validate());
+        Ok(self.config)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A schedule of which layers are [frozen][1] during which part of the training,
+/// e.g. to fine-tune a pretrained network by training only the head first
+/// and unfreezing more and more layers later on.
+/// [1]: ../layer/struct.Layer.html#method.set_frozen
+///
+/// The schedule is made of phases that start at a given iteration. Phases based on
+/// epochs start at the epoch multiplied by the number of iterations per epoch.
+/// When a phase starts the [Solver][2] freezes the layers of the phase and unfreezes
+/// all others. Weights that are unfrozen start without solver history, e.g. without momentum.
+/// [2]: ./struct.Solver.html
+pub struct FreezeSchedule {
+    /// The phases of the schedule.
+    pub phases: Vec<FreezePhase>,
+}
+
+impl FreezeSchedule {
+    /// Add a phase that starts at iteration `start_iter` and freezes all layers
+    /// whose name matches one of the `frozen` patterns.
+    ///
+    /// See [Layer.set_frozen_matching][1] for the syntax of the patterns.
+    /// [1]: ../layer/struct.Layer.html#method.set_frozen_matching
+    pub fn add_phase(&mut self, start_iter: usize, frozen: &[&str]) {
+        self.phases.push(FreezePhase {
+            start_iter: start_iter,
+            frozen: frozen.iter().map(|pattern| pattern.to_string()).collect(),
+        });
     }
 
-    /// Return the stepsize for learning rate calculations.
-    fn stepsize(&self) -> usize {
-        self.stepsize
+    /// Returns the index of the phase that is active at iteration `iter`,
+    /// which is the one that started last.
+    pub fn phase(&self, iter: usize) -> Option<usize> {
+        self.phases
+            .iter()
+            .enumerate()
+            .filter(|&(_, phase)| phase.start_iter <= iter)
+            .max_by_key(|&(_, phase)| phase.start_iter)
+            .map(|(i, _)| i)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+/// A phase of a [FreezeSchedule][1].
+/// [1]: ./struct.FreezeSchedule.html
+pub struct FreezePhase {
+    /// The iteration at which the phase starts.
+    pub start_iter: usize,
+    /// The patterns of the names of the layers that are frozen during the phase.
+    pub frozen: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// All available types of solvers.
 pub enum SolverKind {
     /// Stochastic Gradient Descent.
@@ -346,7 +2303,7 @@ impl SolverKind {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// All available types of Stochastic Gradient Descent solvers.
 pub enum SGDKind {
     /// Stochastic Gradient Descent with Momentum. See [implementation][1]
@@ -367,7 +2324,7 @@ impl SGDKind {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// Learning Rate Policy for a [Solver][1]
 /// [1]: ./struct.Solver.html
 ///
@@ -376,6 +2333,10 @@ impl SGDKind {
 /// for the learning rate calculation.
 ///
 /// [2]: ./struct.SolverConfig.html
+///
+/// The same policies schedule the [weight_decay][3], with the `weight_decay` in place of
+/// `base_lr` and their own gamma and stepsize.
+/// [3]: ./struct.SolverConfig.html#structfield.weight_decay_policy
 pub enum LRPolicy {
     /// always return base_lr
     Fixed,
@@ -398,7 +2359,45 @@ pub enum LRPolicy {
     // Sigmoid,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl LRPolicy {
+    /// Returns the [LrScheduler][1] that implements the policy with the parameters
+    /// of `config`.
+    /// [1]: ./lr_scheduler/trait.LrScheduler.html
+    pub fn scheduler(&self, config: &SolverConfig) -> Box<LrScheduler> {
+        self.schedule(config.base_lr, config.gamma, config.stepsize)
+    }
+
+    /// Returns the schedule of the [weight_decay][1] of `config` under this policy.
+    /// [1]: ./struct.SolverConfig.html#structfield.weight_decay_policy
+    ///
+    /// The decay is zero if no `weight_decay` is configured.
+    pub fn weight_decay_scheduler(&self, config: &SolverConfig) -> Box<LrScheduler> {
+        self.schedule(config.weight_decay.unwrap_or(0f32),
+                      config.weight_decay_gamma,
+                      config.weight_decay_stepsize)
+    }
+
+    fn schedule(&self, base: f32, gamma: f32, stepsize: usize) -> Box<LrScheduler> {
+        match *self {
+            LRPolicy::Fixed => Box::new(ConstantLr { lr: base }),
+            LRPolicy::Step => {
+                Box::new(StepLr {
+                    base_lr: base,
+                    gamma: gamma,
+                    stepsize: stepsize,
+                })
+            }
+            LRPolicy::Exp => {
+                Box::new(ExpLr {
+                    base_lr: base,
+                    gamma: gamma,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// [Regularization][1] method for a [Solver][2].
 /// [1]: https://cs231n.github.io/neural-networks-2/#reg
 /// [2]: ./struct.Solver.html
@@ -406,3 +2405,1109 @@ pub enum RegularizationMethod {
     /// L2 regularization
     L2,
 }
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use data::{Batch, DataIterator};
+    use layer::*;
+    use layers::*;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use super::*;
+    use transfer::{self, TransferCategory};
+    use util::{native_backend, write_to_memory};
+    use util::testing::{temp_path, tensor};
+
+    fn linear_solver(batch_size: usize, base_lr: f32, accumulate_steps: usize) -> Solver<Backend<Native>, Backend<Native>> {
+        let cfg = linear_solver_config(batch_size, base_lr, accumulate_steps);
+        let backend = Rc::new(native_backend());
+        Solver::from_config(backend.clone(), backend, &cfg)
+    }
+
+    fn linear_solver_config(batch_size: usize, base_lr: f32, accumulate_steps: usize) -> SolverConfig {
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[batch_size, 2]);
+        net_cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 3 }));
+
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[batch_size, 3]);
+        obj_cfg.add_input("label", &[batch_size, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 3 }));
+
+        SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: base_lr,
+            accumulate_steps: accumulate_steps,
+            ..SolverConfig::default()
+        }
+    }
+
+    fn weights(solver: &Solver<Backend<Native>, Backend<Native>>) -> Vec<f32> {
+        let native = native_backend();
+        let weights = solver.network().learnable_weights_data();
+        let weight = weights[0].read().unwrap();
+        let data = weight.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+        data
+    }
+
+    fn set_weights(solver: &mut Solver<Backend<Native>, Backend<Native>>, data: &[f32]) {
+        let native = native_backend();
+        let weights = solver.mut_network().learnable_weights_data();
+        let mut weight = weights[0].write().unwrap();
+        write_to_memory(weight.write_only(native.device()).unwrap(), data);
+    }
+
+    #[test]
+    fn structural_regularizers_match_layer_patterns() {
+        let mut plain = linear_solver(2, 0.1, 1);
+        let mut regularized = linear_solver(2, 0.1, 1);
+        let initial = [0.5f32, -0.2, 0.3, 0.8, -0.6, 0.1];
+        set_weights(&mut plain, &initial);
+        set_weights(&mut regularized, &initial);
+
+        let spectral = StructuralRegularizer::SpectralNorm { lambda: 0.5, power_iterations: 5 };
+        assert_eq!(Ok(vec!["linear-0".to_owned()]),
+                   regularized.add_structural_regularizer("lin*", spectral));
+        assert!(regularized.add_structural_regularizer("conv*", spectral).is_err());
+        assert!(regularized.add_structural_regularizer("linear", StructuralRegularizer::Orthogonality { lambda: -1f32 })
+            .is_err());
+        assert!(regularized.spectral_norm_estimates().is_empty());
+
+        for solver in vec![&mut plain, &mut regularized] {
+            solver.train_minibatch(tensor(&[2, 2], &[1f32, -1.0, 0.5, 2.0]), tensor(&[2, 1], &[0f32, 2.0]));
+        }
+        let estimates = regularized.spectral_norm_estimates();
+        assert_eq!(1, estimates.len());
+        assert_eq!("linear-0", estimates[0].0);
+        assert!(estimates[0].1 > 0f32);
+        assert!(weights(&plain) != weights(&regularized));
+    }
+
+    #[test]
+    fn ema_converges_to_constant_weights_and_swaps_exactly() {
+        let cfg = SolverConfig { ema_decay: Some(0.9), ..linear_solver_config(1, 0.1f32, 1) };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        // frozen weights stay constant during training
+        solver.mut_network().set_frozen(true);
+        assert!(solver.swap_in_ema().is_err());
+
+        let train = |solver: &mut Solver<Backend<Native>, Backend<Native>>| {
+            solver.train_minibatch(tensor(&[1, 2], &[1f32, 2f32]), tensor(&[1, 1], &[2f32]));
+        };
+        let initial = [0.1f32, -0.2, 0.3, 0.4, -0.5, 0.6];
+        let constant = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32];
+        set_weights(&mut solver, &initial);
+        train(&mut solver);
+        set_weights(&mut solver, &constant);
+        for _ in 0..5 {
+            train(&mut solver);
+        }
+
+        solver.swap_in_ema().unwrap();
+        let averaged = weights(&solver);
+        assert!(averaged != constant.to_vec());
+        solver.swap_out_ema().unwrap();
+        assert_eq!(constant.to_vec(), weights(&solver));
+        assert!(solver.swap_out_ema().is_err());
+
+        for _ in 0..200 {
+            train(&mut solver);
+        }
+        solver.swap_in_ema().unwrap();
+        for (ema, weight) in weights(&solver).iter().zip(constant.iter()) {
+            assert!((ema - weight).abs() < 1e-5, "{} != {}", ema, weight);
+        }
+        solver.swap_out_ema().unwrap();
+        assert_eq!(constant.to_vec(), weights(&solver));
+    }
+
+    #[test]
+    fn per_example_losses_average_to_loss() {
+        use std::cell::RefCell;
+
+        let mut solver = linear_solver(4, 0.1f32, 1);
+        set_weights(&mut solver, &[0.5, -0.2, 0.1, 0.3, -0.4, 0.2]);
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let callback_reported = reported.clone();
+        solver.set_sample_loss_callback(move |ids: &[usize], losses: &[f32]| {
+            callback_reported.borrow_mut().push((ids.to_vec(), losses.to_vec()));
+        });
+
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+        solver.train_minibatch_with_ids(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels), &[7, 3, 9, 1]);
+
+        let reported = reported.borrow();
+        assert_eq!(1, reported.len());
+        let (ref ids, ref losses) = reported[0];
+        assert_eq!(&vec![7, 3, 9, 1], ids);
+        let mean = losses.iter().fold(0f32, |sum, loss| sum + loss) / losses.len() as f32;
+        let loss = solver.recent_losses(1)[0];
+        assert!((mean - loss).abs() < 1e-5, "{} != {}", mean, loss);
+    }
+
+    #[test]
+    fn probe_activations_are_identical_across_invocations() {
+        use std::cell::RefCell;
+
+        let mut solver = linear_solver(2, 0f32, 1);
+        set_weights(&mut solver, &[0.5, -0.2, 0.1, 0.3, -0.4, 0.2]);
+        let probed = Rc::new(RefCell::new(Vec::new()));
+        let callback_probed = probed.clone();
+        let probe = tensor(&[2, 2], &[1f32, -1f32, 0.5f32, 2f32]);
+        let ignore = |_: usize, _: &[ProbeActivation]| {};
+        assert!(solver.set_activation_probe(probe.clone(), &["data"], 0, ignore).is_err());
+        assert!(solver.set_activation_probe(probe.clone(), &["missing"], 2, ignore).is_err());
+        let blob_names = ["data", "SEQUENTIAL_OUTPUT_0"];
+        solver.set_activation_probe(probe, &blob_names, 2, move |iter: usize, activations: &[ProbeActivation]| {
+                callback_probed.borrow_mut().push((iter, activations.to_vec()));
+            })
+            .unwrap();
+
+        for _ in 0..4 {
+            let out = solver.train_minibatch(tensor(&[2, 2], &[3f32, 1f32, -2f32, 0f32]),
+                                             tensor(&[2, 1], &[0f32, 1f32]));
+            // the outputs of the trained minibatch are restored after the probe
+            let native = native_backend();
+            let out = out.read().unwrap().read(native.device()).unwrap().as_slice::<f32>().to_vec();
+            assert!((out[0] - 1.3).abs() < 1e-5, "{:?}", out);
+        }
+
+        let probed = probed.borrow();
+        assert_eq!(vec![2, 4], probed.iter().map(|&(iter, _)| iter).collect::<Vec<_>>());
+        assert_eq!(probed[0].1, probed[1].1);
+        let activations = &probed[0].1;
+        assert_eq!(("data", vec![2, 2]), (&activations[0].name[..], activations[0].shape.clone()));
+        assert_eq!(vec![1f32, -1f32, 0.5f32, 2f32], activations[0].values);
+        assert_eq!(vec![2, 3], activations[1].shape);
+        assert_eq!(Some(activations.clone()), solver.probe_activations());
+    }
+
+    #[test]
+    fn accumulated_update_equals_full_batch_update() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+
+        // NegativeLogLikelihood sums its gradient over the batch, so the full batch
+        // equivalent of averaging four micro-batches uses a quarter of the learning rate.
+        let mut full_batch = linear_solver(4, 0.025f32, 1);
+        let mut micro_batch = linear_solver(1, 0.1f32, 4);
+        let initial_weights = weights(&full_batch);
+        set_weights(&mut micro_batch, &initial_weights);
+
+        full_batch.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+        for i in 0..4 {
+            micro_batch.train_minibatch(tensor(&[1, 2], &inputs[i * 2..i * 2 + 2]),
+                                        tensor(&[1, 1], &labels[i..i + 1]));
+            if i < 3 {
+                assert_eq!(initial_weights, weights(&micro_batch));
+            }
+        }
+
+        for (full, micro) in weights(&full_batch).iter().zip(weights(&micro_batch).iter()) {
+            assert!((full - micro).abs() < 1e-5, "{} != {}", full, micro);
+        }
+    }
+
+    #[test]
+    fn fused_update_matches_separate_passes() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+        let separate_cfg = SolverConfig {
+            momentum: 0.9,
+            weight_decay: Some(0.01),
+            regularization_method: Some(RegularizationMethod::L2),
+            ..linear_solver_config(4, 0.05f32, 1)
+        };
+        let fused_cfg = SolverConfig { fused_update: true, ..separate_cfg.clone() };
+        let backend = Rc::new(native_backend());
+        let mut separate = Solver::from_config(backend.clone(), backend.clone(), &separate_cfg);
+        let mut fused = Solver::from_config(backend.clone(), backend, &fused_cfg);
+        let initial_weights = weights(&separate);
+        set_weights(&mut fused, &initial_weights);
+
+        // the momentum carries the history of the earlier iterations into the later ones
+        for _ in 0..5 {
+            separate.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+            fused.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+            assert_eq!(weights(&separate), weights(&fused));
+        }
+        assert!(weights(&fused) != initial_weights);
+    }
+
+    #[test]
+    fn pruned_weights_stay_zero_across_updates() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32];
+        let labels = [0f32, 2f32];
+        for &fused_update in &[false, true] {
+            let cfg = SolverConfig {
+                momentum: 0.9,
+                fused_update: fused_update,
+                ..linear_solver_config(2, 0.1f32, 1)
+            };
+            let backend = Rc::new(native_backend());
+            let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+            set_weights(&mut solver, &[0.5f32, -0.2, 0.3, 0.8, -0.6, 0.1]);
+            solver.mut_network().prune_by_magnitude(0.5f32);
+
+            // the momentum keeps pushing the pruned weights away from zero
+            for _ in 0..3 {
+                solver.train_minibatch(tensor(&[2, 2], &inputs), tensor(&[2, 1], &labels));
+                let weights = weights(&solver);
+                assert_eq!([0f32, 0f32, 0f32], [weights[1], weights[2], weights[5]]);
+                assert!(weights[0] != 0f32 && weights[3] != 0f32 && weights[4] != 0f32);
+            }
+        }
+    }
+
+    #[test]
+    fn weight_decay_shrinks_weights_without_gradient() {
+        let cfg = SolverConfig {
+            weight_decay: Some(0.5),
+            regularization_method: Some(RegularizationMethod::L2),
+            ..linear_solver_config(1, 0.1f32, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        let initial_weights = [1f32, -2f32, 0.5f32, 4f32, 0f32, -1f32];
+        set_weights(&mut solver, &initial_weights);
+
+        // a zero input yields a zero gradient, so only the decay changes the weights
+        solver.train_minibatch(tensor(&[1, 2], &[0f32, 0f32]), tensor(&[1, 1], &[1f32]));
+        for (weight, initial) in weights(&solver).iter().zip(initial_weights.iter()) {
+            assert!((weight - initial * 0.95).abs() < 1e-6, "{} != {}", weight, initial * 0.95);
+        }
+    }
+
+    #[test]
+    fn weight_decay_follows_its_policy() {
+        let fixed = SolverConfig {
+            weight_decay: Some(0.5),
+            regularization_method: Some(RegularizationMethod::L2),
+            ..SolverConfig::default()
+        };
+        for &iter in &[0, 7, 1000] {
+            assert_eq!(0.5f32 * 0.3f32, fixed.get_weight_decay(iter, 0.3));
+        }
+        assert_eq!(0f32, SolverConfig { regularization_method: None, ..fixed.clone() }.get_weight_decay(0, 1.0));
+
+        let step = SolverConfig {
+            weight_decay: Some(0.1),
+            weight_decay_policy: LRPolicy::Step,
+            weight_decay_gamma: 0.5,
+            weight_decay_stepsize: 2,
+            ..fixed.clone()
+        };
+        let exp = SolverConfig { weight_decay_policy: LRPolicy::Exp, ..step.clone() };
+        for (iter, (&step_decay, &exp_decay)) in [0.1f32, 0.1, 0.05, 0.05, 0.025]
+            .iter()
+            .zip(&[0.1f32, 0.05, 0.025, 0.0125, 0.00625])
+            .enumerate() {
+            assert!((step.get_weight_decay(iter, 1.0) - step_decay).abs() < 1e-7, "{}", iter);
+            assert!((exp.get_weight_decay(iter, 1.0) - exp_decay).abs() < 1e-7, "{}", iter);
+        }
+        // the learning rate keeps its own policy
+        assert_eq!(0.01f32, step.get_learning_rate(4));
+
+        assert!(SolverConfig { weight_decay: None, regularization_method: None, ..step.clone() }
+            .validate()
+            .unwrap_err()
+            .contains("weight_decay_policy"));
+        assert!(SolverConfig { weight_decay_stepsize: 0, ..step }
+            .validate()
+            .unwrap_err()
+            .contains("weight_decay_stepsize"));
+    }
+
+    #[test]
+    fn weight_decay_schedule_changes_update_magnitude() {
+        let cfg = SolverConfig {
+            weight_decay: Some(0.5),
+            weight_decay_policy: LRPolicy::Step,
+            weight_decay_gamma: 0f32,
+            weight_decay_stepsize: 2,
+            regularization_method: Some(RegularizationMethod::L2),
+            ..linear_solver_config(1, 0.1f32, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        let initial_weights = [1f32, -2f32, 0.5f32, 4f32, 0f32, -1f32];
+        set_weights(&mut solver, &initial_weights);
+
+        // a zero input yields a zero gradient, so only the decay changes the weights,
+        // by a factor of 0.95 in the first two iterations and not at all afterwards
+        for &factor in &[0.95f32, 0.9025, 0.9025, 0.9025] {
+            solver.train_minibatch(tensor(&[1, 2], &[0f32, 0f32]), tensor(&[1, 1], &[1f32]));
+            for (weight, initial) in weights(&solver).iter().zip(initial_weights.iter()) {
+                assert!((weight - initial * factor).abs() < 1e-6, "{} != {}", weight, initial * factor);
+            }
+        }
+    }
+
+    /// Trains a network of two Linear layers for one minibatch and returns the gradient
+    /// statistics, with the weights of the output layer scaled by `scale`.
+    fn two_layer_gradient_stats(scale: f32) -> Vec<GradientStats> {
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[1, 2]);
+        net_cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 2 }));
+        net_cfg.add_layer(LayerConfig::new("out", LinearConfig { output_size: 3 }));
+        let cfg = SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            gradient_stats: Some(GradientStatsConfig { interval: 1, ..GradientStatsConfig::default() }),
+            ..linear_solver_config(1, 0.1f32, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        let native = native_backend();
+        let weights = solver.network().learnable_weights_data();
+        write_to_memory(weights[0].write().unwrap().write_only(native.device()).unwrap(),
+                        &[1f32, 0.5, -0.5, 2.0]);
+        let out_weights = [0.5f32, -1.0, 0.0, 2.0, 0.25, 0.0].iter().map(|w| w * scale).collect::<Vec<_>>();
+        write_to_memory(weights[1].write().unwrap().write_only(native.device()).unwrap(),
+                        &out_weights);
+
+        assert!(solver.gradient_stats().is_empty());
+        solver.train_minibatch(tensor(&[1, 2], &[1f32, -1f32]), tensor(&[1, 1], &[1f32]));
+        let stats = solver.gradient_stats().to_vec();
+        assert_eq!(solver.network().learnable_weights_names(),
+                   stats.iter().map(|stats| stats.name.clone()).collect::<Vec<_>>());
+        stats
+    }
+
+    #[test]
+    fn gradient_stats_reflect_scaled_layer() {
+        let stats = two_layer_gradient_stats(1f32);
+        let scaled = two_layer_gradient_stats(10f32);
+
+        // the gradient of the hidden layer is propagated through the scaled output layer,
+        // while the one of the output layer only depends on the unchanged hidden activations
+        assert!((scaled[0].norm / stats[0].norm - 10f32).abs() < 1e-4);
+        assert!((scaled[0].max_abs / stats[0].max_abs - 10f32).abs() < 1e-4);
+        assert!((scaled[0].mean_abs / stats[0].mean_abs - 10f32).abs() < 1e-4);
+        assert_eq!(stats[1], scaled[1]);
+
+        // only the row of the label in the gradient of the output layer is non-zero,
+        // it is the negative hidden activation [-0.5, 2.5]
+        assert!((stats[1].near_zero_fraction - 4f32 / 6f32).abs() < 1e-6, "{}", stats[1]);
+        assert!((stats[1].norm - 6.5f32.sqrt()).abs() < 1e-6, "{}", stats[1]);
+        assert_eq!(2.5f32, stats[1].max_abs);
+    }
+
+    #[test]
+    fn steady_state_training_transfers_are_bounded() {
+        let cfg = SolverConfig {
+            record_transfers: true,
+            clip_gradients: Some(100f32),
+            ..linear_solver_config(2, 0.1, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        assert!(solver.transfer_summary().is_none());
+
+        for _ in 0..3 {
+            solver.train_minibatch(tensor(&[2, 2], &[1.0, 2.0, -1.0, 0.5]),
+                                   tensor(&[2, 1], &[0.0, 2.0]));
+            let summary = solver.transfer_summary().unwrap();
+            // the data and the target, the loss and the squared norm of the gradient for clipping,
+            // and the inputs, output and their gradients of the negative log likelihood, which
+            // computes on the host
+            assert!(summary.count() <= 10, "{}", summary);
+            assert_eq!(2, summary.category_count(TransferCategory::InputFeed));
+            assert_eq!(1, summary.category_count(TransferCategory::LossReadback));
+            assert_eq!(1, summary.category_count(TransferCategory::WeightUpdate));
+            assert_eq!(0, summary.category_count(TransferCategory::DebugStats));
+            let host_fallbacks = summary.category_count(TransferCategory::HostFallback);
+            assert!(host_fallbacks > 0 && host_fallbacks <= 6, "{}", summary);
+        }
+        assert!(!transfer::is_recording());
+    }
+
+    #[test]
+    fn lars_ratio_falls_back_to_unscaled_rate_for_zero_norms() {
+        assert!((::solvers::lars_ratio(0.001, 4.0, 2.0, 0.0) - 0.002).abs() < 1e-9);
+        assert!((::solvers::lars_ratio(0.001, 4.0, 2.0, 0.5) - 0.001).abs() < 1e-9);
+        assert_eq!(1f32, ::solvers::lars_ratio(0.001, 0.0, 2.0, 0.5));
+        assert_eq!(1f32, ::solvers::lars_ratio(0.001, 4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn lars_ratios_are_reported_per_weight_blob() {
+        let cfg = SolverConfig {
+            weight_decay: Some(0.5),
+            regularization_method: Some(RegularizationMethod::L2),
+            lars_trust_coefficient: Some(0.01),
+            ..linear_solver_config(1, 0.1f32, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        assert!(solver.lars_ratios().is_empty());
+
+        // a zero input yields a zero gradient, so the ratio is trust_coefficient / weight_decay
+        set_weights(&mut solver, &[1f32, -2f32, 0.5f32, 4f32, 0f32, -1f32]);
+        solver.train_minibatch(tensor(&[1, 2], &[0f32, 0f32]), tensor(&[1, 1], &[1f32]));
+        assert_eq!(1, solver.lars_ratios().len());
+        assert!((solver.lars_ratios()[0] - 0.02).abs() < 1e-6, "{:?}", solver.lars_ratios());
+
+        set_weights(&mut solver, &[0f32; 6]);
+        solver.train_minibatch(tensor(&[1, 2], &[1f32, 2f32]), tensor(&[1, 1], &[1f32]));
+        assert_eq!(&[1f32], solver.lars_ratios());
+    }
+
+    /// Fits `y = 2 * x0 - 3 * x1` with updates of 1024 samples, accumulated over 128
+    /// micro-batches of 8 samples, and a learning rate that is far too large for plain momentum.
+    fn train_large_batch_regression(lars_trust_coefficient: Option<f32>) -> Vec<f32> {
+        let batch_size = 8;
+        let accumulate_steps = 128;
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[batch_size, 2]);
+        net_cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 1 }));
+
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[batch_size, 1]);
+        obj_cfg.add_input("label", &[batch_size, 1]);
+        // a beta larger than any residual turns the loss into a squared error
+        obj_cfg.add_layer(LayerConfig::new("loss", SmoothL1LossConfig { beta: 1e30 }));
+
+        let cfg = SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 10f32,
+            momentum: 0.9,
+            weight_decay: Some(0.0005),
+            regularization_method: Some(RegularizationMethod::L2),
+            accumulate_steps: accumulate_steps,
+            lars_trust_coefficient: lars_trust_coefficient,
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend, &cfg);
+        set_weights(&mut solver, &[0.5f32, -0.5f32]);
+        for _ in 0..50 {
+            for step in 0..accumulate_steps {
+                let inputs = (0..batch_size * 2)
+                    .map(|i| ((step * batch_size * 2 + i) as f32 * 1.7f32).sin() * 2f32)
+                    .collect::<Vec<_>>();
+                let labels = inputs.chunks(2).map(|x| 2f32 * x[0] - 3f32 * x[1]).collect::<Vec<_>>();
+                solver.train_minibatch(tensor(&[batch_size, 2], &inputs), tensor(&[batch_size, 1], &labels));
+            }
+        }
+        weights(&solver)
+    }
+
+    #[test]
+    fn lars_is_stable_with_large_batches_where_momentum_diverges() {
+        let momentum = train_large_batch_regression(None);
+        assert!(!momentum.iter().all(|weight| weight.abs() < 1e3), "{:?}", momentum);
+
+        let lars = train_large_batch_regression(Some(0.001));
+        assert!((lars[0] - 2f32).abs() < 0.5 && (lars[1] + 3f32).abs() < 0.5, "{:?}", lars);
+    }
+
+    #[test]
+    fn accumulation_in_f64_is_more_accurate() {
+        let inputs = [1f32, 2f32];
+        let labels = [2f32];
+        let accumulate_steps = 1000;
+
+        // all micro-batches are the same, so the accumulated gradient equals the gradient of one
+        let mut reference = linear_solver(1, 0.1f32, 1);
+        let mut f32_accumulation = linear_solver(1, 0.1f32, accumulate_steps);
+        let mut f64_accumulation = linear_solver(1, 0.1f32, accumulate_steps);
+        f64_accumulation.config.accumulate_in_f64 = true;
+        let initial_weights = weights(&reference);
+        set_weights(&mut f32_accumulation, &initial_weights);
+        set_weights(&mut f64_accumulation, &initial_weights);
+
+        reference.train_minibatch(tensor(&[1, 2], &inputs), tensor(&[1, 1], &labels));
+        for _ in 0..accumulate_steps {
+            f32_accumulation.train_minibatch(tensor(&[1, 2], &inputs), tensor(&[1, 1], &labels));
+            f64_accumulation.train_minibatch(tensor(&[1, 2], &inputs), tensor(&[1, 1], &labels));
+        }
+
+        let error = |solver: &Solver<Backend<Native>, Backend<Native>>| {
+            weights(&reference)
+                .iter()
+                .zip(weights(solver).iter())
+                .fold(0f32, |sum, (expected, actual)| sum + (expected - actual).abs())
+        };
+        assert_ne!(initial_weights, weights(&f64_accumulation));
+        assert!(error(&f64_accumulation) < 1e-7, "f64 error {}", error(&f64_accumulation));
+        assert!(error(&f64_accumulation) < error(&f32_accumulation),
+                "f64 error {} >= f32 error {}",
+                error(&f64_accumulation),
+                error(&f32_accumulation));
+    }
+
+    #[test]
+    fn builder_validates_config() {
+        let config = SolverConfig::builder()
+            .base_lr(0.05)
+            .momentum(0.9)
+            .weight_decay(0.001)
+            .regularization_method(RegularizationMethod::L2)
+            .accumulate_steps(8)
+            .accumulate_in_f64(true)
+            .clip_gradients(5.0)
+            .lr_scheduler(CosineLr::new(0.05, 0.0, 100))
+            .build()
+            .unwrap();
+        assert_eq!(8, config.accumulate_steps);
+        assert_eq!(Some(5f32), config.clip_gradients);
+        assert_eq!(0.05f32, config.get_learning_rate(0));
+
+        let errors = vec![SolverConfig::builder().accumulate_steps(0).build(),
+                          SolverConfig::builder().accumulate_in_f64(true).build(),
+                          SolverConfig::builder().clip_gradients(0.0).build(),
+                          SolverConfig::builder().momentum(1.0).build(),
+                          SolverConfig::builder().regularization_method(RegularizationMethod::L2).build(),
+                          SolverConfig::builder().lr_policy(LRPolicy::Exp).lr_scheduler(ConstantLr { lr: 0.1 }).build(),
+                          SolverConfig::builder().keep_best(2).build()];
+        let expected = ["accumulate_steps",
+                        "accumulate_in_f64",
+                        "clip_gradients",
+                        "momentum",
+                        "regularization_method",
+                        "mutually exclusive",
+                        "snapshot_prefix"];
+        for (result, expected) in errors.into_iter().zip(expected.iter()) {
+            let err = result.unwrap_err();
+            assert!(err.contains(expected), "'{}' does not mention {}", err, expected);
+        }
+    }
+
+    fn predictions(solver: &mut Solver<Backend<Native>, Backend<Native>>, inputs: &[f32]) -> Vec<f32> {
+        let native = native_backend();
+        let outputs = solver.mut_network().forward(&[tensor(&[4, 2], inputs)]);
+        let output = outputs[0].read().unwrap();
+        let data = output.read(native.device()).unwrap().as_slice::<f32>().to_vec();
+        data
+    }
+
+    fn argmax(scores: &[f32]) -> Vec<usize> {
+        scores.chunks(3)
+            .map(|sample| (0..3).fold(0, |max, i| if sample[i] > sample[max] { i } else { max }))
+            .collect()
+    }
+
+    #[test]
+    fn quantized_network_keeps_accuracy() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+
+        let mut solver = linear_solver(4, 0.01f32, 1);
+        for _ in 0..100 {
+            solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+        }
+        let trained = predictions(&mut solver, &inputs);
+
+        let report = solver.mut_network().quantize_linear_weights();
+        assert_eq!(1, report.max_error.len());
+        assert_eq!(6 * 4, report.f32_bytes);
+        assert_eq!(6 + 3 * 4, report.int8_bytes);
+        // the f32 weights are released and only dequantized during the forward passes
+        let native = native_backend();
+        let weights = solver.mut_network().learnable_weights_data();
+        assert!(weights[0].read().unwrap().read(native.device()).is_err());
+        let quantized = predictions(&mut solver, &inputs);
+        assert!(weights[0].read().unwrap().read(native.device()).is_err());
+        assert_eq!(quantized, predictions(&mut solver, &inputs));
+
+        assert_eq!(argmax(&trained), argmax(&quantized));
+        // each output sums 2 inputs with a magnitude of at most 3
+        let max_error = report.max_error[0].1;
+        for (trained, quantized) in trained.iter().zip(quantized.iter()) {
+            assert!((trained - quantized).abs() <= 2f32 * 3f32 * max_error + 1e-5);
+        }
+    }
+
+    #[test]
+    fn recent_losses_keeps_the_last_losses() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+
+        let mut solver = linear_solver(4, 0.1f32, 1);
+        solver.config.loss_history_size = 3;
+        assert!(solver.recent_losses(3).is_empty());
+
+        let mut losses = Vec::new();
+        for _ in 0..5 {
+            solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+            losses.push(solver.recent_losses(1)[0]);
+        }
+        assert!(losses[4] < losses[0]);
+        assert_eq!(&losses[3..], &solver.recent_losses(2)[..]);
+        assert_eq!(&losses[2..], &solver.recent_losses(3)[..]);
+        assert_eq!(&losses[2..], &solver.recent_losses(10)[..]);
+    }
+
+    #[test]
+    fn snapshots_keep_best_and_latest() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+        let dir = temp_path("solver_snapshots");
+        ::std::fs::create_dir_all(&dir).unwrap();
+
+        let mut solver = linear_solver(4, 0.01f32, 1);
+        solver.config.snapshot_interval = Some(3);
+        solver.config.snapshot_prefix = Some(dir.join("net").to_str().unwrap().to_owned());
+        solver.config.keep_best = 2;
+        let train = |solver: &mut Solver<Backend<Native>, Backend<Native>>, iterations: usize| {
+            for _ in 0..iterations {
+                solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+            }
+        };
+
+        train(&mut solver, 1);
+        assert!(solver.report_validation_metric(0.9).unwrap().is_some());
+        // snapshot at the interval
+        train(&mut solver, 2);
+        let best = solver.report_validation_metric(0.5).unwrap().unwrap();
+        train(&mut solver, 3);
+        assert!(solver.report_validation_metric(0.7).unwrap().is_none());
+        let latest = solver.report_validation_metric(0.4).unwrap().unwrap();
+
+        let mut files = ::std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(vec![dir.join("net_iter_3_metric_0.500000.capnp"),
+                        dir.join("net_iter_3_metric_0.500000.progress.capnp"),
+                        dir.join("net_iter_6_metric_0.400000.capnp"),
+                        dir.join("net_iter_6_metric_0.400000.progress.capnp")],
+                   files);
+        assert_eq!(vec![best.clone(), TrainingProgress::snapshot_path(&best), latest.clone(),
+                        TrainingProgress::snapshot_path(&latest)],
+                   files);
+        // the progress is written with the snapshot, so the training can be resumed from it
+        let progress = TrainingProgress::load(TrainingProgress::snapshot_path(&latest)).unwrap();
+        assert_eq!((6, 6, None), (progress.iter, progress.minibatches, progress.phase));
+        assert_eq!(solver.progress().elapsed, progress.elapsed);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn two_layer_solver(momentum: f32, freeze_schedule: FreezeSchedule) -> Solver<Backend<Native>, Backend<Native>> {
+        let mut net_cfg = SequentialConfig::default();
+        net_cfg.add_input("data", &[4, 2]);
+        net_cfg.add_layer(LayerConfig::new("hidden", LinearConfig { output_size: 3 }));
+        net_cfg.add_layer(LayerConfig::new("head", LinearConfig { output_size: 3 }));
+
+        let mut obj_cfg = SequentialConfig::default();
+        obj_cfg.add_input("network_out", &[4, 3]);
+        obj_cfg.add_input("label", &[4, 1]);
+        obj_cfg.add_layer(LayerConfig::new("nll", NegativeLogLikelihoodConfig { num_classes: 3 }));
+
+        let cfg = SolverConfig {
+            network: LayerConfig::new("network", net_cfg),
+            objective: LayerConfig::new("objective", obj_cfg),
+            base_lr: 0.01f32,
+            momentum: momentum,
+            freeze_schedule: freeze_schedule,
+            ..SolverConfig::default()
+        };
+        let backend = Rc::new(native_backend());
+        Solver::from_config(backend.clone(), backend, &cfg)
+    }
+
+    fn all_weights(solver: &Solver<Backend<Native>, Backend<Native>>) -> Vec<Vec<f32>> {
+        let native = native_backend();
+        solver.network()
+            .learnable_weights_data()
+            .iter()
+            .map(|weight| weight.read().unwrap().read(native.device()).unwrap().as_slice::<f32>().to_vec())
+            .collect()
+    }
+
+    fn set_all_weights(solver: &mut Solver<Backend<Native>, Backend<Native>>, data: &[Vec<f32>]) {
+        let native = native_backend();
+        for (weight, data) in solver.mut_network().learnable_weights_data().iter().zip(data) {
+            write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn freeze_schedule_trains_unfrozen_layers() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+        let mut schedule = FreezeSchedule::default();
+        schedule.add_phase(0, &[]);
+        schedule.add_phase(2, &["hid*"]);
+        schedule.add_phase(4, &[]);
+
+        let mut solver = two_layer_solver(0.9f32, schedule);
+        // which of [hidden, head] change in each iteration
+        let expected_changes = [[true, true], [true, true], [false, true], [false, true], [true, true]];
+        for (iter, expected) in expected_changes.iter().enumerate() {
+            let before = all_weights(&solver);
+            if iter == 4 {
+                // the update of the unfrozen layer starts without momentum, like the first
+                // update of a solver without momentum
+                let mut reference = two_layer_solver(0f32, FreezeSchedule::default());
+                set_all_weights(&mut reference, &before);
+                reference.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+                solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+                let (weights, reference_weights) = (all_weights(&solver), all_weights(&reference));
+                for (weight, reference_weight) in weights[0].iter().zip(&reference_weights[0]) {
+                    assert!((weight - reference_weight).abs() < 1e-6);
+                }
+            } else {
+                solver.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+            }
+            let after = all_weights(&solver);
+            let changed = before.iter().zip(&after).map(|(before, after)| before != after).collect::<Vec<_>>();
+            assert_eq!(&expected[..], &changed[..], "iteration {}", iter);
+        }
+    }
+
+    #[test]
+    fn warmup_before_cosine_schedule() {
+        let cfg = SolverConfig {
+            lr_scheduler: Some(Rc::new(WarmupLr::new(10, CosineLr::new(0.1f32, 0.01f32, 100)))),
+            ..SolverConfig::default()
+        };
+        let expected = [(0, 0.01f32),
+                        (4, 0.05),
+                        (9, 0.1),
+                        (10, 0.1),
+                        // halfway through the cosine
+                        (60, 0.055),
+                        (110, 0.01),
+                        (1000, 0.01)];
+        for &(iter, lr) in &expected {
+            let actual = cfg.get_learning_rate(iter);
+            assert!((actual - lr).abs() < 1e-6, "iteration {}: {} != {}", iter, actual, lr);
+        }
+    }
+
+    #[test]
+    fn lr_policies_are_built_in_schedulers() {
+        let cfg = SolverConfig {
+            lr_policy: LRPolicy::Step,
+            base_lr: 5f32,
+            gamma: 0.5f32,
+            stepsize: 10,
+            ..SolverConfig::default()
+        };
+        assert_eq!(5f32, cfg.get_learning_rate(9));
+        assert_eq!(2.5f32, cfg.get_learning_rate(10));
+        assert_eq!(1.25f32, cfg.get_learning_rate(20));
+    }
+
+    /// Yields `num_batches` copies of a batch per epoch.
+    struct RepeatedBatches {
+        num_batches: usize,
+        position: usize,
+        known_length: bool,
+    }
+
+    impl DataIterator for RepeatedBatches {
+        fn next_batch(&mut self) -> Option<Batch> {
+            if self.position == self.num_batches {
+                return None;
+            }
+            self.position += 1;
+            Some(Batch {
+                data: tensor(&[1, 2], &[1f32, 2f32]),
+                target: tensor(&[1, 1], &[2f32]),
+                sample_ids: vec![self.position - 1],
+            })
+        }
+
+        fn reset(&mut self) {
+            self.position = 0;
+        }
+
+        fn batches_per_epoch(&self) -> Option<usize> {
+            if self.known_length {
+                Some(self.num_batches)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn batches(num_batches: usize) -> RepeatedBatches {
+        RepeatedBatches {
+            num_batches: num_batches,
+            position: 0,
+            known_length: true,
+        }
+    }
+
+    /// Yields the same batches in every epoch.
+    struct BatchList {
+        batches: Vec<Batch>,
+        position: usize,
+    }
+
+    impl DataIterator for BatchList {
+        fn next_batch(&mut self) -> Option<Batch> {
+            let batch = self.batches.get(self.position).cloned();
+            self.position += 1;
+            batch
+        }
+
+        fn reset(&mut self) {
+            self.position = 0;
+        }
+    }
+
+    #[test]
+    fn validate_averages_batch_losses_without_training() {
+        let mut solver = linear_solver(1, 0.1f32, 1);
+        let initial_weights = [1f32, 2f32, 3f32, 4f32, 5f32, 6f32];
+        set_weights(&mut solver, &initial_weights);
+        let batch = |data: &[f32], label: f32| {
+            Batch {
+                data: tensor(&[1, 2], data),
+                target: tensor(&[1, 1], &[label]),
+                sample_ids: vec![0],
+            }
+        };
+        // the losses are the negative outputs for the labels, -1, -6 and -7
+        let mut val_data = BatchList {
+            batches: vec![batch(&[1f32, 0f32], 0f32), batch(&[0f32, 1f32], 2f32), batch(&[1f32, 1f32], 1f32)],
+            position: 1,
+        };
+
+        let loss = solver.validate(&mut val_data);
+        assert!((loss + 14f32 / 3f32).abs() < 1e-6, "{}", loss);
+        assert_eq!(initial_weights.to_vec(), weights(&solver));
+        assert!(solver.recent_losses(10).is_empty());
+        assert_eq!(0, solver.progress().iter);
+        // the whole epoch is validated again
+        assert_eq!(loss, solver.validate(&mut val_data));
+    }
+
+    fn fit(cfg: SolverConfig, data: &mut DataIterator) -> Result<TrainingSummary, String> {
+        let backend = Rc::new(native_backend());
+        Solver::from_config(backend.clone(), backend, &cfg).fit(data)
+    }
+
+    #[test]
+    fn fit_stops_at_first_criterion_that_triggers() {
+        let cfg = linear_solver_config(1, 0.1f32, 1);
+
+        let summary = fit(SolverConfig { max_iter: Some(10), max_epochs: Some(3), ..cfg.clone() },
+                          &mut batches(2))
+            .unwrap();
+        assert_eq!(StopCriterion::MaxEpochs, summary.criterion);
+        assert_eq!(6, summary.minibatches);
+        let summary = fit(SolverConfig { max_iter: Some(5), max_epochs: Some(3), ..cfg.clone() },
+                          &mut batches(2))
+            .unwrap();
+        assert_eq!(StopCriterion::MaxIter, summary.criterion);
+        assert_eq!(5, summary.iterations);
+
+        // the iterations count weight updates, not minibatches
+        let summary = fit(SolverConfig { max_iter: Some(2), ..linear_solver_config(1, 0.1f32, 2) },
+                          &mut batches(3))
+            .unwrap();
+        assert_eq!((2, 4), (summary.iterations, summary.minibatches));
+
+        // the loss of the first minibatch is already below the target
+        let summary = fit(SolverConfig {
+                              target_loss: Some(1e6),
+                              max_iter: Some(100),
+                              max_duration: Some(Duration::from_secs(3600)),
+                              ..cfg.clone()
+                          },
+                          &mut batches(2))
+            .unwrap();
+        assert_eq!(StopCriterion::TargetLoss, summary.criterion);
+        assert_eq!(1, summary.minibatches);
+        assert!(summary.smoothed_loss.unwrap() < 1e6);
+        let summary = fit(SolverConfig { max_duration: Some(Duration::from_secs(0)), max_iter: Some(3), ..cfg.clone() },
+                          &mut batches(2))
+            .unwrap();
+        assert_eq!(StopCriterion::MaxDuration, summary.criterion);
+        assert_eq!(1, summary.iterations);
+
+        // criteria that trigger after the same minibatch are resolved in a fixed order
+        let summary = fit(SolverConfig {
+                              max_iter: Some(1),
+                              max_duration: Some(Duration::from_secs(0)),
+                              target_loss: Some(1e6),
+                              max_epochs: Some(1),
+                              ..cfg.clone()
+                          },
+                          &mut batches(1))
+            .unwrap();
+        assert_eq!(StopCriterion::MaxIter, summary.criterion);
+        let summary = fit(SolverConfig { target_loss: Some(1e6), max_epochs: Some(1), ..cfg.clone() },
+                          &mut batches(1))
+            .unwrap();
+        assert_eq!(StopCriterion::TargetLoss, summary.criterion);
+    }
+
+    #[test]
+    fn resuming_keeps_elapsed_time_unless_reset() {
+        let cfg = SolverConfig {
+            max_iter: Some(2),
+            max_duration: Some(Duration::from_secs(60)),
+            ..linear_solver_config(1, 0.1f32, 1)
+        };
+        let backend = Rc::new(native_backend());
+        let mut solver = Solver::from_config(backend.clone(), backend.clone(), &cfg);
+        let summary = solver.fit(&mut batches(1)).unwrap();
+        assert_eq!(StopCriterion::MaxIter, summary.criterion);
+        let mut progress = solver.progress();
+        assert_eq!((2, 2), (progress.iter, progress.minibatches));
+        assert_eq!(summary.elapsed, progress.elapsed);
+
+        // pretend the first run took an hour
+        progress.elapsed = Duration::from_secs(3600);
+        let cfg = SolverConfig { max_iter: Some(5), ..cfg };
+        let mut resumed = Solver::from_config(backend.clone(), backend.clone(), &cfg);
+        resumed.resume(&progress, false);
+        let summary = resumed.fit(&mut batches(1)).unwrap();
+        assert_eq!(StopCriterion::MaxDuration, summary.criterion);
+        assert_eq!((3, 3), (summary.iterations, summary.minibatches));
+        assert!(summary.elapsed >= Duration::from_secs(3600));
+
+        let mut restarted = Solver::from_config(backend.clone(), backend, &cfg);
+        restarted.resume(&progress, true);
+        let summary = restarted.fit(&mut batches(1)).unwrap();
+        assert_eq!(StopCriterion::MaxIter, summary.criterion);
+        assert_eq!(5, summary.iterations);
+        assert!(summary.elapsed < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn plan_changes_frozen_layers_and_lr_at_phase_boundary() {
+        let inputs = [1f32, 2f32, -1f32, 0.5f32, 0f32, 3f32, 2f32, -2f32];
+        let labels = [0f32, 2f32, 1f32, 2f32];
+        let data = || {
+            BatchList {
+                batches: vec![Batch {
+                                  data: tensor(&[4, 2], &inputs),
+                                  target: tensor(&[4, 1], &labels),
+                                  sample_ids: vec![0, 1, 2, 3],
+                              }],
+                position: 0,
+            }
+        };
+        let mut plan = TrainingPlan::default();
+        plan.add_phase("all", 2).lr_scheduler(ConstantLr { lr: 0.01 });
+        plan.add_phase("head", 2).lr_scheduler(ConstantLr { lr: 0.1 }).frozen(&["hid*"]).reset_history(true);
+
+        let dir = temp_path("solver_plan_snapshots");
+        ::std::fs::create_dir_all(&dir).unwrap();
+
+        let mut solver = two_layer_solver(0.9f32, FreezeSchedule::default());
+        solver.config.snapshot_interval = Some(2);
+        solver.config.snapshot_prefix = Some(dir.join("net").to_str().unwrap().to_owned());
+        let initial = all_weights(&solver);
+        let (mut first, mut second) = (data(), data());
+        let summaries = {
+            let mut loaders: Vec<&mut DataIterator> = vec![&mut first, &mut second];
+            solver.run_plan(&plan, &mut loaders).unwrap()
+        };
+        assert_eq!(vec![("all", 0, 2), ("head", 2, 4)],
+                   summaries.iter()
+                       .map(|phase| (&phase.name[..], phase.start_iter, phase.summary.iterations))
+                       .collect::<Vec<_>>());
+        assert_eq!(Some(1), solver.progress().phase);
+        assert_eq!(0.1f32, solver.config.get_learning_rate(4));
+        let boundaries = solver.phase_boundaries();
+        assert_eq!(vec![(0, "all", 0, 0.01f32), (1, "head", 2, 0.1f32)],
+                   boundaries.iter()
+                       .map(|boundary| (boundary.phase, &boundary.name[..], boundary.iter, boundary.lr))
+                       .collect::<Vec<_>>());
+        assert!(boundaries[0].frozen_weights.is_empty());
+        assert!(!boundaries[1].frozen_weights.is_empty());
+        assert!(boundaries[1].frozen_weights.iter().all(|name| name.contains("hidden")));
+
+        // the same training with the phases applied by hand
+        let mut schedule = FreezeSchedule::default();
+        schedule.add_phase(2, &["hid*"]);
+        let mut reference = two_layer_solver(0.9f32, schedule);
+        set_all_weights(&mut reference, &initial);
+        for _ in 0..2 {
+            reference.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+        }
+        let after_first_phase = all_weights(&reference);
+        reference.config.lr_scheduler = Some(Rc::new(ConstantLr { lr: 0.1 }));
+        reference.worker.reset_history(&[0, 1]);
+        for _ in 0..2 {
+            reference.train_minibatch(tensor(&[4, 2], &inputs), tensor(&[4, 1], &labels));
+        }
+        let weights = all_weights(&solver);
+        assert_eq!(all_weights(&reference), weights);
+        assert_eq!(after_first_phase[0], weights[0]);
+        assert!(after_first_phase[1] != weights[1]);
+
+        // a solver resumed from the snapshot after the first phase continues with the second
+        let progress = TrainingProgress::load(TrainingProgress::snapshot_path(dir.join("net_iter_2.capnp")))
+            .unwrap();
+        assert_eq!((2, Some(0)), (progress.iter, progress.phase));
+        let mut resumed = two_layer_solver(0.9f32, FreezeSchedule::default());
+        set_all_weights(&mut resumed, &after_first_phase);
+        resumed.resume(&progress, false);
+        let summaries = {
+            let mut loaders: Vec<&mut DataIterator> = vec![&mut first, &mut second];
+            resumed.run_plan(&plan, &mut loaders).unwrap()
+        };
+        assert_eq!(vec![1], summaries.iter().map(|phase| phase.phase).collect::<Vec<_>>());
+        assert_eq!(weights, all_weights(&resumed));
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plan_phases_train_with_their_batch_size() {
+        let data = |batch_size: usize| {
+            let inputs = (0..batch_size * 2).map(|i| i as f32 * 0.25f32).collect::<Vec<_>>();
+            let labels = (0..batch_size).map(|i| (i % 3) as f32).collect::<Vec<_>>();
+            BatchList {
+                batches: vec![Batch {
+                                  data: tensor(&[batch_size, 2], &inputs),
+                                  target: tensor(&[batch_size, 1], &labels),
+                                  sample_ids: (0..batch_size).collect(),
+                              }],
+                position: 0,
+            }
+        };
+        let mut plan = TrainingPlan::default();
+        plan.add_phase("large", 2).batch_size(4);
+        plan.add_phase("small", 2).batch_size(2);
+
+        let mut solver = two_layer_solver(0.9f32, FreezeSchedule::default());
+        let (mut large, mut small) = (data(4), data(2));
+        {
+            let mut loaders: Vec<&mut DataIterator> = vec![&mut large, &mut small];
+            solver.run_plan(&plan, &mut loaders).unwrap();
+        }
+        assert_eq!(vec![4, 2],
+                   solver.phase_boundaries().iter().map(|boundary| boundary.minibatch_size).collect::<Vec<_>>());
+        assert_eq!(2, solver.config.minibatch_size);
+        assert_eq!(4, solver.progress().iter);
+
+        // the loader of a phase has to yield batches of its size
+        let mut solver = two_layer_solver(0.9f32, FreezeSchedule::default());
+        let (mut large, mut other) = (data(4), data(4));
+        let err = {
+            let mut loaders: Vec<&mut DataIterator> = vec![&mut large, &mut other];
+            solver.run_plan(&plan, &mut loaders).unwrap_err()
+        };
+        assert!(err.contains("batch size of 2"));
+        assert_eq!(2, solver.progress().iter);
+    }
+
+    #[test]
+    fn fit_requires_usable_criteria() {
+        let cfg = linear_solver_config(1, 0.1f32, 1);
+        assert!(fit(cfg.clone(), &mut batches(1)).is_err());
+        let mut unknown_length = RepeatedBatches { known_length: false, ..batches(1) };
+        assert!(fit(SolverConfig { max_epochs: Some(1), ..cfg.clone() }, &mut unknown_length).is_err());
+        assert!(fit(SolverConfig { max_iter: Some(1), ..cfg.clone() }, &mut batches(0)).is_err());
+
+        assert!(SolverConfig::builder().max_iter(0).build().is_err());
+        let err = SolverConfig::builder().target_loss(0.5).loss_history_size(0).build().unwrap_err();
+        assert!(err.contains("target_loss"));
+        assert!(SolverConfig::builder().max_epochs(2).max_duration(Duration::from_secs(10)).build().is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+//! Provides training plans, which train a network in consecutive phases, e.g. for
+//! curriculum learning or for fine-tuning after training only the head of a network.
+//!
+//! A [TrainingPlan][1] is run by [Solver::run_plan][2]. Every [TrainingPhase][3] trains
+//! for a number of iterations with its own data, batch size, [learning rate schedule][4]
+//! and [frozen layers][5]. The weights are carried from one phase into the next, and the
+//! Solver records a [PhaseBoundary][6] whenever a phase starts.
+//!
+//! [1]: ./struct.TrainingPlan.html
+//! [2]: ../struct.Solver.html#method.run_plan
+//! [3]: ./struct.TrainingPhase.html
+//! [4]: ../lr_scheduler/trait.LrScheduler.html
+//! [5]: ../struct.FreezeSchedule.html
+//! [6]: ./struct.PhaseBoundary.html
+
+use solver::{LrScheduler, TrainingSummary};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Default)]
+/// The phases of a training, in the order they are trained.
+pub struct TrainingPlan {
+    /// The phases of the plan.
+    pub phases: Vec<TrainingPhase>,
+}
+
+impl TrainingPlan {
+    /// Add a phase named `name` that trains for `iterations` iterations.
+    ///
+    /// The phase keeps the learning rate and the minibatch size of the [SolverConfig][1],
+    /// freezes no layers and keeps the solver history; the returned phase can be changed to
+    /// do otherwise.
+    /// [1]: ../struct.SolverConfig.html
+    pub fn add_phase(&mut self, name: &str, iterations: usize) -> &mut TrainingPhase {
+        self.phases.push(TrainingPhase {
+            name: name.to_owned(),
+            iterations: iterations,
+            batch_size: None,
+            lr_scheduler: None,
+            frozen: Vec::new(),
+            reset_history: false,
+        });
+        self.phases.last_mut().unwrap()
+    }
+
+    /// Returns the iteration at which the phase `phase` starts, which is the sum of the
+    /// iterations of all phases before it.
+    pub fn start_iter(&self, phase: usize) -> usize {
+        self.phases[..phase].iter().fold(0, |start, phase| start + phase.iterations)
+    }
+
+    /// Checks that the plan has phases and that every phase has iterations and, if it has
+    /// a batch size, samples.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.phases.is_empty() {
+            return Err("A TrainingPlan needs at least one phase".to_owned());
+        }
+        for phase in &self.phases {
+            if phase.iterations == 0 {
+                return Err(format!("The phase '{}' has no iterations", phase.name));
+            }
+            if phase.batch_size == Some(0) {
+                return Err(format!("The phase '{}' has a batch size of 0", phase.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A phase of a [TrainingPlan](./struct.TrainingPlan.html).
+pub struct TrainingPhase {
+    /// The name of the phase, used in the log and the [PhaseSummary][1].
+    /// [1]: ./struct.PhaseSummary.html
+    pub name: String,
+    /// The number of iterations of the phase.
+    pub iterations: usize,
+    /// The number of samples in every batch of the phase.
+    ///
+    /// It replaces the [SolverConfig.minibatch_size][1], which scales the gradients, and
+    /// [Solver::run_plan][2] returns an error if the loader of the phase yields a batch of
+    /// another size. The network is reshaped to the batch size of its inputs. If not set,
+    /// the batches may have any size and the minibatch size of the previous phase is kept.
+    /// [1]: ../struct.SolverConfig.html#structfield.minibatch_size
+    /// [2]: ../struct.Solver.html#method.run_plan
+    pub batch_size: Option<usize>,
+    /// The schedule of the learning rate during the phase.
+    ///
+    /// Like [SolverConfig.lr_scheduler][1] it is consulted with the iteration of the whole
+    /// training, not of the phase. If not set, the learning rate of the previous phase is kept.
+    /// [1]: ../struct.SolverConfig.html#structfield.lr_scheduler
+    pub lr_scheduler: Option<Rc<LrScheduler>>,
+    /// The patterns of the names of the layers that are frozen during the phase.
+    ///
+    /// See [Layer.set_frozen_matching][1] for the syntax of the patterns.
+    /// [1]: ../../layer/struct.Layer.html#method.set_frozen_matching
+    pub frozen: Vec<String>,
+    /// Whether the solver history of all weights, e.g. the momentum, is reset when the
+    /// phase starts.
+    ///
+    /// Weights that are unfrozen by the phase always start without history.
+    pub reset_history: bool,
+}
+
+impl TrainingPhase {
+    /// Set the [batch_size](#structfield.batch_size) of the phase.
+    pub fn batch_size(&mut self, batch_size: usize) -> &mut TrainingPhase {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    /// Set the [lr_scheduler](#structfield.lr_scheduler) of the phase.
+    pub fn lr_scheduler<S: LrScheduler + 'static>(&mut self, lr_scheduler: S) -> &mut TrainingPhase {
+        self.lr_scheduler = Some(Rc::new(lr_scheduler));
+        self
+    }
+
+    /// Freeze the layers whose name matches one of the `frozen` patterns during the phase.
+    pub fn frozen(&mut self, frozen: &[&str]) -> &mut TrainingPhase {
+        self.frozen = frozen.iter().map(|pattern| pattern.to_string()).collect();
+        self
+    }
+
+    /// Set whether the solver history is [reset](#structfield.reset_history) when the phase starts.
+    pub fn reset_history(&mut self, reset_history: bool) -> &mut TrainingPhase {
+        self.reset_history = reset_history;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The start of a phase of a [TrainingPlan][1], as recorded by the Solver that trains it.
+///
+/// See [Solver::phase_boundaries][2].
+/// [1]: ./struct.TrainingPlan.html
+/// [2]: ../struct.Solver.html#method.phase_boundaries
+pub struct PhaseBoundary {
+    /// The index of the phase in the plan.
+    pub phase: usize,
+    /// The name of the phase.
+    pub name: String,
+    /// The iteration at which the phase starts.
+    pub iter: usize,
+    /// The learning rate at the first iteration of the phase.
+    pub lr: f32,
+    /// The minibatch size of the phase.
+    pub minibatch_size: usize,
+    /// The names of the learnable weights that are frozen during the phase.
+    pub frozen_weights: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The summary of a phase of a [TrainingPlan][1] that has been trained by [Solver::run_plan][2].
+/// [1]: ./struct.TrainingPlan.html
+/// [2]: ../struct.Solver.html#method.run_plan
+pub struct PhaseSummary {
+    /// The index of the phase in the plan.
+    pub phase: usize,
+    /// The name of the phase.
+    pub name: String,
+    /// The iteration at which the phase starts.
+    pub start_iter: usize,
+    /// The summary of the training up to the end of the phase.
+    pub summary: TrainingSummary,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrainingPlan;
+
+    #[test]
+    fn phases_start_after_the_previous_ones() {
+        let mut plan = TrainingPlan::default();
+        assert!(plan.validate().is_err());
+        plan.add_phase("head", 10).frozen(&["body*"]);
+        plan.add_phase("all", 5).reset_history(true);
+        assert!(plan.validate().is_ok());
+        assert_eq!((0, 10, 15), (plan.start_iter(0), plan.start_iter(1), plan.start_iter(2)));
+        assert_eq!(vec!["body*".to_owned()], plan.phases[0].frozen);
+
+        plan.add_phase("empty", 0);
+        assert!(plan.validate().unwrap_err().contains("empty"));
+        plan.phases.pop();
+        plan.add_phase("no_samples", 5).batch_size(0);
+        assert!(plan.validate().unwrap_err().contains("no_samples"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Provides structural regularizers, which penalize the structure of 2-D weights instead
+//! of their magnitude.
+//!
+//! Regularizers are added to a [Solver][1] for the weights of the layers whose name
+//! matches a pattern, see [Solver::add_structural_regularizer][2]. Before every weight
+//! update the gradient of the penalty is added to the gradient of each regularized weight.
+//! The penalty is computed on the backend of the Solver; only the normalization of the
+//! singular vectors of the spectral norm runs on the host.
+//!
+//! [1]: ../struct.Solver.html
+//! [2]: ../struct.Solver.html#method.add_structural_regularizer
+
+use co::prelude::*;
+use coblas::transpose::Transpose;
+use util::{SolverOps, native_scalar, normalize_on_host};
+use weight::FillerType;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A penalty on the structure of a weight matrix `W`.
+pub enum StructuralRegularizer {
+    /// The soft orthogonality penalty `lambda * ||W^T W - I||_F^2`, which pushes the
+    /// columns of `W` towards an orthonormal set.
+    Orthogonality {
+        /// The factor of the penalty.
+        lambda: f32,
+    },
+    /// The spectral norm penalty `lambda / 2 * sigma(W)^2`, which penalizes the largest
+    /// singular value `sigma` of `W`. See [Spectral Norm Regularization for Improving the
+    /// Generalizability of Deep Learning][1].
+    /// [1]: https://arxiv.org/abs/1705.10941
+    ///
+    /// The singular value is estimated by power iteration. The singular vectors are kept
+    /// across weight updates, so the iteration continues where the last update stopped.
+    SpectralNorm {
+        /// The factor of the penalty.
+        lambda: f32,
+        /// The number of steps of power iteration per weight update.
+        power_iterations: usize,
+    },
+}
+
+impl StructuralRegularizer {
+    /// Checks that lambda is non-negative and that there is at least one power iteration.
+    pub fn validate(&self) -> Result<(), String> {
+        let lambda = match *self {
+            StructuralRegularizer::Orthogonality { lambda } => lambda,
+            StructuralRegularizer::SpectralNorm { lambda, power_iterations } => {
+                if power_iterations == 0 {
+                    return Err("power_iterations must be positive, but is 0".to_owned());
+                }
+                lambda
+            }
+        };
+        if !lambda.is_finite() || lambda < 0f32 {
+            return Err(format!("lambda must be a non-negative number, but is {}", lambda));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// A [StructuralRegularizer](./enum.StructuralRegularizer.html) applied to one weight,
+/// together with the auxiliary tensors it keeps for it.
+pub struct RegularizedWeight {
+    regularizer: StructuralRegularizer,
+    /// `W^T W` for the orthogonality penalty, or the left singular vector `u` for the
+    /// spectral norm penalty.
+    first: SharedTensor<f32>,
+    /// The right singular vector `v` for the spectral norm penalty.
+    second: SharedTensor<f32>,
+    /// The last estimate of the largest singular value.
+    spectral_norm: Option<f32>,
+}
+
+impl RegularizedWeight {
+    /// Create the auxiliary tensors of `regularizer` for a weight of shape `shape`.
+    ///
+    /// Returns an error if the regularizer is invalid or the weight is not 2-D.
+    pub fn new(regularizer: StructuralRegularizer, shape: &[usize]) -> Result<RegularizedWeight, String> {
+        try!(regularizer.validate());
+        if shape.len() != 2 {
+            return Err(format!("Structural regularizers need 2-D weights, but the weight has the shape {:?}",
+                               shape));
+        }
+        let (rows, columns) = (shape[0], shape[1]);
+        let (first, second) = match regularizer {
+            StructuralRegularizer::Orthogonality { .. } => {
+                (SharedTensor::new(&[columns, columns]), SharedTensor::new(&[1]))
+            }
+            StructuralRegularizer::SpectralNorm { .. } => {
+                let mut u = SharedTensor::new(&[rows, 1]);
+                FillerType::Uniform { low: -1f32, high: 1f32 }.fill(&mut u);
+                normalize_on_host(&mut u);
+                (u, SharedTensor::new(&[columns, 1]))
+            }
+        };
+        Ok(RegularizedWeight {
+            regularizer: regularizer,
+            first: first,
+            second: second,
+            spectral_norm: None,
+        })
+    }
+
+    /// Returns the regularizer.
+    pub fn regularizer(&self) -> StructuralRegularizer {
+        self.regularizer
+    }
+
+    /// Returns the last estimate of the largest singular value of the weight, if the
+    /// regularizer is a spectral norm penalty that has been applied.
+    pub fn spectral_norm(&self) -> Option<f32> {
+        self.spectral_norm
+    }
+
+    /// Adds `scale` times the gradient of the penalty of `weight` to `gradient`.
+    pub fn add_gradient<B: IBackend + SolverOps<f32>>(&mut self,
+                                                      backend: &B,
+                                                      weight: &SharedTensor<f32>,
+                                                      gradient: &mut SharedTensor<f32>,
+                                                      scale: f32)
+                                                      -> Result<(), String> {
+        let one = native_scalar(1f32);
+        let zero = native_scalar(0f32);
+        match self.regularizer {
+            StructuralRegularizer::Orthogonality { lambda } => {
+                // the gradient is 4 * lambda * W (W^T W - I)
+                let factor = 4f32 * lambda * scale;
+                try!(backend.gemm(&one, Transpose::Trans, weight, Transpose::NoTrans, weight, &zero, &mut self.first)
+                    .map_err(|err| format!("{:?}", err)));
+                try!(backend.gemm(&native_scalar(factor),
+                          Transpose::NoTrans,
+                          weight,
+                          Transpose::NoTrans,
+                          &self.first,
+                          &one,
+                          gradient)
+                    .map_err(|err| format!("{:?}", err)));
+                try!(backend.axpy(&native_scalar(-factor), weight, gradient).map_err(|err| format!("{:?}", err)));
+            }
+            StructuralRegularizer::SpectralNorm { lambda, power_iterations } => {
+                let (u, v) = (&mut self.first, &mut self.second);
+                let mut sigma = 0f32;
+                for _ in 0..power_iterations {
+                    // v = W^T u / |W^T u|, u = W v / |W v|, where |W v| converges to sigma
+                    try!(backend.gemm(&one, Transpose::Trans, weight, Transpose::NoTrans, u, &zero, v)
+                        .map_err(|err| format!("{:?}", err)));
+                    normalize_on_host(v);
+                    try!(backend.gemm(&one, Transpose::NoTrans, weight, Transpose::NoTrans, v, &zero, u)
+                        .map_err(|err| format!("{:?}", err)));
+                    sigma = normalize_on_host(u);
+                }
+                self.spectral_norm = Some(sigma);
+                // the gradient is lambda * sigma * u v^T
+                try!(backend.gemm(&native_scalar(lambda * sigma * scale),
+                          Transpose::NoTrans,
+                          u,
+                          Transpose::Trans,
+                          v,
+                          &one,
+                          gradient)
+                    .map_err(|err| format!("{:?}", err)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use super::{RegularizedWeight, StructuralRegularizer};
+    use util::native_backend;
+    use util::testing::{read_shared, shared_tensor};
+
+    #[test]
+    fn power_iteration_converges_to_top_singular_value() {
+        let backend = native_backend();
+        // the singular values are 3 and 1
+        let weight = shared_tensor(&[2, 2], &[2f32, 1.0, 1.0, 2.0]);
+        let regularizer = StructuralRegularizer::SpectralNorm { lambda: 0.5, power_iterations: 1 };
+        let mut regularized = RegularizedWeight::new(regularizer, &[2, 2]).unwrap();
+        assert_eq!(None, regularized.spectral_norm());
+        for _ in 0..20 {
+            let mut gradient = shared_tensor(&[2, 2], &[0f32; 4]);
+            regularized.add_gradient(&backend, &weight, &mut gradient, 1f32).unwrap();
+        }
+        let sigma = regularized.spectral_norm().unwrap();
+        assert!((sigma - 3f32).abs() < 1e-4, "{}", sigma);
+
+        // lambda * sigma * u v^T with u = v = [1, 1] / sqrt(2)
+        let mut gradient = shared_tensor(&[2, 2], &[1f32, 0.0, 0.0, 0.0]);
+        regularized.add_gradient(&backend, &weight, &mut gradient, 1f32).unwrap();
+        let expected = [1.75f32, 0.75, 0.75, 0.75];
+        for (expected, actual) in expected.iter().zip(read_shared(&gradient)) {
+            assert!((expected - actual).abs() < 1e-3, "{:?}", read_shared(&gradient));
+        }
+
+        assert!(RegularizedWeight::new(regularizer, &[2, 2, 1, 1]).is_err());
+        assert!(RegularizedWeight::new(StructuralRegularizer::SpectralNorm { lambda: 0.5, power_iterations: 0 },
+                                       &[2, 2])
+            .is_err());
+    }
+
+    #[test]
+    fn orthogonality_penalty_drives_gram_matrix_to_identity() {
+        let backend = native_backend();
+        let mut weight = [0.9f32, 0.4, -0.2, 1.3, 0.5, 0.1];
+        let regularizer = StructuralRegularizer::Orthogonality { lambda: 1f32 };
+        let mut regularized = RegularizedWeight::new(regularizer, &[3, 2]).unwrap();
+        let deviation = |weight: &[f32]| {
+            // ||W^T W - I||_F of a 3x2 matrix
+            let mut sum = 0f32;
+            for i in 0..2 {
+                for j in 0..2 {
+                    let dot = (0..3).fold(0f32, |dot, row| dot + weight[row * 2 + i] * weight[row * 2 + j]);
+                    let identity = if i == j { 1f32 } else { 0f32 };
+                    sum += (dot - identity).powi(2);
+                }
+            }
+            sum.sqrt()
+        };
+        let initial = deviation(&weight);
+
+        // plain gradient descent on the penalty alone
+        for _ in 0..200 {
+            let mut gradient = shared_tensor(&[3, 2], &[0f32; 6]);
+            regularized.add_gradient(&backend, &shared_tensor(&[3, 2], &weight), &mut gradient, 1f32).unwrap();
+            for (value, gradient) in weight.iter_mut().zip(read_shared(&gradient)) {
+                *value -= 0.02 * gradient;
+            }
+        }
+        assert!(initial > 0.5, "{}", initial);
+        assert!(deviation(&weight) < 1e-3, "{}", deviation(&weight));
+    }
+}
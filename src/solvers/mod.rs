@@ -34,8 +34,25 @@ pub mod sgd;
 use co::{IBackend, SharedTensor};
 use layer::*;
 use solver::*;
+use std::mem;
+use transfer::{self, TransferCategory};
 use util::*;
 
+/// Returns the [LARS][1] ratio that scales the learning rate of a weight blob,
+/// `trust_coefficient * weight_norm / (gradient_norm + weight_decay * weight_norm)`.
+/// [1]: ../solver/struct.SolverConfig.html#structfield.lars_trust_coefficient
+///
+/// Falls back to `1`, the unscaled learning rate, if the norm of the weights or the
+/// denominator is zero.
+pub fn lars_ratio(trust_coefficient: f32, weight_norm: f32, gradient_norm: f32, weight_decay: f32) -> f32 {
+    let denominator = gradient_norm + weight_decay * weight_norm;
+    if weight_norm > 0f32 && denominator > 0f32 {
+        trust_coefficient * weight_norm / denominator
+    } else {
+        1f32
+    }
+}
+
 trait SGDSolver<SolverB: IBackend + SolverOps<f32>, NetB: IBackend + LayerOps<f32>>
     : ISolver<SolverB, NetB> {
     fn compute_update_value(&mut self,
@@ -45,6 +62,24 @@ trait SGDSolver<SolverB: IBackend + SolverOps<f32>, NetB: IBackend + LayerOps<f3
                             global_lr: &f32,
                             blob_lr: &f32);
 
+    /// Regularize the gradient, compute the update value and apply it to the weight blob
+    /// with [SgdUpdate][1], in a single pass on the native backend.
+    /// [1]: ../util/trait.SgdUpdate.html
+    ///
+    /// Leaves the same values in the weight, gradient and history blobs as [regularize][2],
+    /// [compute_update_value][3] and [Layer::update_weights][4].
+    /// [2]: #method.regularize
+    /// [3]: #tymethod.compute_update_value
+    /// [4]: ../layer/struct.Layer.html#method.update_weights
+    fn fused_update_value(&mut self,
+                          config: &SolverConfig,
+                          weight_data: &ArcLock<SharedTensor<f32>>,
+                          weight_gradient: &ArcLock<SharedTensor<f32>>,
+                          history_blob_id: usize,
+                          global_lr: &f32,
+                          blob_lr: &f32,
+                          weight_decay: f32);
+
     /// [Clip gradients][1] when they exceed [SolverConfig.clip_gradients][2].
     /// [1]: http://arxiv.org/abs/1211.5063
     /// [2]: ../solver/struct.SolverConfig.html
@@ -66,15 +101,17 @@ trait SGDSolver<SolverB: IBackend + SolverOps<f32>, NetB: IBackend + LayerOps<f3
             let net_gradients = net.learnable_weights_gradients();
             let mut sumsq_diff = 0f32;
             let backend = self.backend();
-            for net_gradient in net_gradients.clone() {
+            for (name, net_gradient) in net.learnable_weights_names().iter().zip(net_gradients.clone()) {
                 let gradient = net_gradient.read().unwrap();
                 // PERF: preallocate tensor once
                 let mut result = SharedTensor::new(&[1]);
                 // gradient.sumsq_diff(self.backend(), &mut result);
                 self.backend().dot(&gradient, &gradient, &mut result);
 
-                let sumsq_diff_slice = result.read(native.device()).unwrap().as_slice::<f32>();
-                sumsq_diff += sumsq_diff_slice[0];
+                sumsq_diff += transfer::record(TransferCategory::WeightUpdate,
+                                               name,
+                                               mem::size_of::<f32>(),
+                                               || result.read(native.device()).unwrap().as_slice::<f32>()[0]);
             }
             let l2norm_diff = sumsq_diff.sqrt();
             if l2norm_diff > clip_threshold {
@@ -113,35 +150,49 @@ trait SGDSolver<SolverB: IBackend + SolverOps<f32>, NetB: IBackend + LayerOps<f3
         }
     }
 
-    /// [Regularize][1] the gradient according to the configured [RegularizationMethod][2].
+    /// Returns the [LARS ratio][1] of a weight blob, computing the norms of its weights and
+    /// of its gradient with [Nrm2][2] on the solver backend.
+    /// [1]: ./fn.lars_ratio.html
+    /// [2]: https://docs.rs/coaster-blas/*/coaster_blas/plugin/trait.Nrm2.html
+    ///
+    /// Must be called before the gradient is [regularized][3].
+    /// [3]: #method.regularize
+    fn lars_ratio(&self,
+                  trust_coefficient: f32,
+                  name: &str,
+                  weight_data: &ArcLock<SharedTensor<f32>>,
+                  weight_gradient: &ArcLock<SharedTensor<f32>>,
+                  weight_decay: f32)
+                  -> f32 {
+        let native = native_backend();
+        let norm = |blob: &ArcLock<SharedTensor<f32>>| {
+            let mut result = SharedTensor::new(&[1]);
+            self.backend().nrm2(&blob.read().unwrap(), &mut result).unwrap();
+            transfer::record(TransferCategory::WeightUpdate,
+                             name,
+                             mem::size_of::<f32>(),
+                             || result.read(native.device()).unwrap().as_slice::<f32>()[0])
+        };
+        lars_ratio(trust_coefficient, norm(weight_data), norm(weight_gradient), weight_decay)
+    }
+
+    /// [Regularize][1] the gradient with the L2 `weight_decay` of the weight blob.
     /// [1]: https://cs231n.github.io/neural-networks-2/#reg
-    /// [2]: ../solver/enum.RegularizationMethod.html
+    ///
+    /// Adds `weight_decay * weight` to the gradient. The decay of a weight blob is
+    /// determined by [SolverConfig::get_weight_decay][2].
+    /// [2]: ../solver/struct.SolverConfig.html#method.get_weight_decay
     fn regularize(&self,
-                  config: &SolverConfig,
+                  weight_data: &ArcLock<SharedTensor<f32>>,
                   weight_gradient: &ArcLock<SharedTensor<f32>>,
-                  blob_weight_decay: Option<f32>) {
-        if let Some(global_weight_decay) = config.weight_decay {
-            if let Some(regularization_method) = config.regularization_method {
-                match blob_weight_decay {
-                    Some(weight_decay_mult) => {
-                        let local_decay = global_weight_decay * weight_decay_mult;
-                        match regularization_method {
-                            RegularizationMethod::L2 => {
-                                let native = native_backend();
-                                let decay_shared = native_scalar(local_decay);
-                                let gradient = &mut weight_gradient.write().unwrap();
-                                // gradient.regularize_l2(self.backend(), &decay_shared);
-                                // backend.axpy_plain(&decay_shared, &self.data, &mut self.diff).unwrap();
-                                // TODO: solver
-                                unimplemented!();
-                            }
-                        }
-                    }
-                    None => {
-                        error!("Weight decay multiplier for gradient missing.");
-                    }
-                }
-            }
+                  weight_decay: f32) {
+        if weight_decay != 0f32 {
+            let decay_shared = native_scalar(weight_decay);
+            self.backend()
+                .axpy(&decay_shared,
+                      &weight_data.read().unwrap(),
+                      &mut weight_gradient.write().unwrap())
+                .unwrap();
         }
     }
 }
@@ -41,22 +41,71 @@ macro_rules! impl_isolver_sgd {
                 }
             }
 
+            /// Reset the history of the weights to zero.
+            fn reset_history(&mut self, weight_ids: &[usize]) {
+                let filler = ::weight::FillerType::Constant { value: 0f32 };
+                for &weight_id in weight_ids {
+                    filler.fill(&mut self.history[weight_id].write().unwrap());
+                }
+            }
+
             fn compute_update(&mut self, config: &SolverConfig, net: &mut Layer<NetB>, iter: usize) {
                 let rate = config.get_learning_rate(iter);
 
                 SGDSolver::<SolverB, NetB>::clip_gradients(self, config, net);
+                let weights_data = net.learnable_weights_data();
+                let weights_names = net.learnable_weights_names();
+                let weights_lr = net.learnable_weights_lr();
+                let weights_decay_mult = net.learnable_weights_decay_mult();
+                let weights_frozen = net.learnable_weights_frozen();
+                self.lars_ratios.clear();
                 for (weight_id, weight_gradient) in net.learnable_weights_gradients().iter().enumerate() {
                     SGDSolver::<SolverB, NetB>::normalize(self, config, weight_gradient);
-                    // SGDSolver::<SolverB, NetB>::regularize(self, config, weight_gradient, net.weights_weight_decay()[weight_id]);
+                    let weight_decay = config.get_weight_decay(iter, weights_decay_mult[weight_id]);
+                    let mut blob_lr = weights_lr[weight_id].unwrap();
+                    if let Some(trust_coefficient) = config.lars_trust_coefficient {
+                        let ratio = SGDSolver::<SolverB, NetB>::lars_ratio(self,
+                                                  trust_coefficient,
+                                                  &weights_names[weight_id],
+                                                  &weights_data[weight_id],
+                                                  weight_gradient,
+                                                  weight_decay);
+                        debug!("LARS ratio of weight blob {}: {}", weight_id, ratio);
+                        self.lars_ratios.push(ratio);
+                        blob_lr *= ratio;
+                    }
 
-                    SGDSolver::<SolverB, NetB>::compute_update_value(self, config,
-                                              weight_gradient,
-                                              weight_id,
-                                              &rate,
-                                              &net.learnable_weights_lr()[weight_id].unwrap());
+                    // frozen weights only update their history, like on the separate path
+                    if config.fused_update && !weights_frozen[weight_id] {
+                        SGDSolver::<SolverB, NetB>::fused_update_value(self, config,
+                                                  &weights_data[weight_id],
+                                                  weight_gradient,
+                                                  weight_id,
+                                                  &rate,
+                                                  &blob_lr,
+                                                  weight_decay);
+                    } else {
+                        SGDSolver::<SolverB, NetB>::regularize(self, &weights_data[weight_id], weight_gradient, weight_decay);
+                        SGDSolver::<SolverB, NetB>::compute_update_value(self, config,
+                                                  weight_gradient,
+                                                  weight_id,
+                                                  &rate,
+                                                  &blob_lr);
+                    }
+                }
+                if config.fused_update {
+                    net.apply_pruning_masks(&self.backend);
                 }
             }
 
+            fn updates_weights(&self, config: &SolverConfig) -> bool {
+                config.fused_update
+            }
+
+            fn lars_ratios(&self) -> &[f32] {
+                &self.lars_ratios
+            }
+
             fn backend(&self) -> &SolverB {
                 &self.backend
             }
@@ -31,11 +31,8 @@ pub struct Momentum<SolverB: IBackend + SolverOps<f32>> {
     history: Vec<ArcLock<SharedTensor<f32>>>,
     /// The backend used for computing the gradient.
     backend: Rc<SolverB>,
-
-    /// Scalar that temporarily holds learing rate for weight update computations
-    lr: SharedTensor<f32>,
-    /// Scalar that temporarily holds momentum for weight update computations
-    momentum: SharedTensor<f32>,
+    /// The LARS ratio of each blob in the last update, if LARS is enabled.
+    lars_ratios: Vec<f32>,
 }
 
 impl<SolverB: IBackend + SolverOps<f32>> Momentum<SolverB> {
@@ -49,9 +46,7 @@ impl<SolverB: IBackend + SolverOps<f32>> Momentum<SolverB> {
         Momentum {
             history: Vec::new(),
             backend: backend,
-
-            lr: SharedTensor::<f32>::new(&[1]),
-            momentum: SharedTensor::<f32>::new(&[1]),
+            lars_ratios: Vec::new(),
         }
     }
 }
@@ -63,26 +58,37 @@ impl<B: IBackend + SolverOps<f32>, NetB: IBackend + LayerOps<f32> + 'static> SGD
                             history_blob_id: usize,
                             global_lr: &f32,
                             blob_lr: &f32) {
-        // PERF: check if value is changed before writing it
-        ::weight::FillerType::Constant { value: global_lr * blob_lr }.fill(&mut self.lr);
-
-        ::weight::FillerType::Constant { value: config.momentum }.fill(&mut self.momentum);
-
         let backend = ISolver::<B, NetB>::backend(self);
-        let device = IBackend::device(backend);
 
         let history_blob = &self.history[history_blob_id];
-        Axpby::axpby(backend,
-                     &self.lr,
-                     &weight_gradient.read().unwrap(),
-                     &self.momentum,
-                     &mut history_blob.write().unwrap())
+        backend.scaled_axpby(global_lr * blob_lr,
+                             &weight_gradient.read().unwrap(),
+                             config.momentum,
+                             &mut history_blob.write().unwrap())
             .unwrap();
 
         backend.copy(&history_blob.read().unwrap(),
                   &mut weight_gradient.write().unwrap())
             .unwrap();
     }
+
+    fn fused_update_value(&mut self,
+                          config: &SolverConfig,
+                          weight_data: &ArcLock<SharedTensor<f32>>,
+                          weight_gradient: &ArcLock<SharedTensor<f32>>,
+                          history_blob_id: usize,
+                          global_lr: &f32,
+                          blob_lr: &f32,
+                          weight_decay: f32) {
+        let backend = ISolver::<B, NetB>::backend(self);
+        backend.sgd_update(&mut weight_data.write().unwrap(),
+                        &mut weight_gradient.write().unwrap(),
+                        &mut self.history[history_blob_id].write().unwrap(),
+                        global_lr * blob_lr,
+                        config.momentum,
+                        weight_decay)
+            .unwrap();
+    }
 }
 
 impl_isolver_sgd!(Momentum<SolverB>);
@@ -0,0 +1,218 @@
+//! Provides the counting and timing of the synchronizations of tensors between host and
+//! device memory that Juice performs.
+//!
+//! The synchronizations that the [Solver][1] and the layers trigger to read data on the
+//! host or to feed data to the device are recorded while recording is [started][2] on
+//! the current thread. Every record is attributed to the blob that is synchronized and
+//! the [category][3] of the call site. Recording is off by default and costs a single
+//! check of a thread-local flag per call site then.
+//!
+//! The Solver records the transfers of every minibatch if
+//! [SolverConfig.record_transfers][4] is set and exposes them as a [TransferSummary][5]
+//! through [Solver::transfer_summary][6].
+//!
+//! A layer without kernels for its backend computes on the host, see
+//! [ILayer::sync_native][9], and every operation in [util][10] that has no device
+//! implementation reads its operands on the host. While recording, these [host fallbacks][11]
+//! synchronize their blobs explicitly, so they are recorded like the other call sites: the
+//! inputs and output gradients of such a layer are moved to the host and its outputs and
+//! gradients back to the device of its backend. On the native backend they don't copy
+//! anything, but they are counted all the same, so that tests on the native backend see
+//! the transfers a device would make. The synchronizations that happen implicitly inside of
+//! the device operations, e.g. when a layer reads its input on the device, are not recorded.
+//!
+//! The synchronizations of whole devices, which wait for all queued operations, are
+//! counted separately as [device synchronizations][7]. A network synchronizes once per
+//! forward and once per backward step, see [Layer::set_synchronize_passes][8].
+//!
+//! [1]: ../solver/struct.Solver.html
+//! [2]: ./fn.start_recording.html
+//! [3]: ./enum.TransferCategory.html
+//! [4]: ../solver/struct.SolverConfig.html#structfield.record_transfers
+//! [5]: ./struct.TransferSummary.html
+//! [6]: ../solver/struct.Solver.html#method.transfer_summary
+//! [7]: ./struct.TransferSummary.html#structfield.device_synchronizations
+//! [8]: ../layer/struct.Layer.html#method.set_synchronize_passes
+//! [9]: ../layer/trait.ILayer.html#method.sync_native
+//! [10]: ../util/index.html
+//! [11]: ./enum.TransferCategory.html#variant.HostFallback
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The kind of call site that synchronizes a tensor.
+pub enum TransferCategory {
+    /// Feeding the inputs of a minibatch to the device of the network.
+    InputFeed,
+    /// Reading reductions of the weights or gradients while computing the weight update,
+    /// e.g. for gradient clipping.
+    WeightUpdate,
+    /// Reading the loss outputs back to the host.
+    LossReadback,
+    /// Reading values for debugging and monitoring, e.g. the gradient statistics.
+    DebugStats,
+    /// Moving the operands of a layer or an operation that computes on the host between
+    /// the host and the device.
+    HostFallback,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The transfers of one blob within one category.
+pub struct TransferStats {
+    /// The category of the call site.
+    pub category: TransferCategory,
+    /// The name of the synchronized blob.
+    pub blob: String,
+    /// The number of synchronizations.
+    pub count: usize,
+    /// The number of bytes of the blob, summed over its synchronizations.
+    pub bytes: usize,
+    /// The time the synchronizations took in total.
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The transfers recorded between [start_recording][1] and [finish_recording][2].
+/// [1]: ./fn.start_recording.html
+/// [2]: ./fn.finish_recording.html
+pub struct TransferSummary {
+    /// The transfers per blob and category, in the order of their first synchronization.
+    pub stats: Vec<TransferStats>,
+    /// The number of synchronizations of whole devices.
+    pub device_synchronizations: usize,
+}
+
+impl TransferSummary {
+    /// Returns the number of synchronizations.
+    pub fn count(&self) -> usize {
+        self.stats.iter().fold(0, |count, stats| count + stats.count)
+    }
+
+    /// Returns the number of synchronizations of the category `category`.
+    pub fn category_count(&self, category: TransferCategory) -> usize {
+        self.stats
+            .iter()
+            .filter(|stats| stats.category == category)
+            .fold(0, |count, stats| count + stats.count)
+    }
+
+    /// Returns the time all synchronizations took.
+    pub fn time(&self) -> Duration {
+        self.stats.iter().fold(Duration::from_secs(0), |time, stats| time + stats.time)
+    }
+
+    fn add(&mut self, category: TransferCategory, blob: &str, bytes: usize, time: Duration) {
+        if let Some(stats) = self.stats.iter_mut().find(|stats| stats.category == category && stats.blob == blob) {
+            stats.count += 1;
+            stats.bytes += bytes;
+            stats.time += time;
+            return;
+        }
+        self.stats.push(TransferStats {
+            category: category,
+            blob: blob.to_owned(),
+            count: 1,
+            bytes: bytes,
+            time: time,
+        });
+    }
+}
+
+impl fmt::Display for TransferSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "{} transfers in {:.5} ms, {} device synchronizations",
+                    self.count(),
+                    millis(self.time()),
+                    self.device_synchronizations));
+        for stats in &self.stats {
+            try!(write!(f,
+                        "\n  {:?} {}: {} transfers of {} bytes in {:.5} ms",
+                        stats.category,
+                        stats.blob,
+                        stats.count,
+                        stats.bytes,
+                        millis(stats.time)));
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000f64 + duration.subsec_nanos() as f64 / 1_000_000f64
+}
+
+thread_local! {
+    static RECORDING: RefCell<Option<TransferSummary>> = RefCell::new(None);
+}
+
+/// Start recording the transfers on the current thread, discarding the ones recorded so far.
+pub fn start_recording() {
+    RECORDING.with(|recording| *recording.borrow_mut() = Some(TransferSummary::default()));
+}
+
+/// Stop recording the transfers on the current thread and return the recorded ones.
+///
+/// Returns `None` if no recording has been started.
+pub fn finish_recording() -> Option<TransferSummary> {
+    RECORDING.with(|recording| recording.borrow_mut().take())
+}
+
+/// Returns whether the transfers are recorded on the current thread.
+pub fn is_recording() -> bool {
+    RECORDING.with(|recording| recording.borrow().is_some())
+}
+
+/// Runs `sync`, which synchronizes the blob `blob` of `bytes` bytes, and records it as a
+/// transfer of the category `category` if recording has been started.
+pub fn record<T, F: FnOnce() -> T>(category: TransferCategory, blob: &str, bytes: usize, sync: F) -> T {
+    if !is_recording() {
+        return sync();
+    }
+    let start = Instant::now();
+    let result = sync();
+    let time = start.elapsed();
+    RECORDING.with(|recording| {
+        if let Some(ref mut summary) = *recording.borrow_mut() {
+            summary.add(category, blob, bytes, time);
+        }
+    });
+    result
+}
+
+/// Runs `sync`, which synchronizes a whole device, and counts it as a device
+/// synchronization if recording has been started.
+pub fn record_device_synchronization<T, F: FnOnce() -> T>(sync: F) -> T {
+    RECORDING.with(|recording| {
+        if let Some(ref mut summary) = *recording.borrow_mut() {
+            summary.device_synchronizations += 1;
+        }
+    });
+    sync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_while_recording() {
+        assert_eq!(1, record(TransferCategory::LossReadback, "loss", 4, || 1));
+        assert!(finish_recording().is_none());
+
+        start_recording();
+        record(TransferCategory::LossReadback, "loss", 4, || ());
+        record(TransferCategory::InputFeed, "data", 32, || ());
+        record(TransferCategory::LossReadback, "loss", 4, || ());
+        let summary = finish_recording().unwrap();
+        assert!(!is_recording());
+
+        assert_eq!(3, summary.count());
+        assert_eq!(2, summary.category_count(TransferCategory::LossReadback));
+        assert_eq!(0, summary.category_count(TransferCategory::DebugStats));
+        assert_eq!(("loss", 2, 8),
+                   (&summary.stats[0].blob[..], summary.stats[0].count, summary.stats[0].bytes));
+    }
+}
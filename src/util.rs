@@ -2,10 +2,22 @@
 
 use co::prelude::*;
 use co::frameworks::native::flatbox::FlatBox;
-use coblas::plugin::*;
+use coblas::plugin::{Asum, Axpy, Dot, Gemm, Nrm2, Scal};
+use coblas::transpose::Transpose;
 use conn;
 use num::traits::{NumCast, cast};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
 use std::sync::{Arc, RwLock};
+use weight::FillerType;
+
+/// The BLAS copy operation `y := x`.
+///
+/// Re-exported under this name, as importing it by its original name `Copy` shadows
+/// `std::marker::Copy` and breaks `Copy` bounds and derives in the importing module.
+pub use coblas::plugin::Copy as BlasCopy;
 
 /// Shared Lock used for our tensors
 pub type ArcLock<T> = Arc<RwLock<T>>;
@@ -21,12 +33,12 @@ pub fn native_backend() -> Backend<Native> {
 }
 
 /// Write into a native Coaster Memory.
-pub fn write_to_memory<T: NumCast + ::std::marker::Copy>(mem: &mut FlatBox, data: &[T]) {
+pub fn write_to_memory<T: NumCast + Copy>(mem: &mut FlatBox, data: &[T]) {
     write_to_memory_offset(mem, data, 0);
 }
 
 /// Write into a native Coaster Memory with a offset.
-pub fn write_to_memory_offset<T: NumCast + ::std::marker::Copy>(mem: &mut FlatBox, data: &[T], offset: usize) {
+pub fn write_to_memory_offset<T: NumCast + Copy>(mem: &mut FlatBox, data: &[T], offset: usize) {
     let mut mem_buffer = mem.as_mut_slice::<f32>();
     for (index, datum) in data.iter().enumerate() {
         // mem_buffer[index + offset] = *datum;
@@ -41,7 +53,7 @@ pub fn write_to_memory_offset<T: NumCast + ::std::marker::Copy>(mem: &mut FlatBo
 /// is asumed to be the batchsize.
 ///
 /// Allocates memory on a Native Backend if neccessary.
-pub fn write_batch_sample<T: NumCast + ::std::marker::Copy>(tensor: &mut SharedTensor<f32>, data: &[T], i: usize) {
+pub fn write_batch_sample<T: NumCast + Copy>(tensor: &mut SharedTensor<f32>, data: &[T], i: usize) {
     let native_backend = native_backend();
 
     let batch_size = tensor.desc().size();
@@ -53,7 +65,7 @@ pub fn write_batch_sample<T: NumCast + ::std::marker::Copy>(tensor: &mut SharedT
 }
 
 /// Create a Coaster SharedTensor for a scalar value.
-pub fn native_scalar<T: NumCast + ::std::marker::Copy>(scalar: T) -> SharedTensor<T> {
+pub fn native_scalar<T: NumCast + Copy>(scalar: T) -> SharedTensor<T> {
     let native = native_backend();
     let mut shared_scalar = SharedTensor::<T>::new(&[1]);
     write_to_memory(shared_scalar.write_only(native.device()).unwrap(),
@@ -61,6 +73,360 @@ pub fn native_scalar<T: NumCast + ::std::marker::Copy>(scalar: T) -> SharedTenso
     shared_scalar
 }
 
+/// The number of bytes that the [constant tensors][1] of a thread may take up before the least
+/// recently used ones are dropped from the cache.
+/// [1]: ./fn.ones.html
+pub const CONSTANT_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// The number of [constant tensors][1] that a thread caches at most, so that many small
+/// constants, e.g. the [scalars][2] of a decaying learning rate, don't make looking up the
+/// least recently used one slow.
+/// [1]: ./fn.ones.html
+/// [2]: ./fn.cached_scalar.html
+pub const CONSTANT_CACHE_ENTRIES: usize = 1024;
+
+/// The constant tensors that were requested through [ones][1] and the other constants of this
+/// module, by their kind and parameters, with the time of their last request.
+/// [1]: ./fn.ones.html
+struct ConstantCache {
+    tensors: HashMap<(&'static str, Vec<usize>), (Rc<SharedTensor<f32>>, u64)>,
+    bytes: usize,
+    budget: usize,
+    max_entries: usize,
+    clock: u64,
+}
+
+impl ConstantCache {
+    fn new(budget: usize, max_entries: usize) -> ConstantCache {
+        ConstantCache {
+            tensors: HashMap::new(),
+            bytes: 0,
+            budget: budget,
+            max_entries: max_entries,
+            clock: 0,
+        }
+    }
+
+    /// Returns the cached tensor of `kind` and `key`, or creates it with `create` and caches
+    /// it unless it alone exceeds the budget.
+    fn get<F>(&mut self, kind: &'static str, key: &[usize], create: F) -> Rc<SharedTensor<f32>>
+        where F: FnOnce() -> SharedTensor<f32>
+    {
+        self.clock += 1;
+        let key = (kind, key.to_vec());
+        if let Some(&mut (ref tensor, ref mut last_request)) = self.tensors.get_mut(&key) {
+            *last_request = self.clock;
+            return tensor.clone();
+        }
+
+        let tensor = Rc::new(create());
+        let bytes = tensor_bytes(&tensor);
+        if bytes <= self.budget && self.max_entries > 0 {
+            let (budget, max_entries) = (self.budget - bytes, self.max_entries - 1);
+            self.evict(budget, max_entries);
+            self.bytes += bytes;
+            self.tensors.insert(key, (tensor.clone(), self.clock));
+        }
+        tensor
+    }
+
+    /// Drops the least recently requested tensors until the others take up at most `budget`
+    /// bytes and there are at most `max_entries` of them. Tensors that are still in use stay
+    /// valid, they are only no longer shared.
+    fn evict(&mut self, budget: usize, max_entries: usize) {
+        while self.bytes > budget || self.tensors.len() > max_entries {
+            let oldest = self.tensors
+                .iter()
+                .min_by_key(|&(_, &(_, last_request))| last_request)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => {
+                    let (tensor, _) = self.tensors.remove(&key).unwrap();
+                    self.bytes -= tensor_bytes(&tensor);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.tensors.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Returns the number of bytes of the f32 values of `tensor`.
+fn tensor_bytes(tensor: &SharedTensor<f32>) -> usize {
+    tensor.desc().size() * ::std::mem::size_of::<f32>()
+}
+
+thread_local! {
+    static CONSTANTS: RefCell<ConstantCache> =
+        RefCell::new(ConstantCache::new(CONSTANT_CACHE_BYTES, CONSTANT_CACHE_ENTRIES));
+}
+
+/// Returns the constant tensor of `shape` that is identified by `kind` and `key`, which is
+/// filled by `init` on the host unless it is still cached on this thread.
+fn constant_tensor<F>(kind: &'static str, key: &[usize], shape: &[usize], init: F) -> Rc<SharedTensor<f32>>
+    where F: FnOnce(&mut [f32])
+{
+    CONSTANTS.with(|constants| {
+        constants.borrow_mut().get(kind, key, || {
+            let native = native_backend();
+            let mut tensor = SharedTensor::<f32>::new(&shape);
+            init(tensor.write_only(native.device()).unwrap().as_mut_slice::<f32>());
+            tensor
+        })
+    })
+}
+
+/// Drops the constant tensors that are cached on this thread, e.g. to release their device
+/// memory after training with a batch size that won't be used again.
+///
+/// The tensors that are still in use stay valid, and the constants are created again when
+/// they are requested the next time.
+pub fn clear_constants() {
+    CONSTANTS.with(|constants| constants.borrow_mut().clear());
+}
+
+/// Returns a tensor of `shape` whose elements are all `1`.
+///
+/// The tensors are cached per thread up to [CONSTANT_CACHE_BYTES][1], so a backend copies
+/// the ones to its device only the first time it reads a shape. Backends without a
+/// dedicated kernel use them to express reductions and broadcasts with [gemm][2].
+/// [1]: ./constant.CONSTANT_CACHE_BYTES.html
+/// [2]: ../../coaster_blas/plugin/trait.Gemm.html
+pub fn ones(shape: &[usize]) -> Rc<SharedTensor<f32>> {
+    constant_tensor("ones", shape, shape, |values| {
+        for value in values.iter_mut() {
+            *value = 1f32;
+        }
+    })
+}
+
+/// Returns the scalar tensor of shape `[1]` that holds `value`.
+///
+/// Unlike [native_scalar][1] the scalars are cached like [ones][2], so a backend that takes
+/// the scalars of its BLAS calls as tensors copies a value to its device only the first time
+/// it is used.
+/// [1]: ./fn.native_scalar.html
+/// [2]: ./fn.ones.html
+pub fn cached_scalar(value: f32) -> Rc<SharedTensor<f32>> {
+    constant_tensor("scalar", &[value.to_bits() as usize], &[1], |values| values[0] = value)
+}
+
+/// Returns the matrix of shape `[columns, len]` that is `1` at `(offset + j, j)` and `0`
+/// elsewhere.
+///
+/// The matrices are cached like [ones][1]. Multiplied from the right with [gemm][2] it
+/// selects the columns `offset..offset + len` of a matrix with `columns` columns, and its
+/// transpose scatters a block of `len` columns into one.
+/// [1]: ./fn.ones.html
+/// [2]: ../../coaster_blas/plugin/trait.Gemm.html
+pub fn column_block(columns: usize, offset: usize, len: usize) -> Rc<SharedTensor<f32>> {
+    assert!(offset + len <= columns,
+            "Columns {}..{} out of range for {} columns",
+            offset,
+            offset + len,
+            columns);
+    constant_tensor("column_block", &[columns, offset, len], &[columns, len], |values| {
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = if i / len == offset + i % len { 1f32 } else { 0f32 };
+        }
+    })
+}
+
+/// Returns the identity matrix of shape `[len, len]`.
+///
+/// The matrices are cached like [ones][1].
+/// [1]: ./fn.ones.html
+pub fn identity(len: usize) -> Rc<SharedTensor<f32>> {
+    constant_tensor("identity", &[len], &[len, len], |values| {
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = if i / len == i % len { 1f32 } else { 0f32 };
+        }
+    })
+}
+
+/// Scales `vector` to unit length on the host and returns its previous length.
+///
+/// A vector of length zero is left unchanged.
+pub fn normalize_on_host(vector: &mut SharedTensor<f32>) -> f32 {
+    let native = native_backend();
+    let values = vector.read_write(native.device()).unwrap().as_mut_slice::<f32>();
+    let norm = values.iter().fold(0f32, |sum, value| sum + value * value).sqrt();
+    if norm > 0f32 {
+        for value in values.iter_mut() {
+            *value /= norm;
+        }
+    }
+    norm
+}
+
+/// Returns the left and right singular vectors of shape `[rows, 1]` and `[columns, 1]` that
+/// start a [power_iteration][1] on a matrix with `rows` rows and `columns` columns.
+/// [1]: ./fn.power_iteration.html
+///
+/// The left vector is filled with a seeded Gaussian filler, so the iteration is reproducible.
+/// The right vector is computed by the first step.
+pub fn singular_vectors(rows: usize, columns: usize) -> (SharedTensor<f32>, SharedTensor<f32>) {
+    let mut u = SharedTensor::new(&[rows, 1]);
+    FillerType::Gaussian {
+            mean: 0f32,
+            std: 1f32,
+            seed: Some(0),
+        }
+        .fill(&mut u);
+    (u, SharedTensor::new(&[columns, 1]))
+}
+
+/// Advances the power iteration that estimates the spectral norm of `weight` by `iters` steps
+/// and returns the estimate `|W v|`.
+///
+/// The weight is viewed as a matrix `W` with one row per index of its first dimension, and
+/// `u` and `v` are its left and right [singular vectors][1]. The matrix-vector products run
+/// on `backend`, as gemm with single-column matrices.
+/// [1]: ./fn.singular_vectors.html
+pub fn power_iteration<B: IBackend + Gemm<f32>>(backend: &B,
+                                                weight: &SharedTensor<f32>,
+                                                u: &mut SharedTensor<f32>,
+                                                v: &mut SharedTensor<f32>,
+                                                iters: usize)
+                                                -> Result<f32, ::co::error::Error> {
+    normalize_on_host(u);
+    let one = native_scalar(1f32);
+    let zero = native_scalar(0f32);
+    let mut sigma = 0f32;
+    for _ in 0..iters {
+        // v = W^T u / |W^T u|, u = W v / |W v|, where |W v| converges to the spectral norm
+        try!(backend.gemm(&one, Transpose::Trans, weight, Transpose::NoTrans, u, &zero, v));
+        normalize_on_host(v);
+        try!(backend.gemm(&one, Transpose::NoTrans, weight, Transpose::NoTrans, v, &zero, u));
+        sigma = normalize_on_host(u);
+    }
+    Ok(sigma)
+}
+
+/// Returns `uᵀ W v`, the estimate of the spectral norm of `weight` by the singular vectors
+/// `u` and `v` of a [power_iteration][1], without advancing it.
+/// [1]: ./fn.power_iteration.html
+pub fn singular_value<B: IBackend + Gemm<f32>>(backend: &B,
+                                               weight: &SharedTensor<f32>,
+                                               u: &SharedTensor<f32>,
+                                               v: &SharedTensor<f32>)
+                                               -> Result<f32, ::co::error::Error> {
+    let one = native_scalar(1f32);
+    let zero = native_scalar(0f32);
+    let mut weighted = SharedTensor::new(u.desc());
+    try!(backend.gemm(&one, Transpose::NoTrans, weight, Transpose::NoTrans, v, &zero, &mut weighted));
+    let mut sigma = SharedTensor::new(&[1, 1]);
+    try!(backend.gemm(&one, Transpose::Trans, u, Transpose::NoTrans, &weighted, &zero, &mut sigma));
+    read_scalar(&sigma)
+}
+
+/// Returns the dot product of `x` and `y` of the same size, computed with gemm for backends
+/// that are only known to implement [Gemm][1].
+/// [1]: ../../coaster_blas/plugin/trait.Gemm.html
+///
+/// `x` and `y` are viewed as a row and a column vector and get their shapes back afterwards.
+pub fn gemm_dot<B: IBackend + Gemm<f32>>(backend: &B,
+                                         x: &mut SharedTensor<f32>,
+                                         y: &mut SharedTensor<f32>)
+                                         -> Result<f32, ::co::error::Error> {
+    let (x_shape, y_shape) = (x.desc().clone(), y.desc().clone());
+    try!(x.reshape(&[1, x_shape.size()]));
+    try!(y.reshape(&[y_shape.size(), 1]));
+    let mut dot = SharedTensor::new(&[1, 1]);
+    let result = backend.gemm(&native_scalar(1f32),
+                              Transpose::NoTrans,
+                              x,
+                              Transpose::NoTrans,
+                              y,
+                              &native_scalar(0f32),
+                              &mut dot);
+    try!(x.reshape(&x_shape));
+    try!(y.reshape(&y_shape));
+    try!(result);
+    read_scalar(&dot)
+}
+
+/// Computes the dot product of every row of `x` with the same row of `y`, two matrices of
+/// shape `[N, D]`, into `result` of shape `[N]`.
+///
+/// coaster has no element-wise product, so the dot products are taken from the diagonal of
+/// `x yᵀ`: it is selected with the [identity][1] as a relu mask and summed with [ones][2].
+/// This takes `N² D` multiplications and a temporary `[N, N]` matrix.
+/// [1]: ./fn.identity.html
+/// [2]: ./fn.ones.html
+pub fn row_dots<B: IBackend + Gemm<f32> + conn::Relu<f32>>(backend: &B,
+                                                           x: &SharedTensor<f32>,
+                                                           y: &SharedTensor<f32>,
+                                                           result: &mut SharedTensor<f32>)
+                                                           -> Result<(), ::co::error::Error> {
+    let rows = x.desc()[0];
+    let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+    let mut products = SharedTensor::new(&[rows, rows]);
+    try!(backend.gemm(&one, Transpose::NoTrans, x, Transpose::Trans, y, &zero, &mut products));
+    let mut diagonal = SharedTensor::new(&[rows, rows]);
+    let mask = identity(rows);
+    try!(conn::Relu::relu_grad(backend, &*mask, &products, &*mask, &mut diagonal));
+    try!(result.resize(&[rows]));
+    backend.gemm(&one,
+                 Transpose::NoTrans,
+                 &diagonal,
+                 Transpose::NoTrans,
+                 &ones(&[rows, 1]),
+                 &zero,
+                 result)
+}
+
+/// Returns the diagonal matrix of shape `[N, N]` with the `values`, of shape `[N]`, on its
+/// diagonal.
+///
+/// The values are broadcast over the columns by their outer product with [ones][1] and
+/// masked with the [identity][2] like in [row_dots][3].
+/// [1]: ./fn.ones.html
+/// [2]: ./fn.identity.html
+/// [3]: ./fn.row_dots.html
+pub fn diagonal_matrix<B: IBackend + Gemm<f32> + conn::Relu<f32>>(backend: &B,
+                                                                  values: &SharedTensor<f32>)
+                                                                  -> Result<SharedTensor<f32>, ::co::error::Error> {
+    let len = values.desc().size();
+    let mut broadcast = SharedTensor::new(&[len, len]);
+    try!(backend.gemm(&native_scalar(1f32),
+                      Transpose::NoTrans,
+                      values,
+                      Transpose::NoTrans,
+                      &ones(&[1, len]),
+                      &native_scalar(0f32),
+                      &mut broadcast));
+    let mut diagonal = SharedTensor::new(&[len, len]);
+    let mask = identity(len);
+    try!(conn::Relu::relu_grad(backend, &*mask, &broadcast, &*mask, &mut diagonal));
+    Ok(diagonal)
+}
+
+/// Computes `result := diag(scales) x + beta result`, which scales every row of the `[N, D]`
+/// matrix `x` by the value of `scales`, of shape `[N]`, for the row.
+///
+/// The [diagonal matrix][1] of the scales takes a temporary `[N, N]` matrix.
+/// [1]: ./fn.diagonal_matrix.html
+pub fn scale_rows<B: IBackend + Gemm<f32> + conn::Relu<f32>>(backend: &B,
+                                                             scales: &SharedTensor<f32>,
+                                                             x: &SharedTensor<f32>,
+                                                             beta: f32,
+                                                             result: &mut SharedTensor<f32>)
+                                                             -> Result<(), ::co::error::Error> {
+    let diagonal = try!(diagonal_matrix(backend, scales));
+    backend.gemm(&native_scalar(1f32),
+                 Transpose::NoTrans,
+                 &diagonal,
+                 Transpose::NoTrans,
+                 x,
+                 &native_scalar(beta),
+                 result)
+}
+
 /// Casts a Vec<usize> to as Vec<i32>
 pub fn cast_vec_usize_to_i32(input: Vec<usize>) -> Vec<i32> {
     let mut out = Vec::new();
@@ -70,6 +436,138 @@ pub fn cast_vec_usize_to_i32(input: Vec<usize>) -> Vec<i32> {
     out
 }
 
+/// Pad sequences of different lengths into a single batch.
+///
+/// Returns the batch of shape `[num_sequences, max_length]`, where shorter sequences
+/// are filled up with `pad_value`, and a mask of the same shape that is `1` for
+/// the elements of the sequences and `0` for the padding.
+///
+/// Fails if there are no sequences or one of them is empty.
+pub fn pad_sequences(sequences: &[Vec<f32>], pad_value: f32) -> Result<(SharedTensor<f32>, SharedTensor<f32>), String> {
+    if sequences.is_empty() {
+        return Err("No sequences to pad".to_owned());
+    }
+    if let Some(empty) = sequences.iter().position(|sequence| sequence.is_empty()) {
+        return Err(format!("Sequence {} is empty and can not be padded", empty));
+    }
+    let max_length = sequences.iter().map(|sequence| sequence.len()).max().unwrap();
+
+    let mut padded = vec![pad_value; sequences.len() * max_length];
+    let mut mask = vec![0f32; sequences.len() * max_length];
+    for (i, sequence) in sequences.iter().enumerate() {
+        let offset = i * max_length;
+        padded[offset..offset + sequence.len()].copy_from_slice(sequence);
+        for mask_value in &mut mask[offset..offset + sequence.len()] {
+            *mask_value = 1f32;
+        }
+    }
+
+    let native = native_backend();
+    let mut padded_tensor = SharedTensor::new(&[sequences.len(), max_length]);
+    let mut mask_tensor = SharedTensor::new(&[sequences.len(), max_length]);
+    write_to_memory(padded_tensor.write_only(native.device()).unwrap(), &padded);
+    write_to_memory(mask_tensor.write_only(native.device()).unwrap(), &mask);
+    Ok((padded_tensor, mask_tensor))
+}
+
+/// Pad sequences into a batch that is sorted by descending sequence length.
+///
+/// Works like [pad_sequences][1], but additionally returns the order of the rows:
+/// the `i`th row of the batch holds the sequence `order[i]`.
+/// Pass it to [restore_order][2] to bring outputs computed on the sorted batch
+/// back into the original order.
+///
+/// [1]: ./fn.pad_sequences.html
+/// [2]: ./fn.restore_order.html
+pub fn pad_sorted_sequences(sequences: &[Vec<f32>], pad_value: f32) -> Result<(SharedTensor<f32>, SharedTensor<f32>, Vec<usize>), String> {
+    let mut order = (0..sequences.len()).collect::<Vec<_>>();
+    // stable, so sequences of equal length keep their relative order
+    order.sort_by(|&a, &b| sequences[b].len().cmp(&sequences[a].len()));
+    let sorted = order.iter().map(|&i| sequences[i].clone()).collect::<Vec<_>>();
+
+    let (padded, mask) = try!(pad_sequences(&sorted, pad_value));
+    Ok((padded, mask, order))
+}
+
+/// Restore the original order of the rows of a batch sorted by [pad_sorted_sequences][1].
+///
+/// [1]: ./fn.pad_sorted_sequences.html
+pub fn restore_order(batch: &SharedTensor<f32>, order: &[usize]) -> Result<SharedTensor<f32>, String> {
+    if order.is_empty() || batch.desc().is_empty() || batch.desc()[0] != order.len() {
+        return Err(format!("Batch of shape {:?} does not match an order of {} sequences",
+                           batch.desc(),
+                           order.len()));
+    }
+    let row_size = batch.desc().size() / order.len();
+
+    let native = native_backend();
+    let sorted = batch.read(native.device()).unwrap().as_slice::<f32>();
+    let mut restored = vec![0f32; sorted.len()];
+    for (sorted_row, &original_row) in order.iter().enumerate() {
+        restored[original_row * row_size..(original_row + 1) * row_size]
+            .copy_from_slice(&sorted[sorted_row * row_size..(sorted_row + 1) * row_size]);
+    }
+
+    let mut restored_tensor = SharedTensor::new(batch.desc());
+    write_to_memory(restored_tensor.write_only(native.device()).unwrap(), &restored);
+    Ok(restored_tensor)
+}
+
+/// Returns the samples `range` of a batch of shape `[N, ...]` as a tensor of shape
+/// `[range.len(), ...]`, e.g. to mine, bucket or shard a batch.
+///
+/// SharedTensors can not share their memory yet, so the samples are copied instead of
+/// viewed and writes to the slice only reach the batch with [write_batch_slice][1].
+/// Once views are supported the slice will share the memory of the batch, so callers
+/// that write back their changes keep working unchanged.
+/// [1]: ./fn.write_batch_slice.html
+///
+/// The samples have to be contiguous, which is the case for the NCHW layout.
+pub fn batch_slice(tensor: &ArcLock<SharedTensor<f32>>, range: Range<usize>) -> ArcLock<SharedTensor<f32>> {
+    let tensor = tensor.read().unwrap();
+    let sample_size = batch_sample_size(tensor.desc(), &range);
+    let mut shape = tensor.desc().clone();
+    shape[0] = range.len();
+
+    let native = native_backend();
+    let data = tensor.read(native.device()).unwrap().as_slice::<f32>();
+    let mut slice = SharedTensor::new(&shape);
+    write_to_memory(slice.write_only(native.device()).unwrap(),
+                    &data[range.start * sample_size..range.end * sample_size]);
+    Arc::new(RwLock::new(slice))
+}
+
+/// Writes the samples of `slice` back into the samples `range` of the batch `tensor`
+/// they have been sliced from with [batch_slice][1].
+/// [1]: ./fn.batch_slice.html
+///
+/// The other samples of the batch are left unchanged.
+pub fn write_batch_slice(tensor: &ArcLock<SharedTensor<f32>>, range: Range<usize>, slice: &ArcLock<SharedTensor<f32>>) {
+    let mut tensor = tensor.write().unwrap();
+    let sample_size = batch_sample_size(tensor.desc(), &range);
+    let slice = slice.read().unwrap();
+    assert_eq!(range.len() * sample_size,
+               slice.desc().size(),
+               "The slice of shape {:?} does not hold the samples {:?}",
+               slice.desc(),
+               range);
+
+    let native = native_backend();
+    let samples = slice.read(native.device()).unwrap().as_slice::<f32>();
+    let data = tensor.read_write(native.device()).unwrap().as_mut_slice::<f32>();
+    data[range.start * sample_size..range.end * sample_size].copy_from_slice(samples);
+}
+
+/// Returns the size of a sample of a batch of shape `shape`, after checking that the
+/// batch contains the samples `range`.
+fn batch_sample_size(shape: &[usize], range: &Range<usize>) -> usize {
+    assert!(!shape.is_empty() && range.start <= range.end && range.end <= shape[0],
+            "The samples {:?} are out of range for a batch of shape {:?}",
+            range,
+            shape);
+    shape.size() / shape[0]
+}
+
 /// Extends IBlas with Axpby
 pub trait Axpby<F>: Axpy<F> + Scal<F> {
     /// Performs the operation y := a*x + b*y .
@@ -89,27 +587,2397 @@ pub trait Axpby<F>: Axpy<F> + Scal<F> {
 
 impl<T: Axpy<f32> + Scal<f32>> Axpby<f32> for T {}
 
-/// Encapsulates all traits required by Solvers.
-// pub trait SolverOps<F> : Axpby<F> + Dot<F> + Copy<F> {}
-//
-// impl<T: Axpby<f32> + Dot<f32> + Copy<f32>> SolverOps<f32> for T {}
-pub trait SolverOps<F>: LayerOps<F> + Axpby<F> + Dot<F> + Copy<F> {}
+/// Computes the softmax cross-entropy loss and its gradient directly from logits.
+///
+/// This is the numerically stable primitive underlying a softmax + negative log likelihood
+/// loss: the softmax is computed after subtracting the maximum logit of each sample, and the
+/// gradient with respect to the logits is `softmax(logits) - targets`.
+///
+/// The logits are expected in the shape `[batch_size, num_classes]` (or `[num_classes]`).
+/// The returned loss is averaged over the batch, the gradient is not.
+///
+/// Targets whose size differs from the logits and labels that are not the index of a class
+/// are an error. The CUDA backend computes the loss and gradient on the device with the
+/// [LogSoftmax][1] and [Softmax][2] of cuDNN; only the one-hot targets of labels are built
+/// on the host.
+/// [1]: ../../coaster_nn/plugin/trait.LogSoftmax.html
+/// [2]: ../../coaster_nn/plugin/trait.Softmax.html
+pub trait SoftmaxCrossEntropy<F> {
+    /// Computes loss and gradient for one-hot (or soft) `targets` of the same shape as `logits`.
+    fn softmax_cross_entropy(&self,
+                             logits: &SharedTensor<F>,
+                             targets: &SharedTensor<F>,
+                             gradient: &mut SharedTensor<F>)
+                             -> Result<F, ::co::error::Error>;
 
-impl<T: LayerOps<f32> + Axpby<f32> + Dot<f32> + Copy<f32>> SolverOps<f32> for T {}
+    /// Computes loss and gradient for `labels` that contain one class index per sample.
+    fn softmax_cross_entropy_index(&self,
+                                   logits: &SharedTensor<F>,
+                                   labels: &SharedTensor<F>,
+                                   gradient: &mut SharedTensor<F>)
+                                   -> Result<F, ::co::error::Error>;
+}
 
-/// Encapsulates all traits used in Layers.
-pub trait LayerOps<F> : conn::Convolution<F>
-                      + conn::Pooling<F>
-                      + conn::Relu<F> + conn::ReluPointwise<F>
-                      + conn::Sigmoid<F> + conn::SigmoidPointwise<F>
-                      + conn::Tanh<F> + conn::TanhPointwise<F>
-                      + conn::Softmax<F> + conn::LogSoftmax<F>
-                      + Gemm<F> {}
+/// Returns the number of samples and classes of `logits`.
+fn softmax_layout(logits: &SharedTensor<f32>) -> (usize, usize) {
+    let num_classes = *logits.desc().last().unwrap_or(&1);
+    (logits.desc().size() / num_classes, num_classes)
+}
 
-impl<T: conn::Convolution<f32>
-      + conn::Pooling<f32>
-      + conn::Relu<f32> + conn::ReluPointwise<f32>
-      + conn::Sigmoid<f32> + conn::SigmoidPointwise<f32>
-      + conn::Tanh<f32> + conn::TanhPointwise<f32>
-      + conn::Softmax<f32> + conn::LogSoftmax<f32>
-      + Gemm<f32>> LayerOps<f32> for T {}
+/// Returns an error unless `targets` has one target per logit.
+fn check_softmax_targets(logits: &SharedTensor<f32>, targets: &SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+    if logits.desc().size() != targets.desc().size() {
+        return Err(::co::error::Error::Plugin(
+            ::co::plugin::Error::Operation("The targets of a softmax cross-entropy don't match its logits")));
+    }
+    Ok(())
+}
+
+/// Returns the one-hot targets of `labels`, one class index per sample of `logits`.
+///
+/// Returns an error if the number of labels differs from the number of samples or if a
+/// label is not the index of a class.
+fn one_hot_targets(logits: &SharedTensor<f32>, labels: &SharedTensor<f32>) -> Result<Vec<f32>, ::co::error::Error> {
+    let (batch_size, num_classes) = softmax_layout(logits);
+    let native = native_backend();
+    let labels = try!(read_on_host(labels, "labels", &native));
+    if labels.len() != batch_size {
+        return Err(::co::error::Error::Plugin(
+            ::co::plugin::Error::Operation("A softmax cross-entropy needs one label per sample")));
+    }
+
+    let mut targets = vec![0f32; logits.desc().size()];
+    for (sample, &label) in labels.iter().enumerate() {
+        // a label outside of the classes would mark a class of another sample
+        if !(label >= 0f32 && label < num_classes as f32 && label.fract() == 0f32) {
+            return Err(::co::error::Error::Plugin(
+                ::co::plugin::Error::Operation("The label of a softmax cross-entropy is not a class index")));
+        }
+        targets[sample * num_classes + label as usize] = 1f32;
+    }
+    Ok(targets)
+}
+
+impl SoftmaxCrossEntropy<f32> for Backend<Native> {
+    fn softmax_cross_entropy(&self,
+                             logits: &SharedTensor<f32>,
+                             targets: &SharedTensor<f32>,
+                             gradient: &mut SharedTensor<f32>)
+                             -> Result<f32, ::co::error::Error> {
+        try!(check_softmax_targets(logits, targets));
+        let targets = try!(targets.read(self.device())).as_slice::<f32>();
+        native_softmax_cross_entropy(self, logits, targets, gradient)
+    }
+
+    fn softmax_cross_entropy_index(&self,
+                                   logits: &SharedTensor<f32>,
+                                   labels: &SharedTensor<f32>,
+                                   gradient: &mut SharedTensor<f32>)
+                                   -> Result<f32, ::co::error::Error> {
+        let targets = try!(one_hot_targets(logits, labels));
+        native_softmax_cross_entropy(self, logits, &targets, gradient)
+    }
+}
+
+fn native_softmax_cross_entropy(native: &Backend<Native>,
+                                logits: &SharedTensor<f32>,
+                                targets: &[f32],
+                                gradient: &mut SharedTensor<f32>)
+                                -> Result<f32, ::co::error::Error> {
+    let (batch_size, num_classes) = softmax_layout(logits);
+    try!(gradient.resize(logits.desc()));
+
+    let logits = try!(logits.read(native.device())).as_slice::<f32>();
+    let gradient = try!(gradient.write_only(native.device())).as_mut_slice::<f32>();
+
+    let mut loss = 0f32;
+    for sample in 0..batch_size {
+        let range = sample * num_classes..(sample + 1) * num_classes;
+        let sample_logits = &logits[range.clone()];
+        let max = sample_logits.iter().fold(::std::f32::NEG_INFINITY, |max, &x| max.max(x));
+        let log_sum_exp = sample_logits.iter().map(|&x| (x - max).exp()).sum::<f32>().ln();
+
+        for i in range {
+            let log_probability = logits[i] - max - log_sum_exp;
+            gradient[i] = log_probability.exp() - targets[i];
+            loss -= targets[i] * log_probability;
+        }
+    }
+
+    Ok(loss / batch_size as f32)
+}
+
+#[cfg(feature = "cuda")]
+impl SoftmaxCrossEntropy<f32> for Backend<Cuda> {
+    fn softmax_cross_entropy(&self,
+                             logits: &SharedTensor<f32>,
+                             targets: &SharedTensor<f32>,
+                             gradient: &mut SharedTensor<f32>)
+                             -> Result<f32, ::co::error::Error> {
+        try!(check_softmax_targets(logits, targets));
+        let (batch_size, _) = softmax_layout(logits);
+        try!(gradient.resize(logits.desc()));
+
+        // the loss is `-sum(targets * log_softmax(logits)) / batch_size`
+        let mut log_probabilities = SharedTensor::new(logits.desc());
+        try!(conn::LogSoftmax::log_softmax(self, logits, &mut log_probabilities));
+        let mut cross_entropy = SharedTensor::new(&[1]);
+        try!(self.dot(targets, &log_probabilities, &mut cross_entropy));
+
+        try!(conn::Softmax::softmax(self, logits, gradient));
+        try!(self.scaled_add(gradient, -1f32, targets));
+        Ok(-try!(read_scalar(&cross_entropy)) / batch_size as f32)
+    }
+
+    fn softmax_cross_entropy_index(&self,
+                                   logits: &SharedTensor<f32>,
+                                   labels: &SharedTensor<f32>,
+                                   gradient: &mut SharedTensor<f32>)
+                                   -> Result<f32, ::co::error::Error> {
+        let mut targets = SharedTensor::new(logits.desc());
+        let native = native_backend();
+        write_to_memory(try!(targets.write_only(native.device())),
+                        &try!(one_hot_targets(logits, labels)));
+        self.softmax_cross_entropy(logits, &targets, gradient)
+    }
+}
+
+/// Computes the cosine similarity between the rows of two tensors of shape `[N, D]`.
+///
+/// The result is a tensor of shape `[N]`. The norms of the rows are bounded below
+/// by `COSINE_SIMILARITY_EPSILON`, so rows of zeros have a similarity of zero.
+///
+/// The provided implementation computes the dot products of the rows and their squared norms
+/// for all rows at once with [row_dots][1] on the device of the backend. Only those, `N`
+/// values each, and the gradient of the similarities are read on the host, and the gradients
+/// are computed with [scale_rows][2] on the device.
+/// [1]: ./fn.row_dots.html
+/// [2]: ./fn.scale_rows.html
+pub trait CosineSimilarity<F> {
+    /// Computes the cosine similarity of each row of `x` with the same row of `y`.
+    fn cosine_similarity(&self,
+                         x: &SharedTensor<F>,
+                         y: &SharedTensor<F>,
+                         result: &mut SharedTensor<F>)
+                         -> Result<(), ::co::error::Error>;
+
+    /// Computes the gradients of `x` and `y` from the gradient of the similarities.
+    fn cosine_similarity_grad(&self,
+                              x: &SharedTensor<F>,
+                              y: &SharedTensor<F>,
+                              result_diff: &SharedTensor<F>,
+                              x_diff: &mut SharedTensor<F>,
+                              y_diff: &mut SharedTensor<F>)
+                              -> Result<(), ::co::error::Error>;
+}
+
+/// The lower bound of the row norms in [CosineSimilarity][1].
+/// [1]: ./trait.CosineSimilarity.html
+pub const COSINE_SIMILARITY_EPSILON: f32 = 1e-8;
+
+/// The dot products of the rows of two `[N, D]` tensors and their norms bounded below by
+/// `COSINE_SIMILARITY_EPSILON`, read on the host.
+struct CosineRows {
+    dots: Vec<f32>,
+    x_norms: Vec<f32>,
+    y_norms: Vec<f32>,
+}
+
+impl CosineRows {
+    /// Computes the dot products and the norms of the rows of `x` and `y` on the device.
+    fn new<B>(backend: &B, x: &SharedTensor<f32>, y: &SharedTensor<f32>) -> Result<CosineRows, ::co::error::Error>
+        where B: IBackend + Gemm<f32> + conn::Relu<f32>
+    {
+        if x.desc() != y.desc() {
+            return Err(::co::error::Error::Plugin(
+                ::co::plugin::Error::Operation("The rows of a cosine similarity have different shapes")));
+        }
+        let num_rows = x.desc()[0];
+        let (mut dots, mut x_squares, mut y_squares) =
+            (SharedTensor::new(&[num_rows]), SharedTensor::new(&[num_rows]), SharedTensor::new(&[num_rows]));
+        try!(row_dots(backend, x, y, &mut dots));
+        try!(row_dots(backend, x, x, &mut x_squares));
+        try!(row_dots(backend, y, y, &mut y_squares));
+
+        let native = native_backend();
+        let norms = |squares: &[f32]| {
+            squares.iter().map(|square| square.max(0f32).sqrt().max(COSINE_SIMILARITY_EPSILON)).collect()
+        };
+        Ok(CosineRows {
+            dots: try!(read_on_host(&dots, "dots", &native)).to_vec(),
+            x_norms: norms(try!(read_on_host(&x_squares, "x_squares", &native))),
+            y_norms: norms(try!(read_on_host(&y_squares, "y_squares", &native))),
+        })
+    }
+
+    fn similarity(&self, row: usize) -> f32 {
+        self.dots[row] / (self.x_norms[row] * self.y_norms[row])
+    }
+}
+
+/// Reads the value of a scalar tensor on the host, recorded as a [host fallback][1].
+/// [1]: ../transfer/enum.TransferCategory.html#variant.HostFallback
+fn read_scalar(scalar: &SharedTensor<f32>) -> Result<f32, ::co::error::Error> {
+    let native = native_backend();
+    let value = try!(read_on_host(scalar, "scalar", &native));
+    Ok(value[0])
+}
+
+/// Reads `tensor` on the host for an operation that computes on the host and records the
+/// synchronization as a [host fallback][1] of the operand `operand`.
+/// [1]: ../transfer/enum.TransferCategory.html#variant.HostFallback
+pub fn read_on_host<'a>(tensor: &'a SharedTensor<f32>,
+                        operand: &str,
+                        native: &Backend<Native>)
+                        -> Result<&'a [f32], ::co::error::Error> {
+    let bytes = tensor.desc().size() * ::std::mem::size_of::<f32>();
+    let memory = try!(::transfer::record(::transfer::TransferCategory::HostFallback,
+                                         operand,
+                                         bytes,
+                                         || tensor.read(native.device())));
+    Ok(memory.as_slice::<f32>())
+}
+
+impl<T: IBackend + Gemm<f32> + conn::Relu<f32>> CosineSimilarity<f32> for T {
+    fn cosine_similarity(&self,
+                         x: &SharedTensor<f32>,
+                         y: &SharedTensor<f32>,
+                         result: &mut SharedTensor<f32>)
+                         -> Result<(), ::co::error::Error> {
+        let rows = try!(CosineRows::new(self, x, y));
+        let similarities = (0..rows.dots.len()).map(|row| rows.similarity(row)).collect::<Vec<_>>();
+        try!(result.resize(&[similarities.len()]));
+        let native = native_backend();
+        write_to_memory(try!(result.write_only(native.device())), &similarities);
+        Ok(())
+    }
+
+    fn cosine_similarity_grad(&self,
+                              x: &SharedTensor<f32>,
+                              y: &SharedTensor<f32>,
+                              result_diff: &SharedTensor<f32>,
+                              x_diff: &mut SharedTensor<f32>,
+                              y_diff: &mut SharedTensor<f32>)
+                              -> Result<(), ::co::error::Error> {
+        let rows = try!(CosineRows::new(self, x, y));
+        let native = native_backend();
+        let result_diff = try!(read_on_host(result_diff, "result_diff", &native));
+        if result_diff.len() != rows.dots.len() {
+            return Err(::co::error::Error::Plugin(
+                ::co::plugin::Error::Operation("The gradient of a cosine similarity doesn't match its rows")));
+        }
+
+        // d cos / dx = y / (|x| |y|) - cos * x / |x|^2, as factors of the rows of `y` and `x`
+        let (mut cross, mut x_self, mut y_self) = (Vec::new(), Vec::new(), Vec::new());
+        for (i, &diff) in result_diff.iter().enumerate() {
+            let (x_norm, y_norm) = (rows.x_norms[i], rows.y_norms[i]);
+            cross.push(diff / (x_norm * y_norm));
+            x_self.push(-diff * rows.similarity(i) / (x_norm * x_norm));
+            y_self.push(-diff * rows.similarity(i) / (y_norm * y_norm));
+        }
+        let factors = |values: &[f32]| -> Result<SharedTensor<f32>, ::co::error::Error> {
+            let mut tensor = SharedTensor::new(&[values.len()]);
+            write_to_memory(try!(tensor.write_only(native.device())), values);
+            Ok(tensor)
+        };
+        let (cross, x_self, y_self) = (try!(factors(&cross)), try!(factors(&x_self)), try!(factors(&y_self)));
+        try!(x_diff.resize(x.desc()));
+        try!(y_diff.resize(y.desc()));
+        try!(scale_rows(self, &cross, y, 0f32, x_diff));
+        try!(scale_rows(self, &x_self, x, 1f32, x_diff));
+        try!(scale_rows(self, &cross, x, 0f32, y_diff));
+        scale_rows(self, &y_self, y, 1f32, y_diff)
+    }
+}
+
+/// Sets every element of a tensor to a scalar value on the device of the backend.
+///
+/// Unlike the [constant filler][1] this does not write the tensor on a separate native
+/// backend, and the previous contents of the tensor are never synchronized. The CUDA backend
+/// writes two vectors of about the square root of the size of the tensor on the host and
+/// broadcasts them with a gemm on the device.
+/// [1]: ../weight/enum.FillerType.html#method.fill_constant
+pub trait Fill<F> {
+    /// Sets every element of `x` to `value`.
+    fn fill(&self, x: &mut SharedTensor<F>, value: F) -> Result<(), ::co::error::Error>;
+}
+
+impl Fill<f32> for Backend<Native> {
+    fn fill(&self, x: &mut SharedTensor<f32>, value: f32) -> Result<(), ::co::error::Error> {
+        for datum in try!(x.write_only(self.device())).as_mut_slice::<f32>() {
+            *datum = value;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl Fill<f32> for Backend<Cuda> {
+    fn fill(&self, x: &mut SharedTensor<f32>, value: f32) -> Result<(), ::co::error::Error> {
+        let size = x.desc().size();
+        if size == 0 {
+            return Ok(());
+        }
+        // `x := ones(m, 1) * (value * ones(1, n))` with `m * n = size` and `m` and `n` close to
+        // its square root, so only the two short vectors are written on the host for every
+        // call; with a beta of 0 the old contents of `x` are never read
+        let mut rows = (size as f64).sqrt() as usize;
+        while size % rows != 0 {
+            rows -= 1;
+        }
+        let columns = size / rows;
+        let native = native_backend();
+        let mut column = SharedTensor::new(&[rows, 1]);
+        let mut row = SharedTensor::new(&[1, columns]);
+        write_to_memory(try!(column.write_only(native.device())), &vec![1f32; rows]);
+        write_to_memory(try!(row.write_only(native.device())), &vec![value; columns]);
+
+        let shape = x.desc().clone();
+        try!(x.reshape(&[rows, columns]));
+        try!(self.gemm(&native_scalar(1f32),
+                       Transpose::NoTrans,
+                       &column,
+                       Transpose::NoTrans,
+                       &row,
+                       &native_scalar(0f32),
+                       x));
+        try!(x.reshape(&shape));
+        Ok(())
+    }
+}
+
+/// Computes `y := alpha * x + y` with a host scalar `alpha`.
+///
+/// Unlike [axpy][1] this does not need `alpha` as a tensor. The native backend uses it
+/// directly, and the CUDA backend passes it to cuBLAS as a [cached scalar][2], so a value
+/// that repeats, like a constant learning rate, is allocated and copied to the device only
+/// once.
+/// [1]: ../../coaster_blas/plugin/trait.Axpy.html
+/// [2]: ./fn.cached_scalar.html
+pub trait ScaledAdd<F> {
+    /// Adds `alpha * x` to `y`.
+    fn scaled_add(&self, y: &mut SharedTensor<F>, alpha: F, x: &SharedTensor<F>) -> Result<(), ::co::error::Error>;
+
+    /// Computes `y := alpha * x + beta * y` like [axpby][1] with host scalars.
+    /// [1]: ./trait.Axpby.html#method.axpby
+    fn scaled_axpby(&self,
+                    alpha: F,
+                    x: &SharedTensor<F>,
+                    beta: F,
+                    y: &mut SharedTensor<F>)
+                    -> Result<(), ::co::error::Error>;
+}
+
+impl ScaledAdd<f32> for Backend<Native> {
+    fn scaled_add(&self, y: &mut SharedTensor<f32>, alpha: f32, x: &SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        self.scaled_axpby(alpha, x, 1f32, y)
+    }
+
+    fn scaled_axpby(&self,
+                    alpha: f32,
+                    x: &SharedTensor<f32>,
+                    beta: f32,
+                    y: &mut SharedTensor<f32>)
+                    -> Result<(), ::co::error::Error> {
+        assert_eq!(x.desc().size(), y.desc().size());
+        let x = try!(x.read(self.device())).as_slice::<f32>();
+        let y = try!(y.read_write(self.device())).as_mut_slice::<f32>();
+        for (y, x) in y.iter_mut().zip(x) {
+            *y *= beta;
+            *y += alpha * x;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl ScaledAdd<f32> for Backend<Cuda> {
+    fn scaled_add(&self, y: &mut SharedTensor<f32>, alpha: f32, x: &SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        self.axpy(&cached_scalar(alpha), x, y)
+    }
+
+    fn scaled_axpby(&self,
+                    alpha: f32,
+                    x: &SharedTensor<f32>,
+                    beta: f32,
+                    y: &mut SharedTensor<f32>)
+                    -> Result<(), ::co::error::Error> {
+        self.axpby(&cached_scalar(alpha), x, &cached_scalar(beta), y)
+    }
+}
+
+/// Computes a complete SGD step with momentum and L2 weight decay.
+///
+/// The native backend does this in a single pass; for every element it performs
+///
+/// - `gradient = gradient + weight_decay * weight`
+/// - `history = momentum * history + lr * gradient`
+/// - `gradient = history`
+/// - `weight = weight - history`
+///
+/// which is the same sequence of operations as the separate decay, [axpby][1], copy and
+/// [scaled_add][2] steps, so the results are identical. The CUDA backend is not fused:
+/// coaster-blas has no kernel for it, so it falls back to those steps as three to four
+/// separate BLAS calls on the device, each a full pass over the blob, and is no faster than
+/// the unfused update.
+/// [1]: ./trait.Axpby.html
+/// [2]: ./trait.ScaledAdd.html
+pub trait SgdUpdate<F> {
+    /// Updates `weight` and `history` in place and leaves the update value in `gradient`.
+    fn sgd_update(&self,
+                  weight: &mut SharedTensor<F>,
+                  gradient: &mut SharedTensor<F>,
+                  history: &mut SharedTensor<F>,
+                  lr: F,
+                  momentum: F,
+                  weight_decay: F)
+                  -> Result<(), ::co::error::Error>;
+}
+
+impl SgdUpdate<f32> for Backend<Native> {
+    fn sgd_update(&self,
+                  weight: &mut SharedTensor<f32>,
+                  gradient: &mut SharedTensor<f32>,
+                  history: &mut SharedTensor<f32>,
+                  lr: f32,
+                  momentum: f32,
+                  weight_decay: f32)
+                  -> Result<(), ::co::error::Error> {
+        assert_eq!(weight.desc().size(), gradient.desc().size());
+        assert_eq!(weight.desc().size(), history.desc().size());
+        let weight = try!(weight.read_write(self.device())).as_mut_slice::<f32>();
+        let gradient = try!(gradient.read_write(self.device())).as_mut_slice::<f32>();
+        let history = try!(history.read_write(self.device())).as_mut_slice::<f32>();
+        for ((w, g), h) in weight.iter_mut().zip(gradient.iter_mut()).zip(history.iter_mut()) {
+            // the decay is skipped entirely when disabled, like the separate decay pass
+            if weight_decay != 0f32 {
+                *g += weight_decay * *w;
+            }
+            *h *= momentum;
+            *h += lr * *g;
+            *g = *h;
+            *w += -1f32 * *h;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl SgdUpdate<f32> for Backend<Cuda> {
+    fn sgd_update(&self,
+                  weight: &mut SharedTensor<f32>,
+                  gradient: &mut SharedTensor<f32>,
+                  history: &mut SharedTensor<f32>,
+                  lr: f32,
+                  momentum: f32,
+                  weight_decay: f32)
+                  -> Result<(), ::co::error::Error> {
+        // coaster-blas has no fused kernel, so the step is composed of BLAS calls that all
+        // stay on the device.
+        if weight_decay != 0f32 {
+            try!(self.scaled_add(gradient, weight_decay, weight));
+        }
+        try!(self.scaled_axpby(lr, gradient, momentum, history));
+        try!(self.copy(history, gradient));
+        self.scaled_add(weight, -1f32, history)
+    }
+}
+
+/// Computes the L1 norm, the sum of absolute values, along one axis of a tensor.
+///
+/// The result has the shape of `x` with the reduced `axis` removed, so for a tensor of
+/// shape `[N, D]` axis `1` yields the norm of each row and axis `0` the norm of each column.
+pub trait AsumAxis<F> {
+    /// Computes the sum of the absolute values of `x` along `axis`.
+    fn asum_axis(&self, x: &SharedTensor<F>, axis: usize, result: &mut SharedTensor<F>) -> Result<(), ::co::error::Error>;
+
+    /// Computes the gradient of `x`, which is the sign of `x` scaled by the gradient
+    /// of the norm it contributes to.
+    fn asum_axis_grad(&self,
+                      x: &SharedTensor<F>,
+                      axis: usize,
+                      result_diff: &SharedTensor<F>,
+                      x_diff: &mut SharedTensor<F>)
+                      -> Result<(), ::co::error::Error>;
+}
+
+/// Returns the number of elements before, along and after `axis`.
+fn axis_layout(shape: &[usize], axis: usize) -> (usize, usize, usize) {
+    assert!(axis < shape.len(),
+            "Axis {} out of range for shape {:?}",
+            axis,
+            shape);
+    let outer = shape[..axis].iter().fold(1, |prod, i| prod * i);
+    let inner = shape[axis + 1..].iter().fold(1, |prod, i| prod * i);
+    (outer, shape[axis], inner)
+}
+
+/// Returns a copy of `x` of shape `[rows, size / rows]`, or `None` if `x` has `rows` rows
+/// already, so that [gemm][1], which views a tensor as a matrix of its first dimension and the
+/// rest, can use it as a matrix with `rows` rows.
+/// [1]: ../../coaster_blas/plugin/trait.Gemm.html
+#[cfg(any(feature = "cuda", test))]
+fn matrix_copy<B: IBackend + BlasCopy<f32>>(backend: &B,
+                                            x: &SharedTensor<f32>,
+                                            rows: usize)
+                                            -> Result<Option<SharedTensor<f32>>, ::co::error::Error> {
+    if x.desc().get(0) == Some(&rows) {
+        return Ok(None);
+    }
+    let mut matrix = SharedTensor::new(&[rows, x.desc().size() / rows]);
+    try!(backend.copy(x, &mut matrix));
+    Ok(Some(matrix))
+}
+
+/// Returns the transpose of `x`, viewed as a matrix with `rows` rows, computed with a gemm with
+/// the [identity][1] of shape `[rows, rows]`.
+/// [1]: ./fn.identity.html
+#[cfg(any(feature = "cuda", test))]
+fn transpose_matrix<B: IBackend + Gemm<f32> + BlasCopy<f32>>(backend: &B,
+                                                             x: &SharedTensor<f32>,
+                                                             rows: usize)
+                                                             -> Result<SharedTensor<f32>, ::co::error::Error> {
+    let copy = try!(matrix_copy(backend, x, rows));
+    let mut transposed = SharedTensor::new(&[x.desc().size() / rows, rows]);
+    try!(backend.gemm(&native_scalar(1f32),
+                      Transpose::Trans,
+                      copy.as_ref().unwrap_or(x),
+                      Transpose::NoTrans,
+                      &identity(rows),
+                      &native_scalar(0f32),
+                      &mut transposed));
+    Ok(transposed)
+}
+
+fn asum_axis_on(native: &Backend<Native>,
+                x: &SharedTensor<f32>,
+                axis: usize,
+                result: &mut SharedTensor<f32>)
+                -> Result<(), ::co::error::Error> {
+    let (outer, len, inner) = axis_layout(x.desc(), axis);
+    let mut result_shape = x.desc().clone();
+    result_shape.remove(axis);
+    try!(result.resize(&result_shape));
+
+    let x = try!(x.read(native.device())).as_slice::<f32>();
+    let result = try!(result.write_only(native.device())).as_mut_slice::<f32>();
+    for o in 0..outer {
+        for i in 0..inner {
+            result[o * inner + i] = (0..len).fold(0f32, |sum, k| sum + x[(o * len + k) * inner + i].abs());
+        }
+    }
+    Ok(())
+}
+
+fn asum_axis_grad_on(native: &Backend<Native>,
+                     x: &SharedTensor<f32>,
+                     axis: usize,
+                     result_diff: &SharedTensor<f32>,
+                     x_diff: &mut SharedTensor<f32>)
+                     -> Result<(), ::co::error::Error> {
+    let (outer, len, inner) = axis_layout(x.desc(), axis);
+    try!(x_diff.resize(x.desc()));
+
+    let x = try!(x.read(native.device())).as_slice::<f32>();
+    let result_diff = try!(result_diff.read(native.device())).as_slice::<f32>();
+    let x_diff = try!(x_diff.write_only(native.device())).as_mut_slice::<f32>();
+    for o in 0..outer {
+        for k in 0..len {
+            for i in 0..inner {
+                let index = (o * len + k) * inner + i;
+                let sign = if x[index] > 0f32 {
+                    1f32
+                } else if x[index] < 0f32 {
+                    -1f32
+                } else {
+                    0f32
+                };
+                x_diff[index] = sign * result_diff[o * inner + i];
+            }
+        }
+    }
+    Ok(())
+}
+
+impl AsumAxis<f32> for Backend<Native> {
+    fn asum_axis(&self, x: &SharedTensor<f32>, axis: usize, result: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        asum_axis_on(self, x, axis, result)
+    }
+
+    fn asum_axis_grad(&self,
+                      x: &SharedTensor<f32>,
+                      axis: usize,
+                      result_diff: &SharedTensor<f32>,
+                      x_diff: &mut SharedTensor<f32>)
+                      -> Result<(), ::co::error::Error> {
+        asum_axis_grad_on(self, x, axis, result_diff, x_diff)
+    }
+}
+
+/// Sums the `[outer, len, inner]` tensor `x` along its `len` axis into `result` of shape
+/// `[outer, inner]` with a gemm with [ones][1], for backends without a dedicated kernel.
+/// [1]: ./fn.ones.html
+///
+/// Unless `inner` is `1`, the outer axis is moved to the back and back again with a gemm
+/// with the [identity][2] of shape `[outer, outer]`, so the cost stays linear in the size of
+/// `x`.
+/// [2]: ./fn.identity.html
+#[cfg(any(feature = "cuda", test))]
+fn sum_axis_by_gemm<B: IBackend + Gemm<f32> + BlasCopy<f32>>(backend: &B,
+                                                             x: &SharedTensor<f32>,
+                                                             (outer, len, inner): (usize, usize, usize),
+                                                             result: &mut SharedTensor<f32>)
+                                                             -> Result<(), ::co::error::Error> {
+    let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+    try!(result.resize(&[outer, inner]));
+    if inner == 1 {
+        let copy = try!(matrix_copy(backend, x, outer));
+        return backend.gemm(&one,
+                            Transpose::NoTrans,
+                            copy.as_ref().unwrap_or(x),
+                            Transpose::NoTrans,
+                            &ones(&[len, 1]),
+                            &zero,
+                            result);
+    }
+    let mut columns = try!(transpose_matrix(backend, x, outer));
+    try!(columns.reshape(&[len, inner * outer]));
+    let mut sums = SharedTensor::new(&[1, inner * outer]);
+    try!(backend.gemm(&one,
+                      Transpose::NoTrans,
+                      &ones(&[1, len]),
+                      Transpose::NoTrans,
+                      &columns,
+                      &zero,
+                      &mut sums));
+    try!(sums.reshape(&[inner, outer]));
+    backend.gemm(&one,
+                 Transpose::NoTrans,
+                 &identity(outer),
+                 Transpose::Trans,
+                 &sums,
+                 &zero,
+                 result)
+}
+
+/// Repeats every value of the `[outer, inner]` tensor `x` `len` times into `result` of shape
+/// `[outer, len, inner]`, the transpose of [sum_axis_by_gemm][1].
+/// [1]: ./fn.sum_axis_by_gemm.html
+#[cfg(any(feature = "cuda", test))]
+fn broadcast_axis_by_gemm<B: IBackend + Gemm<f32> + BlasCopy<f32>>(backend: &B,
+                                                                   x: &SharedTensor<f32>,
+                                                                   (outer, len, inner): (usize, usize, usize),
+                                                                   result: &mut SharedTensor<f32>)
+                                                                   -> Result<(), ::co::error::Error> {
+    let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+    try!(result.resize(&[outer, len * inner]));
+    if inner == 1 {
+        let copy = try!(matrix_copy(backend, x, outer));
+        return backend.gemm(&one,
+                            Transpose::NoTrans,
+                            copy.as_ref().unwrap_or(x),
+                            Transpose::NoTrans,
+                            &ones(&[1, len]),
+                            &zero,
+                            result);
+    }
+    let mut columns = try!(transpose_matrix(backend, x, outer));
+    try!(columns.reshape(&[1, inner * outer]));
+    let mut repeated = SharedTensor::new(&[len, inner * outer]);
+    try!(backend.gemm(&one,
+                      Transpose::NoTrans,
+                      &ones(&[len, 1]),
+                      Transpose::NoTrans,
+                      &columns,
+                      &zero,
+                      &mut repeated));
+    try!(repeated.reshape(&[len * inner, outer]));
+    backend.gemm(&one,
+                 Transpose::NoTrans,
+                 &identity(outer),
+                 Transpose::Trans,
+                 &repeated,
+                 &zero,
+                 result)
+}
+
+/// Returns `-x`, computed on the device of `backend`.
+#[cfg(feature = "cuda")]
+fn negated(backend: &Backend<Cuda>, x: &SharedTensor<f32>) -> Result<SharedTensor<f32>, ::co::error::Error> {
+    let mut negated = SharedTensor::new(x.desc());
+    try!(backend.copy(x, &mut negated));
+    try!(backend.scal(&native_scalar(-1f32), &mut negated));
+    Ok(negated)
+}
+
+#[cfg(feature = "cuda")]
+impl AsumAxis<f32> for Backend<Cuda> {
+    fn asum_axis(&self, x: &SharedTensor<f32>, axis: usize, result: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        let mut result_shape = x.desc().clone();
+        result_shape.remove(axis);
+
+        // |x| = relu(x) + relu(-x)
+        let mut abs = SharedTensor::new(x.desc());
+        let mut negative_part = SharedTensor::new(x.desc());
+        try!(conn::Relu::relu(self, x, &mut abs));
+        try!(conn::Relu::relu(self, &try!(negated(self, x)), &mut negative_part));
+        try!(self.scaled_add(&mut abs, 1f32, &negative_part));
+
+        try!(sum_axis_by_gemm(self, &abs, axis_layout(x.desc(), axis), result));
+        try!(result.reshape(&result_shape));
+        Ok(())
+    }
+
+    fn asum_axis_grad(&self,
+                      x: &SharedTensor<f32>,
+                      axis: usize,
+                      result_diff: &SharedTensor<f32>,
+                      x_diff: &mut SharedTensor<f32>)
+                      -> Result<(), ::co::error::Error> {
+        // repeat the gradient of every norm for the `len` values it sums
+        let mut broadcast = SharedTensor::new(x.desc());
+        try!(broadcast_axis_by_gemm(self, result_diff, axis_layout(x.desc(), axis), &mut broadcast));
+        try!(broadcast.reshape(x.desc()));
+
+        // the sign of `x` is `(x > 0) - (-x > 0)`, which is applied by the relu gradients
+        let negated = try!(negated(self, x));
+        let mut negative_part = SharedTensor::new(x.desc());
+        try!(x_diff.resize(x.desc()));
+        try!(conn::Relu::relu_grad(self, x, &broadcast, x, x_diff));
+        try!(conn::Relu::relu_grad(self, &negated, &broadcast, &negated, &mut negative_part));
+        self.scaled_add(x_diff, -1f32, &negative_part)
+    }
+}
+
+/// Computes the weighted sum `out = sum_i coefficients[i] * x[i]` of equally-shaped tensors,
+/// e.g. to combine the branches of residual or feature pyramid networks.
+///
+/// The native backend computes the sum in a single pass over all inputs instead of one axpy
+/// per input. The CUDA backend is not fused: coaster has no views to stack the inputs for a
+/// single gemm, so it copies and scales the first input and adds each other one with its own
+/// axpy, i.e. it makes one BLAS call per input. Its coefficients are [cached scalars][1], so
+/// they are not allocated and copied to the device on every call.
+/// [1]: ./fn.cached_scalar.html
+pub trait WeightedSum<F> {
+    /// Computes the weighted sum of `x` into `out`.
+    fn weighted_sum(&self, coefficients: &[F], x: &[&SharedTensor<F>], out: &mut SharedTensor<F>) -> Result<(), ::co::error::Error>;
+
+    /// Computes the gradient of each input, which is `coefficients[i] * out_diff`.
+    fn weighted_sum_grad(&self,
+                         coefficients: &[F],
+                         out_diff: &SharedTensor<F>,
+                         x_diff: &mut [&mut SharedTensor<F>])
+                         -> Result<(), ::co::error::Error>;
+}
+
+impl WeightedSum<f32> for Backend<Native> {
+    fn weighted_sum(&self, coefficients: &[f32], x: &[&SharedTensor<f32>], out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        assert_eq!(coefficients.len(), x.len());
+        assert!(!x.is_empty(), "Weighted sum of no tensors");
+        try!(out.resize(x[0].desc()));
+
+        let mut inputs = Vec::with_capacity(x.len());
+        for tensor in x {
+            assert_eq!(x[0].desc(), tensor.desc());
+            inputs.push(try!(tensor.read(self.device())).as_slice::<f32>());
+        }
+        let out = try!(out.write_only(self.device())).as_mut_slice::<f32>();
+        for (i, out) in out.iter_mut().enumerate() {
+            *out = inputs.iter().zip(coefficients).fold(0f32, |sum, (input, coefficient)| sum + coefficient * input[i]);
+        }
+        Ok(())
+    }
+
+    fn weighted_sum_grad(&self,
+                         coefficients: &[f32],
+                         out_diff: &SharedTensor<f32>,
+                         x_diff: &mut [&mut SharedTensor<f32>])
+                         -> Result<(), ::co::error::Error> {
+        assert_eq!(coefficients.len(), x_diff.len());
+        let out_diff_slice = try!(out_diff.read(self.device())).as_slice::<f32>();
+        for (x_diff, &coefficient) in x_diff.iter_mut().zip(coefficients) {
+            try!(x_diff.resize(out_diff.desc()));
+            let x_diff = try!(x_diff.write_only(self.device())).as_mut_slice::<f32>();
+            for (x_diff, out_diff) in x_diff.iter_mut().zip(out_diff_slice) {
+                *x_diff = coefficient * out_diff;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl WeightedSum<f32> for Backend<Cuda> {
+    fn weighted_sum(&self, coefficients: &[f32], x: &[&SharedTensor<f32>], out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        assert_eq!(coefficients.len(), x.len());
+        assert!(!x.is_empty(), "Weighted sum of no tensors");
+        try!(out.resize(x[0].desc()));
+        // the first input initializes the sum, so the old contents of `out` are never read
+        try!(self.copy(x[0], out));
+        if coefficients[0] != 1f32 {
+            try!(self.scal(&cached_scalar(coefficients[0]), out));
+        }
+        for (tensor, &coefficient) in x.iter().zip(coefficients).skip(1) {
+            assert_eq!(x[0].desc(), tensor.desc());
+            try!(self.scaled_add(out, coefficient, tensor));
+        }
+        Ok(())
+    }
+
+    fn weighted_sum_grad(&self,
+                         coefficients: &[f32],
+                         out_diff: &SharedTensor<f32>,
+                         x_diff: &mut [&mut SharedTensor<f32>])
+                         -> Result<(), ::co::error::Error> {
+        assert_eq!(coefficients.len(), x_diff.len());
+        for (x_diff, &coefficient) in x_diff.iter_mut().zip(coefficients) {
+            try!(x_diff.resize(out_diff.desc()));
+            try!(self.copy(out_diff, x_diff));
+            if coefficient != 1f32 {
+                try!(self.scal(&cached_scalar(coefficient), x_diff));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the element-wise maximum `out[i] = max(a[i], b[i])` of two equally-shaped tensors,
+/// e.g. for maxout or gating layers.
+///
+/// On ties `a` is selected, so its gradient receives the whole gradient of the element.
+pub trait Max<F> {
+    /// Computes the element-wise maximum of `a` and `b` into `out`.
+    fn max(&self, a: &SharedTensor<F>, b: &SharedTensor<F>, out: &mut SharedTensor<F>) -> Result<(), ::co::error::Error>;
+
+    /// Routes `out_diff` to the gradient of the input selected for each element,
+    /// the gradient of the other input is `0` there.
+    fn max_grad(&self,
+                a: &SharedTensor<F>,
+                b: &SharedTensor<F>,
+                out_diff: &SharedTensor<F>,
+                a_diff: &mut SharedTensor<F>,
+                b_diff: &mut SharedTensor<F>)
+                -> Result<(), ::co::error::Error>;
+}
+
+/// Computes the element-wise minimum `out[i] = min(a[i], b[i])` of two equally-shaped tensors.
+///
+/// On ties `a` is selected, like for [Max][1].
+/// [1]: ./trait.Max.html
+pub trait Min<F> {
+    /// Computes the element-wise minimum of `a` and `b` into `out`.
+    fn min(&self, a: &SharedTensor<F>, b: &SharedTensor<F>, out: &mut SharedTensor<F>) -> Result<(), ::co::error::Error>;
+
+    /// Routes `out_diff` to the gradient of the input selected for each element,
+    /// the gradient of the other input is `0` there.
+    fn min_grad(&self,
+                a: &SharedTensor<F>,
+                b: &SharedTensor<F>,
+                out_diff: &SharedTensor<F>,
+                a_diff: &mut SharedTensor<F>,
+                b_diff: &mut SharedTensor<F>)
+                -> Result<(), ::co::error::Error>;
+}
+
+/// Returns whether `a` is selected over `b` by the maximum, which includes ties.
+fn selects_max(a: f32, b: f32) -> bool {
+    a >= b
+}
+
+/// Returns whether `a` is selected over `b` by the minimum, which includes ties.
+fn selects_min(a: f32, b: f32) -> bool {
+    a <= b
+}
+
+fn select_on(native: &Backend<Native>,
+             a: &SharedTensor<f32>,
+             b: &SharedTensor<f32>,
+             selects_a: fn(f32, f32) -> bool,
+             out: &mut SharedTensor<f32>)
+             -> Result<(), ::co::error::Error> {
+    assert_eq!(a.desc(), b.desc());
+    try!(out.resize(a.desc()));
+
+    let a = try!(a.read(native.device())).as_slice::<f32>();
+    let b = try!(b.read(native.device())).as_slice::<f32>();
+    let out = try!(out.write_only(native.device())).as_mut_slice::<f32>();
+    for ((out, &a), &b) in out.iter_mut().zip(a).zip(b) {
+        *out = if selects_a(a, b) { a } else { b };
+    }
+    Ok(())
+}
+
+fn select_grad_on(native: &Backend<Native>,
+                  a: &SharedTensor<f32>,
+                  b: &SharedTensor<f32>,
+                  selects_a: fn(f32, f32) -> bool,
+                  out_diff: &SharedTensor<f32>,
+                  a_diff: &mut SharedTensor<f32>,
+                  b_diff: &mut SharedTensor<f32>)
+                  -> Result<(), ::co::error::Error> {
+    assert_eq!(a.desc(), b.desc());
+    try!(a_diff.resize(a.desc()));
+    try!(b_diff.resize(b.desc()));
+
+    let a = try!(a.read(native.device())).as_slice::<f32>();
+    let b = try!(b.read(native.device())).as_slice::<f32>();
+    let out_diff = try!(out_diff.read(native.device())).as_slice::<f32>();
+    let a_diff = try!(a_diff.write_only(native.device())).as_mut_slice::<f32>();
+    let b_diff = try!(b_diff.write_only(native.device())).as_mut_slice::<f32>();
+    for i in 0..out_diff.len() {
+        if selects_a(a[i], b[i]) {
+            a_diff[i] = out_diff[i];
+            b_diff[i] = 0f32;
+        } else {
+            a_diff[i] = 0f32;
+            b_diff[i] = out_diff[i];
+        }
+    }
+    Ok(())
+}
+
+impl Max<f32> for Backend<Native> {
+    fn max(&self, a: &SharedTensor<f32>, b: &SharedTensor<f32>, out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        select_on(self, a, b, selects_max, out)
+    }
+
+    fn max_grad(&self,
+                a: &SharedTensor<f32>,
+                b: &SharedTensor<f32>,
+                out_diff: &SharedTensor<f32>,
+                a_diff: &mut SharedTensor<f32>,
+                b_diff: &mut SharedTensor<f32>)
+                -> Result<(), ::co::error::Error> {
+        select_grad_on(self, a, b, selects_max, out_diff, a_diff, b_diff)
+    }
+}
+
+impl Min<f32> for Backend<Native> {
+    fn min(&self, a: &SharedTensor<f32>, b: &SharedTensor<f32>, out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        select_on(self, a, b, selects_min, out)
+    }
+
+    fn min_grad(&self,
+                a: &SharedTensor<f32>,
+                b: &SharedTensor<f32>,
+                out_diff: &SharedTensor<f32>,
+                a_diff: &mut SharedTensor<f32>,
+                b_diff: &mut SharedTensor<f32>)
+                -> Result<(), ::co::error::Error> {
+        select_grad_on(self, a, b, selects_min, out_diff, a_diff, b_diff)
+    }
+}
+
+/// Returns `a - b`, computed on the device of `backend`.
+#[cfg(feature = "cuda")]
+fn difference(backend: &Backend<Cuda>,
+              a: &SharedTensor<f32>,
+              b: &SharedTensor<f32>)
+              -> Result<SharedTensor<f32>, ::co::error::Error> {
+    let mut difference = SharedTensor::new(a.desc());
+    try!(backend.copy(a, &mut difference));
+    try!(backend.scaled_add(&mut difference, -1f32, b));
+    Ok(difference)
+}
+
+/// Computes the element-wise maximum, or the minimum if `maximum` is `false`, on the device
+/// as `max(a, b) = b + relu(a - b)` and `min(a, b) = b - relu(b - a)`.
+#[cfg(feature = "cuda")]
+fn select_on_device(backend: &Backend<Cuda>,
+                    a: &SharedTensor<f32>,
+                    b: &SharedTensor<f32>,
+                    maximum: bool,
+                    out: &mut SharedTensor<f32>)
+                    -> Result<(), ::co::error::Error> {
+    assert_eq!(a.desc(), b.desc());
+    try!(out.resize(a.desc()));
+    let (difference, sign) = if maximum {
+        (try!(difference(backend, a, b)), 1f32)
+    } else {
+        (try!(difference(backend, b, a)), -1f32)
+    };
+    let mut rectified = SharedTensor::new(a.desc());
+    try!(conn::Relu::relu(backend, &difference, &mut rectified));
+    try!(backend.copy(b, out));
+    backend.scaled_add(out, sign, &rectified)
+}
+
+/// Computes the gradients of [select_on_device][1] on the device.
+/// [1]: ./fn.select_on_device.html
+#[cfg(feature = "cuda")]
+fn select_grad_on_device(backend: &Backend<Cuda>,
+                         a: &SharedTensor<f32>,
+                         b: &SharedTensor<f32>,
+                         maximum: bool,
+                         out_diff: &SharedTensor<f32>,
+                         a_diff: &mut SharedTensor<f32>,
+                         b_diff: &mut SharedTensor<f32>)
+                         -> Result<(), ::co::error::Error> {
+    assert_eq!(a.desc(), b.desc());
+    try!(a_diff.resize(a.desc()));
+    try!(b_diff.resize(b.desc()));
+    // `b` is only selected where it is strictly greater for the maximum and strictly smaller
+    // for the minimum, so the relu gradient of the difference routes ties to `a`
+    let difference = if maximum {
+        try!(difference(backend, b, a))
+    } else {
+        try!(difference(backend, a, b))
+    };
+    try!(conn::Relu::relu_grad(backend, &difference, out_diff, &difference, b_diff));
+    try!(backend.copy(out_diff, a_diff));
+    backend.scaled_add(a_diff, -1f32, b_diff)
+}
+
+#[cfg(feature = "cuda")]
+impl Max<f32> for Backend<Cuda> {
+    fn max(&self, a: &SharedTensor<f32>, b: &SharedTensor<f32>, out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        select_on_device(self, a, b, true, out)
+    }
+
+    fn max_grad(&self,
+                a: &SharedTensor<f32>,
+                b: &SharedTensor<f32>,
+                out_diff: &SharedTensor<f32>,
+                a_diff: &mut SharedTensor<f32>,
+                b_diff: &mut SharedTensor<f32>)
+                -> Result<(), ::co::error::Error> {
+        select_grad_on_device(self, a, b, true, out_diff, a_diff, b_diff)
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl Min<f32> for Backend<Cuda> {
+    fn min(&self, a: &SharedTensor<f32>, b: &SharedTensor<f32>, out: &mut SharedTensor<f32>) -> Result<(), ::co::error::Error> {
+        select_on_device(self, a, b, false, out)
+    }
+
+    fn min_grad(&self,
+                a: &SharedTensor<f32>,
+                b: &SharedTensor<f32>,
+                out_diff: &SharedTensor<f32>,
+                a_diff: &mut SharedTensor<f32>,
+                b_diff: &mut SharedTensor<f32>)
+                -> Result<(), ::co::error::Error> {
+        select_grad_on_device(self, a, b, false, out_diff, a_diff, b_diff)
+    }
+}
+
+/// Computes the cumulative sum along one axis of a tensor, e.g. for sequence operations
+/// or differentiable sorting.
+///
+/// The inclusive sum of `[1, 2, 3]` is `[1, 3, 6]`, the `exclusive` sum leaves out the
+/// element itself and is `[0, 1, 3]`.
+pub trait CumSum<F> {
+    /// Computes the cumulative sum of `x` along `axis` into `out`.
+    fn cumsum(&self,
+              x: &SharedTensor<F>,
+              axis: usize,
+              exclusive: bool,
+              out: &mut SharedTensor<F>)
+              -> Result<(), ::co::error::Error>;
+
+    /// Computes the gradient of `x`, which is the reverse cumulative sum of `out_diff`
+    /// along `axis`.
+    fn cumsum_grad(&self,
+                   out_diff: &SharedTensor<F>,
+                   axis: usize,
+                   exclusive: bool,
+                   x_diff: &mut SharedTensor<F>)
+                   -> Result<(), ::co::error::Error>;
+}
+
+/// Computes the cumulative sum along `axis`, from the last element backwards if `reverse`.
+fn cumsum_on(native: &Backend<Native>,
+             x: &SharedTensor<f32>,
+             axis: usize,
+             exclusive: bool,
+             reverse: bool,
+             out: &mut SharedTensor<f32>)
+             -> Result<(), ::co::error::Error> {
+    let (outer, len, inner) = axis_layout(x.desc(), axis);
+    try!(out.resize(x.desc()));
+
+    let x = try!(x.read(native.device())).as_slice::<f32>();
+    let out = try!(out.write_only(native.device())).as_mut_slice::<f32>();
+    for o in 0..outer {
+        for i in 0..inner {
+            let mut sum = 0f32;
+            for step in 0..len {
+                let k = if reverse { len - 1 - step } else { step };
+                let index = (o * len + k) * inner + i;
+                if exclusive {
+                    out[index] = sum;
+                    sum += x[index];
+                } else {
+                    sum += x[index];
+                    out[index] = sum;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl CumSum<f32> for Backend<Native> {
+    fn cumsum(&self,
+              x: &SharedTensor<f32>,
+              axis: usize,
+              exclusive: bool,
+              out: &mut SharedTensor<f32>)
+              -> Result<(), ::co::error::Error> {
+        cumsum_on(self, x, axis, exclusive, false, out)
+    }
+
+    fn cumsum_grad(&self,
+                   out_diff: &SharedTensor<f32>,
+                   axis: usize,
+                   exclusive: bool,
+                   x_diff: &mut SharedTensor<f32>)
+                   -> Result<(), ::co::error::Error> {
+        cumsum_on(self, out_diff, axis, exclusive, true, x_diff)
+    }
+}
+
+/// Returns the matrix of shape `[len, len]` that is `1` at `(to, from)` if the element `from`
+/// is part of the cumulative sum at `to`, and `0` elsewhere.
+///
+/// Multiplied from the left with [gemm][1] it computes the cumulative sum along the rows of a
+/// matrix with `len` rows, and its transpose computes the reverse cumulative sum. The
+/// matrices are cached like [ones][2].
+/// [1]: ../../coaster_blas/plugin/trait.Gemm.html
+/// [2]: ./fn.ones.html
+pub fn scan_matrix(len: usize, exclusive: bool) -> Rc<SharedTensor<f32>> {
+    constant_tensor("scan", &[len, exclusive as usize], &[len, len], |values| {
+        for (index, value) in values.iter_mut().enumerate() {
+            let (to, from) = (index / len, index % len);
+            let summed = if exclusive { from < to } else { from <= to };
+            *value = if summed { 1f32 } else { 0f32 };
+        }
+    })
+}
+
+/// Computes the cumulative sum with gemm and the [scan matrix][1], for backends without a
+/// dedicated kernel.
+/// [1]: ./fn.scan_matrix.html
+///
+/// The cost is linear in the size of `x`, times `len` for the scan and times `outer` for
+/// moving the outer axis out of the way when the summed axis is not the last one.
+#[cfg(any(feature = "cuda", test))]
+fn cumsum_by_gemm<B: IBackend + Gemm<f32> + BlasCopy<f32>>(backend: &B,
+                                                           x: &SharedTensor<f32>,
+                                                           axis: usize,
+                                                           exclusive: bool,
+                                                           reverse: bool,
+                                                           out: &mut SharedTensor<f32>)
+                                                           -> Result<(), ::co::error::Error> {
+    let (outer, len, inner) = axis_layout(x.desc(), axis);
+    let shape = x.desc().clone();
+    let scan = scan_matrix(len, exclusive);
+    let (scanned, transposed) = if reverse {
+        (Transpose::Trans, Transpose::NoTrans)
+    } else {
+        (Transpose::NoTrans, Transpose::Trans)
+    };
+    let (one, zero) = (native_scalar(1f32), native_scalar(0f32));
+    if inner == 1 {
+        // every row of the `[outer, len]` matrix is summed by the transposed scan matrix
+        let copy = try!(matrix_copy(backend, x, outer));
+        try!(out.resize(&[outer, len]));
+        try!(backend.gemm(&one,
+                          Transpose::NoTrans,
+                          copy.as_ref().unwrap_or(x),
+                          transposed,
+                          &scan,
+                          &zero,
+                          out));
+    } else {
+        // with the outer axis moved to the back, every column of the `[len, inner * outer]`
+        // matrix is summed by the scan matrix, and the outer axis is moved back afterwards
+        let mut columns = try!(transpose_matrix(backend, x, outer));
+        try!(columns.reshape(&[len, inner * outer]));
+        let mut sums = SharedTensor::new(&[len, inner * outer]);
+        try!(backend.gemm(&one, scanned, &scan, Transpose::NoTrans, &columns, &zero, &mut sums));
+        try!(sums.reshape(&[len * inner, outer]));
+        try!(out.resize(&[outer, len * inner]));
+        try!(backend.gemm(&one,
+                          Transpose::NoTrans,
+                          &identity(outer),
+                          Transpose::Trans,
+                          &sums,
+                          &zero,
+                          out));
+    }
+    try!(out.reshape(&shape));
+    Ok(())
+}
+
+#[cfg(feature = "cuda")]
+impl CumSum<f32> for Backend<Cuda> {
+    fn cumsum(&self,
+              x: &SharedTensor<f32>,
+              axis: usize,
+              exclusive: bool,
+              out: &mut SharedTensor<f32>)
+              -> Result<(), ::co::error::Error> {
+        cumsum_by_gemm(self, x, axis, exclusive, false, out)
+    }
+
+    fn cumsum_grad(&self,
+                   out_diff: &SharedTensor<f32>,
+                   axis: usize,
+                   exclusive: bool,
+                   x_diff: &mut SharedTensor<f32>)
+                   -> Result<(), ::co::error::Error> {
+        cumsum_by_gemm(self, out_diff, axis, exclusive, true, x_diff)
+    }
+}
+
+/// The precision in which [GemmAccumulate][1] sums up the products of a matrix multiplication.
+/// [1]: ./trait.GemmAccumulate.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GemmAccumulation {
+    /// Accumulate in the precision of the inputs with the [gemm][1] of the backend.
+    /// [1]: ../../coaster_blas/plugin/trait.Gemm.html
+    Input,
+    /// Accumulate in `f64`, regardless of the precision of the inputs and the output.
+    ///
+    /// Reduces the rounding error for large inner dimensions, where the partial sums of
+    /// `f32` soon grow too large to take small products into account.
+    F64,
+}
+
+/// Computes `c := alpha * op(a) * op(b) + beta * c` like [gemm][1] with a configurable
+/// [accumulation precision][2].
+/// [1]: ../../coaster_blas/plugin/trait.Gemm.html
+/// [2]: ./enum.GemmAccumulation.html
+///
+/// The matrices are given by the first dimension of the tensors and the product of the
+/// remaining ones, `op(a)` is `[m, k]`, `op(b)` is `[k, n]` and `c` is `[m, n]`.
+///
+/// Only the native backend implements it: cuBLAS has no f32 gemm that accumulates in f64,
+/// so other backends use their [gemm][1] directly.
+pub trait GemmAccumulate<F>: Gemm<F> {
+    /// Multiplies `op(a)` and `op(b)`, accumulating the products in `accumulation` precision.
+    fn gemm_accumulate(&self,
+                       accumulation: GemmAccumulation,
+                       alpha: &SharedTensor<F>,
+                       at: Transpose,
+                       a: &SharedTensor<F>,
+                       bt: Transpose,
+                       b: &SharedTensor<F>,
+                       beta: &SharedTensor<F>,
+                       c: &mut SharedTensor<F>)
+                       -> Result<(), ::co::error::Error>;
+}
+
+/// Returns the number of rows and columns of a tensor seen as a matrix.
+fn matrix_shape(desc: &[usize]) -> (usize, usize) {
+    let rows = desc[0];
+    let size = desc.iter().fold(1, |size, &dim| size * dim);
+    (rows, size / rows)
+}
+
+fn gemm_f64_on(native: &Backend<Native>,
+               alpha: &SharedTensor<f32>,
+               at: Transpose,
+               a: &SharedTensor<f32>,
+               bt: Transpose,
+               b: &SharedTensor<f32>,
+               beta: &SharedTensor<f32>,
+               c: &mut SharedTensor<f32>)
+               -> Result<(), ::co::error::Error> {
+    let (a_rows, a_cols) = matrix_shape(a.desc());
+    let (b_rows, b_cols) = matrix_shape(b.desc());
+    let transposed = |t: Transpose| match t {
+        Transpose::NoTrans => false,
+        _ => true,
+    };
+    let (m, k) = if transposed(at) { (a_cols, a_rows) } else { (a_rows, a_cols) };
+    let (b_k, n) = if transposed(bt) { (b_cols, b_rows) } else { (b_rows, b_cols) };
+    assert_eq!(k, b_k, "The inner dimensions of the matrices differ");
+    assert_eq!(m * n, c.desc().size());
+
+    let alpha = try!(alpha.read(native.device())).as_slice::<f32>()[0] as f64;
+    let beta = try!(beta.read(native.device())).as_slice::<f32>()[0] as f64;
+    let a = try!(a.read(native.device())).as_slice::<f32>();
+    let b = try!(b.read(native.device())).as_slice::<f32>();
+    let a_at = |i: usize, l: usize| if transposed(at) { a[l * a_cols + i] } else { a[i * a_cols + l] };
+    let b_at = |l: usize, j: usize| if transposed(bt) { b[j * b_cols + l] } else { b[l * b_cols + j] };
+    let c = try!(c.read_write(native.device())).as_mut_slice::<f32>();
+    for i in 0..m {
+        for j in 0..n {
+            let mut sum = 0f64;
+            for l in 0..k {
+                sum += a_at(i, l) as f64 * b_at(l, j) as f64;
+            }
+            // like BLAS, c is not read if beta is zero, so it may hold NaNs
+            let previous = if beta == 0f64 { 0f64 } else { beta * c[i * n + j] as f64 };
+            c[i * n + j] = (alpha * sum + previous) as f32;
+        }
+    }
+    Ok(())
+}
+
+impl GemmAccumulate<f32> for Backend<Native> {
+    fn gemm_accumulate(&self,
+                       accumulation: GemmAccumulation,
+                       alpha: &SharedTensor<f32>,
+                       at: Transpose,
+                       a: &SharedTensor<f32>,
+                       bt: Transpose,
+                       b: &SharedTensor<f32>,
+                       beta: &SharedTensor<f32>,
+                       c: &mut SharedTensor<f32>)
+                       -> Result<(), ::co::error::Error> {
+        match accumulation {
+            GemmAccumulation::Input => self.gemm(alpha, at, a, bt, b, beta, c),
+            GemmAccumulation::F64 => gemm_f64_on(self, alpha, at, a, bt, b, beta, c),
+        }
+    }
+}
+
+/// The scale of the last forward pass of a [LocalResponseNormalization][1], which its
+/// backward pass consumes.
+/// [1]: ./trait.LocalResponseNormalization.html
+///
+/// Backends that recompute the scale in the backward pass only keep the shape of the input,
+/// so that a backward pass without a preceding forward pass is an error on every backend.
+#[derive(Debug, Clone, Default)]
+pub struct LrnScale {
+    shape: Option<Vec<usize>>,
+    values: Vec<f32>,
+}
+
+impl LrnScale {
+    /// Save the scale `values` of a forward pass of an input of shape `shape`.
+    pub fn save(&mut self, shape: &[usize], values: Vec<f32>) {
+        self.shape = Some(shape.to_vec());
+        self.values = values;
+    }
+
+    /// Returns the saved scale of an input of shape `shape` and forgets it.
+    ///
+    /// Returns an error if no scale has been saved since the last call or if it has been
+    /// saved for an input of another shape.
+    pub fn take(&mut self, shape: &[usize]) -> Result<Vec<f32>, ::co::error::Error> {
+        match self.shape.take() {
+            Some(ref saved) if &saved[..] == shape => Ok(::std::mem::replace(&mut self.values, Vec::new())),
+            Some(_) => {
+                self.values.clear();
+                Err(::co::error::Error::Plugin(
+                    ::co::plugin::Error::Operation("The saved scale of the LRN doesn't match its input")))
+            }
+            None => {
+                Err(::co::error::Error::Plugin(
+                    ::co::plugin::Error::Operation("The backward pass of a LRN needs a preceding forward pass")))
+            }
+        }
+    }
+
+    /// Forget the saved scale.
+    pub fn clear(&mut self) {
+        self.shape = None;
+        self.values.clear();
+    }
+}
+
+/// Applies local response normalization across the channels, the second dimension, of a
+/// tensor, see the [LRN][1] layer.
+/// [1]: ../layers/common/lrn/index.html
+pub trait LocalResponseNormalization<F> {
+    /// The config of the normalization, which layers with the same hyperparameters share.
+    type CLRN: ::std::fmt::Debug + 'static;
+
+    /// Create the config of a normalization over `local_size` channels.
+    fn new_lrn_config(&self,
+                      local_size: usize,
+                      alpha: f32,
+                      beta: f32,
+                      k: f32)
+                      -> Result<Self::CLRN, ::co::error::Error>;
+
+    /// Normalizes `x` into `result` and saves the scale for the backward pass.
+    fn lrn_forward(&self,
+                   config: &Self::CLRN,
+                   scale: &mut LrnScale,
+                   x: &SharedTensor<F>,
+                   result: &mut SharedTensor<F>)
+                   -> Result<(), ::co::error::Error>;
+
+    /// Computes the gradient of `x` from the gradient of the `result` of the preceding
+    /// forward pass, consuming its scale.
+    ///
+    /// Returns a plugin error if there is no preceding forward pass of `x`.
+    fn lrn_backward(&self,
+                    config: &Self::CLRN,
+                    scale: &mut LrnScale,
+                    x: &SharedTensor<F>,
+                    result: &SharedTensor<F>,
+                    result_diff: &SharedTensor<F>,
+                    x_diff: &mut SharedTensor<F>)
+                    -> Result<(), ::co::error::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The config of a [LocalResponseNormalization][1] on the native backend.
+/// [1]: ./trait.LocalResponseNormalization.html
+pub struct NativeLrnConfig {
+    local_size: usize,
+    alpha: f32,
+    beta: f32,
+    k: f32,
+}
+
+impl NativeLrnConfig {
+    /// Returns the number of channels and the number of values per channel of a sample.
+    fn channel_dims(shape: &[usize]) -> (usize, usize) {
+        assert!(shape.len() >= 2,
+                "A LRN expects the channels in the second dimension of its input, but its shape is {:?}",
+                shape);
+        (shape[1], shape[2..].iter().fold(1, |size, dim| size * dim))
+    }
+
+    /// Returns the channels of the window of the channel `channel`.
+    fn window(&self, channel: usize, channels: usize) -> Range<usize> {
+        let start = channel as isize - ((self.local_size - 1) / 2) as isize;
+        let end = ::std::cmp::min(start + self.local_size as isize, channels as isize);
+        (start.max(0) as usize)..(end as usize)
+    }
+
+    /// Returns the scale of every value of the input `input` of shape `shape`.
+    fn scale(&self, shape: &[usize], input: &[f32]) -> Vec<f32> {
+        let (channels, inner) = Self::channel_dims(shape);
+        let norm = self.alpha / self.local_size as f32;
+        let mut scale = vec![self.k; input.len()];
+        for (sample, sample_scale) in input.chunks(channels * inner).zip(scale.chunks_mut(channels * inner)) {
+            for channel in 0..channels {
+                for other in self.window(channel, channels) {
+                    for i in 0..inner {
+                        let value = sample[other * inner + i];
+                        sample_scale[channel * inner + i] += norm * value * value;
+                    }
+                }
+            }
+        }
+        scale
+    }
+
+    /// Computes the gradient of the input from the gradient of the output and the scale.
+    fn input_gradient(&self,
+                      shape: &[usize],
+                      scale: &[f32],
+                      input: &[f32],
+                      output: &[f32],
+                      output_gradient: &[f32])
+                      -> Vec<f32> {
+        let (channels, inner) = Self::channel_dims(shape);
+        let norm = 2f32 * self.alpha * self.beta / self.local_size as f32;
+        // the contribution of each value to the scales of the values whose window contains it
+        let ratio = output_gradient.iter()
+            .zip(output)
+            .zip(scale)
+            .map(|((gradient, output), scale)| gradient * output / scale)
+            .collect::<Vec<_>>();
+
+        let mut gradient = output_gradient.iter()
+            .zip(scale)
+            .map(|(gradient, scale)| gradient * scale.powf(-self.beta))
+            .collect::<Vec<_>>();
+        let sample_size = channels * inner;
+        for sample in 0..input.len() / sample_size {
+            let offset = sample * sample_size;
+            for channel in 0..channels {
+                for other in self.window(channel, channels) {
+                    for i in 0..inner {
+                        let (target, source) = (offset + other * inner + i, offset + channel * inner + i);
+                        gradient[target] -= norm * input[target] * ratio[source];
+                    }
+                }
+            }
+        }
+        gradient
+    }
+}
+
+impl LocalResponseNormalization<f32> for Backend<Native> {
+    type CLRN = NativeLrnConfig;
+
+    fn new_lrn_config(&self,
+                      local_size: usize,
+                      alpha: f32,
+                      beta: f32,
+                      k: f32)
+                      -> Result<NativeLrnConfig, ::co::error::Error> {
+        Ok(NativeLrnConfig {
+            local_size: local_size,
+            alpha: alpha,
+            beta: beta,
+            k: k,
+        })
+    }
+
+    fn lrn_forward(&self,
+                   config: &NativeLrnConfig,
+                   scale: &mut LrnScale,
+                   x: &SharedTensor<f32>,
+                   result: &mut SharedTensor<f32>)
+                   -> Result<(), ::co::error::Error> {
+        let input = try!(x.read(self.device())).as_slice::<f32>();
+        let values = config.scale(x.desc(), input);
+        {
+            let result = try!(result.write_only(self.device())).as_mut_slice::<f32>();
+            for ((result, value), scale) in result.iter_mut().zip(input).zip(&values) {
+                *result = value * scale.powf(-config.beta);
+            }
+        }
+        scale.save(x.desc(), values);
+        Ok(())
+    }
+
+    fn lrn_backward(&self,
+                    config: &NativeLrnConfig,
+                    scale: &mut LrnScale,
+                    x: &SharedTensor<f32>,
+                    result: &SharedTensor<f32>,
+                    result_diff: &SharedTensor<f32>,
+                    x_diff: &mut SharedTensor<f32>)
+                    -> Result<(), ::co::error::Error> {
+        let values = try!(scale.take(x.desc()));
+        let gradient = config.input_gradient(x.desc(),
+                                             &values,
+                                             try!(x.read(self.device())).as_slice::<f32>(),
+                                             try!(result.read(self.device())).as_slice::<f32>(),
+                                             try!(result_diff.read(self.device())).as_slice::<f32>());
+        write_to_memory(try!(x_diff.write_only(self.device())), &gradient);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl LocalResponseNormalization<f32> for Backend<Cuda> {
+    type CLRN = <Backend<Cuda> as conn::NN<f32>>::CLRN;
+
+    fn new_lrn_config(&self,
+                      local_size: usize,
+                      alpha: f32,
+                      beta: f32,
+                      k: f32)
+                      -> Result<<Backend<Cuda> as conn::NN<f32>>::CLRN, ::co::error::Error> {
+        conn::LRN::new_lrn_config(self, local_size as u32, alpha as f64, beta as f64, k as f64)
+    }
+
+    fn lrn_forward(&self,
+                   config: &<Backend<Cuda> as conn::NN<f32>>::CLRN,
+                   scale: &mut LrnScale,
+                   x: &SharedTensor<f32>,
+                   result: &mut SharedTensor<f32>)
+                   -> Result<(), ::co::error::Error> {
+        try!(conn::LRN::lrn(self, x, result, config));
+        // cuDNN recomputes the scale in the backward pass
+        scale.save(x.desc(), Vec::new());
+        Ok(())
+    }
+
+    fn lrn_backward(&self,
+                    config: &<Backend<Cuda> as conn::NN<f32>>::CLRN,
+                    scale: &mut LrnScale,
+                    x: &SharedTensor<f32>,
+                    result: &SharedTensor<f32>,
+                    result_diff: &SharedTensor<f32>,
+                    x_diff: &mut SharedTensor<f32>)
+                    -> Result<(), ::co::error::Error> {
+        try!(scale.take(x.desc()));
+        conn::LRN::lrn_grad(self, result, result_diff, x, x_diff, config)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The window of a pooling: its shape, stride and padding in every spatial dimension.
+pub struct PoolingWindow {
+    /// The shape of the window.
+    pub filter: Vec<usize>,
+    /// The stride of the window.
+    pub stride: Vec<usize>,
+    /// The padding on both sides of the input.
+    pub padding: Vec<usize>,
+}
+
+/// Max pooling whose padded border is never selected, as if it was negative infinity.
+///
+/// This is the convention of cuDNN, so CUDA runs the [pooling][1] of coaster-nn. The
+/// native pooling of coaster-nn pads with zeros, so a padded max pooling on the native
+/// backend selects the maxima itself.
+/// [1]: ../../coaster_nn/plugin/trait.Pooling.html
+pub trait PaddedMaxPooling<F>: conn::Pooling<F> {
+    /// Computes the maximum of every window of `x`, whose geometry `window` matches `config`.
+    fn padded_pooling_max(&self,
+                          x: &SharedTensor<F>,
+                          result: &mut SharedTensor<F>,
+                          config: &Self::CPOOL,
+                          window: &PoolingWindow)
+                          -> Result<(), ::co::error::Error>;
+
+    /// Routes the gradient of every window to its maximum in `x`.
+    fn padded_pooling_max_grad(&self,
+                               result: &SharedTensor<F>,
+                               result_diff: &SharedTensor<F>,
+                               x: &SharedTensor<F>,
+                               x_diff: &mut SharedTensor<F>,
+                               config: &Self::CPOOL,
+                               window: &PoolingWindow)
+                               -> Result<(), ::co::error::Error>;
+}
+
+/// Returns the row-major multi-index of `index` in a tensor of shape `dims`.
+fn unravel_index(index: usize, dims: &[usize]) -> Vec<usize> {
+    let mut index = index;
+    let mut multi_index = vec![0; dims.len()];
+    for (dim, coordinate) in dims.iter().zip(multi_index.iter_mut()).rev() {
+        *coordinate = index % dim;
+        index /= *dim;
+    }
+    multi_index
+}
+
+/// Returns the index of the maximum of every pooling window of the input `input` of shape
+/// `shape`, skipping the padded border.
+fn max_pooling_argmax(shape: &[usize], window: &PoolingWindow, input: &[f32]) -> Vec<usize> {
+    let spatial = &shape[2..];
+    let output_spatial = spatial.iter()
+        .enumerate()
+        .map(|(d, &dim)| (dim + 2 * window.padding[d] - window.filter[d]) / window.stride[d] + 1)
+        .collect::<Vec<_>>();
+    let plane_size = spatial.iter().fold(1, |size, dim| size * dim);
+    let output_plane_size = output_spatial.iter().fold(1, |size, dim| size * dim);
+    let window_size = window.filter.iter().fold(1, |size, dim| size * dim);
+
+    let mut argmax = Vec::with_capacity(shape[0] * shape[1] * output_plane_size);
+    for plane in 0..shape[0] * shape[1] {
+        for position in 0..output_plane_size {
+            let output_coordinates = unravel_index(position, &output_spatial);
+            let mut best: Option<usize> = None;
+            'window: for offset in 0..window_size {
+                let offset_coordinates = unravel_index(offset, &window.filter);
+                let mut index = 0;
+                for d in 0..spatial.len() {
+                    let coordinate = (output_coordinates[d] * window.stride[d] + offset_coordinates[d]) as isize -
+                                     window.padding[d] as isize;
+                    if coordinate < 0 || coordinate >= spatial[d] as isize {
+                        continue 'window;
+                    }
+                    index = index * spatial[d] + coordinate as usize;
+                }
+                let index = plane * plane_size + index;
+                if best.map_or(true, |best| input[index] > input[best]) {
+                    best = Some(index);
+                }
+            }
+            // the padding is smaller than the window, so every window covers an input value
+            argmax.push(best.unwrap());
+        }
+    }
+    argmax
+}
+
+impl PaddedMaxPooling<f32> for Backend<Native> {
+    fn padded_pooling_max(&self,
+                          x: &SharedTensor<f32>,
+                          result: &mut SharedTensor<f32>,
+                          config: &Self::CPOOL,
+                          window: &PoolingWindow)
+                          -> Result<(), ::co::error::Error> {
+        if window.padding.iter().all(|&pad| pad == 0) {
+            return conn::Pooling::pooling_max(self, x, result, config);
+        }
+        let input = try!(x.read(self.device())).as_slice::<f32>();
+        let output = max_pooling_argmax(x.desc(), window, input)
+            .iter()
+            .map(|&index| input[index])
+            .collect::<Vec<_>>();
+        write_to_memory(try!(result.write_only(self.device())), &output);
+        Ok(())
+    }
+
+    fn padded_pooling_max_grad(&self,
+                               result: &SharedTensor<f32>,
+                               result_diff: &SharedTensor<f32>,
+                               x: &SharedTensor<f32>,
+                               x_diff: &mut SharedTensor<f32>,
+                               config: &Self::CPOOL,
+                               window: &PoolingWindow)
+                               -> Result<(), ::co::error::Error> {
+        if window.padding.iter().all(|&pad| pad == 0) {
+            return conn::Pooling::pooling_max_grad(self, result, result_diff, x, x_diff, config);
+        }
+        let argmax = max_pooling_argmax(x.desc(), window, try!(x.read(self.device())).as_slice::<f32>());
+        let result_diff = try!(result_diff.read(self.device())).as_slice::<f32>();
+        let mut gradient = vec![0f32; x.desc().size()];
+        for (&index, &diff) in argmax.iter().zip(result_diff) {
+            gradient[index] += diff;
+        }
+        write_to_memory(try!(x_diff.write_only(self.device())), &gradient);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cuda")]
+impl PaddedMaxPooling<f32> for Backend<Cuda> {
+    fn padded_pooling_max(&self,
+                          x: &SharedTensor<f32>,
+                          result: &mut SharedTensor<f32>,
+                          config: &Self::CPOOL,
+                          window: &PoolingWindow)
+                          -> Result<(), ::co::error::Error> {
+        conn::Pooling::pooling_max(self, x, result, config)
+    }
+
+    fn padded_pooling_max_grad(&self,
+                               result: &SharedTensor<f32>,
+                               result_diff: &SharedTensor<f32>,
+                               x: &SharedTensor<f32>,
+                               x_diff: &mut SharedTensor<f32>,
+                               config: &Self::CPOOL,
+                               window: &PoolingWindow)
+                               -> Result<(), ::co::error::Error> {
+        conn::Pooling::pooling_max_grad(self, result, result_diff, x, x_diff, config)
+    }
+}
+
+/// Encapsulates all traits required by Solvers.
+// pub trait SolverOps<F> : Axpby<F> + Dot<F> + BlasCopy<F> {}
+//
+// impl<T: Axpby<f32> + Dot<f32> + BlasCopy<f32>> SolverOps<f32> for T {}
+pub trait SolverOps<F>
+    : LayerOps<F> + Axpby<F> + Dot<F> + Nrm2<F> + Asum<F> + BlasCopy<F> + ScaledAdd<F> + SgdUpdate<F> {}
+
+impl<T: LayerOps<f32> + Axpby<f32> + Dot<f32> + Nrm2<f32> + Asum<f32> + BlasCopy<f32> + ScaledAdd<f32> + SgdUpdate<f32>>
+    SolverOps<f32> for T {}
+
+/// Encapsulates all traits used in Layers.
+pub trait LayerOps<F> : conn::Convolution<F>
+                      + conn::Pooling<F>
+                      + conn::Relu<F> + conn::ReluPointwise<F>
+                      + conn::Sigmoid<F> + conn::SigmoidPointwise<F>
+                      + conn::Tanh<F> + conn::TanhPointwise<F>
+                      + conn::Softmax<F> + conn::LogSoftmax<F>
+                      + LocalResponseNormalization<F> + PaddedMaxPooling<F>
+                      + Gemm<F> + Axpy<F> + Fill<F> + WeightedSum<F> {}
+
+impl<T: conn::Convolution<f32>
+      + conn::Pooling<f32>
+      + conn::Relu<f32> + conn::ReluPointwise<f32>
+      + conn::Sigmoid<f32> + conn::SigmoidPointwise<f32>
+      + conn::Tanh<f32> + conn::TanhPointwise<f32>
+      + conn::Softmax<f32> + conn::LogSoftmax<f32>
+      + LocalResponseNormalization<f32> + PaddedMaxPooling<f32>
+      + Gemm<f32> + Axpy<f32> + Fill<f32> + WeightedSum<f32>> LayerOps<f32> for T {}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+/// Fixtures shared by the tests of all modules.
+pub mod testing {
+    use co::SharedTensor;
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::{Arc, RwLock};
+    use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use super::{ArcLock, native_backend, write_to_memory};
+
+    static TEMP_PATHS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    /// Returns a path in the temporary directory that ends with `name` and is used by no
+    /// other test, neither of this process nor of one that runs concurrently.
+    pub fn temp_path(name: &str) -> PathBuf {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        env::temp_dir().join(format!("juice_{}_{}_{}_{}",
+                                     now.as_secs(),
+                                     now.subsec_nanos(),
+                                     TEMP_PATHS.fetch_add(1, Ordering::SeqCst),
+                                     name))
+    }
+
+    /// Returns a tensor of `shape` with the values `data`, written on the native backend.
+    pub fn shared_tensor(shape: &[usize], data: &[f32]) -> SharedTensor<f32> {
+        let native = native_backend();
+        let mut tensor = SharedTensor::new(&shape);
+        write_to_memory(tensor.write_only(native.device()).unwrap(), data);
+        tensor
+    }
+
+    /// Returns a blob of `shape` with the values `data`, written on the native backend.
+    pub fn tensor(shape: &[usize], data: &[f32]) -> ArcLock<SharedTensor<f32>> {
+        Arc::new(RwLock::new(shared_tensor(shape, data)))
+    }
+
+    /// Returns the values of `tensor`, read on the native backend.
+    pub fn read_shared(tensor: &SharedTensor<f32>) -> Vec<f32> {
+        let native = native_backend();
+        tensor.read(native.device()).unwrap().as_slice::<f32>().to_vec()
+    }
+
+    /// Returns the values of the blob `tensor`, read on the native backend.
+    pub fn read(tensor: &ArcLock<SharedTensor<f32>>) -> Vec<f32> {
+        read_shared(&tensor.read().unwrap())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::prelude::*;
+    use conn::Softmax;
+    use std::sync::{Arc, RwLock};
+    use super::*;
+    use super::testing::shared_tensor;
+
+    #[test]
+    #[cfg(feature="native")]
+    fn softmax_cross_entropy_matches_softmax_then_subtract() {
+        let native = native_backend();
+        let logits = shared_tensor(&[2, 3], &[1.0, 2.0, 3.0, -1.0, 0.5, 10.0]);
+        let one_hot = shared_tensor(&[2, 3], &[0.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+        let labels = shared_tensor(&[2], &[1.0, 0.0]);
+
+        let mut probabilities = SharedTensor::new(&[2, 3]);
+        for sample in 0..2 {
+            let start = sample * 3;
+            let logits = logits.read(native.device()).unwrap().as_slice::<f32>();
+            let sample_logits = shared_tensor(&[3], &logits[start..start + 3]);
+            let mut sample_probabilities = SharedTensor::new(&[3]);
+            native.softmax(&sample_logits, &mut sample_probabilities).unwrap();
+            let sample_probabilities = sample_probabilities.read(native.device()).unwrap()
+                .as_slice::<f32>();
+            write_to_memory_offset(probabilities.write_only(native.device()).unwrap(),
+                                   sample_probabilities,
+                                   start);
+        }
+        let probabilities = probabilities.read(native.device()).unwrap().as_slice::<f32>();
+        let targets = one_hot.read(native.device()).unwrap().as_slice::<f32>();
+        let expected_loss = -(probabilities[1].ln() + probabilities[3].ln()) / 2.0;
+
+        let mut gradient = SharedTensor::new(&[2, 3]);
+        let loss = native.softmax_cross_entropy(&logits, &one_hot, &mut gradient).unwrap();
+        let mut index_gradient = SharedTensor::new(&[2, 3]);
+        let index_loss = native.softmax_cross_entropy_index(&logits, &labels, &mut index_gradient).unwrap();
+
+        assert!((loss - expected_loss).abs() < 1e-5);
+        assert!((index_loss - expected_loss).abs() < 1e-5);
+        let gradient = gradient.read(native.device()).unwrap().as_slice::<f32>();
+        let index_gradient = index_gradient.read(native.device()).unwrap().as_slice::<f32>();
+        for i in 0..6 {
+            let expected = probabilities[i] - targets[i];
+            assert!((gradient[i] - expected).abs() < 1e-5);
+            assert!((index_gradient[i] - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn softmax_cross_entropy_rejects_mismatched_targets_and_invalid_labels() {
+        let native = native_backend();
+        let logits = shared_tensor(&[2, 3], &[1.0, 2.0, 3.0, -1.0, 0.5, 10.0]);
+        let mut gradient = SharedTensor::new(&[2, 3]);
+        assert!(native.softmax_cross_entropy(&logits, &shared_tensor(&[2, 2], &[0.0, 1.0, 1.0, 0.0]), &mut gradient)
+            .is_err());
+        assert!(native.softmax_cross_entropy_index(&logits, &shared_tensor(&[3], &[0.0, 1.0, 2.0]), &mut gradient)
+            .is_err());
+        // a label of 3 would mark the first class of the next sample
+        for labels in &[[0f32, 3.0], [-1.0, 0.0], [0.5, 1.0]] {
+            assert!(native.softmax_cross_entropy_index(&logits, &shared_tensor(&[2], labels), &mut gradient).is_err());
+        }
+        assert!(native.softmax_cross_entropy_index(&logits, &shared_tensor(&[2], &[0.0, 2.0]), &mut gradient).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cosine_similarity_of_rows() {
+        let native = native_backend();
+        let x = shared_tensor(&[2, 3], &[1.0, 0.0, 0.0, 1.0, 2.0, 3.0]);
+        let y = shared_tensor(&[2, 3], &[0.0, 1.0, 0.0, 1.0, 2.0, 3.0]);
+        let mut similarity = SharedTensor::new(&[2]);
+        native.cosine_similarity(&x, &y, &mut similarity).unwrap();
+
+        let similarity = similarity.read(native.device()).unwrap().as_slice::<f32>();
+        assert!(similarity[0].abs() < 1e-6);
+        assert!((similarity[1] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cosine_similarity_gradient_matches_finite_differences() {
+        let native = native_backend();
+        let x_data = [0.5f32, -1.0, 2.0, 1.5, 0.3, -0.7];
+        let y_data = [1.0f32, 0.4, -0.2, -0.5, 1.2, 0.9];
+        let weights = [1.0f32, 0.5];
+        let objective = |x_data: &[f32]| {
+            let mut similarity = SharedTensor::new(&[2]);
+            native.cosine_similarity(&shared_tensor(&[2, 3], x_data), &shared_tensor(&[2, 3], &y_data), &mut similarity)
+                .unwrap();
+            let similarity = similarity.read(native.device()).unwrap().as_slice::<f32>();
+            similarity[0] * weights[0] + similarity[1] * weights[1]
+        };
+
+        let mut x_diff = SharedTensor::new(&[2, 3]);
+        let mut y_diff = SharedTensor::new(&[2, 3]);
+        native.cosine_similarity_grad(&shared_tensor(&[2, 3], &x_data),
+                                    &shared_tensor(&[2, 3], &y_data),
+                                    &shared_tensor(&[2], &weights),
+                                    &mut x_diff,
+                                    &mut y_diff)
+            .unwrap();
+        let x_diff = x_diff.read(native.device()).unwrap().as_slice::<f32>();
+
+        let h = 1e-2f32;
+        for i in 0..6 {
+            let mut plus = x_data.to_vec();
+            plus[i] += h;
+            let mut minus = x_data.to_vec();
+            minus[i] -= h;
+            let numeric = (objective(&plus) - objective(&minus)) / (2.0 * h);
+            assert!((x_diff[i] - numeric).abs() < 1e-3, "{} != {}", x_diff[i], numeric);
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cosine_similarity_rejects_rows_of_different_shapes() {
+        let native = native_backend();
+        let x = shared_tensor(&[2, 3], &[1.0, 0.0, 0.0, 1.0, 2.0, 3.0]);
+        let y = shared_tensor(&[3, 2], &[0.0, 1.0, 0.0, 1.0, 2.0, 3.0]);
+        let (mut x_diff, mut y_diff) = (SharedTensor::new(&[2, 3]), SharedTensor::new(&[2, 3]));
+        assert!(native.cosine_similarity(&x, &y, &mut SharedTensor::new(&[2])).is_err());
+        assert!(native.cosine_similarity_grad(&x, &y, &shared_tensor(&[2], &[1.0, 1.0]), &mut x_diff, &mut y_diff)
+            .is_err());
+        assert!(native.cosine_similarity_grad(&x, &x, &shared_tensor(&[3], &[1.0, 1.0, 1.0]), &mut x_diff, &mut y_diff)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn fill_sets_every_element() {
+        let native = native_backend();
+        let mut x = SharedTensor::<f32>::new(&[2, 3]);
+        native.fill(&mut x, 3.5f32).unwrap();
+        assert_eq!(&[3.5f32; 6], x.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn ones_are_shared_by_shape() {
+        let native = native_backend();
+        let ones_2x3 = ones(&[2, 3]);
+        assert_eq!(&[1f32; 6], ones_2x3.read(native.device()).unwrap().as_slice::<f32>());
+        assert!(::std::rc::Rc::ptr_eq(&ones_2x3, &ones(&[2, 3])));
+        assert_eq!(vec![3, 2], ones(&[3, 2]).desc().clone());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cleared_constants_are_created_again() {
+        let ones_2x3 = ones(&[2, 3]);
+        clear_constants();
+        assert!(!::std::rc::Rc::ptr_eq(&ones_2x3, &ones(&[2, 3])));
+        assert!(::std::rc::Rc::ptr_eq(&ones(&[2, 3]), &ones(&[2, 3])));
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn constant_cache_drops_the_least_recently_requested_tensors() {
+        let bytes = 6 * ::std::mem::size_of::<f32>();
+        let mut cache = ConstantCache::new(2 * bytes, 3);
+        let first = cache.get("ones", &[2, 3], || SharedTensor::new(&[2, 3]));
+        let second = cache.get("ones", &[3, 2], || SharedTensor::new(&[3, 2]));
+        // requesting the first tensor again makes the second one the least recent
+        assert!(::std::rc::Rc::ptr_eq(&first, &cache.get("ones", &[2, 3], || SharedTensor::new(&[2, 3]))));
+        cache.get("ones", &[6], || SharedTensor::new(&[6]));
+
+        assert_eq!(2 * bytes, cache.bytes);
+        assert!(::std::rc::Rc::ptr_eq(&first, &cache.get("ones", &[2, 3], || SharedTensor::new(&[2, 3]))));
+        assert!(!::std::rc::Rc::ptr_eq(&second, &cache.get("ones", &[3, 2], || SharedTensor::new(&[3, 2]))));
+        // a tensor that exceeds the budget on its own is not cached
+        cache.get("ones", &[13], || SharedTensor::new(&[13]));
+        assert_eq!(2, cache.tensors.len());
+
+        // small tensors are limited by their number
+        for value in 0..3 {
+            cache.get("scalar", &[value], || SharedTensor::new(&[1]));
+        }
+        assert_eq!(3, cache.tensors.len());
+        assert_eq!(bytes + 2 * ::std::mem::size_of::<f32>(), cache.bytes);
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn row_dots_and_scaled_rows() {
+        let native = native_backend();
+        let x = shared_tensor(&[3, 2], &[1.0, 2.0, 3.0, 4.0, -1.0, 0.5]);
+        let y = shared_tensor(&[3, 2], &[2.0, 1.0, 0.0, -1.0, 4.0, 2.0]);
+        let mut dots = SharedTensor::new(&[3]);
+        row_dots(&native, &x, &y, &mut dots).unwrap();
+        assert_eq!(&[4f32, -4.0, -3.0], dots.read(native.device()).unwrap().as_slice::<f32>());
+
+        let mut scaled = shared_tensor(&[3, 2], &[1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+        scale_rows(&native, &shared_tensor(&[3], &[2.0, -1.0, 0.0]), &x, 1f32, &mut scaled).unwrap();
+        assert_eq!(&[3f32, 5.0, -2.0, -3.0, 1.0, 1.0],
+                   scaled.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn sum_and_broadcast_axis_by_gemm() {
+        let native = native_backend();
+        let x = shared_tensor(&[2, 2, 2], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let mut sums = SharedTensor::new(&[2, 2]);
+        let mut repeated = SharedTensor::new(&[2, 2, 2]);
+        let expected_sums = [[6f32, 8.0, 10.0, 12.0], [4.0, 6.0, 12.0, 14.0], [3.0, 7.0, 11.0, 15.0]];
+        for axis in 0..3 {
+            let layout = axis_layout(x.desc(), axis);
+            sum_axis_by_gemm(&native, &x, layout, &mut sums).unwrap();
+            assert_eq!(&expected_sums[axis], sums.read(native.device()).unwrap().as_slice::<f32>());
+
+            broadcast_axis_by_gemm(&native, &sums, layout, &mut repeated).unwrap();
+            let (_, len, inner) = layout;
+            let repeated = repeated.read(native.device()).unwrap().as_slice::<f32>();
+            let sums = sums.read(native.device()).unwrap().as_slice::<f32>();
+            for (index, value) in repeated.iter().enumerate() {
+                assert_eq!(sums[index / (len * inner) * inner + index % inner], *value);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn pad_sequences_of_ragged_lengths() {
+        let native = native_backend();
+        let sequences = vec![vec![1.0, 2.0], vec![3.0, 4.0, 5.0], vec![6.0]];
+        let (padded, mask) = pad_sequences(&sequences, -1.0).unwrap();
+
+        assert_eq!(&vec![3, 3], padded.desc());
+        assert_eq!(&[1.0f32, 2.0, -1.0, 3.0, 4.0, 5.0, 6.0, -1.0, -1.0],
+                   padded.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[1.0f32, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0],
+                   mask.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn pad_sequences_rejects_empty_sequences() {
+        let sequences = vec![vec![1.0, 2.0], vec![], vec![6.0]];
+        let error = pad_sequences(&sequences, 0.0).err().unwrap();
+        assert_eq!("Sequence 1 is empty and can not be padded", error);
+        assert!(pad_sequences(&[], 0.0).is_err());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn sorted_sequences_can_be_restored() {
+        let native = native_backend();
+        let sequences = vec![vec![1.0], vec![2.0, 3.0, 4.0], vec![5.0, 6.0]];
+        let (padded, mask, order) = pad_sorted_sequences(&sequences, 0.0).unwrap();
+
+        assert_eq!(vec![1, 2, 0], order);
+        assert_eq!(&[2.0f32, 3.0, 4.0, 5.0, 6.0, 0.0, 1.0, 0.0, 0.0],
+                   padded.read(native.device()).unwrap().as_slice::<f32>());
+        let restored = restore_order(&padded, &order).unwrap();
+        assert_eq!(&[1.0f32, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0, 6.0, 0.0],
+                   restored.read(native.device()).unwrap().as_slice::<f32>());
+        let restored_mask = restore_order(&mask, &order).unwrap();
+        assert_eq!(&[1.0f32, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0],
+                   restored_mask.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn asum_axis_of_rows_and_columns() {
+        let native = native_backend();
+        let x = shared_tensor(&[2, 3], &[1.0, -2.0, 3.0, -4.0, 0.0, 6.0]);
+
+        let mut rows = SharedTensor::new(&[2]);
+        native.asum_axis(&x, 1, &mut rows).unwrap();
+        assert_eq!(&vec![2], rows.desc());
+        assert_eq!(&[6.0f32, 10.0], rows.read(native.device()).unwrap().as_slice::<f32>());
+
+        let mut columns = SharedTensor::new(&[3]);
+        native.asum_axis(&x, 0, &mut columns).unwrap();
+        assert_eq!(&vec![3], columns.desc());
+        assert_eq!(&[5.0f32, 2.0, 9.0], columns.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn asum_axis_gradient_is_scaled_sign() {
+        let native = native_backend();
+        let x = shared_tensor(&[2, 3], &[1.0, -2.0, 3.0, -4.0, 0.0, 6.0]);
+
+        let mut x_diff = SharedTensor::new(&[2, 3]);
+        native.asum_axis_grad(&x, 1, &shared_tensor(&[2], &[0.5, 2.0]), &mut x_diff).unwrap();
+        assert_eq!(&[0.5f32, -0.5, 0.5, -2.0, 0.0, 2.0],
+                   x_diff.read(native.device()).unwrap().as_slice::<f32>());
+
+        native.asum_axis_grad(&x, 0, &shared_tensor(&[3], &[1.0, 2.0, 3.0]), &mut x_diff).unwrap();
+        assert_eq!(&[1.0f32, -2.0, 3.0, -1.0, 0.0, 3.0],
+                   x_diff.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    fn duplicate<T: Copy>(value: T) -> (T, T) {
+        (value, value)
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn blas_copy_keeps_marker_copy_usable() {
+        let native = native_backend();
+        let x = shared_tensor(&[3], &[1.0, 2.0, 3.0]);
+        let mut y = SharedTensor::<f32>::new(&[3]);
+        native.copy(&x, &mut y).unwrap();
+        assert_eq!(&[1.0f32, 2.0, 3.0], y.read(native.device()).unwrap().as_slice::<f32>());
+        // `Copy` still refers to the marker trait next to the BLAS operation
+        assert_eq!((2usize, 2usize), duplicate(2usize));
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn scaled_add_matches_axpy() {
+        let native = native_backend();
+        let x = shared_tensor(&[4], &[1.0, -2.0, 0.5, 4.0]);
+        let mut expected = shared_tensor(&[4], &[0.25, 1.0, -3.0, 2.0]);
+        native.axpy(&native_scalar(-0.75f32), &x, &mut expected).unwrap();
+
+        let mut y = shared_tensor(&[4], &[0.25, 1.0, -3.0, 2.0]);
+        native.scaled_add(&mut y, -0.75f32, &x).unwrap();
+        assert_eq!(expected.read(native.device()).unwrap().as_slice::<f32>(),
+                   y.read(native.device()).unwrap().as_slice::<f32>());
+
+        let mut expected = shared_tensor(&[4], &[0.25, 1.0, -3.0, 2.0]);
+        native.axpby(&native_scalar(0.1f32), &x, &native_scalar(0.9f32), &mut expected).unwrap();
+        let mut y = shared_tensor(&[4], &[0.25, 1.0, -3.0, 2.0]);
+        native.scaled_axpby(0.1f32, &x, 0.9f32, &mut y).unwrap();
+        assert_eq!(expected.read(native.device()).unwrap().as_slice::<f32>(),
+                   y.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn sgd_update_matches_composed_ops() {
+        let native = native_backend();
+        let weight_values = [0.25f32, -1.5, 3.0, 0.1, -0.7];
+        let gradient_values = [0.3f32, 0.01, -2.0, 1.25, 0.0];
+        let history_values = [0.5f32, -0.125, 0.2, 0.0, 1.0];
+
+        let mut weight = shared_tensor(&[5], &weight_values);
+        let mut gradient = shared_tensor(&[5], &gradient_values);
+        let mut history = shared_tensor(&[5], &history_values);
+        native.axpy(&native_scalar(0.01f32), &weight, &mut gradient).unwrap();
+        native.axpby(&native_scalar(0.1f32), &gradient, &native_scalar(0.9f32), &mut history).unwrap();
+        native.copy(&history, &mut gradient).unwrap();
+        native.scaled_add(&mut weight, -1f32, &gradient).unwrap();
+
+        let mut fused_weight = shared_tensor(&[5], &weight_values);
+        let mut fused_gradient = shared_tensor(&[5], &gradient_values);
+        let mut fused_history = shared_tensor(&[5], &history_values);
+        native.sgd_update(&mut fused_weight, &mut fused_gradient, &mut fused_history, 0.1, 0.9, 0.01).unwrap();
+
+        for &(expected, actual) in &[(&weight, &fused_weight), (&gradient, &fused_gradient), (&history, &fused_history)] {
+            assert_eq!(expected.read(native.device()).unwrap().as_slice::<f32>(),
+                       actual.read(native.device()).unwrap().as_slice::<f32>());
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn weighted_sum_scales_inputs_and_gradients() {
+        let native = native_backend();
+        let coefficients = [1f32, 0.5, 0.25];
+        let a = shared_tensor(&[2, 2], &[1.0, 2.0, 3.0, 4.0]);
+        let b = shared_tensor(&[2, 2], &[2.0, -2.0, 4.0, 0.0]);
+        let c = shared_tensor(&[2, 2], &[4.0, 8.0, -4.0, 16.0]);
+
+        let mut out = SharedTensor::new(&[2, 2]);
+        native.weighted_sum(&coefficients, &[&a, &b, &c], &mut out).unwrap();
+        assert_eq!(&[3.0f32, 3.0, 4.0, 8.0], out.read(native.device()).unwrap().as_slice::<f32>());
+
+        let out_diff = shared_tensor(&[2, 2], &[1.0, -2.0, 4.0, 8.0]);
+        let mut a_diff = SharedTensor::new(&[2, 2]);
+        let mut b_diff = SharedTensor::new(&[2, 2]);
+        let mut c_diff = SharedTensor::new(&[2, 2]);
+        native.weighted_sum_grad(&coefficients, &out_diff, &mut [&mut a_diff, &mut b_diff, &mut c_diff]).unwrap();
+        assert_eq!(&[1.0f32, -2.0, 4.0, 8.0], a_diff.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[0.5f32, -1.0, 2.0, 4.0], b_diff.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[0.25f32, -0.5, 1.0, 2.0], c_diff.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn batch_slice_reads_and_writes_back_samples() {
+        let native = native_backend();
+        let values = (0..12).map(|i| i as f32).collect::<Vec<_>>();
+        let batch = Arc::new(RwLock::new(shared_tensor(&[4, 3], &values)));
+
+        let slice = batch_slice(&batch, 1..3);
+        assert_eq!(&vec![2, 3], slice.read().unwrap().desc());
+        assert_eq!(&values[3..9], slice.read().unwrap().read(native.device()).unwrap().as_slice::<f32>());
+
+        write_to_memory(slice.write().unwrap().write_only(native.device()).unwrap(), &[-1f32; 6]);
+        write_batch_slice(&batch, 1..3, &slice);
+        let batch = batch.read().unwrap();
+        let data = batch.read(native.device()).unwrap().as_slice::<f32>();
+        assert_eq!(&values[0..3], &data[0..3]);
+        assert_eq!(&[-1f32; 6], &data[3..9]);
+        assert_eq!(&values[9..12], &data[9..12]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn batch_slice_beyond_batch_panics() {
+        let batch = Arc::new(RwLock::new(shared_tensor(&[2, 3], &[0f32; 6])));
+        batch_slice(&batch, 1..3);
+    }
+
+    #[test]
+    fn max_and_min_route_gradient_to_selected_input() {
+        let native = native_backend();
+        let a = shared_tensor(&[4], &[1.0, -2.0, 3.0, 0.5]);
+        let b = shared_tensor(&[4], &[2.0, -3.0, 3.0, 0.5]);
+        let out_diff = shared_tensor(&[4], &[10.0, 20.0, 30.0, 40.0]);
+
+        let mut out = SharedTensor::new(&[4]);
+        native.max(&a, &b, &mut out).unwrap();
+        assert_eq!(&[2f32, -2.0, 3.0, 0.5], out.read(native.device()).unwrap().as_slice::<f32>());
+        native.min(&a, &b, &mut out).unwrap();
+        assert_eq!(&[1f32, -3.0, 3.0, 0.5], out.read(native.device()).unwrap().as_slice::<f32>());
+
+        // ties route the gradient to the first input
+        let mut a_diff = SharedTensor::new(&[4]);
+        let mut b_diff = SharedTensor::new(&[4]);
+        native.max_grad(&a, &b, &out_diff, &mut a_diff, &mut b_diff).unwrap();
+        assert_eq!(&[0f32, 20.0, 30.0, 40.0], a_diff.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[10f32, 0.0, 0.0, 0.0], b_diff.read(native.device()).unwrap().as_slice::<f32>());
+        native.min_grad(&a, &b, &out_diff, &mut a_diff, &mut b_diff).unwrap();
+        assert_eq!(&[10f32, 0.0, 30.0, 40.0], a_diff.read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(&[0f32, 20.0, 0.0, 0.0], b_diff.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn gemm_accumulation_in_f64_matches_transposes() {
+        let native = native_backend();
+        let a = shared_tensor(&[2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = shared_tensor(&[2, 3], &[1.0, 0.0, -1.0, 2.0, 1.0, 0.5]);
+        let mut c = shared_tensor(&[2, 2], &[1.0, 1.0, 1.0, 1.0]);
+        native.gemm_accumulate(GemmAccumulation::F64,
+                             &native_scalar(2f32),
+                             Transpose::NoTrans,
+                             &a,
+                             Transpose::Trans,
+                             &b,
+                             &native_scalar(1f32),
+                             &mut c)
+            .unwrap();
+        assert_eq!(&[-3f32, 12.0, -3.0, 33.0], c.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn gemm_accumulation_in_f64_is_more_accurate() {
+        // a large first product swallows the following small ones in f32
+        let k = 4097;
+        let mut a_values = vec![1f32; k];
+        let mut b_values = vec![1f32; k];
+        a_values[0] = 1e4;
+        b_values[0] = 1e4;
+        let reference = 1e8f64 + (k - 1) as f64;
+
+        let native = native_backend();
+        let a = shared_tensor(&[1, k], &a_values);
+        let b = shared_tensor(&[k, 1], &b_values);
+        let error = |accumulation: GemmAccumulation| {
+            let mut c = SharedTensor::new(&[1, 1]);
+            native.gemm_accumulate(accumulation,
+                                 &native_scalar(1f32),
+                                 Transpose::NoTrans,
+                                 &a,
+                                 Transpose::NoTrans,
+                                 &b,
+                                 &native_scalar(0f32),
+                                 &mut c)
+                .unwrap();
+            let c = c.read(native.device()).unwrap().as_slice::<f32>()[0];
+            (c as f64 - reference).abs()
+        };
+        let input_error = error(GemmAccumulation::Input);
+        let f64_error = error(GemmAccumulation::F64);
+        assert!(f64_error < input_error, "{} >= {}", f64_error, input_error);
+        assert_eq!(0f64, f64_error);
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cumsum_inclusive_and_exclusive() {
+        let native = native_backend();
+        let x = shared_tensor(&[5], &[1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        let mut out = SharedTensor::new(&[5]);
+        native.cumsum(&x, 0, false, &mut out).unwrap();
+        assert_eq!(&[1f32, 3.0, 6.0, 10.0, 15.0], out.read(native.device()).unwrap().as_slice::<f32>());
+        native.cumsum(&x, 0, true, &mut out).unwrap();
+        assert_eq!(&[0f32, 1.0, 3.0, 6.0, 10.0], out.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cumsum_by_gemm_matches_cumsum() {
+        let native = native_backend();
+        let values = [1f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0];
+        let x = shared_tensor(&[2, 3, 2], &values);
+        for axis in 0..3 {
+            for &exclusive in &[false, true] {
+                for &reverse in &[false, true] {
+                    let mut expected = SharedTensor::new(&[2, 3, 2]);
+                    cumsum_on(&native, &x, axis, exclusive, reverse, &mut expected).unwrap();
+                    let mut out = SharedTensor::new(&[2, 3, 2]);
+                    cumsum_by_gemm(&native, &x, axis, exclusive, reverse, &mut out).unwrap();
+                    assert_eq!(&vec![2, 3, 2], out.desc());
+                    assert_eq!(expected.read(native.device()).unwrap().as_slice::<f32>(),
+                               out.read(native.device()).unwrap().as_slice::<f32>());
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature="native")]
+    fn cumsum_gradient_matches_finite_differences() {
+        let native = native_backend();
+        let values = [0.5f32, -1.0, 2.0, 0.25, 3.0];
+        let out_diff = [1.0f32, -2.0, 0.5, 3.0, -1.5];
+        // the loss `sum(out_diff * cumsum(x))`
+        let loss = |values: &[f32], exclusive: bool| {
+            let mut out = SharedTensor::new(&[5]);
+            native.cumsum(&shared_tensor(&[5], values), 0, exclusive, &mut out).unwrap();
+            let out = out.read(native.device()).unwrap().as_slice::<f32>();
+            out.iter().zip(&out_diff).fold(0f32, |sum, (o, d)| sum + o * d)
+        };
+
+        for &exclusive in &[false, true] {
+            let mut x_diff = SharedTensor::new(&[5]);
+            native.cumsum_grad(&shared_tensor(&[5], &out_diff), 0, exclusive, &mut x_diff).unwrap();
+            let x_diff = x_diff.read(native.device()).unwrap().as_slice::<f32>();
+
+            let epsilon = 0.01f32;
+            for i in 0..5 {
+                let mut plus = values.to_vec();
+                plus[i] += epsilon;
+                let mut minus = values.to_vec();
+                minus[i] -= epsilon;
+                let expected = (loss(&plus, exclusive) - loss(&minus, exclusive)) / (2f32 * epsilon);
+                assert!((x_diff[i] - expected).abs() < 1e-3,
+                        "{} != {}",
+                        x_diff[i],
+                        expected);
+            }
+        }
+        // the last element only contributes to its own inclusive sum
+        let mut x_diff = SharedTensor::new(&[5]);
+        native.cumsum_grad(&shared_tensor(&[5], &out_diff), 0, false, &mut x_diff).unwrap();
+        assert_eq!(-1.5f32, x_diff.read(native.device()).unwrap().as_slice::<f32>()[4]);
+    }
+}
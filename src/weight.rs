@@ -1,13 +1,19 @@
 //! Provides configuration of weights and their initialization.
 
 use capnp_util::*;
-use co::{ITensorDesc, SharedTensor};
+use co::{IDevice, ITensorDesc, SharedTensor};
 use juice_capnp::weight_config as capnp_config;
-use rand;
-use rand::distributions::{IndependentSample, Range};
-use util::native_backend;
+use rand::{self, Rng, SeedableRng, StdRng};
+use rand::distributions::{IndependentSample, Normal, Range};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock, Weak};
+use util::{ArcLock, native_backend, write_to_memory};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Specifies training configuration for a weight blob.
 pub struct WeightConfig {
     /// The name of the weight blob -- useful for sharing weights among
@@ -130,7 +136,7 @@ impl<'a> CapnpRead<'a> for WeightConfig {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// Enum for specifing the shared weights behaviour
 pub enum DimCheckMode {
     /// Strict requires that shapes match.
@@ -139,7 +145,7 @@ pub enum DimCheckMode {
     Permissive,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 /// Enum for specifing the type of Filler.
 pub enum FillerType {
     /// Fills the weight blob with a constant `value` (all values are the same).
@@ -151,13 +157,63 @@ pub enum FillerType {
     ///
     /// `[Bengio and Glorot 2010]: Understanding the difficulty of training deep feedforward neural networks.`
     ///
-    /// Also known as Xavier filler.
+    /// Also known as Xavier filler. A Glorot filler with an `input_size` or `output_size`
+    /// of `0` takes both sizes from the shape of the filled blob, see [for_shape](#method.for_shape).
     Glorot {
         /// Number of input nodes for each output.
         input_size: usize,
         /// Number of output nodes for each input.
         output_size: usize,
     },
+    /// Fills the weight blobs with values drawn from a normal distribution with a mean of
+    /// `0` and a standard deviation of `sqrt(2 / input_size)`, based on the paper:
+    ///
+    /// `[He et al. 2015]: Delving Deep into Rectifiers: Surpassing Human-Level Performance on ImageNet Classification.`
+    ///
+    /// Also known as Kaiming or He filler. Keeps the variance of the activations of
+    /// networks with ReLU activations, which the Glorot filler under-scales. A MSRA filler
+    /// with an `input_size` of `0` takes it from the shape of the filled blob, see
+    /// [for_shape](#method.for_shape).
+    MSRA {
+        /// Number of input nodes for each output.
+        input_size: usize,
+    },
+    /// Fills the weight blob with values drawn from a normal distribution.
+    ///
+    /// Recommended for deep convolutional networks with ReLU activations when paired
+    /// with a small `std` like `0.01`.
+    Gaussian {
+        /// The mean of the distribution.
+        mean: f32,
+        /// The standard deviation of the distribution.
+        std: f32,
+        /// The seed of the random number generator. The same seed fills a blob with the
+        /// same values, e.g. to make tests reproducible. Without a seed the values are
+        /// drawn from the generator of the current thread.
+        ///
+        /// A default filler of the [InitDefaults](./struct.InitDefaults.html) fills every
+        /// blob with its own seed, which is derived from this one, see [for_blob](#method.for_blob).
+        seed: Option<usize>,
+    },
+    /// Fills the weight blob with values drawn uniformly from `[low, high)`.
+    Uniform {
+        /// The lower bound of the values.
+        low: f32,
+        /// The upper bound of the values.
+        high: f32,
+    },
+    /// Fills a blob of shape `[seq_len, d_model]` with the sinusoidal positional encoding
+    /// of the paper:
+    ///
+    /// `[Vaswani et al. 2017]: Attention Is All You Need.`
+    ///
+    /// The even features of position `pos` are `sin(pos / base^(2i / d_model))` and
+    /// the odd features `cos(pos / base^(2i / d_model))`, for the feature pair `i`.
+    /// The encoding is fixed, so the blob is usually not learned.
+    Sinusoidal {
+        /// The base of the wavelengths, `10000` in the paper.
+        base: f32,
+    },
 }
 
 impl FillerType {
@@ -165,17 +221,93 @@ impl FillerType {
     ///
     /// This filling of weights is usually done directly after creation of the weight blob.
     pub fn fill(&self, weight: &mut SharedTensor<f32>) {
+        if placeholder_weights() {
+            return;
+        }
         let native = native_backend();
         let native_device = native.device();
 
         match *self {
             FillerType::Constant { value } => Self::fill_constant(weight, value),
+            FillerType::Glorot { input_size, output_size } if input_size == 0 || output_size == 0 => {
+                let shape = weight.desc().clone();
+                self.for_shape(&shape).fill(weight)
+            }
             FillerType::Glorot { input_size, output_size } => Self::fill_glorot(weight, input_size, output_size),
+            FillerType::MSRA { input_size: 0 } => {
+                let shape = weight.desc().clone();
+                self.for_shape(&shape).fill(weight)
+            }
+            FillerType::MSRA { input_size } => Self::fill_msra(weight, input_size),
+            FillerType::Gaussian { mean, std, seed } => Self::fill_gaussian(weight, mean, std, seed),
+            FillerType::Uniform { low, high } => Self::fill_uniform(weight, low, high),
+            FillerType::Sinusoidal { base } => Self::fill_sinusoidal(weight, base),
+        }
+    }
+
+    /// Returns the filler with the input and output size of a [Glorot Filler](#variant.Glorot)
+    /// or the input size of a [MSRA Filler](#variant.MSRA) replaced by the ones of a weight
+    /// blob of shape `shape`.
+    ///
+    /// The first dimension of the shape is the number of outputs, the second one the number
+    /// of inputs and the remaining dimensions (e.g. of a convolution filter) count for both.
+    pub fn for_shape(&self, shape: &[usize]) -> FillerType {
+        match *self {
+            FillerType::Glorot { .. } => {
+                let receptive_field = shape.iter().skip(2).fold(1, |size, dim| size * dim);
+                FillerType::Glorot {
+                    input_size: shape.get(1).unwrap_or(&1) * receptive_field,
+                    output_size: shape.get(0).unwrap_or(&1) * receptive_field,
+                }
+            }
+            FillerType::MSRA { .. } => {
+                let receptive_field = shape.iter().skip(2).fold(1, |size, dim| size * dim);
+                FillerType::MSRA { input_size: shape.get(1).unwrap_or(&1) * receptive_field }
+            }
+            filler => filler,
+        }
+    }
+
+    /// Returns the filler with the seed of a seeded [Gaussian Filler](#variant.Gaussian)
+    /// replaced by one that is derived from it and the name of the weight blob `blob_name`.
+    ///
+    /// Blobs of the same shape that are filled with the same default filler would otherwise
+    /// all get the same values. The derived seed only depends on the seed and the name, so
+    /// the fillers stay reproducible.
+    pub fn for_blob(&self, blob_name: &str) -> FillerType {
+        match *self {
+            FillerType::Gaussian { mean, std, seed: Some(seed) } => {
+                // FNV-1a, which unlike the std hashers is guaranteed to stay the same
+                let seed = blob_name.bytes().fold(seed as u64 ^ 0xcbf29ce484222325, |hash, byte| {
+                    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+                });
+                FillerType::Gaussian {
+                    mean: mean,
+                    std: std,
+                    seed: Some(seed as usize),
+                }
+            }
+            filler => filler,
+        }
+    }
+
+    /// Checks that the parameters of the filler are valid, e.g. that the standard deviation
+    /// of a [Gaussian Filler](#variant.Gaussian) is not negative.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            FillerType::Gaussian { std, .. } if !(std >= 0f32) => {
+                Err(format!("The standard deviation of a Gaussian filler must not be negative, but is {}",
+                            std))
+            }
+            _ => Ok(()),
         }
     }
 
     /// Directly use the [Constant Filler](#variant.Constant).
     pub fn fill_constant(weight: &mut SharedTensor<f32>, value: f32) {
+        if placeholder_weights() {
+            return;
+        }
         let native = native_backend();
         let native_weight = weight.write_only(native.device()).unwrap();
 
@@ -196,4 +328,628 @@ impl FillerType {
             *e = between.ind_sample(&mut rng);
         }
     }
+
+    /// Directly use the [MSRA Filler](#variant.MSRA).
+    pub fn fill_msra(weight: &mut SharedTensor<f32>, num_inputs: usize) {
+        Self::fill_gaussian(weight, 0f32, (2f32 / num_inputs as f32).sqrt(), None);
+    }
+
+    /// Directly use the [Gaussian Filler](#variant.Gaussian).
+    pub fn fill_gaussian(weight: &mut SharedTensor<f32>, mean: f32, std: f32, seed: Option<usize>) {
+        let native = native_backend();
+        let native_weight = weight.write_only(native.device()).unwrap();
+
+        let normal = Normal::new(mean as f64, std as f64);
+        let values = native_weight.as_mut_slice::<f32>();
+        match seed {
+            Some(seed) => {
+                let seed: &[usize] = &[seed];
+                let mut rng: StdRng = SeedableRng::from_seed(seed);
+                Self::sample_into(values, &normal, &mut rng);
+            }
+            None => Self::sample_into(values, &normal, &mut rand::thread_rng()),
+        }
+    }
+
+    fn sample_into<D: IndependentSample<f64>, R: Rng>(values: &mut [f32], distribution: &D, rng: &mut R) {
+        for e in values {
+            *e = distribution.ind_sample(rng) as f32;
+        }
+    }
+
+    /// Directly use the [Uniform Filler](#variant.Uniform).
+    pub fn fill_uniform(weight: &mut SharedTensor<f32>, low: f32, high: f32) {
+        let native = native_backend();
+        let native_weight = weight.write_only(native.device()).unwrap();
+
+        let between = Range::new(low, high);
+        let mut rng = rand::thread_rng();
+        for e in native_weight.as_mut_slice::<f32>() {
+            *e = between.ind_sample(&mut rng);
+        }
+    }
+
+    /// Directly use the [Sinusoidal Filler](#variant.Sinusoidal).
+    pub fn fill_sinusoidal(weight: &mut SharedTensor<f32>, base: f32) {
+        Self::fill_with(weight, |shape, i| {
+            let d_model = *shape.last().unwrap_or(&1);
+            let position = (i / d_model) as f32;
+            let feature = i % d_model;
+            let angle = position / base.powf((feature - feature % 2) as f32 / d_model as f32);
+            if feature % 2 == 0 { angle.sin() } else { angle.cos() }
+        });
+    }
+
+    /// Fill a weight with the values returned by `filler` for the shape of the weight
+    /// and the flat index of each element.
+    ///
+    /// Allows arbitrary programmatic initializations, like identity matrices or
+    /// positional encodings.
+    ///
+    /// ```
+    /// # extern crate coaster;
+    /// # extern crate juice;
+    /// # use coaster::SharedTensor;
+    /// # use juice::weight::FillerType;
+    /// # fn main() {
+    /// let mut weight = SharedTensor::<f32>::new(&[3, 3]);
+    /// // identity matrix
+    /// FillerType::fill_with(&mut weight, |shape, i| if i / shape[1] == i % shape[1] { 1.0 } else { 0.0 });
+    /// # }
+    /// ```
+    pub fn fill_with<F: Fn(&[usize], usize) -> f32>(weight: &mut SharedTensor<f32>, filler: F) {
+        if placeholder_weights() {
+            return;
+        }
+        let native = native_backend();
+        let shape = weight.desc().clone();
+        let native_weight = weight.write_only(native.device()).unwrap();
+
+        for (i, e) in native_weight.as_mut_slice::<f32>().iter_mut().enumerate() {
+            *e = filler(&shape, i);
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the fillers leave the blobs untouched, see [with_placeholder_weights][1].
+    /// [1]: ./fn.with_placeholder_weights.html
+    static PLACEHOLDER_WEIGHTS: Cell<bool> = Cell::new(false);
+}
+
+/// Returns whether the fillers leave the blobs untouched.
+fn placeholder_weights() -> bool {
+    PLACEHOLDER_WEIGHTS.with(|placeholder| placeholder.get())
+}
+
+/// Calls `f` with all the fillers of this thread leaving the blobs untouched.
+///
+/// Tensors only allocate memory once they are written, so the blobs that are filled while
+/// constructing a Layer, e.g. its weights and their gradients, are placeholders that hold
+/// no memory. Used by [dry_run][1] to infer the shapes of a network without allocating it.
+/// [1]: ../layer/fn.dry_run.html
+pub fn with_placeholder_weights<T, F: FnOnce() -> T>(f: F) -> T {
+    let previous = placeholder_weights();
+    PLACEHOLDER_WEIGHTS.with(|placeholder| placeholder.set(true));
+    let result = f();
+    PLACEHOLDER_WEIGHTS.with(|placeholder| placeholder.set(previous));
+    result
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The default fillers of the weights of each layer type.
+///
+/// Weights whose [WeightConfig][1] doesn't specify a filler are filled with the default
+/// filler of their layer type, so the initialization of a whole network can be changed in
+/// one place. Weights without a default keep the initialization of their layer.
+/// [1]: ./struct.WeightConfig.html
+///
+/// The defaults are identified by the [name of the layer type][2] and the index of the
+/// weight in the layer. [Glorot Fillers](./enum.FillerType.html#variant.Glorot) get the
+/// input and output size from the shape of each weight blob and seeded
+/// [Gaussian Fillers](./enum.FillerType.html#variant.Gaussian) a seed for each weight blob.
+/// [2]: ../layer/enum.LayerType.html#method.name
+///
+/// ```
+/// # use juice::weight::*;
+/// let defaults = InitDefaults::new()
+///     .with_filler("Linear", 0, FillerType::Uniform { low: -0.05, high: 0.05 })
+///     .with_filler("Convolution", 0, FillerType::Glorot { input_size: 0, output_size: 0 });
+/// assert!(defaults.filler("Linear", 0).is_some());
+/// assert!(defaults.filler("Linear", 1).is_none());
+/// ```
+pub struct InitDefaults {
+    fillers: HashMap<(String, usize), FillerType>,
+}
+
+impl InitDefaults {
+    /// Create InitDefaults without any default fillers.
+    pub fn new() -> InitDefaults {
+        InitDefaults::default()
+    }
+
+    /// Checks that all default fillers are [valid](./enum.FillerType.html#method.validate).
+    pub fn validate(&self) -> Result<(), String> {
+        for (&(ref layer_type, weight_id), filler) in &self.fillers {
+            try!(filler.validate().map_err(|message| {
+                format!("The default filler of weight {} of {} layers: {}",
+                        weight_id,
+                        layer_type,
+                        message)
+            }));
+        }
+        Ok(())
+    }
+
+    /// Sets the default filler of the weight `weight_id` of layers of type `layer_type`.
+    pub fn set_filler(&mut self, layer_type: &str, weight_id: usize, filler: FillerType) {
+        self.fillers.insert((layer_type.to_owned(), weight_id), filler);
+    }
+
+    /// Returns the InitDefaults with the default filler of the weight `weight_id` of
+    /// layers of type `layer_type` set to `filler`.
+    pub fn with_filler(mut self, layer_type: &str, weight_id: usize, filler: FillerType) -> InitDefaults {
+        self.set_filler(layer_type, weight_id, filler);
+        self
+    }
+
+    /// Returns the default filler of the weight `weight_id` of layers of type `layer_type`.
+    pub fn filler(&self, layer_type: &str, weight_id: usize) -> Option<FillerType> {
+        self.fillers.get(&(layer_type.to_owned(), weight_id)).cloned()
+    }
+}
+
+/// Computes a binary mask that prunes the `fraction` of values in `weight` with the smallest magnitude.
+///
+/// Values that are already zero are the first ones to be pruned, so applying this repeatedly
+/// with a growing `fraction` allows iterative magnitude pruning.
+pub fn magnitude_mask(weight: &SharedTensor<f32>, fraction: f32) -> SharedTensor<f32> {
+    assert!(fraction >= 0f32 && fraction <= 1f32,
+            "The pruning fraction has to be between 0 and 1, but is {}",
+            fraction);
+    let native = native_backend();
+    let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+    let num_pruned = (values.len() as f32 * fraction).floor() as usize;
+
+    let mut indices = (0..values.len()).collect::<Vec<_>>();
+    indices.sort_by(|&a, &b| values[a].abs().partial_cmp(&values[b].abs()).unwrap_or(Ordering::Equal));
+    let mut mask_values = vec![1f32; values.len()];
+    for &i in indices.iter().take(num_pruned) {
+        mask_values[i] = 0f32;
+    }
+
+    let mut mask = SharedTensor::new(weight.desc());
+    write_to_memory(mask.write_only(native.device()).unwrap(), &mask_values);
+    mask
+}
+
+/// Multiplies the weight blob `weight` element-wise with a binary `mask` on the host and
+/// returns the resulting sparsity.
+///
+/// The sparsity is the fraction of values in `weight` that are zero. The write is
+/// [recorded][1], so the native device holds the latest values of the weight afterwards.
+/// [1]: ./fn.record_weight_written.html
+pub fn apply_mask(weight: &ArcLock<SharedTensor<f32>>, mask: &SharedTensor<f32>) -> f32 {
+    let native = native_backend();
+    let sparsity = {
+        let mut weight = weight.write().unwrap();
+        let mask_values = mask.read(native.device()).unwrap().as_slice::<f32>();
+        let weight_values = weight.read_write(native.device()).unwrap().as_mut_slice::<f32>();
+        let mut num_zeros = 0;
+        for (value, mask_value) in weight_values.iter_mut().zip(mask_values) {
+            *value *= *mask_value;
+            if *value == 0f32 {
+                num_zeros += 1;
+            }
+        }
+        num_zeros as f32 / weight_values.len() as f32
+    };
+    record_weight_written(weight, native.device());
+    sparsity
+}
+
+#[derive(Debug, Clone)]
+/// The sparsity of the learnable weights of a pruned Layer.
+///
+/// See [Layer::prune_by_magnitude][1].
+/// [1]: ../layer/struct.Layer.html#method.prune_by_magnitude
+pub struct PruningReport {
+    /// The display name of each weight blob and the fraction of its values that are zero.
+    pub sparsity: Vec<(String, f32)>,
+}
+
+impl fmt::Display for PruningReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref name, sparsity) in &self.sparsity {
+            try!(writeln!(f, "{:<15} {:.2?}% sparse", name, sparsity * 100f32));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An int8 copy of a weight blob with one scale factor per output channel.
+///
+/// The output channels are the first dimension of the weight blob, which holds for
+/// the weights of Linear and Convolution layers.
+pub struct QuantizedWeight {
+    /// The shape of the original weight blob.
+    pub shape: Vec<usize>,
+    /// The quantized values, `round(value / scale)` of the respective output channel.
+    pub values: Vec<i8>,
+    /// The scale factor of each output channel.
+    pub scales: Vec<f32>,
+}
+
+impl QuantizedWeight {
+    /// Quantizes a weight blob symmetrically, so that the largest magnitude of each
+    /// output channel maps to 127.
+    pub fn quantize(weight: &SharedTensor<f32>) -> QuantizedWeight {
+        let native = native_backend();
+        let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+        let num_channels = *weight.desc().get(0).unwrap_or(&1);
+        let channel_size = values.len() / num_channels;
+
+        let mut scales = Vec::with_capacity(num_channels);
+        let mut quantized = Vec::with_capacity(values.len());
+        for channel in values.chunks(channel_size) {
+            let max = channel.iter().fold(0f32, |max, value| max.max(value.abs()));
+            let scale = if max > 0f32 { max / 127f32 } else { 1f32 };
+            for value in channel {
+                quantized.push((value / scale).round() as i8);
+            }
+            scales.push(scale);
+        }
+
+        QuantizedWeight {
+            shape: weight.desc().clone(),
+            values: quantized,
+            scales: scales,
+        }
+    }
+
+    /// Writes the dequantized values into `weight`.
+    pub fn dequantize(&self, weight: &mut SharedTensor<f32>) {
+        let native = native_backend();
+        let channel_size = self.values.len() / self.scales.len();
+        let values = weight.write_only(native.device()).unwrap().as_mut_slice::<f32>();
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.values[i] as f32 * self.scales[i / channel_size];
+        }
+    }
+
+    /// Returns the maximum absolute difference between `weight` and the dequantized values.
+    pub fn max_error(&self, weight: &SharedTensor<f32>) -> f32 {
+        let native = native_backend();
+        let channel_size = self.values.len() / self.scales.len();
+        let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+        values.iter().enumerate().fold(0f32, |max, (i, value)| {
+            max.max((value - self.values[i] as f32 * self.scales[i / channel_size]).abs())
+        })
+    }
+
+    /// Returns the memory the quantized weight occupies in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        self.values.len() + self.scales.len() * 4
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The result of quantizing the weights of a Layer.
+///
+/// See [Layer::quantize_linear_weights][1].
+/// [1]: ../layer/struct.Layer.html#method.quantize_linear_weights
+pub struct QuantizationReport {
+    /// The display name of each quantized weight blob and its maximum quantization error.
+    pub max_error: Vec<(String, f32)>,
+    /// The memory of the quantized weight blobs as f32 in bytes.
+    pub f32_bytes: usize,
+    /// The memory of the quantized weight blobs as int8 (including scales) in bytes.
+    pub int8_bytes: usize,
+}
+
+impl QuantizationReport {
+    /// Appends the results of another report.
+    pub fn merge(&mut self, other: QuantizationReport) {
+        self.max_error.extend(other.max_error);
+        self.f32_bytes += other.f32_bytes;
+        self.int8_bytes += other.int8_bytes;
+    }
+}
+
+impl Default for QuantizationReport {
+    fn default() -> QuantizationReport {
+        QuantizationReport {
+            max_error: Vec::new(),
+            f32_bytes: 0,
+            int8_bytes: 0,
+        }
+    }
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(ref name, max_error) in &self.max_error {
+            try!(writeln!(f, "{:<15} max error {:.6}", name, max_error));
+        }
+        writeln!(f, "{} bytes -> {} bytes", self.f32_bytes, self.int8_bytes)
+    }
+}
+
+/// The devices that hold the latest values of a weight blob.
+struct WeightLocations {
+    /// The weight blob, to evict the entry once it has been dropped.
+    weight: Weak<RwLock<SharedTensor<f32>>>,
+    devices: Vec<Box<Any>>,
+}
+
+/// The locations of the weight blobs by the address of their lock.
+///
+/// An entry keeps the allocation of its weight alive through the weak reference, so the
+/// address of a dropped weight is not reused while its entry exists.
+struct WeightRegistry {
+    locations: HashMap<usize, WeightLocations>,
+    /// The number of entries at which the entries of dropped weights are evicted next.
+    eviction_size: usize,
+}
+
+/// The smallest number of entries at which the [registry][1] evicts dropped weights.
+/// [1]: ./struct.WeightRegistry.html
+const MIN_EVICTION_SIZE: usize = 64;
+
+thread_local! {
+    static WEIGHT_LOCATIONS: RefCell<WeightRegistry> = RefCell::new(WeightRegistry {
+        locations: HashMap::new(),
+        eviction_size: MIN_EVICTION_SIZE,
+    });
+}
+
+/// Returns the address that identifies `weight`.
+fn weight_address(weight: &ArcLock<SharedTensor<f32>>) -> usize {
+    let weight_ptr: *const RwLock<SharedTensor<f32>> = &**weight;
+    weight_ptr as usize
+}
+
+/// Records that `weight` has been written on `device`, so no other device holds its
+/// latest values until it is [synchronized][1].
+/// [1]: ./fn.record_weight_synced.html
+///
+/// The coaster tensors don't expose which of their copies is the latest one, so the
+/// [Layer][2] and the [Solver][3] record where they write the weights. The locations are
+/// recorded per thread.
+/// [2]: ../layer/struct.Layer.html
+/// [3]: ../solver/struct.Solver.html
+pub fn record_weight_written<D: IDevice>(weight: &ArcLock<SharedTensor<f32>>, device: &D) {
+    WEIGHT_LOCATIONS.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let address = weight_address(weight);
+        // the entries of dropped weights are evicted whenever the number of entries has
+        // doubled, so a write takes amortized constant time
+        if !registry.locations.contains_key(&address) && registry.locations.len() >= registry.eviction_size {
+            registry.locations.retain(|_, location| location.weight.upgrade().is_some());
+            registry.eviction_size = ::std::cmp::max(2 * registry.locations.len(), MIN_EVICTION_SIZE);
+        }
+        registry.locations.insert(address,
+                                  WeightLocations {
+                                      weight: Arc::downgrade(weight),
+                                      devices: vec![Box::new(device.clone())],
+                                  });
+    });
+}
+
+/// Records that the latest values of `weight` have been synchronized to `device`.
+///
+/// Nothing is recorded if no write of `weight` has been recorded.
+pub fn record_weight_synced<D: IDevice>(weight: &ArcLock<SharedTensor<f32>>, device: &D) {
+    WEIGHT_LOCATIONS.with(|registry| {
+        if let Some(location) = registry.borrow_mut().locations.get_mut(&weight_address(weight)) {
+            if !location.devices.iter().any(|latest| latest.downcast_ref::<D>() == Some(device)) {
+                location.devices.push(Box::new(device.clone()));
+            }
+        }
+    });
+}
+
+/// Returns whether `device` holds the latest values of `weight`, without synchronizing it.
+///
+/// Returns `None` if no write of `weight` has been recorded.
+pub fn weight_is_latest_on<D: IDevice>(weight: &ArcLock<SharedTensor<f32>>, device: &D) -> Option<bool> {
+    WEIGHT_LOCATIONS.with(|registry| {
+        registry.borrow()
+            .locations
+            .get(&weight_address(weight))
+            .map(|location| location.devices.iter().any(|latest| latest.downcast_ref::<D>() == Some(device)))
+    })
+}
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod tests {
+    use co::SharedTensor;
+    use std::sync::{Arc, RwLock};
+    use super::{FillerType, InitDefaults, QuantizedWeight, apply_mask, magnitude_mask};
+    use super::{record_weight_synced, record_weight_written, weight_is_latest_on};
+    use util::{native_backend, write_to_memory};
+
+    #[test]
+    fn glorot_takes_sizes_from_shape() {
+        let unsized_glorot = FillerType::Glorot { input_size: 0, output_size: 0 };
+        // linear weight of shape [output_size, input_size]
+        match unsized_glorot.for_shape(&[3, 4]) {
+            FillerType::Glorot { input_size, output_size } => assert_eq!((4, 3), (input_size, output_size)),
+            filler => panic!("unexpected filler {:?}", filler),
+        }
+        // convolution filter of shape [num_output, channels, height, width]
+        match unsized_glorot.for_shape(&[8, 3, 5, 5]) {
+            FillerType::Glorot { input_size, output_size } => assert_eq!((75, 200), (input_size, output_size)),
+            filler => panic!("unexpected filler {:?}", filler),
+        }
+
+        let native = native_backend();
+        let mut weight = SharedTensor::<f32>::new(&[4, 5]);
+        FillerType::Uniform { low: -0.05, high: 0.05 }.for_shape(&[4, 5]).fill(&mut weight);
+        for value in weight.read(native.device()).unwrap().as_slice::<f32>() {
+            assert!(*value >= -0.05f32 && *value < 0.05f32);
+        }
+    }
+
+    #[test]
+    fn unsized_glorot_fills_within_range_of_shape() {
+        let native = native_backend();
+        let mut weight = SharedTensor::<f32>::new(&[8, 3, 5, 5]);
+        FillerType::Glorot { input_size: 0, output_size: 0 }.fill(&mut weight);
+        // fan_in = 3 * 5 * 5, fan_out = 8 * 5 * 5
+        let init_range = (6f32 / (75f32 + 200f32)).sqrt();
+        let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+        assert!(values.iter().all(|value| value.abs() <= init_range));
+        assert!(values.iter().any(|value| value.abs() > init_range / 2f32));
+    }
+
+    #[test]
+    fn msra_matches_std_of_fan_in() {
+        let native = native_backend();
+        // convolution filter of shape [num_output, channels, height, width]
+        let mut weight = SharedTensor::<f32>::new(&[128, 64, 3, 3]);
+        FillerType::MSRA { input_size: 0 }.fill(&mut weight);
+        let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+        let mean = values.iter().fold(0f32, |sum, value| sum + value) / values.len() as f32;
+        let variance = values.iter().fold(0f32, |sum, value| sum + (value - mean).powi(2)) / values.len() as f32;
+        let expected = (2f32 / (64f32 * 9f32)).sqrt();
+        assert!((variance.sqrt() - expected).abs() < 0.1 * expected,
+                "{} != {}",
+                variance.sqrt(),
+                expected);
+        assert!(mean.abs() < 0.1 * expected, "{}", mean);
+    }
+
+    #[test]
+    fn seeded_gaussian_is_reproducible() {
+        let native = native_backend();
+        let filler = FillerType::Gaussian { mean: 1f32, std: 0.01, seed: Some(42) };
+        let mut first = SharedTensor::<f32>::new(&[16, 64]);
+        let mut second = SharedTensor::<f32>::new(&[16, 64]);
+        filler.fill(&mut first);
+        filler.fill(&mut second);
+
+        let values = first.read(native.device()).unwrap().as_slice::<f32>();
+        assert_eq!(values, second.read(native.device()).unwrap().as_slice::<f32>());
+        let mean = values.iter().fold(0f32, |sum, value| sum + value) / values.len() as f32;
+        let variance = values.iter().fold(0f32, |sum, value| sum + (value - mean).powi(2)) / values.len() as f32;
+        assert!((mean - 1f32).abs() < 1e-3, "{}", mean);
+        assert!((variance.sqrt() - 0.01).abs() < 1e-3, "{}", variance.sqrt());
+
+        FillerType::Gaussian { mean: 1f32, std: 0.01, seed: Some(43) }.fill(&mut second);
+        assert!(values != second.read(native.device()).unwrap().as_slice::<f32>());
+    }
+
+    #[test]
+    fn seeded_gaussian_is_derived_per_blob() {
+        let filler = FillerType::Gaussian { mean: 0f32, std: 1f32, seed: Some(42) };
+        assert_eq!(filler.for_blob("first"), filler.for_blob("first"));
+        assert!(filler.for_blob("first") != filler.for_blob("second"));
+        assert!(filler.for_blob("first") != filler);
+        let unseeded = FillerType::Gaussian { mean: 0f32, std: 1f32, seed: None };
+        assert_eq!(unseeded, unseeded.for_blob("first"));
+
+        assert!(filler.validate().is_ok());
+        let negative = FillerType::Gaussian { mean: 0f32, std: -0.01, seed: None };
+        assert!(negative.validate().unwrap_err().contains("-0.01"));
+        let defaults = InitDefaults::new().with_filler("Linear", 1, negative);
+        assert!(defaults.validate().unwrap_err().contains("weight 1 of Linear"));
+    }
+
+    #[test]
+    fn fill_with_writes_identity() {
+        let native = native_backend();
+        let mut weight = SharedTensor::<f32>::new(&[4, 4]);
+        FillerType::fill_with(&mut weight, |shape, i| if i / shape[1] == i % shape[1] { 1f32 } else { 0f32 });
+
+        let values = weight.read(native.device()).unwrap().as_slice::<f32>();
+        for row in 0..4 {
+            for col in 0..4 {
+                let expected = if row == col { 1f32 } else { 0f32 };
+                assert_eq!(expected, values[row * 4 + col]);
+            }
+        }
+    }
+
+    #[test]
+    fn sinusoidal_positional_encoding() {
+        let native = native_backend();
+        let mut encoding = SharedTensor::<f32>::new(&[4, 8]);
+        FillerType::Sinusoidal { base: 10000f32 }.fill(&mut encoding);
+
+        let values = encoding.read(native.device()).unwrap().as_slice::<f32>();
+        let at = |pos: usize, feature: usize| values[pos * 8 + feature];
+        // position 0 encodes as sin(0) = 0 and cos(0) = 1
+        for feature in 0..8 {
+            assert_eq!(if feature % 2 == 0 { 0f32 } else { 1f32 }, at(0, feature));
+        }
+        assert!((at(1, 0) - 1f32.sin()).abs() < 1e-6);
+        assert!((at(1, 1) - 1f32.cos()).abs() < 1e-6);
+        assert!((at(3, 2) - (3f32 / 10f32).sin()).abs() < 1e-6);
+        assert!((at(3, 3) - (3f32 / 10f32).cos()).abs() < 1e-6);
+        assert!((at(2, 6) - (2f32 / 1000f32).sin()).abs() < 1e-6);
+        assert!((at(2, 7) - (2f32 / 1000f32).cos()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn magnitude_mask_prunes_smallest_values() {
+        let native = native_backend();
+        let weight = Arc::new(RwLock::new(SharedTensor::<f32>::new(&[2, 3])));
+        write_to_memory(weight.write().unwrap().write_only(native.device()).unwrap(),
+                        &[0.5f32, -0.1f32, 2f32, -3f32, 0.2f32, 1f32]);
+
+        let mask = magnitude_mask(&weight.read().unwrap(), 0.5f32);
+        assert_eq!(&[0f32, 0f32, 1f32, 1f32, 0f32, 1f32],
+                   mask.read(native.device()).unwrap().as_slice::<f32>());
+
+        let sparsity = apply_mask(&weight, &mask);
+        assert_eq!(0.5f32, sparsity);
+        assert_eq!(&[0f32, 0f32, 2f32, -3f32, 0f32, 1f32],
+                   weight.read().unwrap().read(native.device()).unwrap().as_slice::<f32>());
+        assert_eq!(Some(true), weight_is_latest_on(&weight, native.device()));
+    }
+
+    #[test]
+    fn quantize_per_output_channel() {
+        let native = native_backend();
+        let mut weight = SharedTensor::<f32>::new(&[2, 3]);
+        write_to_memory(weight.write_only(native.device()).unwrap(),
+                        &[1.27f32, -0.5f32, 0.01f32, 100f32, -40f32, 3f32]);
+
+        let quantized = QuantizedWeight::quantize(&weight);
+        assert_eq!(vec![127i8, -50, 1, 127, -51, 4], quantized.values);
+        assert_eq!(10, quantized.size_in_bytes());
+        // the error is bounded by half a quantization step of each channel
+        assert!(quantized.max_error(&weight) <= 100f32 / 127f32 / 2f32);
+
+        quantized.dequantize(&mut weight);
+        assert!(quantized.max_error(&weight) < 1e-6);
+    }
+
+    #[test]
+    fn weight_locations_are_recorded_per_weight() {
+        let native = native_backend();
+        let weight = Arc::new(RwLock::new(SharedTensor::<f32>::new(&[2])));
+        let other = Arc::new(RwLock::new(SharedTensor::<f32>::new(&[2])));
+        assert_eq!(None, weight_is_latest_on(&weight, native.device()));
+
+        record_weight_synced(&weight, native.device());
+        assert_eq!(None, weight_is_latest_on(&weight, native.device()));
+        record_weight_written(&weight, native.device());
+        assert_eq!(Some(true), weight_is_latest_on(&weight, native.device()));
+        assert_eq!(None, weight_is_latest_on(&other, native.device()));
+    }
+
+    #[test]
+    fn locations_of_dropped_weights_are_evicted() {
+        let native = native_backend();
+        for _ in 0..1000 {
+            let weight = Arc::new(RwLock::new(SharedTensor::<f32>::new(&[1])));
+            record_weight_written(&weight, native.device());
+        }
+        let entries = super::WEIGHT_LOCATIONS.with(|registry| registry.borrow().locations.len());
+        assert!(entries <= super::MIN_EVICTION_SIZE, "{} entries", entries);
+    }
 }
@@ -1,10 +1,10 @@
-extern crate leaf;
+extern crate juice;
 extern crate coaster as co;
 
 #[cfg(test)]
 mod layer_spec {
     use co::prelude::*;
-    use leaf::layer::*;
+    use juice::layer::*;
     use std::rc::Rc;
     // only used by cuda right now
     #[allow(dead_code)]
@@ -24,16 +24,16 @@ mod layer_spec {
     #[cfg(all(feature="native", feature="cuda"))]
     mod native_cuda {
         use super::{native_backend, cuda_backend};
-        use leaf::layer::*;
-        use leaf::layers::*;
+        use juice::layer::*;
+        use juice::layers::*;
 
         #[test]
         fn create_layer_with_either() {
             let cfg = super::new_layer_config();
-            Layer::from_config(native_backend(), &cfg);
+            Layer::from_config(native_backend(), &cfg).unwrap();
 
             let cfg = super::new_layer_config();
-            Layer::from_config(cuda_backend(), &cfg);
+            Layer::from_config(cuda_backend(), &cfg).unwrap();
         }
     }
 
@@ -41,8 +41,8 @@ mod layer_spec {
     mod native {
         use super::native_backend;
         use co::prelude::*;
-        use leaf::layer::*;
-        use leaf::layers::*;
+        use juice::layer::*;
+        use juice::layers::*;
 
         fn simple_network() -> LayerConfig {
             let mut net_cfg = SequentialConfig::default();
@@ -68,13 +68,13 @@ mod layer_spec {
 
             let backend = native_backend();
             let _ = Layer::from_config(backend.clone(),
-                                       &LayerConfig::new("network", LayerType::Sequential(cfg)));
+                                       &LayerConfig::new("network", LayerType::Sequential(cfg))).unwrap();
         }
 
         #[test]
         fn save_and_load_layer() {
             let cfg = simple_network();
-            let mut original_layer = Layer::from_config(native_backend(), &cfg);
+            let mut original_layer = Layer::from_config(native_backend(), &cfg).unwrap();
 
             original_layer.save("target/testnetwork").unwrap();
             let loaded_layer = Layer::<Backend<Native>>::load(native_backend(), "target/testnetwork").unwrap();
@@ -94,28 +94,65 @@ mod layer_spec {
 
             assert_eq!(original_weight, loaded_weight);
         }
+
+        #[test]
+        fn network_flops_are_counted_per_layer() {
+            let mut cfg = SequentialConfig::default();
+            cfg.add_input("data", &[2, 1, 8, 8]);
+            cfg.add_layer(LayerConfig::new("conv",
+                                           ConvolutionConfig {
+                                               num_output: 4,
+                                               filter_shape: vec![3],
+                                               stride: vec![1],
+                                               padding: vec![0],
+                                               bias: false,
+                                           }));
+            cfg.add_layer(LayerConfig::new("relu", LayerType::ReLU));
+            cfg.add_layer(LayerConfig::new("pool",
+                                           PoolingConfig {
+                                               mode: PoolingMode::Max,
+                                               filter_shape: vec![2],
+                                               stride: vec![2],
+                                               padding: vec![0],
+                                               padding_value: None,
+                                           }));
+            cfg.add_layer(LayerConfig::new("nhwc", LayerType::NchwToNhwc));
+            cfg.add_layer(LayerConfig::new("reshape", ReshapeConfig::of_shape(&[2, 36])));
+            cfg.add_layer(LayerConfig::new("linear", LinearConfig { output_size: 10 }));
+            let network = Layer::from_config(native_backend(), &LayerConfig::new("network", cfg)).unwrap();
+
+            // conv: [2, 4, 6, 6] outputs of 3x3 filters over one channel
+            // pool: [2, 4, 3, 3] outputs of 2x2 windows
+            let expected = vec![("conv".to_owned(), 288 * 9),
+                                ("relu".to_owned(), 288),
+                                ("pool".to_owned(), 72 * 4),
+                                ("nhwc".to_owned(), 0),
+                                ("reshape".to_owned(), 0),
+                                ("linear".to_owned(), 20 * 36)];
+            assert_eq!(expected, network.flops());
+        }
     }
 
     #[cfg(feature="cuda")]
     mod cuda {
         use super::{native_backend, cuda_backend};
         use co::prelude::*;
-        use leaf::layer::*;
-        use leaf::layers::*;
-        use leaf::util::write_to_memory;
+        use juice::layer::*;
+        use juice::layers::*;
+        use juice::util::write_to_memory;
         use std::sync::{Arc, RwLock};
 
         #[test]
         fn new_layer() {
             let cfg = super::new_layer_config();
-            Layer::from_config(cuda_backend(), &cfg);
+            Layer::from_config(cuda_backend(), &cfg).unwrap();
         }
 
         #[test]
         fn can_create_empty_sequential_layer() {
             let model = SequentialConfig::default();
             Layer::from_config(cuda_backend(),
-                               &LayerConfig::new("model", LayerType::Sequential(model)));
+                               &LayerConfig::new("model", LayerType::Sequential(model))).unwrap();
         }
 
         #[test]
@@ -125,7 +162,7 @@ mod layer_spec {
             model.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
 
             Layer::from_config(cuda_backend(),
-                               &LayerConfig::new("model", LayerType::Sequential(model)));
+                               &LayerConfig::new("model", LayerType::Sequential(model))).unwrap();
         }
 
         #[test]
@@ -137,7 +174,7 @@ mod layer_spec {
             model.add_layer(LayerConfig::new("linear2", LinearConfig { output_size: 10 }));
 
             let _ = Layer::from_config(cuda_backend(),
-                                       &LayerConfig::new("model", LayerType::Sequential(model)));
+                                       &LayerConfig::new("model", LayerType::Sequential(model))).unwrap();
         }
 
         #[test]
@@ -150,7 +187,7 @@ mod layer_spec {
             normal_model.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
             let mut normal_network = Layer::from_config(cuda_backend.clone(),
                                                         &LayerConfig::new("normal_model",
-                                                                          LayerType::Sequential(normal_model)));
+                                                                          LayerType::Sequential(normal_model))).unwrap();
 
             let mut reshape_model = SequentialConfig::default();
             reshape_model.add_input("data", &[3]);
@@ -158,7 +195,7 @@ mod layer_spec {
             reshape_model.add_layer(LayerConfig::new("sigmoid", LayerType::Sigmoid));
             let mut reshape_network = Layer::from_config(cuda_backend.clone(),
                                                          &LayerConfig::new("reshape_model",
-                                                                           LayerType::Sequential(reshape_model)));
+                                                                           LayerType::Sequential(reshape_model))).unwrap();
 
             let input = vec![1f32, 1f32, 2f32];
             let mut normal_tensor = SharedTensor::<f32>::new(&[3]);
@@ -0,0 +1,127 @@
+extern crate juice;
+extern crate coaster as co;
+
+#[cfg(test)]
+#[cfg(feature="native")]
+mod model_specs {
+    use co::prelude::*;
+    use juice::layer::*;
+    use juice::models;
+    use juice::solver::*;
+    use juice::util::{ArcLock, write_to_memory};
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+
+    fn native_backend() -> Rc<Backend<Native>> {
+        Rc::new(Backend::<Native>::default().unwrap())
+    }
+
+    /// Reads the 5x7 glyphs of the digits from `tests/fixtures/digits.txt`, where `#` marks
+    /// a set pixel.
+    fn read_glyphs() -> Vec<Vec<bool>> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/digits.txt");
+        let mut text = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .unwrap_or_else(|err| panic!("Can't read {}: {}", path.display(), err));
+        text.split("\n\n")
+            .map(|glyph| glyph.lines().flat_map(|line| line.trim().chars()).map(|pixel| pixel == '#').collect())
+            .collect()
+    }
+
+    /// Renders `num_samples` images of 28x28 pixels and their labels, cycling through the
+    /// digits. Each glyph is scaled by 3 and placed at a pseudo-random offset on a background
+    /// with pseudo-random noise, which is seeded by `seed`.
+    fn render_digits(num_samples: usize, seed: u32) -> (Vec<f32>, Vec<usize>) {
+        let glyphs = read_glyphs();
+        assert_eq!(10, glyphs.len());
+        // linear congruential generator, so the samples are the same on every run
+        let mut state = seed;
+        let mut next = |range: u32| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) % range
+        };
+
+        let mut images = Vec::with_capacity(num_samples * 784);
+        let mut labels = Vec::with_capacity(num_samples);
+        for sample in 0..num_samples {
+            let label = sample % 10;
+            let (left, top) = (next(14) as usize, next(8) as usize);
+            for y in 0..28 {
+                for x in 0..28 {
+                    let noise = next(64) as f32 / 255f32;
+                    let set = x >= left && x < left + 15 && y >= top && y < top + 21 &&
+                              glyphs[label][(y - top) / 3 * 5 + (x - left) / 3];
+                    images.push(if set { 1f32 - noise } else { noise });
+                }
+            }
+            labels.push(label);
+        }
+        (images, labels)
+    }
+
+    fn tensor(shape: &[usize], data: &[f32]) -> ArcLock<SharedTensor<f32>> {
+        let native = Backend::<Native>::default().unwrap();
+        let mut tensor = SharedTensor::new(&shape);
+        write_to_memory(tensor.write_only(native.device()).unwrap(), data);
+        Arc::new(RwLock::new(tensor))
+    }
+
+    #[test]
+    fn lenet_learns_rendered_digits() {
+        let batch_size = 10;
+        let (train_images, train_labels) = render_digits(200, 1);
+        let (test_images, test_labels) = render_digits(100, 2);
+
+        let config = SolverConfig {
+            network: LayerConfig::new("network", models::lenet(batch_size, 10)),
+            objective: LayerConfig::new("objective", models::nll_objective(batch_size, 10)),
+            base_lr: 0.01f32,
+            momentum: 0.9f32,
+            ..SolverConfig::default()
+        };
+        let mut solver = Solver::from_config(native_backend(), native_backend(), &config);
+        for _ in 0..5 {
+            for batch in 0..train_labels.len() / batch_size {
+                let images = &train_images[batch * batch_size * 784..(batch + 1) * batch_size * 784];
+                let labels = train_labels[batch * batch_size..(batch + 1) * batch_size]
+                    .iter()
+                    .map(|&label| label as f32)
+                    .collect::<Vec<_>>();
+                solver.train_minibatch(tensor(&[batch_size, 1, 28, 28], images),
+                                       tensor(&[batch_size, 1], &labels));
+            }
+        }
+
+        let mut correct = 0;
+        for batch in 0..test_labels.len() / batch_size {
+            let images = &test_images[batch * batch_size * 784..(batch + 1) * batch_size * 784];
+            let labels = &test_labels[batch * batch_size..(batch + 1) * batch_size];
+            let outputs = solver.mut_network().forward(&[tensor(&[batch_size, 1, 28, 28], images)]);
+            let native = Backend::<Native>::default().unwrap();
+            let output = outputs[0].read().unwrap();
+            let values = output.read(native.device()).unwrap().as_slice::<f32>();
+            for (sample, &label) in values.chunks(10).zip(labels) {
+                let prediction = (0..10).fold(0, |best, class| if sample[class] > sample[best] { class } else { best });
+                if prediction == label {
+                    correct += 1;
+                }
+            }
+        }
+        let accuracy = correct as f32 / test_labels.len() as f32;
+        assert!(accuracy > 0.9, "{}", accuracy);
+    }
+
+    #[test]
+    fn model_configs_build() {
+        for (name, config) in vec![("mlp", models::mlp(4, &[784, 64, 10])),
+                                   ("lenet", models::lenet(4, 10)),
+                                   ("vgg_small", models::vgg_small(4, 10))] {
+            let network = Layer::from_config(native_backend(), &LayerConfig::new(name, config)).unwrap();
+            assert!(!network.learnable_weights_data().is_empty(), "{}", name);
+        }
+    }
+}
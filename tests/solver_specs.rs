@@ -1,12 +1,9 @@
-extern crate leaf;
+extern crate juice;
 extern crate coaster as co;
 
-#[cfg(all(test, whatever))]
-// #[cfg(test)]
+#[cfg(test)]
 mod solver_specs {
-    use co::backend::Backend;
-    use co::frameworks::Native;
-    use leaf::solver::*;
+    use juice::solver::*;
 
     #[test]
     // fixed: always return base_lr.
@@ -63,8 +60,14 @@ mod solver_specs {
     }
 
     #[test]
+    #[cfg(feature="native")]
     fn instantiate_solver_sgd_momentum() {
+        use co::prelude::*;
+        use juice::util::native_backend;
+        use std::rc::Rc;
+
         let cfg = SolverConfig { solver: SolverKind::SGD(SGDKind::Momentum), ..SolverConfig::default() };
-        Solver::<Box<ISolver<Backend<Native>>>, Backend<Native>>::from_config(&cfg);
+        let backend = Rc::new(native_backend());
+        Solver::<Backend<Native>, Backend<Native>>::from_config(backend.clone(), backend, &cfg);
     }
 }